@@ -0,0 +1,40 @@
+//! Builds release APKs from an `xtask` or build script instead of `cargo apk build`.
+//!
+//! ```sh
+//! cargo run -p cargo-apk --example build_apk -- path/to/Cargo.toml
+//! ```
+
+use cargo_apk::{ApkBuildOptions, ApkBuilder, Profile, Target};
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    let manifest_path = std::env::args_os().nth(1).map(PathBuf::from);
+
+    // One APK per flavor, each with its own features and ABIs
+    for (features, targets) in [
+        (vec![], vec![Target::Arm64V8a]),
+        (
+            vec!["demo".to_string()],
+            vec![Target::Arm64V8a, Target::X86_64],
+        ),
+    ] {
+        let builder = ApkBuilder::from_options(ApkBuildOptions {
+            manifest_path: manifest_path.clone(),
+            profile: Some(Profile::Release),
+            features,
+            targets,
+            ..Default::default()
+        })?;
+        for artifact in builder.artifacts() {
+            let apk = builder.build(artifact)?;
+            println!(
+                "Built `{}` {} ({:?}) at `{}`",
+                apk.package(),
+                apk.version_name().unwrap_or_default(),
+                apk.version_code(),
+                apk.path().display()
+            );
+        }
+    }
+    Ok(())
+}
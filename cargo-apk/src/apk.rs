@@ -1,187 +1,538 @@
+use crate::asset_transform;
+use crate::build_info::{self, BuildInfo};
+use crate::cargo_messages;
+use crate::dependency_manifest::{self, DependencyManifest};
 use crate::error::Error;
+use crate::flavor;
+use crate::gradle::{self, GradleOutput, OutputLayout};
+use crate::hooks::{Hook, HookEnv};
+use crate::licenses::{Licenses, cargo_metadata};
 use crate::manifest::{Inheritable, Manifest, Root};
+use crate::permission_audit;
+use crate::publish::{self, PublishOptions};
+use crate::resolve::{
+    self, ManifestDefaults, ResolveContext, SdkVersions, print_warnings, resolve_manifest,
+};
+use crate::sbom::{self, SbomFormat};
+use crate::size_limit::{self, BuildSettings};
+use crate::splits;
+use crate::watch;
 use cargo_subcommand::{Artifact, ArtifactType, CrateType, Profile, Subcommand};
-use ndk_build::apk::{Apk, ApkConfig};
-use ndk_build::cargo::{VersionCode, cargo_ndk};
+use ndk_build::apk::{
+    Apk, ApkConfig, LaunchExtras, LibOrigin, StripConfig, StripMode, SystemInstall,
+    check_install_args,
+};
+use ndk_build::cargo::{CargoNdkOptions, cargo_ndk_with};
+use ndk_build::checksums::sha256_file;
 use ndk_build::dylibs::get_libs_search_paths;
-use ndk_build::manifest::{IntentFilter, MetaData};
-use ndk_build::ndk::{Key, Ndk};
+use ndk_build::env;
+use ndk_build::error::NdkError;
+use ndk_build::installed::InstallNotice;
+use ndk_build::manifest::{AndroidManifest, MetaData, Profileable};
+use ndk_build::ndk::plan::ToolPlan;
+use ndk_build::ndk::{DeviceInfo, Key, KeyRotation, Ndk, NdkOptions, OlderKey};
+use ndk_build::sanitizer::Sanitizer;
+use ndk_build::simpleperf::{self, RecordOptions};
 use ndk_build::target::Target;
-use ndk_build::util::output_error;
-use std::path::PathBuf;
-use std::process::{Stdio, exit};
+use ndk_build::util::{
+    ManagedChild, Phase, Verbosity, color, output_error, status, stream_error, verbosity,
+};
+use ndk_build::vulkan::{self, GPU_DEBUG_LAYERS_MIN_API_LEVEL, VALIDATION_LAYER};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Time without further changes before [`ApkBuilder::watch()`] rebuilds.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How long [`ApkBuilder::watch()`] waits for the app to start to follow its `logcat`.
+const WATCH_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options for [`ApkBuilder::check()`] and [`ApkBuilder::clippy()`].
+#[derive(Clone, Debug, Default)]
+pub struct CheckOptions {
+    /// Also check the tests, examples and benches, like `cargo check --all-targets`.
+    /// They compile for Android even though `cargo apk` can't run them
+    pub all_targets: bool,
+}
+
+/// Options for [`ApkBuilder::run()`].
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    /// Do not print or follow `logcat` after starting the app
+    pub no_logcat: bool,
+    /// Path within the app's internal storage to pull once the app exits
+    pub pull_after_run: Option<String>,
+    /// Do not collect tombstones and ANR traces after an abnormal exit
+    pub no_diagnostics: bool,
+    /// Runs shorter than this are considered abnormal exits, even without a crash
+    /// marker in the log. `adb bugreport` is only captured for crashes though.
+    pub diagnostics_threshold: Duration,
+    /// Compile the app ahead of time after installing it, avoiding the jank of the
+    /// first launches
+    pub compile_mode: Option<CompileMode>,
+    /// Push the apk to `/system/priv-app/` of a rooted device through
+    /// [`Apk::install_system()`] instead of installing it normally
+    pub system_install: bool,
+    /// Skip [`DeviceInfo::incompatibilities()`] and let the installation fail on the
+    /// device instead
+    pub force_install: bool,
+    /// Fail when the device has a newer `versionCode` of the app installed, instead of
+    /// warning that `adb install` will refuse it
+    pub fail_on_downgrade: bool,
+    /// Push assets that change on the host to the running app, as configured by
+    /// `[package.metadata.android.watch_assets]`
+    pub watch_assets: bool,
+    /// Install the apk of the last build instead of building, see
+    /// [`ApkBuilder::built_apk()`]
+    pub no_build: bool,
+    /// Start the app that is already installed, which also skips the build
+    pub no_install: bool,
+    /// Stop a running instance of the app before starting it, see [`Apk::restart()`]
+    pub restart: bool,
+    /// Arguments and variables passed on the launch intent
+    pub launch_extras: LaunchExtras,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            no_logcat: false,
+            pull_after_run: None,
+            no_diagnostics: false,
+            diagnostics_threshold: Duration::from_secs(2),
+            compile_mode: None,
+            system_install: false,
+            force_install: false,
+            fail_on_downgrade: false,
+            watch_assets: false,
+            no_build: false,
+            no_install: false,
+            restart: false,
+            launch_extras: LaunchExtras::default(),
+        }
+    }
+}
+
+/// The `cmd package compile -m` mode of [`RunOptions::compile_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompileMode {
+    /// Compile everything
+    Speed,
+    /// Compile what the baseline profile lists
+    SpeedProfile,
+    /// Only verify the dex code
+    Verify,
+}
+
+impl CompileMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Speed => "speed",
+            Self::SpeedProfile => "speed-profile",
+            Self::Verify => "verify",
+        }
+    }
+}
+
+/// What [`ApkBuilder::profile()`] produces besides the raw `perf.data`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProfileFormat {
+    /// Only pull the `perf.data` recording
+    #[default]
+    Data,
+    /// Print a `simpleperf report` sorted by library and symbol
+    Report,
+    /// Write stacks in the folded format for flamegraph tools next to the recording
+    Folded,
+}
+
+/// Options for [`ApkBuilder::profile()`].
+#[derive(Clone, Debug)]
+pub struct ProfileOptions {
+    pub record: RecordOptions,
+    /// Host path to pull the `perf.data` recording to
+    pub output: PathBuf,
+    pub format: ProfileFormat,
+}
+
+/// Options for [`ApkBuilder::from_options()`] and [`ApkBuilder::from_subcommand()`],
+/// mirroring the `cargo apk` arguments.
+#[derive(Clone, Debug, Default)]
+pub struct ApkBuildOptions {
+    /// `Cargo.toml` of the package, looked up from the working directory if unset
+    pub manifest_path: Option<PathBuf>,
+    /// Package to build when `manifest_path` is a workspace
+    pub package: Option<String>,
+    /// Defaults to [`Profile::Dev`]
+    pub profile: Option<Profile>,
+    /// Targets to build for, overriding `build_targets` in the manifest. Ignored by
+    /// [`ApkBuilder::from_subcommand()`], which takes the `--target` of the [`Subcommand`].
+    pub targets: Vec<Target>,
+    pub target_dir: Option<PathBuf>,
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    /// Build these examples instead of the library
+    pub examples: Vec<String>,
+    /// Device to detect the target ABI on and to run on, see `adb devices`
+    pub device_serial: Option<String>,
+    /// Android user to install and launch the app for
+    pub user: Option<u32>,
+    /// Build for all ABIs the device supports
+    pub auto_abis: bool,
+    pub ndk_options: NdkOptions,
+    /// Fail instead of raising `min_sdk_version` to the lowest level the NDK supports
+    pub strict_sdk_check: bool,
+    /// Like `replace_linker_args = true` in the manifest
+    pub no_default_linker_args: bool,
+    /// Fail on unknown keys in `[package.metadata.android]`, like `strict_manifest = true`
+    pub strict_manifest: bool,
+    /// Don't prune libraries and assets left in the build directory by previous builds
+    pub keep_stale: bool,
+    /// Write `<apk_name>-native-debug-symbols.zip` next to the apk
+    pub debug_symbols_bundle: bool,
+    /// Write a bill of materials of the crates and native libraries next to the apk
+    pub sbom: Option<SbomFormat>,
+    /// `[package.metadata.android.flavor.<name>]` to apply
+    pub flavor: Option<String>,
+    /// Don't check that the `@string/` and `@mipmap/` references of the manifest exist
+    pub skip_resource_validation: bool,
+    /// Appended to `adb install` after the `install_flags` of the manifest
+    pub install_args: Vec<String>,
+}
+
+/// The [`Subcommand`] passed to [`ApkBuilder::from_subcommand()`], or the one
+/// created by [`ApkBuilder::from_options()`].
+enum SubcommandRef<'a> {
+    Borrowed(&'a Subcommand),
+    Owned(Box<Subcommand>),
+}
+
+impl std::ops::Deref for SubcommandRef<'_> {
+    type Target = Subcommand;
+
+    fn deref(&self) -> &Subcommand {
+        match self {
+            Self::Borrowed(cmd) => cmd,
+            Self::Owned(cmd) => cmd,
+        }
+    }
+}
 
 pub struct ApkBuilder<'a> {
-    cmd: &'a Subcommand,
+    cmd: SubcommandRef<'a>,
     ndk: Ndk,
+    /// The manifest as configured, see [`ApkBuilder::android_manifest()`] for the one
+    /// that is packaged
     manifest: Manifest,
+    defaults: ManifestDefaults,
+    sdk_versions: SdkVersions,
     build_dir: PathBuf,
     build_targets: Vec<Target>,
     device_serial: Option<String>,
+    user: Option<u32>,
+    /// The flags of the profile's `optimize` preset
+    optimize_rustflags: Vec<String>,
+    keep_stale: bool,
+    /// `--debug-symbols-bundle` or the `debug_symbols_bundle` of the profile
+    debug_symbols_bundle: bool,
+    sbom: Option<SbomFormat>,
+    skip_resource_validation: bool,
+    /// `install_flags` followed by `--install-arg`
+    install_args: Vec<String>,
+}
+
+impl ApkBuilder<'static> {
+    /// Sets up a build without going through the `cargo apk` command line, e.g. from
+    /// an `xtask`.
+    pub fn from_options(options: ApkBuildOptions) -> Result<Self, Error> {
+        let cmd = Subcommand::new(subcommand_args(&options))?;
+        Self::new(SubcommandRef::Owned(Box::new(cmd)), options)
+    }
 }
 
 impl<'a> ApkBuilder<'a> {
-    pub fn from_subcommand(
-        cmd: &'a Subcommand,
-        device_serial: Option<String>,
-    ) -> Result<Self, Error> {
-        println!(
-            "Using package `{}` in `{}`",
-            cmd.package(),
-            cmd.manifest().display()
-        );
-        let ndk = Ndk::from_env()?;
+    /// Sets up a build of `cmd`. Only the settings of `options` that `cargo` doesn't
+    /// know about are used, the rest, including the `targets`, comes from `cmd`.
+    pub fn from_subcommand(cmd: &'a Subcommand, options: ApkBuildOptions) -> Result<Self, Error> {
+        let options = ApkBuildOptions {
+            targets: Vec::new(),
+            ..options
+        };
+        Self::new(SubcommandRef::Borrowed(cmd), options)
+    }
+
+    /// Only takes the non-`cargo` settings and `targets` from `options`, the rest comes
+    /// from `subcommand`.
+    fn new(subcommand: SubcommandRef<'a>, options: ApkBuildOptions) -> Result<Self, Error> {
+        let ApkBuildOptions {
+            targets,
+            device_serial,
+            user,
+            auto_abis,
+            ndk_options,
+            strict_sdk_check,
+            no_default_linker_args,
+            strict_manifest,
+            keep_stale,
+            debug_symbols_bundle,
+            sbom,
+            flavor,
+            skip_resource_validation,
+            install_args,
+            ..
+        } = options;
+        let cmd = &*subcommand;
+        let mut env = crate::config::cargo_home_env()?;
+        env.extend(ndk_options.env.clone());
         let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+        manifest.check_unknown_keys(strict_manifest)?;
+        if let Some(flavor) = &flavor {
+            manifest.apply_flavor(flavor)?;
+        }
+        manifest.replace_linker_args |= no_default_linker_args;
+        let install_args = manifest
+            .install_flags
+            .iter()
+            .cloned()
+            .chain(install_args)
+            .collect::<Vec<_>>();
+        check_install_args(&install_args)?;
+        let ndk = Ndk::from_env_with(&NdkOptions {
+            sdk_path: manifest.sdk_path.clone().or(ndk_options.sdk_path.clone()),
+            ndk_path: manifest.ndk_path.clone().or(ndk_options.ndk_path.clone()),
+            ndk_version: manifest.ndk_version.clone(),
+            build_tools_version: manifest.build_tools_version.clone(),
+            adb_timeout: manifest.adb_timeout_secs.map(Duration::from_secs),
+            install_missing: ndk_options.install_missing || manifest.auto_install_sdk,
+            target_sdk_version: manifest.android_manifest.sdk.target_sdk_version,
+            compile_sdk_version: manifest.compile_sdk_version,
+            packaging_backend: manifest.packaging_backend,
+            env,
+            ..ndk_options
+        })?;
         let workspace_manifest: Option<Root> = cmd
             .workspace_manifest()
             .map(Root::parse_from_toml)
             .transpose()?;
-        let build_targets = if let Some(target) = cmd.target() {
-            vec![Target::from_rust_triple(target)?]
+        let optimize_rustflags = optimize_rustflags(cmd, &manifest, workspace_manifest.as_ref())?;
+        let debug_symbols_bundle = debug_symbols_bundle
+            || manifest
+                .profiles
+                .get(profile_name(cmd.profile()))
+                .is_some_and(|profile| profile.debug_symbols_bundle);
+        let build_targets = if !targets.is_empty() {
+            targets
+        } else if let Some(target) = cmd.target() {
+            vec![Target::from_abi_or_triple(target)?]
+        } else if auto_abis {
+            let targets = ndk.detect_abis(device_serial.as_deref())?;
+            if targets.is_empty() {
+                return Err(NdkError::UnsupportedTarget.into());
+            }
+            targets
         } else if !manifest.build_targets.is_empty() {
             manifest.build_targets.clone()
         } else {
-            vec![
-                ndk.detect_abi(device_serial.as_deref())
-                    .unwrap_or(Target::Arm64V8a),
-            ]
-        };
-        let build_dir = dunce::simplified(cmd.target_dir())
-            .join(cmd.profile())
-            .join("apk");
-
-        let package_version = match &manifest.version {
-            Inheritable::Value(v) => v.clone(),
-            Inheritable::Inherited { workspace: true } => {
-                let workspace = workspace_manifest
-                    .ok_or(Error::InheritanceMissingWorkspace)?
-                    .workspace
-                    .unwrap_or_else(|| {
-                        // Unlikely to fail as cargo-subcommand should give us
-                        // a `Cargo.toml` containing a `[workspace]` table
-                        panic!(
-                            "Manifest `{:?}` must contain a `[workspace]` table",
-                            cmd.workspace_manifest().unwrap()
-                        )
-                    });
-
-                workspace
-                    .package
-                    .ok_or(Error::WorkspaceMissingInheritedField("package"))?
-                    .version
-                    .ok_or(Error::WorkspaceMissingInheritedField("package.version"))?
-            }
-            Inheritable::Inherited { workspace: false } => return Err(Error::InheritedFalse),
+            vec![ndk.detect_abi_or_default(device_serial.as_deref())]
         };
-        let version_code = VersionCode::from_semver(&package_version)?.to_code(1);
-
-        // Set default Android manifest values
-        if manifest
-            .android_manifest
-            .version_name
-            .replace(package_version)
-            .is_some()
-        {
-            panic!("version_name should not be set in TOML");
-        }
+        let mut warnings = vec![];
+        let is_release = *cmd.profile() != Profile::Dev;
+        warnings.extend(check_64bit(
+            &build_targets,
+            manifest.require_64bit,
+            is_release,
+        )?);
+        status(
+            "Using",
+            format_args!(
+                "package `{}` in `{}`, building for {}",
+                cmd.package(),
+                cmd.manifest().display(),
+                build_targets
+                    .iter()
+                    .map(|t| t.android_abi())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        );
+        let build_dir = apk_build_dir(cmd, flavor.as_deref())?;
+        ndk_build::util::set_log_dir(build_dir.join("logs"));
 
-        if manifest
-            .android_manifest
-            .version_code
-            .replace(version_code)
-            .is_some()
-        {
-            panic!("version_code should not be set in TOML");
+        let package_version = package_version(cmd, &manifest, workspace_manifest.as_ref())?;
+        if let Some(sanitizer) = manifest.sanitizer {
+            check_sanitizer(&ndk, sanitizer, &build_targets)?;
         }
-
-        let target_sdk_version = *manifest
-            .android_manifest
-            .sdk
-            .target_sdk_version
-            .get_or_insert_with(|| ndk.default_target_platform());
-
-        manifest
-            .android_manifest
-            .application
-            .debuggable
-            .get_or_insert_with(|| *cmd.profile() == Profile::Dev);
-
-        let activity = &mut manifest.android_manifest.application.activity;
-
-        // Add a default `MAIN` action to launch the activity, if the user didn't supply it by hand.
-        if activity
-            .intent_filter
-            .iter()
-            .all(|i| i.actions.iter().all(|f| f != "android.intent.action.MAIN"))
-        {
-            activity.intent_filter.push(IntentFilter {
-                actions: vec!["android.intent.action.MAIN".to_string()],
-                categories: vec!["android.intent.category.LAUNCHER".to_string()],
-                data: vec![],
-            });
+        let defaults = ManifestDefaults {
+            package_version,
+            timestamp: build_info::timestamp(env::SOURCE_DATE_EPOCH.get().ok())?,
+            is_debug_profile: *cmd.profile() == Profile::Dev,
+            target_sdk_version: ndk.default_target_platform(),
+            supported_api_levels: ndk.supported_api_levels(),
+            ndk_version: Some(ndk.ndk_version().to_string()),
+            strict_sdk_check,
+        };
+        let sdk_versions = resolve::sdk_versions(&manifest, &defaults, &mut warnings)?;
+        // Devices that shipped with API level 21 and later all support NEON
+        let neon = manifest
+            .target
+            .get(&Target::ArmV7a)
+            .is_some_and(|t| t.enables_neon());
+        if neon && sdk_versions.min < 21 && build_targets.contains(&Target::ArmV7a) {
+            warnings.push(format!(
+                "`target_features` of `armeabi-v7a` enable NEON, which some devices below \
+                API level 21 lack, but minSdkVersion is {}: the app crashes with `SIGILL` on \
+                those",
+                sdk_versions.min
+            ));
         }
-
-        // Export the sole Rust activity on Android S and up, if the user didn't explicitly do so.
-        // Without this, apps won't start on S+.
-        // https://developer.android.com/about/versions/12/behavior-changes-12#exported
-        if target_sdk_version >= 31 {
-            activity.exported.get_or_insert(true);
+        // Fail on invalid manifests before building anything
+        for artifact in cmd.artifacts() {
+            let ctx = ResolveContext {
+                artifact,
+                defaults: &defaults,
+                warnings: &mut warnings,
+            };
+            resolve_manifest(manifest.clone(), ctx)?;
         }
+        print_warnings(warnings);
 
-        Ok(Self {
-            cmd,
+        let builder = Self {
+            cmd: subcommand,
             ndk,
             manifest,
+            defaults,
+            sdk_versions,
             build_dir,
             build_targets,
             device_serial,
-        })
+            user,
+            optimize_rustflags,
+            keep_stale,
+            debug_symbols_bundle,
+            sbom,
+            skip_resource_validation,
+            install_args,
+        };
+        if builder.ndk.frozen_env() {
+            builder.tool_plan().validate()?;
+        }
+        Ok(builder)
+    }
+
+    /// The library, binaries or examples selected for building.
+    pub fn artifacts(&self) -> impl Iterator<Item = &Artifact> {
+        self.cmd.artifacts()
     }
 
-    pub fn check(&self) -> Result<(), Error> {
+    pub fn check(&self, options: &CheckOptions) -> Result<(), Error> {
+        self.check_targets("check", options, &[])
+    }
+
+    /// Runs `cargo clippy` for every build target like [`ApkBuilder::check()`], passing
+    /// `lint_args` to clippy after `--`.
+    pub fn clippy(&self, options: &CheckOptions, lint_args: &[String]) -> Result<(), Error> {
+        self.check_targets("clippy", options, lint_args)
+    }
+
+    /// Streams the diagnostics of `cargo <subcommand>` for every build target, and only
+    /// fails once all of them were checked.
+    fn check_targets(
+        &self,
+        subcommand: &'static str,
+        options: &CheckOptions,
+        lint_args: &[String],
+    ) -> Result<(), Error> {
+        let mut failed = Vec::new();
         for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
+            let phase = Phase::start(format!("{} ({})", subcommand, target.android_abi()));
+            let mut cargo = cargo_ndk_with(
                 &self.ndk,
                 *target,
                 self.min_sdk_version(),
                 self.cmd.target_dir(),
+                &self.cargo_ndk_options(*target),
             )?;
-            cargo.arg("check");
+            cargo.arg(subcommand);
+            // The output is piped to be streamed
+            if color() {
+                cargo.arg("--color").arg("always");
+            }
             if self.cmd.target().is_none() {
                 let triple = target.rust_triple();
                 cargo.arg("--target").arg(triple);
             }
-            self.cmd.args().apply(&mut cargo);
-            output_error(cargo)?;
+            if options.all_targets {
+                cargo.arg("--all-targets");
+            }
+            self.apply_cargo_args(&mut cargo);
+            if !lint_args.is_empty() {
+                cargo.arg("--").args(lint_args);
+            }
+            match stream_error(cargo, None) {
+                Ok(_) => phase.finish(),
+                // The diagnostics were already printed
+                Err(NdkError::CmdFailed {
+                    status: Some(_), ..
+                }) => failed.push(target.android_abi().to_string()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TargetsFailed(subcommand, failed))
         }
-        Ok(())
     }
 
-    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
-        // Set artifact specific manifest default values.
-        let mut manifest = self.manifest.android_manifest.clone();
-
-        if manifest.package.is_empty() {
-            let name = artifact.name.replace('-', "_");
-            manifest.package = match artifact.r#type {
-                ArtifactType::Lib => format!("rust.{}", name),
-                ArtifactType::Bin => format!("rust.{}", name),
-                ArtifactType::Example => format!("rust.example.{}", name),
-            };
+    /// Passes the `cargo` arguments of the command line, and the features of the flavor.
+    fn apply_cargo_args(&self, cargo: &mut Command) {
+        self.cmd.args().apply(cargo);
+        if let Some(flavor) = &self.manifest.flavor {
+            if !flavor.features.is_empty() {
+                cargo.arg("--features").arg(flavor.features.join(","));
+            }
         }
+    }
 
-        if manifest.application.label.is_empty() {
-            manifest.application.label = artifact.name.to_string();
-        }
+    /// The features that the flavor enables in addition to `--features`.
+    fn flavor_features(&self) -> &[String] {
+        self.manifest
+            .flavor
+            .as_ref()
+            .map_or(&[][..], |f| &f.features)
+    }
 
-        manifest.application.activity.meta_data.push(MetaData {
-            name: "android.app.lib_name".to_string(),
-            value: artifact.name.replace('-', "_"),
-        });
+    /// The `AndroidManifest.xml` of `artifact`. Its warnings were already reported by
+    /// [`ApkBuilder::from_subcommand()`].
+    fn android_manifest(&self, artifact: &Artifact) -> Result<AndroidManifest, Error> {
+        let ctx = ResolveContext {
+            artifact,
+            defaults: &self.defaults,
+            warnings: &mut vec![],
+        };
+        resolve_manifest(self.manifest.clone(), ctx)
+    }
+
+    /// The apk that the last build of `artifact` left in the build directory, without
+    /// invoking `cargo`.
+    pub fn built_apk(&self, artifact: &Artifact) -> Result<Apk, Error> {
+        let config = self.apk_config(artifact)?;
+        let path = config.apk();
+        if !path.exists() && !self.ndk.exec().is_dry_run() {
+            return Err(Error::ApkNotBuilt(path));
+        }
+        Ok(Apk::from_config(&config))
+    }
 
-        let crate_path = self.cmd.manifest().parent().expect("invalid manifest path");
+    /// Resolves the artifact specific [`ApkConfig`] without building anything.
+    fn apk_config(&self, artifact: &Artifact) -> Result<ApkConfig, Error> {
+        let manifest = self.android_manifest(artifact)?;
 
         let is_debug_profile = *self.cmd.profile() == Profile::Dev;
 
@@ -189,24 +540,21 @@ impl<'a> ApkBuilder<'a> {
             .manifest
             .assets
             .as_ref()
-            .map(|assets| dunce::simplified(&crate_path.join(assets)).to_owned());
+            .map(|assets| self.manifest.resolve_path("assets", assets))
+            .transpose()?;
         let resources = self
             .manifest
             .resources
             .as_ref()
-            .map(|res| dunce::simplified(&crate_path.join(res)).to_owned());
-        let runtime_libs = self
-            .manifest
-            .runtime_libs
-            .as_ref()
-            .map(|libs| dunce::simplified(&crate_path.join(libs)).to_owned());
+            .map(|res| self.manifest.resolve_path("resources", res))
+            .transpose()?;
         let apk_name = self
             .manifest
             .apk_name
             .clone()
             .unwrap_or_else(|| artifact.name.to_string());
 
-        let config = ApkConfig {
+        Ok(ApkConfig {
             ndk: self.ndk.clone(),
             build_dir: self.build_dir.join(artifact.build_dir()),
             apk_name,
@@ -214,172 +562,988 @@ impl<'a> ApkBuilder<'a> {
             resources,
             manifest,
             disable_aapt_compression: is_debug_profile,
-            strip: self.manifest.strip,
+            strip: self.strip_mode(),
+            split_debuginfo: self.manifest.strip == StripConfig::Split,
             reverse_port_forward: self.manifest.reverse_port_forward.clone(),
+            keep_stale: self.keep_stale,
+            skip_resource_validation: self.skip_resource_validation,
+            split: None,
+            install_args: self.install_args.clone(),
+        })
+    }
+
+    /// Merges the `[package.metadata.android]` tables of the dependencies into `manifest`,
+    /// see [`dependency_manifest::merge()`].
+    fn inherit_dependency_manifest(&self, manifest: &mut AndroidManifest) -> Result<(), Error> {
+        let metadata = cargo_metadata(
+            &self.cmd,
+            &self.build_targets,
+            self.flavor_features(),
+            self.ndk.frozen_env(),
+        )?;
+        let dependencies = metadata
+            .android_tables(self.cmd.package(), self.cmd.manifest())?
+            .into_iter()
+            .map(|(name, table)| {
+                DependencyManifest::from_json(table)
+                    .map(|dependency| (name, dependency))
+                    .map_err(|e| Error::DependencyManifest(name.to_string(), e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        dependency_manifest::merge(manifest, dependencies)
+    }
+
+    /// Runs the `hooks` configured under `[package.metadata.android.hooks]` as `name`.
+    fn run_hooks(
+        &self,
+        name: &'static str,
+        hooks: &[Hook],
+        artifact: &Artifact,
+        apk: Option<&Path>,
+    ) -> Result<(), Error> {
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        let manifest = self.android_manifest(artifact)?;
+        HookEnv {
+            crate_path: &self.manifest.crate_path,
+            package: &manifest.package,
+            version_code: manifest.version_code,
+            profile: profile_name(self.cmd.profile()),
+            apk,
+            exec: self.ndk.exec(),
+        }
+        .run(name, hooks)
+    }
+
+    /// Installs `apk` after running the `pre_install` hooks and comparing it with the
+    /// installed app, see [`ApkBuilder::check_installed()`].
+    fn install(
+        &self,
+        apk: &Apk,
+        artifact: &Artifact,
+        fail_on_downgrade: bool,
+    ) -> Result<(), Error> {
+        let hooks = &self.manifest.hooks.pre_install;
+        self.run_hooks("pre_install", hooks, artifact, Some(apk.path()))?;
+        if !self.ndk.exec().is_dry_run() {
+            self.check_installed(apk, fail_on_downgrade)?;
+        }
+        let splits = self.device_splits(artifact)?;
+        let splits = splits.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        apk.install_with_splits(self.device_serial.as_deref(), self.user, &splits)?;
+        Ok(())
+    }
+
+    /// Explains what `adb install` will make of the app that the device has installed:
+    /// a downgrade, which only fails with `fail_on_downgrade`, a signing certificate it
+    /// refuses, or a reinstall of the same `versionCode`.
+    fn check_installed(&self, apk: &Apk, fail_on_downgrade: bool) -> Result<(), Error> {
+        let Some(installed) = apk.installed(self.device_serial.as_deref())? else {
+            return Ok(());
         };
+        // Without a JDK, only the `versionCode` is compared
+        let signatures = self.certificate_hashes().unwrap_or_else(|e| {
+            if verbosity() >= Verbosity::Verbose {
+                eprintln!("Warning: not comparing the signing certificates: {}", e);
+            }
+            vec![]
+        });
+        let downgrade = self.install_args.iter().any(|arg| arg == "-d");
+        for notice in installed.compare(apk.version_code(), &signatures) {
+            match notice {
+                InstallNotice::Downgrade { installed, apk } if fail_on_downgrade => {
+                    return Err(Error::Downgrade { installed, apk });
+                }
+                InstallNotice::Downgrade { installed, apk } if downgrade => status(
+                    "Downgrading",
+                    format_args!("from `versionCode` {} to {}", installed, apk),
+                ),
+                InstallNotice::SameVersion(code) => status(
+                    "Reinstalling",
+                    format_args!("`versionCode` {}, which the device has installed", code),
+                ),
+                notice => eprintln!("Warning: {}", notice),
+            }
+        }
+        Ok(())
+    }
+
+    /// The [`Ndk::certificate_hash()`]es of the signing key and the older keys of its
+    /// rotation, which the device also accepts.
+    fn certificate_hashes(&self) -> Result<Vec<String>, Error> {
+        let mut hashes = vec![self.ndk.certificate_hash(&self.signing_key()?)?];
+        if let Some(rotation) = key_rotation(&self.manifest, self.cmd.profile())? {
+            for older in &rotation.older_keys {
+                hashes.push(self.ndk.certificate_hash(&older.key)?);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// The `split_assets` apks built for `artifact` that match the density and locale of
+    /// the device, see [`splits::select()`].
+    fn device_splits(&self, artifact: &Artifact) -> Result<Vec<PathBuf>, Error> {
+        let split_assets = &self.manifest.split_assets;
+        if split_assets.is_empty() {
+            return Ok(vec![]);
+        }
+        let serial = self.device_serial.as_deref();
+        let density = self.ndk.density(serial)?;
+        let locale = self.ndk.locale(serial)?;
+        let selected = splits::select(split_assets, density, locale.as_deref());
+        if !selected.is_empty() {
+            let names = selected.iter().map(|split| split.name.as_str());
+            status(
+                "Selected",
+                format_args!("splits {}", names.collect::<Vec<_>>().join(", ")),
+            );
+        }
+        let config = self.apk_config(artifact)?;
+        Ok(selected
+            .into_iter()
+            .map(|split| config.config_split(&split.name, split.assets.clone()).apk())
+            .collect())
+    }
+
+    /// Refuses to push `apk` to a device that is too old, can't run any of its ABIs or
+    /// lacks the space for it, which `adb install` only reports after the transfer.
+    fn check_device(&self, apk: &Apk) -> Result<(), Error> {
+        let serial = self.device_serial.as_deref();
+        let device = DeviceInfo {
+            sdk: self.ndk.api_level(serial)?,
+            abis: self.ndk.device_abis(serial)?,
+            free_data: self.ndk.free_space(serial, "/data")?,
+            ..Default::default()
+        };
+        let size = std::fs::metadata(apk.path())
+            .map_err(|e| NdkError::IoPathError(apk.path().to_owned(), e))?
+            .len();
+        let reasons = device.incompatibilities(self.min_sdk_version(), &self.build_targets, size);
+        if reasons.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::IncompatibleDevice(reasons))
+        }
+    }
+
+    /// Installs `apk` as a privileged system app after running the `pre_install` hooks.
+    fn install_system(&self, apk: &Apk, artifact: &Artifact) -> Result<(), Error> {
+        let hooks = &self.manifest.hooks.pre_install;
+        self.run_hooks("pre_install", hooks, artifact, Some(apk.path()))?;
+        match apk.install_system(self.device_serial.as_deref())? {
+            SystemInstall::PrivApp(path) => {
+                status("Installed", format_args!("system app `{}`", path));
+            }
+            SystemInstall::PmInstall(remount) => eprintln!(
+                "Warning: the device refused `adb remount` ({}), installed `{}` through \
+                `pm install -r --full` instead of as a system app",
+                remount,
+                apk.package()
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, artifact: &Artifact) -> Result<Apk, Error> {
+        // Before compiling, as a missing `crate-type` is only noticed after it otherwise
+        self.manifest.check_cdylib(artifact)?;
+        splits::check(&self.manifest)?;
+        let hooks = &self.manifest.hooks;
+        self.run_hooks("pre_build", &hooks.pre_build, artifact, None)?;
+        let mut config = self.apk_config(artifact)?;
+        if let Some(flavor) = &self.manifest.flavor {
+            if let Some(assets) = &flavor.assets {
+                let key = format!("flavor.{}.assets", flavor.name);
+                let assets = self.manifest.resolve_path(&key, assets)?;
+                match &config.assets {
+                    Some(base) => {
+                        let merged = config.build_dir.join("flavor-assets");
+                        flavor::merge_assets(self.ndk.exec(), Some(base), &assets, &merged)?;
+                        config.assets = Some(merged);
+                    }
+                    None => config.assets = Some(assets),
+                }
+            }
+        }
+        if let (Some(transform), Some(assets)) = (&self.manifest.asset_transform, &config.assets) {
+            let staged = config.build_dir.join("transformed-assets");
+            let crate_path = &self.manifest.crate_path;
+            asset_transform::stage(self.ndk.exec(), transform, crate_path, assets, &staged)?;
+            config.assets = Some(staged);
+        }
+        if self.manifest.inherit_dependency_manifest {
+            self.inherit_dependency_manifest(&mut config.manifest)?;
+        }
+        let build_info = if self.manifest.stamp_build_info {
+            let info =
+                BuildInfo::collect(&self.manifest.crate_path, profile_name(self.cmd.profile()))?;
+            config.manifest.application.meta_data.push(MetaData {
+                name: build_info::META_DATA_NAME.to_string(),
+                value: info.to_json(),
+                ..Default::default()
+            });
+            Some(info)
+        } else {
+            None
+        };
+        let phase = Phase::start("package assets");
         let mut apk = config.create_apk()?;
+        phase.finish();
+        if let Some(info) = build_info {
+            let json = serde_json::to_string_pretty(&info).unwrap();
+            apk.add_asset("build_info.json", json.as_bytes())?;
+        }
+
+        let crate_path = &self.manifest.crate_path;
+        let runtime_libs = self
+            .manifest
+            .runtime_libs
+            .as_ref()
+            .map(|libs| dunce::simplified(&crate_path.join(libs)).to_owned());
 
         for target in &self.build_targets {
             let triple = target.rust_triple();
             let build_dir = self.cmd.build_dir(Some(triple));
-            let artifact = self.cmd.artifact(artifact, Some(triple), CrateType::Cdylib);
 
-            let mut cargo = cargo_ndk(
+            let phase = Phase::start(format!("compile ({})", target.android_abi()));
+            let mut cargo = cargo_ndk_with(
                 &self.ndk,
                 *target,
                 self.min_sdk_version(),
                 self.cmd.target_dir(),
+                &self.cargo_ndk_options(*target),
             )?;
-            cargo.arg("build");
+            // Diagnostics are still rendered on stderr. The command line can't conflict,
+            // `cargo_subcommand::Args` has no `--message-format`.
+            cargo
+                .arg("build")
+                .arg("--message-format=json-render-diagnostics");
             if self.cmd.target().is_none() {
                 cargo.arg("--target").arg(triple);
             }
-            self.cmd.args().apply(&mut cargo);
+            self.apply_cargo_args(&mut cargo);
 
-            output_error(cargo)?;
+            let messages = self.ndk.exec().output_messages(cargo, None)?;
+            phase.finish();
+            // Where cargo wrote the library, which a dry run doesn't say
+            let artifact_lib =
+                cargo_messages::cdylib_paths(&messages, self.cmd.manifest(), artifact)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| {
+                        self.cmd.artifact(artifact, Some(triple), CrateType::Cdylib)
+                    });
 
+            let phase = Phase::start(format!("collect libs ({})", target.android_abi()));
             let mut libs_search_paths =
                 get_libs_search_paths(self.cmd.target_dir(), triple, self.cmd.profile().as_ref())?;
             libs_search_paths.push(build_dir.join("deps"));
 
+            // The runtime is picked up as a dependency of the instrumented library
+            let sanitizer = self.sanitizer(*target);
+            if let Some(sanitizer) = sanitizer {
+                let runtime = self.ndk.sanitizer_runtime(sanitizer, *target)?;
+                libs_search_paths.push(runtime.parent().unwrap().to_owned());
+            }
+
             let libs_search_paths = libs_search_paths
                 .iter()
                 .map(|path| path.as_path())
                 .collect::<Vec<_>>();
 
-            apk.add_lib_recursively(&artifact, *target, libs_search_paths.as_slice())?;
+            let lib_name = self
+                .manifest
+                .target
+                .get(target)
+                .and_then(|t| t.lib_name.as_deref());
+            let lib = primary_lib(
+                artifact_lib,
+                lib_name,
+                *target,
+                &libs_search_paths,
+                self.ndk.exec().is_dry_run(),
+            )?;
+            apk.add_lib_recursively(&lib, *target, libs_search_paths.as_slice(), LibOrigin::Deps)?;
 
             if let Some(runtime_libs) = &runtime_libs {
-                apk.add_runtime_libs(runtime_libs, *target, libs_search_paths.as_slice())?;
+                apk.add_runtime_libs(
+                    runtime_libs,
+                    *target,
+                    libs_search_paths.as_slice(),
+                    &self.manifest.runtime_lib_checksums,
+                )?;
+            }
+
+            if self.manifest.vulkan_validation {
+                let layer = self.vulkan_validation_layer(*target)?;
+                // Layers outside the NDK are prebuilt like `runtime_libs`
+                apk.add_lib_recursively(
+                    &layer,
+                    *target,
+                    libs_search_paths.as_slice(),
+                    LibOrigin::RuntimeLibs,
+                )?;
+            }
+
+            if let Some(sanitizer) = sanitizer {
+                apk.add_wrap_sh(*target, sanitizer.wrap_sh())?;
+            }
+            phase.finish();
+        }
+
+        // The libraries of a dry run don't exist to be read
+        if self.manifest.audit_permissions && !self.ndk.exec().is_dry_run() {
+            let phase = Phase::start("audit permissions");
+            let libs = permission_audit::scan(&apk)?;
+            let uses_permission = &apk.config().manifest.uses_permission;
+            for warning in permission_audit::audit(&libs, uses_permission) {
+                eprintln!("Warning: {}", warning);
             }
+            phase.finish();
         }
 
-        let profile_name = match self.cmd.profile() {
-            Profile::Dev => "dev",
-            Profile::Release => "release",
-            Profile::Custom(c) => c.as_str(),
+        if let Some(baseline_profile) = &self.manifest.baseline_profile {
+            apk.add_baseline_profile(&crate_path.join(baseline_profile))?;
+        }
+        if self.manifest.bundle_licenses {
+            let phase = Phase::start("bundle licenses");
+            let metadata = cargo_metadata(
+                &self.cmd,
+                &self.build_targets,
+                self.flavor_features(),
+                self.ndk.frozen_env(),
+            )?;
+            let licenses = Licenses::collect(
+                &metadata,
+                self.cmd.package(),
+                self.cmd.manifest(),
+                &self.manifest.bundle_licenses_exclude,
+            )?;
+            apk.add_asset("licenses/index.json", licenses.index_json().as_bytes())?;
+            apk.add_asset("licenses/LICENSES.txt", licenses.texts.as_bytes())?;
+            phase.finish();
+        }
+
+        if self.debug_symbols_bundle {
+            let phase = Phase::start("bundle debug symbols");
+            let missing = apk.write_native_debug_symbols()?;
+            if !missing.is_empty() {
+                eprintln!(
+                    "Warning: no unstripped copy of {} to add to `{}`, check the `strip` \
+                    setting of the cargo profile",
+                    missing.join(", "),
+                    config.native_debug_symbols().display()
+                );
+            }
+            phase.finish();
+        }
+
+        // The libraries of a dry run don't exist to be hashed
+        let native_libs = match self.sbom {
+            Some(_) if !self.ndk.exec().is_dry_run() => Some(sbom::native_libs(&apk)?),
+            _ => None,
         };
 
-        let keystore_env = format!(
-            "CARGO_APK_{}_KEYSTORE",
-            profile_name.to_uppercase().replace('-', "_")
+        let signing_key = self.signing_key()?;
+        let rotation = key_rotation(&self.manifest, self.cmd.profile())?;
+        let phase = Phase::start("align");
+        let unsigned = apk.add_pending_libs_and_align()?;
+        phase.finish();
+
+        status(
+            "Signing",
+            format_args!(
+                "`{}` with keystore `{}`",
+                config.apk().display(),
+                signing_key.path.display()
+            ),
         );
-        let password_env = format!("{}_PASSWORD", keystore_env);
+        if let Some(rotation) = &rotation {
+            status(
+                "Rotating",
+                format_args!(
+                    "from {} older key(s) with lineage `{}`",
+                    rotation.older_keys.len(),
+                    rotation.lineage.display()
+                ),
+            );
+        }
+        let phase = Phase::start("sign");
+        let apk = match &rotation {
+            Some(rotation) => unsigned.sign_rotated(signing_key, rotation)?,
+            None => unsigned.sign(signing_key)?,
+        };
+        phase.finish();
 
-        let path = std::env::var_os(&keystore_env).map(PathBuf::from);
-        let password = std::env::var(&password_env).ok();
+        status("Built", format_args!("`{}`", apk.path().display()));
+        self.check_size(apk.path())?;
+        for split in &self.manifest.split_assets {
+            let key = format!("split_assets.{}.assets", split.name);
+            let assets = self.manifest.resolve_path(&key, &split.assets)?;
+            let split_config = config.config_split(&split.name, assets);
+            let phase = Phase::start(format!("split {}", split.name));
+            let unsigned = split_config.create_apk()?.add_pending_libs_and_align()?;
+            let split_apk = match &rotation {
+                Some(rotation) => unsigned.sign_rotated(self.signing_key()?, rotation)?,
+                None => unsigned.sign(self.signing_key()?)?,
+            };
+            phase.finish();
+            status(
+                "Built",
+                format_args!("split `{}`", split_apk.path().display()),
+            );
+            self.check_size(split_apk.path())?;
+        }
+        if self.manifest.output_layout == OutputLayout::Gradle {
+            let path = self.gradle_output(&config.apk_name, &apk).write(
+                self.ndk.exec(),
+                &self.manifest.gradle_output_dir,
+                apk.path(),
+            )?;
+            status("Copied", format_args!("to `{}`", path.display()));
+        }
+        if let (Some(format), Some(native_libs)) = (self.sbom, native_libs) {
+            let phase = Phase::start("sbom");
+            let metadata = cargo_metadata(
+                &self.cmd,
+                &self.build_targets,
+                self.flavor_features(),
+                self.ndk.frozen_env(),
+            )?;
+            let path = sbom::write(
+                format,
+                &self.output_path(artifact, &apk),
+                &metadata,
+                self.cmd.package(),
+                self.cmd.manifest(),
+                &native_libs,
+            )?;
+            phase.finish();
+            status("SBOM", format_args!("written to `{}`", path.display()));
+        }
+        // The path is the only output of a quiet build
+        if verbosity() == Verbosity::Quiet {
+            println!("{}", self.output_path(artifact, &apk).display());
+        }
+        self.run_hooks("post_build", &hooks.post_build, artifact, Some(apk.path()))?;
+        Ok(apk)
+    }
 
-        let signing_key = match (path, password) {
-            (Some(path), Some(password)) => Key { path, password },
-            (Some(path), None) if is_debug_profile => {
-                eprintln!(
-                    "{} not specified, falling back to default password",
-                    password_env
+    /// Where users find the apk built by [`ApkBuilder::build()`]: its copy in the Gradle
+    /// layout with `output_layout = "gradle"`, otherwise [`Apk::path()`].
+    pub fn output_path(&self, artifact: &Artifact, apk: &Apk) -> PathBuf {
+        if self.manifest.output_layout != OutputLayout::Gradle {
+            return apk.path().to_owned();
+        }
+        let module = self
+            .manifest
+            .apk_name
+            .clone()
+            .unwrap_or_else(|| artifact.name.to_string());
+        self.gradle_output(&module, apk)
+            .path(&self.manifest.gradle_output_dir)
+    }
+
+    fn gradle_output<'b>(&'b self, module: &'b str, apk: &'b Apk) -> GradleOutput<'b> {
+        GradleOutput {
+            module,
+            flavor: self.manifest.flavor.as_ref().map(|f| f.name.as_str()),
+            build_type: gradle::build_type(profile_name(self.cmd.profile())),
+            application_id: apk.package(),
+            version_code: apk.version_code(),
+            version_name: apk.version_name(),
+            min_sdk_version: self.min_sdk_version(),
+        }
+    }
+
+    /// Resolves the keystore for the current profile from `$CARGO_APK_<PROFILE>_KEYSTORE`,
+    /// `[package.metadata.android.signing.<profile>]`, or the generated debug keystore.
+    fn signing_key(&self) -> Result<Key, Error> {
+        match configured_key(&self.manifest, self.cmd.profile(), self.ndk.env())? {
+            Some((key, origin)) => check_keystore(key, origin),
+            None => Ok(self.ndk.debug_key()?),
+        }
+    }
+
+    /// Every external tool and file [`ApkBuilder::build()`] needs, see
+    /// [`Ndk::tool_plan()`].
+    pub fn tool_plan(&self) -> ToolPlan {
+        let mut plan = self
+            .ndk
+            .tool_plan(&self.build_targets, self.target_sdk_version());
+        for &target in &self.build_targets {
+            if let Some(sanitizer) = self.sanitizer(target) {
+                plan.require(
+                    sanitizer.runtime_name(target),
+                    self.ndk.sanitizer_runtime(sanitizer, target),
                 );
-                Key {
-                    path,
-                    password: ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned(),
-                }
             }
-            (Some(path), None) => {
-                eprintln!(
-                    "`{}` was specified via `{}`, but `{}` was not specified, both or neither must be present for profiles other than `dev`",
-                    path.display(),
-                    keystore_env,
-                    password_env
+        }
+        if self.manifest.vulkan_validation {
+            for &target in &self.build_targets {
+                plan.require(
+                    format!(
+                        "{} ({})",
+                        vulkan::VALIDATION_LAYER_LIB,
+                        target.android_abi()
+                    ),
+                    self.vulkan_validation_layer(target),
                 );
-                return Err(Error::MissingReleaseKey(profile_name.to_owned()));
             }
-            (None, _) => {
-                if let Some(msk) = self.manifest.signing.get(profile_name) {
-                    Key {
-                        path: crate_path.join(&msk.path),
-                        password: msk.keystore_password.clone(),
-                    }
-                } else if is_debug_profile {
-                    self.ndk.debug_key()?
-                } else {
-                    return Err(Error::MissingReleaseKey(profile_name.to_owned()));
+        }
+        plan.require("keystore", self.signing_key().map(|key| key.path));
+        plan
+    }
+
+    pub fn run(&self, artifact: &Artifact, options: &RunOptions) -> Result<Apk, Error> {
+        // Before spending a build on it
+        if self.manifest.vulkan_validation && !self.ndk.exec().is_dry_run() {
+            match self.ndk.api_level(self.device_serial.as_deref())? {
+                Some(api_level) if api_level < GPU_DEBUG_LAYERS_MIN_API_LEVEL => {
+                    return Err(Error::GpuDebugLayersUnsupported(api_level));
                 }
+                _ => {}
             }
+        }
+        let apk = if options.no_install {
+            // Only the package id is needed to start the installed app
+            Apk::from_config(&self.apk_config(artifact)?)
+        } else if options.no_build {
+            self.built_apk(artifact)?
+        } else {
+            self.build(artifact)?
+        };
+        if !options.force_install && !options.no_install && !self.ndk.exec().is_dry_run() {
+            self.check_device(&apk)?;
+        }
+        if options.no_install {
+            apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+        } else if options.system_install {
+            // The reboot drops the forwarded ports
+            self.install_system(&apk, artifact)?;
+            apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+        } else {
+            apk.reverse_port_forwarding(self.device_serial.as_deref())?;
+            self.install(&apk, artifact, options.fail_on_downgrade)?;
+        }
+        if let Some(mode) = options.compile_mode {
+            self.compile(&apk, mode)?;
+        }
+        // Read by the app when it starts, restored once it exits
+        let gpu_debug_layers = if self.manifest.vulkan_validation {
+            status(
+                "Enabling",
+                format_args!(
+                    "`{}` through the GPU debug layer settings",
+                    VALIDATION_LAYER
+                ),
+            );
+            Some(self.ndk.enable_gpu_debug_layers(
+                self.device_serial.as_deref(),
+                apk.package(),
+                &[VALIDATION_LAYER],
+            )?)
+        } else {
+            None
         };
+        if options.restart {
+            apk.restart(
+                self.device_serial.as_deref(),
+                self.user,
+                &options.launch_extras,
+            )?;
+        } else {
+            apk.start(
+                self.device_serial.as_deref(),
+                self.user,
+                &options.launch_extras,
+            )?;
+        }
+        // Without a running app, there is nothing to follow
+        if self.ndk.exec().is_dry_run() {
+            return Ok(apk);
+        }
+        //let uid = apk.uidof(self.device_serial.as_deref(), self.user)?;
 
-        let unsigned = apk.add_pending_libs_and_align()?;
+        if !options.no_logcat
+            || options.pull_after_run.is_some()
+            || options.watch_assets
+            || gpu_debug_layers.is_some()
+        {
+            let pid = self.wait_for_pid(&apk)?;
+            let started = Instant::now();
+            let watcher = apk.watch_process(self.device_serial.as_deref(), self.user, pid)?;
+            let assets = if options.watch_assets {
+                let roots = self.asset_dirs()?;
+                if roots.is_empty() {
+                    return Err(Error::NoAssetsToWatch);
+                }
+                let config = &self.manifest.watch_assets;
+                let (assets_watcher, changes) = watch::watch(&roots, config.debounce())?;
+                status(
+                    "Watching",
+                    format_args!("`{}` for changes to push", roots[0].display()),
+                );
+                Some((assets_watcher, changes, roots))
+            } else {
+                None
+            };
+            let logcat = if options.no_logcat {
+                None
+            } else {
+                Some(self.follow_logcat(pid)?)
+            };
+            // Pushes from another thread, the logcat keeps streaming through failed pushes
+            std::thread::scope(|scope| {
+                let assets_watcher = assets.map(|(assets_watcher, changes, roots)| {
+                    let config = &self.manifest.watch_assets;
+                    let (ndk, apk) = (&self.ndk, &apk);
+                    let (device_serial, user) = (self.device_serial.as_deref(), self.user);
+                    scope
+                        .spawn(move || config.sync(ndk, apk, device_serial, user, &roots, changes));
+                    assets_watcher
+                });
+                let result = watcher.wait();
+                // Ends the sync thread once it finished the current push
+                drop(assets_watcher);
+                result
+            })?;
+            sleep(Duration::from_millis(250));
+            if let Some(mut logcat) = logcat {
+                logcat.kill()?;
+            }
+            drop(gpu_debug_layers);
 
-        println!(
-            "Signing `{}` with keystore `{}`",
-            config.apk().display(),
-            signing_key.path.display()
-        );
-        Ok(unsigned.sign(signing_key)?)
+            if !options.no_diagnostics {
+                let crashed = apk.crashed(self.device_serial.as_deref(), pid)?;
+                if crashed || started.elapsed() < options.diagnostics_threshold {
+                    self.collect_diagnostics(&apk, artifact, started.elapsed(), crashed)?;
+                }
+            }
+        }
+
+        if let Some(device_path) = &options.pull_after_run {
+            self.pull_from(&apk, device_path, None)?;
+        }
+
+        let hooks = &self.manifest.hooks.post_run;
+        self.run_hooks("post_run", hooks, artifact, Some(apk.path()))?;
+        Ok(apk)
     }
 
-    pub fn run(&self, artifact: &Artifact, no_logcat: bool) -> Result<(), Error> {
-        let apk = self.build(artifact)?;
-        apk.reverse_port_forwarding(self.device_serial.as_deref())?;
-        apk.install(self.device_serial.as_deref())?;
-        apk.start(self.device_serial.as_deref())?;
-        //let uid = apk.uidof(self.device_serial.as_deref())?;
-
-        if !no_logcat {
-            let mut waiting = false;
-            let pid = loop {
-                sleep(Duration::from_millis(250));
-                let out = self
-                    .ndk
-                    .adb(self.device_serial.as_deref())?
-                    .arg("shell")
-                    .arg("pidof")
-                    .arg(apk.package())
-                    .output()?;
-                if out.status.success() {
-                    break out.stdout;
-                } else if !waiting {
-                    waiting = true;
-                    eprintln!("Waiting for the app to start!");
+    /// Streams the `logcat` of process `pid` until killed. It dies with `cargo apk`, also
+    /// when Ctrl-C skips the `kill()`.
+    fn follow_logcat(&self, pid: u32) -> Result<ManagedChild, Error> {
+        let mut logcat = self.ndk.adb(self.device_serial.as_deref())?;
+        logcat
+            .arg("logcat")
+            .arg("-v")
+            .arg("color")
+            .arg("--pid")
+            .arg(pid.to_string())
+            .stdin(Stdio::null());
+        Ok(ManagedChild::spawn(&mut logcat)?)
+    }
+
+    /// Builds, installs and starts the app like [`ApkBuilder::run()`], and does so again
+    /// whenever the sources, `Cargo.toml`, `Cargo.lock`, `build.rs`, assets or resources of
+    /// the package change, until Ctrl-C ends the process. The app is stopped before each
+    /// restart, and only reinstalled if the apk changed. A failed iteration is reported and
+    /// retried on the next change.
+    pub fn watch(&self, artifact: &Artifact, options: &RunOptions) -> Result<(), Error> {
+        let crate_path = &self.manifest.crate_path;
+        let mut dirs = vec![crate_path.join("src")];
+        dirs.extend(self.asset_dirs()?);
+        if let Some(resources) = &self.manifest.resources {
+            dirs.push(self.manifest.resolve_path("resources", resources)?);
+        }
+        dirs.retain(|dir| dir.is_dir());
+        let (_watcher, changes) =
+            watch::watch_with(&dirs, std::slice::from_ref(crate_path), WATCH_DEBOUNCE)?;
+        let mut installed = None;
+        loop {
+            let logcat = self
+                .redeploy(artifact, options, &mut installed)
+                .unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    None
+                });
+            status(
+                "Watching",
+                format_args!("`{}` for changes to rebuild", crate_path.display()),
+            );
+            let changed = loop {
+                let Some(paths) = changes.next() else {
+                    return Ok(());
+                };
+                let changed = paths
+                    .into_iter()
+                    .filter(|path| triggers_rebuild(crate_path, &dirs, path))
+                    .collect::<Vec<_>>();
+                if !changed.is_empty() {
+                    break changed;
                 }
             };
-            let Ok(pid) = String::from_utf8(pid) else {
-                eprintln!("App not running!");
-                exit(1);
-            };
-            let mut process = self
-                .ndk
-                .adb(self.device_serial.as_deref())?
-                .arg("logcat")
-                .arg("-v")
-                .arg("color")
-                .arg("--pid")
-                .arg(pid.trim())
-                .spawn()?;
-            loop {
-                sleep(Duration::from_secs(1));
-                if matches!(
-                    self.ndk
-                        .adb(self.device_serial.as_deref())?
-                        .arg("shell")
-                        .arg("pidof")
-                        .arg(apk.package())
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::null())
-                        .stdin(Stdio::null())
-                        .status()
-                        .map(|x| x.success()),
-                    Err(_) | Ok(false)
-                ) {
-                    break;
-                }
+            if let Some(mut logcat) = logcat {
+                logcat.kill()?;
             }
+            status(
+                "Rebuilding",
+                format_args!(
+                    "after changes to `{}`{}",
+                    changed[0].display(),
+                    match changed.len() {
+                        1 => String::new(),
+                        n => format!(" and {} more", n - 1),
+                    }
+                ),
+            );
+        }
+    }
+
+    /// An iteration of [`ApkBuilder::watch()`]: builds the apk, installs it unless its
+    /// SHA-256 is `installed`, restarts the app and follows its `logcat`.
+    fn redeploy(
+        &self,
+        artifact: &Artifact,
+        options: &RunOptions,
+        installed: &mut Option<String>,
+    ) -> Result<Option<ManagedChild>, Error> {
+        let apk = self.build(artifact)?;
+        let hash = sha256_file(apk.path())?;
+        let device_serial = self.device_serial.as_deref();
+        apk.force_stop(device_serial, self.user)?;
+        if installed.as_ref() == Some(&hash) {
+            status("Unchanged", "apk, not reinstalling it");
+        } else {
+            apk.reverse_port_forwarding(device_serial)?;
+            self.install(&apk, artifact, options.fail_on_downgrade)?;
+            *installed = Some(hash);
+        }
+        apk.start(device_serial, self.user, &options.launch_extras)?;
+        if options.no_logcat {
+            return Ok(None);
+        }
+        // Unlike `run`, a crash on start must not keep the loop from the next change
+        let started = Instant::now();
+        while started.elapsed() < WATCH_START_TIMEOUT {
             sleep(Duration::from_millis(250));
-            process.kill()?;
+            if let Some(pid) = apk.pid(device_serial, self.user)? {
+                return Ok(Some(self.follow_logcat(pid)?));
+            }
         }
+        eprintln!(
+            "Warning: the app did not start within {}s, see `adb logcat`",
+            WATCH_START_TIMEOUT.as_secs()
+        );
+        Ok(None)
+    }
+
+    /// The directories packaged into `assets/`, with the assets of the flavor last as they
+    /// are merged over the others.
+    fn asset_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let flavor = self.manifest.flavor.as_ref().and_then(|f| {
+            let key = format!("flavor.{}.assets", f.name);
+            Some((key, f.assets.as_ref()?))
+        });
+        self.manifest
+            .assets
+            .as_ref()
+            .map(|assets| ("assets".to_string(), assets))
+            .into_iter()
+            .chain(flavor)
+            .map(|(key, assets)| self.manifest.resolve_path(&key, assets))
+            .collect()
+    }
 
+    /// Compiles the installed `apk` with `mode`, skipped on devices older than API 24.
+    fn compile(&self, apk: &Apk, mode: CompileMode) -> Result<(), Error> {
+        let device_serial = self.device_serial.as_deref();
+        match self.ndk.api_level(device_serial)? {
+            Some(api_level) if api_level < 24 => {
+                status(
+                    "Skipping",
+                    format_args!(
+                        "`--compile-mode`, `cmd package compile` requires API 24 but the device is API {}",
+                        api_level
+                    ),
+                );
+            }
+            _ => {
+                let phase = Phase::start(format!("dexopt ({})", mode.as_str()));
+                apk.compile(device_serial, mode.as_str())?;
+                phase.finish();
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_for_pid(&self, apk: &Apk) -> Result<u32, Error> {
+        let mut waiting = false;
+        loop {
+            sleep(Duration::from_millis(250));
+            if let Some(pid) = apk.pid(self.device_serial.as_deref(), self.user)? {
+                return Ok(pid);
+            } else if !waiting {
+                waiting = true;
+                status("Waiting", "for the app to start");
+            }
+        }
+    }
+
+    /// Builds a `profileable` apk, launches it and records a CPU profile of it with
+    /// the NDK's `simpleperf`.
+    pub fn profile(&mut self, artifact: &Artifact, options: &ProfileOptions) -> Result<(), Error> {
+        self.manifest
+            .android_manifest
+            .application
+            .profileable
+            .get_or_insert(Profileable {
+                shell: Some(true),
+                enabled: None,
+            });
+        let apk = self.build(artifact)?;
+        self.install(&apk, artifact, false)?;
+        apk.start(
+            self.device_serial.as_deref(),
+            self.user,
+            &LaunchExtras::default(),
+        )?;
+        // `simpleperf` would wait for the app, but then its startup counts against the
+        // duration
+        self.wait_for_pid(&apk)?;
+
+        let target = self.ndk.detect_abi(self.device_serial.as_deref())?;
+        simpleperf::push(&self.ndk, self.device_serial.as_deref(), target)?;
+        status(
+            "Recording",
+            format_args!(
+                "`{}` for {:?}",
+                options.record.event, options.record.duration
+            ),
+        );
+        simpleperf::record(
+            &self.ndk,
+            self.device_serial.as_deref(),
+            apk.package(),
+            &options.record,
+            &options.output,
+        )?;
+        status("Saved", format_args!("`{}`", options.output.display()));
+
+        // The unstripped libraries, before they were copied into the apk
+        let lib = self
+            .cmd
+            .artifact(artifact, Some(target.rust_triple()), CrateType::Cdylib);
+        let symfs = lib.parent().expect("artifact has no parent directory");
+        match options.format {
+            ProfileFormat::Data => {}
+            ProfileFormat::Report => {
+                let report = simpleperf::report(&self.ndk, &options.output, symfs)?;
+                print!("{}", String::from_utf8_lossy(&report));
+            }
+            ProfileFormat::Folded => {
+                let folded = simpleperf::folded(&self.ndk, &options.output, symfs)?;
+                let path = options.output.with_extension("folded");
+                std::fs::write(&path, folded)?;
+                status("Saved", format_args!("`{}`", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves what [`Apk::collect_diagnostics()`] finds of the run that started `max_age`
+    /// ago, only capturing `adb bugreport` when the app `crashed`.
+    fn collect_diagnostics(
+        &self,
+        apk: &Apk,
+        artifact: &Artifact,
+        max_age: Duration,
+        crashed: bool,
+    ) -> Result<(), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let dir = self
+            .build_dir
+            .join(artifact.build_dir())
+            .join("diagnostics")
+            .join(timestamp.to_string());
+        let reason = if crashed {
+            "the app crashed"
+        } else {
+            "the app exited early"
+        };
+        status("Collecting", format_args!("diagnostics, {}", reason));
+        let diagnostics =
+            apk.collect_diagnostics(self.device_serial.as_deref(), &dir, max_age, crashed)?;
+        for file in &diagnostics.files {
+            status("Saved", format_args!("`{}`", file.display()));
+        }
+        for (source, reason) in &diagnostics.inaccessible {
+            eprintln!("Could not access `{}`: {}", source, reason);
+        }
+        Ok(())
+    }
+
+    /// Copies `device_path` from the app's internal storage to `host_path`, which
+    /// defaults to the last component of `device_path` in the current directory.
+    /// Force-stops the installed app, resolving its package id from the manifest without
+    /// building.
+    pub fn stop(&self, artifact: &Artifact) -> Result<(), Error> {
+        let apk = Apk::from_config(&self.apk_config(artifact)?);
+        status("Stopping", format_args!("`{}`", apk.package()));
+        apk.force_stop(self.device_serial.as_deref(), self.user)?;
+        Ok(())
+    }
+
+    pub fn pull(
+        &self,
+        artifact: &Artifact,
+        device_path: &str,
+        host_path: Option<&Path>,
+    ) -> Result<(), Error> {
+        let apk = Apk::from_config(&self.apk_config(artifact)?);
+        self.pull_from(&apk, device_path, host_path)
+    }
+
+    fn pull_from(
+        &self,
+        apk: &Apk,
+        device_path: &str,
+        host_path: Option<&Path>,
+    ) -> Result<(), Error> {
+        let host_path = match host_path {
+            Some(host_path) => host_path.to_owned(),
+            None => PathBuf::from(
+                Path::new(device_path)
+                    .file_name()
+                    .unwrap_or_else(|| apk.package().as_ref()),
+            ),
+        };
+        status(
+            "Pulling",
+            format_args!(
+                "`{}` from `{}` to `{}`",
+                device_path,
+                apk.package(),
+                host_path.display()
+            ),
+        );
+        apk.pull_private(
+            self.device_serial.as_deref(),
+            self.user,
+            device_path,
+            &host_path,
+        )?;
         Ok(())
     }
 
     pub fn gdb(&self, artifact: &Artifact) -> Result<(), Error> {
         let apk = self.build(artifact)?;
-        apk.install(self.device_serial.as_deref())?;
+        self.install(&apk, artifact, false)?;
 
         let target_dir = self.build_dir.join(artifact.build_dir());
         self.ndk.ndk_gdb(
@@ -390,16 +1554,27 @@ impl<'a> ApkBuilder<'a> {
         Ok(())
     }
 
+    /// Builds the apk, like before installing it, and uploads it with the publisher
+    /// selected from `[package.metadata.android.publish]`.
+    pub fn publish(&self, artifact: &Artifact, options: &PublishOptions) -> Result<Apk, Error> {
+        let (name, publisher) =
+            publish::select(&self.manifest.publish, options.publisher.as_deref())?;
+        let apk = self.build(artifact)?;
+        publisher.publish(name, apk.path(), options.notes.as_ref())?;
+        Ok(apk)
+    }
+
     pub fn default(&self, cargo_cmd: &str, cargo_args: &[String]) -> Result<(), Error> {
         for target in &self.build_targets {
-            let mut cargo = cargo_ndk(
+            let mut cargo = cargo_ndk_with(
                 &self.ndk,
                 *target,
                 self.min_sdk_version(),
                 self.cmd.target_dir(),
+                &self.cargo_ndk_options(*target),
             )?;
             cargo.arg(cargo_cmd);
-            self.cmd.args().apply(&mut cargo);
+            self.apply_cargo_args(&mut cargo);
 
             if self.cmd.target().is_none() {
                 let triple = target.rust_triple();
@@ -410,7 +1585,7 @@ impl<'a> ApkBuilder<'a> {
                 cargo.arg(additional_arg);
             }
 
-            output_error(cargo)?;
+            output_error(cargo, None)?;
         }
         Ok(())
     }
@@ -421,11 +1596,848 @@ impl<'a> ApkBuilder<'a> {
     /// Has a lower bound of `23` to retain backwards compatibility with
     /// the previous default.
     fn min_sdk_version(&self) -> u32 {
+        self.sdk_versions.min
+    }
+
+    /// Combines the global and per-target `link_args`, with the `target_cpu` and
+    /// `target_features` of `target`.
+    fn cargo_ndk_options(&self, target: Target) -> CargoNdkOptions {
+        let target_metadata = self.manifest.target.get(&target);
+        let target_args = target_metadata.map_or(&[][..], |t| &t.link_args);
+        CargoNdkOptions {
+            link_args: [&self.manifest.link_args[..], target_args].concat(),
+            replace_default_args: self.manifest.replace_linker_args,
+            sanitizer: self.sanitizer(target),
+            rustflags: self.optimize_rustflags.clone(),
+            target_cpu: target_metadata.and_then(|t| t.target_cpu.clone()),
+            target_features: target_metadata.map_or_else(Vec::new, |t| t.target_features.clone()),
+        }
+    }
+
+    /// The validation layer from `vulkan_validation_layers` or the NDK.
+    fn vulkan_validation_layer(&self, target: Target) -> Result<PathBuf, NdkError> {
+        let dir = self
+            .manifest
+            .vulkan_validation_layers
+            .as_ref()
+            .map(|dir| dunce::simplified(&self.manifest.crate_path.join(dir)).to_owned());
+        self.ndk.vulkan_validation_layer(dir.as_deref(), target)
+    }
+
+    /// The configured sanitizer, if `target` supports it.
+    fn sanitizer(&self, target: Target) -> Option<Sanitizer> {
+        self.manifest.sanitizer.filter(|s| s.supports(target))
+    }
+
+    /// Reports an apk above `max_apk_size_mb`, see [`size_limit::check()`].
+    fn check_size(&self, apk: &Path) -> Result<(), Error> {
+        if self.ndk.exec().is_dry_run() {
+            return Ok(());
+        }
+        size_limit::check(
+            apk,
+            self.manifest.max_apk_size_mb,
+            self.manifest.size_limit_action,
+            &BuildSettings {
+                strip: self.strip_mode(),
+                multiple_abis: self.build_targets.len() > 1,
+            },
+        )
+    }
+
+    /// The `strip` of the profile, or else what the global `strip` stands for.
+    fn strip_mode(&self) -> StripMode {
         self.manifest
-            .android_manifest
-            .sdk
-            .min_sdk_version
-            .unwrap_or(23)
-            .max(23)
+            .profiles
+            .get(profile_name(self.cmd.profile()))
+            .and_then(|profile| profile.strip)
+            .unwrap_or_else(|| self.manifest.strip.mode())
+    }
+
+    /// Returns `targetSdkVersion`, the NDK's default unless the manifest sets it.
+    fn target_sdk_version(&self) -> u32 {
+        self.sdk_versions.target
+    }
+}
+
+/// Lists the library, binaries and examples of the package, i.e. everything `--lib`, `--bin`
+/// and `--example` accept. Only [`ApkBuildOptions::manifest_path`] and
+/// [`ApkBuildOptions::package`] are used.
+pub fn package_artifacts(options: &ApkBuildOptions) -> Result<Vec<Artifact>, Error> {
+    // Without a specific target selected, `cargo-subcommand` keeps all artifacts
+    let cmd = Subcommand::new(cargo_subcommand::Args {
+        lib: false,
+        example: Vec::new(),
+        ..subcommand_args(options)
+    })?;
+    Ok(cmd.artifacts().cloned().collect())
+}
+
+/// Picks the single artifact that commands like `run` operate on, like `cargo run` does:
+/// the one selected through `--lib`, `--bin` or `--example`, or otherwise the library,
+/// or otherwise the only binary.
+pub fn select_artifact(cmd: &Subcommand) -> Result<&Artifact, Error> {
+    let args = cmd.args();
+    for (kind, r#type, names) in [
+        ("example", ArtifactType::Example, &args.example),
+        ("bin", ArtifactType::Bin, &args.bin),
+    ] {
+        let selected = |name: &&String| {
+            cmd.artifacts()
+                .any(|a| a.r#type == r#type && &a.name == *name)
+        };
+        // `cargo-subcommand` drops unknown names instead of reporting them
+        if let Some(name) = names.iter().find(|name| !selected(name)) {
+            let all = Subcommand::new(cargo_subcommand::Args {
+                lib: false,
+                bin: Vec::new(),
+                bins: false,
+                example: Vec::new(),
+                examples: false,
+                ..args.clone()
+            })?;
+            let mut available = all
+                .artifacts()
+                .filter(|a| a.r#type == r#type)
+                .map(|a| a.name.clone())
+                .collect::<Vec<_>>();
+            available.sort();
+            return Err(Error::ArtifactNotFound {
+                kind,
+                name: name.clone(),
+                package: cmd.package().to_string(),
+                available,
+            });
+        }
+    }
+
+    let mut candidates = cmd.artifacts().collect::<Vec<_>>();
+    if !args.specific_target_selected() {
+        if let Some(lib) = candidates.iter().find(|a| a.r#type == ArtifactType::Lib) {
+            return Ok(lib);
+        }
+        candidates.retain(|a| a.r#type == ArtifactType::Bin);
+    }
+    match candidates[..] {
+        [artifact] => Ok(artifact),
+        [] => Err(Error::NoArtifact(cmd.package().to_string())),
+        _ => {
+            candidates.sort_by_key(|a| selection_flag(a));
+            Err(Error::AmbiguousArtifact(
+                candidates.into_iter().map(selection_flag).collect(),
+            ))
+        }
+    }
+}
+
+/// The flag selecting `artifact`, like `--example name`.
+fn selection_flag(artifact: &Artifact) -> String {
+    match artifact.r#type {
+        ArtifactType::Lib => "--lib".to_string(),
+        ArtifactType::Bin => format!("--bin {}", artifact.name),
+        ArtifactType::Example => format!("--example {}", artifact.name),
+    }
+}
+
+/// The `cargo` arguments for [`ApkBuilder::from_options()`].
+fn subcommand_args(options: &ApkBuildOptions) -> cargo_subcommand::Args {
+    cargo_subcommand::Args {
+        quiet: false,
+        package: options.package.iter().cloned().collect(),
+        workspace: false,
+        exclude: Vec::new(),
+        lib: options.examples.is_empty(),
+        bin: Vec::new(),
+        bins: false,
+        example: options.examples.clone(),
+        examples: false,
+        release: false,
+        profile: options.profile.clone(),
+        features: options.features.clone(),
+        all_features: options.all_features,
+        no_default_features: options.no_default_features,
+        target: None,
+        target_dir: options.target_dir.clone(),
+        manifest_path: options.manifest_path.clone(),
+    }
+}
+
+pub(crate) fn profile_name(profile: &Profile) -> &str {
+    match profile {
+        Profile::Dev => "dev",
+        Profile::Release => "release",
+        Profile::Custom(c) => c.as_str(),
+    }
+}
+
+/// `<target-dir>/<profile>/apk/<package>/`, namespaced by package so that workspaces
+/// sharing a `$CARGO_TARGET_DIR` don't replace each other's files, and by `flavor` to
+/// keep the outputs of flavors apart. The path is absolute because the build tools run
+/// in other directories.
+pub(crate) fn apk_build_dir(cmd: &Subcommand, flavor: Option<&str>) -> Result<PathBuf, Error> {
+    let target_dir = std::path::absolute(cmd.target_dir())?;
+    let mut build_dir = dunce::simplified(&target_dir)
+        .join(cmd.profile())
+        .join("apk")
+        .join(cmd.package());
+    if let Some(flavor) = flavor {
+        build_dir.push(flavor);
+    }
+    Ok(build_dir)
+}
+
+/// Resolves the `optimize` preset of the current profile, printing the flags it adds.
+/// Options set through `RUSTFLAGS` or the `[profile]` of the root manifest win.
+fn optimize_rustflags(
+    cmd: &Subcommand,
+    manifest: &Manifest,
+    workspace_manifest: Option<&Root>,
+) -> Result<Vec<String>, Error> {
+    let profile = profile_name(cmd.profile());
+    let Some(optimize) = manifest.profiles.get(profile).and_then(|p| p.optimize) else {
+        return Ok(Vec::new());
+    };
+    // Profiles are only read from the workspace root
+    let root = match workspace_manifest {
+        Some(root) => root.clone(),
+        None => Root::parse_from_toml(cmd.manifest())?,
+    };
+    let env_rustflags = ndk_build::cargo::env_rustflags()?;
+    let rustflags = optimize.rustflags(&env_rustflags, |key| {
+        crate::optimize::profile_sets(&root.profile, |var| env::var(var).ok(), profile, key)
+    });
+    status(
+        "Applying",
+        format_args!(
+            "`optimize = \"{}\"` for profile `{}`: {}",
+            optimize.name(),
+            profile,
+            if rustflags.is_empty() {
+                "all options are set explicitly".to_string()
+            } else {
+                rustflags.join(" ")
+            }
+        ),
+    );
+    Ok(rustflags)
+}
+
+/// The keystore of `profile` and where it was configured, from
+/// `$CARGO_APK_<PROFILE>_KEYSTORE` or `[package.metadata.android.signing.<profile>]`, or
+/// [`None`] if the `dev` profile falls back to the generated debug keystore. `config_env`
+/// takes precedence over the environment, see [`NdkOptions::env`].
+pub(crate) fn configured_key(
+    manifest: &Manifest,
+    profile: &Profile,
+    config_env: &BTreeMap<String, String>,
+) -> Result<Option<(Key, String)>, Error> {
+    let crate_path = &manifest.crate_path;
+    let is_debug_profile = *profile == Profile::Dev;
+    let profile_name = profile_name(profile);
+
+    let keystore_env = env::CARGO_APK_KEYSTORE.expand(&[profile_name]);
+    let password_env = env::CARGO_APK_KEYSTORE_PASSWORD.expand(&[profile_name]);
+
+    let path = env::var_with(config_env, &keystore_env).map(PathBuf::from);
+    let password = env::var_with(config_env, &password_env);
+
+    let env_origin = || format!("`${}`", keystore_env);
+    let (key, origin) = match (path, password) {
+        (Some(path), Some(password)) => (Key { path, password }, env_origin()),
+        (Some(path), None) if is_debug_profile => {
+            status(
+                "Using",
+                format_args!(
+                    "the default keystore password, `{}` is not set",
+                    password_env
+                ),
+            );
+            let key = Key {
+                path,
+                password: ndk_build::ndk::DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned(),
+            };
+            (key, env_origin())
+        }
+        (Some(path), None) => {
+            eprintln!(
+                "`{}` was specified via `{}`, but `{}` was not specified, both or neither must be present for profiles other than `dev`",
+                path.display(),
+                keystore_env,
+                password_env
+            );
+            return Err(Error::MissingReleaseKey(profile_name.to_owned()));
+        }
+        (None, _) => {
+            if let Some(msk) = manifest.signing.get(profile_name) {
+                let key = Key {
+                    path: crate_path.join(&msk.path),
+                    password: msk.keystore_password.clone(),
+                };
+                let origin = format!("`[package.metadata.android.signing.{}]`", profile_name);
+                (key, origin)
+            } else if is_debug_profile {
+                return Ok(None);
+            } else {
+                return Err(Error::MissingReleaseKey(profile_name.to_owned()));
+            }
+        }
+    };
+    Ok(Some((key, origin)))
+}
+
+/// The [`KeyRotation`] of `[package.metadata.android.signing.<profile>]`, which also
+/// applies to a keystore from `$CARGO_APK_<PROFILE>_KEYSTORE`.
+pub(crate) fn key_rotation(
+    manifest: &Manifest,
+    profile: &Profile,
+) -> Result<Option<KeyRotation>, Error> {
+    let profile_name = profile_name(profile);
+    let Some(signing) = manifest.signing.get(profile_name) else {
+        return Ok(None);
+    };
+    let origin = format!("`[package.metadata.android.signing.{}]`", profile_name);
+    let Some(lineage) = &signing.lineage else {
+        if signing.rotation_min_sdk_version.is_some() || !signing.older_keys.is_empty() {
+            return Err(Error::LineageMissing(origin));
+        }
+        return Ok(None);
+    };
+    let lineage = manifest.crate_path.join(lineage);
+    if !lineage.exists() {
+        return Err(Error::LineageNotFound {
+            path: lineage,
+            origin,
+        });
+    }
+    let older_keys = signing
+        .older_keys
+        .iter()
+        .map(|older| {
+            let key = Key {
+                path: manifest.crate_path.join(&older.path),
+                password: older
+                    .keystore_password
+                    .clone()
+                    .unwrap_or_else(|| signing.keystore_password.clone()),
+            };
+            Ok(OlderKey {
+                key: check_keystore(key, format!("`older_keys` of {}", origin))?,
+                alias: older.alias.clone(),
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(Some(KeyRotation {
+        lineage,
+        rotation_min_sdk_version: signing.rotation_min_sdk_version,
+        older_keys,
+    }))
+}
+
+/// Fails with an actionable error instead of letting `apksigner` fail on a missing `key`.
+pub(crate) fn check_keystore(key: Key, origin: String) -> Result<Key, Error> {
+    if key.path.exists() {
+        Ok(key)
+    } else {
+        Err(Error::KeystoreNotFound {
+            path: key.path,
+            origin,
+        })
+    }
+}
+
+/// Resolves the package version, which may be inherited from the workspace.
+pub(crate) fn package_version(
+    cmd: &Subcommand,
+    manifest: &Manifest,
+    workspace_manifest: Option<&Root>,
+) -> Result<String, Error> {
+    Ok(match &manifest.version {
+        Inheritable::Value(v) => v.clone(),
+        Inheritable::Inherited { workspace: true } => {
+            let workspace = workspace_manifest
+                .ok_or(Error::InheritanceMissingWorkspace)?
+                .workspace
+                .clone()
+                .ok_or_else(|| {
+                    let path = cmd.workspace_manifest().unwrap_or(cmd.manifest());
+                    Error::WorkspaceTableMissing(path.to_owned())
+                })?;
+
+            workspace
+                .package
+                .ok_or(Error::WorkspaceMissingInheritedField("package"))?
+                .version
+                .ok_or(Error::WorkspaceMissingInheritedField("package.version"))?
+        }
+        Inheritable::Inherited { workspace: false } => return Err(Error::InheritedFalse),
+    })
+}
+
+/// Warns about `build_targets` that will be built without `sanitizer`, and checks that the
+/// NDK ships its runtime for the others.
+fn check_sanitizer(ndk: &Ndk, sanitizer: Sanitizer, build_targets: &[Target]) -> Result<(), Error> {
+    for &target in build_targets {
+        if sanitizer.supports(target) {
+            ndk.sanitizer_runtime(sanitizer, target)?;
+        } else {
+            eprintln!(
+                "Warning: `sanitizer = \"{}\"` is not supported for `{}`, building it without",
+                sanitizer.name(),
+                target.android_abi()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Google Play rejects apps whose 32-bit ABIs lack their 64-bit counterpart: fails with
+/// `require_64bit`, otherwise returns a warning for release profiles.
+fn check_64bit(
+    build_targets: &[Target],
+    require_64bit: bool,
+    is_release: bool,
+) -> Result<Option<String>, Error> {
+    let missing = [
+        (Target::ArmV7a, Target::Arm64V8a),
+        (Target::X86, Target::X86_64),
+    ]
+    .into_iter()
+    .filter(|(abi32, abi64)| build_targets.contains(abi32) && !build_targets.contains(abi64))
+    .map(|(abi32, abi64)| {
+        format!(
+            "`{}` without `{}`",
+            abi32.android_abi(),
+            abi64.android_abi()
+        )
+    })
+    .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(None);
+    }
+    let missing = missing.join(" and ");
+    if require_64bit {
+        return Err(Error::Missing64BitAbi { missing });
+    }
+    Ok(is_release.then(|| {
+        format!(
+            "Google Play rejects apps without 64-bit native code, but {} is built. Add the \
+            64-bit ABI to `build_targets`, or set `require_64bit = true` to fail the build",
+            missing
+        )
+    }))
+}
+
+/// The library packaged for the activity on `target`: the one of the artifact at
+/// `artifact_lib`, or `lib<lib_name>.so` next to it or in `search_paths`.
+fn primary_lib(
+    artifact_lib: PathBuf,
+    lib_name: Option<&str>,
+    target: Target,
+    search_paths: &[&Path],
+    dry_run: bool,
+) -> Result<PathBuf, Error> {
+    let Some(lib_name) = lib_name else {
+        return Ok(artifact_lib);
+    };
+    let file_name = format!("lib{}.so", lib_name);
+    let dir = artifact_lib
+        .parent()
+        .expect("artifact has no parent directory");
+    let found = std::iter::once(dir)
+        .chain(search_paths.iter().copied())
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file());
+    match found {
+        Some(path) => Ok(path),
+        // Nothing is built in a dry run
+        None if dry_run => Ok(dir.join(file_name)),
+        None => Err(Error::LibNameNotBuilt {
+            lib_name: lib_name.to_string(),
+            abi: target.android_abi(),
+        }),
+    }
+}
+
+/// The files at the root of the package whose changes [`ApkBuilder::watch()`] rebuilds for.
+const ROOT_TRIGGERS: [&str; 3] = ["Cargo.toml", "Cargo.lock", "build.rs"];
+
+/// Whether the change of `path` that [`ApkBuilder::watch()`] saw warrants a rebuild: a
+/// file in one of the watched `dirs` or one of the [`ROOT_TRIGGERS`] of `crate_path`,
+/// except for the hidden and backup files that editors write next to the edited ones.
+fn triggers_rebuild(crate_path: &Path, dirs: &[PathBuf], path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+        return false;
+    };
+    if name.starts_with('.') || name.ends_with('~') {
+        return false;
+    }
+    ROOT_TRIGGERS
+        .iter()
+        .any(|trigger| path == crate_path.join(trigger))
+        || dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn rebuild_triggers() {
+        let crate_path = Path::new("/app");
+        let dirs = [crate_path.join("src"), PathBuf::from("/art/assets")];
+        let triggers = |path: &str| triggers_rebuild(crate_path, &dirs, Path::new(path));
+        assert!(triggers("/app/src/lib.rs"));
+        assert!(triggers("/app/src/render/mod.rs"));
+        assert!(triggers("/app/Cargo.toml"));
+        assert!(triggers("/app/Cargo.lock"));
+        assert!(triggers("/app/build.rs"));
+        assert!(triggers("/art/assets/levels/1.json"));
+        assert!(!triggers("/app/README.md"));
+        assert!(!triggers("/app/Cargo.lock.orig"));
+        assert!(!triggers("/app/target"));
+        assert!(!triggers("/app/src/.lib.rs.swp"));
+        assert!(!triggers("/app/src/lib.rs~"));
+    }
+
+    #[test]
+    fn missing_64bit() {
+        use Target::*;
+        let warning = |targets: &[Target]| check_64bit(targets, false, true).unwrap();
+        assert!(
+            warning(&[ArmV7a])
+                .unwrap()
+                .contains("`armeabi-v7a` without `arm64-v8a` is built")
+        );
+        assert_eq!(warning(&[ArmV7a, Arm64V8a]), None);
+        assert!(
+            warning(&[X86])
+                .unwrap()
+                .contains("`x86` without `x86_64` is built")
+        );
+        assert_eq!(warning(&[X86, X86_64]), None);
+        assert!(
+            warning(&[ArmV7a, X86, X86_64])
+                .unwrap()
+                .contains("`armeabi-v7a` without `arm64-v8a` is")
+        );
+
+        // Debug builds for an old device are fine, unless 64-bit is required
+        assert_eq!(check_64bit(&[ArmV7a], false, false).unwrap(), None);
+        assert!(matches!(
+            check_64bit(&[X86], true, false),
+            Err(Error::Missing64BitAbi { missing }) if missing == "`x86` without `x86_64`"
+        ));
+        assert_eq!(check_64bit(&[X86, X86_64], true, true).unwrap(), None);
+    }
+
+    #[test]
+    fn artifacts_of_package() {
+        let artifacts = package_artifacts(&ApkBuildOptions {
+            manifest_path: Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")),
+            ..Default::default()
+        })
+        .unwrap();
+        let has = |name: &str, r#type| {
+            artifacts
+                .iter()
+                .any(|a| a.name == name && a.r#type == r#type)
+        };
+        assert!(has("cargo-apk", ArtifactType::Lib));
+        assert!(has("cargo-apk", ArtifactType::Bin));
+        assert!(has("build_apk", ArtifactType::Example));
+    }
+
+    /// Selects artifacts of `testdata/artifacts`, a workspace whose `app` package has a
+    /// library, the `app` and `tool` binaries and the `shadow-mapping` and `triangle`
+    /// examples.
+    fn select(args: &[&str]) -> Result<(String, ArtifactType), Error> {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        let mut args = args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        args.insert(0, "cargo-apk".to_string());
+        args.push("--manifest-path".to_string());
+        args.push(
+            workspace
+                .join("app/Cargo.toml")
+                .to_string_lossy()
+                .into_owned(),
+        );
+        let cmd = Subcommand::new(clap::Parser::parse_from(args))?;
+        select_artifact(&cmd).map(|a| (a.name.clone(), a.r#type))
+    }
+
+    /// Checks the apk build dir of `testdata/artifacts` in release mode under `target_dir`,
+    /// outside of the workspace.
+    fn check_shared_target_dir(cmd: &Subcommand, target_dir: &Path) {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        assert_eq!(cmd.target_dir(), target_dir);
+        assert_eq!(
+            apk_build_dir(cmd, None).unwrap(),
+            target_dir.join("release/apk/app")
+        );
+        assert_eq!(
+            apk_build_dir(cmd, Some("demo")).unwrap(),
+            target_dir.join("release/apk/app/demo")
+        );
+        let manifest = Manifest::parse_from_toml(cmd.manifest()).unwrap();
+        let artifact = select_artifact(cmd).unwrap();
+        assert_eq!(
+            resolve::artifact_manifest(&manifest, artifact).package,
+            "rust.app"
+        );
+        // Nothing is written, neither into the target dir nor the workspace
+        assert!(!target_dir.exists());
+        assert!(!workspace.join("target").exists());
+    }
+
+    #[test]
+    fn build_dir_in_shared_target_dir() {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        let tmp = TestDir::new("target-dir");
+        let target_dir = tmp.join("target");
+        let cmd = Subcommand::new(clap::Parser::parse_from([
+            "cargo-apk".as_ref(),
+            "--release".as_ref(),
+            "--target-dir".as_ref(),
+            target_dir.as_os_str(),
+            "--manifest-path".as_ref(),
+            workspace.join("app/Cargo.toml").as_os_str(),
+        ]))
+        .unwrap();
+        check_shared_target_dir(&cmd, &target_dir);
+    }
+
+    /// Like [`build_dir_in_shared_target_dir()`] with `$CARGO_TARGET_DIR`, which
+    /// [`Subcommand::new()`] reads from the environment of the process. The test binary
+    /// runs this test again in a child process with the variable set, so that no other
+    /// test sees it.
+    #[test]
+    fn build_dir_in_cargo_target_dir() {
+        const CHILD: &str = "CARGO_APK_TEST_CARGO_TARGET_DIR";
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        if env::is_set(CHILD) {
+            let cmd = Subcommand::new(clap::Parser::parse_from([
+                "cargo-apk".as_ref(),
+                "--release".as_ref(),
+                "--manifest-path".as_ref(),
+                workspace.join("app/Cargo.toml").as_os_str(),
+            ]))
+            .unwrap();
+            let target_dir = PathBuf::from(env::user_var("CARGO_TARGET_DIR").unwrap());
+            check_shared_target_dir(&cmd, &target_dir);
+            return;
+        }
+
+        let tmp = TestDir::new("cargo-target-dir");
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "apk::tests::build_dir_in_cargo_target_dir"])
+            .env(CHILD, "1")
+            .env("CARGO_TARGET_DIR", tmp.join("target"))
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", stdout);
+        // The filter matched, rather than the child running nothing
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+    }
+
+    #[test]
+    fn artifact_selection() {
+        let example = |name: &str| (name.to_string(), ArtifactType::Example);
+        assert_eq!(select(&[]).unwrap(), ("app".to_string(), ArtifactType::Lib));
+        assert_eq!(
+            select(&["--example", "shadow-mapping"]).unwrap(),
+            example("shadow-mapping")
+        );
+        assert_eq!(
+            select(&["--example", "triangle"]).unwrap(),
+            example("triangle")
+        );
+        assert_eq!(
+            select(&["--bin", "tool"]).unwrap(),
+            ("tool".to_string(), ArtifactType::Bin)
+        );
+
+        assert!(matches!(
+            select(&["--example", "shadow-maping"]),
+            Err(Error::ArtifactNotFound { kind: "example", name, available, .. })
+                if name == "shadow-maping" && available == ["shadow-mapping", "triangle"]
+        ));
+        assert!(matches!(
+            select(&["--bin", "server"]),
+            Err(Error::ArtifactNotFound { kind: "bin", available, .. })
+                if available == ["app", "tool"]
+        ));
+        assert!(matches!(
+            select(&["--bins"]),
+            Err(Error::AmbiguousArtifact(flags)) if flags == ["--bin app", "--bin tool"]
+        ));
+        assert!(matches!(
+            select(&["--lib", "--examples"]),
+            Err(Error::AmbiguousArtifact(flags))
+                if flags == ["--example shadow-mapping", "--example triangle", "--lib"]
+        ));
+    }
+
+    #[test]
+    fn build_without_platform_tools() {
+        let sdk_path = TestDir::new("no-adb");
+        let ndk_path = sdk_path.join("ndk").join("25.2.9519653");
+        std::fs::create_dir_all(ndk_path.join("meta")).unwrap();
+        std::fs::write(
+            ndk_path.join("source.properties"),
+            "Pkg.Desc = Android NDK\nPkg.Revision = 25.2.9519653\n",
+        )
+        .unwrap();
+        std::fs::write(
+            ndk_path.join("meta").join("platforms.json"),
+            r#"{"min": 19, "max": 33}"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(sdk_path.join("build-tools").join("33.0.0")).unwrap();
+        let platform = sdk_path.join("platforms").join("android-33");
+        std::fs::create_dir_all(&platform).unwrap();
+        std::fs::write(platform.join("android.jar"), "").unwrap();
+
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        let builder = ApkBuilder::from_options(ApkBuildOptions {
+            manifest_path: Some(workspace.join("app/Cargo.toml")),
+            target_dir: Some(sdk_path.join("target")),
+            ndk_options: NdkOptions {
+                sdk_path: Some(sdk_path.to_path_buf()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        // Nothing needed `adb` so far, and the ABI that it would detect has a default
+        assert!(builder.ndk.adb_path().is_err());
+        assert_eq!(builder.build_targets, [Target::Arm64V8a]);
+        let artifact = select_artifact(&builder.cmd).unwrap();
+        let config = builder.apk_config(artifact).unwrap();
+        assert_eq!(config.manifest.package, "rust.app");
+        assert!(config.manifest.to_xml().is_ok());
+
+        // Like the other `cargo` settings, the targets come from the subcommand
+        let from_subcommand = ApkBuilder::from_subcommand(
+            &builder.cmd,
+            ApkBuildOptions {
+                targets: vec![Target::X86_64],
+                ndk_options: NdkOptions {
+                    sdk_path: Some(sdk_path.to_path_buf()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(from_subcommand.build_targets, [Target::Arm64V8a]);
+    }
+
+    #[test]
+    fn example_package_and_build_dir() {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        let manifest = Manifest::parse_from_toml(&workspace.join("app/Cargo.toml")).unwrap();
+        let example = Artifact {
+            name: "shadow-mapping".to_string(),
+            path: PathBuf::from("examples/shadow-mapping.rs"),
+            r#type: ArtifactType::Example,
+        };
+        let lib = Artifact {
+            name: "app".to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type: ArtifactType::Lib,
+        };
+        // Examples get their own package id and directory so they install side by side
+        assert_eq!(
+            resolve::artifact_manifest(&manifest, &example).package,
+            "rust.example.shadow_mapping"
+        );
+        assert_eq!(
+            resolve::artifact_manifest(&manifest, &lib).package,
+            "rust.app"
+        );
+        assert_eq!(example.build_dir(), Path::new("examples"));
+        assert_eq!(lib.build_dir(), Path::new(""));
+    }
+
+    #[test]
+    fn signing_lineage() {
+        let dir = TestDir::new("lineage");
+        std::fs::write(dir.join("old.keystore"), "").unwrap();
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/artifacts");
+        let mut manifest = Manifest::parse_from_toml(&workspace.join("app/Cargo.toml")).unwrap();
+        manifest.crate_path = dir.to_path_buf();
+        let profile = Profile::Release;
+        assert!(key_rotation(&manifest, &profile).unwrap().is_none());
+
+        let signing = crate::manifest::Signing {
+            path: "new.keystore".into(),
+            keystore_password: "new".to_string(),
+            older_keys: vec![crate::manifest::OlderSigningKey {
+                path: "old.keystore".into(),
+                alias: Some("old".to_string()),
+                keystore_password: None,
+            }],
+            ..Default::default()
+        };
+        manifest
+            .signing
+            .insert("release".to_string(), signing.clone());
+        assert!(matches!(
+            key_rotation(&manifest, &profile),
+            Err(Error::LineageMissing(_))
+        ));
+
+        manifest.signing.insert(
+            "release".to_string(),
+            crate::manifest::Signing {
+                lineage: Some("upload.lineage".into()),
+                rotation_min_sdk_version: Some(33),
+                ..signing
+            },
+        );
+        assert!(matches!(
+            key_rotation(&manifest, &profile),
+            Err(Error::LineageNotFound { path, .. }) if path == dir.join("upload.lineage")
+        ));
+        std::fs::write(dir.join("upload.lineage"), "").unwrap();
+        let rotation = key_rotation(&manifest, &profile).unwrap().unwrap();
+        assert_eq!(rotation.rotation_min_sdk_version, Some(33));
+        assert_eq!(rotation.older_keys.len(), 1);
+        assert_eq!(rotation.older_keys[0].key.path, dir.join("old.keystore"));
+        // Older keys share the password of the signing key unless they have their own
+        assert_eq!(rotation.older_keys[0].key.password, "new");
+        assert_eq!(rotation.older_keys[0].alias.as_deref(), Some("old"));
+    }
+
+    #[test]
+    fn lib_name_override() {
+        let dir = TestDir::new("lib-name");
+        let deps = dir.join("deps");
+        std::fs::create_dir_all(&deps).unwrap();
+        let artifact_lib = dir.join("libgame.so");
+        std::fs::write(&artifact_lib, "").unwrap();
+        std::fs::write(deps.join("libgame64.so"), "").unwrap();
+        let lib = |lib_name, dry_run| {
+            primary_lib(
+                artifact_lib.clone(),
+                lib_name,
+                Target::Arm64V8a,
+                &[&deps],
+                dry_run,
+            )
+        };
+
+        assert_eq!(lib(None, false).unwrap(), artifact_lib);
+        assert_eq!(
+            lib(Some("game64"), false).unwrap(),
+            deps.join("libgame64.so")
+        );
+        assert!(matches!(
+            lib(Some("game32"), false),
+            Err(Error::LibNameNotBuilt { lib_name, abi: "arm64-v8a" }) if lib_name == "game32"
+        ));
+        assert_eq!(lib(Some("game32"), true).unwrap(), dir.join("libgame32.so"));
     }
 }
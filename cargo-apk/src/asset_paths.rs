@@ -0,0 +1,183 @@
+//! Paths of `assets` and `resources`: relative to the crate, absolute, or relative to the
+//! workspace root with a `workspace:` prefix, with `$VAR` and `${VAR}` expanded. Both
+//! `/` and `\` separate components, so that the same setting works on every platform.
+//! Paths are normalized without touching the file system, as canonicalizing resolves
+//! symlinks and would move a symlinked workspace out of itself.
+
+use crate::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+/// Prefix of paths relative to the workspace root.
+const WORKSPACE_PREFIX: &str = "workspace:";
+
+/// Resolves `path`, the value of the setting `key`, against `crate_path` or
+/// `workspace_root`. `var` looks up the variables it expands. Paths outside of the
+/// workspace are refused unless `allow_external`.
+pub(crate) fn resolve(
+    key: &str,
+    path: &Path,
+    crate_path: &Path,
+    workspace_root: &Path,
+    allow_external: bool,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<PathBuf, Error> {
+    let expanded = expand(key, &path.to_string_lossy(), var)?;
+    let (base, rest) = match expanded.strip_prefix(WORKSPACE_PREFIX) {
+        Some(rest) => (workspace_root, rest),
+        None => (crate_path, expanded.as_str()),
+    };
+    // Windows already accepts both separators
+    let rest = if cfg!(windows) {
+        rest.to_string()
+    } else {
+        rest.replace('\\', "/")
+    };
+    // Joining an absolute path replaces `base`
+    let resolved = normalize(&base.join(rest));
+    let workspace_root = normalize(workspace_root);
+    if !allow_external && !resolved.starts_with(&workspace_root) {
+        return Err(Error::ExternalPath {
+            key: key.to_string(),
+            path: resolved,
+            workspace: workspace_root,
+        });
+    }
+    Ok(resolved)
+}
+
+/// Replaces `$VAR` and `${VAR}` in `path` with the value of the variable. A `$` that is
+/// not followed by a name is kept.
+fn expand(key: &str, path: &str, var: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::new();
+    let mut rest = path;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+        let (name, len) = match after.strip_prefix('{').and_then(|a| a.split_once('}')) {
+            Some((name, _)) => (name, name.len() + 2),
+            None => {
+                let len = after.find(|c| !is_name(c)).unwrap_or(after.len());
+                (&after[..len], len)
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let value = var(name).ok_or_else(|| Error::UndefinedPathVariable {
+            key: key.to_string(),
+            var: name.to_string(),
+        })?;
+        expanded.push_str(&value);
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Removes `.` and resolves `..` against the preceding component.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in dunce::simplified(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            // `..` of the root is the root
+            Component::ParentDir if normalized.has_root() => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> PathBuf {
+        std::env::temp_dir().join("workspace")
+    }
+
+    fn resolve(path: &str, allow_external: bool) -> Result<PathBuf, Error> {
+        let workspace = workspace();
+        super::resolve(
+            "assets",
+            Path::new(path),
+            &workspace.join("games").join("app"),
+            &workspace,
+            allow_external,
+            |name| match name {
+                "ASSET_ROOT" => Some(workspace.join("art").to_string_lossy().into_owned()),
+                "QUALITY" => Some("hd".to_string()),
+                _ => None,
+            },
+        )
+    }
+
+    #[test]
+    fn forms() {
+        let art = workspace().join("art").join("textures");
+        let absolute = art.to_string_lossy().into_owned();
+        for separator in ["/", "\\"] {
+            let path = |path: &str| path.replace('/', separator);
+            assert_eq!(
+                resolve(&path("assets/ui"), false).unwrap(),
+                workspace()
+                    .join("games")
+                    .join("app")
+                    .join("assets")
+                    .join("ui")
+            );
+            assert_eq!(resolve(&path("../../art/./textures"), false).unwrap(), art);
+            assert_eq!(
+                resolve(&path("workspace:art/textures"), false).unwrap(),
+                art
+            );
+            assert_eq!(resolve(&path("$ASSET_ROOT/textures"), false).unwrap(), art);
+            assert_eq!(
+                resolve(&path("${ASSET_ROOT}/textures"), false).unwrap(),
+                art
+            );
+            assert_eq!(
+                resolve(&path("workspace:art/textures-${QUALITY}"), false).unwrap(),
+                workspace().join("art").join("textures-hd")
+            );
+            let absolute = absolute.replace(std::path::MAIN_SEPARATOR, separator);
+            assert_eq!(resolve(&absolute, false).unwrap(), art);
+        }
+        // Not a variable
+        assert_eq!(
+            resolve("workspace:cost$/1", false).unwrap(),
+            workspace().join("cost$").join("1")
+        );
+    }
+
+    #[test]
+    fn external() {
+        for path in ["../../../shared/art", "..\\..\\..\\shared\\art"] {
+            assert!(matches!(
+                resolve(path, false),
+                Err(Error::ExternalPath { key, path, workspace: root })
+                    if key == "assets" && path.ends_with("shared/art") && root == workspace()
+            ));
+            assert_eq!(
+                resolve(path, true).unwrap(),
+                workspace().parent().unwrap().join("shared").join("art")
+            );
+        }
+        assert!(resolve("workspace:../shared", false).is_err());
+        assert!(matches!(
+            resolve("$MISSING/art", false),
+            Err(Error::UndefinedPathVariable { var, .. }) if var == "MISSING"
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! `asset_transform`: a command that [`crate::ApkBuilder`] passes the assets with
+//! matching extensions through while staging them, e.g. to encrypt scripts. Its outputs
+//! are kept between builds, keyed by the command line and the contents of the tool and
+//! the asset.
+
+use crate::error::Error;
+use crate::licenses::fnv1a;
+use ndk_build::error::NdkError;
+use ndk_build::util::{Exec, command_line, status};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Lists the key of every transformed asset, next to the staged assets.
+const CACHE_FILE: &str = "transformed-assets.json";
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct AssetTransform {
+    /// Run in the crate root with `{src}` replaced by the asset and `{dst}` by the path
+    /// to write the staged asset to
+    pub(crate) command: Vec<String>,
+    /// Extensions of the assets to transform, without the `.`
+    pub(crate) extensions: Vec<String>,
+}
+
+impl AssetTransform {
+    fn matches(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.iter().any(|e| e == ext))
+    }
+
+    /// Hashes the command line, the tool if it is a file relative to `crate_path` rather
+    /// than found on `$PATH`, and `contents`.
+    fn key(&self, crate_path: &Path, contents: &[u8]) -> String {
+        let mut bytes = self.command.join("\0").into_bytes();
+        if let Some(tool) = self.command.first().map(|tool| crate_path.join(tool)) {
+            if tool.is_file() {
+                bytes.extend(std::fs::read(tool).unwrap_or_default());
+            }
+        }
+        bytes.extend(contents);
+        format!("{:016x}", fnv1a(&bytes))
+    }
+
+    fn command(&self, crate_path: &Path, src: &Path, dst: &Path) -> Result<Command, Error> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or(Error::EmptyAssetTransformCommand)?;
+        let expand = |arg: &String| {
+            arg.replace("{src}", &src.to_string_lossy())
+                .replace("{dst}", &dst.to_string_lossy())
+        };
+        let mut command = Command::new(expand(program));
+        command
+            .args(args.iter().map(expand))
+            .current_dir(crate_path);
+        Ok(command)
+    }
+}
+
+/// Stages `assets` into `out`, passing the files that `transform` matches through its
+/// command and copying the others. Transformed files whose key did not change since the
+/// last build are kept, and files that are no longer in `assets` are removed.
+pub(crate) fn stage(
+    exec: Exec,
+    transform: &AssetTransform,
+    crate_path: &Path,
+    assets: &Path,
+    out: &Path,
+) -> Result<(), Error> {
+    let cache_path = out.with_file_name(CACHE_FILE);
+    let cache: BTreeMap<String, String> = std::fs::read(&cache_path)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    let mut keys = BTreeMap::new();
+    let mut staged = BTreeSet::new();
+    let (mut transformed, mut fresh) = (0, 0);
+    for file in files(assets)? {
+        let rel = file.strip_prefix(assets).unwrap();
+        let src = assets.join(rel);
+        let dst = out.join(rel);
+        exec.create_dir_all(dst.parent().unwrap())?;
+        staged.insert(rel.to_owned());
+        if !transform.matches(rel) {
+            exec.copy(&src, &dst)?;
+            continue;
+        }
+        let contents = std::fs::read(&src).map_err(|e| NdkError::IoPathError(src.clone(), e))?;
+        let name = rel.to_string_lossy().replace('\\', "/");
+        let key = transform.key(crate_path, &contents);
+        if dst.is_file() && cache.get(&name) == Some(&key) {
+            fresh += 1;
+        } else {
+            let command = transform.command(crate_path, &src, &dst)?;
+            let command_line = command_line(&command);
+            exec.output_error(command, None)
+                .map_err(|error| Error::AssetTransformFailed {
+                    asset: src.clone(),
+                    error: Box::new(error),
+                })?;
+            if !exec.is_dry_run() && !dst.is_file() {
+                return Err(Error::AssetTransformNoOutput {
+                    asset: src,
+                    command: command_line,
+                });
+            }
+            transformed += 1;
+        }
+        keys.insert(name, key);
+    }
+    for file in files(out)? {
+        if !staged.contains(file.strip_prefix(out).unwrap()) {
+            exec.remove(&file)?;
+        }
+    }
+    exec.write(&cache_path, serde_json::to_string_pretty(&keys).unwrap())?;
+    status(
+        "Transformed",
+        format_args!("{} asset(s), {} unchanged", transformed, fresh),
+    );
+    Ok(())
+}
+
+/// The files under `dir`, which may not exist.
+fn files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(self::files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn stage_cached() {
+        let dir = TestDir::new("transform");
+        for (path, contents) in [
+            ("assets/main.lua", "print(1)"),
+            ("assets/levels/1.json", "{}"),
+            ("assets/logo.png", "png"),
+            ("out/removed.lua", "stale"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        // Counts its runs in `runs`
+        let transform = AssetTransform {
+            command: [
+                "sh",
+                "-c",
+                "echo >> runs; tr a-z A-Z < \"$0\" > \"$1\"",
+                "{src}",
+                "{dst}",
+            ]
+            .map(String::from)
+            .to_vec(),
+            extensions: vec!["lua".to_string(), "json".to_string()],
+        };
+        let (assets, out) = (dir.join("assets"), dir.join("out"));
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+
+        stage(Exec::Run, &transform, &dir, &assets, &out).unwrap();
+        assert_eq!(read("out/main.lua"), "PRINT(1)");
+        assert_eq!(read("out/levels/1.json"), "{}");
+        assert_eq!(read("out/logo.png"), "png");
+        assert!(!out.join("removed.lua").exists());
+        assert_eq!(read("runs").lines().count(), 2);
+
+        std::fs::write(assets.join("main.lua"), "print(2)").unwrap();
+        stage(Exec::Run, &transform, &dir, &assets, &out).unwrap();
+        assert_eq!(read("out/main.lua"), "PRINT(2)");
+        assert_eq!(read("runs").lines().count(), 3);
+
+        let failing = AssetTransform {
+            command: vec!["false".to_string()],
+            extensions: vec!["png".to_string()],
+        };
+        let error = stage(Exec::Run, &failing, &dir, &assets, &out).unwrap_err();
+        assert!(
+            matches!(error, Error::AssetTransformFailed { asset, .. } if asset.ends_with("logo.png"))
+        );
+    }
+}
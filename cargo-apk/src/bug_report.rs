@@ -0,0 +1,113 @@
+//! `--bug-report`: writes what is usually asked for about a failure, the host, the
+//! tools found by `cargo apk doctor`, the variables listed by `cargo apk env` and the
+//! resolved metadata, next to the error into the build directory.
+
+use crate::apk::{apk_build_dir, select_artifact};
+use crate::build_info::{stdout, utc_time};
+use crate::error::Error;
+use crate::manifest::Manifest;
+use cargo_subcommand::Subcommand;
+use ndk_build::error::NdkError;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes a report of the failed `invocation` into the build directory of `cmd` and
+/// returns its path. Outside of a package the report goes to the temporary directory.
+///
+/// `error` is the full error, including the command line and output of a failed
+/// command. Parts of the environment that can't be resolved, which may be why the
+/// build failed, are reported with their error instead.
+pub fn write_bug_report(
+    cmd: Option<&Subcommand>,
+    flavor: Option<&str>,
+    invocation: &str,
+    error: &str,
+) -> Result<PathBuf, Error> {
+    let mut report = String::new();
+    writeln!(report, "cargo-apk {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(report, "Invocation: {}", invocation).unwrap();
+    writeln!(
+        report,
+        "Host: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+    .unwrap();
+    for tool in ["rustc", "cargo"] {
+        let mut command = Command::new(tool);
+        command.arg("--version");
+        let version = stdout(command).unwrap_or_else(|| "not found".to_string());
+        writeln!(report, "{}: {}", tool, version).unwrap();
+    }
+
+    section(&mut report, "Tools");
+    match crate::doctor::check_lines(cmd) {
+        Ok((lines, _)) => lines
+            .iter()
+            .for_each(|line| writeln!(report, "{}", line).unwrap()),
+        Err(e) => writeln!(report, "Could not run the checks: {}", e).unwrap(),
+    }
+
+    section(&mut report, "Environment variables");
+    let manifest = cmd.and_then(|cmd| Manifest::parse_from_toml(cmd.manifest()).ok());
+    let config_env = crate::config::cargo_home_env().unwrap_or_default();
+    for (value, _) in crate::print_env::variables(manifest.as_ref(), &config_env) {
+        writeln!(report, "{}", value).unwrap();
+    }
+
+    if let Some(cmd) = cmd {
+        section(&mut report, "Metadata");
+        let metadata = select_artifact(cmd)
+            .and_then(|artifact| crate::metadata::resolve(cmd, artifact, false, false, flavor));
+        match metadata {
+            Ok(metadata) => writeln!(
+                report,
+                "{}",
+                serde_json::to_string_pretty(&metadata).unwrap()
+            )
+            .unwrap(),
+            Err(e) => writeln!(report, "Could not resolve the metadata: {}", e).unwrap(),
+        }
+    }
+
+    section(&mut report, "Error");
+    writeln!(report, "{}", error).unwrap();
+
+    let dir = match cmd.map(|cmd| apk_build_dir(cmd, flavor)) {
+        Some(dir) => dir?,
+        None => std::env::temp_dir(),
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| NdkError::IoPathError(dir.clone(), e))?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let path = dir.join(file_name(timestamp));
+    std::fs::write(&path, report).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+    Ok(path)
+}
+
+fn section(report: &mut String, title: &str) {
+    writeln!(report, "\n{}\n{}", title, "-".repeat(title.len())).unwrap();
+}
+
+/// `cargo-apk-report-<time>.txt`, with the time in UTC and without the `:` that
+/// Windows does not allow in file names.
+fn file_name(timestamp: u64) -> String {
+    let time = utc_time(timestamp).replace(['-', ':'], "");
+    format!("cargo-apk-report-{}.txt", time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name() {
+        assert_eq!(
+            file_name(1714566600),
+            "cargo-apk-report-20240501T123000Z.txt"
+        );
+    }
+}
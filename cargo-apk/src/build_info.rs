@@ -0,0 +1,150 @@
+//! `stamp_build_info = true`: records where an apk came from, for
+//! [`crate::ApkBuilder::build()`] to add to the manifest and `assets/build_info.json`.
+
+use crate::error::Error;
+use ndk_build::env;
+use ndk_build::util::output_error;
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the `<meta-data>` element under `<application>` holding [`BuildInfo::to_json()`].
+pub(crate) const META_DATA_NAME: &str = "cargo_apk.build_info";
+
+const UNKNOWN: &str = "unknown";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct BuildInfo {
+    /// `git describe --tags --always`
+    pub(crate) git_describe: String,
+    /// `git rev-parse HEAD`
+    pub(crate) git_sha: String,
+    /// `clean`, `dirty` with uncommitted changes, or `unknown`
+    pub(crate) git_state: &'static str,
+    /// Seconds since the Unix epoch, `$SOURCE_DATE_EPOCH` if set
+    pub(crate) timestamp: u64,
+    /// [`BuildInfo::timestamp`] in UTC, like `2024-05-01T12:30:00Z`
+    pub(crate) time: String,
+    pub(crate) profile: String,
+    /// `rustc --version`
+    pub(crate) rustc: String,
+}
+
+impl BuildInfo {
+    /// Looks up the git state of `crate_path`, recording `unknown` outside of a git
+    /// checkout or without `git`.
+    pub(crate) fn collect(crate_path: &Path, profile: &str) -> Result<Self, Error> {
+        let git = |args: &[&str]| {
+            let mut git = Command::new("git");
+            git.arg("-C").arg(crate_path).args(args);
+            stdout(git)
+        };
+        let git_sha = git(&["rev-parse", "HEAD"]);
+        let git_state = match (&git_sha, git(&["status", "--porcelain"])) {
+            (Some(_), Some(status)) if status.is_empty() => "clean",
+            (Some(_), Some(_)) => "dirty",
+            _ => UNKNOWN,
+        };
+        let timestamp = timestamp(env::SOURCE_DATE_EPOCH.get().ok())?;
+        let mut rustc = Command::new("rustc");
+        rustc.arg("--version");
+        Ok(Self {
+            git_describe: git(&["describe", "--tags", "--always"])
+                .unwrap_or_else(|| UNKNOWN.to_string()),
+            git_sha: git_sha.unwrap_or_else(|| UNKNOWN.to_string()),
+            git_state,
+            timestamp,
+            time: utc_time(timestamp),
+            profile: profile.to_string(),
+            rustc: stdout(rustc).unwrap_or_else(|| UNKNOWN.to_string()),
+        })
+    }
+
+    /// Single-line JSON, as stored in the `<meta-data>` element.
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// The trimmed stdout of `command`, or [`None`] if it could not run or failed.
+pub(crate) fn stdout(command: Command) -> Option<String> {
+    let output = output_error(command, None).ok()?;
+    Some(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// `$SOURCE_DATE_EPOCH` if set, or else the current time, in seconds since the Unix epoch.
+pub(crate) fn timestamp(source_date_epoch: Option<String>) -> Result<u64, Error> {
+    match source_date_epoch {
+        Some(epoch) => epoch
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidSourceDateEpoch(epoch)),
+        None => Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())),
+    }
+}
+
+/// Formats `timestamp` as ISO 8601 in UTC.
+pub(crate) fn utc_time(timestamp: u64) -> String {
+    let (year, month, day) = utc_date(timestamp);
+    let seconds = timestamp % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The year, month and day of `timestamp` in UTC, converting days to a civil date with
+/// Howard Hinnant's `civil_from_days`.
+pub(crate) fn utc_date(timestamp: u64) -> (u32, u32, u32) {
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year as u32, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_date_epoch() {
+        assert_eq!(
+            timestamp(Some("1714566600".to_string())).unwrap(),
+            1714566600
+        );
+        assert!(matches!(
+            timestamp(Some("yesterday".to_string())),
+            Err(Error::InvalidSourceDateEpoch(_))
+        ));
+        assert_eq!(utc_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_time(1714566600), "2024-05-01T12:30:00Z");
+        assert_eq!(utc_time(951782400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn outside_git() {
+        // A missing directory, which unlike the temporary directory can't be inside a
+        // checkout
+        let dir = std::env::temp_dir().join(format!("cargo-apk-no-git-{}", std::process::id()));
+        let info = BuildInfo::collect(&dir, "release").unwrap();
+        assert_eq!(info.git_describe, "unknown");
+        assert_eq!(info.git_sha, "unknown");
+        assert_eq!(info.git_state, "unknown");
+        assert_eq!(info.profile, "release");
+    }
+}
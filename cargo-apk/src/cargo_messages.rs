@@ -0,0 +1,130 @@
+//! Reads the `compiler-artifact` messages of `cargo build
+//! --message-format=json-render-diagnostics`, to package the libraries where cargo wrote
+//! them instead of where they would be with the default target directory layout.
+
+use cargo_subcommand::{Artifact, ArtifactType};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The parts of a JSON message on the stdout of `cargo build` that locate an artifact.
+#[derive(Debug, Deserialize)]
+struct Message {
+    reason: String,
+    manifest_path: Option<PathBuf>,
+    target: Option<MessageTarget>,
+    #[serde(default)]
+    filenames: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl MessageTarget {
+    /// Whether this is the target that `artifact` stands for. Libraries are named with
+    /// underscores by cargo, examples keep the name from `Cargo.toml`.
+    fn is(&self, artifact: &Artifact) -> bool {
+        match artifact.r#type {
+            ArtifactType::Lib => {
+                let is_lib = self.kind.iter().any(|kind| kind.ends_with("lib"));
+                is_lib && self.name.replace('-', "_") == artifact.name.replace('-', "_")
+            }
+            ArtifactType::Example => self.kind == ["example"] && self.name == artifact.name,
+            ArtifactType::Bin => false,
+        }
+    }
+}
+
+/// The `.so` files that cargo wrote for `artifact` of the package at `manifest_path`,
+/// according to the messages in `stdout`. Lines that aren't messages are skipped.
+pub(crate) fn cdylib_paths(
+    stdout: &[u8],
+    manifest_path: &Path,
+    artifact: &Artifact,
+) -> Vec<PathBuf> {
+    let manifest_path = canonical(manifest_path);
+    let mut paths = vec![];
+    for line in stdout.split(|&b| b == b'\n') {
+        let Ok(message) = serde_json::from_slice::<Message>(line) else {
+            continue;
+        };
+        if message.reason != "compiler-artifact" {
+            continue;
+        }
+        let (Some(path), Some(target)) = (&message.manifest_path, &message.target) else {
+            continue;
+        };
+        if !target.is(artifact) || canonical(path) != manifest_path {
+            continue;
+        }
+        paths.extend(
+            message
+                .filenames
+                .into_iter()
+                .filter(|file| file.extension().is_some_and(|ext| ext == "so")),
+        );
+    }
+    paths
+}
+
+/// Cargo reports canonical paths, which `--manifest-path` may not be.
+fn canonical(path: &Path) -> PathBuf {
+    dunce::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: &[u8] = include_bytes!("../testdata/cargo_messages.jsonl");
+
+    fn artifact(name: &str, r#type: ArtifactType) -> Artifact {
+        Artifact {
+            name: name.to_string(),
+            path: PathBuf::new(),
+            r#type,
+        }
+    }
+
+    fn paths(name: &str, r#type: ArtifactType, manifest_path: &str) -> Vec<PathBuf> {
+        cdylib_paths(MESSAGES, Path::new(manifest_path), &artifact(name, r#type))
+    }
+
+    #[test]
+    fn compiler_artifacts() {
+        let game = "/ws/crates/my-game/Cargo.toml";
+        // The profile has a custom `dir-name`, and the package and library names differ
+        assert_eq!(
+            paths("my-game", ArtifactType::Lib, game),
+            [Path::new(
+                "/ws/target/aarch64-linux-android/release-lto/libmy_game.so"
+            )]
+        );
+        assert_eq!(
+            paths("shadow-mapping", ArtifactType::Example, game),
+            [Path::new(
+                "/ws/target/aarch64-linux-android/release-lto/examples/libshadow_mapping.so"
+            )]
+        );
+        // The rlib next to the library isn't packaged
+        assert_eq!(
+            paths(
+                "renderer",
+                ArtifactType::Lib,
+                "/ws/crates/renderer/Cargo.toml"
+            ),
+            [Path::new(
+                "/ws/target/aarch64-linux-android/release-lto/deps/librenderer.so"
+            )]
+        );
+
+        // Libraries of other packages, and targets that aren't built, are not matched
+        assert!(paths("renderer", ArtifactType::Lib, game).is_empty());
+        assert!(paths("tool", ArtifactType::Bin, game).is_empty());
+        assert!(paths("triangle", ArtifactType::Example, game).is_empty());
+        let lib = artifact("my-game", ArtifactType::Lib);
+        assert!(cdylib_paths(b"warning: not json\n", Path::new(game), &lib).is_empty());
+    }
+}
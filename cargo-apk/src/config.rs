@@ -0,0 +1,51 @@
+use crate::error::Error;
+use cargo_subcommand::EnvOption;
+use ndk_build::env;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The `[env]` table of `$CARGO_HOME/config.toml`.
+#[derive(Debug, Default, Deserialize)]
+struct CargoHomeConfig {
+    env: Option<BTreeMap<String, EnvOption>>,
+}
+
+/// Resolves the `[env]` table of `$CARGO_HOME/config.toml` (`~/.cargo/config.toml`),
+/// so that e.g. `ANDROID_HOME` can be configured once per user.
+///
+/// [`cargo_subcommand::Subcommand::new()`] only applies the `.cargo/config.toml`
+/// closest to the workspace. Call this afterwards: like `cargo`, variables that are
+/// already set, including those from the workspace config, take precedence unless
+/// `force = true` is used. The values are passed on through [`NdkOptions::env`]
+/// rather than set on this process.
+///
+/// [`NdkOptions::env`]: ndk_build::ndk::NdkOptions::env
+pub(crate) fn cargo_home_env() -> Result<BTreeMap<String, String>, Error> {
+    let mut vars = BTreeMap::new();
+    let Some(cargo_home) = env::CARGO_HOME
+        .get_os()
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")))
+    else {
+        return Ok(vars);
+    };
+    let path = cargo_home.join("config.toml");
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Ok(vars);
+    };
+    let config: CargoHomeConfig = toml::from_str(&contents)?;
+    // Relative values are relative to the parent of the `.cargo` directory
+    let config_parent = cargo_home.parent().unwrap_or(&cargo_home);
+    for (key, option) in config.env.unwrap_or_default() {
+        if !matches!(option, EnvOption::Value { force: true, .. }) && env::is_set(&key) {
+            continue;
+        }
+        let value = option
+            .resolve_value(config_parent)
+            .map_err(|e| Error::CargoHomeEnv(key.clone(), e))?
+            .into_owned();
+        vars.insert(key, value);
+    }
+    Ok(vars)
+}
@@ -0,0 +1,292 @@
+//! `inherit_dependency_manifest = true`: merges the parts of the
+//! `[package.metadata.android]` tables of dependencies that describe what a library
+//! needs, like `uses_permission`, into the manifest of the app. Nothing else of these
+//! tables is read, so that a library can't change the rest of the app.
+
+use crate::error::Error;
+use ndk_build::manifest::{
+    AndroidManifest, Feature, IntentFilter, MetaData, Queries, UsesPermission,
+};
+use ndk_build::util::status;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The subset of `[package.metadata.android]` that dependencies pass on.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct DependencyManifest {
+    #[serde(default)]
+    uses_permission: Vec<UsesPermission>,
+    #[serde(default)]
+    uses_feature: Vec<Feature>,
+    queries: Option<Queries>,
+    #[serde(default)]
+    application: DependencyApplication,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DependencyApplication {
+    /// Only merged with [`MetaData::exported_to_dependents`], as most `meta_data` of a
+    /// library configures its own examples
+    #[serde(default)]
+    meta_data: Vec<MetaData>,
+}
+
+impl DependencyManifest {
+    /// Reads the `[package.metadata.android]` table of a dependency, ignoring the keys
+    /// that are not passed on.
+    pub(crate) fn from_json(table: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        Self::deserialize(table)
+    }
+}
+
+/// Merges the `dependencies`, named by their crate, into the `manifest` of the app.
+/// Entries that `manifest` already contains are skipped, as the app settles what its
+/// dependencies disagree on, and each merged one is reported with the crate it came
+/// from. A `uses_permission` with another `max_sdk_version`, or a `meta_data` with
+/// another value than that of an earlier dependency, is a conflict.
+pub(crate) fn merge(
+    manifest: &mut AndroidManifest,
+    dependencies: Vec<(&str, DependencyManifest)>,
+) -> Result<(), Error> {
+    // The crate that merged each entry, `None` for those of the app
+    let mut permissions = manifest
+        .uses_permission
+        .iter()
+        .map(|p| (p.name.clone(), (p.max_sdk_version, None::<String>)))
+        .collect::<HashMap<_, _>>();
+    let mut meta_data = manifest
+        .application
+        .meta_data
+        .iter()
+        .map(|m| (m.name.clone(), (m.value.clone(), None::<String>)))
+        .collect::<HashMap<_, _>>();
+    for (source, dependency) in dependencies {
+        for permission in dependency.uses_permission {
+            match permissions.get(&permission.name) {
+                Some((max_sdk_version, Some(first)))
+                    if *max_sdk_version != permission.max_sdk_version =>
+                {
+                    return Err(Error::DependencyManifestConflict {
+                        entry: format!("`uses_permission` `{}`", permission.name),
+                        first: describe_max_sdk_version(*max_sdk_version),
+                        first_source: first.clone(),
+                        second: describe_max_sdk_version(permission.max_sdk_version),
+                        second_source: source.to_string(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    inherit("uses_permission", &permission.name, source);
+                    permissions.insert(
+                        permission.name.clone(),
+                        (permission.max_sdk_version, Some(source.to_string())),
+                    );
+                    manifest.uses_permission.push(permission);
+                }
+            }
+        }
+
+        for feature in dependency.uses_feature {
+            let same = |f: &Feature| {
+                (f.name.as_ref(), f.version, f.opengles_version)
+                    == (
+                        feature.name.as_ref(),
+                        feature.version,
+                        feature.opengles_version,
+                    )
+            };
+            if !manifest.uses_feature.iter().any(same) {
+                let name = match (&feature.name, feature.opengles_version) {
+                    (Some(name), _) => name.clone(),
+                    (None, Some((major, minor))) => format!("OpenGL ES {}.{}", major, minor),
+                    (None, None) => "<unnamed>".to_string(),
+                };
+                inherit("uses_feature", &name, source);
+                manifest.uses_feature.push(feature);
+            }
+        }
+
+        if let Some(queries) = dependency.queries {
+            merge_queries(
+                manifest.queries.get_or_insert_with(Default::default),
+                queries,
+                source,
+            );
+        }
+
+        for exported in dependency.application.meta_data {
+            if !exported.exported_to_dependents {
+                continue;
+            }
+            match meta_data.get(&exported.name) {
+                Some((value, Some(first))) if *value != exported.value => {
+                    return Err(Error::DependencyManifestConflict {
+                        entry: format!("`meta_data` `{}`", exported.name),
+                        first: format!("value `{}`", value),
+                        first_source: first.clone(),
+                        second: format!("value `{}`", exported.value),
+                        second_source: source.to_string(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    inherit("meta_data", &exported.name, source);
+                    meta_data.insert(
+                        exported.name.clone(),
+                        (exported.value.clone(), Some(source.to_string())),
+                    );
+                    manifest.application.meta_data.push(exported);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn merge_queries(queries: &mut Queries, from: Queries, source: &str) {
+    for package in from.package {
+        if !queries.package.iter().any(|p| p.name == package.name) {
+            inherit("query for package", &package.name, source);
+            queries.package.push(package);
+        }
+    }
+    for provider in from.provider {
+        if !queries
+            .provider
+            .iter()
+            .any(|p| p.authorities == provider.authorities)
+        {
+            inherit("query for provider", &provider.authorities, source);
+            queries.provider.push(provider);
+        }
+    }
+    // Intent filters have no name, they are compared by what they would serialize to
+    let json = |intent: &IntentFilter| serde_json::to_value(intent).unwrap();
+    for intent in from.intent {
+        let value = json(&intent);
+        if !queries.intent.iter().any(|i| json(i) == value) {
+            inherit("query for intent", &intent.actions.join(", "), source);
+            queries.intent.push(intent);
+        }
+    }
+}
+
+fn inherit(what: &str, name: &str, source: &str) {
+    status(
+        "Inheriting",
+        format_args!("{} `{}` from `{}`", what, name, source),
+    );
+}
+
+fn describe_max_sdk_version(max_sdk_version: Option<u32>) -> String {
+    match max_sdk_version {
+        Some(max_sdk_version) => format!("`max_sdk_version = {}`", max_sdk_version),
+        None => "no `max_sdk_version`".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependency(toml: &str) -> DependencyManifest {
+        let value: toml::Value = toml::from_str(toml).unwrap();
+        DependencyManifest::from_json(&serde_json::to_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn merge_subset() {
+        let mut manifest = AndroidManifest::default();
+        manifest.uses_permission.push(UsesPermission {
+            name: "android.permission.INTERNET".to_string(),
+            max_sdk_version: None,
+        });
+        let engine = dependency(
+            r#"
+            package = "com.example.engine"
+            [[uses_permission]]
+            name = "android.permission.INTERNET"
+            [[uses_permission]]
+            name = "android.permission.VIBRATE"
+            [[uses_feature]]
+            name = "android.hardware.vulkan.level"
+            version = 1
+            [queries]
+            package = [{ name = "com.example.store" }]
+            [[application.meta_data]]
+            name = "engine.backend"
+            value = "vulkan"
+            exported_to_dependents = true
+            [[application.meta_data]]
+            name = "engine.example"
+            value = "triangle"
+            "#,
+        );
+        let audio = dependency(
+            r#"
+            [[uses_permission]]
+            name = "android.permission.VIBRATE"
+            [[uses_feature]]
+            name = "android.hardware.vulkan.level"
+            version = 1
+            "#,
+        );
+        merge(&mut manifest, vec![("engine", engine), ("audio", audio)]).unwrap();
+
+        let permissions = manifest
+            .uses_permission
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            permissions,
+            ["android.permission.INTERNET", "android.permission.VIBRATE"]
+        );
+        assert_eq!(manifest.uses_feature.len(), 1);
+        assert_eq!(
+            manifest.queries.unwrap().package[0].name,
+            "com.example.store"
+        );
+        let meta_data = &manifest.application.meta_data;
+        assert_eq!(meta_data.len(), 1);
+        assert_eq!(meta_data[0].name, "engine.backend");
+        // Not part of the subset
+        assert_eq!(manifest.package, "");
+    }
+
+    #[test]
+    fn conflict() {
+        let storage = |max_sdk_version| {
+            dependency(&format!(
+                "[[uses_permission]]\nname = \"android.permission.WRITE_EXTERNAL_STORAGE\"\n\
+                max_sdk_version = {}",
+                max_sdk_version
+            ))
+        };
+        let error = merge(
+            &mut AndroidManifest::default(),
+            vec![("engine", storage(28)), ("saves", storage(32))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`uses_permission` `android.permission.WRITE_EXTERNAL_STORAGE` is declared with \
+            `max_sdk_version = 28` by `engine` and with `max_sdk_version = 32` by `saves`, \
+            declare it in the app to settle on one"
+        );
+
+        // Unless the app declares it
+        let mut manifest = AndroidManifest::default();
+        manifest.uses_permission.push(UsesPermission {
+            name: "android.permission.WRITE_EXTERNAL_STORAGE".to_string(),
+            max_sdk_version: Some(29),
+        });
+        merge(
+            &mut manifest,
+            vec![("engine", storage(28)), ("saves", storage(32))],
+        )
+        .unwrap();
+        assert_eq!(manifest.uses_permission.len(), 1);
+        assert_eq!(manifest.uses_permission[0].max_sdk_version, Some(29));
+    }
+}
@@ -0,0 +1,147 @@
+//! `cargo apk devices`: lists the connected devices with their model, Android version,
+//! ABIs, screen and free storage.
+
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::resolve::artifact_manifest;
+use cargo_subcommand::Subcommand;
+use ndk_build::ndk::transport::SshRemote;
+use ndk_build::ndk::{AdbDevice, DeviceInfo, Ndk, NdkOptions};
+use ndk_build::util::format_size;
+
+/// Prints a [`DeviceInfo`] for every device known to `adb`, or only for `device`, as
+/// text or as a JSON array. With `remote`, these are the devices of its adb server.
+///
+/// When invoked inside a package with a single artifact, `cmd` provides the package id
+/// whose installation and `versionCode` are reported for every device.
+pub fn devices(
+    cmd: Option<&Subcommand>,
+    device: Option<&str>,
+    remote: Option<SshRemote>,
+    json: bool,
+) -> Result<(), Error> {
+    let manifest = cmd
+        .map(|cmd| Manifest::parse_from_toml(cmd.manifest()))
+        .transpose()?;
+    let ndk = Ndk::from_env_with(&NdkOptions {
+        sdk_path: manifest.as_ref().and_then(|m| m.sdk_path.clone()),
+        remote,
+        env: crate::config::cargo_home_env()?,
+        ..Default::default()
+    })?;
+    let package = cmd.zip(manifest.as_ref()).and_then(|(cmd, manifest)| {
+        let mut artifacts = cmd.artifacts();
+        match (artifacts.next(), artifacts.next()) {
+            (Some(artifact), None) => Some(artifact_manifest(manifest, artifact).package),
+            _ => None,
+        }
+    });
+
+    let mut devices = ndk.devices()?;
+    if let Some(serial) = device {
+        devices.retain(|device| device.serial == serial);
+        if devices.is_empty() {
+            devices.push(AdbDevice {
+                serial: serial.to_string(),
+                state: "not found".to_string(),
+            });
+        }
+    }
+    let infos = devices
+        .iter()
+        .map(|device| ndk.device_info(device, package.as_deref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&infos).unwrap());
+    } else if infos.is_empty() {
+        println!("No devices attached");
+    } else {
+        for info in &infos {
+            print!("{}", describe(info));
+        }
+    }
+    Ok(())
+}
+
+/// The serial and state of the device, followed by one indented line per known detail.
+fn describe(info: &DeviceInfo) -> String {
+    let mut text = format!("{}\t{}\n", info.serial, info.state);
+    let mut line = |label: &str, value: String| {
+        text.push_str(&format!("    {:<9}{}\n", format!("{}:", label), value));
+    };
+    if let Some(model) = &info.model {
+        line("model", model.clone());
+    }
+    match (&info.release, info.sdk) {
+        (Some(release), Some(sdk)) => line("android", format!("{} (API {})", release, sdk)),
+        (Some(release), None) => line("android", release.clone()),
+        (None, Some(sdk)) => line("android", format!("API {}", sdk)),
+        (None, None) => {}
+    }
+    if !info.abis.is_empty() {
+        line("abis", info.abis.join(", "));
+    }
+    match (&info.resolution, info.density) {
+        (Some(resolution), Some(density)) => {
+            line("screen", format!("{} @ {}dpi", resolution, density))
+        }
+        (Some(resolution), None) => line("screen", resolution.clone()),
+        (None, Some(density)) => line("screen", format!("{}dpi", density)),
+        (None, None) => {}
+    }
+    if let Some(free_data) = info.free_data {
+        line("storage", format!("{} free", format_size(free_data)));
+    }
+    if let Some(package) = &info.package {
+        let status = match package.version_code {
+            Some(version_code) => format!("installed (versionCode {})", version_code),
+            None => "not installed".to_string(),
+        };
+        line("package", format!("{} {}", package.name, status));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndk_build::ndk::InstalledPackage;
+
+    #[test]
+    fn describe_devices() {
+        let info = DeviceInfo {
+            serial: "emulator-5554".to_string(),
+            state: "device".to_string(),
+            model: Some("sdk_gphone64_x86_64".to_string()),
+            release: Some("14".to_string()),
+            sdk: Some(34),
+            abis: vec!["x86_64".to_string(), "arm64-v8a".to_string()],
+            resolution: Some("1080x2400".to_string()),
+            density: Some(420),
+            free_data: Some(83292808 * 1024),
+            package: Some(InstalledPackage {
+                name: "com.example.app".to_string(),
+                installed: true,
+                version_code: Some(3),
+            }),
+        };
+        assert_eq!(
+            describe(&info),
+            "emulator-5554\tdevice\n    \
+            model:   sdk_gphone64_x86_64\n    \
+            android: 14 (API 34)\n    \
+            abis:    x86_64, arm64-v8a\n    \
+            screen:  1080x2400 @ 420dpi\n    \
+            storage: 79.43 GiB free\n    \
+            package: com.example.app installed (versionCode 3)\n"
+        );
+
+        let unauthorized = DeviceInfo {
+            serial: "R58M12ABCDE".to_string(),
+            state: "unauthorized".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(describe(&unauthorized), "R58M12ABCDE\tunauthorized\n");
+    }
+}
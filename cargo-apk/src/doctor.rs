@@ -0,0 +1,76 @@
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::resolve::requested_min_sdk_version;
+use cargo_subcommand::Subcommand;
+use ndk_build::ndk::NdkOptions;
+use ndk_build::ndk::doctor::{Environment, Status, default_checks};
+
+/// Prints the status of every component checked by [`default_checks()`], and fails if
+/// a required one is missing.
+///
+/// When invoked inside a package, `cmd` provides its `minSdkVersion`,
+/// `targetSdkVersion`, `ndk_version` requirement, `build_tools_version`,
+/// `compile_sdk_version` and `packaging_backend`.
+pub fn doctor(cmd: Option<&Subcommand>) -> Result<(), Error> {
+    let (lines, missing) = check_lines(cmd)?;
+    for line in lines {
+        println!("{}", line);
+    }
+    if missing > 0 {
+        Err(Error::DoctorFailed(missing))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs [`default_checks()`], returning a line for every check and how many required
+/// ones are missing.
+pub(crate) fn check_lines(cmd: Option<&Subcommand>) -> Result<(Vec<String>, usize), Error> {
+    let config_env = crate::config::cargo_home_env()?;
+    let manifest = cmd
+        .map(|cmd| Manifest::parse_from_toml(cmd.manifest()))
+        .transpose()?;
+    let env = match manifest {
+        Some(manifest) => {
+            let sdk = &manifest.android_manifest.sdk;
+            Environment::from_env(
+                NdkOptions {
+                    sdk_path: manifest.sdk_path.clone(),
+                    ndk_path: manifest.ndk_path.clone(),
+                    ndk_version: manifest.ndk_version.clone(),
+                    build_tools_version: manifest.build_tools_version.clone(),
+                    compile_sdk_version: manifest.compile_sdk_version,
+                    packaging_backend: manifest.packaging_backend,
+                    env: config_env,
+                    ..Default::default()
+                },
+                requested_min_sdk_version(&manifest),
+                sdk.target_sdk_version,
+            )
+        }
+        None => Environment::from_env(
+            NdkOptions {
+                env: config_env,
+                ..Default::default()
+            },
+            23,
+            None,
+        ),
+    };
+
+    let mut missing = 0;
+    let mut lines = vec![];
+    for check in default_checks() {
+        let (label, message) = match check.run(&env) {
+            Status::Ok(found) => ("ok", found),
+            Status::Warning(warning) => ("warning", warning),
+            Status::Missing(hint) if check.required() => {
+                missing += 1;
+                ("missing", hint)
+            }
+            Status::Missing(hint) => ("optional", hint),
+        };
+        lines.push(format!("[{:>8}] {}: {}", label, check.name(), message));
+    }
+    Ok((lines, missing))
+}
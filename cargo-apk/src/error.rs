@@ -1,6 +1,8 @@
-use cargo_subcommand::Error as SubcommandError;
+use cargo_subcommand::{EnvError, Error as SubcommandError};
 use ndk_build::error::NdkError;
 use std::io::Error as IoError;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use thiserror::Error;
 use toml::de::Error as TomlError;
 
@@ -22,6 +24,262 @@ pub enum Error {
     InheritanceMissingWorkspace,
     #[error("Failed to inherit field: `workspace.{0}` was not defined in workspace root manifest")]
     WorkspaceMissingInheritedField(&'static str),
+    #[error("Failed to set `{0}` from the `[env]` table in `$CARGO_HOME/config.toml`: {1}")]
+    CargoHomeEnv(String, EnvError),
+    #[error(
+        "minSdkVersion {min_sdk_version} is not supported by the NDK, which supports API levels {} to {}",
+        .supported.start(),
+        .supported.end()
+    )]
+    MinSdkVersionUnsupported {
+        min_sdk_version: u32,
+        supported: RangeInclusive<u32>,
+    },
+    #[error("targetSdkVersion {target_sdk_version} is lower than minSdkVersion {min_sdk_version}")]
+    TargetSdkVersionTooLow {
+        target_sdk_version: u32,
+        min_sdk_version: u32,
+    },
+    #[error("Destination `{0}` already exists")]
+    ProjectExists(PathBuf),
+    #[error("Cannot derive a package name from `{0}`")]
+    InvalidProjectName(String),
+    #[error(
+        "Invalid package id `{0}`, expected at least two `.`-separated segments of letters, \
+        digits and `_` that start with a letter, like `com.example.foo`"
+    )]
+    InvalidPackageId(String),
+    #[error("{0} required component(s) missing, see above")]
+    DoctorFailed(usize),
+    #[error("{0} lint error(s), see above")]
+    LintFailed(usize),
+    #[error(
+        "Setting `{0}` in `[package.metadata.android]` is not allowed, it is derived from \
+        `package.version`. Remove it and bump the package version instead"
+    )]
+    DerivedManifestField(&'static str),
+    #[error(
+        "`scheme = \"explicit\"` requires a `code` in \
+        `[package.metadata.android.version_code]`"
+    )]
+    ExplicitVersionCodeMissing,
+    #[error("`version.workspace = true` requires a `[workspace]` table in `{0}`")]
+    WorkspaceTableMissing(PathBuf),
+    #[error("Manifest `{}` must contain a `[package]` table", .0.display())]
+    PackageTableMissing(PathBuf),
+    #[error("Manifest path `{0}` has no parent directory")]
+    InvalidManifestPath(PathBuf),
+    #[error("Keystore `{path}` configured through {origin} does not exist")]
+    KeystoreNotFound { path: PathBuf, origin: String },
+    #[error("`[package.metadata.android]` has unknown keys:\n  {}", .0.join("\n  "))]
+    UnknownManifestKeys(Vec<String>),
+    #[error("`{0}` in `[package.metadata.android.hooks]` contains an empty command")]
+    EmptyHookCommand(&'static str),
+    #[error("`{hook}` hook failed, set `allow_failure = true` on it to continue anyway:\n{error}")]
+    HookFailed {
+        hook: &'static str,
+        error: Box<NdkError>,
+    },
+    #[error("`asset_transform` in `[package.metadata.android]` contains an empty command")]
+    EmptyAssetTransformCommand,
+    #[error("`asset_transform` failed on `{}`:\n{error}", .asset.display())]
+    AssetTransformFailed {
+        asset: PathBuf,
+        error: Box<NdkError>,
+    },
+    #[error(
+        "`asset_transform` did not write the staged file of `{}`, `{command}` must write \
+        to `{{dst}}`",
+        .asset.display()
+    )]
+    AssetTransformNoOutput { asset: PathBuf, command: String },
+    #[error(
+        "{entry} is declared with {first} by `{first_source}` and with {second} by \
+        `{second_source}`, declare it in the app to settle on one"
+    )]
+    DependencyManifestConflict {
+        entry: String,
+        first: String,
+        first_source: String,
+        second: String,
+        second_source: String,
+    },
+    #[error("Failed to read `[package.metadata.android]` of dependency `{0}`: {1}")]
+    DependencyManifest(String, serde_json::Error),
+    #[error("No publisher is configured, add one to `[package.metadata.android.publish.<name>]`")]
+    NoPublisher,
+    #[error("Publisher `{0}` is not configured, available: {}", .1.join(", "))]
+    UnknownPublisher(String, Vec<String>),
+    #[error("Several publishers are configured, pick one of: {}", .0.join(", "))]
+    AmbiguousPublisher(Vec<String>),
+    #[error("`${var}` must be set for the `{header}` header of the publisher")]
+    PublishEnvMissing { header: String, var: String },
+    #[error("Failed to parse the output of `cargo metadata`: {0}")]
+    CargoMetadata(serde_json::Error),
+    #[error("Package `{0}` is missing from the output of `cargo metadata`")]
+    PackageNotInMetadata(String),
+    #[error("`$SOURCE_DATE_EPOCH` must be a number of seconds, found `{0}`")]
+    InvalidSourceDateEpoch(String),
+    #[error(
+        "Flavor `{0}` is not configured in `[package.metadata.android.flavor]`, available: {}",
+        list_or_none(.1)
+    )]
+    UnknownFlavor(String, Vec<String>),
+    #[error("No {kind} target named `{name}` in package `{package}`, available: {}", list_or_none(.available))]
+    ArtifactNotFound {
+        kind: &'static str,
+        name: String,
+        package: String,
+        available: Vec<String>,
+    },
+    #[error("Several targets are selected, pick one with {}", .0.join(", "))]
+    AmbiguousArtifact(Vec<String>),
+    #[error("Package `{0}` has no library or binary, select an example with `--example <NAME>`")]
+    NoArtifact(String),
+    #[error(
+        "Binary `{0}` cannot be packaged, Android loads apps from a `cdylib`. Move the code \
+        into the library or an example with `crate-type = [\"cdylib\"]`"
+    )]
+    BinNotPackageable(String),
+    #[error(
+        "`activity_alias` `{alias}` targets `{target}`, which is not an activity of the manifest, \
+        only `{activity}` is"
+    )]
+    UnknownTargetActivity {
+        alias: String,
+        target: String,
+        activity: String,
+    },
+    #[error(
+        "Permission `{permission}` has an invalid `protection_level` `{level}`, expected one of \
+        `normal`, `dangerous`, `signature` or `internal`, optionally combined with flags like \
+        `signature|privileged`"
+    )]
+    InvalidProtectionLevel { permission: String, level: String },
+    #[error(
+        "Activity has an invalid `window_layout_in_display_cutout_mode` `{0}`, expected one of \
+        `default`, `shortEdges`, `never` or `always`"
+    )]
+    InvalidDisplayCutoutMode(String),
+    #[error(
+        "`target_sdk = \"{0}\"` of `application.activity.when` is not a comma-separated list of \
+        comparisons with an API level, like `\">= 24, < 34\"`"
+    )]
+    InvalidSdkCondition(String),
+    #[error(
+        "Not installing on an incompatible device: {}. Pass `--force-install` to try anyway",
+        .0.join("; ")
+    )]
+    IncompatibleDevice(Vec<String>),
+    #[error(
+        "Not downgrading the installed `versionCode` {installed} to the {apk} of the APK, \
+        as `--fail-on-downgrade` was passed"
+    )]
+    Downgrade { installed: u32, apk: u32 },
+    #[error(
+        "No apk was built at `{}` to install with `--no-build`, build it first or pass \
+        `--no-install` to start the installed app",
+        .0.display()
+    )]
+    ApkNotBuilt(PathBuf),
+    #[error("`cargo {0}` failed for {}", .1.join(", "))]
+    TargetsFailed(&'static str, Vec<String>),
+    #[error(
+        "{kind} `{name}` is not built as a `cdylib`, which Android loads apps from. Add this \
+        to `Cargo.toml`:\n\n{snippet}"
+    )]
+    CdylibMissing {
+        kind: &'static str,
+        name: String,
+        snippet: String,
+    },
+    #[error(
+        "`{0}` is experimental, enable it with `experimental = [\"{0}\"]` in \
+        `[package.metadata.android]`"
+    )]
+    ExperimentalFeature(&'static str),
+    #[error(
+        "Invalid split name `{0}` in `split_assets`, names must be unique and only contain \
+        ASCII letters, digits and `_`"
+    )]
+    InvalidSplitName(String),
+    #[error("{0} sets `older_keys` or `rotation_min_sdk_version` without a `lineage`")]
+    LineageMissing(String),
+    #[error(
+        "Signing lineage `{}` configured through {origin} does not exist, create it with \
+        `apksigner rotate`",
+        .path.display()
+    )]
+    LineageNotFound { path: PathBuf, origin: String },
+    #[error(
+        "`vulkan_validation` requires API {} to enable the validation layer, the device is \
+        API {0}",
+        ndk_build::vulkan::GPU_DEBUG_LAYERS_MIN_API_LEVEL
+    )]
+    GpuDebugLayersUnsupported(u32),
+    #[error("`--watch-assets` requires `assets` in `[package.metadata.android]` or the flavor")]
+    NoAssetsToWatch,
+    #[error("Failed to watch `{}` for changes: {source}", .path.display())]
+    Watch {
+        path: PathBuf,
+        source: notify::Error,
+    },
+    #[error(
+        "`lib_name = \"{lib_name}\"` of `[package.metadata.android.target.{abi}]` names \
+        `lib{lib_name}.so`, which the build did not produce"
+    )]
+    LibNameNotBuilt { lib_name: String, abi: &'static str },
+    #[error(
+        "`{}` is {size}, more than `max_apk_size_mb = {limit_mb}` allows. {details}",
+        .apk.display()
+    )]
+    ApkTooLarge {
+        apk: PathBuf,
+        size: String,
+        limit_mb: u64,
+        details: String,
+    },
+    #[error("`{key}` uses `${var}`, which is not set")]
+    UndefinedPathVariable { key: String, var: String },
+    #[error(
+        "`{key}` resolves to `{}`, outside of the workspace at `{}`. Set \
+        `allow_external_paths = true` in `[package.metadata.android]` to allow it",
+        .path.display(),
+        .workspace.display()
+    )]
+    ExternalPath {
+        key: String,
+        path: PathBuf,
+        workspace: PathBuf,
+    },
+    #[error(
+        "minSdkVersion {min_sdk_version} is below API level {lowest}, the lowest that NDK \
+        {ndk_version} supports, also with `allow_low_minsdk`. Raise `min_sdk_version` or \
+        select an older NDK with `ndk_version`"
+    )]
+    LowMinSdkVersionUnsupported {
+        min_sdk_version: u32,
+        lowest: u32,
+        ndk_version: String,
+    },
+    #[error(
+        "`{feature}` in the `target_features` of `[package.metadata.android.target.{abi}]` \
+        is not a feature enabled with `+` or disabled with `-`, like `+neon`"
+    )]
+    InvalidTargetFeature { abi: &'static str, feature: String },
+    #[error(
+        "Google Play requires 64-bit native code, but {missing} is built. Add the 64-bit \
+        ABI to `build_targets` or unset `require_64bit`"
+    )]
+    Missing64BitAbi { missing: String },
+}
+
+fn list_or_none(names: &[String]) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
 }
 
 impl Error {
@@ -29,3 +287,150 @@ impl Error {
         Self::Subcommand(SubcommandError::InvalidArgs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snapshots the wording of errors caused by configuration mistakes, see
+    /// `testdata/error_messages.txt`.
+    #[test]
+    fn error_messages() {
+        let errors = [
+            Error::DerivedManifestField("version_code"),
+            Error::WorkspaceTableMissing("/ws/Cargo.toml".into()),
+            Error::PackageTableMissing("/ws/Cargo.toml".into()),
+            Error::InvalidManifestPath("".into()),
+            Error::KeystoreNotFound {
+                path: "/app/release.keystore".into(),
+                origin: "`[package.metadata.android.signing.release]`".to_string(),
+            },
+            Error::MissingReleaseKey("release".to_string()),
+            Error::InheritanceMissingWorkspace,
+            Error::WorkspaceMissingInheritedField("package.version"),
+            NdkError::ConflictingRustflags.into(),
+            NdkError::NonUnicodeEnv("RUSTFLAGS").into(),
+            NdkError::NonUtf8Path("/target/cargo-apk-temp-extra-link-libraries".into()).into(),
+            Error::EmptyHookCommand("post_build"),
+            Error::EmptyAssetTransformCommand,
+            Error::AssetTransformNoOutput {
+                asset: "/app/assets/main.lua".into(),
+                command: "./tools/encrypt.sh /app/assets/main.lua /app/out/main.lua".to_string(),
+            },
+            Error::DependencyManifestConflict {
+                entry: "`meta_data` `engine.backend`".to_string(),
+                first: "value `vulkan`".to_string(),
+                first_source: "engine".to_string(),
+                second: "value `gles`".to_string(),
+                second_source: "renderer".to_string(),
+            },
+            Error::NoPublisher,
+            Error::UnknownPublisher("nightly".to_string(), vec!["beta".to_string()]),
+            Error::AmbiguousPublisher(vec!["beta".to_string(), "upload".to_string()]),
+            Error::PublishEnvMissing {
+                header: "Authorization".to_string(),
+                var: "UPLOAD_TOKEN".to_string(),
+            },
+            Error::PackageNotInMetadata("my-app".to_string()),
+            Error::InvalidSourceDateEpoch("yesterday".to_string()),
+            Error::UnknownFlavor(
+                "trial".to_string(),
+                vec!["demo".to_string(), "full".to_string()],
+            ),
+            Error::UnknownFlavor("demo".to_string(), vec![]),
+            Error::ArtifactNotFound {
+                kind: "example",
+                name: "shadow-maping".to_string(),
+                package: "app".to_string(),
+                available: vec!["shadow-mapping".to_string(), "triangle".to_string()],
+            },
+            Error::AmbiguousArtifact(vec!["--bin app".to_string(), "--bin tool".to_string()]),
+            Error::NoArtifact("app".to_string()),
+            Error::BinNotPackageable("tool".to_string()),
+            Error::UnknownTargetActivity {
+                alias: ".WinterIcon".to_string(),
+                target: ".MainActivity".to_string(),
+                activity: "android.app.NativeActivity".to_string(),
+            },
+            Error::InvalidProtectionLevel {
+                permission: "com.example.permission.SHARE".to_string(),
+                level: "signatures".to_string(),
+            },
+            Error::InvalidDisplayCutoutMode("short_edges".to_string()),
+            Error::InvalidSdkCondition("> = 24".to_string()),
+            Error::IncompatibleDevice(vec![
+                "device is API 26, APK requires 28".to_string(),
+                "device supports armeabi-v7a, APK contains arm64-v8a".to_string(),
+            ]),
+            Error::ApkNotBuilt("/app/target/debug/apk/app.apk".into()),
+            Error::TargetsFailed("clippy", vec!["armeabi-v7a".to_string(), "x86".to_string()]),
+            Error::CdylibMissing {
+                kind: "Library",
+                name: "app".to_string(),
+                snippet: "[lib]\ncrate-type = [\"rlib\", \"cdylib\"]".to_string(),
+            },
+            Error::ExperimentalFeature("split_assets"),
+            Error::InvalidSplitName("pt-BR".to_string()),
+            Error::LineageMissing("`[package.metadata.android.signing.release]`".to_string()),
+            Error::LineageNotFound {
+                path: "/app/upload.lineage".into(),
+                origin: "`[package.metadata.android.signing.release]`".to_string(),
+            },
+            Error::GpuDebugLayersUnsupported(27),
+            Error::NoAssetsToWatch,
+            Error::Watch {
+                path: "/app/assets".into(),
+                source: notify::Error::path_not_found(),
+            },
+            Error::ExplicitVersionCodeMissing,
+            NdkError::VersionCodeComponentTooLarge {
+                component: "minor",
+                value: 256,
+                max: 255,
+            }
+            .into(),
+            NdkError::VersionCodeTooLarge(2_104_050_100).into(),
+            Error::LibNameNotBuilt {
+                lib_name: "game64".into(),
+                abi: "arm64-v8a",
+            },
+            NdkError::AaptFailed(2).into(),
+            Error::Downgrade {
+                installed: 1000300,
+                apk: 1000100,
+            },
+            Error::ApkTooLarge {
+                apk: "/app/target/release/apk/app.apk".into(),
+                size: "131.20 MiB".to_string(),
+                limit_mb: 100,
+                details: "Largest parts, compressed:\n    - strip the libraries".to_string(),
+            },
+            Error::UndefinedPathVariable {
+                key: "assets".to_string(),
+                var: "ASSET_ROOT".to_string(),
+            },
+            Error::ExternalPath {
+                key: "flavor.demo.assets".to_string(),
+                path: "/shared/art".into(),
+                workspace: "/ws".into(),
+            },
+            Error::LowMinSdkVersionUnsupported {
+                min_sdk_version: 16,
+                lowest: 21,
+                ndk_version: "26.1.10909125".to_string(),
+            },
+            Error::InvalidTargetFeature {
+                abi: "armeabi-v7a",
+                feature: "neon".to_string(),
+            },
+            Error::Missing64BitAbi {
+                missing: "`armeabi-v7a` without `arm64-v8a`".to_string(),
+            },
+        ];
+        let messages = errors
+            .iter()
+            .map(|e| format!("{}\n", e))
+            .collect::<String>();
+        assert_eq!(messages, include_str!("../testdata/error_messages.txt"));
+    }
+}
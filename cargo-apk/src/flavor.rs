@@ -0,0 +1,90 @@
+//! `[package.metadata.android.flavor.<name>]`: variants of an app selected with
+//! `--flavor`, which [`crate::ApkBuilder`] applies over the rest of the metadata.
+
+use crate::error::Error;
+use crate::form_factor::FormFactor;
+use crate::manifest::Signing;
+use ndk_build::util::Exec;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct Flavor {
+    /// The key of the table, filled in by [`crate::manifest::Manifest::apply_flavor()`]
+    #[serde(skip)]
+    pub(crate) name: String,
+    /// Appended to the package id, like `.demo`
+    pub(crate) package_suffix: Option<String>,
+    /// Replaces `application.label`
+    pub(crate) label: Option<String>,
+    /// Replaces `form_factor`, e.g. for a TV variant of a phone app
+    pub(crate) form_factor: Option<FormFactor>,
+    /// Directory merged over `assets`, replacing the files at the same paths
+    pub(crate) assets: Option<PathBuf>,
+    /// Enabled in addition to `--features`
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+    /// Maps profiles to keystores, taking precedence over `[package.metadata.android.signing]`
+    #[serde(default)]
+    pub(crate) signing: HashMap<String, Signing>,
+}
+
+/// Copies `base` and then `overlay` into a fresh `out` directory, so that files of
+/// `overlay` replace those of `base`.
+pub(crate) fn merge_assets(
+    exec: Exec,
+    base: Option<&Path>,
+    overlay: &Path,
+    out: &Path,
+) -> Result<(), Error> {
+    exec.remove(out)?;
+    for dir in base.into_iter().chain([overlay]) {
+        copy_dir(exec, dir, out)?;
+    }
+    Ok(())
+}
+
+fn copy_dir(exec: Exec, from: &Path, to: &Path) -> Result<(), Error> {
+    exec.create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(exec, &entry.path(), &to)?;
+        } else {
+            exec.copy(&entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn merge() {
+        let dir = TestDir::new("flavor");
+        for (path, contents) in [
+            ("base/config.json", "base"),
+            ("base/textures/grass.png", "grass"),
+            ("demo/config.json", "demo"),
+            ("demo/levels/1.txt", "level"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let out = dir.join("out");
+        std::fs::create_dir_all(out.join("removed")).unwrap();
+
+        merge_assets(Exec::Run, Some(&dir.join("base")), &dir.join("demo"), &out).unwrap();
+        let read = |path: &str| std::fs::read_to_string(out.join(path)).unwrap();
+        assert_eq!(read("config.json"), "demo");
+        assert_eq!(read("textures/grass.png"), "grass");
+        assert_eq!(read("levels/1.txt"), "level");
+        assert!(!out.join("removed").exists());
+    }
+}
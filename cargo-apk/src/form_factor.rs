@@ -0,0 +1,77 @@
+//! `form_factor`: presets of manifest additions for Android TV and Wear OS, applied by
+//! [`crate::resolve::resolve_manifest()`]. Presets only fill in what the manifest
+//! doesn't set explicitly.
+
+use ndk_build::manifest::{AndroidManifest, Feature, MetaData};
+use serde::Deserialize;
+
+/// The banner that Android TV requires, unless `application.banner` is set.
+pub(crate) const DEFAULT_BANNER: &str = "@drawable/banner";
+
+const LEANBACK_LAUNCHER: &str = "android.intent.category.LEANBACK_LAUNCHER";
+const WEARABLE_STANDALONE: &str = "com.google.android.wearable.standalone";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FormFactor {
+    /// No additions
+    #[default]
+    Phone,
+    /// Android TV: requires the `android.software.leanback` feature but no touchscreen,
+    /// launches from the `LEANBACK_LAUNCHER` and shows a `banner`
+    Tv,
+    /// Wear OS: requires the `android.hardware.type.watch` feature and runs without a
+    /// companion phone app
+    Watch,
+}
+
+impl FormFactor {
+    pub(crate) fn apply(self, manifest: &mut AndroidManifest) {
+        match self {
+            Self::Phone => {}
+            Self::Tv => {
+                add_feature(manifest, "android.software.leanback", true);
+                add_feature(manifest, "android.hardware.touchscreen", false);
+                let application = &mut manifest.application;
+                application
+                    .banner
+                    .get_or_insert_with(|| DEFAULT_BANNER.to_string());
+                let filters = &mut application.activity.intent_filter;
+                let has_leanback_launcher = filters
+                    .iter()
+                    .any(|filter| filter.categories.iter().any(|c| c == LEANBACK_LAUNCHER));
+                if !has_leanback_launcher {
+                    for filter in filters.iter_mut().filter(|filter| filter.is_launcher()) {
+                        filter.categories.push(LEANBACK_LAUNCHER.to_string());
+                    }
+                }
+            }
+            Self::Watch => {
+                add_feature(manifest, "android.hardware.type.watch", true);
+                let meta_data = &mut manifest.application.meta_data;
+                if !meta_data.iter().any(|m| m.name == WEARABLE_STANDALONE) {
+                    meta_data.push(MetaData {
+                        name: WEARABLE_STANDALONE.to_string(),
+                        value: "true".to_string(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Adds `<uses-feature android:name="{name}">`, unless the manifest declares it already.
+fn add_feature(manifest: &mut AndroidManifest, name: &str, required: bool) {
+    if manifest
+        .uses_feature
+        .iter()
+        .all(|feature| feature.name.as_deref() != Some(name))
+    {
+        manifest.uses_feature.push(Feature {
+            name: Some(name.to_string()),
+            required: Some(required),
+            ..Default::default()
+        });
+    }
+}
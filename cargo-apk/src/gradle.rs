@@ -0,0 +1,208 @@
+//! `output_layout = "gradle"`: copies the signed apk for [`crate::ApkBuilder::build()`]
+//! to where scripts written for the Android Gradle plugin look for it, like
+//! `build/outputs/apk/<flavor>/<buildType>/<module>-<flavor>-<buildType>.apk`, next to
+//! an `output-metadata.json` in the format of AGP 8.
+
+use crate::error::Error;
+use ndk_build::util::Exec;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where the Gradle layout is rooted by default, relative to the manifest.
+pub(crate) const DEFAULT_OUTPUT_DIR: &str = "build/outputs/apk";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputLayout {
+    /// Only `target/<profile>/apk/<package>/`
+    #[default]
+    Native,
+    /// Also copy the apk into the layout of the Android Gradle plugin
+    Gradle,
+}
+
+/// One apk in the Gradle layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct GradleOutput<'a> {
+    /// Prefix of the file name, which is the module name in Gradle projects
+    pub(crate) module: &'a str,
+    pub(crate) flavor: Option<&'a str>,
+    /// The profile name, with `dev` mapped to Gradle's `debug` build type
+    pub(crate) build_type: &'a str,
+    pub(crate) application_id: &'a str,
+    pub(crate) version_code: Option<u32>,
+    pub(crate) version_name: Option<&'a str>,
+    pub(crate) min_sdk_version: u32,
+}
+
+/// `output-metadata.json` of AGP 8, as read by fastlane and other tools.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutputMetadata<'a> {
+    version: u32,
+    artifact_type: ArtifactType,
+    application_id: &'a str,
+    variant_name: String,
+    elements: [Element<'a>; 1],
+    element_type: &'static str,
+    min_sdk_version_for_dexing: u32,
+}
+
+#[derive(Serialize)]
+struct ArtifactType {
+    r#type: &'static str,
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Element<'a> {
+    r#type: &'static str,
+    filters: [(); 0],
+    attributes: [(); 0],
+    version_code: Option<u32>,
+    version_name: Option<&'a str>,
+    output_file: String,
+}
+
+/// Gradle's name of the build type of `profile`.
+pub(crate) fn build_type(profile: &str) -> &str {
+    match profile {
+        "dev" => "debug",
+        profile => profile,
+    }
+}
+
+impl GradleOutput<'_> {
+    /// `<flavor>/<buildType>/`, relative to the root of the layout.
+    fn dir(&self) -> PathBuf {
+        self.flavor.into_iter().chain([self.build_type]).collect()
+    }
+
+    /// Like `app-demo-release.apk`.
+    fn file_name(&self) -> String {
+        let mut name = self.module.to_string();
+        for part in self.flavor.into_iter().chain([self.build_type]) {
+            name.push('-');
+            name.push_str(part);
+        }
+        name + ".apk"
+    }
+
+    /// Like `demoRelease`.
+    fn variant_name(&self) -> String {
+        match self.flavor {
+            Some(flavor) => {
+                let mut chars = self.build_type.chars();
+                let first = chars.next().map(|c| c.to_ascii_uppercase());
+                format!(
+                    "{}{}{}",
+                    flavor,
+                    first.into_iter().collect::<String>(),
+                    chars.as_str()
+                )
+            }
+            None => self.build_type.to_string(),
+        }
+    }
+
+    fn metadata_json(&self) -> String {
+        let metadata = OutputMetadata {
+            version: 3,
+            artifact_type: ArtifactType {
+                r#type: "APK",
+                kind: "Directory",
+            },
+            application_id: self.application_id,
+            variant_name: self.variant_name(),
+            elements: [Element {
+                r#type: "SINGLE",
+                filters: [],
+                attributes: [],
+                version_code: self.version_code,
+                version_name: self.version_name,
+                output_file: self.file_name(),
+            }],
+            element_type: "File",
+            min_sdk_version_for_dexing: self.min_sdk_version,
+        };
+        serde_json::to_string_pretty(&metadata).unwrap()
+    }
+
+    /// Where [`GradleOutput::write()`] copies the apk to in `root`.
+    pub(crate) fn path(&self, root: &Path) -> PathBuf {
+        root.join(self.dir()).join(self.file_name())
+    }
+
+    /// Copies `apk` into `root` and writes the `output-metadata.json` next to it,
+    /// returning the path of the copy.
+    pub(crate) fn write(&self, exec: Exec, root: &Path, apk: &Path) -> Result<PathBuf, Error> {
+        let dir = root.join(self.dir());
+        exec.create_dir_all(&dir)?;
+        let path = self.path(root);
+        exec.copy(apk, &path)?;
+        exec.write(&dir.join("output-metadata.json"), self.metadata_json())?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn layout() {
+        let dir = TestDir::new("gradle");
+        let apk = dir.join("my-app.apk");
+        std::fs::write(&apk, "apk").unwrap();
+        let output = GradleOutput {
+            module: "app",
+            flavor: Some("demo"),
+            build_type: build_type("release"),
+            application_id: "com.example.app.demo",
+            version_code: Some(16843267),
+            version_name: Some("1.2.3"),
+            min_sdk_version: 24,
+        };
+
+        let root = dir.join("build/outputs/apk");
+        let path = output.write(Exec::Run, &root, &apk).unwrap();
+        assert_eq!(path, root.join("demo/release/app-demo-release.apk"));
+        assert_eq!(path, output.path(&root));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "apk");
+        assert_eq!(
+            std::fs::read_to_string(root.join("demo/release/output-metadata.json")).unwrap(),
+            r#"{
+  "version": 3,
+  "artifactType": {
+    "type": "APK",
+    "kind": "Directory"
+  },
+  "applicationId": "com.example.app.demo",
+  "variantName": "demoRelease",
+  "elements": [
+    {
+      "type": "SINGLE",
+      "filters": [],
+      "attributes": [],
+      "versionCode": 16843267,
+      "versionName": "1.2.3",
+      "outputFile": "app-demo-release.apk"
+    }
+  ],
+  "elementType": "File",
+  "minSdkVersionForDexing": 24
+}"#
+        );
+
+        let debug = GradleOutput {
+            flavor: None,
+            build_type: build_type("dev"),
+            ..output
+        };
+        assert_eq!(debug.dir(), Path::new("debug"));
+        assert_eq!(debug.file_name(), "app-debug.apk");
+        assert_eq!(debug.variant_name(), "debug");
+    }
+}
@@ -0,0 +1,91 @@
+//! `cargo apk hash-libs`: prints the `runtime_lib_checksums` entries of the libraries in a
+//! `runtime_libs` directory.
+
+use crate::error::Error;
+use ndk_build::checksums::sha256_file;
+use ndk_build::error::NdkError;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Prints a `[[package.metadata.android.runtime_lib_checksums]]` entry for every `*.so`
+/// below `dir`, to be pasted into `Cargo.toml`.
+pub fn hash_libs(dir: &Path) -> Result<(), Error> {
+    let mut libs = vec![];
+    find_libs(dir, &mut libs)?;
+    libs.sort();
+    let mut entries = vec![];
+    for lib in libs {
+        let file = lib
+            .strip_prefix(dir)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        entries.push((file, sha256_file(&lib)?));
+    }
+    print!("{}", toml(&entries));
+    Ok(())
+}
+
+fn find_libs(dir: &Path, libs: &mut Vec<PathBuf>) -> Result<(), NdkError> {
+    for entry in std::fs::read_dir(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_libs(&path, libs)?;
+        } else if path.extension().is_some_and(|ext| ext == "so") {
+            libs.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn toml(entries: &[(String, String)]) -> String {
+    let mut toml = String::new();
+    for (file, sha256) in entries {
+        writeln!(
+            toml,
+            "[[package.metadata.android.runtime_lib_checksums]]\nfile = {:?}\nsha256 = {:?}\n",
+            file, sha256
+        )
+        .unwrap();
+    }
+    toml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_parse() {
+        #[derive(serde::Deserialize)]
+        struct Entries {
+            package: Package,
+        }
+        #[derive(serde::Deserialize)]
+        struct Package {
+            metadata: Metadata,
+        }
+        #[derive(serde::Deserialize)]
+        struct Metadata {
+            android: Android,
+        }
+        #[derive(serde::Deserialize)]
+        struct Android {
+            runtime_lib_checksums: Vec<ndk_build::checksums::LibChecksum>,
+        }
+
+        let toml = toml(&[
+            ("arm64-v8a/libfoo.so".to_string(), "ab".repeat(32)),
+            ("x86_64/libfoo.so".to_string(), "cd".repeat(32)),
+        ]);
+        let entries = toml::from_str::<Entries>(&toml)
+            .unwrap()
+            .package
+            .metadata
+            .android
+            .runtime_lib_checksums;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].file, "x86_64/libfoo.so");
+        assert_eq!(entries[1].sha256, "cd".repeat(32));
+    }
+}
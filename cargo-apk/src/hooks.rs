@@ -0,0 +1,192 @@
+//! `[package.metadata.android.hooks]`: commands that [`crate::ApkBuilder`] runs before
+//! and after building, installing and running an apk.
+
+use crate::error::Error;
+use ndk_build::util::{Exec, command_line, status};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct Hooks {
+    #[serde(default)]
+    pub(crate) pre_build: Vec<Hook>,
+    #[serde(default)]
+    pub(crate) post_build: Vec<Hook>,
+    #[serde(default)]
+    pub(crate) pre_install: Vec<Hook>,
+    #[serde(default)]
+    pub(crate) post_run: Vec<Hook>,
+}
+
+/// A command line, either as `["program", "arg"]` or as a table that also configures
+/// `allow_failure`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Hook {
+    Command(Vec<String>),
+    Table {
+        command: Vec<String>,
+        /// Only print a warning when the command fails
+        #[serde(default)]
+        allow_failure: bool,
+    },
+}
+
+impl Hook {
+    fn command(&self) -> &[String] {
+        match self {
+            Self::Command(command) | Self::Table { command, .. } => command,
+        }
+    }
+
+    fn allow_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::Table {
+                allow_failure: true,
+                ..
+            }
+        )
+    }
+}
+
+/// What hooks are told about the build, through environment variables.
+pub(crate) struct HookEnv<'a> {
+    pub(crate) crate_path: &'a Path,
+    pub(crate) package: &'a str,
+    pub(crate) version_code: Option<u32>,
+    pub(crate) profile: &'a str,
+    /// The built apk, except for `pre_build`
+    pub(crate) apk: Option<&'a Path>,
+    /// Only prints the hooks in a dry run
+    pub(crate) exec: Exec,
+}
+
+impl HookEnv<'_> {
+    /// The command line of `hook`, with `{apk}` replaced by the path of the apk, run in
+    /// the crate root.
+    fn command(&self, name: &'static str, hook: &Hook) -> Result<Command, Error> {
+        let (program, args) = hook
+            .command()
+            .split_first()
+            .ok_or(Error::EmptyHookCommand(name))?;
+        let expand = |arg: &String| match self.apk {
+            Some(apk) => arg.replace("{apk}", &apk.to_string_lossy()),
+            None => arg.clone(),
+        };
+        let mut command = Command::new(expand(program));
+        command
+            .args(args.iter().map(expand))
+            .current_dir(self.crate_path)
+            .env("CARGO_APK_PACKAGE", self.package)
+            .env("CARGO_APK_PROFILE", self.profile);
+        if let Some(version_code) = self.version_code {
+            command.env("CARGO_APK_VERSION_CODE", version_code.to_string());
+        }
+        if let Some(apk) = self.apk {
+            command.env("CARGO_APK_OUTPUT", apk);
+        }
+        Ok(command)
+    }
+
+    /// Runs `hooks` in order, stopping at the first one that fails unless it sets
+    /// `allow_failure`.
+    pub(crate) fn run(&self, name: &'static str, hooks: &[Hook]) -> Result<(), Error> {
+        for hook in hooks {
+            let command = self.command(name, hook)?;
+            status(
+                "Running",
+                format_args!("`{}` hook `{}`", name, command_line(&command)),
+            );
+            match self.exec.stream_error(command, None) {
+                Ok(_) => {}
+                Err(error) if hook.allow_failure() => eprintln!(
+                    "Warning: `{}` hook failed, continuing because of `allow_failure = true`: {}",
+                    name, error
+                ),
+                Err(error) => {
+                    return Err(Error::HookFailed {
+                        hook: name,
+                        error: Box::new(error),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook_env(apk: Option<&Path>) -> HookEnv<'_> {
+        HookEnv {
+            crate_path: Path::new("/app"),
+            package: "com.example.app",
+            version_code: Some(1002003),
+            profile: "release",
+            apk,
+            exec: Exec::Run,
+        }
+    }
+
+    #[test]
+    fn command() {
+        let hooks: Hooks = toml::from_str(
+            r#"
+            pre_build = [["cargo", "run", "-p", "bindgen"]]
+            post_build = [{ command = ["upload", "{apk}"], allow_failure = true }]
+            "#,
+        )
+        .unwrap();
+        assert!(!hooks.pre_build[0].allow_failure());
+        assert!(hooks.post_build[0].allow_failure());
+
+        let apk = Path::new("/app/target/release/apk/app.apk");
+        let command = hook_env(Some(apk))
+            .command("post_build", &hooks.post_build[0])
+            .unwrap();
+        assert_eq!(
+            command_line(&command),
+            "CARGO_APK_OUTPUT=/app/target/release/apk/app.apk CARGO_APK_PACKAGE=com.example.app \
+            CARGO_APK_PROFILE=release CARGO_APK_VERSION_CODE=1002003 \
+            upload /app/target/release/apk/app.apk"
+        );
+        assert_eq!(command.get_current_dir(), Some(Path::new("/app")));
+
+        let command = hook_env(None)
+            .command(
+                "pre_build",
+                &Hook::Command(vec!["echo".into(), "{apk}".into()]),
+            )
+            .unwrap();
+        assert!(command.get_envs().all(|(key, _)| key != "CARGO_APK_OUTPUT"));
+        assert!(matches!(
+            hook_env(None).command("pre_install", &Hook::Command(vec![])),
+            Err(Error::EmptyHookCommand("pre_install"))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allow_failure() {
+        let failing = |allow_failure| Hook::Table {
+            command: vec!["false".into()],
+            allow_failure,
+        };
+        let env = HookEnv {
+            crate_path: &std::env::temp_dir(),
+            ..hook_env(None)
+        };
+        assert!(env.run("post_run", &[failing(true)]).is_ok());
+        assert!(matches!(
+            env.run("post_run", &[failing(false)]),
+            Err(Error::HookFailed {
+                hook: "post_run",
+                ..
+            })
+        ));
+    }
+}
@@ -1,6 +1,66 @@
 mod apk;
+mod asset_paths;
+mod asset_transform;
+mod bug_report;
+mod build_info;
+mod cargo_messages;
+mod config;
+mod dependency_manifest;
+mod devices;
+mod doctor;
 mod error;
+mod flavor;
+mod form_factor;
+mod gradle;
+mod hash_libs;
+mod hooks;
+mod licenses;
+mod lint;
 mod manifest;
+mod metadata;
+mod optimize;
+mod permission_audit;
+mod print_env;
+mod print_manifest;
+mod publish;
+mod resolve;
+mod run_config;
+mod sbom;
+mod scaffold;
+mod size_limit;
+mod splits;
+#[cfg(test)]
+mod test_dir;
+mod timings;
+mod unknown_keys;
+mod version_code;
+mod watch;
+mod watch_assets;
 
-pub use apk::ApkBuilder;
+pub use apk::{
+    ApkBuildOptions, ApkBuilder, CheckOptions, CompileMode, ProfileFormat, ProfileOptions,
+    RunOptions, package_artifacts, select_artifact,
+};
+pub use bug_report::write_bug_report;
+pub use cargo_subcommand::{Artifact, ArtifactType, Profile, Subcommand};
+pub use devices::devices;
+pub use doctor::doctor;
 pub use error::Error;
+pub use hash_libs::hash_libs;
+pub use lint::lint;
+pub use metadata::print_metadata;
+// Re-exported so that users of the library API don't have to match our `ndk-build` version
+pub use ndk_build::{
+    self,
+    apk::Apk,
+    error::NdkError,
+    ndk::{Ndk, NdkOptions},
+    target::Target,
+};
+pub use print_env::print_env;
+pub use print_manifest::print_manifest;
+pub use publish::{PublishOptions, ReleaseNotes};
+pub use run_config::RunConfig;
+pub use sbom::SbomFormat;
+pub use scaffold::{ScaffoldOptions, Template, init_project, new_project};
+pub use timings::{TimingsFormat, write_timings};
@@ -0,0 +1,424 @@
+//! `bundle_licenses = true`: collects the license expressions and texts of the
+//! dependencies through `cargo metadata`, for [`crate::ApkBuilder::build()`] to package
+//! as `assets/licenses/`.
+
+use crate::error::Error;
+use cargo_subcommand::Subcommand;
+use ndk_build::error::NdkError;
+use ndk_build::target::Target;
+use ndk_build::util::output_error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The parts of `cargo metadata --format-version 1` that are needed to walk the
+/// dependencies of a package.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    resolve: Option<Resolve>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Package {
+    id: String,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) license: Option<String>,
+    license_file: Option<PathBuf>,
+    manifest_path: PathBuf,
+    targets: Vec<CargoTarget>,
+    /// `[package.metadata]` of its `Cargo.toml`
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoTarget {
+    kind: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resolve {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    id: String,
+    deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeDep {
+    pkg: String,
+    dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepKind {
+    /// `null` for normal dependencies, `"build"` or `"dev"` otherwise
+    kind: Option<String>,
+}
+
+impl Package {
+    /// Proc macros only run on the host, nothing of them ends up in the apk.
+    fn is_proc_macro(&self) -> bool {
+        self.targets
+            .iter()
+            .any(|target| target.kind.iter().any(|kind| kind == "proc-macro"))
+    }
+
+    /// `LICENSE*`, `LICENCE*`, `COPYING*` and `NOTICE*` files in the package root, and the
+    /// `license-file` from its manifest.
+    fn license_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.manifest_path.parent().unwrap();
+        let mut files = BTreeSet::new();
+        if let Some(license_file) = &self.license_file {
+            files.insert(dir.join(license_file));
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_uppercase();
+            let is_license = ["LICENSE", "LICENCE", "COPYING", "NOTICE"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+            if is_license && entry.file_type()?.is_file() {
+                files.insert(entry.path());
+            }
+        }
+        Ok(files.into_iter().collect())
+    }
+}
+
+/// Runs `cargo metadata` for the package of `cmd`, resolving the dependencies that are
+/// built for `targets` with the selected features.
+pub(crate) fn cargo_metadata(
+    cmd: &Subcommand,
+    targets: &[Target],
+    extra_features: &[String],
+    frozen_env: bool,
+) -> Result<Metadata, Error> {
+    let mut cargo = Command::new("cargo");
+    cargo
+        .arg("metadata")
+        .arg("--format-version")
+        .arg("1")
+        .arg("--manifest-path")
+        .arg(cmd.manifest());
+    if frozen_env {
+        cargo.arg("--offline").arg("--locked");
+    }
+    for target in targets {
+        cargo.arg("--filter-platform").arg(target.rust_triple());
+    }
+    let args = cmd.args();
+    for features in args.features.iter().chain(extra_features) {
+        cargo.arg("--features").arg(features);
+    }
+    if args.all_features {
+        cargo.arg("--all-features");
+    }
+    if args.no_default_features {
+        cargo.arg("--no-default-features");
+    }
+    let output = output_error(cargo, None)?;
+    serde_json::from_slice(&output).map_err(Error::CargoMetadata)
+}
+
+/// `assets/licenses/index.json`, listing every bundled package with its license
+/// expression and the texts that apply to it.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Index {
+    pub(crate) packages: Vec<PackageLicenses>,
+    pub(crate) texts: Vec<Text>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct PackageLicenses {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    /// The SPDX expression of `license` in `Cargo.toml`
+    pub(crate) license: Option<String>,
+    /// [`Text::id`]s of the license files of the package
+    pub(crate) texts: Vec<String>,
+}
+
+/// A license text in `assets/licenses/LICENSES.txt`, shared by all packages with an
+/// identical license file.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct Text {
+    /// Hash of the text
+    pub(crate) id: String,
+    /// Byte range of the text in `LICENSES.txt`
+    pub(crate) offset: usize,
+    pub(crate) length: usize,
+}
+
+/// The contents of `assets/licenses/`.
+#[derive(Debug, Default)]
+pub(crate) struct Licenses {
+    pub(crate) index: Index,
+    /// `LICENSES.txt`: every text once, preceded by the packages it applies to
+    pub(crate) texts: String,
+}
+
+impl Metadata {
+    /// `package` at `manifest_path` itself.
+    pub(crate) fn root(&self, package: &str, manifest_path: &Path) -> Result<&Package, Error> {
+        self.packages
+            .iter()
+            .find(|p| p.name == package && p.manifest_path == manifest_path)
+            .ok_or_else(|| Error::PackageNotInMetadata(package.to_string()))
+    }
+
+    /// The dependencies of `package` at `manifest_path` that are compiled into the apk,
+    /// leaving out proc macros, build and dev dependencies.
+    pub(crate) fn dependencies(
+        &self,
+        package: &str,
+        manifest_path: &Path,
+    ) -> Result<Vec<&Package>, Error> {
+        let packages = self
+            .packages
+            .iter()
+            .map(|package| (package.id.as_str(), package))
+            .collect::<HashMap<_, _>>();
+        let nodes = self
+            .resolve
+            .iter()
+            .flat_map(|resolve| &resolve.nodes)
+            .map(|node| (node.id.as_str(), node))
+            .collect::<HashMap<_, _>>();
+        let root = self.root(package, manifest_path)?;
+
+        // Normal dependencies only, build scripts and tests don't ship
+        let mut reachable = BTreeSet::new();
+        let mut queue = vec![root.id.as_str()];
+        while let Some(id) = queue.pop() {
+            let Some(node) = nodes.get(id) else {
+                continue;
+            };
+            for dep in &node.deps {
+                let is_normal = dep.dep_kinds.iter().any(|kind| kind.kind.is_none());
+                let is_proc_macro = packages
+                    .get(dep.pkg.as_str())
+                    .is_some_and(|p| p.is_proc_macro());
+                if is_normal && !is_proc_macro && reachable.insert(dep.pkg.as_str()) {
+                    queue.push(&dep.pkg);
+                }
+            }
+        }
+        Ok(reachable
+            .into_iter()
+            .filter_map(|id| packages.get(id).copied())
+            .collect())
+    }
+
+    /// The names and `[package.metadata.android]` tables of the
+    /// [`Metadata::dependencies()`] of `package` that have one, sorted by name.
+    pub(crate) fn android_tables(
+        &self,
+        package: &str,
+        manifest_path: &Path,
+    ) -> Result<Vec<(&str, &serde_json::Value)>, Error> {
+        let mut tables = self
+            .dependencies(package, manifest_path)?
+            .into_iter()
+            .filter_map(|p| {
+                let android = p.metadata.as_ref()?.get("android")?;
+                Some((p.name.as_str(), android))
+            })
+            .collect::<Vec<_>>();
+        tables.sort_by_key(|(name, _)| *name);
+        Ok(tables)
+    }
+}
+
+impl Licenses {
+    /// The [`Metadata::dependencies()`] of `package` at `manifest_path`, leaving out
+    /// workspace members and the packages named in `exclude`.
+    pub(crate) fn collect(
+        metadata: &Metadata,
+        package: &str,
+        manifest_path: &Path,
+        exclude: &[String],
+    ) -> Result<Self, Error> {
+        let mut bundled = metadata
+            .dependencies(package, manifest_path)?
+            .into_iter()
+            .filter(|p| !metadata.workspace_members.contains(&p.id))
+            .filter(|p| !exclude.contains(&p.name))
+            .collect::<Vec<_>>();
+        bundled.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+        // Texts are collected first to list all their packages in `LICENSES.txt`
+        let mut texts = Vec::<SharedText>::new();
+        let mut by_text = HashMap::new();
+        let mut index = Index::default();
+        for package in bundled {
+            let files = package.license_files()?;
+            if package.license.is_none() && files.is_empty() {
+                eprintln!(
+                    "Warning: `{} {}` declares no license and has no license files to bundle",
+                    package.name, package.version
+                );
+            }
+            let mut text_ids = Vec::new();
+            for file in files {
+                let contents =
+                    std::fs::read(&file).map_err(|e| NdkError::IoPathError(file.clone(), e))?;
+                let text = normalize(&String::from_utf8_lossy(&contents));
+                let i = *by_text.entry(text.clone()).or_insert_with(|| {
+                    texts.push(SharedText {
+                        id: format!("{:016x}", fnv1a(text.as_bytes())),
+                        text,
+                        packages: Vec::new(),
+                    });
+                    texts.len() - 1
+                });
+                let shared = &mut texts[i];
+                let user = format!("{} {}", package.name, package.version);
+                if !shared.packages.contains(&user) {
+                    shared.packages.push(user);
+                    text_ids.push(shared.id.clone());
+                }
+            }
+            index.packages.push(PackageLicenses {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                license: package.license.clone(),
+                texts: text_ids,
+            });
+        }
+
+        let mut licenses = String::new();
+        for shared in texts {
+            for package in &shared.packages {
+                licenses.push_str(package);
+                licenses.push('\n');
+            }
+            licenses.push('\n');
+            index.texts.push(Text {
+                id: shared.id,
+                offset: licenses.len(),
+                length: shared.text.len(),
+            });
+            licenses.push_str(&shared.text);
+            licenses.push_str("\n\n");
+        }
+        Ok(Self {
+            index,
+            texts: licenses,
+        })
+    }
+
+    pub(crate) fn index_json(&self) -> String {
+        serde_json::to_string_pretty(&self.index).unwrap()
+    }
+}
+
+/// A deduplicated license file and the `<name> <version>` of the packages containing it.
+struct SharedText {
+    id: String,
+    text: String,
+    packages: Vec<String>,
+}
+
+/// Unifies line endings and trailing whitespace, so that copies of the same license
+/// are bundled once.
+fn normalize(text: &str) -> String {
+    let lines = text.lines().map(str::trim_end).collect::<Vec<_>>();
+    lines.join("\n").trim().to_string()
+}
+
+/// 64-bit FNV-1a, stable across Rust versions unlike `DefaultHasher`.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn fixture() -> (PathBuf, Metadata) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/licenses");
+        let mut cargo = Command::new("cargo");
+        cargo
+            .arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--offline")
+            .arg("--locked")
+            .arg("--manifest-path")
+            .arg(dir.join("app/Cargo.toml"));
+        let metadata = serde_json::from_slice(&output_error(cargo, None).unwrap()).unwrap();
+        (dir, metadata)
+    }
+
+    #[test]
+    fn collect() {
+        let (dir, metadata) = fixture();
+        let licenses =
+            Licenses::collect(&metadata, "app", &dir.join("app/Cargo.toml"), &[]).unwrap();
+        let index = &licenses.index;
+
+        // `internal` is a workspace member and `baz` a build dependency
+        let names = index
+            .packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["bar", "foo", "qux"]);
+        assert_eq!(
+            index.packages[0].license.as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(index.packages[2].license, None);
+
+        // Both MIT texts only differ in their line endings
+        let mit = &index.packages[1].texts;
+        assert_eq!(mit.len(), 1);
+        assert!(index.packages[0].texts.contains(&mit[0]));
+        assert_eq!(index.texts.len(), 4);
+
+        let text = |id: &str| {
+            let text = index.texts.iter().find(|t| t.id == id).unwrap();
+            &licenses.texts[text.offset..text.offset + text.length]
+        };
+        assert!(text(&mit[0]).starts_with("Permission is hereby granted"));
+        assert!(
+            licenses
+                .texts
+                .starts_with("bar 2.1.0\n\nLicensed under the Apache")
+        );
+        assert!(
+            licenses
+                .texts
+                .contains("bar 2.1.0\nfoo 1.0.0\n\nPermission is")
+        );
+        assert_eq!(
+            text(&index.packages[2].texts[0]),
+            "Copyright (c) Qux authors. All rights reserved."
+        );
+
+        let excluded = Licenses::collect(
+            &metadata,
+            "app",
+            &dir.join("app/Cargo.toml"),
+            &["qux".to_string()],
+        )
+        .unwrap();
+        assert_eq!(excluded.index.packages.len(), 2);
+    }
+
+    #[test]
+    fn dedup_hash() {
+        assert_eq!(normalize("a  \r\nb\r\n\r\n"), "a\nb");
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_ne!(fnv1a(b"MIT"), fnv1a(b"Apache-2.0"));
+    }
+}
@@ -0,0 +1,531 @@
+//! `cargo apk lint`: checks of the metadata, the signing setup and previously built
+//! libraries that need neither a device nor a build, for pull request CI.
+//!
+//! Every [`Lint`] has an id, which `lint_allow = ["<id>"]` lists to not report it.
+
+use crate::apk::{check_keystore, configured_key, key_rotation};
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::print_manifest::{NO_NDK_DEFAULTS, offline_defaults, offline_ndk};
+use crate::resolve::{self, ResolveContext, resolve_manifest};
+use crate::splits;
+use cargo_subcommand::{Artifact, CrateType, Subcommand};
+use ndk_build::apk::validate_resource_references;
+use ndk_build::error::NdkError;
+use ndk_build::ndk::Ndk;
+use ndk_build::readelf::{defined_dynamic_symbols, load_alignments};
+use ndk_build::target::Target;
+use ndk_build::util::status;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Page size of the devices that [`ElfAlignmentLint`] checks for.
+const PAGE_SIZE_16K: u64 = 16384;
+
+/// A problem found by a [`Lint`], or why it could not run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Finding {
+    Error(String),
+    Warning(String),
+    /// The lint needs something that isn't there, like the libraries of a previous build
+    Skipped(String),
+}
+
+/// What [`Lint`]s inspect.
+pub(crate) struct LintContext<'a> {
+    pub(crate) cmd: &'a Subcommand,
+    pub(crate) artifact: &'a Artifact,
+    /// With the flavor applied, but without the defaults of a build
+    pub(crate) manifest: &'a Manifest,
+    /// Only for `readelf` and the API levels, linting works without one
+    pub(crate) ndk: Result<Ndk, NdkError>,
+    /// The ABIs whose built libraries are checked
+    pub(crate) build_targets: Vec<Target>,
+    pub(crate) strict_sdk_check: bool,
+    pub(crate) strict_manifest: bool,
+    /// The `[env]` table of `$CARGO_HOME/config.toml`, see [`crate::config::cargo_home_env()`]
+    pub(crate) config_env: BTreeMap<String, String>,
+}
+
+pub(crate) trait Lint {
+    /// Name in the report and in `lint_allow`, e.g. `elf-16k-alignment`
+    fn id(&self) -> &'static str;
+
+    /// Everything found, where no [`Finding`] means the lint passed
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding>;
+}
+
+/// All lints run by `cargo apk lint`, in the order they are reported.
+pub(crate) fn default_lints() -> Vec<Box<dyn Lint>> {
+    vec![
+        Box::new(ManifestLint),
+        Box::new(UnknownKeysLint),
+        Box::new(CdylibLint),
+        Box::new(SigningLint),
+        Box::new(PathsLint),
+        Box::new(ResourcesLint),
+        Box::new(ElfAlignmentLint),
+        Box::new(ElfSymbolsLint),
+    ]
+}
+
+/// Builds the `AndroidManifest.xml` like [`crate::ApkBuilder`] would, with all of its
+/// validation.
+pub(crate) struct ManifestLint;
+
+impl Lint for ManifestLint {
+    fn id(&self) -> &'static str {
+        "manifest"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let mut findings = vec![];
+        if let Err(e) = &ctx.ndk {
+            findings.push(Finding::Warning(format!("{} {}", e, NO_NDK_DEFAULTS)));
+        }
+        let ndk = ctx.ndk.as_ref().ok();
+        let mut warnings = vec![];
+        let result = offline_defaults(ctx.cmd, ctx.manifest, ndk, ctx.strict_sdk_check)
+            .and_then(|defaults| {
+                let resolve_ctx = ResolveContext {
+                    artifact: ctx.artifact,
+                    defaults: &defaults,
+                    warnings: &mut warnings,
+                };
+                resolve_manifest(ctx.manifest.clone(), resolve_ctx)
+            })
+            .and_then(|manifest| Ok(manifest.to_xml()?));
+        findings.extend(warnings.into_iter().map(Finding::Warning));
+        for result in [result.map(drop), splits::check(ctx.manifest)] {
+            if let Err(e) = result {
+                findings.push(Finding::Error(e.to_string()));
+            }
+        }
+        findings
+    }
+}
+
+/// Keys of `[package.metadata.android]` that cargo-apk ignores, errors with
+/// `strict_manifest = true` or `--strict`.
+pub(crate) struct UnknownKeysLint;
+
+impl Lint for UnknownKeysLint {
+    fn id(&self) -> &'static str {
+        "unknown-keys"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let strict = ctx.strict_manifest || ctx.manifest.strict_manifest;
+        ctx.manifest
+            .unknown_keys
+            .iter()
+            .map(|key| {
+                let message = format!("`[package.metadata.android]` has unknown key {}", key);
+                if strict {
+                    Finding::Error(message)
+                } else {
+                    Finding::Warning(message)
+                }
+            })
+            .collect()
+    }
+}
+
+/// The selected library or example is built as a `cdylib`.
+pub(crate) struct CdylibLint;
+
+impl Lint for CdylibLint {
+    fn id(&self) -> &'static str {
+        "cdylib"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        match ctx.manifest.check_cdylib(ctx.artifact) {
+            Ok(()) => vec![],
+            Err(e) => vec![Finding::Error(e.to_string())],
+        }
+    }
+}
+
+/// The keystore of the profile, and its signing lineage if any, is configured completely
+/// and exists.
+pub(crate) struct SigningLint;
+
+impl Lint for SigningLint {
+    fn id(&self) -> &'static str {
+        "signing"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let key = configured_key(ctx.manifest, ctx.cmd.profile(), &ctx.config_env);
+        let result = key.and_then(|key| match key {
+            Some((key, origin)) => check_keystore(key, origin).map(drop),
+            // The debug keystore is generated when needed
+            None => Ok(()),
+        });
+        let result = result.and_then(|()| key_rotation(ctx.manifest, ctx.cmd.profile()).map(drop));
+        match result {
+            Ok(()) => vec![],
+            Err(e) => vec![Finding::Error(e.to_string())],
+        }
+    }
+}
+
+/// The directories and files that the metadata points to exist.
+pub(crate) struct PathsLint;
+
+impl Lint for PathsLint {
+    fn id(&self) -> &'static str {
+        "paths"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let manifest = ctx.manifest;
+        let flavor_assets = manifest
+            .flavor
+            .as_ref()
+            .and_then(|f| Some((format!("flavor.{}.assets", f.name), f.assets.as_ref()?)));
+        let split_assets = manifest
+            .split_assets
+            .iter()
+            .map(|split| (format!("split_assets.{}.assets", split.name), &split.assets));
+        [
+            ("assets", manifest.assets.as_ref()),
+            ("resources", manifest.resources.as_ref()),
+            ("runtime_libs", manifest.runtime_libs.as_ref()),
+            ("baseline_profile", manifest.baseline_profile.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(key, path)| Some((key.to_string(), path?)))
+        .chain(flavor_assets)
+        .chain(split_assets)
+        .filter_map(|(key, path)| {
+            // Only these settings are resolved like `assets`
+            let resolved = if key == "runtime_libs" || key == "baseline_profile" {
+                manifest.crate_path.join(path)
+            } else {
+                match manifest.resolve_path(&key, path) {
+                    Ok(resolved) => resolved,
+                    Err(e) => return Some(Finding::Error(e.to_string())),
+                }
+            };
+            (!resolved.exists()).then(|| {
+                Finding::Error(format!(
+                    "`{}` points to `{}`, which does not exist",
+                    key,
+                    path.display()
+                ))
+            })
+        })
+        .collect()
+    }
+}
+
+/// The `@string/` and `@mipmap/` references of the labels and icons are defined under
+/// `resources`, like `cargo apk build` checks unless `--skip-resource-validation`.
+pub(crate) struct ResourcesLint;
+
+impl Lint for ResourcesLint {
+    fn id(&self) -> &'static str {
+        "resources"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let manifest = resolve::artifact_manifest(ctx.manifest, ctx.artifact);
+        let resources = ctx
+            .manifest
+            .resources
+            .as_ref()
+            .map(|res| ctx.manifest.crate_path.join(res));
+        match validate_resource_references(&manifest, resources.as_deref()) {
+            Ok(()) => vec![],
+            Err(e) => vec![Finding::Error(e.to_string())],
+        }
+    }
+}
+
+/// The libraries of a previous build of the artifact, with the `readelf` to inspect them.
+fn built_libs(ctx: &LintContext<'_>) -> Result<Vec<(Target, PathBuf, PathBuf)>, Finding> {
+    let ndk = ctx
+        .ndk
+        .as_ref()
+        .map_err(|e| Finding::Skipped(format!("needs the NDK's `readelf`. {}", e)))?;
+    let mut libs = vec![];
+    for &target in &ctx.build_targets {
+        let lib = ctx
+            .cmd
+            .artifact(ctx.artifact, Some(target.rust_triple()), CrateType::Cdylib);
+        if lib.exists() {
+            let readelf = ndk
+                .toolchain_bin("readelf", target)
+                .map_err(|e| Finding::Skipped(e.to_string()))?;
+            libs.push((target, lib, readelf));
+        }
+    }
+    if libs.is_empty() {
+        return Err(Finding::Skipped(format!(
+            "no `{}` was built yet, run `cargo apk build` first",
+            lib_file_name(ctx.artifact)
+        )));
+    }
+    Ok(libs)
+}
+
+fn lib_file_name(artifact: &Artifact) -> String {
+    // Only the file name, which doesn't depend on the target
+    artifact.file_name(CrateType::Cdylib, "")
+}
+
+/// The `LOAD` segments of the 64-bit libraries are aligned for devices with 16 KiB
+/// pages, which Google Play requires for apps targeting Android 15 and up.
+pub(crate) struct ElfAlignmentLint;
+
+impl Lint for ElfAlignmentLint {
+    fn id(&self) -> &'static str {
+        "elf-16k-alignment"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let libs = match built_libs(ctx) {
+            Ok(libs) => libs,
+            Err(skipped) => return vec![skipped],
+        };
+        let mut findings = vec![];
+        for (target, lib, readelf) in libs {
+            // 32-bit devices keep 4 KiB pages
+            if !matches!(target, Target::Arm64V8a | Target::X86_64) {
+                continue;
+            }
+            match load_alignments(&readelf, &lib) {
+                Ok(alignments) => {
+                    if let Some(align) = alignments.into_iter().filter(|&a| a < PAGE_SIZE_16K).min()
+                    {
+                        findings.push(Finding::Error(format!(
+                            "`{}` for {} has a `LOAD` segment aligned to {} bytes, which devices \
+                            with 16 KiB pages can't load. Add `-Wl,-z,max-page-size={}` to \
+                            `link_args`",
+                            display_name(&lib),
+                            target.android_abi(),
+                            align,
+                            PAGE_SIZE_16K
+                        )));
+                    }
+                }
+                Err(e) => findings.push(Finding::Error(e.to_string())),
+            }
+        }
+        findings
+    }
+}
+
+/// The libraries export the entry point of the activity.
+pub(crate) struct ElfSymbolsLint;
+
+impl Lint for ElfSymbolsLint {
+    fn id(&self) -> &'static str {
+        "elf-symbols"
+    }
+
+    fn run(&self, ctx: &LintContext<'_>) -> Vec<Finding> {
+        let activity = &ctx.manifest.android_manifest.application.activity.name;
+        // Other activities load the library from Java, with their own entry points
+        if activity != "android.app.NativeActivity" {
+            return vec![];
+        }
+        let libs = match built_libs(ctx) {
+            Ok(libs) => libs,
+            Err(skipped) => return vec![skipped],
+        };
+        let mut findings = vec![];
+        for (target, lib, readelf) in libs {
+            match defined_dynamic_symbols(&readelf, &lib) {
+                Ok(symbols) if symbols.contains("ANativeActivity_onCreate") => {}
+                Ok(_) => findings.push(Finding::Error(format!(
+                    "`{}` for {} does not export `ANativeActivity_onCreate`, which `{}` \
+                    calls to start the app",
+                    display_name(&lib),
+                    target.android_abi(),
+                    activity
+                ))),
+                Err(e) => findings.push(Finding::Error(e.to_string())),
+            }
+        }
+        findings
+    }
+}
+
+fn display_name(lib: &Path) -> String {
+    lib.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+/// Runs the `lints` that `lint_allow` doesn't list, returning the report and the number
+/// of errors and warnings in it.
+fn report(lints: &[Box<dyn Lint>], ctx: &LintContext<'_>) -> (String, usize, usize) {
+    let (mut text, mut errors, mut warnings) = (String::new(), 0, 0);
+    let mut line = |label: &str, id: &str, message: Option<&str>| {
+        text.push_str(&format!("[{:>7}] {}", label, id));
+        if let Some(message) = message {
+            text.push_str(&format!(": {}", message));
+        }
+        text.push('\n');
+    };
+    for id in &ctx.manifest.lint_allow {
+        if !lints.iter().any(|lint| lint.id() == id) {
+            warnings += 1;
+            let ids = lints.iter().map(|lint| lint.id()).collect::<Vec<_>>();
+            let message = format!("unknown lint `{}`, expected one of {}", id, ids.join(", "));
+            line("warning", "lint_allow", Some(&message));
+        }
+    }
+    for lint in lints {
+        if ctx.manifest.lint_allow.iter().any(|id| id == lint.id()) {
+            line("allowed", lint.id(), None);
+            continue;
+        }
+        let findings = lint.run(ctx);
+        if findings.is_empty() {
+            line("ok", lint.id(), None);
+        }
+        for finding in &findings {
+            let (label, message) = match finding {
+                Finding::Error(message) => {
+                    errors += 1;
+                    ("error", message)
+                }
+                Finding::Warning(message) => {
+                    warnings += 1;
+                    ("warning", message)
+                }
+                Finding::Skipped(message) => ("skipped", message),
+            };
+            line(label, lint.id(), Some(message));
+        }
+    }
+    (text, errors, warnings)
+}
+
+/// Prints the findings of [`default_lints()`] for `artifact`, and fails if any of them
+/// is an error.
+///
+/// The NDK is optional: without it the API levels aren't checked and the lints of the
+/// built libraries are skipped. `strict_manifest` turns unknown keys into errors.
+pub fn lint(
+    cmd: &Subcommand,
+    artifact: &Artifact,
+    strict_sdk_check: bool,
+    strict_manifest: bool,
+    flavor: Option<&str>,
+) -> Result<(), Error> {
+    let config_env = crate::config::cargo_home_env()?;
+    let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+    if let Some(flavor) = flavor {
+        manifest.apply_flavor(flavor)?;
+    }
+    let build_targets = if let Some(target) = cmd.target() {
+        vec![Target::from_abi_or_triple(target)?]
+    } else if !manifest.build_targets.is_empty() {
+        manifest.build_targets.clone()
+    } else {
+        vec![
+            Target::ArmV7a,
+            Target::Arm64V8a,
+            Target::X86,
+            Target::X86_64,
+        ]
+    };
+    let ctx = LintContext {
+        cmd,
+        artifact,
+        ndk: offline_ndk(&manifest, config_env.clone()),
+        config_env,
+        manifest: &manifest,
+        build_targets,
+        strict_sdk_check,
+        strict_manifest,
+    };
+    let (text, errors, warnings) = report(&default_lints(), &ctx);
+    print!("{}", text);
+    status(
+        "Linted",
+        format_args!(
+            "`{}`: {} error(s), {} warning(s)",
+            artifact.name, errors, warnings
+        ),
+    );
+    if errors > 0 {
+        Err(Error::LintFailed(errors))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint_report() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/lint");
+        let cmd = Subcommand::new(clap::Parser::parse_from([
+            "cargo-apk".as_ref(),
+            "--release".as_ref(),
+            "--manifest-path".as_ref(),
+            dir.join("Cargo.toml").as_os_str(),
+        ]))
+        .unwrap();
+        let manifest = Manifest::parse_from_toml(cmd.manifest()).unwrap();
+        let artifact = crate::select_artifact(&cmd).unwrap();
+        let ctx = LintContext {
+            cmd: &cmd,
+            artifact,
+            manifest: &manifest,
+            ndk: Err(NdkError::NdkNotFound),
+            build_targets: vec![Target::Arm64V8a],
+            strict_sdk_check: false,
+            strict_manifest: false,
+            config_env: BTreeMap::new(),
+        };
+
+        let (text, errors, warnings) = report(&default_lints(), &ctx);
+        assert_eq!(
+            text,
+            format!(
+                "[warning] lint_allow: unknown lint `elf-alignment`, expected one of manifest, \
+                unknown-keys, cdylib, signing, paths, resources, elf-16k-alignment, elf-symbols\n\
+                [warning] manifest: {} {}\n\
+                [  error] manifest: `activity_alias` `.WinterIcon` targets `.MainActivity`, \
+                which is not an activity of the manifest, only `android.app.NativeActivity` is\n\
+                [warning] unknown-keys: `[package.metadata.android]` has unknown key \
+                `bulid_targets`, did you mean `build_targets`?\n\
+                [allowed] cdylib\n\
+                [  error] signing: Keystore `{}` configured through \
+                `[package.metadata.android.signing.release]` does not exist\n\
+                [  error] paths: `assets` points to `missing-assets`, which does not exist\n\
+                [  error] resources: Resource `@string/app_name` referenced by \
+                `application.label` not found as no `resources` directory is configured, pass \
+                `--skip-resource-validation` to package anyway\n\
+                [skipped] elf-16k-alignment: needs the NDK's `readelf`. {}\n\
+                [skipped] elf-symbols: needs the NDK's `readelf`. {}\n",
+                NdkError::NdkNotFound,
+                NO_NDK_DEFAULTS,
+                dir.join("release.keystore").display(),
+                NdkError::NdkNotFound,
+                NdkError::NdkNotFound,
+            )
+        );
+        assert_eq!((errors, warnings), (4, 3));
+
+        let strict = LintContext {
+            strict_manifest: true,
+            ..ctx
+        };
+        assert_eq!(
+            UnknownKeysLint.run(&strict),
+            [Finding::Error(
+                "`[package.metadata.android]` has unknown key `bulid_targets`, did you mean \
+                `build_targets`?"
+                    .to_string()
+            )]
+        );
+    }
+}
@@ -1,8 +1,33 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use cargo_apk::{ApkBuilder, Error};
+use anyhow::Context;
+
+use cargo_apk::{
+    ApkBuildOptions, ApkBuilder, ArtifactType, CheckOptions, CompileMode, ProfileFormat,
+    ProfileOptions, PublishOptions, ReleaseNotes, RunConfig, RunOptions, SbomFormat,
+    ScaffoldOptions, Template, TimingsFormat, devices, doctor, hash_libs, init_project, lint,
+    new_project, package_artifacts, print_env, print_manifest, print_metadata, select_artifact,
+    write_bug_report, write_timings,
+};
 use cargo_subcommand::Subcommand;
+use clap::builder::PossibleValuesParser;
 use clap::{CommandFactory, FromArgMatches, Parser};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
+use ndk_build::apk::LaunchExtras;
+use ndk_build::emulator::{Emulator, EmulatorOptions, list_avds};
+use ndk_build::env;
+use ndk_build::ndk::transport::SshRemote;
+use ndk_build::ndk::{Ndk, NdkOptions};
+use ndk_build::simpleperf::RecordOptions;
+use ndk_build::target::Target;
+use ndk_build::util::{
+    Verbosity, remove_temp_paths, run_cleanups, set_output_limit, set_verbosity,
+    show_in_file_manager, status, terminate_children,
+};
 
 #[derive(Parser)]
 struct Cmd {
@@ -10,12 +35,41 @@ struct Cmd {
     apk: ApkCmd,
 }
 
+#[derive(clap::Args)]
+struct ScaffoldArgs {
+    /// Android package name, defaults to `com.example.<name>`
+    #[clap(long)]
+    package_id: Option<String>,
+    #[clap(long, value_enum, default_value_t)]
+    template: Template,
+}
+
+impl ScaffoldArgs {
+    fn options(self) -> ScaffoldOptions {
+        ScaffoldOptions {
+            package_id: self.package_id,
+            template: self.template,
+        }
+    }
+}
+
 #[derive(clap::Subcommand)]
 enum ApkCmd {
     /// Helps cargo build apks for Android
+    #[clap(arg_required_else_help = true)]
     Apk {
+        /// List the subcommands with a short description
+        #[clap(long)]
+        list: bool,
+        /// Print the output of successful tools, `-vv` also prints every command line
+        #[clap(short, long, action = clap::ArgAction::Count, global = true)]
+        verbose: u8,
+        /// Write a report of the environment into the build directory when the command
+        /// fails, for attaching to an issue
+        #[clap(long, global = true)]
+        bug_report: bool,
         #[clap(subcommand)]
-        cmd: ApkSubCmd,
+        cmd: Option<ApkSubCmd>,
     },
 }
 
@@ -25,8 +79,102 @@ struct Args {
     #[clap(flatten)]
     subcommand_args: cargo_subcommand::Args,
     /// Use device with the given serial (see `adb devices`)
-    #[clap(short, long)]
+    #[clap(short, long, env = env::ANDROID_SERIAL.name)]
     device: Option<String>,
+    /// Install and launch the app for the given Android user id, e.g. a work
+    /// profile (see `adb shell pm list users`)
+    #[clap(long)]
+    user: Option<u32>,
+    /// Build for all ABIs supported by the device (`ro.product.cpu.abilist`) instead
+    /// of `build_targets`
+    #[clap(long)]
+    auto_abis: bool,
+    /// Install a missing NDK, build-tools or platform through `sdkmanager`
+    #[clap(long)]
+    install_missing: bool,
+    /// Accept all SDK licenses before installing missing components
+    #[clap(long, requires = "install_missing")]
+    accept_licenses: bool,
+    /// Fail instead of raising `min_sdk_version` to the lowest API level supported by the NDK
+    #[clap(long)]
+    strict_sdk_check: bool,
+    /// Hermetic build: pass `--offline --locked` to cargo, never run `sdkmanager` or
+    /// generate a debug keystore, and check that all required tools exist up front
+    #[clap(long, conflicts_with = "install_missing")]
+    frozen_env: bool,
+    /// Only pass the `link_args` metadata to the linker, like `replace_linker_args = true`
+    #[clap(long)]
+    no_default_linker_args: bool,
+    /// Fail on unknown keys in `[package.metadata.android]`, like `strict_manifest = true`
+    #[clap(long)]
+    strict: bool,
+    /// Don't remove libraries and assets of previous builds, e.g. for ABIs that are no
+    /// longer built, from the build directory
+    #[clap(long)]
+    keep_stale: bool,
+    /// Zip the unstripped libraries into `<apk_name>-native-debug-symbols.zip` next to
+    /// the apk, for uploading to the Play Console
+    #[clap(long)]
+    debug_symbols_bundle: bool,
+    /// Write a software bill of materials of the crates and native libraries in the apk
+    /// to `<apk_name>.cdx.json` next to it
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "cyclonedx-json"
+    )]
+    sbom: Option<SbomFormat>,
+    /// Apply `[package.metadata.android.flavor.<FLAVOR>]`, building into
+    /// `target/<profile>/apk/<package>/<FLAVOR>/`
+    #[clap(long)]
+    flavor: Option<String>,
+    /// Package even when `label` or `icon` refer to a `@string/` or `@mipmap/` resource
+    /// that is not found under the `resources` directory
+    #[clap(long)]
+    skip_resource_validation: bool,
+    /// Append an argument to `adb install`, after the `install_flags` of the manifest,
+    /// like `--install-arg=-d` to allow downgrading the `versionCode`
+    #[clap(long = "install-arg", value_name = "ARG", allow_hyphen_values = true)]
+    install_args: Vec<String>,
+    /// Use the adb server of a remote device lab, `ssh://[user@]host[:port]`, through an
+    /// SSH tunnel that is opened by the first `adb` command
+    #[clap(long, value_name = "URL")]
+    remote: Option<SshRemote>,
+}
+
+impl Args {
+    /// The options of [`ApkBuilder::from_subcommand()`], besides those of `cargo`.
+    fn build_options(&self) -> ApkBuildOptions {
+        ApkBuildOptions {
+            device_serial: self.device.clone(),
+            user: self.user,
+            auto_abis: self.auto_abis,
+            ndk_options: self.ndk_options(),
+            strict_sdk_check: self.strict_sdk_check,
+            no_default_linker_args: self.no_default_linker_args,
+            strict_manifest: self.strict,
+            keep_stale: self.keep_stale,
+            debug_symbols_bundle: self.debug_symbols_bundle,
+            sbom: self.sbom,
+            flavor: self.flavor.clone(),
+            skip_resource_validation: self.skip_resource_validation,
+            install_args: self.install_args.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn ndk_options(&self) -> NdkOptions {
+        NdkOptions {
+            install_missing: self.install_missing,
+            accept_licenses: self.accept_licenses,
+            frozen_env: self.frozen_env,
+            remote: self.remote.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(clap::Subcommand)]
@@ -36,12 +184,47 @@ enum ApkSubCmd {
     Check {
         #[clap(flatten)]
         args: Args,
+        /// Also check tests, examples and benches
+        #[clap(long)]
+        all_targets: bool,
+    },
+    /// Lint the current package and all of its dependencies with `cargo clippy`, with
+    /// the NDK environment of a build
+    Clippy {
+        #[clap(flatten)]
+        args: Args,
+        /// Also lint tests, examples and benches
+        #[clap(long)]
+        all_targets: bool,
+        /// Arguments passed to clippy, like `-D warnings`
+        #[clap(last = true, value_name = "LINT_ARGS")]
+        lint_args: Vec<String>,
     },
     /// Compile the current package and create an apk
     #[clap(visible_alias = "b")]
     Build {
         #[clap(flatten)]
         args: Args,
+        /// Print the commands and file operations of the build instead of running them
+        #[clap(long)]
+        dry_run: bool,
+        /// Show the built apk in the file manager
+        #[clap(long)]
+        open: bool,
+        /// Print only the path of the built apk to stdout, like `--quiet`
+        #[clap(long)]
+        print_path_only: bool,
+        /// Record how long each phase and command takes, and write a report to
+        /// `cargo-apk-timings.<FORMAT>` in the build directory
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "html"
+        )]
+        timings: Option<TimingsFormat>,
     },
     /// Invoke `cargo` under the detected NDK environment
     #[clap(name = "--")]
@@ -56,7 +239,7 @@ enum ApkSubCmd {
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
         cargo_args: Vec<String>,
     },
-    /// Run a binary or example apk of the local package
+    /// Run the library or an example apk of the local package
     #[clap(visible_alias = "r")]
     Run {
         #[clap(flatten)]
@@ -64,16 +247,292 @@ enum ApkSubCmd {
         /// Do not print or follow `logcat` after running the app
         #[clap(short, long)]
         no_logcat: bool,
+        /// Pull the given path from the app's internal storage once the app exits
+        #[clap(long, value_name = "PATH")]
+        pull_after_run: Option<String>,
+        /// Compile the app ahead of time after installing it (API 24+)
+        #[clap(long, value_enum, value_name = "MODE")]
+        compile_mode: Option<CompileMode>,
+        /// Push the apk to `/system/priv-app/` of a rooted `userdebug` device and reboot,
+        /// falling back to `pm install -r --full` when `adb remount` is refused
+        #[clap(long, conflicts_with = "user")]
+        system_install: bool,
+        /// Install even if the device is older than `min_sdk_version`, can't run any of
+        /// the packaged ABIs or seems to lack the space
+        #[clap(long)]
+        force_install: bool,
+        /// Fail instead of warning when the device has a newer `versionCode` of the app
+        /// installed
+        #[clap(long)]
+        fail_on_downgrade: bool,
+        /// Print the commands and file operations of the build, install and launch
+        /// instead of running them
+        #[clap(long, conflicts_with_all = ["system_install", "start_emulator"])]
+        dry_run: bool,
+        /// Do not collect tombstones and ANR traces when the app exits abnormally
+        #[clap(long)]
+        no_diagnostics: bool,
+        /// Treat runs shorter than this many seconds as abnormal exits [default: 2]
+        #[clap(long, value_name = "SECONDS")]
+        diagnostics_threshold: Option<u64>,
+        /// Start the given AVD when no device is connected
+        #[clap(long, value_name = "AVD", conflicts_with = "remote")]
+        start_emulator: Option<String>,
+        /// Shut down the emulator started by `--start-emulator` after the app exits
+        #[clap(long, requires = "start_emulator")]
+        stop_emulator_after: bool,
+        #[clap(flatten)]
+        emulator_args: EmulatorArgs,
+        /// Push the assets that change on the host to the running app, see
+        /// `[package.metadata.android.watch_assets]`
+        #[clap(long)]
+        watch_assets: bool,
+        /// Save the device and flags of this run to `<target-dir>/.cargo-apk/run-config.toml`,
+        /// which later runs apply below the flags given to them
+        #[clap(long, conflicts_with = "dry_run")]
+        save_config: bool,
+        /// Ignore the saved run config
+        #[clap(long)]
+        no_config: bool,
+        /// Install the apk of the last build instead of building it again
+        #[clap(long)]
+        no_build: bool,
+        /// Start the app that is already installed, without building or installing it
+        #[clap(long, conflicts_with_all = ["system_install", "force_install", "fail_on_downgrade"])]
+        no_install: bool,
+        /// Force-stop a running instance of the app before launching it
+        #[clap(long)]
+        restart: bool,
+        /// Pass `VALUE` to the app as the `cargo_apk.env.KEY` string extra of the launch
+        /// intent
+        #[clap(long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+        app_env: Vec<(String, String)>,
+        /// Arguments passed to the app as the `cargo_apk.args` string-array extra of the
+        /// launch intent
+        #[clap(last = true, value_name = "APP_ARGS")]
+        app_args: Vec<String>,
+        /// Record how long each phase and command takes, and write a report to
+        /// `cargo-apk-timings.<FORMAT>` in the build directory
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "html"
+        )]
+        timings: Option<TimingsFormat>,
+    },
+    /// Build, install and run the app again whenever its sources, `Cargo.toml`, assets or
+    /// resources change, until Ctrl-C
+    Watch {
+        #[clap(flatten)]
+        args: Args,
+        /// Do not print or follow `logcat` after starting the app
+        #[clap(short, long)]
+        no_logcat: bool,
+        /// Fail instead of warning when the device has a newer `versionCode` of the app
+        /// installed
+        #[clap(long)]
+        fail_on_downgrade: bool,
+        /// Pass `VALUE` to the app as the `cargo_apk.env.KEY` string extra of the launch
+        /// intent
+        #[clap(long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+        app_env: Vec<(String, String)>,
+        /// Arguments passed to the app as the `cargo_apk.args` string-array extra of the
+        /// launch intent
+        #[clap(last = true, value_name = "APP_ARGS")]
+        app_args: Vec<String>,
     },
     /// Start a gdb session attached to an adb device with symbols loaded
     Gdb {
         #[clap(flatten)]
         args: Args,
     },
+    /// Record a CPU profile of the app with the NDK's `simpleperf`
+    Profile {
+        #[clap(flatten)]
+        args: Args,
+        /// Seconds to record for
+        #[clap(long, default_value_t = 10)]
+        duration: u64,
+        /// Event to sample, use `cpu-clock` on devices without hardware counters
+        #[clap(long, default_value = "cpu-cycles")]
+        event: String,
+        /// Where to save the `perf.data` recording
+        #[clap(short, long, default_value = "perf.data")]
+        output: PathBuf,
+        /// Additionally print a report or write folded stacks for flamegraphs
+        #[clap(long, value_enum, default_value_t)]
+        format: ProfileFormat,
+    },
+    /// Force-stop the installed app without building it
+    Stop {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Copy files from the app's internal storage through `run-as`
+    Pull {
+        #[clap(flatten)]
+        args: Args,
+        /// Path relative to the app's internal storage, e.g. `databases`
+        device_path: String,
+        /// Destination on the host, defaults to the last component of `device_path`
+        host_path: Option<PathBuf>,
+    },
+    /// Build the apk and upload it with a backend from `[package.metadata.android.publish]`
+    Publish {
+        #[clap(flatten)]
+        args: Args,
+        /// Name of the publisher, may be omitted if only one is configured
+        publisher: Option<String>,
+        /// Release notes for the testers
+        #[clap(long, conflicts_with = "notes_file")]
+        notes: Option<String>,
+        /// Read the release notes from a file
+        #[clap(long, value_name = "PATH")]
+        notes_file: Option<PathBuf>,
+    },
+    /// Manage Android Virtual Devices
+    Emulator {
+        #[clap(subcommand)]
+        cmd: EmulatorSubCmd,
+    },
+    /// List connected devices with their model, Android version, ABIs and screen
+    Devices {
+        #[clap(flatten)]
+        args: Args,
+        /// List the Android users (e.g. work profiles) on the device
+        #[clap(long)]
+        users: bool,
+        /// Print the devices as a JSON array
+        #[clap(long, conflicts_with = "users")]
+        json: bool,
+    },
+    /// Print the `AndroidManifest.xml` of the library, binary or example without building it
+    PrintManifest {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Print the resolved configuration of the library, binary or example as JSON
+    /// without building it
+    Metadata {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Check the manifest, signing setup and built libraries without a device, for CI
+    Lint {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Check the SDK, NDK, JDK and device setup and suggest fixes
+    Doctor {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Print the environment variables cargo-apk reads and the SDK and NDK they resolve to
+    Env {
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Create a new app in `<path>`
+    New {
+        path: PathBuf,
+        #[clap(flatten)]
+        scaffold: ScaffoldArgs,
+    },
+    /// Set up an app in an existing directory or crate
+    Init {
+        #[clap(default_value = ".")]
+        path: PathBuf,
+        #[clap(flatten)]
+        scaffold: ScaffoldArgs,
+    },
+    /// Print the `runtime_lib_checksums` of the `*.so` files in a `runtime_libs` directory
+    HashLibs {
+        #[clap(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Print a script that enables shell completions, including `--example` and `--bin` names
+    Completions {
+        #[clap(value_parser = PossibleValuesParser::new(Shells::builtins().names()))]
+        shell: String,
+    },
     /// Print the version of cargo-apk
     Version,
 }
 
+impl ApkSubCmd {
+    /// The common arguments of the subcommands that build or inspect the package.
+    fn args(&self) -> Option<&Args> {
+        match self {
+            Self::Check { args, .. }
+            | Self::Clippy { args, .. }
+            | Self::Build { args, .. }
+            | Self::Run { args, .. }
+            | Self::Watch { args, .. }
+            | Self::Gdb { args }
+            | Self::Profile { args, .. }
+            | Self::Stop { args }
+            | Self::Pull { args, .. }
+            | Self::Publish { args, .. }
+            | Self::PrintManifest { args }
+            | Self::Metadata { args }
+            | Self::Lint { args }
+            | Self::Doctor { args }
+            | Self::Env { args }
+            | Self::Devices { args, .. } => Some(args),
+            _ => None,
+        }
+    }
+}
+
+#[derive(clap::Subcommand)]
+enum EmulatorSubCmd {
+    /// List the available AVDs
+    List,
+    /// Start an AVD, wait for it to finish booting and print its serial
+    Start {
+        /// Name of the AVD (see `cargo apk emulator list`)
+        avd: String,
+        #[clap(flatten)]
+        emulator_args: EmulatorArgs,
+    },
+}
+
+#[derive(Clone, Debug, Parser)]
+#[group(skip)]
+struct EmulatorArgs {
+    /// Show the emulator window instead of running headless
+    #[clap(long)]
+    window: bool,
+    /// GPU emulation mode
+    #[clap(long, default_value = "swiftshader_indirect")]
+    gpu: String,
+    /// Seconds to wait for the emulator to finish booting
+    #[clap(long, default_value_t = 300)]
+    boot_timeout: u64,
+}
+
+impl EmulatorArgs {
+    fn options(&self) -> EmulatorOptions {
+        EmulatorOptions {
+            no_window: !self.window,
+            gpu: Some(self.gpu.clone()),
+            boot_timeout: Duration::from_secs(self.boot_timeout),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parses the `KEY=VALUE` of `run --env`.
+fn parse_env(env: &str) -> Result<(String, String), String> {
+    match env.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("`{}` is not of the form `KEY=VALUE`", env)),
+    }
+}
+
 fn split_apk_and_cargo_args(input: Vec<String>) -> (Args, Vec<String>) {
     // Clap doesn't support parsing unknown args properly:
     // https://github.com/clap-rs/clap/issues/1404
@@ -129,32 +588,110 @@ fn split_apk_and_cargo_args(input: Vec<String>) -> (Args, Vec<String>) {
     (args, split_args.cargo_args)
 }
 
-fn iterator_single_item<T>(mut iter: impl Iterator<Item = T>) -> Option<T> {
-    let first_item = iter.next()?;
-    if iter.next().is_some() {
-        None
-    } else {
-        Some(first_item)
-    }
-}
-
 fn main() -> anyhow::Result<()> {
+    CompleteEnv::with_factory(completion_command)
+        .var(env::COMPLETE.name)
+        .complete();
     env_logger::init();
     let Cmd {
-        apk: ApkCmd::Apk { cmd },
+        apk:
+            ApkCmd::Apk {
+                list,
+                verbose,
+                bug_report,
+                cmd,
+            },
     } = Cmd::parse();
+    let Some(cmd) = cmd.filter(|_| !list) else {
+        print_subcommands();
+        return Ok(());
+    };
+    let quiet = cmd.args().is_some_and(|args| args.subcommand_args.quiet)
+        || matches!(
+            cmd,
+            ApkSubCmd::Build {
+                print_path_only: true,
+                ..
+            }
+        );
+    set_verbosity(match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::VeryVerbose,
+    });
+    // `gdb` handles Ctrl-C itself, to interrupt the app
+    CTRLC_HANDLER.store(!matches!(cmd, ApkSubCmd::Gdb { .. }), Ordering::Relaxed);
+    // Otherwise `subcommand()` installs it
+    if cmd.args().is_none() {
+        set_ctrlc_handler();
+    }
+    if let Ok(limit) = env::CARGO_APK_OUTPUT_LIMIT.get() {
+        set_output_limit(
+            limit
+                .parse()
+                .with_context(|| format!("Invalid $CARGO_APK_OUTPUT_LIMIT `{}`", limit))?,
+        );
+    }
+    let bug_report = bug_report || env::CARGO_APK_BUG_REPORT.get().is_ok_and(|v| v == "1");
+    let args = cmd.args().cloned();
+    let result = run(cmd);
+    if let (Err(error), true) = (&result, bug_report) {
+        report_bug(args, error);
+    }
+    result
+}
+
+fn run(cmd: ApkSubCmd) -> anyhow::Result<()> {
     match cmd {
-        ApkSubCmd::Check { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            builder.check()?;
+        ApkSubCmd::Check { args, all_targets } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            builder.check(&CheckOptions { all_targets })?;
+        }
+        ApkSubCmd::Clippy {
+            args,
+            all_targets,
+            lint_args,
+        } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            builder.clippy(&CheckOptions { all_targets }, &lint_args)?;
         }
-        ApkSubCmd::Build { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            for artifact in cmd.artifacts() {
-                builder.build(artifact)?;
+        ApkSubCmd::Build {
+            args,
+            dry_run,
+            open,
+            print_path_only: _,
+            timings,
+        } => {
+            if timings.is_some() {
+                ndk_build::timings::enable();
             }
+            let mut options = args.build_options();
+            options.ndk_options.dry_run = dry_run;
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let result = cmd.artifacts().try_for_each(|artifact| {
+                let apk = builder.build(artifact)?;
+                if open && !dry_run {
+                    let path = builder.output_path(artifact, &apk);
+                    // Headless machines like CI have no file manager, which is no reason to
+                    // fail the build
+                    if let Err(e) = show_in_file_manager(&path) {
+                        eprintln!(
+                            "Warning: could not open the file manager: {}, the apk is at `{}`",
+                            e,
+                            path.display()
+                        );
+                    }
+                }
+                Ok::<_, cargo_apk::Error>(())
+            });
+            report_timings(&cmd, args.flavor.as_deref(), timings)?;
+            result?;
         }
         ApkSubCmd::Ndk {
             cargo_cmd,
@@ -162,22 +699,310 @@ fn main() -> anyhow::Result<()> {
         } => {
             let (args, cargo_args) = split_apk_and_cargo_args(cargo_args);
 
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
             builder.default(&cargo_cmd, &cargo_args)?;
         }
-        ApkSubCmd::Run { args, no_logcat } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
-            builder.run(artifact, no_logcat)?;
+        ApkSubCmd::Run {
+            args,
+            no_logcat,
+            pull_after_run,
+            compile_mode,
+            system_install,
+            force_install,
+            fail_on_downgrade,
+            dry_run,
+            no_diagnostics,
+            diagnostics_threshold,
+            start_emulator,
+            stop_emulator_after,
+            emulator_args,
+            watch_assets,
+            save_config,
+            no_config,
+            no_build,
+            no_install,
+            restart,
+            app_env,
+            app_args,
+            timings,
+        } => {
+            if timings.is_some() {
+                ndk_build::timings::enable();
+            }
+            let mut options = args.build_options();
+            options.ndk_options.dry_run = dry_run;
+            let cmd = subcommand(args.subcommand_args)?;
+            let cli = RunConfig {
+                device: args.device,
+                user: args.user,
+                no_logcat,
+                pull_after_run,
+                compile_mode,
+                force_install,
+                fail_on_downgrade,
+                no_diagnostics,
+                diagnostics_threshold,
+                start_emulator,
+                stop_emulator_after,
+                watch_assets,
+                ..Default::default()
+            };
+            let path = RunConfig::path(cmd.target_dir());
+            let config = match (!no_config).then(|| RunConfig::load(&path)).flatten() {
+                Some(saved) => {
+                    status(
+                        "Applying",
+                        format_args!("saved run config `{}`", path.display()),
+                    );
+                    saved.merge(cli)
+                }
+                None => cli,
+            };
+            if save_config {
+                config.save(&path)?;
+                status("Saved", format_args!("run config to `{}`", path.display()));
+            }
+            let mut device = config.device.clone();
+            let emulator = match &config.start_emulator {
+                Some(avd) => {
+                    let ndk = Ndk::from_env()?;
+                    let connected = ndk.devices()?.into_iter().any(|d| {
+                        d.state == "device" && device.as_ref().is_none_or(|s| *s == d.serial)
+                    });
+                    if connected {
+                        None
+                    } else {
+                        status("Starting", format_args!("emulator `{}`", avd));
+                        let emulator = Emulator::start(&ndk, avd, &emulator_args.options())?;
+                        device = Some(emulator.serial().to_string());
+                        Some((ndk, emulator))
+                    }
+                }
+                None => None,
+            };
+            let builder = ApkBuilder::from_subcommand(
+                &cmd,
+                ApkBuildOptions {
+                    device_serial: device,
+                    user: config.user,
+                    ..options
+                },
+            )?;
+            let artifact = select_artifact(&cmd)?;
+            let options = RunOptions {
+                no_logcat: config.no_logcat,
+                pull_after_run: config.pull_after_run,
+                no_diagnostics: config.no_diagnostics,
+                diagnostics_threshold: Duration::from_secs(
+                    config.diagnostics_threshold.unwrap_or(2),
+                ),
+                compile_mode: config.compile_mode,
+                system_install,
+                force_install: config.force_install,
+                fail_on_downgrade: config.fail_on_downgrade,
+                watch_assets: config.watch_assets,
+                no_build,
+                no_install,
+                restart,
+                launch_extras: LaunchExtras {
+                    args: app_args,
+                    env: app_env,
+                },
+            };
+            let result = builder.run(artifact, &options);
+            // Neither a failure to stop the emulator nor to write the timings may hide
+            // why the run failed
+            if let Some((ndk, emulator)) = emulator {
+                if config.stop_emulator_after {
+                    if let Err(e) = emulator.stop(&ndk) {
+                        eprintln!("Warning: could not stop the emulator: {}", e);
+                    }
+                }
+            }
+            let reported = report_timings(&cmd, args.flavor.as_deref(), timings);
+            result?;
+            reported?;
+        }
+        ApkSubCmd::Watch {
+            args,
+            no_logcat,
+            fail_on_downgrade,
+            app_env,
+            app_args,
+        } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
+            builder.watch(
+                artifact,
+                &RunOptions {
+                    no_logcat,
+                    fail_on_downgrade,
+                    launch_extras: LaunchExtras {
+                        args: app_args,
+                        env: app_env,
+                    },
+                    ..Default::default()
+                },
+            )?;
         }
         ApkSubCmd::Gdb { args } => {
-            let cmd = Subcommand::new(args.subcommand_args)?;
-            let builder = ApkBuilder::from_subcommand(&cmd, args.device)?;
-            let artifact = iterator_single_item(cmd.artifacts()).ok_or(Error::invalid_args())?;
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
             builder.gdb(artifact)?;
         }
+        ApkSubCmd::Profile {
+            args,
+            duration,
+            event,
+            output,
+            format,
+        } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let mut builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
+            let options = ProfileOptions {
+                record: RecordOptions {
+                    duration: Duration::from_secs(duration),
+                    event,
+                },
+                output,
+                format,
+            };
+            builder.profile(artifact, &options)?;
+        }
+        ApkSubCmd::Stop { args } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
+            builder.stop(artifact)?;
+        }
+        ApkSubCmd::Pull {
+            args,
+            device_path,
+            host_path,
+        } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
+            builder.pull(artifact, &device_path, host_path.as_deref())?;
+        }
+        ApkSubCmd::Publish {
+            args,
+            publisher,
+            notes,
+            notes_file,
+        } => {
+            let options = args.build_options();
+            let cmd = subcommand(args.subcommand_args)?;
+            let builder = ApkBuilder::from_subcommand(&cmd, options)?;
+            let artifact = select_artifact(&cmd)?;
+            let options = PublishOptions {
+                publisher,
+                notes: notes
+                    .map(ReleaseNotes::Text)
+                    .or(notes_file.map(ReleaseNotes::File)),
+            };
+            builder.publish(artifact, &options)?;
+        }
+        ApkSubCmd::Emulator { cmd } => {
+            let ndk = Ndk::from_env()?;
+            match cmd {
+                EmulatorSubCmd::List => {
+                    for avd in list_avds(&ndk)? {
+                        println!("{}", avd);
+                    }
+                }
+                EmulatorSubCmd::Start { avd, emulator_args } => {
+                    let emulator = Emulator::start(&ndk, &avd, &emulator_args.options())?;
+                    println!("{}", emulator.serial());
+                }
+            }
+        }
+        ApkSubCmd::Devices { args, users, json } => {
+            if users {
+                set_ctrlc_handler();
+                let ndk = Ndk::from_env_with(&args.ndk_options())?;
+                for user in ndk.users(args.device.as_deref())? {
+                    println!(
+                        "{}\t{}{}",
+                        user.id,
+                        user.name,
+                        if user.running { " (running)" } else { "" }
+                    );
+                }
+            } else {
+                // Like `doctor`, also usable outside of a package, without the package id
+                let cmd = subcommand(args.subcommand_args).ok();
+                devices(cmd.as_ref(), args.device.as_deref(), args.remote, json)?;
+            }
+        }
+        ApkSubCmd::PrintManifest { args } => {
+            let cmd = subcommand(args.subcommand_args)?;
+            let artifact = select_artifact(&cmd)?;
+            print_manifest(
+                &cmd,
+                artifact,
+                args.strict_sdk_check,
+                args.strict,
+                args.flavor.as_deref(),
+            )?;
+        }
+        ApkSubCmd::Metadata { args } => {
+            let cmd = subcommand(args.subcommand_args)?;
+            let artifact = select_artifact(&cmd)?;
+            print_metadata(
+                &cmd,
+                artifact,
+                args.strict_sdk_check,
+                args.strict,
+                args.flavor.as_deref(),
+            )?;
+        }
+        ApkSubCmd::Lint { args } => {
+            let cmd = subcommand(args.subcommand_args)?;
+            let artifact = select_artifact(&cmd)?;
+            lint(
+                &cmd,
+                artifact,
+                args.strict_sdk_check,
+                args.strict,
+                args.flavor.as_deref(),
+            )?;
+        }
+        ApkSubCmd::Doctor { args } => {
+            // Also usable outside of a package, without its sdk versions and `ndk_version`
+            let cmd = subcommand(args.subcommand_args).ok();
+            doctor(cmd.as_ref())?;
+        }
+        ApkSubCmd::Env { args } => {
+            // Like `doctor`, also usable outside of a package
+            let cmd = subcommand(args.subcommand_args).ok();
+            print_env(cmd.as_ref())?;
+        }
+        ApkSubCmd::New { path, scaffold } => new_project(&path, &scaffold.options())?,
+        ApkSubCmd::Init { path, scaffold } => init_project(&path, &scaffold.options())?,
+        ApkSubCmd::HashLibs { dir } => hash_libs(&dir)?,
+        ApkSubCmd::Completions { shell } => {
+            let shells = Shells::builtins();
+            let shell = shells.completer(&shell).unwrap();
+            // Resolved through `$PATH` so that the script keeps working after upgrades
+            shell.write_registration(
+                env::COMPLETE.name,
+                "cargo-apk",
+                "cargo-apk",
+                "cargo-apk",
+                &mut std::io::stdout(),
+            )?;
+        }
         ApkSubCmd::Version => {
             println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         }
@@ -185,6 +1010,216 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Writes the `--bug-report` of `error`, only warning if that fails as well.
+/// Writes the `--timings` report, also of a failed build, and prints its path.
+/// [`Subcommand::new()`] with `--target` given as an ABI like `arm64-v8a` replaced by its
+/// rust triple, which is what `cargo` and the target directory layout expect.
+fn subcommand(mut args: cargo_subcommand::Args) -> Result<Subcommand, cargo_apk::Error> {
+    if let Some(target) = &args.target {
+        let target = Target::from_abi_or_triple(target)?;
+        args.target = Some(target.rust_triple().to_string());
+    }
+    let cmd = Subcommand::new(args);
+    set_ctrlc_handler();
+    Ok(cmd?)
+}
+
+/// Whether [`set_ctrlc_handler()`] has yet to install the handler.
+static CTRLC_HANDLER: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler that terminates the spawned tools and removes temporary
+/// files, once [`main()`] enabled it. The handler runs on a thread of its own, so this
+/// waits until [`Subcommand::new()`] has set the `[env]` of `.cargo/config.toml` on
+/// this process.
+fn set_ctrlc_handler() {
+    if !CTRLC_HANDLER.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let result = ctrlc::set_handler(|| {
+        terminate_children();
+        run_cleanups();
+        remove_temp_paths();
+        std::process::exit(130);
+    });
+    if let Err(e) = result {
+        eprintln!("Warning: could not install the Ctrl-C handler: {}", e);
+    }
+}
+
+fn report_timings(
+    cmd: &Subcommand,
+    flavor: Option<&str>,
+    format: Option<TimingsFormat>,
+) -> anyhow::Result<()> {
+    if let Some(format) = format {
+        let path = write_timings(cmd, flavor, format)?;
+        status("Timings", format_args!("written to `{}`", path.display()));
+    }
+    Ok(())
+}
+
+fn report_bug(args: Option<Args>, error: &anyhow::Error) {
+    let flavor = args.as_ref().and_then(|args| args.flavor.clone());
+    // Also outside of a package, or when its `Cargo.toml` is what failed
+    let cmd = args.and_then(|args| subcommand(args.subcommand_args).ok());
+    let invocation = std::env::args().collect::<Vec<_>>().join(" ");
+    // Without the backtrace of `{:?}`, which only points into `main()`
+    let error = error
+        .chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\nCaused by: ");
+    match write_bug_report(cmd.as_ref(), flavor.as_deref(), &invocation, &error) {
+        Ok(path) => eprintln!(
+            "Wrote a bug report to `{}`, attach it when reporting this failure",
+            path.display()
+        ),
+        Err(e) => eprintln!("Warning: could not write the bug report: {}", e),
+    }
+}
+
+/// The `apk` subcommand, for `--list` and completions.
+fn apk_command() -> clap::Command {
+    Cmd::command().find_subcommand("apk").unwrap().clone()
+}
+
+fn print_subcommands() {
+    println!("Available subcommands:");
+    for cmd in apk_command().get_subcommands().filter(|c| !c.is_hide_set()) {
+        let about = cmd.get_about().map(ToString::to_string).unwrap_or_default();
+        println!("    {:<16} {}", cmd.get_name(), about);
+    }
+}
+
+/// The command line as parsed by [`Cmd`], with the `--example` and `--bin` names of the
+/// package in the working directory as completion candidates.
+fn completion_command() -> clap::Command {
+    Cmd::command().mut_subcommand("apk", |apk| {
+        apk.mut_subcommands(|cmd| {
+            cmd.mut_args(|arg| match arg.get_id().as_str() {
+                "example" => arg.add(ArgValueCandidates::new(|| {
+                    artifact_candidates(ArtifactType::Example)
+                })),
+                "bin" => arg.add(ArgValueCandidates::new(|| {
+                    artifact_candidates(ArtifactType::Bin)
+                })),
+                _ => arg,
+            })
+        })
+    })
+}
+
+fn artifact_candidates(r#type: ArtifactType) -> Vec<CompletionCandidate> {
+    // Completions are best-effort, e.g. outside of a package there is nothing to offer
+    let artifacts = package_artifacts(&Default::default()).unwrap_or_default();
+    let mut names = artifacts
+        .into_iter()
+        .filter(|a| a.r#type == r#type)
+        .map(|a| a.name)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.into_iter().map(CompletionCandidate::new).collect()
+}
+
+#[test]
+fn completion_command_is_valid() {
+    completion_command().debug_assert();
+    assert!(apk_command().find_subcommand("completions").is_some());
+}
+
+#[test]
+fn clippy_lint_args() {
+    let Cmd {
+        apk:
+            ApkCmd::Apk {
+                cmd:
+                    Some(ApkSubCmd::Clippy {
+                        args,
+                        all_targets,
+                        lint_args,
+                    }),
+                ..
+            },
+    } = Cmd::parse_from([
+        "cargo",
+        "apk",
+        "clippy",
+        "--all-targets",
+        "--lib",
+        "--",
+        "-D",
+        "warnings",
+    ])
+    else {
+        panic!("not parsed as `clippy`");
+    };
+    assert!(all_targets);
+    assert!(args.subcommand_args.lib);
+    assert_eq!(lint_args, ["-D", "warnings"]);
+}
+
+#[test]
+fn run_app_args() {
+    let Cmd {
+        apk:
+            ApkCmd::Apk {
+                cmd:
+                    Some(ApkSubCmd::Run {
+                        app_env, app_args, ..
+                    }),
+                ..
+            },
+    } = Cmd::parse_from([
+        "cargo",
+        "apk",
+        "run",
+        "--env",
+        "RUST_LOG=debug,wgpu=warn",
+        "--release",
+        "--",
+        "--level",
+        "2",
+    ])
+    else {
+        panic!("not parsed as `run`");
+    };
+    assert_eq!(
+        app_env,
+        [("RUST_LOG".to_string(), "debug,wgpu=warn".to_string())]
+    );
+    assert_eq!(app_args, ["--level", "2"]);
+    assert!(Cmd::try_parse_from(["cargo", "apk", "run", "--env", "=debug"]).is_err());
+}
+
+#[test]
+fn message_format_is_rejected() {
+    // `build` passes its own `--message-format` to `cargo`
+    assert!(Cmd::try_parse_from(["cargo", "apk", "build", "--message-format=json"]).is_err());
+}
+
+#[test]
+fn abi_target() {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let args = |target: &str| {
+        cargo_subcommand::Args::parse_from([
+            "cargo-apk",
+            "--manifest-path",
+            manifest,
+            "--target",
+            target,
+        ])
+    };
+    let cmd = subcommand(args("ARM64-v8a")).unwrap();
+    assert_eq!(cmd.target(), Some("aarch64-linux-android"));
+    assert_eq!(cmd.args().target.as_deref(), Some("aarch64-linux-android"));
+    let cmd = subcommand(args("i686-linux-android")).unwrap();
+    assert_eq!(cmd.target(), Some("i686-linux-android"));
+    assert!(matches!(
+        subcommand(args("arm64")),
+        Err(cargo_apk::Error::Ndk(cargo_apk::NdkError::UnknownTarget(_)))
+    ));
+}
+
 #[test]
 fn test_split_apk_and_cargo_args() {
     // Set up a default because cargo-subcommand doesn't derive a default
@@ -281,12 +1316,28 @@ fn test_split_apk_and_cargo_args() {
                     package: vec!["foo".to_string()],
                     ..args_default.subcommand_args.clone()
                 },
-                ..args_default
+                ..args_default.clone()
             },
             vec!["--no-deps".to_string(), "--unrecognized".to_string()]
         )
     );
 
+    assert_eq!(
+        split_apk_and_cargo_args(vec![
+            "--install-arg".to_string(),
+            "-d".to_string(),
+            "--install-arg=--no-streaming".to_string(),
+            "--no-deps".to_string(),
+        ]),
+        (
+            Args {
+                install_args: vec!["-d".to_string(), "--no-streaming".to_string()],
+                ..args_default.clone()
+            },
+            vec!["--no-deps".to_string()]
+        )
+    );
+
     assert_eq!(
         split_apk_and_cargo_args(vec![
             "--no-deps".to_string(),
@@ -302,6 +1353,7 @@ fn test_split_apk_and_cargo_args() {
                     ..args_default.subcommand_args
                 },
                 device: Some("adb:test".to_string()),
+                ..args_default
             },
             vec!["--no-deps".to_string(), "--unrecognized".to_string()]
         )
@@ -1,10 +1,29 @@
+use crate::asset_paths;
+use crate::asset_transform::AssetTransform;
 use crate::error::Error;
-use ndk_build::apk::StripConfig;
+use crate::flavor::Flavor;
+use crate::form_factor::FormFactor;
+use crate::gradle::{self, OutputLayout};
+use crate::hooks::Hooks;
+use crate::optimize::Optimize;
+use crate::publish::Publisher;
+use crate::size_limit::{PLAY_APK_LIMIT_MB, SizeLimitAction};
+use crate::splits::SplitAssets;
+use crate::unknown_keys::{self, UnknownKey};
+use crate::version_code::VersionCodeConfig;
+use crate::watch_assets::WatchAssets;
+use cargo_subcommand::{Artifact, ArtifactType};
+use ndk_build::apk::{StripConfig, StripMode};
+use ndk_build::checksums::{LibChecksum, LibChecksums};
 use ndk_build::manifest::AndroidManifest;
+use ndk_build::native::PackagingBackend;
+use ndk_build::ndk::VersionReq;
+use ndk_build::reverse::ReversePort;
+use ndk_build::sanitizer::Sanitizer;
 use ndk_build::target::Target;
 use serde::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 
@@ -15,18 +34,78 @@ pub enum Inheritable<T> {
     Inherited { workspace: bool },
 }
 
+#[derive(Clone)]
 pub(crate) struct Manifest {
+    /// The directory containing `Cargo.toml`, which relative paths are resolved against
+    pub(crate) crate_path: PathBuf,
+    /// The nearest directory above or at [`Manifest::crate_path`] whose `Cargo.toml` has
+    /// a `[workspace]` table, or else `crate_path`
+    pub(crate) workspace_root: PathBuf,
     pub(crate) version: Inheritable<String>,
     pub(crate) apk_name: Option<String>,
     pub(crate) android_manifest: AndroidManifest,
     pub(crate) build_targets: Vec<Target>,
+    /// As written, see [`Manifest::resolve_path()`]
     pub(crate) assets: Option<PathBuf>,
     pub(crate) resources: Option<PathBuf>,
     pub(crate) runtime_libs: Option<PathBuf>,
+    pub(crate) runtime_lib_checksums: LibChecksums,
+    pub(crate) baseline_profile: Option<PathBuf>,
+    pub(crate) bundle_licenses: bool,
+    pub(crate) bundle_licenses_exclude: Vec<String>,
+    pub(crate) inherit_dependency_manifest: bool,
+    pub(crate) stamp_build_info: bool,
+    pub(crate) legacy_storage: bool,
+    pub(crate) lint_allow: Vec<String>,
+    pub(crate) experimental: Vec<String>,
+    pub(crate) split_assets: Vec<SplitAssets>,
+    pub(crate) install_flags: Vec<String>,
+    pub(crate) output_layout: OutputLayout,
+    pub(crate) form_factor: FormFactor,
+    pub(crate) watch_assets: WatchAssets,
+    pub(crate) version_code: VersionCodeConfig,
+    /// Root of the `gradle` output layout
+    pub(crate) gradle_output_dir: PathBuf,
     /// Maps profiles to keystores
     pub(crate) signing: HashMap<String, Signing>,
-    pub(crate) reverse_port_forward: HashMap<String, String>,
+    pub(crate) reverse_port_forward: HashMap<String, ReversePort>,
     pub(crate) strip: StripConfig,
+    pub(crate) sdk_path: Option<PathBuf>,
+    pub(crate) ndk_path: Option<PathBuf>,
+    pub(crate) ndk_version: Option<VersionReq>,
+    pub(crate) build_tools_version: Option<String>,
+    pub(crate) compile_sdk_version: Option<u32>,
+    pub(crate) packaging_backend: PackagingBackend,
+    pub(crate) auto_install_sdk: bool,
+    pub(crate) adb_timeout_secs: Option<u64>,
+    pub(crate) link_args: Vec<String>,
+    pub(crate) replace_linker_args: bool,
+    pub(crate) target: HashMap<Target, TargetMetadata>,
+    pub(crate) sanitizer: Option<Sanitizer>,
+    pub(crate) vulkan_validation: bool,
+    pub(crate) vulkan_validation_layers: Option<PathBuf>,
+    pub(crate) audit_permissions: bool,
+    pub(crate) strict_manifest: bool,
+    pub(crate) allow_low_minsdk: bool,
+    pub(crate) require_64bit: bool,
+    pub(crate) hooks: Hooks,
+    pub(crate) asset_transform: Option<AssetTransform>,
+    pub(crate) allow_external_paths: bool,
+    /// Defaults to [`PLAY_APK_LIMIT_MB`]
+    pub(crate) max_apk_size_mb: u64,
+    pub(crate) size_limit_action: SizeLimitAction,
+    pub(crate) publish: BTreeMap<String, Publisher>,
+    pub(crate) flavors: BTreeMap<String, Flavor>,
+    /// The flavor selected through [`Manifest::apply_flavor()`]
+    pub(crate) flavor: Option<Flavor>,
+    /// Keys of `[package.metadata.android]` that were ignored
+    pub(crate) unknown_keys: Vec<UnknownKey>,
+    /// Maps profiles to their `[package.metadata.android.<profile>]` settings
+    pub(crate) profiles: HashMap<String, AndroidProfile>,
+    /// `[lib]`, for its `crate-type`
+    pub(crate) lib: Option<CargoTarget>,
+    /// `[[example]]`, for their `crate-type`
+    pub(crate) examples: Vec<CargoTarget>,
 }
 
 impl Manifest {
@@ -36,31 +115,229 @@ impl Manifest {
         // a `[package]` table (with a matching `name` when requested by the user)
         let package = toml
             .package
-            .unwrap_or_else(|| panic!("Manifest `{:?}` must contain a `[package]`", path));
-        let metadata = package
+            .ok_or_else(|| Error::PackageTableMissing(path.to_owned()))?;
+        let AndroidMetadata {
+            settings,
+            mut android_manifest,
+            profiles,
+        } = package
             .metadata
             .unwrap_or_default()
             .android
             .unwrap_or_default();
+        let raw = toml::from_str::<toml::Value>(&std::fs::read_to_string(path)?)?;
+        let unknown_keys = raw
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("android"))
+            .map_or_else(
+                Vec::new,
+                unknown_keys::find::<MetadataSettings, AndroidManifest, AndroidProfile>,
+            );
+        let crate_path = path
+            .parent()
+            .ok_or_else(|| Error::InvalidManifestPath(path.to_owned()))?;
+        // Any other table is taken to be a profile
+        let profiles = profiles
+            .into_iter()
+            .filter(|(_, value)| value.is_table())
+            .map(|(name, value)| Ok((name, value.try_into()?)))
+            .collect::<Result<_, Error>>()?;
+        for (target, metadata) in &settings.target {
+            metadata.validate(*target)?;
+        }
+        let version_code = match settings.version_code {
+            // The attribute itself, which is derived and thus rejected by
+            // `resolve_manifest()`
+            Some(toml::Value::Integer(code)) => {
+                android_manifest.version_code = Some(code as u32);
+                VersionCodeConfig::default()
+            }
+            Some(table) => table.try_into()?,
+            None => VersionCodeConfig::default(),
+        };
         Ok(Self {
+            crate_path: crate_path.to_owned(),
+            workspace_root: workspace_root(crate_path),
             version: package.version,
-            apk_name: metadata.apk_name,
-            android_manifest: metadata.android_manifest,
-            build_targets: metadata.build_targets,
-            assets: metadata.assets,
-            resources: metadata.resources,
-            runtime_libs: metadata.runtime_libs,
-            signing: metadata.signing,
-            reverse_port_forward: metadata.reverse_port_forward,
-            strip: metadata.strip,
+            apk_name: settings.apk_name,
+            android_manifest,
+            build_targets: settings.build_targets,
+            assets: settings.assets,
+            resources: settings.resources,
+            runtime_libs: settings.runtime_libs,
+            runtime_lib_checksums: LibChecksums {
+                entries: settings.runtime_lib_checksums,
+                require: settings.require_checksums,
+            },
+            baseline_profile: settings.baseline_profile,
+            bundle_licenses: settings.bundle_licenses,
+            bundle_licenses_exclude: settings.bundle_licenses_exclude,
+            inherit_dependency_manifest: settings.inherit_dependency_manifest,
+            stamp_build_info: settings.stamp_build_info,
+            legacy_storage: settings.legacy_storage,
+            lint_allow: settings.lint_allow,
+            experimental: settings.experimental,
+            split_assets: settings.split_assets,
+            install_flags: settings.install_flags,
+            output_layout: settings.output_layout,
+            form_factor: settings.form_factor,
+            watch_assets: settings.watch_assets,
+            version_code,
+            gradle_output_dir: crate_path.join(
+                settings
+                    .gradle_output_dir
+                    .as_deref()
+                    .unwrap_or(Path::new(gradle::DEFAULT_OUTPUT_DIR)),
+            ),
+            signing: settings.signing,
+            reverse_port_forward: settings.reverse_port_forward,
+            strip: settings.strip,
+            sdk_path: settings.sdk_path.map(|sdk_path| crate_path.join(sdk_path)),
+            ndk_path: settings.ndk_path.map(|ndk_path| crate_path.join(ndk_path)),
+            ndk_version: settings.ndk_version,
+            build_tools_version: settings.build_tools_version,
+            compile_sdk_version: settings.compile_sdk_version,
+            packaging_backend: settings.packaging_backend,
+            auto_install_sdk: settings.auto_install_sdk,
+            adb_timeout_secs: settings.adb_timeout_secs,
+            link_args: settings.link_args,
+            replace_linker_args: settings.replace_linker_args,
+            target: settings.target,
+            sanitizer: settings.sanitizer,
+            vulkan_validation: settings.vulkan_validation,
+            vulkan_validation_layers: settings.vulkan_validation_layers,
+            audit_permissions: settings.audit_permissions,
+            strict_manifest: settings.strict_manifest,
+            allow_low_minsdk: settings.allow_low_minsdk,
+            require_64bit: settings.require_64bit,
+            hooks: settings.hooks,
+            asset_transform: settings.asset_transform,
+            allow_external_paths: settings.allow_external_paths,
+            max_apk_size_mb: settings.max_apk_size_mb.unwrap_or(PLAY_APK_LIMIT_MB),
+            size_limit_action: settings.size_limit_action,
+            publish: settings.publish,
+            flavors: settings.flavor,
+            flavor: None,
+            unknown_keys,
+            profiles,
+            lib: toml.lib,
+            examples: toml.example,
         })
     }
+
+    /// Fails unless `Cargo.toml` makes `cargo` build `artifact` as a `cdylib`, which is
+    /// otherwise only noticed once the library is missing after compiling.
+    pub(crate) fn check_cdylib(&self, artifact: &Artifact) -> Result<(), Error> {
+        let (kind, target, header) = match artifact.r#type {
+            ArtifactType::Lib => ("Library", self.lib.as_ref(), "[lib]".to_string()),
+            ArtifactType::Example => (
+                "Example",
+                self.examples
+                    .iter()
+                    .find(|t| t.name.as_deref() == Some(&artifact.name)),
+                format!("[[example]]\nname = \"{}\"", artifact.name),
+            ),
+            ArtifactType::Bin => return Err(Error::BinNotPackageable(artifact.name.clone())),
+        };
+        let mut crate_types = target
+            .and_then(|t| t.crate_type.clone())
+            .unwrap_or_default();
+        if crate_types.iter().any(|t| t == "cdylib") {
+            return Ok(());
+        }
+        crate_types.push("cdylib".to_string());
+        let crate_types = crate_types
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>();
+        Err(Error::CdylibMissing {
+            kind,
+            name: artifact.name.clone(),
+            snippet: format!("{}\ncrate-type = [{}]", header, crate_types.join(", ")),
+        })
+    }
+
+    /// Warns about [`Manifest::unknown_keys`], or fails if `strict` or `strict_manifest = true`.
+    pub(crate) fn check_unknown_keys(&self, strict: bool) -> Result<(), Error> {
+        if (strict || self.strict_manifest) && !self.unknown_keys.is_empty() {
+            return Err(Error::UnknownManifestKeys(
+                self.unknown_keys.iter().map(ToString::to_string).collect(),
+            ));
+        }
+        for key in &self.unknown_keys {
+            eprintln!(
+                "Warning: `[package.metadata.android]` has unknown key {}",
+                key
+            );
+        }
+        Ok(())
+    }
+
+    /// Selects `[package.metadata.android.flavor.<name>]`, replacing the label, the
+    /// form factor and the keystores it configures. The other settings are read from
+    /// [`Manifest::flavor`].
+    /// Resolves `path`, the value of the setting `key` like `assets`, see [`asset_paths`].
+    pub(crate) fn resolve_path(&self, key: &str, path: &Path) -> Result<PathBuf, Error> {
+        asset_paths::resolve(
+            key,
+            path,
+            &self.crate_path,
+            &self.workspace_root,
+            self.allow_external_paths,
+            ndk_build::env::user_var,
+        )
+    }
+
+    pub(crate) fn apply_flavor(&mut self, name: &str) -> Result<(), Error> {
+        let mut flavor = self.flavors.remove(name).ok_or_else(|| {
+            Error::UnknownFlavor(name.to_string(), self.flavors.keys().cloned().collect())
+        })?;
+        flavor.name = name.to_string();
+        if let Some(label) = &flavor.label {
+            self.android_manifest.application.label = label.clone();
+        }
+        if let Some(form_factor) = flavor.form_factor {
+            self.form_factor = form_factor;
+        }
+        self.signing.extend(std::mem::take(&mut flavor.signing));
+        self.flavor = Some(flavor);
+        Ok(())
+    }
+}
+
+/// The nearest directory at or above `crate_path` whose `Cargo.toml` has a `[workspace]`
+/// table, like `cargo` looks for the workspace of a package.
+fn workspace_root(crate_path: &Path) -> PathBuf {
+    crate_path
+        .ancestors()
+        .find(|dir| {
+            std::fs::read_to_string(dir.join("Cargo.toml"))
+                .ok()
+                .and_then(|toml| toml::from_str::<toml::Value>(&toml).ok())
+                .is_some_and(|toml| toml.get("workspace").is_some())
+        })
+        .unwrap_or(crate_path)
+        .to_owned()
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct Root {
     pub(crate) package: Option<Package>,
     pub(crate) workspace: Option<Workspace>,
+    #[serde(default)]
+    pub(crate) profile: HashMap<String, toml::Value>,
+    pub(crate) lib: Option<CargoTarget>,
+    #[serde(default)]
+    pub(crate) example: Vec<CargoTarget>,
+}
+
+/// A `[lib]` or `[[example]]` table of `Cargo.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct CargoTarget {
+    pub(crate) name: Option<String>,
+    #[serde(rename = "crate-type", alias = "crate_type")]
+    pub(crate) crate_type: Option<Vec<String>>,
 }
 
 impl Root {
@@ -93,28 +370,576 @@ pub(crate) struct PackageMetadata {
     android: Option<AndroidMetadata>,
 }
 
+/// `[package.metadata.android]`, see [`crate::unknown_keys`] for how unknown keys are found
 #[derive(Clone, Debug, Default, Deserialize)]
 struct AndroidMetadata {
-    apk_name: Option<String>,
+    #[serde(flatten)]
+    settings: MetadataSettings,
     #[serde(flatten)]
     android_manifest: AndroidManifest,
+    #[serde(flatten)]
+    profiles: HashMap<String, toml::Value>,
+}
+
+/// The keys of [`AndroidMetadata`] that configure cargo-apk itself
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MetadataSettings {
+    apk_name: Option<String>,
     #[serde(default)]
     build_targets: Vec<Target>,
     assets: Option<PathBuf>,
     resources: Option<PathBuf>,
     runtime_libs: Option<PathBuf>,
+    /// SHA-256 of the libraries in `runtime_libs`, which fail the build if they differ
+    #[serde(default)]
+    runtime_lib_checksums: Vec<LibChecksum>,
+    /// Warn about libraries in `runtime_libs` without an entry in `runtime_lib_checksums`
+    #[serde(default)]
+    require_checksums: bool,
+    /// `baseline.prof` to package into `assets/dexopt/`, with the `baseline.profm` next
+    /// to it
+    baseline_profile: Option<PathBuf>,
+    /// Package the licenses of the dependencies into `assets/licenses/`
+    #[serde(default)]
+    bundle_licenses: bool,
+    /// Dependencies left out of `bundle_licenses`, besides the workspace members
+    #[serde(default)]
+    bundle_licenses_exclude: Vec<String>,
+    /// Merge `uses_permission`, `uses_feature`, `queries` and exported `meta_data` of the
+    /// dependencies into the manifest
+    #[serde(default)]
+    inherit_dependency_manifest: bool,
+    /// Record the git commit, build time, profile and `rustc` version in the manifest and
+    /// `assets/build_info.json`
+    #[serde(default)]
+    stamp_build_info: bool,
+    /// Request and preserve legacy external storage, and use the external storage
+    /// permissions
+    #[serde(default)]
+    legacy_storage: bool,
+    /// Ids of `cargo apk lint` checks that are not reported
+    #[serde(default)]
+    lint_allow: Vec<String>,
+    /// Unstable features to enable, like `split_assets`
+    #[serde(default)]
+    experimental: Vec<String>,
+    /// Configuration splits built next to the apk, each with the assets for one density
+    /// or language
+    #[serde(default)]
+    split_assets: Vec<SplitAssets>,
+    /// Appended to `adb install`, like `-d` to allow downgrades
+    #[serde(default)]
+    install_flags: Vec<String>,
+    /// `gradle` also copies the signed apk into the layout of the Android Gradle plugin
+    #[serde(default)]
+    output_layout: OutputLayout,
+    /// `tv` or `watch` apply a preset of manifest additions for that device type
+    #[serde(default)]
+    form_factor: FormFactor,
+    /// Where and how `cargo apk run --watch-assets` pushes changed assets
+    #[serde(default)]
+    watch_assets: WatchAssets,
+    /// The `[version_code]` table, taking the key from [`AndroidManifest::version_code`]
+    version_code: Option<toml::Value>,
+    /// Root of the `gradle` output layout, relative to the manifest
+    gradle_output_dir: Option<PathBuf>,
     /// Maps profiles to keystores
     #[serde(default)]
     signing: HashMap<String, Signing>,
     /// Set up reverse port forwarding before launching the application
     #[serde(default)]
-    reverse_port_forward: HashMap<String, String>,
+    reverse_port_forward: HashMap<String, ReversePort>,
     #[serde(default)]
     strip: StripConfig,
+    /// SDK root, relative to the manifest, taking precedence over `$ANDROID_HOME`
+    sdk_path: Option<PathBuf>,
+    /// NDK root, relative to the manifest, taking precedence over `$ANDROID_NDK_ROOT`
+    ndk_path: Option<PathBuf>,
+    /// Version requirement for NDKs installed side by side under `$ANDROID_HOME/ndk/`
+    ndk_version: Option<VersionReq>,
+    /// Exact `build-tools` version, overridden by `$ANDROID_BUILD_TOOLS_VERSION`
+    build_tools_version: Option<String>,
+    /// Platform whose `android.jar` resources are compiled against, instead of the
+    /// `target_sdk_version`
+    compile_sdk_version: Option<u32>,
+    /// Package and sign with the `aapt`, `zipalign` and `apksigner` of build-tools, or
+    /// with the `native` replacements, which need neither the SDK nor an `android.jar`
+    #[serde(default)]
+    packaging_backend: PackagingBackend,
+    /// Install missing SDK components through `sdkmanager`, like `--install-missing`
+    #[serde(default)]
+    auto_install_sdk: bool,
+    /// Seconds after which `adb` commands are killed, `0` disables the timeout
+    adb_timeout_secs: Option<u64>,
+    /// Appended to the final link of every target as `-Clink-arg=`
+    #[serde(default)]
+    link_args: Vec<String>,
+    /// Only pass [`MetadataSettings::link_args`] to the linker, without the defaults
+    #[serde(default)]
+    replace_linker_args: bool,
+    /// Per-target settings, keyed by Rust triple or Android ABI
+    #[serde(default)]
+    target: HashMap<Target, TargetMetadata>,
+    /// Instrument the build with ASan or HWASan and package the runtime and `wrap.sh`
+    sanitizer: Option<Sanitizer>,
+    /// Package the Khronos Vulkan validation layer and enable it during `cargo apk run`
+    #[serde(default)]
+    vulkan_validation: bool,
+    /// Directory with `<abi>/libVkLayer_khronos_validation.so`, relative to the manifest
+    vulkan_validation_layers: Option<PathBuf>,
+    /// Warn about `uses_permission` that don't match the APIs the libraries seem to use
+    #[serde(default)]
+    audit_permissions: bool,
+    /// Fail on unknown keys instead of warning about them, like `--strict`
+    #[serde(default)]
+    strict_manifest: bool,
+    /// Allow a `min_sdk_version` below 23, down to the lowest API level of the NDK
+    #[serde(default)]
+    allow_low_minsdk: bool,
+    /// Fail instead of warning when a 32-bit ABI is built without its 64-bit counterpart
+    #[serde(default)]
+    require_64bit: bool,
+    /// Commands run before and after building, installing and running
+    #[serde(default)]
+    hooks: Hooks,
+    /// Command that the assets with matching extensions are passed through when staged
+    asset_transform: Option<AssetTransform>,
+    /// Allow `assets` and `resources` outside of the workspace
+    #[serde(default)]
+    allow_external_paths: bool,
+    /// Size in megabytes above which a built apk is reported, 100 by default, the limit
+    /// of Google Play
+    max_apk_size_mb: Option<u64>,
+    /// Whether an apk above `max_apk_size_mb` is a warning or an error
+    #[serde(default)]
+    size_limit_action: SizeLimitAction,
+    /// Upload targets for `cargo apk publish`, keyed by name
+    #[serde(default)]
+    publish: BTreeMap<String, Publisher>,
+    /// Variants selected with `--flavor`, keyed by name
+    #[serde(default)]
+    flavor: BTreeMap<String, Flavor>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct AndroidProfile {
+    /// Size or speed preset for the `-C` codegen options
+    pub(crate) optimize: Option<Optimize>,
+    /// Zip the unstripped libraries next to the apk, like `--debug-symbols-bundle`
+    #[serde(default)]
+    pub(crate) debug_symbols_bundle: bool,
+    /// Overrides what the global `strip` removes from the libraries of this profile
+    pub(crate) strip: Option<StripMode>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct TargetMetadata {
+    /// Appended after the global `link_args`
+    #[serde(default)]
+    pub(crate) link_args: Vec<String>,
+    /// Package `lib<lib_name>.so` as the library of the activity on this target, instead
+    /// of the library of the artifact
+    pub(crate) lib_name: Option<String>,
+    /// `-Ctarget-cpu` of this target only
+    pub(crate) target_cpu: Option<String>,
+    /// `-Ctarget-feature`s of this target only, each enabled with `+` or disabled with `-`
+    #[serde(default)]
+    pub(crate) target_features: Vec<String>,
+}
+
+impl TargetMetadata {
+    /// Checks that every feature of `target_features` is a `+` or `-` followed by a name.
+    fn validate(&self, target: Target) -> Result<(), Error> {
+        for feature in &self.target_features {
+            let name = feature.strip_prefix(['+', '-']).unwrap_or_default();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                return Err(Error::InvalidTargetFeature {
+                    abi: target.android_abi(),
+                    feature: feature.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `target_features` enables NEON.
+    pub(crate) fn enables_neon(&self) -> bool {
+        self.target_features.iter().any(|f| f == "+neon")
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
 pub(crate) struct Signing {
     pub(crate) path: PathBuf,
     pub(crate) keystore_password: String,
+    /// Signing lineage from `apksigner rotate`, for signing with a rotated key
+    pub(crate) lineage: Option<PathBuf>,
+    pub(crate) rotation_min_sdk_version: Option<u32>,
+    /// The keys of the `lineage` before `path`, from the oldest to the newest
+    #[serde(default)]
+    pub(crate) older_keys: Vec<OlderSigningKey>,
+}
+
+// Keeps the password out of `{:?}` output of the whole manifest
+impl std::fmt::Debug for Signing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signing")
+            .field("path", &self.path)
+            .field("keystore_password", &"***")
+            .field("lineage", &self.lineage)
+            .field("rotation_min_sdk_version", &self.rotation_min_sdk_version)
+            .field("older_keys", &self.older_keys)
+            .finish()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct OlderSigningKey {
+    pub(crate) path: PathBuf,
+    pub(crate) alias: Option<String>,
+    /// Defaults to the `keystore_password` of the signing key
+    pub(crate) keystore_password: Option<String>,
+}
+
+impl std::fmt::Debug for OlderSigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OlderSigningKey")
+            .field("path", &self.path)
+            .field("alias", &self.alias)
+            .field(
+                "keystore_password",
+                &self.keystore_password.as_ref().map(|_| "***"),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use crate::version_code::VersionCodeScheme;
+
+    /// Parses a `Cargo.toml` with `contents` from a fresh directory named after `test`.
+    fn parse(test: &str, contents: &str) -> Result<Manifest, Error> {
+        let dir = TestDir::new(test);
+        let path = dir.join("Cargo.toml");
+        std::fs::write(&path, contents).unwrap();
+        Manifest::parse_from_toml(&path)
+    }
+
+    #[test]
+    fn profiles() {
+        let manifest = parse(
+            "profiles",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android]
+            package = "com.example"
+            build_targets = ["aarch64-linux-android"]
+
+            [package.metadata.android.sdk]
+            min_sdk_version = 26
+
+            [package.metadata.android.release]
+            optimize = "aggressive-size"
+            debug_symbols_bundle = true
+            strip = "all"
+
+            [package.metadata.android.profiling]
+            strip = "debuginfo"
+
+            [package.metadata.android.signing.release]
+            path = "release.keystore"
+            keystore_password = "android"
+            "#,
+        )
+        .unwrap();
+        assert!(
+            manifest.unknown_keys.is_empty(),
+            "{:?}",
+            manifest.unknown_keys
+        );
+        assert_eq!(manifest.android_manifest.package, "com.example");
+        assert_eq!(manifest.android_manifest.sdk.min_sdk_version, Some(26));
+        assert!(manifest.signing.contains_key("release"));
+        assert_eq!(
+            manifest.profiles["release"].optimize,
+            Some(Optimize::AggressiveSize)
+        );
+        assert!(manifest.profiles["release"].debug_symbols_bundle);
+        assert_eq!(manifest.profiles["release"].strip, Some(StripMode::All));
+        assert_eq!(
+            manifest.profiles["profiling"].strip,
+            Some(StripMode::Debuginfo)
+        );
+        assert!(!manifest.profiles.contains_key("signing"));
+    }
+
+    #[test]
+    fn flavors() {
+        let mut manifest = parse(
+            "flavors",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android]
+            package = "com.example"
+            application.label = "Example"
+
+            [package.metadata.android.signing.release]
+            path = "release.keystore"
+            keystore_password = "android"
+
+            [package.metadata.android.flavor.demo]
+            package_suffix = ".demo"
+            label = "Example Demo"
+            assets = "assets-demo"
+            features = ["demo"]
+
+            [package.metadata.android.flavor.demo.signing.release]
+            path = "demo.keystore"
+            keystore_password = "demo"
+
+            [package.metadata.android.flavor.full]
+            "#,
+        )
+        .unwrap();
+        assert!(
+            manifest.unknown_keys.is_empty(),
+            "{:?}",
+            manifest.unknown_keys
+        );
+        assert!(!manifest.profiles.contains_key("flavor"));
+        assert!(matches!(
+            manifest.apply_flavor("trial"),
+            Err(Error::UnknownFlavor(name, names)) if name == "trial" && names == ["demo", "full"]
+        ));
+
+        manifest.apply_flavor("demo").unwrap();
+        assert_eq!(manifest.android_manifest.application.label, "Example Demo");
+        assert_eq!(
+            manifest.signing["release"].path,
+            PathBuf::from("demo.keystore")
+        );
+        let flavor = manifest.flavor.unwrap();
+        assert_eq!(flavor.name, "demo");
+        assert_eq!(flavor.package_suffix.as_deref(), Some(".demo"));
+        assert_eq!(flavor.features, ["demo"]);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let manifest = parse(
+            "unknown-keys",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android]
+            runtime-libs = "libs"
+
+            [package.metadata.android.application.activity]
+            lable = "Main"
+
+            [package.metadata.android.dev]
+            optimise = "size"
+
+            [package.metadata.android.publish.upload]
+            backend = "http"
+            url = "https://example.com/app.apk"
+            headers = { Authorization = "UPLOAD_AUTHORIZATION" }
+            "#,
+        )
+        .unwrap();
+        let unknown = |path: &str, suggestion: &str| UnknownKey {
+            path: path.to_string(),
+            suggestion: Some(suggestion.to_string()),
+        };
+        assert_eq!(
+            manifest.unknown_keys,
+            [
+                unknown("application.activity.lable", "application.activity.label"),
+                unknown("dev.optimise", "dev.optimize"),
+                unknown("runtime-libs", "runtime_libs"),
+            ]
+        );
+        assert!(manifest.check_unknown_keys(false).is_ok());
+        assert!(matches!(
+            manifest.check_unknown_keys(true),
+            Err(Error::UnknownManifestKeys(keys)) if keys.len() == 3
+        ));
+    }
+
+    #[test]
+    fn version_code() {
+        let manifest = parse(
+            "version-code",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android.version_code]
+            scheme = "date"
+            offset = 100000
+
+            [package.metadata.android.application]
+            lable = "Main"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.version_code.scheme, VersionCodeScheme::Date);
+        assert_eq!(manifest.version_code.offset, 100000);
+        assert_eq!(manifest.android_manifest.version_code, None);
+        // Neither a profile nor in the way of finding unknown keys
+        assert!(manifest.profiles.is_empty());
+        assert_eq!(manifest.unknown_keys.len(), 1);
+
+        // A plain code is the derived attribute
+        let manifest = parse(
+            "version-code-attribute",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android]
+            version_code = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.android_manifest.version_code, Some(3));
+        assert_eq!(manifest.version_code.scheme, VersionCodeScheme::Semver);
+    }
+
+    #[test]
+    fn targets() {
+        let manifest = parse(
+            "targets",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android.target.armv7-linux-androideabi]
+            link_args = ["-Wl,-z,nocopyreloc"]
+            target_cpu = "cortex-a7"
+            target_features = ["+neon", "+vfp4"]
+
+            [package.metadata.android.target.arm64-v8a]
+            lib_name = "game64"
+            "#,
+        )
+        .unwrap();
+        assert!(
+            manifest.unknown_keys.is_empty(),
+            "{:?}",
+            manifest.unknown_keys
+        );
+        assert_eq!(
+            manifest.target[&Target::ArmV7a].link_args,
+            ["-Wl,-z,nocopyreloc"]
+        );
+        assert_eq!(manifest.target[&Target::ArmV7a].lib_name, None);
+        assert!(manifest.target[&Target::ArmV7a].enables_neon());
+        assert_eq!(
+            manifest.target[&Target::ArmV7a].target_cpu.as_deref(),
+            Some("cortex-a7")
+        );
+        assert_eq!(
+            manifest.target[&Target::Arm64V8a].lib_name.as_deref(),
+            Some("game64")
+        );
+        assert!(
+            manifest.target[&Target::Arm64V8a]
+                .target_features
+                .is_empty()
+        );
+
+        for feature in ["neon", "+", "+neon,+vfp4", "+ neon"] {
+            let toml = format!(
+                "[package]\nversion = \"0.1.0\"\n\
+                [package.metadata.android.target.armv7-linux-androideabi]\n\
+                target_features = [{:?}]\n",
+                feature
+            );
+            assert!(
+                matches!(
+                    parse("target-features", &toml),
+                    Err(Error::InvalidTargetFeature { abi: "armeabi-v7a", feature: f }) if f == feature
+                ),
+                "{:?}",
+                feature
+            );
+        }
+    }
+
+    #[test]
+    fn strict_manifest() {
+        let manifest = parse(
+            "strict-manifest",
+            r#"
+            [package]
+            version = "0.1.0"
+
+            [package.metadata.android]
+            strict_manifest = true
+            biuld_targets = ["aarch64-linux-android"]
+            "#,
+        )
+        .unwrap();
+        let error = manifest.check_unknown_keys(false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`[package.metadata.android]` has unknown keys:\n  `biuld_targets`, did you mean `build_targets`?"
+        );
+    }
+
+    #[test]
+    fn cdylib() {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let artifact = |name: &str, r#type| Artifact {
+            name: name.to_string(),
+            path: PathBuf::new(),
+            r#type,
+        };
+        let snippet = |name: &str, r#type| {
+            let manifest =
+                Manifest::parse_from_toml(&testdata.join("misconfigured/Cargo.toml")).unwrap();
+            match manifest.check_cdylib(&artifact(name, r#type)) {
+                Err(Error::CdylibMissing { snippet, .. }) => snippet,
+                other => panic!("{:?}", other),
+            }
+        };
+        assert_eq!(
+            snippet("misconfigured", ArtifactType::Lib),
+            "[lib]\ncrate-type = [\"rlib\", \"cdylib\"]"
+        );
+        assert_eq!(
+            snippet("listed", ArtifactType::Example),
+            "[[example]]\nname = \"listed\"\ncrate-type = [\"cdylib\"]"
+        );
+        assert_eq!(
+            snippet("auto", ArtifactType::Example),
+            "[[example]]\nname = \"auto\"\ncrate-type = [\"cdylib\"]"
+        );
+
+        let manifest =
+            Manifest::parse_from_toml(&testdata.join("artifacts/app/Cargo.toml")).unwrap();
+        manifest
+            .check_cdylib(&artifact("app", ArtifactType::Lib))
+            .unwrap();
+        manifest
+            .check_cdylib(&artifact("triangle", ArtifactType::Example))
+            .unwrap();
+        assert!(matches!(
+            manifest.check_cdylib(&artifact("tool", ArtifactType::Bin)),
+            Err(Error::BinNotPackageable(name)) if name == "tool"
+        ));
+    }
 }
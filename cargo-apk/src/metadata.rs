@@ -0,0 +1,250 @@
+//! `cargo apk metadata`: prints the resolved configuration of an artifact as JSON, for
+//! scripts and tools that need the package id or the version without building.
+
+use crate::apk::profile_name;
+use crate::error::Error;
+use crate::manifest::Manifest;
+use crate::print_manifest::{NO_NDK_DEFAULTS, offline_defaults, offline_ndk};
+use crate::resolve::{
+    ManifestDefaults, ResolveContext, print_warnings, resolve_manifest, sdk_versions,
+};
+use cargo_subcommand::{Artifact, Subcommand};
+use ndk_build::manifest::{AndroidManifest, IntentFilter};
+use ndk_build::target::Target;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Version of the [`Metadata`] document, raised on every change that can break its
+/// readers. Adding a field doesn't.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// The document printed by `cargo apk metadata`.
+#[derive(Debug, Serialize)]
+pub(crate) struct Metadata {
+    pub(crate) schema_version: u32,
+    /// The application id, with the suffix of the flavor
+    pub(crate) package: String,
+    pub(crate) version_name: Option<String>,
+    pub(crate) version_code: Option<u32>,
+    pub(crate) min_sdk_version: u32,
+    pub(crate) target_sdk_version: u32,
+    pub(crate) max_sdk_version: Option<u32>,
+    /// Android ABIs that would be built
+    pub(crate) targets: Vec<&'static str>,
+    pub(crate) profile: String,
+    pub(crate) apk_name: String,
+    pub(crate) flavor: Option<FlavorMetadata>,
+    pub(crate) assets: Option<PathBuf>,
+    pub(crate) resources: Option<PathBuf>,
+    /// The profiles of `[package.metadata.android.signing.<profile>]`, without secrets
+    pub(crate) signing: Vec<String>,
+    pub(crate) permissions: Vec<PermissionMetadata>,
+    /// The activity, followed by its aliases
+    pub(crate) activities: Vec<ActivityMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FlavorMetadata {
+    pub(crate) name: String,
+    /// Merged over [`Metadata::assets`]
+    pub(crate) assets: Option<PathBuf>,
+    pub(crate) features: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PermissionMetadata {
+    pub(crate) name: String,
+    pub(crate) max_sdk_version: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ActivityMetadata {
+    pub(crate) name: String,
+    /// The activity launched by an alias
+    pub(crate) target_activity: Option<String>,
+    pub(crate) exported: Option<bool>,
+    /// Whether it shows up in the launcher
+    pub(crate) launcher: bool,
+}
+
+/// Prints the [`Metadata`] of `artifact` to stdout, resolved like
+/// [`crate::print_manifest()`] resolves the `AndroidManifest.xml`.
+///
+/// The targets are the `--target`, or else `build_targets`, or else `arm64-v8a`, as
+/// no device is asked for its ABI.
+pub fn print_metadata(
+    cmd: &Subcommand,
+    artifact: &Artifact,
+    strict_sdk_check: bool,
+    strict_manifest: bool,
+    flavor: Option<&str>,
+) -> Result<(), Error> {
+    let metadata = resolve(cmd, artifact, strict_sdk_check, strict_manifest, flavor)?;
+    println!("{}", serde_json::to_string_pretty(&metadata).unwrap());
+    Ok(())
+}
+
+/// The [`Metadata`] that [`print_metadata()`] prints.
+pub(crate) fn resolve(
+    cmd: &Subcommand,
+    artifact: &Artifact,
+    strict_sdk_check: bool,
+    strict_manifest: bool,
+    flavor: Option<&str>,
+) -> Result<Metadata, Error> {
+    let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+    manifest.check_unknown_keys(strict_manifest)?;
+    if let Some(flavor) = flavor {
+        manifest.apply_flavor(flavor)?;
+    }
+    let ndk = offline_ndk(&manifest, crate::config::cargo_home_env()?);
+    if let Err(e) = &ndk {
+        eprintln!("Warning: {}\n{}", e, NO_NDK_DEFAULTS);
+    }
+    let defaults = offline_defaults(cmd, &manifest, ndk.ok().as_ref(), strict_sdk_check)?;
+    let targets = match cmd.target() {
+        Some(target) => vec![Target::from_abi_or_triple(target)?],
+        None if !manifest.build_targets.is_empty() => manifest.build_targets.clone(),
+        None => vec![Target::Arm64V8a],
+    };
+    collect(
+        manifest,
+        artifact,
+        &defaults,
+        &targets,
+        profile_name(cmd.profile()),
+    )
+}
+
+/// Resolves the [`Metadata`] of `artifact`, printing the warnings of the resolution.
+fn collect(
+    manifest: Manifest,
+    artifact: &Artifact,
+    defaults: &ManifestDefaults,
+    targets: &[Target],
+    profile: &str,
+) -> Result<Metadata, Error> {
+    let mut warnings = vec![];
+    let sdk = sdk_versions(&manifest, defaults, &mut warnings)?;
+    let apk_name = manifest
+        .apk_name
+        .clone()
+        .unwrap_or_else(|| artifact.name.to_string());
+    let resolve = |key: &str, path: Option<&PathBuf>| {
+        path.map(|path| manifest.resolve_path(key, path))
+            .transpose()
+    };
+    let flavor = match &manifest.flavor {
+        Some(flavor) => Some(FlavorMetadata {
+            name: flavor.name.clone(),
+            assets: resolve(
+                &format!("flavor.{}.assets", flavor.name),
+                flavor.assets.as_ref(),
+            )?,
+            features: flavor.features.clone(),
+        }),
+        None => None,
+    };
+    let assets = resolve("assets", manifest.assets.as_ref())?;
+    let resources = resolve("resources", manifest.resources.as_ref())?;
+    let mut signing = manifest.signing.keys().cloned().collect::<Vec<_>>();
+    signing.sort();
+
+    let ctx = ResolveContext {
+        artifact,
+        defaults,
+        warnings: &mut warnings,
+    };
+    let android_manifest = resolve_manifest(manifest, ctx)?;
+    print_warnings(warnings);
+    Ok(Metadata {
+        schema_version: SCHEMA_VERSION,
+        package: android_manifest.package.clone(),
+        version_name: android_manifest.version_name.clone(),
+        version_code: android_manifest.version_code,
+        min_sdk_version: sdk.min,
+        target_sdk_version: sdk.target,
+        max_sdk_version: android_manifest.sdk.max_sdk_version,
+        targets: targets.iter().map(|t| t.android_abi()).collect(),
+        profile: profile.to_string(),
+        apk_name,
+        flavor,
+        assets,
+        resources,
+        signing,
+        permissions: android_manifest
+            .uses_permission
+            .iter()
+            .map(|p| PermissionMetadata {
+                name: p.name.clone(),
+                max_sdk_version: p.max_sdk_version,
+            })
+            .collect(),
+        activities: activities(&android_manifest),
+    })
+}
+
+fn activities(manifest: &AndroidManifest) -> Vec<ActivityMetadata> {
+    let activity = &manifest.application.activity;
+    let aliases = manifest
+        .application
+        .activity_alias
+        .iter()
+        .map(|alias| ActivityMetadata {
+            name: alias.name.clone(),
+            target_activity: Some(alias.target_activity.clone()),
+            exported: alias.exported,
+            launcher: alias.intent_filter.iter().any(IntentFilter::is_launcher),
+        });
+    std::iter::once(ActivityMetadata {
+        name: activity.name.clone(),
+        target_activity: None,
+        exported: activity.exported,
+        launcher: activity.intent_filter.iter().any(IntentFilter::is_launcher),
+    })
+    .chain(aliases)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_subcommand::ArtifactType;
+    use std::path::Path;
+
+    #[test]
+    fn schema() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/metadata/Cargo.toml");
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+        manifest.apply_flavor("demo").unwrap();
+        manifest.crate_path = PathBuf::from("/app");
+        manifest.workspace_root = PathBuf::from("/app");
+        let artifact = Artifact {
+            name: "my-game".to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type: ArtifactType::Lib,
+        };
+        let defaults = ManifestDefaults {
+            package_version: "1.2.3".to_string(),
+            timestamp: 0,
+            is_debug_profile: false,
+            target_sdk_version: 34,
+            supported_api_levels: 21..=35,
+            ndk_version: None,
+            strict_sdk_check: false,
+        };
+        let metadata = collect(
+            manifest,
+            &artifact,
+            &defaults,
+            &[Target::Arm64V8a, Target::ArmV7a],
+            "release",
+        )
+        .unwrap();
+        // Changing the snapshot breaks the readers of the document, see `SCHEMA_VERSION`
+        assert_eq!(
+            serde_json::to_string_pretty(&metadata).unwrap() + "\n",
+            include_str!("../testdata/metadata/metadata.json")
+        );
+    }
+}
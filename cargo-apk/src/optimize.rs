@@ -0,0 +1,175 @@
+//! `optimize` presets from `[package.metadata.android.<profile>]`, translated to `-C`
+//! flags that leave the options configured by the user alone.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Optimize {
+    /// Optimize for size, keeping parallel codegen and unwinding
+    Size,
+    /// Optimize for speed with thin LTO
+    Speed,
+    /// Smallest binaries: `opt-level=z`, fat LTO, a single codegen unit and `panic=abort`
+    AggressiveSize,
+}
+
+impl Optimize {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Size => "size",
+            Self::Speed => "speed",
+            Self::AggressiveSize => "aggressive-size",
+        }
+    }
+
+    /// `(option, value)` pairs for `-C<option>=<value>`.
+    fn codegen_options(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Size => &[("opt-level", "s"), ("lto", "thin"), ("strip", "symbols")],
+            Self::Speed => &[("opt-level", "3"), ("lto", "thin")],
+            Self::AggressiveSize => &[
+                ("opt-level", "z"),
+                ("lto", "fat"),
+                ("codegen-units", "1"),
+                ("panic", "abort"),
+                ("strip", "symbols"),
+            ],
+        }
+    }
+
+    /// Returns the flags of this preset, leaving out options that `env_rustflags` or the
+    /// profile already set, as reported by `profile_sets(<option>)`.
+    pub(crate) fn rustflags(
+        self,
+        env_rustflags: &[String],
+        profile_sets: impl Fn(&str) -> bool,
+    ) -> Vec<String> {
+        let explicit = explicit_codegen_options(env_rustflags);
+        let mut flags = Vec::new();
+        for (option, value) in self.codegen_options() {
+            if explicit.contains(&option.to_string()) || profile_sets(option) {
+                continue;
+            }
+            flags.push(format!("-C{}={}", option, value));
+            // Cargo passes `-Cembed-bitcode=no` to dependencies when the profile
+            // doesn't enable LTO, which `rustc` rejects in combination with `-Clto`
+            if *option == "lto" {
+                flags.push("-Cembed-bitcode=yes".to_string());
+            }
+        }
+        flags
+    }
+}
+
+/// Names of the `-C` options set in `rustflags`, in any of the `-Cname=value`,
+/// `-C name=value` or `--codegen name=value` forms.
+fn explicit_codegen_options(rustflags: &[String]) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut flags = rustflags.iter();
+    while let Some(flag) = flags.next() {
+        let option = match flag.as_str() {
+            "-C" | "--codegen" => flags.next().map(String::as_str),
+            "-O" => Some("opt-level"),
+            flag => flag
+                .strip_prefix("--codegen=")
+                .or_else(|| flag.strip_prefix("-C")),
+        };
+        if let Some(option) = option {
+            let name = option.split('=').next().unwrap();
+            options.push(name.replace('_', "-"));
+        }
+    }
+    options
+}
+
+/// Whether `key` is set for `profile` in the `[profile]` tables of the workspace root
+/// manifest, following `inherits`, or through `$CARGO_PROFILE_<PROFILE>_<KEY>`.
+pub(crate) fn profile_sets(
+    profiles: &HashMap<String, toml::Value>,
+    env: impl Fn(&str) -> Option<String>,
+    profile: &str,
+    key: &str,
+) -> bool {
+    let mut profile = profile;
+    // Bounded to not loop on cyclic `inherits`, which cargo rejects anyway
+    for _ in 0..profiles.len() + 1 {
+        let var = ndk_build::env::CARGO_PROFILE.expand(&[profile, key]);
+        if env(&var).is_some() {
+            return true;
+        }
+        let table = match profiles.get(profile) {
+            Some(table) => table,
+            None => return false,
+        };
+        if table.get(key).is_some() {
+            return true;
+        }
+        match table.get("inherits").and_then(toml::Value::as_str) {
+            Some(inherits) => profile = inherits,
+            None => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn preset() {
+        assert_eq!(
+            Optimize::AggressiveSize.rustflags(&[], |_| false),
+            flags(&[
+                "-Copt-level=z",
+                "-Clto=fat",
+                "-Cembed-bitcode=yes",
+                "-Ccodegen-units=1",
+                "-Cpanic=abort",
+                "-Cstrip=symbols",
+            ])
+        );
+    }
+
+    #[test]
+    fn explicit_settings_win() {
+        let env = flags(&[
+            "-Copt-level=2",
+            "-C",
+            "panic=unwind",
+            "--codegen=codegen_units=4",
+        ]);
+        assert_eq!(
+            Optimize::AggressiveSize.rustflags(&env, |key| key == "lto"),
+            flags(&["-Cstrip=symbols"])
+        );
+        assert_eq!(
+            Optimize::Speed.rustflags(&flags(&["-O"]), |_| false),
+            flags(&["-Clto=thin", "-Cembed-bitcode=yes"])
+        );
+    }
+
+    #[test]
+    fn profile_inherits() {
+        let profiles: HashMap<String, toml::Value> = toml::from_str(
+            r#"
+            release = { lto = false }
+            mobile = { inherits = "release", opt-level = 3 }
+            "#,
+        )
+        .unwrap();
+        let no_env = |_: &str| None;
+        assert!(profile_sets(&profiles, no_env, "mobile", "opt-level"));
+        assert!(profile_sets(&profiles, no_env, "mobile", "lto"));
+        assert!(!profile_sets(&profiles, no_env, "mobile", "strip"));
+        assert!(!profile_sets(&profiles, no_env, "dev", "lto"));
+        let env = |var: &str| (var == "CARGO_PROFILE_DEV_CODEGEN_UNITS").then(String::new);
+        assert!(profile_sets(&profiles, env, "dev", "codegen-units"));
+    }
+}
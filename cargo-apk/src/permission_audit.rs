@@ -0,0 +1,234 @@
+//! `audit_permissions = true`: guesses from the imported symbols and the strings of the
+//! packaged libraries which APIs behind a permission the app uses, and warns about
+//! requested permissions that nothing seems to use and uses whose permission is not
+//! requested. Java code and libraries loaded at runtime are invisible to it.
+
+use ndk_build::apk::UnalignedApk;
+use ndk_build::error::NdkError;
+use ndk_build::manifest::UsesPermission;
+use ndk_build::readelf::{imported_dynamic_symbols, section_strings};
+use std::collections::HashSet;
+
+/// What the audit looks at in one library.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LibUsage {
+    /// `<abi>/<lib>.so`
+    pub(crate) name: String,
+    pub(crate) imports: HashSet<String>,
+    /// The strings of `.rodata`, where JNI class and method names end up
+    pub(crate) strings: Vec<String>,
+}
+
+/// An API that needs a permission.
+struct Api {
+    /// What the app does with it, for the warnings
+    what: &'static str,
+    /// Any of them grants access to the API
+    permissions: &'static [&'static str],
+    /// Prefixes of the imported symbols of its NDK interface
+    symbols: &'static [&'static str],
+    /// Parts of the strings that reach it over JNI
+    strings: &'static [&'static str],
+}
+
+const APIS: &[Api] = &[
+    Api {
+        what: "the camera",
+        permissions: &["android.permission.CAMERA"],
+        symbols: &[
+            "ACameraManager_",
+            "ACameraDevice_",
+            "ACameraCaptureSession_",
+        ],
+        strings: &["android/hardware/Camera", "android/hardware/camera2/"],
+    },
+    Api {
+        what: "the microphone",
+        permissions: &["android.permission.RECORD_AUDIO"],
+        symbols: &["AAudioStreamBuilder_setInputPreset", "SL_IID_RECORD"],
+        strings: &["android/media/AudioRecord"],
+    },
+    Api {
+        what: "the location",
+        permissions: &[
+            "android.permission.ACCESS_FINE_LOCATION",
+            "android.permission.ACCESS_COARSE_LOCATION",
+        ],
+        symbols: &[],
+        strings: &[
+            "android/location/LocationManager",
+            "requestLocationUpdates",
+            "getLastKnownLocation",
+        ],
+    },
+    Api {
+        what: "the network",
+        permissions: &["android.permission.INTERNET"],
+        symbols: &["getaddrinfo", "gethostbyname"],
+        strings: &[],
+    },
+    Api {
+        what: "the vibrator",
+        permissions: &["android.permission.VIBRATE"],
+        symbols: &[],
+        strings: &["android/os/Vibrator"],
+    },
+    Api {
+        what: "Bluetooth",
+        permissions: &[
+            "android.permission.BLUETOOTH_CONNECT",
+            "android.permission.BLUETOOTH",
+        ],
+        symbols: &[],
+        strings: &["android/bluetooth/"],
+    },
+    Api {
+        what: "wake locks",
+        permissions: &["android.permission.WAKE_LOCK"],
+        symbols: &[],
+        strings: &["android/os/PowerManager$WakeLock"],
+    },
+];
+
+impl Api {
+    /// How `lib` seems to use the API, if it does.
+    fn evidence(&self, lib: &LibUsage) -> Option<String> {
+        let mut imports = lib.imports.iter().collect::<Vec<_>>();
+        imports.sort();
+        if let Some(symbol) = imports
+            .into_iter()
+            .find(|symbol| self.symbols.iter().any(|s| symbol.starts_with(s)))
+        {
+            return Some(format!("imports `{}`", symbol));
+        }
+        self.strings
+            .iter()
+            .find(|s| lib.strings.iter().any(|string| string.contains(*s)))
+            .map(|s| format!("contains `{}`", s))
+    }
+}
+
+/// Reads the imports and strings of every library added to `apk`.
+pub(crate) fn scan(apk: &UnalignedApk<'_>) -> Result<Vec<LibUsage>, NdkError> {
+    let ndk = &apk.config().ndk;
+    let mut libs = vec![];
+    for (path, target) in apk.libs() {
+        let readelf = ndk.toolchain_bin("readelf", target)?;
+        libs.push(LibUsage {
+            name: format!(
+                "{}/{}",
+                target.android_abi(),
+                path.file_name().unwrap().to_string_lossy()
+            ),
+            imports: imported_dynamic_symbols(&readelf, path)?,
+            strings: section_strings(&readelf, path, ".rodata")?,
+        });
+    }
+    Ok(libs)
+}
+
+/// The warnings about `uses_permission` that don't match the APIs used by `libs`. Only
+/// the permissions of the APIs that the audit knows are checked.
+pub(crate) fn audit(libs: &[LibUsage], uses_permission: &[UsesPermission]) -> Vec<String> {
+    let requested = |permission: &str| uses_permission.iter().any(|p| p.name == permission);
+    let mut warnings = vec![];
+    for api in APIS {
+        let is_requested = api.permissions.iter().any(|p| requested(p));
+        let uses = libs
+            .iter()
+            .filter_map(|lib| Some((lib, api.evidence(lib)?)))
+            .collect::<Vec<_>>();
+        if uses.is_empty() {
+            for permission in api.permissions.iter().filter(|p| requested(p)) {
+                warnings.push(format!(
+                    "`{}` is requested, but no library seems to use {}. Remove it from \
+                    `uses_permission` unless Java code or a library loaded at runtime does",
+                    permission, api.what
+                ));
+            }
+        } else if !is_requested {
+            for (lib, evidence) in uses {
+                warnings.push(format!(
+                    "`{}` seems to use {} as it {}, but `uses_permission` doesn't request {}",
+                    lib.name,
+                    api.what,
+                    evidence,
+                    alternatives(api.permissions)
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Like "`A`" or "`A` or `B`".
+fn alternatives(permissions: &[&str]) -> String {
+    permissions
+        .iter()
+        .map(|p| format!("`{}`", p))
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission(name: &str) -> UsesPermission {
+        UsesPermission {
+            name: name.to_string(),
+            max_sdk_version: None,
+        }
+    }
+
+    #[test]
+    fn permissions() {
+        let game = LibUsage {
+            name: "arm64-v8a/libgame.so".to_string(),
+            imports: HashSet::from([
+                "ACameraManager_create".to_string(),
+                "ACameraManager_getCameraIdList".to_string(),
+                "malloc".to_string(),
+            ]),
+            strings: vec!["Failed to call requestLocationUpdates: %s".to_string()],
+        };
+        let cxx = LibUsage {
+            name: "arm64-v8a/libc++_shared.so".to_string(),
+            ..Default::default()
+        };
+        let libs = [game, cxx];
+
+        // Any of the location permissions will do
+        let declared = [
+            permission("android.permission.CAMERA"),
+            permission("android.permission.ACCESS_COARSE_LOCATION"),
+        ];
+        assert!(audit(&libs, &declared).is_empty());
+
+        let declared = [
+            permission("android.permission.INTERNET"),
+            permission("android.permission.ACCESS_FINE_LOCATION"),
+            // Not known to the audit
+            permission("android.permission.FOREGROUND_SERVICE"),
+        ];
+        assert_eq!(
+            audit(&libs, &declared),
+            [
+                "`arm64-v8a/libgame.so` seems to use the camera as it imports \
+                `ACameraManager_create`, but `uses_permission` doesn't request \
+                `android.permission.CAMERA`",
+                "`android.permission.INTERNET` is requested, but no library seems to use \
+                the network. Remove it from `uses_permission` unless Java code or a library \
+                loaded at runtime does",
+            ]
+        );
+
+        assert_eq!(
+            audit(&libs, &[])[1],
+            "`arm64-v8a/libgame.so` seems to use the location as it contains \
+            `requestLocationUpdates`, but `uses_permission` doesn't request \
+            `android.permission.ACCESS_FINE_LOCATION` or \
+            `android.permission.ACCESS_COARSE_LOCATION`"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! `cargo apk env`: lists the environment variables that influence a build.
+
+use crate::error::Error;
+use crate::manifest::Manifest;
+use cargo_subcommand::Subcommand;
+use ndk_build::ndk::{Ndk, NdkOptions};
+use std::collections::BTreeMap;
+
+/// Prints every variable of [`ndk_build::env::ALL`] with its value, passwords redacted,
+/// followed by the SDK, NDK and build-tools that they resolve to.
+///
+/// Values include the `[env]` tables of the cargo configs. When invoked inside a
+/// package, `cmd` provides its `sdk_path`, `ndk_path`, `ndk_version` and
+/// `build_tools_version` metadata for resolving the paths, and the header variables of
+/// its `[package.metadata.android.publish]` backends, which are always redacted.
+pub fn print_env(cmd: Option<&Subcommand>) -> Result<(), Error> {
+    let config_env = crate::config::cargo_home_env()?;
+    let manifest = cmd
+        .map(|cmd| Manifest::parse_from_toml(cmd.manifest()))
+        .transpose()?;
+
+    println!("Environment variables:");
+    for (value, description) in variables(manifest.as_ref(), &config_env) {
+        println!("    {}", value);
+        println!("        {}", description);
+    }
+
+    println!();
+    println!("Resolved paths:");
+    let options = match &manifest {
+        Some(manifest) => NdkOptions {
+            sdk_path: manifest.sdk_path.clone(),
+            ndk_path: manifest.ndk_path.clone(),
+            ndk_version: manifest.ndk_version.clone(),
+            build_tools_version: manifest.build_tools_version.clone(),
+            env: config_env,
+            ..Default::default()
+        },
+        None => NdkOptions {
+            env: config_env,
+            ..Default::default()
+        },
+    };
+    match Ndk::from_env_with(&options) {
+        Ok(ndk) => {
+            match ndk.sdk() {
+                Some(sdk) => println!("    SDK:         {}", sdk.display()),
+                None => println!("    SDK:         not found"),
+            }
+            println!(
+                "    NDK:         {} ({})",
+                ndk.ndk().display(),
+                ndk.ndk_version()
+            );
+            if let Some(sdk) = ndk.sdk() {
+                let build_tools = sdk.join("build-tools").join(ndk.build_tools_version());
+                println!("    build-tools: {}", build_tools.display());
+            }
+        }
+        Err(e) => println!("    {}", e),
+    }
+    Ok(())
+}
+
+/// Every variable of [`ndk_build::env::ALL`], including those of `config_env`, and the
+/// publisher headers of `manifest`, redacted, with its description.
+pub(crate) fn variables(
+    manifest: Option<&Manifest>,
+    config_env: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut variables = ndk_build::env::current_with(config_env)
+        .into_iter()
+        .map(|value| (value.to_string(), value.var.description.to_string()))
+        .collect::<Vec<_>>();
+    // Header values are credentials, so they are never shown
+    let publishers = manifest.iter().flat_map(|manifest| &manifest.publish);
+    for (name, publisher) in publishers {
+        for var in publisher.env_vars() {
+            let value = if config_env.contains_key(var) || ndk_build::env::is_set(var) {
+                "=***"
+            } else {
+                " (not set)"
+            };
+            variables.push((
+                format!("{}{}", var, value),
+                format!("Header of the `{}` publisher", name),
+            ));
+        }
+    }
+    variables
+}
@@ -0,0 +1,287 @@
+//! `cargo apk print-manifest`: renders the `AndroidManifest.xml` of an artifact without
+//! building it.
+
+use crate::apk::package_version;
+use crate::build_info;
+use crate::error::Error;
+use crate::manifest::{Manifest, Root};
+use crate::resolve::{ManifestDefaults, ResolveContext, print_warnings, resolve_manifest};
+use cargo_subcommand::{Artifact, Profile, Subcommand};
+use ndk_build::env;
+use ndk_build::error::NdkError;
+use ndk_build::ndk::{Ndk, NdkOptions};
+use std::collections::BTreeMap;
+
+/// Prints the `AndroidManifest.xml` that [`crate::ApkBuilder::build()`] would package for
+/// `artifact` to stdout.
+///
+/// Neither `cargo` nor any SDK tool is invoked. The NDK is only consulted for the default
+/// `targetSdkVersion` and the supported API levels; without one, `targetSdkVersion`
+/// defaults to `30` and `minSdkVersion` is not checked.
+///
+/// The package id and label reflect `flavor`, if given.
+pub fn print_manifest(
+    cmd: &Subcommand,
+    artifact: &Artifact,
+    strict_sdk_check: bool,
+    strict_manifest: bool,
+    flavor: Option<&str>,
+) -> Result<(), Error> {
+    let mut manifest = Manifest::parse_from_toml(cmd.manifest())?;
+    manifest.check_unknown_keys(strict_manifest)?;
+    if let Some(flavor) = flavor {
+        manifest.apply_flavor(flavor)?;
+    }
+    let ndk = offline_ndk(&manifest, crate::config::cargo_home_env()?);
+    if let Err(e) = &ndk {
+        eprintln!("Warning: {}\n{}", e, NO_NDK_DEFAULTS);
+    }
+    let defaults = offline_defaults(cmd, &manifest, ndk.ok().as_ref(), strict_sdk_check)?;
+    let mut warnings = vec![];
+    let ctx = ResolveContext {
+        artifact,
+        defaults: &defaults,
+        warnings: &mut warnings,
+    };
+    let manifest = resolve_manifest(manifest, ctx)?;
+    print_warnings(warnings);
+    println!("{}", manifest.to_xml()?);
+    Ok(())
+}
+
+/// What [`offline_defaults()`] uses in place of an NDK that isn't found.
+pub(crate) const NO_NDK_DEFAULTS: &str = "Using targetSdkVersion 30 and not checking minSdkVersion";
+
+/// The NDK selected by `manifest` and `env`, see [`NdkOptions::env`], without installing
+/// anything.
+pub(crate) fn offline_ndk(
+    manifest: &Manifest,
+    env: BTreeMap<String, String>,
+) -> Result<Ndk, NdkError> {
+    Ndk::from_env_with(&NdkOptions {
+        sdk_path: manifest.sdk_path.clone(),
+        ndk_path: manifest.ndk_path.clone(),
+        ndk_version: manifest.ndk_version.clone(),
+        build_tools_version: manifest.build_tools_version.clone(),
+        target_sdk_version: manifest.android_manifest.sdk.target_sdk_version,
+        env,
+        ..Default::default()
+    })
+}
+
+/// The [`ManifestDefaults`] of a build of `cmd` that doesn't run, taking the default
+/// `targetSdkVersion` and the supported API levels from `ndk` if there is one.
+pub(crate) fn offline_defaults(
+    cmd: &Subcommand,
+    manifest: &Manifest,
+    ndk: Option<&Ndk>,
+    strict_sdk_check: bool,
+) -> Result<ManifestDefaults, Error> {
+    let workspace_manifest: Option<Root> = cmd
+        .workspace_manifest()
+        .map(Root::parse_from_toml)
+        .transpose()?;
+    let (target_sdk_version, supported_api_levels, ndk_version) = match ndk {
+        Some(ndk) => (
+            ndk.default_target_platform(),
+            ndk.supported_api_levels(),
+            Some(ndk.ndk_version().to_string()),
+        ),
+        None => (30, 0..=u32::MAX, None),
+    };
+    Ok(ManifestDefaults {
+        package_version: package_version(cmd, manifest, workspace_manifest.as_ref())?,
+        timestamp: build_info::timestamp(env::SOURCE_DATE_EPOCH.get().ok())?,
+        is_debug_profile: *cmd.profile() == Profile::Dev,
+        target_sdk_version,
+        supported_api_levels,
+        ndk_version,
+        strict_sdk_check,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_subcommand::ArtifactType;
+    use ndk_build::manifest::AndroidManifest;
+    use std::path::{Path, PathBuf};
+
+    /// Renders `testdata/print_manifest/<name>.toml`, for comparing against `<name>.xml`.
+    fn render(name: &str, artifact: Artifact, defaults: &ManifestDefaults) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/print_manifest")
+            .join(format!("{}.toml", name));
+        let manifest = Manifest::parse_from_toml(&path).unwrap();
+        let manifest = resolve(manifest, &artifact, defaults).unwrap();
+        // Golden files have one element per line to keep their diffs readable
+        manifest.to_xml().unwrap().replace("><", ">\n<") + "\n"
+    }
+
+    fn resolve(
+        manifest: Manifest,
+        artifact: &Artifact,
+        defaults: &ManifestDefaults,
+    ) -> Result<AndroidManifest, Error> {
+        let ctx = ResolveContext {
+            artifact,
+            defaults,
+            warnings: &mut vec![],
+        };
+        resolve_manifest(manifest, ctx)
+    }
+
+    fn defaults(is_debug_profile: bool) -> ManifestDefaults {
+        ManifestDefaults {
+            package_version: "1.2.3".to_string(),
+            timestamp: 0,
+            is_debug_profile,
+            target_sdk_version: 30,
+            supported_api_levels: 24..=34,
+            ndk_version: None,
+            strict_sdk_check: false,
+        }
+    }
+
+    fn artifact(name: &str, r#type: ArtifactType) -> Artifact {
+        Artifact {
+            name: name.to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type,
+        }
+    }
+
+    #[test]
+    fn minimal() {
+        let xml = render(
+            "minimal",
+            artifact("my-app", ArtifactType::Lib),
+            &defaults(true),
+        );
+        assert_eq!(xml, include_str!("../testdata/print_manifest/minimal.xml"));
+    }
+
+    #[test]
+    fn derived_version_code() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/print_manifest/minimal.toml");
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+        manifest.android_manifest.version_code = Some(3);
+        let artifact = artifact("my-app", ArtifactType::Lib);
+        assert!(matches!(
+            resolve(manifest, &artifact, &defaults(true)),
+            Err(Error::DerivedManifestField("version_code"))
+        ));
+    }
+
+    #[test]
+    fn flavor() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/print_manifest/flavor.toml");
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+        manifest.apply_flavor("demo").unwrap();
+        let artifact = artifact("my-app", ArtifactType::Lib);
+        let manifest = resolve(manifest, &artifact, &defaults(true)).unwrap();
+        let xml = manifest.to_xml().unwrap().replace("><", ">\n<") + "\n";
+        assert_eq!(xml, include_str!("../testdata/print_manifest/flavor.xml"));
+    }
+
+    #[test]
+    fn unknown_target_activity() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/print_manifest/full.toml");
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+        manifest.android_manifest.application.activity_alias[0].target_activity =
+            ".MainActivity".to_string();
+        let artifact = artifact("hello-world", ArtifactType::Example);
+        assert!(matches!(
+            resolve(manifest, &artifact, &defaults(false)),
+            Err(Error::UnknownTargetActivity { target, .. }) if target == ".MainActivity"
+        ));
+    }
+
+    #[test]
+    fn invalid_protection_level() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/print_manifest/full.toml");
+        let with_level = |level: &str| {
+            let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+            manifest.android_manifest.permission[0].protection_level = Some(level.to_string());
+            manifest
+        };
+        let artifact = artifact("hello-world", ArtifactType::Example);
+        let manifest = with_level("signature|privileged");
+        // Requesting the declared permission is not flagged either
+        assert!(
+            manifest.unknown_keys.is_empty(),
+            "{:?}",
+            manifest.unknown_keys
+        );
+        assert!(resolve(manifest, &artifact, &defaults(false)).is_ok());
+        for level in ["signatures", "normal|signature", "signature|"] {
+            assert!(matches!(
+                resolve(with_level(level), &artifact, &defaults(false)),
+                Err(Error::InvalidProtectionLevel { level: l, .. }) if l == level
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_display_cutout_mode() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/print_manifest/full.toml");
+        let mut manifest = Manifest::parse_from_toml(&path).unwrap();
+        manifest
+            .android_manifest
+            .application
+            .activity
+            .window_layout_in_display_cutout_mode = Some("short_edges".to_string());
+        let artifact = artifact("hello-world", ArtifactType::Example);
+        assert!(matches!(
+            resolve(manifest, &artifact, &defaults(false)),
+            Err(Error::InvalidDisplayCutoutMode(mode)) if mode == "short_edges"
+        ));
+    }
+
+    #[test]
+    fn no_launcher() {
+        let xml = render(
+            "no_launcher",
+            artifact("my-ime", ArtifactType::Lib),
+            &ManifestDefaults {
+                target_sdk_version: 34,
+                ..defaults(false)
+            },
+        );
+        assert!(!xml.contains("intent-filter"), "{}", xml);
+        assert_eq!(
+            xml,
+            include_str!("../testdata/print_manifest/no_launcher.xml")
+        );
+    }
+
+    #[test]
+    fn form_factors() {
+        let render = |name| render(name, artifact("my-app", ArtifactType::Lib), &defaults(true));
+        // Phones need no additions
+        assert_eq!(
+            render("phone"),
+            include_str!("../testdata/print_manifest/minimal.xml")
+        );
+        assert_eq!(
+            render("tv"),
+            include_str!("../testdata/print_manifest/tv.xml")
+        );
+        assert_eq!(
+            render("watch"),
+            include_str!("../testdata/print_manifest/watch.xml")
+        );
+    }
+
+    #[test]
+    fn full() {
+        let xml = render(
+            "full",
+            artifact("hello-world", ArtifactType::Example),
+            &defaults(false),
+        );
+        assert_eq!(xml, include_str!("../testdata/print_manifest/full.xml"));
+    }
+}
@@ -0,0 +1,325 @@
+//! `[package.metadata.android.publish.<name>]`: backends that upload an apk for
+//! [`crate::ApkBuilder::publish()`].
+
+use crate::error::Error;
+use ndk_build::error::NdkError;
+use ndk_build::util::{TempPath, status, stream_error};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Options for [`crate::ApkBuilder::publish()`].
+#[derive(Clone, Debug, Default)]
+pub struct PublishOptions {
+    /// Name of the publisher, may be omitted if only one is configured
+    pub publisher: Option<String>,
+    pub notes: Option<ReleaseNotes>,
+}
+
+/// Release notes for the testers, passed to backends that support them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReleaseNotes {
+    Text(String),
+    File(PathBuf),
+}
+
+/// A configured upload target. Secrets are never part of the manifest, they are
+/// taken from the environment.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub(crate) enum Publisher {
+    /// Uploads the apk as the request body with `curl`
+    Http {
+        url: String,
+        #[serde(default)]
+        method: HttpMethod,
+        /// Maps header names to the environment variables holding their values
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    /// Distributes the apk to testers through `firebase appdistribution:distribute`,
+    /// which authenticates through `$FIREBASE_TOKEN` or `$GOOGLE_APPLICATION_CREDENTIALS`
+    Firebase {
+        /// Firebase app id, like `1:1234567890:android:0a1b2c3d4e5f67890`
+        app: String,
+        #[serde(default)]
+        groups: Vec<String>,
+        #[serde(default)]
+        testers: Vec<String>,
+    },
+}
+
+/// The Firebase CLI is installed through `npm`, which wraps it in a batch file on Windows.
+const FIREBASE: &str = if cfg!(windows) {
+    "firebase.cmd"
+} else {
+    "firebase"
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum HttpMethod {
+    #[default]
+    Put,
+    Post,
+}
+
+impl Publisher {
+    fn backend(&self) -> &'static str {
+        match self {
+            Self::Http { .. } => "http",
+            Self::Firebase { .. } => "firebase",
+        }
+    }
+
+    /// The environment variables referenced by the publisher.
+    pub(crate) fn env_vars(&self) -> Vec<&str> {
+        match self {
+            Self::Http { headers, .. } => headers.values().map(String::as_str).collect(),
+            Self::Firebase { .. } => Vec::new(),
+        }
+    }
+
+    /// The command uploading `apk`, with `env` looking up the values of headers. Headers are
+    /// written to `header_file` instead of the command line, where other users could see them.
+    fn command(
+        &self,
+        apk: &Path,
+        notes: Option<&ReleaseNotes>,
+        env: impl Fn(&str) -> Option<String>,
+        header_file: &Path,
+    ) -> Result<Command, Error> {
+        match self {
+            Self::Http {
+                url,
+                method,
+                headers,
+            } => {
+                if notes.is_some() {
+                    eprintln!("Warning: the `http` backend does not support release notes");
+                }
+                let mut curl = Command::new("curl");
+                curl.arg("--fail-with-body")
+                    .arg("--silent")
+                    .arg("--show-error");
+                match method {
+                    HttpMethod::Put => curl.arg("--upload-file").arg(apk),
+                    HttpMethod::Post => curl
+                        .arg("--request")
+                        .arg("POST")
+                        .arg("--data-binary")
+                        .arg(format!("@{}", apk.display())),
+                };
+                curl.arg("--header")
+                    .arg("Content-Type: application/vnd.android.package-archive");
+                if !headers.is_empty() {
+                    let mut lines = String::new();
+                    for (name, var) in headers {
+                        let value = env(var).ok_or_else(|| Error::PublishEnvMissing {
+                            header: name.clone(),
+                            var: var.clone(),
+                        })?;
+                        lines.push_str(&format!("{}: {}\n", name, value));
+                    }
+                    write_private(header_file, &lines)?;
+                    curl.arg("--header")
+                        .arg(format!("@{}", header_file.display()));
+                }
+                curl.arg(url);
+                Ok(curl)
+            }
+            Self::Firebase {
+                app,
+                groups,
+                testers,
+            } => {
+                let mut firebase = Command::new(FIREBASE);
+                firebase
+                    .arg("appdistribution:distribute")
+                    .arg(apk)
+                    .arg("--app")
+                    .arg(app);
+                if !groups.is_empty() {
+                    firebase.arg("--groups").arg(groups.join(","));
+                }
+                if !testers.is_empty() {
+                    firebase.arg("--testers").arg(testers.join(","));
+                }
+                match notes {
+                    Some(ReleaseNotes::Text(notes)) => {
+                        firebase.arg("--release-notes").arg(notes);
+                    }
+                    Some(ReleaseNotes::File(path)) => {
+                        firebase.arg("--release-notes-file").arg(path);
+                    }
+                    None => {}
+                }
+                Ok(firebase)
+            }
+        }
+    }
+
+    /// Uploads `apk`, showing the output of the backend.
+    pub(crate) fn publish(
+        &self,
+        name: &str,
+        apk: &Path,
+        notes: Option<&ReleaseNotes>,
+    ) -> Result<(), Error> {
+        let header_file = apk.with_extension("headers");
+        let _guard = TempPath::new(&header_file);
+        let result = self
+            .command(
+                apk,
+                notes,
+                |var| ndk_build::env::configured(var).ok(),
+                &header_file,
+            )
+            .and_then(|command| {
+                let program = command.get_program().to_string_lossy().into_owned();
+                status(
+                    "Publishing",
+                    format_args!("`{}` to `{}` ({})", apk.display(), name, self.backend()),
+                );
+                match stream_error(command, None) {
+                    Ok(_) => Ok(()),
+                    Err(NdkError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                        Err(NdkError::CmdNotFound(program).into())
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            });
+        let _ = std::fs::remove_file(&header_file);
+        result
+    }
+}
+
+/// Writes `contents` to a new file at `path` that only the current user can read.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Picks the publisher called `name`, or the only one if `name` is [`None`].
+pub(crate) fn select<'p>(
+    publishers: &'p BTreeMap<String, Publisher>,
+    name: Option<&str>,
+) -> Result<(&'p str, &'p Publisher), Error> {
+    let names = || publishers.keys().cloned().collect::<Vec<_>>();
+    match name {
+        Some(name) => publishers
+            .get_key_value(name)
+            .map(|(name, publisher)| (name.as_str(), publisher))
+            .ok_or_else(|| Error::UnknownPublisher(name.to_string(), names())),
+        None if publishers.len() == 1 => {
+            let (name, publisher) = publishers.iter().next().unwrap();
+            Ok((name, publisher))
+        }
+        None if publishers.is_empty() => Err(Error::NoPublisher),
+        None => Err(Error::AmbiguousPublisher(names())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use ndk_build::util::command_line;
+
+    fn publishers() -> BTreeMap<String, Publisher> {
+        toml::from_str(
+            r#"
+            [upload]
+            backend = "http"
+            url = "https://example.com/apks/app.apk"
+            headers = { Authorization = "UPLOAD_AUTHORIZATION" }
+
+            [testers]
+            backend = "firebase"
+            app = "1:1234567890:android:0a1b2c3d4e5f67890"
+            groups = ["qa", "beta"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        let dir = TestDir::new("publish-commands");
+        let publishers = publishers();
+        let apk = Path::new("app.apk");
+        let header_file = dir.join("app.headers");
+        let env = |var: &str| (var == "UPLOAD_AUTHORIZATION").then(|| "Bearer xyz".to_string());
+
+        let curl = publishers["upload"]
+            .command(apk, None, env, &header_file)
+            .unwrap();
+        assert_eq!(
+            command_line(&curl),
+            format!(
+                "curl --fail-with-body --silent --show-error --upload-file app.apk --header \
+                \"Content-Type: application/vnd.android.package-archive\" --header @{} \
+                https://example.com/apks/app.apk",
+                header_file.display()
+            )
+        );
+        assert!(
+            curl.get_args()
+                .all(|arg| !arg.to_string_lossy().contains("Bearer xyz"))
+        );
+        assert_eq!(
+            std::fs::read_to_string(&header_file).unwrap(),
+            "Authorization: Bearer xyz\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&header_file)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(matches!(
+            publishers["upload"].command(apk, None, |_| None, &header_file),
+            Err(Error::PublishEnvMissing { var, .. }) if var == "UPLOAD_AUTHORIZATION"
+        ));
+
+        let notes = ReleaseNotes::Text("Fixes".to_string());
+        let firebase = publishers["testers"]
+            .command(apk, Some(&notes), env, &header_file)
+            .unwrap();
+        assert_eq!(
+            command_line(&firebase),
+            format!(
+                "{} appdistribution:distribute app.apk --app \
+                1:1234567890:android:0a1b2c3d4e5f67890 --groups qa,beta --release-notes Fixes",
+                FIREBASE
+            )
+        );
+    }
+
+    #[test]
+    fn selection() {
+        let publishers = publishers();
+        assert_eq!(select(&publishers, Some("testers")).unwrap().0, "testers");
+        assert!(matches!(
+            select(&publishers, None),
+            Err(Error::AmbiguousPublisher(names)) if names == ["testers", "upload"]
+        ));
+        assert!(matches!(
+            select(&publishers, Some("nightly")),
+            Err(Error::UnknownPublisher(..))
+        ));
+        assert!(matches!(
+            select(&BTreeMap::new(), None),
+            Err(Error::NoPublisher)
+        ));
+    }
+}
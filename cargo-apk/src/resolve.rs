@@ -0,0 +1,1015 @@
+//! The defaults that turn the `[package.metadata.android]` of a package into the
+//! `AndroidManifest.xml` of one of its artifacts. [`resolve_manifest()`] neither touches
+//! the filesystem nor the NDK, whatever it needs from them comes in the
+//! [`ResolveContext`].
+
+use crate::error::Error;
+use crate::manifest::Manifest;
+use cargo_subcommand::{Artifact, ArtifactType};
+use ndk_build::manifest::{AndroidManifest, IntentFilter, MetaData, UsesPermission};
+use std::ops::RangeInclusive;
+
+/// The values from the `cargo` invocation and the NDK that [`resolve_manifest()`] fills
+/// in.
+#[derive(Clone, Debug)]
+pub(crate) struct ManifestDefaults {
+    /// The version of the package, also when inherited from the workspace
+    pub package_version: String,
+    /// Seconds since the Unix epoch that the `date` scheme of the `versionCode` uses,
+    /// `$SOURCE_DATE_EPOCH` if set
+    pub timestamp: u64,
+    pub is_debug_profile: bool,
+    /// Used when `targetSdkVersion` is not set
+    pub target_sdk_version: u32,
+    /// A `minSdkVersion` below this range is clamped, see [`check_sdk_versions()`]
+    pub supported_api_levels: RangeInclusive<u32>,
+    /// The version of the NDK that `supported_api_levels` come from
+    pub ndk_version: Option<String>,
+    pub strict_sdk_check: bool,
+}
+
+/// Everything besides the manifest that [`resolve_manifest()`] depends on.
+pub(crate) struct ResolveContext<'a> {
+    /// The artifact that the manifest is resolved for
+    pub artifact: &'a Artifact,
+    pub defaults: &'a ManifestDefaults,
+    /// Collects the warnings about values that are overridden or take no effect, for the
+    /// caller to report
+    pub warnings: &'a mut Vec<String>,
+}
+
+/// Returns the `AndroidManifest.xml` of `ctx.artifact`, with every value that
+/// `manifest` leaves out set to its default, and the values that it sets checked.
+pub(crate) fn resolve_manifest(
+    mut manifest: Manifest,
+    ctx: ResolveContext<'_>,
+) -> Result<AndroidManifest, Error> {
+    apply_defaults(&mut manifest, ctx.defaults, ctx.warnings)?;
+    Ok(artifact_manifest(&manifest, ctx.artifact))
+}
+
+/// The `minSdkVersion` and `targetSdkVersion` that [`resolve_manifest()`] sets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SdkVersions {
+    pub min: u32,
+    pub target: u32,
+}
+
+/// The `minSdkVersion` that `manifest` asks for, `23` by default.
+///
+/// Unless `allow_low_minsdk = true`, it has a lower bound of `23` to retain backwards
+/// compatibility with the previous default.
+pub(crate) fn requested_min_sdk_version(manifest: &Manifest) -> u32 {
+    let min_sdk_version = manifest.android_manifest.sdk.min_sdk_version.unwrap_or(23);
+    if manifest.allow_low_minsdk {
+        min_sdk_version
+    } else {
+        min_sdk_version.max(23)
+    }
+}
+
+/// Resolves the SDK versions of `manifest`, see [`check_sdk_versions()`].
+///
+/// With `allow_low_minsdk = true`, a `minSdkVersion` below 23 is warned about and one
+/// below the NDK's range fails instead of being clamped.
+pub(crate) fn sdk_versions(
+    manifest: &Manifest,
+    defaults: &ManifestDefaults,
+    warnings: &mut Vec<String>,
+) -> Result<SdkVersions, Error> {
+    let sdk = &manifest.android_manifest.sdk;
+    let target = sdk
+        .target_sdk_version
+        .unwrap_or(defaults.target_sdk_version);
+    let requested = requested_min_sdk_version(manifest);
+    if requested < 23 {
+        let lowest = *defaults.supported_api_levels.start();
+        if requested < lowest {
+            return Err(Error::LowMinSdkVersionUnsupported {
+                min_sdk_version: requested,
+                lowest,
+                ndk_version: defaults.ndk_version.clone().unwrap_or_default(),
+            });
+        }
+        warnings.push(format!(
+            "minSdkVersion {} is below 23: libc functions and NDK APIs introduced after API \
+            level {} are missing on the oldest devices, where libraries using them fail to \
+            load with `cannot locate symbol`",
+            requested, requested
+        ));
+        if manifest.android_manifest.application.extract_native_libs == Some(false) {
+            warnings.push(
+                "`extract_native_libs = false` requires API level 23, older devices extract \
+                the libraries on install and store them twice"
+                    .to_string(),
+            );
+        }
+    }
+    let min = check_sdk_versions(
+        requested,
+        target,
+        defaults.supported_api_levels.clone(),
+        defaults.strict_sdk_check,
+        warnings,
+    )?;
+    Ok(SdkVersions { min, target })
+}
+
+/// Sets the default Android manifest values that don't depend on the artifact, see
+/// [`artifact_manifest()`] for those that do.
+fn apply_defaults(
+    manifest: &mut Manifest,
+    defaults: &ManifestDefaults,
+    warnings: &mut Vec<String>,
+) -> Result<(), Error> {
+    let version_code = manifest
+        .version_code
+        .code(&defaults.package_version, defaults.timestamp)?;
+
+    if manifest
+        .android_manifest
+        .version_name
+        .replace(defaults.package_version.clone())
+        .is_some()
+    {
+        return Err(Error::DerivedManifestField("version_name"));
+    }
+
+    if manifest
+        .android_manifest
+        .version_code
+        .replace(version_code)
+        .is_some()
+    {
+        return Err(Error::DerivedManifestField("version_code"));
+    }
+
+    let sdk_versions = sdk_versions(manifest, defaults, warnings)?;
+    let target_sdk_version = sdk_versions.target;
+    let requested_min = requested_min_sdk_version(manifest);
+    let sdk = &mut manifest.android_manifest.sdk;
+    sdk.target_sdk_version = Some(target_sdk_version);
+    if sdk_versions.min != requested_min {
+        sdk.min_sdk_version = Some(sdk_versions.min);
+    }
+    if let Some(max_sdk_version) = sdk.max_sdk_version {
+        warnings.push(format!(
+            "maxSdkVersion {} is not enforced by Android 2.0.1 and up, which install and \
+            keep the app on newer versions, it only hides the app from them on Google Play",
+            max_sdk_version
+        ));
+    }
+
+    let activity = &mut manifest.android_manifest.application.activity;
+    for when in std::mem::take(&mut activity.when) {
+        if target_sdk_matches(&when.target_sdk, target_sdk_version)? {
+            when.apply(activity);
+        }
+    }
+
+    manifest
+        .android_manifest
+        .application
+        .debuggable
+        .get_or_insert(defaults.is_debug_profile);
+
+    if let Some(sanitizer) = manifest.sanitizer {
+        // `wrap.sh` is only run for debuggable apps, from the extracted libraries
+        let application = &mut manifest.android_manifest.application;
+        if application.debuggable == Some(false) || application.extract_native_libs == Some(false) {
+            warnings.push(format!(
+                "`sanitizer = \"{}\"` forces `debuggable` and `extract_native_libs` to `true`",
+                sanitizer.name()
+            ));
+        }
+        application.debuggable = Some(true);
+        application.extract_native_libs = Some(true);
+    }
+
+    if manifest.vulkan_validation && !defaults.is_debug_profile {
+        warnings.push(
+            "`vulkan_validation` packages the validation layer into a release build, \
+            where it slows down rendering and is only loaded if the app is `debuggable`"
+                .to_string(),
+        );
+    }
+
+    if manifest.legacy_storage {
+        expand_legacy_storage(&mut manifest.android_manifest);
+    }
+
+    let activity = &mut manifest.android_manifest.application.activity;
+
+    // Add a default `MAIN` action to launch the activity, if the user didn't supply it by hand.
+    if !activity.no_default_intent_filter
+        && activity
+            .intent_filter
+            .iter()
+            .all(|i| i.actions.iter().all(|f| f != "android.intent.action.MAIN"))
+    {
+        activity.intent_filter.push(IntentFilter {
+            actions: vec!["android.intent.action.MAIN".to_string()],
+            categories: vec!["android.intent.category.LAUNCHER".to_string()],
+            data: vec![],
+        });
+    }
+
+    manifest.form_factor.apply(&mut manifest.android_manifest);
+    let activity = &mut manifest.android_manifest.application.activity;
+
+    // Export the sole Rust activity on Android S and up, if the user didn't explicitly do so.
+    // Without this, apps with intent filters won't install on S+.
+    // https://developer.android.com/about/versions/12/behavior-changes-12#exported
+    if target_sdk_version >= 31 && !activity.intent_filter.is_empty() {
+        if activity.exported == Some(false)
+            && activity.intent_filter.iter().any(IntentFilter::is_launcher)
+        {
+            warnings.push(
+                "`exported = false` keeps the launcher from starting the activity on Android 12 and up"
+                    .to_string(),
+            );
+        }
+        activity.exported.get_or_insert(true);
+    }
+
+    if let Some(permission) = manifest
+        .android_manifest
+        .permission
+        .iter()
+        .find(|permission| !permission.has_valid_protection_level())
+    {
+        return Err(Error::InvalidProtectionLevel {
+            permission: permission.name.clone(),
+            level: permission.protection_level.clone().unwrap_or_default(),
+        });
+    }
+
+    let activity = &manifest.android_manifest.application.activity;
+    if !activity.has_valid_display_cutout_mode() {
+        return Err(Error::InvalidDisplayCutoutMode(
+            activity
+                .window_layout_in_display_cutout_mode
+                .clone()
+                .unwrap_or_default(),
+        ));
+    }
+
+    // Predictive back stays off on Android 15 unless the app opts in
+    if target_sdk_version >= 35
+        && manifest
+            .android_manifest
+            .application
+            .enable_on_back_invoked_callback
+            .is_none()
+    {
+        warnings.push(format!(
+            "targetSdkVersion {} without `enable_on_back_invoked_callback` keeps the \
+            app on legacy back handling, set it under `[package.metadata.android.application]` \
+            to opt into predictive back",
+            target_sdk_version
+        ));
+    }
+
+    // Aliases in the launcher need the same, and can only launch the Rust activity
+    let android_manifest = &mut manifest.android_manifest;
+    let package = &android_manifest.package;
+    let activity = qualified_name(package, &android_manifest.application.activity.name);
+    for alias in &mut android_manifest.application.activity_alias {
+        if qualified_name(package, &alias.target_activity) != activity {
+            return Err(Error::UnknownTargetActivity {
+                alias: alias.name.clone(),
+                target: alias.target_activity.clone(),
+                activity,
+            });
+        }
+        if target_sdk_version >= 31 && alias.intent_filter.iter().any(IntentFilter::is_launcher) {
+            alias.exported.get_or_insert(true);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `target_sdk_version` satisfies every comparison of the `condition` of an
+/// `application.activity.when` table, like `">= 24, < 34"`.
+fn target_sdk_matches(condition: &str, target_sdk_version: u32) -> Result<bool, Error> {
+    let invalid = || Error::InvalidSdkCondition(condition.to_string());
+    let mut matches = true;
+    for comparison in condition.split(',').map(str::trim) {
+        // Two-character operators first, `>` is a prefix of `>=`
+        let (op, level) = [">=", "<=", "==", ">", "<"]
+            .into_iter()
+            .find_map(|op| Some((op, comparison.strip_prefix(op)?)))
+            .ok_or_else(invalid)?;
+        let level = level.trim().parse::<u32>().map_err(|_| invalid())?;
+        matches &= match op {
+            ">=" => target_sdk_version >= level,
+            "<=" => target_sdk_version <= level,
+            "==" => target_sdk_version == level,
+            ">" => target_sdk_version > level,
+            _ => target_sdk_version < level,
+        };
+    }
+    Ok(matches)
+}
+
+/// Applies `legacy_storage = true`, keeping attributes and permissions that are set
+/// explicitly.
+fn expand_legacy_storage(manifest: &mut AndroidManifest) {
+    let application = &mut manifest.application;
+    application
+        .request_legacy_external_storage
+        .get_or_insert(true);
+    application
+        .preserve_legacy_external_storage
+        .get_or_insert(true);
+    for (name, max_sdk_version) in [
+        ("android.permission.READ_EXTERNAL_STORAGE", None),
+        ("android.permission.WRITE_EXTERNAL_STORAGE", Some(32)),
+    ] {
+        if !manifest.uses_permission.iter().any(|p| p.name == name) {
+            manifest.uses_permission.push(UsesPermission {
+                name: name.to_string(),
+                max_sdk_version,
+            });
+        }
+    }
+}
+
+/// Resolves a component name starting with `.` against `package`, like Android does.
+fn qualified_name(package: &str, name: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", package, name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Returns `manifest` with the default values specific to `artifact` set.
+pub(crate) fn artifact_manifest(manifest: &Manifest, artifact: &Artifact) -> AndroidManifest {
+    let suffix = manifest
+        .flavor
+        .as_ref()
+        .and_then(|flavor| flavor.package_suffix.as_deref());
+    let targets = &manifest.target;
+    let mut manifest = manifest.android_manifest.clone();
+
+    if manifest.package.is_empty() {
+        let name = artifact.name.replace('-', "_");
+        manifest.package = match artifact.r#type {
+            ArtifactType::Lib => format!("rust.{}", name),
+            ArtifactType::Bin => format!("rust.{}", name),
+            ArtifactType::Example => format!("rust.example.{}", name),
+        };
+    }
+
+    if let Some(suffix) = suffix {
+        manifest.package.push_str(suffix);
+    }
+
+    if manifest.application.label.is_empty() {
+        manifest.application.label = artifact.name.to_string();
+    }
+
+    let meta_data = &mut manifest.application.activity.meta_data;
+    meta_data.push(MetaData {
+        name: "android.app.lib_name".to_string(),
+        value: artifact.name.replace('-', "_"),
+        ..Default::default()
+    });
+    // `NativeActivity` only reads the above, these are for activities that pick the
+    // library by ABI
+    let mut overrides = targets
+        .iter()
+        .filter_map(|(target, t)| Some((target.android_abi(), t.lib_name.as_ref()?)))
+        .collect::<Vec<_>>();
+    overrides.sort();
+    for (abi, lib_name) in overrides {
+        meta_data.push(MetaData {
+            name: format!("android.app.lib_name_{}", abi),
+            value: lib_name.clone(),
+            ..Default::default()
+        });
+    }
+    manifest
+}
+
+/// Prints the warnings collected through [`ResolveContext::warnings`], once each.
+pub(crate) fn print_warnings(mut warnings: Vec<String>) {
+    let mut seen = std::collections::HashSet::new();
+    warnings.retain(|warning| seen.insert(warning.clone()));
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// Checks that `min_sdk_version` is supported by the NDK, clamping it up to the lowest
+/// supported API level unless `strict`, and that `target_sdk_version` is not lower.
+fn check_sdk_versions(
+    min_sdk_version: u32,
+    target_sdk_version: u32,
+    supported: RangeInclusive<u32>,
+    strict: bool,
+    warnings: &mut Vec<String>,
+) -> Result<u32, Error> {
+    let unsupported = || Error::MinSdkVersionUnsupported {
+        min_sdk_version,
+        supported: supported.clone(),
+    };
+    let min_sdk_version = if min_sdk_version > *supported.end() {
+        return Err(unsupported());
+    } else if min_sdk_version < *supported.start() {
+        if strict {
+            return Err(unsupported());
+        }
+        warnings.push(format!(
+            "minSdkVersion {} is not supported by the NDK, which supports API \
+            levels {} to {}. Using minSdkVersion {} instead, pass `--strict-sdk-check` to \
+            turn this into an error",
+            min_sdk_version,
+            supported.start(),
+            supported.end(),
+            supported.start()
+        ));
+        *supported.start()
+    } else {
+        min_sdk_version
+    };
+
+    if target_sdk_version < min_sdk_version {
+        return Err(Error::TargetSdkVersionTooLow {
+            target_sdk_version,
+            min_sdk_version,
+        });
+    }
+    Ok(min_sdk_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flavor::Flavor;
+    use crate::version_code::VersionCodeScheme;
+    use ndk_build::cargo::VersionCode;
+    use ndk_build::manifest::{ActivityWhen, IntentFilterData};
+    use ndk_build::target::Target;
+    use std::path::{Path, PathBuf};
+
+    fn check(
+        min_sdk_version: u32,
+        target_sdk_version: u32,
+        supported: RangeInclusive<u32>,
+        strict: bool,
+    ) -> Result<u32, Error> {
+        check_sdk_versions(
+            min_sdk_version,
+            target_sdk_version,
+            supported,
+            strict,
+            &mut vec![],
+        )
+    }
+
+    #[test]
+    fn sdk_versions() {
+        assert_eq!(check(23, 30, 21..=34, false).unwrap(), 23);
+        assert!(matches!(
+            check(35, 35, 21..=34, false),
+            Err(Error::MinSdkVersionUnsupported {
+                min_sdk_version: 35,
+                ..
+            })
+        ));
+        assert!(matches!(
+            check(24, 23, 21..=34, false),
+            Err(Error::TargetSdkVersionTooLow {
+                target_sdk_version: 23,
+                min_sdk_version: 24
+            })
+        ));
+    }
+
+    #[test]
+    fn sdk_versions_clamp() {
+        let mut warnings = vec![];
+        assert_eq!(
+            check_sdk_versions(23, 30, 24..=34, false, &mut warnings).unwrap(),
+            24
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("minSdkVersion 23 is not supported"));
+        // The clamped value is checked against the target
+        assert!(matches!(
+            check(23, 23, 24..=34, false),
+            Err(Error::TargetSdkVersionTooLow { .. })
+        ));
+    }
+
+    #[test]
+    fn sdk_versions_strict() {
+        assert!(matches!(
+            check(23, 30, 24..=34, true),
+            Err(Error::MinSdkVersionUnsupported {
+                min_sdk_version: 23,
+                ..
+            })
+        ));
+        assert_eq!(check(24, 30, 24..=34, true).unwrap(), 24);
+    }
+
+    #[test]
+    fn sdk_conditions() {
+        assert!(target_sdk_matches(">= 24", 24).unwrap());
+        assert!(!target_sdk_matches(">24", 24).unwrap());
+        assert!(target_sdk_matches(" >= 24 , < 34 ", 33).unwrap());
+        assert!(!target_sdk_matches(">= 24, < 34", 34).unwrap());
+        assert!(target_sdk_matches("== 30", 30).unwrap());
+        for invalid in ["", "24", "> = 24", ">= 24,", "=> 24", ">= android-24"] {
+            assert!(
+                matches!(
+                    target_sdk_matches(invalid, 30),
+                    Err(Error::InvalidSdkCondition(condition)) if condition == invalid
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn max_sdk_version_warning() {
+        let lib = artifact("my-game", ArtifactType::Lib);
+        let mut manifest = base();
+        manifest.android_manifest.sdk.max_sdk_version = Some(33);
+        let (resolved, warnings) = resolve(manifest, &lib, &defaults());
+        assert_eq!(resolved.unwrap().sdk.max_sdk_version, Some(33));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("maxSdkVersion 33 is not enforced"));
+    }
+
+    #[test]
+    fn low_min_sdk() {
+        let with_min = |min_sdk_version, allow_low_minsdk| {
+            let mut manifest = base();
+            manifest.android_manifest.sdk.min_sdk_version = Some(min_sdk_version);
+            manifest.allow_low_minsdk = allow_low_minsdk;
+            let mut warnings = vec![];
+            let min = super::sdk_versions(&manifest, &defaults(), &mut warnings).map(|sdk| sdk.min);
+            (min, warnings)
+        };
+        // Clamped without a warning, like before `allow_low_minsdk`
+        let (min, warnings) = with_min(21, false);
+        assert_eq!(min.unwrap(), 23);
+        assert!(warnings.is_empty());
+
+        let (min, warnings) = with_min(21, true);
+        assert_eq!(min.unwrap(), 21);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("minSdkVersion 21 is below 23"));
+        assert!(matches!(
+            with_min(19, true).0,
+            Err(Error::LowMinSdkVersionUnsupported {
+                min_sdk_version: 19,
+                lowest: 21,
+                ndk_version,
+            }) if ndk_version == "26.1.10909125"
+        ));
+
+        let mut manifest = base();
+        manifest.allow_low_minsdk = true;
+        manifest.android_manifest.sdk.min_sdk_version = Some(22);
+        manifest.android_manifest.application.extract_native_libs = Some(false);
+        let (resolved, warnings) = resolve(
+            manifest,
+            &artifact("my-game", ArtifactType::Lib),
+            &defaults(),
+        );
+        assert_eq!(resolved.unwrap().sdk.min_sdk_version, Some(22));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].starts_with("`extract_native_libs = false` requires API level 23"));
+    }
+
+    #[test]
+    fn legacy_storage() {
+        let mut manifest = AndroidManifest::default();
+        manifest.uses_permission.push(UsesPermission {
+            name: "android.permission.WRITE_EXTERNAL_STORAGE".to_string(),
+            max_sdk_version: Some(28),
+        });
+        manifest.application.preserve_legacy_external_storage = Some(false);
+        expand_legacy_storage(&mut manifest);
+        expand_legacy_storage(&mut manifest);
+
+        assert_eq!(
+            manifest.application.request_legacy_external_storage,
+            Some(true)
+        );
+        assert_eq!(
+            manifest.application.preserve_legacy_external_storage,
+            Some(false)
+        );
+        let permissions = manifest
+            .uses_permission
+            .iter()
+            .map(|p| (p.name.as_str(), p.max_sdk_version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            permissions,
+            [
+                ("android.permission.WRITE_EXTERNAL_STORAGE", Some(28)),
+                ("android.permission.READ_EXTERNAL_STORAGE", None),
+            ]
+        );
+    }
+
+    /// `testdata/resolve`, a package without any `[package.metadata.android]`.
+    fn base() -> Manifest {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/resolve/Cargo.toml");
+        Manifest::parse_from_toml(&path).unwrap()
+    }
+
+    fn artifact(name: &str, r#type: ArtifactType) -> Artifact {
+        Artifact {
+            name: name.to_string(),
+            path: PathBuf::from("src/lib.rs"),
+            r#type,
+        }
+    }
+
+    fn defaults() -> ManifestDefaults {
+        ManifestDefaults {
+            package_version: "1.2.3".to_string(),
+            // 2024-05-01T12:30:00Z
+            timestamp: 1714566600,
+            is_debug_profile: true,
+            target_sdk_version: 33,
+            supported_api_levels: 21..=34,
+            ndk_version: Some("26.1.10909125".to_string()),
+            strict_sdk_check: false,
+        }
+    }
+
+    /// Resolves `manifest` for `artifact`, returning the warnings along.
+    fn resolve(
+        manifest: Manifest,
+        artifact: &Artifact,
+        defaults: &ManifestDefaults,
+    ) -> (Result<AndroidManifest, Error>, Vec<String>) {
+        let mut warnings = vec![];
+        let resolved = resolve_manifest(
+            manifest,
+            ResolveContext {
+                artifact,
+                defaults,
+                warnings: &mut warnings,
+            },
+        );
+        (resolved, warnings)
+    }
+
+    /// What a rule is about, how the manifest is set up for it, the defaults it is
+    /// resolved with and the check of the result.
+    type Rule = (
+        &'static str,
+        fn(&mut Manifest),
+        ManifestDefaults,
+        fn(&AndroidManifest) -> bool,
+    );
+
+    fn view_filter() -> IntentFilter {
+        IntentFilter {
+            actions: vec!["android.intent.action.VIEW".to_string()],
+            categories: vec!["android.intent.category.BROWSABLE".to_string()],
+            data: vec![IntentFilterData {
+                scheme: Some("mygame".to_string()),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let lib = artifact("my-game", ArtifactType::Lib);
+        let release = ManifestDefaults {
+            is_debug_profile: false,
+            ..defaults()
+        };
+        let target = |target_sdk_version| ManifestDefaults {
+            target_sdk_version,
+            ..defaults()
+        };
+
+        let table: &[Rule] = &[
+            (
+                "versions come from the package",
+                |_| {},
+                defaults(),
+                |m| {
+                    m.version_name.as_deref() == Some("1.2.3")
+                        && m.version_code == VersionCode::new(1, 2, 3).to_code(1).ok()
+                },
+            ),
+            (
+                "the versionCode follows its scheme",
+                |m| {
+                    m.version_code.scheme = VersionCodeScheme::Date;
+                    m.version_code.offset = 1_000_000_000;
+                },
+                defaults(),
+                |m| {
+                    m.version_name.as_deref() == Some("1.2.3")
+                        && m.version_code == Some(1_024_050_100)
+                },
+            ),
+            (
+                "targetSdkVersion defaults to the NDK's",
+                |_| {},
+                defaults(),
+                |m| m.sdk.target_sdk_version == Some(33) && m.sdk.min_sdk_version == Some(23),
+            ),
+            (
+                "minSdkVersion is clamped to the NDK's range",
+                |m| m.android_manifest.sdk.min_sdk_version = Some(23),
+                ManifestDefaults {
+                    supported_api_levels: 24..=34,
+                    ..defaults()
+                },
+                |m| m.sdk.min_sdk_version == Some(24),
+            ),
+            (
+                "debug builds are debuggable",
+                |_| {},
+                defaults(),
+                |m| m.application.debuggable == Some(true),
+            ),
+            (
+                "release builds are not",
+                |_| {},
+                release.clone(),
+                |m| m.application.debuggable == Some(false),
+            ),
+            (
+                "an explicit debuggable is kept",
+                |m| m.android_manifest.application.debuggable = Some(true),
+                release.clone(),
+                |m| m.application.debuggable == Some(true),
+            ),
+            (
+                "MAIN is added",
+                |_| {},
+                defaults(),
+                |m| {
+                    let filters = &m.application.activity.intent_filter;
+                    filters.len() == 1 && filters[0].is_launcher()
+                },
+            ),
+            (
+                "MAIN is added next to a VIEW-only filter",
+                |m| {
+                    m.android_manifest
+                        .application
+                        .activity
+                        .intent_filter
+                        .push(view_filter())
+                },
+                defaults(),
+                |m| {
+                    let filters = &m.application.activity.intent_filter;
+                    filters.len() == 2 && !filters[0].is_launcher() && filters[1].is_launcher()
+                },
+            ),
+            (
+                "a filter with MAIN replaces the default",
+                |m| {
+                    m.android_manifest
+                        .application
+                        .activity
+                        .intent_filter
+                        .push(IntentFilter {
+                            actions: vec!["android.intent.action.MAIN".to_string()],
+                            categories: vec![],
+                            data: vec![],
+                        })
+                },
+                defaults(),
+                |m| {
+                    let filters = &m.application.activity.intent_filter;
+                    filters.len() == 1 && filters[0].categories.is_empty()
+                },
+            ),
+            (
+                "no_default_intent_filter leaves out MAIN",
+                |m| {
+                    m.android_manifest
+                        .application
+                        .activity
+                        .no_default_intent_filter = true
+                },
+                target(31),
+                |m| {
+                    let activity = &m.application.activity;
+                    activity.intent_filter.is_empty() && activity.exported.is_none()
+                },
+            ),
+            (
+                "the activity is exported from Android 12",
+                |_| {},
+                target(31),
+                |m| m.application.activity.exported == Some(true),
+            ),
+            (
+                "but not before",
+                |_| {},
+                target(30),
+                |m| m.application.activity.exported.is_none(),
+            ),
+            (
+                "an explicit exported is kept",
+                |m| m.android_manifest.application.activity.exported = Some(false),
+                target(31),
+                |m| m.application.activity.exported == Some(false),
+            ),
+            (
+                "a `when` table applies to its targetSdkVersions",
+                |m| {
+                    m.android_manifest
+                        .application
+                        .activity
+                        .when
+                        .push(ActivityWhen {
+                            target_sdk: ">= 24, < 34".to_string(),
+                            resizeable_activity: Some(false),
+                            ..Default::default()
+                        })
+                },
+                target(33),
+                |m| m.application.activity.resizeable_activity == Some(false),
+            ),
+            (
+                "but not to others",
+                |m| {
+                    let activity = &mut m.android_manifest.application.activity;
+                    activity.resizeable_activity = Some(true);
+                    activity.when.push(ActivityWhen {
+                        target_sdk: ">= 24, < 34".to_string(),
+                        resizeable_activity: Some(false),
+                        ..Default::default()
+                    })
+                },
+                target(34),
+                |m| m.application.activity.resizeable_activity == Some(true),
+            ),
+            (
+                "the library is named in the meta-data",
+                |_| {},
+                defaults(),
+                |m| {
+                    let meta_data = &m.application.activity.meta_data;
+                    meta_data.len() == 1
+                        && meta_data[0].name == "android.app.lib_name"
+                        && meta_data[0].value == "my_game"
+                },
+            ),
+            (
+                "per-target lib_name overrides are named by ABI",
+                |m| {
+                    for (target, lib_name) in
+                        [(Target::X86_64, "game_x64"), (Target::Arm64V8a, "game64")]
+                    {
+                        m.target.entry(target).or_default().lib_name = Some(lib_name.to_string());
+                    }
+                },
+                defaults(),
+                |m| {
+                    let meta_data = m
+                        .application
+                        .activity
+                        .meta_data
+                        .iter()
+                        .map(|m| (m.name.as_str(), m.value.as_str()))
+                        .collect::<Vec<_>>();
+                    meta_data
+                        == [
+                            ("android.app.lib_name", "my_game"),
+                            ("android.app.lib_name_arm64-v8a", "game64"),
+                            ("android.app.lib_name_x86_64", "game_x64"),
+                        ]
+                },
+            ),
+            (
+                "the package id falls back to the artifact",
+                |_| {},
+                defaults(),
+                |m| m.package == "rust.my_game",
+            ),
+            (
+                "an explicit package id is kept",
+                |m| m.android_manifest.package = "com.example.game".to_string(),
+                defaults(),
+                |m| m.package == "com.example.game",
+            ),
+            (
+                "the flavor suffixes the package id",
+                |m| {
+                    m.flavor = Some(Flavor {
+                        package_suffix: Some(".demo".to_string()),
+                        ..Default::default()
+                    })
+                },
+                defaults(),
+                |m| m.package == "rust.my_game.demo",
+            ),
+            (
+                "the label falls back to the artifact",
+                |_| {},
+                defaults(),
+                |m| m.application.label == "my-game",
+            ),
+            (
+                "an explicit label is kept",
+                |m| m.android_manifest.application.label = "@string/app_name".to_string(),
+                defaults(),
+                |m| m.application.label == "@string/app_name",
+            ),
+        ];
+        for (rule, setup, defaults, check) in table {
+            let mut manifest = base();
+            setup(&mut manifest);
+            let (resolved, _) = resolve(manifest, &lib, defaults);
+            let resolved = resolved.unwrap_or_else(|e| panic!("{}: {}", rule, e));
+            assert!(check(&resolved), "{}: {:#?}", rule, resolved);
+        }
+    }
+
+    #[test]
+    fn example_package() {
+        let example = artifact("shadow-mapping", ArtifactType::Example);
+        let (resolved, _) = resolve(base(), &example, &defaults());
+        let resolved = resolved.unwrap();
+        assert_eq!(resolved.package, "rust.example.shadow_mapping");
+        // The library keeps the name of the example
+        assert_eq!(
+            resolved.application.activity.meta_data[0].value,
+            "shadow_mapping"
+        );
+        let bin = artifact("tool", ArtifactType::Bin);
+        let (resolved, _) = resolve(base(), &bin, &defaults());
+        assert_eq!(resolved.unwrap().package, "rust.tool");
+    }
+
+    #[test]
+    fn derived_fields() {
+        let lib = artifact("my-game", ArtifactType::Lib);
+        let mut manifest = base();
+        manifest.android_manifest.version_code = Some(1);
+        assert!(matches!(
+            resolve(manifest, &lib, &defaults()).0,
+            Err(Error::DerivedManifestField("version_code"))
+        ));
+        let mut manifest = base();
+        manifest.android_manifest.version_name = Some("1.0".to_string());
+        assert!(matches!(
+            resolve(manifest, &lib, &defaults()).0,
+            Err(Error::DerivedManifestField("version_name"))
+        ));
+    }
+
+    #[test]
+    fn sanitizer_overrides() {
+        let lib = artifact("my-game", ArtifactType::Lib);
+        let mut manifest = base();
+        manifest.sanitizer = Some(ndk_build::sanitizer::Sanitizer::Address);
+        manifest.android_manifest.application.debuggable = Some(false);
+        let release = ManifestDefaults {
+            is_debug_profile: false,
+            ..defaults()
+        };
+        let (resolved, warnings) = resolve(manifest, &lib, &release);
+        let application = resolved.unwrap().application;
+        assert_eq!(application.debuggable, Some(true));
+        assert_eq!(application.extract_native_libs, Some(true));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("forces `debuggable`"));
+
+        // Without overriding anything there is nothing to warn about
+        let mut manifest = base();
+        manifest.sanitizer = Some(ndk_build::sanitizer::Sanitizer::Address);
+        let (_, warnings) = resolve(manifest, &lib, &defaults());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn launcher_warnings() {
+        let lib = artifact("my-game", ArtifactType::Lib);
+        let mut manifest = base();
+        manifest.android_manifest.application.activity.exported = Some(false);
+        let defaults = ManifestDefaults {
+            target_sdk_version: 35,
+            ..defaults()
+        };
+        let (resolved, warnings) = resolve(manifest, &lib, &defaults);
+        assert!(resolved.is_ok());
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("`exported = false`"));
+        assert!(warnings[1].starts_with("targetSdkVersion 35 without"));
+    }
+}
@@ -0,0 +1,158 @@
+//! `cargo apk run --save-config`: the device and flags of a run, stored in the target
+//! directory and applied to later runs of the same project.
+
+use crate::apk::CompileMode;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Schema of the file, bumped when a field changes its meaning. Files with a higher
+/// version were written by a newer cargo-apk and are ignored with a warning, fields
+/// this version doesn't know are ignored silently.
+const VERSION: u32 = 1;
+
+/// The flags of `cargo apk run` that are saved and loaded. Every field is optional so
+/// that files written by older versions still load.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RunConfig {
+    /// The [`VERSION`] of the schema, set by [`RunConfig::save()`]
+    pub version: u32,
+    pub device: Option<String>,
+    pub user: Option<u32>,
+    pub no_logcat: bool,
+    pub pull_after_run: Option<String>,
+    pub compile_mode: Option<CompileMode>,
+    pub force_install: bool,
+    pub fail_on_downgrade: bool,
+    pub no_diagnostics: bool,
+    pub diagnostics_threshold: Option<u64>,
+    pub start_emulator: Option<String>,
+    pub stop_emulator_after: bool,
+    pub watch_assets: bool,
+}
+
+impl RunConfig {
+    /// `<target-dir>/.cargo-apk/run-config.toml`, which is covered by the usual
+    /// `.gitignore` of the target directory.
+    pub fn path(target_dir: &Path) -> PathBuf {
+        target_dir.join(".cargo-apk").join("run-config.toml")
+    }
+
+    /// Reads the config saved at `path`, or [`None`] if there is none. Files that can't
+    /// be read or were written by a newer cargo-apk are skipped with a warning.
+    pub fn load(path: &Path) -> Option<Self> {
+        let toml = std::fs::read_to_string(path).ok()?;
+        match toml::from_str::<Self>(&toml) {
+            Ok(config) if config.version > VERSION => {
+                eprintln!(
+                    "Warning: ignoring `{}`, which was saved by a newer cargo-apk (version {}, \
+                    this one reads up to {})",
+                    path.display(),
+                    config.version,
+                    VERSION
+                );
+                None
+            }
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("Warning: ignoring `{}`: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let config = Self {
+            version: VERSION,
+            ..self.clone()
+        };
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, toml::to_string(&config).unwrap())?;
+        Ok(())
+    }
+
+    /// Applies the flags given on the command line over the saved ones. Switches can
+    /// only be turned on, pass `--no-config` to get rid of a saved one.
+    pub fn merge(self, cli: Self) -> Self {
+        Self {
+            version: self.version,
+            device: cli.device.or(self.device),
+            user: cli.user.or(self.user),
+            no_logcat: cli.no_logcat || self.no_logcat,
+            pull_after_run: cli.pull_after_run.or(self.pull_after_run),
+            compile_mode: cli.compile_mode.or(self.compile_mode),
+            force_install: cli.force_install || self.force_install,
+            fail_on_downgrade: cli.fail_on_downgrade || self.fail_on_downgrade,
+            no_diagnostics: cli.no_diagnostics || self.no_diagnostics,
+            diagnostics_threshold: cli.diagnostics_threshold.or(self.diagnostics_threshold),
+            start_emulator: cli.start_emulator.or(self.start_emulator),
+            stop_emulator_after: cli.stop_emulator_after || self.stop_emulator_after,
+            watch_assets: cli.watch_assets || self.watch_assets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn save_and_merge() {
+        let dir = TestDir::new("run-config");
+        let path = RunConfig::path(&dir);
+        assert_eq!(RunConfig::load(&path), None);
+
+        let saved = RunConfig {
+            device: Some("emulator-5554".to_string()),
+            compile_mode: Some(CompileMode::SpeedProfile),
+            no_logcat: true,
+            diagnostics_threshold: Some(5),
+            ..Default::default()
+        };
+        saved.save(&path).unwrap();
+        let loaded = RunConfig::load(&path).unwrap();
+        assert_eq!(
+            loaded,
+            RunConfig {
+                version: VERSION,
+                ..saved
+            }
+        );
+
+        let cli = RunConfig {
+            device: Some("R58M12ABCDE".to_string()),
+            user: Some(10),
+            ..Default::default()
+        };
+        let merged = loaded.merge(cli);
+        assert_eq!(merged.device.as_deref(), Some("R58M12ABCDE"));
+        assert_eq!(merged.user, Some(10));
+        assert_eq!(merged.compile_mode, Some(CompileMode::SpeedProfile));
+        assert!(merged.no_logcat);
+        assert_eq!(merged.diagnostics_threshold, Some(5));
+    }
+
+    #[test]
+    fn versions() {
+        let dir = TestDir::new("run-config-v");
+        let path = RunConfig::path(&dir);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        // Older files lack fields, newer ones may have more
+        std::fs::write(
+            &path,
+            "version = 1\ndevice = \"emulator-5554\"\nlog-tags = [\"Rust\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            RunConfig::load(&path).unwrap().device.as_deref(),
+            Some("emulator-5554")
+        );
+        std::fs::write(&path, "version = 2\ndevice = \"emulator-5554\"\n").unwrap();
+        assert_eq!(RunConfig::load(&path), None);
+        std::fs::write(&path, "device = 5554\n").unwrap();
+        assert_eq!(RunConfig::load(&path), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! `--sbom`: writes a [CycloneDX](https://cyclonedx.org/) bill of materials next to the
+//! apk, listing the crates compiled into it and the native libraries packaged in `lib/`.
+
+use crate::error::Error;
+use crate::licenses::{Metadata, Package};
+use ndk_build::apk::{LibOrigin, UnalignedApk};
+use ndk_build::checksums::sha256_file;
+use ndk_build::error::NdkError;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SbomFormat {
+    #[default]
+    CyclonedxJson,
+}
+
+/// A library in `lib/<abi>/` of the apk, hashed after stripping.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct NativeLib {
+    pub(crate) abi: String,
+    pub(crate) name: String,
+    pub(crate) source: PathBuf,
+    pub(crate) origin: LibOrigin,
+    pub(crate) sha256: String,
+}
+
+/// The libraries added to `apk` so far, hashing the copies that are packaged.
+pub(crate) fn native_libs(apk: &UnalignedApk<'_>) -> Result<Vec<NativeLib>, Error> {
+    let lib_dir = apk.config().build_dir.join("lib");
+    let mut libs = vec![];
+    for (entry, lib) in apk.packaged_libs() {
+        let (abi, name) = entry.split_once('/').unwrap();
+        libs.push(NativeLib {
+            abi: abi.to_string(),
+            name: name.to_string(),
+            source: lib.source.clone(),
+            origin: lib.origin,
+            sha256: sha256_file(&lib_dir.join(entry))?,
+        });
+    }
+    Ok(libs)
+}
+
+/// Writes the SBOM of `package` next to `apk`, as `<apk_name>.cdx.json`, and returns its
+/// path.
+pub(crate) fn write(
+    format: SbomFormat,
+    apk: &Path,
+    metadata: &Metadata,
+    package: &str,
+    manifest_path: &Path,
+    libs: &[NativeLib],
+) -> Result<PathBuf, Error> {
+    let (extension, contents) = match format {
+        SbomFormat::CyclonedxJson => (
+            "cdx.json",
+            cyclonedx_json(metadata, package, manifest_path, libs)?,
+        ),
+    };
+    let path = apk.with_extension(extension);
+    std::fs::write(&path, contents).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+    Ok(path)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: BomMetadata,
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Serialize)]
+struct BomMetadata {
+    tools: Tools,
+    component: Component,
+}
+
+#[derive(Debug, Serialize)]
+struct Tools {
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref", skip_serializing_if = "Option::is_none")]
+    bom_ref: Option<String>,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<License>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<Hash>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Serialize)]
+struct License {
+    /// The SPDX expression of `license` in `Cargo.toml`
+    expression: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Hash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Property {
+    name: &'static str,
+    value: String,
+}
+
+impl Component {
+    fn package(kind: &'static str, package: &Package) -> Self {
+        let purl = format!("pkg:cargo/{}@{}", package.name, package.version);
+        Self {
+            kind,
+            bom_ref: Some(purl.clone()),
+            name: package.name.clone(),
+            version: Some(package.version.clone()),
+            purl: Some(purl),
+            licenses: package
+                .license
+                .iter()
+                .map(|expression| License {
+                    expression: expression.clone(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn native_lib(lib: &NativeLib) -> Self {
+        let origin = serde_json::to_value(lib.origin).unwrap();
+        Self {
+            kind: "library",
+            bom_ref: Some(format!("lib/{}/{}", lib.abi, lib.name)),
+            name: lib.name.clone(),
+            hashes: vec![Hash {
+                alg: "SHA-256",
+                content: lib.sha256.clone(),
+            }],
+            properties: vec![
+                Property {
+                    name: "cargo-apk:abi",
+                    value: lib.abi.clone(),
+                },
+                Property {
+                    name: "cargo-apk:source",
+                    value: lib.source.display().to_string(),
+                },
+                Property {
+                    name: "cargo-apk:origin",
+                    value: origin.as_str().unwrap().to_string(),
+                },
+            ],
+            ..Default::default()
+        }
+    }
+}
+
+/// A CycloneDX 1.5 document with `package` as the application, and its
+/// [`Metadata::dependencies()`] and `libs` as components.
+fn cyclonedx_json(
+    metadata: &Metadata,
+    package: &str,
+    manifest_path: &Path,
+    libs: &[NativeLib],
+) -> Result<String, Error> {
+    let root = metadata.root(package, manifest_path)?;
+    let mut crates = metadata.dependencies(package, manifest_path)?;
+    crates.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    let components = crates
+        .into_iter()
+        .map(|p| Component::package("library", p))
+        .chain(libs.iter().map(Component::native_lib))
+        .collect();
+    let bom = Bom {
+        bom_format: "CycloneDX",
+        spec_version: "1.5",
+        version: 1,
+        metadata: BomMetadata {
+            tools: Tools {
+                components: vec![Component {
+                    kind: "application",
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                    ..Default::default()
+                }],
+            },
+            component: Component::package("application", root),
+        },
+        components,
+    };
+    Ok(serde_json::to_string_pretty(&bom).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::licenses::tests::fixture;
+
+    #[test]
+    fn cyclonedx() {
+        let (dir, metadata) = fixture();
+        let libs = [NativeLib {
+            abi: "arm64-v8a".to_string(),
+            name: "libc++_shared.so".to_string(),
+            source: PathBuf::from("/ndk/sysroot/libc++_shared.so"),
+            origin: LibOrigin::NdkSysroot,
+            sha256: "ab".repeat(32),
+        }];
+        let json = cyclonedx_json(&metadata, "app", &dir.join("app/Cargo.toml"), &libs).unwrap();
+        let bom = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["metadata"]["component"]["purl"], "pkg:cargo/app@0.1.0");
+
+        // Workspace members are compiled in too, build dependencies are not
+        let components = bom["components"].as_array().unwrap();
+        let names = components
+            .iter()
+            .map(|c| c["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["bar", "foo", "internal", "qux", "libc++_shared.so"]);
+        assert_eq!(
+            components[0]["licenses"][0]["expression"],
+            "MIT OR Apache-2.0"
+        );
+
+        let lib = &components[4];
+        assert_eq!(lib["bom-ref"], "lib/arm64-v8a/libc++_shared.so");
+        assert_eq!(lib["hashes"][0]["content"], "ab".repeat(32));
+        assert_eq!(lib["properties"][2]["value"], "ndk_sysroot");
+    }
+}
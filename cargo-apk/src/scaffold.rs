@@ -0,0 +1,263 @@
+//! `cargo apk new` and `cargo apk init`, generating a minimal app from templates
+//! embedded in the binary.
+
+use crate::error::Error;
+use std::path::Path;
+
+const CARGO_TOML: &str = include_str!("../templates/Cargo.toml.template");
+const METADATA: &str = include_str!("../templates/metadata.toml.template");
+const README: &str = include_str!("../templates/README.md.template");
+const ICON: &[u8] = include_bytes!("../templates/ic_launcher.png");
+
+/// Which `src/lib.rs` and dependencies to generate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Template {
+    /// A `NativeActivity` event loop through `android-activity`
+    #[default]
+    Bare,
+    /// A `winit` window
+    Winit,
+    /// The deprecated `ndk-glue`
+    Legacy,
+}
+
+impl Template {
+    fn lib_rs(self) -> &'static str {
+        match self {
+            Self::Bare => include_str!("../templates/bare/lib.rs"),
+            Self::Winit => include_str!("../templates/winit/lib.rs"),
+            Self::Legacy => include_str!("../templates/legacy/lib.rs"),
+        }
+    }
+
+    fn dependencies(self) -> &'static str {
+        match self {
+            Self::Bare => include_str!("../templates/bare/dependencies.toml"),
+            Self::Winit => include_str!("../templates/winit/dependencies.toml"),
+            Self::Legacy => include_str!("../templates/legacy/dependencies.toml"),
+        }
+    }
+}
+
+/// Options for [`new_project()`] and [`init_project()`].
+#[derive(Clone, Debug, Default)]
+pub struct ScaffoldOptions {
+    /// Defaults to `com.example.<name>`
+    pub package_id: Option<String>,
+    pub template: Template,
+}
+
+/// Creates a new app named after the last component of `path`, which must not exist.
+pub fn new_project(path: &Path, options: &ScaffoldOptions) -> Result<(), Error> {
+    if path.exists() {
+        return Err(Error::ProjectExists(path.to_owned()));
+    }
+    init_project(path, options)
+}
+
+/// Sets up an app in `path`. An existing `Cargo.toml` gets the
+/// `[package.metadata.android]` tables appended, and existing files are never overwritten.
+pub fn init_project(path: &Path, options: &ScaffoldOptions) -> Result<(), Error> {
+    let manifest_path = path.join("Cargo.toml");
+    let existing = match std::fs::read_to_string(&manifest_path) {
+        Ok(manifest) => Some(manifest),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let name = match &existing {
+        Some(manifest) => package_name(manifest)?,
+        None => {
+            // Resolve `.` and `..` for `cargo apk init`
+            let path = if path.exists() {
+                dunce::canonicalize(path)?
+            } else {
+                path.to_owned()
+            };
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| {
+                    name.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                })
+                .map(ToString::to_string)
+                .ok_or_else(|| Error::InvalidProjectName(path.display().to_string()))?
+        }
+    };
+    let package_id = match &options.package_id {
+        Some(package_id) => package_id.clone(),
+        None => format!("com.example.{}", name.replace('-', "_")),
+    };
+    if !is_valid_package_id(&package_id) {
+        return Err(Error::InvalidPackageId(package_id));
+    }
+
+    let render = |template: &str| {
+        template
+            .replace("{{name}}", &name)
+            .replace("{{label}}", &name)
+            .replace("{{package_id}}", &package_id)
+    };
+    let metadata = render(METADATA);
+
+    std::fs::create_dir_all(path.join("src"))?;
+    match existing {
+        Some(manifest) if manifest.contains("[package.metadata.android") => {
+            println!("`{}` is already configured", manifest_path.display());
+        }
+        Some(manifest) => {
+            if !manifest.contains("cdylib") {
+                eprintln!(
+                    "Warning: add `crate-type = [\"cdylib\"]` under `[lib]` in `{}`",
+                    manifest_path.display()
+                );
+            }
+            let separator = if manifest.ends_with('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            };
+            std::fs::write(
+                &manifest_path,
+                format!("{}{}{}", manifest, separator, metadata),
+            )?;
+        }
+        None => {
+            let manifest = render(CARGO_TOML)
+                .replace("{{dependencies}}", options.template.dependencies())
+                .replace("{{metadata}}", &metadata);
+            std::fs::write(&manifest_path, manifest)?;
+        }
+    }
+
+    write_new(
+        &path.join("src").join("lib.rs"),
+        render(options.template.lib_rs()),
+    )?;
+    write_new(&path.join("README.md"), render(README))?;
+    write_new(&path.join("assets").join(".gitkeep"), "")?;
+    write_new(
+        &path.join("res").join("mipmap-mdpi").join("ic_launcher.png"),
+        ICON,
+    )?;
+
+    let gitignore = path.join(".gitignore");
+    let ignored = std::fs::read_to_string(&gitignore).unwrap_or_default();
+    if !ignored
+        .lines()
+        .any(|line| line == "/target" || line == "target")
+    {
+        std::fs::write(&gitignore, format!("{}/target\n", ignored))?;
+    }
+
+    println!(
+        "Created `{}` ({}) in `{}`, run it with `cargo apk run`",
+        name,
+        package_id,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Writes `contents` to `path` unless it exists.
+fn write_new(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    if path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+fn package_name(manifest: &str) -> Result<String, Error> {
+    let manifest: toml::Value = toml::from_str(manifest)?;
+    manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str)
+        .map(ToString::to_string)
+        .ok_or_else(|| Error::InvalidProjectName("Cargo.toml".to_string()))
+}
+
+/// At least two `.`-separated segments starting with a letter, like `com.example`.
+fn is_valid_package_id(package_id: &str) -> bool {
+    let segments = package_id.split('.').collect::<Vec<_>>();
+    segments.len() >= 2
+        && segments.iter().all(|segment| {
+            segment.starts_with(|c: char| c.is_ascii_alphabetic())
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Manifest;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn new() {
+        for template in [Template::Bare, Template::Winit, Template::Legacy] {
+            let dir = TestDir::new(&format!("{:?}", template));
+            let path = dir.join("my-app");
+            new_project(
+                &path,
+                &ScaffoldOptions {
+                    template,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let manifest = Manifest::parse_from_toml(&path.join("Cargo.toml")).unwrap();
+            assert_eq!(manifest.android_manifest.package, "com.example.my_app");
+            assert_eq!(manifest.android_manifest.application.label, "my-app");
+            assert_eq!(manifest.resources.as_deref(), Some(Path::new("res")));
+            assert!(path.join("res/mipmap-mdpi/ic_launcher.png").exists());
+            let lib_rs = std::fs::read_to_string(path.join("src/lib.rs")).unwrap();
+            assert!(!lib_rs.contains("{{"));
+
+            assert!(matches!(
+                new_project(&path, &ScaffoldOptions::default()),
+                Err(Error::ProjectExists(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn init_existing() {
+        let path = TestDir::new("init");
+        std::fs::create_dir_all(path.join("src")).unwrap();
+        let manifest = "[package]\nname = \"existing\"\nversion = \"0.2.0\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n";
+        std::fs::write(path.join("Cargo.toml"), manifest).unwrap();
+        std::fs::write(path.join("src/lib.rs"), "// mine\n").unwrap();
+
+        let options = ScaffoldOptions {
+            package_id: Some("org.foo.existing".to_string()),
+            ..Default::default()
+        };
+        init_project(&path, &options).unwrap();
+        // Idempotent
+        init_project(&path, &options).unwrap();
+
+        let parsed = Manifest::parse_from_toml(&path.join("Cargo.toml")).unwrap();
+        assert_eq!(parsed.android_manifest.package, "org.foo.existing");
+        assert_eq!(
+            std::fs::read_to_string(path.join("src/lib.rs")).unwrap(),
+            "// mine\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(path.join(".gitignore")).unwrap(),
+            "/target\n"
+        );
+    }
+
+    #[test]
+    fn package_ids() {
+        assert!(is_valid_package_id("com.example.my_app"));
+        assert!(!is_valid_package_id("example"));
+        assert!(!is_valid_package_id("com.example.my-app"));
+        assert!(!is_valid_package_id("com.1example"));
+    }
+}
@@ -0,0 +1,256 @@
+//! `max_apk_size_mb`: checks the size of every built apk against the limit of Google
+//! Play, listing what takes up the most space and how to shrink it.
+
+use crate::error::Error;
+use ndk_build::apk::StripMode;
+use ndk_build::error::NdkError;
+use ndk_build::util::format_size;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The largest apk that Google Play accepts. App bundles, which cargo-apk doesn't build,
+/// may have a compressed download size of 200 MB instead.
+pub(crate) const PLAY_APK_LIMIT_MB: u64 = 100;
+
+/// How many of the largest parts of an apk are listed.
+const TOP_CONTRIBUTORS: usize = 5;
+
+/// What exceeding `max_apk_size_mb` does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SizeLimitAction {
+    #[default]
+    Warn,
+    /// Fails the build, e.g. to gate merges in CI
+    Error,
+}
+
+/// What the libraries of the checked apk were built with, for the suggestions.
+pub(crate) struct BuildSettings {
+    pub(crate) strip: StripMode,
+    /// Whether several ABIs are packaged into the apk
+    pub(crate) multiple_abis: bool,
+}
+
+/// Warns about, or with [`SizeLimitAction::Error`] fails on, an `apk` that is larger than
+/// `limit_mb` MiB.
+pub(crate) fn check(
+    apk: &Path,
+    limit_mb: u64,
+    action: SizeLimitAction,
+    settings: &BuildSettings,
+) -> Result<(), Error> {
+    let size = std::fs::metadata(apk)
+        .map_err(|e| NdkError::IoPathError(apk.to_owned(), e))?
+        .len();
+    if size <= limit_mb * 1024 * 1024 {
+        return Ok(());
+    }
+    let entries = zip_entries(apk).map_err(|e| NdkError::IoPathError(apk.to_owned(), e))?;
+    let contributors = contributors(&entries);
+    let mut details = String::from("Largest parts, compressed:");
+    for (name, size) in contributors.iter().take(TOP_CONTRIBUTORS) {
+        details.push_str(&format!("\n    {:>10}  {}", format_size(*size), name));
+    }
+    details.push_str("\nTo shrink it:");
+    for suggestion in suggestions(&contributors, settings) {
+        details.push_str("\n    - ");
+        details.push_str(&suggestion);
+    }
+    let error = Error::ApkTooLarge {
+        apk: apk.to_owned(),
+        size: format_size(size),
+        limit_mb,
+        details,
+    };
+    match action {
+        SizeLimitAction::Warn => {
+            eprintln!("Warning: {}", error);
+            Ok(())
+        }
+        SizeLimitAction::Error => Err(error),
+    }
+}
+
+/// The parts of an apk that the user controls separately, like one library, one
+/// directory of assets or all resources, with their compressed size, largest first.
+fn contributors(entries: &[(String, u64)]) -> Vec<(String, u64)> {
+    let mut sizes = BTreeMap::<String, u64>::new();
+    for (name, size) in entries {
+        let part = match name.split('/').collect::<Vec<_>>()[..] {
+            ["assets", dir, _, ..] => format!("assets/{}/", dir),
+            ["res", ..] => "res/".to_string(),
+            ["META-INF", ..] => "META-INF/".to_string(),
+            _ => name.clone(),
+        };
+        *sizes.entry(part).or_default() += size;
+    }
+    let mut sizes = sizes.into_iter().collect::<Vec<_>>();
+    sizes.sort_by(|(a, a_size), (b, b_size)| b_size.cmp(a_size).then(a.cmp(b)));
+    sizes
+}
+
+/// Ways to shrink the apk that apply to its largest parts.
+fn suggestions(contributors: &[(String, u64)], settings: &BuildSettings) -> Vec<String> {
+    let top = &contributors[..contributors.len().min(TOP_CONTRIBUTORS)];
+    let libs = top.iter().any(|(name, _)| name.starts_with("lib/"));
+    let assets = top.iter().any(|(name, _)| name.starts_with("assets/"));
+    let mut suggestions = vec![];
+    if libs && settings.strip != StripMode::All {
+        suggestions.push(
+            "strip the libraries with `strip = \"strip\"`, or `strip = \"all\"` in the \
+            `[package.metadata.android.<profile>]` of the profile"
+                .to_string(),
+        );
+    }
+    if libs && settings.multiple_abis {
+        suggestions.push(
+            "build an apk per ABI with `--target`, instead of packaging all `build_targets` \
+            into one"
+                .to_string(),
+        );
+    }
+    if assets {
+        suggestions.push(
+            "move assets for one screen density or language into `split_assets`, or \
+            deliver large ones separately, e.g. as Play Asset Delivery packs or OBB \
+            expansion files"
+                .to_string(),
+        );
+    }
+    if suggestions.is_empty() {
+        suggestions.push(
+            "deliver large files separately, e.g. as Play Asset Delivery packs or OBB \
+            expansion files, and download them on first launch"
+                .to_string(),
+        );
+    }
+    suggestions
+}
+
+/// The name and compressed size of every entry in the central directory of the zip at
+/// `path`.
+fn zip_entries(path: &Path) -> std::io::Result<Vec<(String, u64)>> {
+    const END_SIGNATURE: u32 = 0x0605_4b50;
+    const ENTRY_SIGNATURE: u32 = 0x0201_4b50;
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "not a zip file");
+    let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at =
+        |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    // The end of central directory record is followed by a comment of up to 64 KiB
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let tail_len = len.min(22 + u16::MAX as u64);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| u32_at(&tail, at) == END_SIGNATURE)
+        .ok_or_else(invalid)?;
+    let count = u16_at(&tail, end + 10) as usize;
+    let directory_len = u32_at(&tail, end + 12) as usize;
+    let directory_offset = u32_at(&tail, end + 16) as u64;
+
+    file.seek(SeekFrom::Start(directory_offset))?;
+    let mut directory = vec![0; directory_len];
+    file.read_exact(&mut directory)?;
+    let mut entries = Vec::with_capacity(count);
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > directory.len() || u32_at(&directory, at) != ENTRY_SIGNATURE {
+            return Err(invalid());
+        }
+        let compressed = u32_at(&directory, at + 20) as u64;
+        let name_len = u16_at(&directory, at + 28) as usize;
+        let extra_len = u16_at(&directory, at + 30) as usize;
+        let comment_len = u16_at(&directory, at + 32) as usize;
+        let name = directory
+            .get(at + 46..at + 46 + name_len)
+            .ok_or_else(invalid)?;
+        entries.push((String::from_utf8_lossy(name).into_owned(), compressed));
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    /// A zip with only a central directory, which is all that [`zip_entries()`] reads.
+    fn zip(entries: &[(&str, u32)]) -> Vec<u8> {
+        let mut directory = vec![];
+        for (name, size) in entries {
+            let mut header = vec![0; 46];
+            header[..4].copy_from_slice(&0x0201_4b50u32.to_le_bytes());
+            header[20..24].copy_from_slice(&size.to_le_bytes());
+            header[28..30].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend(header);
+            directory.extend(name.as_bytes());
+        }
+        let mut end = vec![0; 22];
+        end[..4].copy_from_slice(&0x0605_4b50u32.to_le_bytes());
+        end[10..12].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        end[12..16].copy_from_slice(&(directory.len() as u32).to_le_bytes());
+        directory.extend(end);
+        directory
+    }
+
+    #[test]
+    fn largest_parts() {
+        let dir = TestDir::new("size");
+        let path = dir.join("app.apk");
+        std::fs::write(
+            &path,
+            zip(&[
+                ("AndroidManifest.xml", 2_000),
+                ("lib/arm64-v8a/libgame.so", 60_000_000),
+                ("lib/armeabi-v7a/libgame.so", 45_000_000),
+                ("assets/music/theme.ogg", 7_000_000),
+                ("assets/music/credits.ogg", 5_000_000),
+                ("assets/config.json", 1_000),
+                ("res/mipmap-hdpi/icon.png", 30_000),
+                ("res/mipmap-xhdpi/icon.png", 50_000),
+            ]),
+        )
+        .unwrap();
+        let entries = zip_entries(&path).unwrap();
+        assert_eq!(entries.len(), 8);
+        assert_eq!(
+            contributors(&entries),
+            [
+                ("lib/arm64-v8a/libgame.so".to_string(), 60_000_000),
+                ("lib/armeabi-v7a/libgame.so".to_string(), 45_000_000),
+                ("assets/music/".to_string(), 12_000_000),
+                ("res/".to_string(), 80_000),
+                ("AndroidManifest.xml".to_string(), 2_000),
+                ("assets/config.json".to_string(), 1_000),
+            ]
+        );
+
+        let suggestions = suggestions(
+            &contributors(&entries),
+            &BuildSettings {
+                strip: StripMode::All,
+                multiple_abis: true,
+            },
+        );
+        assert_eq!(suggestions.len(), 2);
+        assert!(suggestions[0].starts_with("build an apk per ABI"));
+        assert!(suggestions[1].contains("`split_assets`"));
+    }
+
+    #[test]
+    fn not_a_zip() {
+        let dir = TestDir::new("size");
+        let path = dir.join("app.txt");
+        std::fs::write(&path, "not an apk").unwrap();
+        let error = zip_entries(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,144 @@
+//! `split_assets`: configuration splits holding the assets of one screen density or
+//! language, which `cargo apk run` installs next to the base apk with
+//! `adb install-multiple`. Experimental, enabled with `experimental = ["split_assets"]`.
+
+use crate::error::Error;
+use crate::manifest::Manifest;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// The name of the feature in `experimental`.
+pub(crate) const FEATURE: &str = "split_assets";
+
+/// Every feature that `experimental` can enable.
+const EXPERIMENTAL_FEATURES: &[&str] = &[FEATURE];
+
+/// The density buckets of Android resources with their dpi.
+const DENSITIES: &[(&str, u32)] = &[
+    ("ldpi", 120),
+    ("mdpi", 160),
+    ("tvdpi", 213),
+    ("hdpi", 240),
+    ("xhdpi", 320),
+    ("xxhdpi", 480),
+    ("xxxhdpi", 640),
+];
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct SplitAssets {
+    /// A density bucket like `xxhdpi`, or a language like `de` or `pt_BR`, which becomes
+    /// the split `config.<name>`
+    pub(crate) name: String,
+    /// Directory of the assets packaged into the split, resolved like `assets`
+    pub(crate) assets: PathBuf,
+}
+
+impl SplitAssets {
+    fn density(&self) -> Option<u32> {
+        DENSITIES
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map(|(_, dpi)| *dpi)
+    }
+}
+
+/// Fails if `split_assets` is configured without enabling it or has invalid names, and
+/// warns about unknown `experimental` features.
+pub(crate) fn check(manifest: &Manifest) -> Result<(), Error> {
+    for feature in &manifest.experimental {
+        if !EXPERIMENTAL_FEATURES.contains(&feature.as_str()) {
+            eprintln!(
+                "Warning: unknown experimental feature `{}`, expected one of {}",
+                feature,
+                EXPERIMENTAL_FEATURES.join(", ")
+            );
+        }
+    }
+    if manifest.split_assets.is_empty() {
+        return Ok(());
+    }
+    if !manifest.experimental.iter().any(|f| f == FEATURE) {
+        return Err(Error::ExperimentalFeature(FEATURE));
+    }
+    for (i, split) in manifest.split_assets.iter().enumerate() {
+        let valid = !split.name.is_empty()
+            && split
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let duplicate = manifest.split_assets[..i]
+            .iter()
+            .any(|other| other.name == split.name);
+        if !valid || duplicate {
+            return Err(Error::InvalidSplitName(split.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// The splits to install on a device with `density` and `locale`: the density split
+/// closest to the device, preferring a higher density that is scaled down, and the
+/// splits for its locale or language.
+pub(crate) fn select<'a>(
+    splits: &'a [SplitAssets],
+    density: Option<u32>,
+    locale: Option<&str>,
+) -> Vec<&'a SplitAssets> {
+    let mut densities = splits
+        .iter()
+        .filter_map(|split| Some((split.density()?, split)))
+        .collect::<Vec<_>>();
+    densities.sort_by_key(|(dpi, _)| *dpi);
+    let density_split = density.and_then(|density| {
+        densities
+            .iter()
+            .find(|(dpi, _)| *dpi >= density)
+            .or(densities.last())
+            .map(|(_, split)| *split)
+    });
+
+    // `de-DE` is `de_DE` in split names, which can't contain a `-`
+    let locale = locale.map(|locale| locale.replace('-', "_"));
+    let language = locale
+        .as_deref()
+        .map(|locale| locale.split('_').next().unwrap());
+    let locale_splits = splits.iter().filter(|split| {
+        split.density().is_none()
+            && (locale.as_deref() == Some(&split.name) || language == Some(&split.name))
+    });
+    density_split.into_iter().chain(locale_splits).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splits(names: &[&str]) -> Vec<SplitAssets> {
+        names
+            .iter()
+            .map(|name| SplitAssets {
+                name: name.to_string(),
+                assets: PathBuf::from(format!("assets-{}", name)),
+            })
+            .collect()
+    }
+
+    fn names(selected: Vec<&SplitAssets>) -> Vec<&str> {
+        selected.iter().map(|split| split.name.as_str()).collect()
+    }
+
+    #[test]
+    fn select_for_device() {
+        let splits = splits(&["hdpi", "xxxhdpi", "xhdpi", "de", "pt_BR"]);
+        assert_eq!(names(select(&splits, Some(320), None)), ["xhdpi"]);
+        assert_eq!(names(select(&splits, Some(420), None)), ["xxxhdpi"]);
+        assert_eq!(names(select(&splits, Some(160), None)), ["hdpi"]);
+        assert_eq!(names(select(&splits, Some(800), None)), ["xxxhdpi"]);
+        assert_eq!(names(select(&splits, None, Some("de-DE"))), ["de"]);
+        assert_eq!(
+            names(select(&splits, Some(240), Some("pt-BR"))),
+            ["hdpi", "pt_BR"]
+        );
+        assert!(select(&splits, None, Some("pt-PT")).is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+//! `--timings`: writes the phases and commands recorded by [`ndk_build::timings`] to the
+//! build directory, as a static page with a bar per entry or as JSON.
+
+use crate::apk::apk_build_dir;
+use crate::error::Error;
+use cargo_subcommand::Subcommand;
+use ndk_build::error::NdkError;
+use ndk_build::timings::{Timing, TimingKind};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimingsFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+/// Writes what [`ndk_build::timings`] recorded since it was enabled to the build directory
+/// of `cmd`, and returns the path of the report.
+pub fn write_timings(
+    cmd: &Subcommand,
+    flavor: Option<&str>,
+    format: TimingsFormat,
+) -> Result<PathBuf, Error> {
+    let timings = ndk_build::timings::take().unwrap_or_default();
+    write(&apk_build_dir(cmd, flavor)?, format, &timings)
+}
+
+/// Writes `timings` to `cargo-apk-timings.html` or `.json` in `dir` and returns its path.
+fn write(dir: &Path, format: TimingsFormat, timings: &[Timing]) -> Result<PathBuf, Error> {
+    let (extension, contents) = match format {
+        TimingsFormat::Html => ("html", html(timings)),
+        TimingsFormat::Json => ("json", serde_json::to_string_pretty(timings).unwrap()),
+    };
+    std::fs::create_dir_all(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?;
+    let path = dir.join(format!("cargo-apk-timings.{}", extension));
+    std::fs::write(&path, contents).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+    Ok(path)
+}
+
+/// A page with a row per entry, whose bar spans the time it ran relative to the whole
+/// invocation. Commands are indented below the phases that ran them.
+fn html(timings: &[Timing]) -> String {
+    let total = timings
+        .iter()
+        .map(|t| t.start + t.duration)
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+    let mut rows = String::new();
+    for timing in timings {
+        let class = match (timing.kind, timing.success) {
+            (_, false) => "failed",
+            (TimingKind::Phase, true) => "phase",
+            (TimingKind::Command, true) => "command",
+        };
+        let mut title = format!("{:.2}s at {:.2}s", timing.duration, timing.start);
+        if let Some(hash) = &timing.args_hash {
+            write!(title, ", args {}", hash).unwrap();
+        }
+        if let Some(code) = timing.exit_code {
+            write!(title, ", exit code {}", code).unwrap();
+        }
+        writeln!(
+            rows,
+            "<tr class=\"{}\"><td>{}</td><td>{:.2}s</td><td class=\"track\">\
+            <div style=\"left: {:.3}%; width: {:.3}%\" title=\"{}\"></div></td></tr>",
+            class,
+            escape(&timing.name),
+            timing.duration,
+            timing.start / total * 100.0,
+            (timing.duration / total * 100.0).max(0.1),
+            title,
+        )
+        .unwrap();
+    }
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>cargo-apk timings</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; width: 100%; }}
+td {{ padding: 2px 8px; white-space: nowrap; }}
+td.track {{ position: relative; width: 100%; }}
+td.track div {{ position: absolute; top: 3px; bottom: 3px; min-width: 1px; }}
+tr.phase td.track div {{ background: #4a7ebb; }}
+tr.command td:first-child {{ padding-left: 24px; color: #555; }}
+tr.command td.track div {{ background: #9bbb59; }}
+tr.failed td.track div {{ background: #c0504d; }}
+</style>
+</head>
+<body>
+<h1>cargo-apk timings</h1>
+<p>{} phases and {} commands in {:.2}s</p>
+<table>
+{}</table>
+</body>
+</html>
+",
+        timings
+            .iter()
+            .filter(|t| t.kind == TimingKind::Phase)
+            .count(),
+        timings
+            .iter()
+            .filter(|t| t.kind == TimingKind::Command)
+            .count(),
+        total,
+        rows
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars() {
+        let timing = |kind, name: &str, start, duration, success| Timing {
+            kind,
+            name: name.to_string(),
+            args_hash: (kind == TimingKind::Command).then(|| "00000000deadbeef".to_string()),
+            start,
+            duration,
+            success,
+            exit_code: (kind == TimingKind::Command).then_some(if success { 0 } else { 101 }),
+        };
+        let page = html(&[
+            timing(TimingKind::Phase, "compile (arm64-v8a)", 0.0, 3.0, true),
+            timing(TimingKind::Command, "cargo", 0.0, 3.0, true),
+            timing(TimingKind::Phase, "sign <release>", 3.0, 1.0, false),
+        ]);
+        assert!(page.contains("2 phases and 1 commands in 4.00s"));
+        assert!(page.contains(
+            "<tr class=\"phase\"><td>compile (arm64-v8a)</td><td>3.00s</td><td class=\"track\">\
+            <div style=\"left: 0.000%; width: 75.000%\" title=\"3.00s at 0.00s\"></div>"
+        ));
+        assert!(page.contains("title=\"3.00s at 0.00s, args 00000000deadbeef, exit code 0\""));
+        assert!(page.contains(
+            "<tr class=\"failed\"><td>sign &lt;release&gt;</td><td>1.00s</td><td class=\"track\">\
+            <div style=\"left: 75.000%; width: 25.000%\""
+        ));
+    }
+}
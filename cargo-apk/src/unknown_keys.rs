@@ -0,0 +1,298 @@
+//! Finds keys under `[package.metadata.android]` that serde would silently skip.
+//!
+//! Instead of `deny_unknown_fields`, which would also reject keys of newer cargo-apk
+//! versions, the metadata is deserialized a second time through [`Walker`]. It wraps
+//! [`toml::Value`] and compares the keys of every table against the fields that the
+//! `Deserialize` impl of the struct at that position asks for.
+
+use serde::de::{self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess};
+use serde::{Deserialize, forward_to_deserialize_any};
+use std::cell::RefCell;
+use toml::Value;
+
+/// A key that is not a field of the table it was found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct UnknownKey {
+    /// Path below `[package.metadata.android]`, e.g. `application.activity.lable`
+    pub(crate) path: String,
+    /// [`UnknownKey::path`] with the last key replaced by the closest valid spelling
+    pub(crate) suggestion: Option<String>,
+}
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}`", self.path)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean `{}`?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// An unknown key, with the valid keys of its table.
+struct Found {
+    parent: String,
+    key: String,
+    fields: &'static [&'static str],
+}
+
+impl Found {
+    fn unknown_key(&self, fields: &[&str]) -> UnknownKey {
+        UnknownKey {
+            path: join(&self.parent, &self.key),
+            suggestion: closest(&self.key, fields).map(|field| join(&self.parent, field)),
+        }
+    }
+}
+
+/// Lists the keys of `value` that `T` does not know, at every nesting level.
+fn walk<'de, T: Deserialize<'de>>(value: Value, path: &str) -> Vec<Found> {
+    let found = RefCell::new(Vec::new());
+    // Invalid values are reported by the actual deserialization
+    let _ = T::deserialize(Walker {
+        value,
+        path: path.to_string(),
+        found: &found,
+    });
+    found.into_inner()
+}
+
+/// Checks a `[package.metadata.android]` table that is deserialized by flattening
+/// `Settings` and `Manifest` into it, where every other table is a `Profile`.
+pub(crate) fn find<'de, Settings, Manifest, Profile>(metadata: &Value) -> Vec<UnknownKey>
+where
+    Settings: Deserialize<'de>,
+    Manifest: Deserialize<'de>,
+    Profile: Deserialize<'de>,
+{
+    let Value::Table(table) = metadata else {
+        return Vec::new();
+    };
+    let (settings, mut nested): (Vec<_>, Vec<_>) = walk::<Settings>(metadata.clone(), "")
+        .into_iter()
+        .partition(|found| found.parent.is_empty());
+    let (manifest, manifest_nested): (Vec<_>, Vec<_>) = walk::<Manifest>(metadata.clone(), "")
+        .into_iter()
+        .partition(|found| found.parent.is_empty());
+    nested.extend(manifest_nested);
+    let mut unknown = nested
+        .iter()
+        .map(|found| found.unknown_key(found.fields))
+        .collect::<Vec<_>>();
+
+    // Top-level keys belong to either part, and are only unknown if neither knows them
+    if let (Some(s), Some(m)) = (settings.first(), manifest.first()) {
+        let fields = [s.fields, m.fields].concat();
+        for found in settings
+            .iter()
+            .filter(|found| manifest.iter().any(|m| m.key == found.key))
+        {
+            match &table[&found.key] {
+                // Unless it looks like a misspelled table of the manifest
+                profile @ Value::Table(_) if closest(&found.key, &fields).is_none() => unknown
+                    .extend(
+                        walk::<Profile>(profile.clone(), &found.key)
+                            .iter()
+                            .map(|found| found.unknown_key(found.fields)),
+                    ),
+                _ => unknown.push(found.unknown_key(&fields)),
+            }
+        }
+    }
+    unknown.sort_by(|a, b| a.path.cmp(&b.path));
+    unknown
+}
+
+fn join(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+/// The field with the smallest edit distance to `key`, if it is close enough to be a typo.
+fn closest<'f>(key: &str, fields: &[&'f str]) -> Option<&'f str> {
+    let max_distance = (key.chars().count() / 3).max(1);
+    fields
+        .iter()
+        .map(|field| (edit_distance(key, field), *field))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+/// The number of insertions, deletions, substitutions and transpositions of adjacent
+/// characters that turn `a` into `b` (optimal string alignment distance).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = Vec::new();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for i in 1..=a.len() {
+        let mut next = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            next[j] = (row[j - 1] + usize::from(a[i - 1] != b[j - 1]))
+                .min(row[j] + 1)
+                .min(next[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                next[j] = next[j].min(previous[j - 2] + 1);
+            }
+        }
+        previous = std::mem::replace(&mut row, next);
+    }
+    row[b.len()]
+}
+
+/// Deserializes [`Walker::value`] like [`toml::Value`] does, recording the keys of
+/// every table that are not among the fields of the struct it is deserialized into.
+struct Walker<'a> {
+    value: Value,
+    path: String,
+    found: &'a RefCell<Vec<Found>>,
+}
+
+impl<'de> Deserializer<'de> for Walker<'_> {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Table(table) => visitor.visit_map(Entries {
+                entries: table.into_iter(),
+                pending: None,
+                path: self.path,
+                found: self.found,
+            }),
+            Value::Array(array) => visitor.visit_seq(Elements {
+                elements: array.into_iter().enumerate(),
+                path: self.path,
+                found: self.found,
+            }),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if let Value::Table(table) = &self.value {
+            let mut found = self.found.borrow_mut();
+            for key in table.keys().filter(|key| !fields.contains(&key.as_str())) {
+                found.push(Found {
+                    parent: self.path.clone(),
+                    key: key.clone(),
+                    fields,
+                });
+            }
+        }
+        self.deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+struct Entries<'a> {
+    entries: <toml::map::Map<String, Value> as IntoIterator>::IntoIter,
+    pending: Option<(String, Value)>,
+    path: String,
+    found: &'a RefCell<Vec<Found>>,
+}
+
+impl<'de> MapAccess<'de> for Entries<'_> {
+    type Error = toml::de::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        let deserializer: de::value::StringDeserializer<Self::Error> =
+            key.clone().into_deserializer();
+        self.pending = Some((key, value));
+        seed.deserialize(deserializer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .pending
+            .take()
+            .expect("next_value_seed() is called after next_key_seed()");
+        seed.deserialize(Walker {
+            value,
+            path: join(&self.path, &key),
+            found: self.found,
+        })
+    }
+}
+
+struct Elements<'a> {
+    elements: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+    path: String,
+    found: &'a RefCell<Vec<Found>>,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_> {
+    type Error = toml::de::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        let Some((i, value)) = self.elements.next() else {
+            return Ok(None);
+        };
+        seed.deserialize(Walker {
+            value,
+            path: format!("{}[{}]", self.path, i),
+            found: self.found,
+        })
+        .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(edit_distance("runtime-libs", "runtime_libs"), 1);
+        assert_eq!(edit_distance("lable", "label"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(closest("lable", &["label", "name"]), Some("label"));
+        assert_eq!(closest("optimise", &["optimize"]), Some("optimize"));
+        assert_eq!(closest("icon", &["label", "theme"]), None);
+    }
+}
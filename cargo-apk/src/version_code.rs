@@ -0,0 +1,118 @@
+//! `[package.metadata.android.version_code]`: how the `versionCode` is derived, for
+//! apps whose earlier uploads used a different scheme than cargo-apk's default.
+
+use crate::build_info::utc_date;
+use crate::error::Error;
+use ndk_build::cargo::{SemverWidths, VersionCode};
+use serde::Deserialize;
+
+/// `[package.metadata.android.version_code]`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct VersionCodeConfig {
+    pub(crate) scheme: VersionCodeScheme,
+    /// Added to the code of every scheme, to continue above the codes uploaded before
+    pub(crate) offset: u32,
+    /// Bits of the `semver` scheme for each component of the version
+    pub(crate) major_bits: u8,
+    pub(crate) minor_bits: u8,
+    pub(crate) patch_bits: u8,
+    /// The code of the `explicit` scheme
+    pub(crate) code: Option<u32>,
+    /// The `NN` of the `date` scheme, counting the builds of a day
+    pub(crate) build: u32,
+}
+
+impl Default for VersionCodeConfig {
+    fn default() -> Self {
+        let widths = SemverWidths::default();
+        Self {
+            scheme: VersionCodeScheme::default(),
+            offset: 0,
+            major_bits: widths.major,
+            minor_bits: widths.minor,
+            patch_bits: widths.patch,
+            code: None,
+            build: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VersionCodeScheme {
+    /// The version of the package, packed into `major_bits`, `minor_bits` and
+    /// `patch_bits` below a leading `1`
+    #[default]
+    Semver,
+    /// `yyMMddNN` of the build date in UTC and the `build` number
+    Date,
+    /// `code` as is
+    Explicit,
+}
+
+impl VersionCodeConfig {
+    /// The `versionCode` of the package at `version`, built at `timestamp` seconds since
+    /// the Unix epoch.
+    pub(crate) fn code(&self, version: &str, timestamp: u64) -> Result<u32, Error> {
+        Ok(match self.scheme {
+            VersionCodeScheme::Semver => {
+                let widths = SemverWidths {
+                    major: self.major_bits,
+                    minor: self.minor_bits,
+                    patch: self.patch_bits,
+                };
+                VersionCode::from_semver(version)?.to_code_with(1, widths, self.offset)?
+            }
+            VersionCodeScheme::Date => {
+                let (year, month, day) = utc_date(timestamp);
+                VersionCode::date_code(year, month, day, self.build, self.offset)?
+            }
+            VersionCodeScheme::Explicit => {
+                let code = self.code.ok_or(Error::ExplicitVersionCodeMissing)?;
+                VersionCode::explicit_code(code, self.offset)?
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndk_build::error::NdkError;
+
+    /// 2024-05-01T12:30:00Z
+    const TIMESTAMP: u64 = 1714566600;
+
+    #[test]
+    fn schemes() {
+        let config = |toml: &str| toml::from_str::<VersionCodeConfig>(toml).unwrap();
+        let code = |toml: &str| config(toml).code("1.2.3", TIMESTAMP);
+
+        // The default matches `VersionCode::to_code(1)`
+        assert_eq!(code("").unwrap(), 0x01_01_02_03);
+        assert_eq!(code("offset = 100000").unwrap(), 0x01_01_02_03 + 100_000);
+        assert_eq!(
+            code("major_bits = 4\nminor_bits = 10\npatch_bits = 10").unwrap(),
+            1 << 24 | 1 << 20 | 2 << 10 | 3
+        );
+        assert_eq!(code("scheme = \"date\"").unwrap(), 24_05_01_00);
+        assert_eq!(
+            code("scheme = \"date\"\nbuild = 7\noffset = 1000000000").unwrap(),
+            1_024_050_107
+        );
+        assert_eq!(code("scheme = \"explicit\"\ncode = 42").unwrap(), 42);
+        assert!(matches!(
+            code("scheme = \"explicit\""),
+            Err(Error::ExplicitVersionCodeMissing)
+        ));
+        assert!(matches!(
+            config("patch_bits = 1").code("1.2.3", TIMESTAMP),
+            Err(Error::Ndk(NdkError::VersionCodeComponentTooLarge {
+                component: "patch",
+                value: 3,
+                max: 1
+            }))
+        ));
+    }
+}
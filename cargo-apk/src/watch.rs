@@ -0,0 +1,140 @@
+//! Debounced watching of directories on the host, the reusable part of the live reloads
+//! of `cargo apk run`, like `--watch-assets`.
+
+use crate::error::Error;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::time::Duration;
+
+/// Watches directories for as long as it lives, dropping it ends [`Changes::next()`].
+pub(crate) struct Watcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// The changes seen by a [`Watcher`], which can be moved to the thread handling them.
+pub(crate) struct Changes {
+    events: Receiver<notify::Result<Event>>,
+    debounce: Duration,
+}
+
+/// Starts watching `dirs` recursively. Editors and build tools write a file in several
+/// steps and often several files at once, so changes are reported once no further change
+/// followed within `debounce`.
+pub(crate) fn watch(dirs: &[PathBuf], debounce: Duration) -> Result<(Watcher, Changes), Error> {
+    watch_with(dirs, &[], debounce)
+}
+
+/// Like [`watch()`], additionally watching the entries of `flat_dirs` but not their
+/// subdirectories, e.g. for the `Cargo.toml` next to a `target/` directory.
+pub(crate) fn watch_with(
+    dirs: &[PathBuf],
+    flat_dirs: &[PathBuf],
+    debounce: Duration,
+) -> Result<(Watcher, Changes), Error> {
+    let (sender, events) = channel();
+    let first = dirs.iter().chain(flat_dirs).next().unwrap();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|source| Error::Watch {
+        path: first.clone(),
+        source,
+    })?;
+    let modes = dirs
+        .iter()
+        .map(|dir| (dir, RecursiveMode::Recursive))
+        .chain(
+            flat_dirs
+                .iter()
+                .map(|dir| (dir, RecursiveMode::NonRecursive)),
+        );
+    for (dir, mode) in modes {
+        watcher.watch(dir, mode).map_err(|source| Error::Watch {
+            path: dir.clone(),
+            source,
+        })?;
+    }
+    Ok((Watcher { _watcher: watcher }, Changes { events, debounce }))
+}
+
+impl Changes {
+    /// Blocks until paths were created or modified, and returns them sorted and without
+    /// duplicates once the changes settled. Returns [`None`] once the [`Watcher`] was
+    /// dropped.
+    pub(crate) fn next(&self) -> Option<Vec<PathBuf>> {
+        let mut paths = BTreeSet::new();
+        while paths.is_empty() {
+            self.collect(self.events.recv().ok()?, &mut paths);
+        }
+        loop {
+            match self.events.recv_timeout(self.debounce) {
+                Ok(event) => self.collect(event, &mut paths),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {
+                    return Some(paths.into_iter().collect());
+                }
+            }
+        }
+    }
+
+    fn collect(&self, event: notify::Result<Event>, paths: &mut BTreeSet<PathBuf>) {
+        match event {
+            Ok(event) => {
+                if matches!(
+                    event.kind,
+                    EventKind::Any | EventKind::Create(_) | EventKind::Modify(_)
+                ) {
+                    paths.extend(event.paths);
+                }
+            }
+            // E.g. the kernel's queue overflowed, later changes are still seen
+            Err(e) => eprintln!("Warning: failed to watch for changes: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind};
+    use std::path::Path;
+
+    fn event(kind: EventKind, path: &str) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(PathBuf::from(path)))
+    }
+
+    #[test]
+    fn debounce() {
+        let (sender, events) = channel();
+        let changes = Changes {
+            events,
+            debounce: Duration::from_millis(50),
+        };
+        let modify = EventKind::Modify(ModifyKind::Any);
+        sender
+            .send(event(EventKind::Create(CreateKind::File), "b.json"))
+            .unwrap();
+        sender.send(event(modify, "a.png")).unwrap();
+        sender.send(event(modify, "b.json")).unwrap();
+        sender
+            .send(event(EventKind::Remove(RemoveKind::File), "c.png"))
+            .unwrap();
+        assert_eq!(
+            changes.next().unwrap(),
+            [Path::new("a.png"), Path::new("b.json")]
+        );
+
+        let settled = std::thread::spawn(move || {
+            // Only access, which doesn't end the wait
+            sender
+                .send(event(EventKind::Access(AccessKind::Any), "a.png"))
+                .unwrap();
+            sender
+                .send(Err(notify::Error::generic("overflow")))
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            sender.send(event(modify, "d.ogg")).unwrap();
+        });
+        assert_eq!(changes.next().unwrap(), [Path::new("d.ogg")]);
+        settled.join().unwrap();
+        assert_eq!(changes.next(), None);
+    }
+}
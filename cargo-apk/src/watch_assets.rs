@@ -0,0 +1,212 @@
+//! `cargo apk run --watch-assets`: pushes the assets that change on the host to the
+//! running app, which reads them from its files instead of the apk while live-tuning.
+
+use crate::error::Error;
+use crate::watch::Changes;
+use ndk_build::apk::Apk;
+use ndk_build::ndk::Ndk;
+use ndk_build::util::{output_error, status};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `[package.metadata.android.watch_assets]`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct WatchAssets {
+    /// Where the changed assets are pushed to
+    pub(crate) storage: AssetStorage,
+    /// Directory within `files/` of the `storage` that mirrors `assets`
+    pub(crate) dir: String,
+    /// Send the broadcast `<package>.ASSETS_CHANGED` to the app after each push
+    pub(crate) broadcast: bool,
+    /// Milliseconds without further changes before the changes are pushed
+    pub(crate) debounce_ms: u64,
+}
+
+impl Default for WatchAssets {
+    fn default() -> Self {
+        Self {
+            storage: AssetStorage::default(),
+            dir: "assets".to_string(),
+            broadcast: false,
+            debounce_ms: 200,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AssetStorage {
+    /// `files/` of the app's internal storage, written through `run-as` and thus only
+    /// for debuggable apps, see [`Context.getFilesDir()`]
+    ///
+    /// [`Context.getFilesDir()`]: https://developer.android.com/reference/android/content/Context#getFilesDir()
+    #[default]
+    Internal,
+    /// `/sdcard/Android/data/<package>/files/`, which `adb` writes without `run-as`,
+    /// see [`Context.getExternalFilesDir()`]
+    ///
+    /// [`Context.getExternalFilesDir()`]: https://developer.android.com/reference/android/content/Context#getExternalFilesDir(java.lang.String)
+    External,
+}
+
+impl WatchAssets {
+    pub(crate) fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    /// The path on the device that the asset at `relative` (with `/` separators) is
+    /// pushed to.
+    fn device_path(&self, apk: &Apk, user: Option<u32>, relative: &str) -> String {
+        let dir = self.dir.trim_matches('/');
+        let files_dir = match self.storage {
+            AssetStorage::Internal => "files".to_string(),
+            AssetStorage::External => apk.external_files_dir(user),
+        };
+        match dir {
+            "" => format!("{}/{}", files_dir, relative),
+            dir => format!("{}/{}/{}", files_dir, dir, relative),
+        }
+    }
+
+    /// Pushes the assets that changed in the batches of `changes` until it ends. Failures
+    /// are reported as warnings, the app keeps running and the next change is pushed
+    /// again.
+    pub(crate) fn sync(
+        &self,
+        ndk: &Ndk,
+        apk: &Apk,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        roots: &[PathBuf],
+        changes: Changes,
+    ) {
+        while let Some(paths) = changes.next() {
+            let assets = changed_assets(roots, &paths);
+            if assets.is_empty() {
+                continue;
+            }
+            match self.push(ndk, apk, device_serial, user, &assets) {
+                Ok(()) => status(
+                    "Pushed",
+                    format_args!(
+                        "{} changed asset{}: {}",
+                        assets.len(),
+                        if assets.len() == 1 { "" } else { "s" },
+                        assets
+                            .iter()
+                            .map(|(_, relative)| relative.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ),
+                Err(e) => eprintln!("Warning: failed to push the changed assets: {}", e),
+            }
+        }
+    }
+
+    fn push(
+        &self,
+        ndk: &Ndk,
+        apk: &Apk,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        assets: &[(PathBuf, String)],
+    ) -> Result<(), Error> {
+        let files = assets
+            .iter()
+            .map(|(host_path, relative)| (host_path.clone(), self.device_path(apk, user, relative)))
+            .collect::<Vec<_>>();
+        match self.storage {
+            AssetStorage::Internal => apk.push_private(device_serial, user, &files)?,
+            AssetStorage::External => {
+                for (host_path, device_path) in &files {
+                    let mut adb = ndk.adb(device_serial)?;
+                    adb.arg("push").arg(host_path).arg(device_path);
+                    output_error(adb, ndk.adb_transfer_timeout())?;
+                }
+            }
+        }
+        if self.broadcast {
+            let action = format!("{}.ASSETS_CHANGED", apk.package());
+            apk.broadcast(device_serial, user, &action)?;
+        }
+        Ok(())
+    }
+}
+
+/// The files among `paths` that are packaged from the asset directories `roots`, with
+/// their path in `assets/`. Later roots are merged over earlier ones, like the assets of
+/// a flavor, so files that a later root replaces are skipped.
+fn changed_assets(roots: &[PathBuf], paths: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    paths
+        .iter()
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let (i, relative) = roots
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, root)| Some((i, path.strip_prefix(root).ok()?)))?;
+            if roots[i + 1..]
+                .iter()
+                .any(|root| root.join(relative).exists())
+            {
+                return None;
+            }
+            Some((path.clone(), slash_path(relative)))
+        })
+        .collect()
+}
+
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn changed_assets_of_flavor() {
+        let dir = TestDir::new("watch-assets");
+        let base = dir.join("assets");
+        let flavor = dir.join("assets-demo");
+        for path in [
+            base.join("levels/1.json"),
+            base.join("music.ogg"),
+            flavor.join("levels/1.json"),
+            flavor.join("demo.png"),
+        ] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let roots = [base.clone(), flavor.clone()];
+        let changed = changed_assets(
+            &roots,
+            &[
+                base.join("levels/1.json"),
+                base.join("levels"),
+                base.join("music.ogg"),
+                flavor.join("levels/1.json"),
+                flavor.join("demo.png"),
+                // Removed
+                base.join("gone.png"),
+                dir.join("Cargo.toml"),
+            ],
+        );
+        assert_eq!(
+            changed,
+            [
+                (base.join("music.ogg"), "music.ogg".to_string()),
+                (flavor.join("levels/1.json"), "levels/1.json".to_string()),
+                (flavor.join("demo.png"), "demo.png".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,18 @@
+use android_activity::{AndroidApp, MainEvent, PollEvent};
+
+#[no_mangle]
+fn android_main(app: AndroidApp) {
+    android_logger::init_once(
+        android_logger::Config::default().with_max_level(log::LevelFilter::Info),
+    );
+    log::info!("Hello from {{name}}!");
+
+    let mut quit = false;
+    while !quit {
+        app.poll_events(None, |event| {
+            if let PollEvent::Main(MainEvent::Destroy) = event {
+                quit = true;
+            }
+        });
+    }
+}
@@ -0,0 +1,7 @@
+#[cfg_attr(
+    target_os = "android",
+    ndk_glue::main(backtrace = "on", logger(level = "info", tag = "{{name}}"))
+)]
+pub fn main() {
+    log::info!("Hello from {{name}}!");
+}
@@ -0,0 +1 @@
+// Selected with `--example shadow-mapping`
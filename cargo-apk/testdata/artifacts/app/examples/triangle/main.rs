@@ -0,0 +1 @@
+// A multi-file example, selected with `--example triangle`
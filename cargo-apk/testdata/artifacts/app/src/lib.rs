@@ -0,0 +1 @@
+// Packaged as the app by default
@@ -0,0 +1,326 @@
+//! Condenses the output of a failed `aapt package` into its errors and warnings, tracing
+//! the attributes of the generated `AndroidManifest.xml` back to the keys of
+//! `[package.metadata.android]` that set them.
+
+use crate::error::NdkError;
+use crate::manifest::{AndroidManifest, Provenance};
+use crate::util::{Verbosity, paint, verbosity};
+use std::fmt::{self, Display};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Context of the previous diagnostic, like where a duplicate was first defined
+    Note,
+}
+
+/// One message of `aapt`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// As printed by `aapt`, like `AndroidManifest.xml` or `/app/res/values/strings.xml`
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub message: String,
+    /// The key of `[package.metadata.android]` that configures the offending attribute
+    pub field: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => paint("error", "31"),
+            Severity::Warning => paint("warning", "33"),
+            Severity::Note => paint("note", "36"),
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        if let Some(file) = &self.file {
+            write!(f, "\n  --> {}", file)?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+            }
+        }
+        if let Some(field) = &self.field {
+            write!(
+                f,
+                "\n   = help: the attribute is `{}` in `[package.metadata.android]`",
+                field
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the diagnostics of the `aapt package` that failed with `error`, returning an
+/// error that refers to them instead of the raw output. The raw output is kept at
+/// [`Verbosity::VeryVerbose`], or if it contains nothing recognizable.
+pub fn explain(error: NdkError, manifest: &AndroidManifest) -> NdkError {
+    let NdkError::CmdFailed {
+        status: Some(_),
+        error: output,
+        ..
+    } = &error
+    else {
+        return error;
+    };
+    let diagnostics = parse(&output.to_string(), &manifest.provenance());
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    if errors == 0 {
+        return error;
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if verbosity() >= Verbosity::VeryVerbose {
+        error
+    } else {
+        NdkError::AaptFailed(errors)
+    }
+}
+
+/// Reads the diagnostics from the output of `aapt`, looking up the attributes that
+/// messages about `AndroidManifest.xml` mention in `provenance`.
+pub fn parse(output: &str, provenance: &[Provenance]) -> Vec<Diagnostic> {
+    output
+        .lines()
+        .filter_map(|line| parse_line(line.trim()))
+        .map(|mut diagnostic| {
+            let in_manifest = diagnostic
+                .file
+                .as_ref()
+                .is_none_or(|file| file.ends_with("AndroidManifest.xml"));
+            if in_manifest {
+                diagnostic.field = field(&diagnostic.message, provenance);
+            }
+            diagnostic
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    // `ERROR getting 'android:name' attribute: attribute is not a string value`
+    if let Some(message) = line
+        .strip_prefix("ERROR: ")
+        .or_else(|| line.strip_prefix("ERROR ").map(|_| line))
+    {
+        return Some(Diagnostic {
+            severity: Severity::Error,
+            file: None,
+            line: None,
+            message: message.to_string(),
+            field: None,
+        });
+    }
+    let (file, line_number, message) = split_location(line)?;
+    let (severity, message) = if let Some(message) = message.strip_prefix("error: ") {
+        (Severity::Error, message)
+    } else if let Some(message) = message.strip_prefix("warning: ") {
+        (Severity::Warning, message)
+    } else if message.starts_with("Originally defined here") {
+        (Severity::Note, message)
+    } else {
+        // Like `Tag <uses-permission> attribute name has invalid character ' '.`
+        (Severity::Error, message)
+    };
+    Some(Diagnostic {
+        severity,
+        file: Some(file.to_string()),
+        line: Some(line_number),
+        message: message
+            .strip_prefix("Error: ")
+            .unwrap_or(message)
+            .to_string(),
+        field: None,
+    })
+}
+
+/// Splits `<file>:<line>: <message>`, where `<file>` may contain `:` itself on Windows.
+fn split_location(line: &str) -> Option<(&str, u32, &str)> {
+    let mut start = 0;
+    while let Some(colon) = line[start..].find(':').map(|i| start + i) {
+        let rest = &line[colon + 1..];
+        if let Some((number, message)) = rest.split_once(": ") {
+            if let Ok(number) = number.parse() {
+                return Some((&line[..colon], number, message));
+            }
+        }
+        start = colon + 1;
+    }
+    None
+}
+
+/// The TOML key of the attribute that `message` complains about.
+fn field(message: &str, provenance: &[Provenance]) -> Option<String> {
+    let local_name = |attribute: &str| attribute.rsplit(':').next().unwrap().to_string();
+    let candidates = if let Some((attribute, value)) = quoted_attribute_value(message) {
+        // `... (at 'screenOrientation' with value 'sideways').`
+        provenance
+            .iter()
+            .filter(|p| local_name(&p.attribute) == attribute && p.value == value)
+            .collect::<Vec<_>>()
+    } else if let Some((tag, attribute)) = tag_attribute(message) {
+        // `Tag <uses-permission> attribute name has invalid character ' '.`
+        provenance
+            .iter()
+            .filter(|p| p.element == tag && local_name(&p.attribute) == attribute)
+            .collect()
+    } else if let Some(attribute) = between(message, "getting '", "' attribute") {
+        // `ERROR getting 'android:name' attribute: attribute is not a string value`
+        provenance
+            .iter()
+            .filter(|p| p.attribute == attribute)
+            .collect()
+    } else {
+        vec![]
+    };
+    // Only when the message is not ambiguous
+    match candidates[..] {
+        [provenance] => Some(provenance.field.clone()),
+        [first, ..] if candidates.iter().all(|p| p.field == first.field) => {
+            Some(first.field.clone())
+        }
+        _ => None,
+    }
+}
+
+fn quoted_attribute_value(message: &str) -> Option<(&str, &str)> {
+    let at = between(message, "(at '", "')")?;
+    at.split_once("' with value '")
+}
+
+fn tag_attribute(message: &str) -> Option<(&str, &str)> {
+    let tag = between(message, "Tag <", ">")?;
+    let attribute = between(message, "> attribute ", " ")?;
+    Some((tag, attribute))
+}
+
+/// The text of `s` between the first `start` and the next `end`.
+fn between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let s = &s[s.find(start)? + start.len()..];
+    Some(&s[..s.find(end)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::UsesPermission;
+
+    fn output(name: &str) -> String {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/aapt")
+            .join(name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn provenance() {
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "com.example".to_string();
+        manifest.application.activity.orientation = Some("sideways".to_string());
+        let provenance = manifest.provenance();
+        let find = |attribute: &str| {
+            provenance
+                .iter()
+                .find(|p| p.attribute == attribute)
+                .map(|p| (p.element.as_str(), p.value.as_str(), p.field.as_str()))
+        };
+        assert_eq!(
+            find("package"),
+            Some(("manifest", "com.example", "package"))
+        );
+        assert_eq!(
+            find("android:minSdkVersion"),
+            Some(("uses-sdk", "23", "sdk.min_sdk_version"))
+        );
+        assert_eq!(
+            find("android:screenOrientation"),
+            Some(("activity", "sideways", "application.activity.orientation"))
+        );
+        assert_eq!(
+            find("android:hasCode"),
+            Some(("application", "false", "application.has_code"))
+        );
+        assert_eq!(find("xmlns:android"), None);
+    }
+
+    #[test]
+    fn invalid_attribute_value() {
+        let mut manifest = AndroidManifest::default();
+        manifest.application.activity.orientation = Some("sideways".to_string());
+        let diagnostics = parse(&output("orientation.txt"), &manifest.provenance());
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                severity: Severity::Error,
+                file: Some("AndroidManifest.xml".to_string()),
+                line: Some(1),
+                message: "String types not allowed (at 'screenOrientation' with value \
+                    'sideways')."
+                    .to_string(),
+                field: Some("application.activity.orientation".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_permission_name() {
+        let mut manifest = AndroidManifest::default();
+        for name in ["android.permission.INTERNET", "android.permission.CAMERA "] {
+            manifest.uses_permission.push(UsesPermission {
+                name: name.to_string(),
+                max_sdk_version: None,
+            });
+        }
+        let diagnostics = parse(&output("permission_name.txt"), &manifest.provenance());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "Tag <uses-permission> attribute name has invalid character ' '."
+        );
+        // Either of the permissions could be meant
+        assert_eq!(diagnostics[0].field, None);
+
+        manifest.uses_permission.remove(0);
+        let diagnostics = parse(&output("permission_name.txt"), &manifest.provenance());
+        assert_eq!(
+            diagnostics[0].field.as_deref(),
+            Some("uses_permission[0].name")
+        );
+    }
+
+    #[test]
+    fn duplicate_resources() {
+        let diagnostics = parse(&output("duplicate_resource.txt"), &[]);
+        let summary = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.file.as_deref().unwrap(), d.line.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (Severity::Error, "/app/res/values/strings.xml", 4),
+                (Severity::Note, "/app/res/values/strings.xml", 3),
+                (Severity::Warning, "/app/res/values/colors.xml", 2),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "Resource entry app_name is already defined."
+        );
+        assert!(diagnostics.iter().all(|d| d.field.is_none()));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "error: Resource entry app_name is already defined.\n  \
+            --> /app/res/values/strings.xml:4"
+        );
+        assert_eq!(
+            split_location(r"C:\app\res\values\strings.xml:4: error: x"),
+            Some((r"C:\app\res\values\strings.xml", 4, "error: x"))
+        );
+    }
+}
@@ -1,14 +1,27 @@
+use crate::aapt;
+use crate::checksums::LibChecksums;
 use crate::error::NdkError;
+use crate::installed::InstalledApp;
 use crate::manifest::AndroidManifest;
-use crate::ndk::{Key, Ndk};
+use crate::native::{self, PackagingBackend};
+use crate::ndk::{Key, KeyRotation, Ndk};
+use crate::readelf::has_symbols;
+use crate::reverse::{self, ReverseAction, ReversePort};
 use crate::target::Target;
-use crate::util::output_error;
-use std::collections::HashMap;
+use crate::util::{
+    SecretArg, TempPath, TrackedChild, echo_command, format_size, output_error, output_timeout,
+    status,
+};
 use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 use std::fs;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 /// The options for how to treat debug symbols that are present in any `.so`
 /// files that are added to the APK.
@@ -35,6 +48,45 @@ impl Default for StripConfig {
     }
 }
 
+impl StripConfig {
+    /// The [`StripMode`] that this setting stands for: `strip` and `split` remove the
+    /// debug info.
+    pub fn mode(self) -> StripMode {
+        match self {
+            Self::Default => StripMode::None,
+            Self::Strip | Self::Split => StripMode::Debuginfo,
+        }
+    }
+}
+
+/// How much of every `.so` file is stripped before it is copied into the APK.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StripMode {
+    /// Copies the libraries as they are, e.g. for debugging with `lldb`
+    #[default]
+    None,
+    /// Removes the debug info but keeps the symbol table, e.g. for profiling
+    Debuginfo,
+    /// Also removes the symbols that relocations don't need
+    Symbols,
+    /// Removes all symbols and sections that aren't needed at runtime
+    All,
+}
+
+impl StripMode {
+    /// The `objcopy` (or `llvm-strip`) flag stripping this much, [`None`] for
+    /// [`StripMode::None`].
+    pub fn objcopy_flag(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Debuginfo => Some("--strip-debug"),
+            Self::Symbols => Some("--strip-unneeded"),
+            Self::All => Some("--strip-all"),
+        }
+    }
+}
+
 pub struct ApkConfig {
     pub ndk: Ndk,
     pub build_dir: PathBuf,
@@ -43,38 +95,113 @@ pub struct ApkConfig {
     pub resources: Option<PathBuf>,
     pub manifest: AndroidManifest,
     pub disable_aapt_compression: bool,
-    pub strip: StripConfig,
-    pub reverse_port_forward: HashMap<String, String>,
+    pub strip: StripMode,
+    /// Write the debug info of stripped libraries to `.dwarf` files next to them, linked
+    /// through `.gnu_debuglink`, as [`StripConfig::Split`] does
+    pub split_debuginfo: bool,
+    /// Maps device ports to the host ports that `adb reverse` forwards them to
+    pub reverse_port_forward: HashMap<String, ReversePort>,
+    /// Keep what previous builds left in `build_dir/lib/` and `build_dir/assets/`,
+    /// which is otherwise pruned to the files added to this apk
+    pub keep_stale: bool,
+    /// Package even when the manifest refers to resources that are not in `resources`
+    pub skip_resource_validation: bool,
+    /// Build the configuration split of this name instead of the base apk, see
+    /// [`ApkConfig::config_split()`]
+    pub split: Option<String>,
+    /// Appended to `adb install`, like `-d` to allow downgrading the `versionCode`, see
+    /// [`check_install_args()`]
+    pub install_args: Vec<String>,
 }
 
 impl ApkConfig {
     fn build_tool(&self, tool: &'static str) -> Result<Command, NdkError> {
         let mut cmd = self.ndk.build_tool(tool)?;
-        cmd.current_dir(&self.build_dir);
+        cmd.current_dir(dunce::simplified(&self.build_dir));
         Ok(cmd)
     }
 
+    // Paths passed to the build tools are stripped from `\\?\` verbatim prefixes,
+    // which `aapt` and `zipalign` don't understand
     fn unaligned_apk(&self) -> PathBuf {
-        self.build_dir
-            .join(format!("{}-unaligned.apk", self.apk_name))
+        dunce::simplified(&self.build_dir).join(format!("{}-unaligned.apk", self.apk_name))
+    }
+
+    /// The configuration split `name` of this apk, which only packages `assets` into
+    /// `build_dir/splits/<name>/<apk_name>-<name>.apk`.
+    pub fn config_split(&self, name: &str, assets: PathBuf) -> Self {
+        Self {
+            ndk: self.ndk.clone(),
+            build_dir: self.build_dir.join("splits").join(name),
+            apk_name: format!("{}-{}", self.apk_name, name),
+            assets: Some(assets),
+            resources: None,
+            manifest: self.manifest.clone(),
+            disable_aapt_compression: self.disable_aapt_compression,
+            strip: self.strip,
+            split_debuginfo: self.split_debuginfo,
+            reverse_port_forward: HashMap::new(),
+            keep_stale: self.keep_stale,
+            skip_resource_validation: true,
+            split: Some(name.to_string()),
+            install_args: self.install_args.clone(),
+        }
     }
 
     /// Retrieves the path of the APK that will be written when [`UnsignedApk::sign`]
     /// is invoked
     #[inline]
     pub fn apk(&self) -> PathBuf {
-        self.build_dir.join(format!("{}.apk", self.apk_name))
+        dunce::simplified(&self.build_dir).join(format!("{}.apk", self.apk_name))
+    }
+
+    /// Retrieves the path of the `native-debug-symbols.zip` that will be written next
+    /// to the APK when [`UnalignedApk::write_native_debug_symbols`] is invoked
+    #[inline]
+    pub fn native_debug_symbols(&self) -> PathBuf {
+        dunce::simplified(&self.build_dir)
+            .join(format!("{}-native-debug-symbols.zip", self.apk_name))
     }
 
     pub fn create_apk(&self) -> Result<UnalignedApk, NdkError> {
-        std::fs::create_dir_all(&self.build_dir)?;
-        self.manifest.write_to(&self.build_dir)?;
+        let exec = self.ndk.exec();
+        exec.create_dir_all(&self.build_dir)?;
+        let previous_size = std::fs::metadata(self.apk()).ok().map(|m| m.len());
+        exec.write(
+            &self.build_dir.join("AndroidManifest.xml"),
+            self.manifest_xml()?,
+        )?;
+        // Half-written APKs are removed when the build is interrupted, which a dry run
+        // must not do to the APKs of previous builds
+        let temp_paths = if exec.is_dry_run() {
+            vec![]
+        } else {
+            vec![
+                TempPath::new(self.unaligned_apk()),
+                TempPath::new(self.apk()),
+            ]
+        };
 
         let target_sdk_version = self
             .manifest
             .sdk
             .target_sdk_version
             .unwrap_or_else(|| self.ndk.default_target_platform());
+        if !self.skip_resource_validation {
+            validate_resource_references(&self.manifest, self.resources.as_deref())?;
+        }
+        let unaligned = |temp_paths| UnalignedApk {
+            config: self,
+            pending_libs: HashSet::default(),
+            libs: BTreeMap::new(),
+            temp_paths,
+            previous_size,
+        };
+        if self.ndk.packaging_backend() == PackagingBackend::Native {
+            // Everything is zipped at once by `add_pending_libs_and_align()`
+            native::check_supported(self)?;
+            return Ok(unaligned(temp_paths));
+        }
         let mut aapt = self.build_tool(bin!("aapt"))?;
         aapt.arg("package")
             .arg("-f")
@@ -83,32 +210,64 @@ impl ApkConfig {
             .arg("-M")
             .arg("AndroidManifest.xml")
             .arg("-I")
-            .arg(self.ndk.android_jar(target_sdk_version)?);
+            .arg(self.ndk.compile_android_jar(target_sdk_version)?);
 
         if self.disable_aapt_compression {
             aapt.arg("-0").arg("");
         }
 
         if let Some(res) = &self.resources {
-            aapt.arg("-S").arg(res);
+            aapt.arg("-S").arg(dunce::simplified(res));
         }
 
         if let Some(assets) = &self.assets {
-            aapt.arg("-A").arg(assets);
+            aapt.arg("-A").arg(dunce::simplified(assets));
         }
 
-        output_error(aapt)?;
+        exec.output_error(aapt, None)
+            .map_err(|e| aapt::explain(e, &self.manifest))?;
 
-        Ok(UnalignedApk {
-            config: self,
-            pending_libs: HashSet::default(),
-        })
+        Ok(unaligned(temp_paths))
+    }
+
+    /// The `AndroidManifest.xml` of this apk, or of its configuration split.
+    fn manifest_xml(&self) -> Result<String, NdkError> {
+        match &self.split {
+            Some(name) => self.manifest.config_split(name).to_xml(),
+            None => self.manifest.to_xml(),
+        }
     }
 }
 
+/// Where a library in `lib/<abi>/` of the apk was picked up from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibOrigin {
+    /// Built by cargo, or found in the library search paths of the build
+    Deps,
+    /// The `runtime_libs` directory of the manifest
+    RuntimeLibs,
+    /// Shipped with the NDK, like `libc++_shared.so`
+    NdkSysroot,
+}
+
+/// A library added to the apk, before it was stripped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackagedLib {
+    pub source: PathBuf,
+    pub target: Target,
+    pub origin: LibOrigin,
+}
+
 pub struct UnalignedApk<'a> {
     config: &'a ApkConfig,
     pending_libs: HashSet<String>,
+    /// Maps the `<abi>/<lib>.so` of every library added to the apk to where it was
+    /// copied or stripped from
+    libs: BTreeMap<String, PackagedLib>,
+    temp_paths: Vec<TempPath>,
+    /// Size of the apk of the previous build, to report the difference
+    previous_size: Option<u64>,
 }
 
 impl<'a> UnalignedApk<'a> {
@@ -116,45 +275,73 @@ impl<'a> UnalignedApk<'a> {
         self.config
     }
 
-    pub fn add_lib(&mut self, path: &Path, target: Target) -> Result<(), NdkError> {
-        if !path.exists() {
+    /// The libraries added so far, before they were stripped, with their target.
+    pub fn libs(&self) -> impl Iterator<Item = (&Path, Target)> {
+        self.libs
+            .values()
+            .map(|lib| (lib.source.as_path(), lib.target))
+    }
+
+    /// The libraries added so far, keyed by their `<abi>/<lib>.so` in `lib/`.
+    pub fn packaged_libs(&self) -> &BTreeMap<String, PackagedLib> {
+        &self.libs
+    }
+
+    pub fn add_lib(
+        &mut self,
+        path: &Path,
+        target: Target,
+        origin: LibOrigin,
+    ) -> Result<(), NdkError> {
+        let exec = self.config.ndk.exec();
+        // A dry run does not build the libraries
+        if !path.exists() && !exec.is_dry_run() {
             return Err(NdkError::PathNotFound(path.into()));
         }
         let abi = target.android_abi();
-        let lib_path = Path::new("lib").join(abi).join(path.file_name().unwrap());
+        let file_name = path.file_name().unwrap();
+        let lib_path = Path::new("lib").join(abi).join(file_name);
+        self.libs.insert(
+            format!("{}/{}", abi, file_name.to_string_lossy()),
+            PackagedLib {
+                source: path.to_owned(),
+                target,
+                origin,
+            },
+        );
         let out = self.config.build_dir.join(&lib_path);
-        std::fs::create_dir_all(out.parent().unwrap())?;
+        exec.create_dir_all(out.parent().unwrap())?;
 
-        match self.config.strip {
-            StripConfig::Default => {
-                std::fs::copy(path, out)?;
+        match self.config.strip.objcopy_flag() {
+            None => {
+                exec.copy(path, &out)?;
             }
-            StripConfig::Strip | StripConfig::Split => {
+            Some(strip) => {
                 let obj_copy = self.config.ndk.toolchain_bin("objcopy", target)?;
 
                 {
-                    let mut cmd = Command::new(&obj_copy);
-                    cmd.arg("--strip-debug");
-                    cmd.arg(path);
+                    let mut cmd = self.config.ndk.command(&obj_copy);
+                    cmd.arg(strip);
+                    cmd.arg(dunce::simplified(path));
                     cmd.arg(&out);
-                    output_error(cmd)?;
+                    exec.output_error(cmd, None)?;
                 }
 
-                if self.config.strip == StripConfig::Split {
+                if self.config.split_debuginfo {
                     let dwarf_path = out.with_extension("dwarf");
 
                     {
-                        let mut cmd = Command::new(&obj_copy);
+                        let mut cmd = self.config.ndk.command(&obj_copy);
                         cmd.arg("--only-keep-debug");
-                        cmd.arg(path);
+                        cmd.arg(dunce::simplified(path));
                         cmd.arg(&dwarf_path);
-                        output_error(cmd)?;
+                        exec.output_error(cmd, None)?;
                     }
 
-                    let mut cmd = Command::new(obj_copy);
+                    let mut cmd = self.config.ndk.command(obj_copy);
                     cmd.arg(format!("--add-gnu-debuglink={}", dwarf_path.display()));
                     cmd.arg(out);
-                    output_error(cmd)?;
+                    exec.output_error(cmd, None)?;
                 }
             }
         }
@@ -169,24 +356,178 @@ impl<'a> UnalignedApk<'a> {
         Ok(())
     }
 
+    /// Adds the libraries in `path/<abi>/` and their dependencies, after verifying them
+    /// against `checksums`.
     pub fn add_runtime_libs(
         &mut self,
         path: &Path,
         target: Target,
         search_paths: &[&Path],
+        checksums: &LibChecksums,
     ) -> Result<(), NdkError> {
         let abi_dir = path.join(target.android_abi());
+        let mut libs = vec![];
         for entry in fs::read_dir(&abi_dir).map_err(|e| NdkError::IoPathError(abi_dir, e))? {
             let entry = entry?;
             let path = entry.path();
             if path.extension() == Some(OsStr::new("so")) {
-                self.add_lib_recursively(&path, target, search_paths)?;
+                libs.push(path);
             }
         }
+        libs.sort();
+        for lib in &libs {
+            let file = format!(
+                "{}/{}",
+                target.android_abi(),
+                lib.file_name().unwrap().to_string_lossy()
+            );
+            checksums.verify(&file, lib)?;
+        }
+        self.add_libs_recursively(&libs, target, search_paths, LibOrigin::RuntimeLibs)
+    }
+
+    /// Adds a [`wrap.sh`](https://developer.android.com/ndk/guides/wrap-script) to
+    /// `lib/<abi>/`, which Android only runs for debuggable apps.
+    pub fn add_wrap_sh(&mut self, target: Target, contents: &str) -> Result<(), NdkError> {
+        let exec = self.config.ndk.exec();
+        let lib_path = Path::new("lib").join(target.android_abi()).join("wrap.sh");
+        let out = self.config.build_dir.join(&lib_path);
+        exec.create_dir_all(out.parent().unwrap())?;
+        exec.write(&out, contents)?;
+        #[cfg(unix)]
+        if !exec.is_dry_run() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&out, std::fs::Permissions::from_mode(0o755))?;
+        }
+        self.pending_libs
+            .insert(lib_path.to_str().unwrap().replace('\\', "/"));
+        Ok(())
+    }
+
+    /// Adds a [baseline profile](https://developer.android.com/topic/performance/baselineprofiles/overview)
+    /// to `assets/dexopt/baseline.prof`, along with the `baseline.profm` metadata next to
+    /// `prof` if there is one, which Android 12 and later require.
+    pub fn add_baseline_profile(&mut self, prof: &Path) -> Result<(), NdkError> {
+        if !prof.exists() {
+            return Err(NdkError::PathNotFound(prof.into()));
+        }
+        let mut files = vec![(prof.to_owned(), "baseline.prof")];
+        let profm = prof.with_extension("profm");
+        if profm.exists() {
+            files.push((profm, "baseline.profm"));
+        }
+        let exec = self.config.ndk.exec();
+        let dexopt = Path::new("assets").join("dexopt");
+        exec.create_dir_all(&self.config.build_dir.join(&dexopt))?;
+        for (source, name) in files {
+            let asset_path = dexopt.join(name);
+            let out = self.config.build_dir.join(&asset_path);
+            exec.copy(&source, &out)?;
+            self.pending_libs
+                .insert(asset_path.to_str().unwrap().replace('\\', "/"));
+        }
         Ok(())
     }
 
+    /// Adds a generated file to `assets/<asset_path>`, next to the assets of
+    /// [`ApkConfig::assets`], which must not contain the same path.
+    pub fn add_asset(&mut self, asset_path: &str, contents: &[u8]) -> Result<(), NdkError> {
+        let exec = self.config.ndk.exec();
+        let asset_path = Path::new("assets").join(asset_path);
+        let out = self.config.build_dir.join(&asset_path);
+        exec.create_dir_all(out.parent().unwrap())?;
+        exec.write(&out, contents)?;
+        self.pending_libs
+            .insert(asset_path.to_str().unwrap().replace('\\', "/"));
+        Ok(())
+    }
+
+    /// Zips the unstripped libraries added so far into
+    /// [`ApkConfig::native_debug_symbols()`], laid out as `<abi>/<lib>.so` like the Play
+    /// Console expects, returning the libraries left out because they have no symbols.
+    pub fn write_native_debug_symbols(&self) -> Result<Vec<String>, NdkError> {
+        let exec = self.config.ndk.exec();
+        let zip = self.config.native_debug_symbols();
+        let staging = self.config.build_dir.join("native-debug-symbols");
+        exec.remove(&staging)?;
+        // `aapt add` would add to the zip of the previous build
+        exec.remove(&zip)?;
+
+        let mut entries = Vec::new();
+        let mut missing = Vec::new();
+        for (entry, lib) in &self.libs {
+            let (path, target) = (&lib.source, lib.target);
+            let readelf = self.config.ndk.toolchain_bin("readelf", target)?;
+            // The libraries of a dry run don't exist to be checked
+            if !exec.is_dry_run() && !has_symbols(&readelf, path)? {
+                missing.push(entry.clone());
+                continue;
+            }
+            let out = staging.join(entry);
+            exec.create_dir_all(out.parent().unwrap())?;
+            exec.copy(path, &out)?;
+            entries.push(entry);
+        }
+        if entries.is_empty() {
+            return Ok(missing);
+        }
+
+        if self.config.ndk.packaging_backend() == PackagingBackend::Native {
+            exec.plan("zip", zip.display());
+            if !exec.is_dry_run() {
+                native::write_zip(&zip, &staging, &entries)?;
+            }
+        } else {
+            let mut aapt = self.config.ndk.build_tool(bin!("aapt"))?;
+            aapt.current_dir(&staging)
+                .arg("add")
+                .arg(&zip)
+                .args(&entries);
+            exec.output_error(aapt, None)?;
+        }
+        if exec.is_dry_run() {
+            return Ok(missing);
+        }
+        status(
+            "Bundled",
+            format_args!(
+                "the symbols of {} lib(s) into `{}`",
+                entries.len(),
+                zip.display()
+            ),
+        );
+        Ok(missing)
+    }
+
     pub fn add_pending_libs_and_align(self) -> Result<UnsignedApk<'a>, NdkError> {
+        let exec = self.config.ndk.exec();
+        if !self.config.keep_stale && !exec.is_dry_run() {
+            let (files, bytes) = prune_staging(&self.config.build_dir, &self.pending_libs)?;
+            if files > 0 {
+                status(
+                    "Pruned",
+                    format_args!(
+                        "{} stale file(s) ({}) from previous builds",
+                        files,
+                        format_size(bytes)
+                    ),
+                );
+            }
+        }
+
+        if self.config.ndk.packaging_backend() == PackagingBackend::Native {
+            exec.plan("zip", self.config.apk().display());
+            if !exec.is_dry_run() {
+                let manifest = self.config.manifest_xml()?;
+                native::write_apk(self.config, &manifest, &self.pending_libs)?;
+            }
+            return Ok(UnsignedApk(
+                self.config,
+                self.temp_paths,
+                self.previous_size,
+            ));
+        }
+
         let mut aapt = self.config.build_tool(bin!("aapt"))?;
         aapt.arg("add");
 
@@ -200,7 +541,7 @@ impl<'a> UnalignedApk<'a> {
             aapt.arg(lib_path_unix);
         }
 
-        output_error(aapt)?;
+        exec.output_error(aapt, None)?;
 
         let mut zipalign = self.config.build_tool(bin!("zipalign"))?;
         zipalign
@@ -210,34 +551,253 @@ impl<'a> UnalignedApk<'a> {
             .arg(self.config.unaligned_apk())
             .arg(self.config.apk());
 
-        output_error(zipalign)?;
+        exec.output_error(zipalign, None)?;
+
+        Ok(UnsignedApk(
+            self.config,
+            self.temp_paths,
+            self.previous_size,
+        ))
+    }
+}
+
+/// Checks that the `@string/`, `@mipmap/` and `@drawable/` references of the labels
+/// and icons in `manifest` are defined under `resources`, so that a typo fails here
+/// rather than as an `aapt` error about the generated `AndroidManifest.xml`.
+///
+/// References to other packages like `@android:string/ok` and to other kinds of
+/// resources are passed through unchecked.
+pub fn validate_resource_references(
+    manifest: &AndroidManifest,
+    resources: Option<&Path>,
+) -> Result<(), NdkError> {
+    let application = &manifest.application;
+    let mut fields = vec![
+        ("application.label", Some(&application.label)),
+        ("application.icon", application.icon.as_ref()),
+        ("application.banner", application.banner.as_ref()),
+        (
+            "application.activity.label",
+            application.activity.label.as_ref(),
+        ),
+    ];
+    for alias in &application.activity_alias {
+        fields.push(("application.activity_alias.label", alias.label.as_ref()));
+        fields.push(("application.activity_alias.icon", alias.icon.as_ref()));
+    }
+    for (field, value) in fields {
+        let Some(value) = value else { continue };
+        let Some((kind, name)) = parse_resource_reference(value) else {
+            continue;
+        };
+        if !resources.is_some_and(|res| has_resource(res, kind, name)) {
+            return Err(NdkError::ResourceNotFound {
+                reference: value.clone(),
+                field: field.to_string(),
+                resources: resources.map(Path::to_owned),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The kind and name of a reference like `@string/app_name`, for the kinds that
+/// [`validate_resource_references()`] knows how to look up.
+fn parse_resource_reference(value: &str) -> Option<(&str, &str)> {
+    let (kind, name) = value.strip_prefix('@')?.split_once('/')?;
+    matches!(kind, "string" | "mipmap" | "drawable").then_some((kind, name))
+}
+
+/// Whether `res` defines `name`, as a `<string>` in `values*/*.xml` for strings and
+/// as a file like `mipmap-hdpi/ic_launcher.png` otherwise.
+fn has_resource(res: &Path, kind: &str, name: &str) -> bool {
+    let dir_kind = if kind == "string" { "values" } else { kind };
+    let Ok(dirs) = fs::read_dir(res) else {
+        return false;
+    };
+    for dir in dirs.flatten() {
+        let dir_name = dir.file_name();
+        let dir_name = dir_name.to_string_lossy();
+        if dir_name != dir_kind && !dir_name.starts_with(&format!("{}-", dir_kind)) {
+            continue;
+        }
+        let Ok(files) = fs::read_dir(dir.path()) else {
+            continue;
+        };
+        for file in files.flatten() {
+            let path = file.path();
+            let found = if kind == "string" {
+                path.extension() == Some(OsStr::new("xml"))
+                    && fs::read_to_string(&path).is_ok_and(|xml| defines_string(&xml, name))
+            } else {
+                let file_name = file.file_name();
+                file_name.to_string_lossy().split('.').next() == Some(name)
+            };
+            if found {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Whether `xml` has a `<string name="...">` element for `name`.
+fn defines_string(xml: &str, name: &str) -> bool {
+    let attribute = format!("name=\"{}\"", name);
+    xml.split("<string").skip(1).any(|element| {
+        element.starts_with(char::is_whitespace)
+            && element
+                .split('>')
+                .next()
+                .is_some_and(|attributes| attributes.contains(&attribute))
+    })
+}
 
-        Ok(UnsignedApk(self.config))
+/// Removes the files under `build_dir/lib/` and `build_dir/assets/` that are not in
+/// `keep`, along with directories left empty, returning how many files and bytes
+/// were removed. The `.dwarf` files split from kept libraries stay.
+fn prune_staging(build_dir: &Path, keep: &HashSet<String>) -> Result<(usize, u64), NdkError> {
+    fn prune(dir: &Path, prefix: &str, keep: &HashSet<String>) -> Result<(usize, u64), NdkError> {
+        let mut removed = (0, 0);
+        for entry in fs::read_dir(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                let (files, bytes) = prune(&path, &name, keep)?;
+                removed = (removed.0 + files, removed.1 + bytes);
+                if fs::read_dir(&path)?.next().is_none() {
+                    fs::remove_dir(&path)?;
+                }
+            } else {
+                let lib = name
+                    .strip_suffix(".dwarf")
+                    .map(|stem| format!("{}.so", stem));
+                if !keep.contains(&name) && !lib.is_some_and(|lib| keep.contains(&lib)) {
+                    removed = (removed.0 + 1, removed.1 + entry.metadata()?.len());
+                    fs::remove_file(&path).map_err(|e| NdkError::IoPathError(path, e))?;
+                }
+            }
+        }
+        Ok(removed)
     }
+
+    let mut removed = (0, 0);
+    for dir in ["lib", "assets"] {
+        let path = build_dir.join(dir);
+        if path.is_dir() {
+            let (files, bytes) = prune(&path, dir, keep)?;
+            removed = (removed.0 + files, removed.1 + bytes);
+        }
+    }
+    Ok(removed)
 }
 
-pub struct UnsignedApk<'a>(&'a ApkConfig);
+pub struct UnsignedApk<'a>(&'a ApkConfig, Vec<TempPath>, Option<u64>);
 
 impl UnsignedApk<'_> {
     pub fn sign(self, key: Key) -> Result<Apk, NdkError> {
-        let mut apksigner = self.0.build_tool(bat!("apksigner"))?;
+        self.sign_with(key, None)
+    }
+
+    /// Signs with `key` after the `older_keys` of `rotation`, so that the v1 and v2
+    /// signatures use the oldest key and the v3.1 signature the rotated one.
+    pub fn sign_rotated(self, key: Key, rotation: &KeyRotation) -> Result<Apk, NdkError> {
+        self.sign_with(key, Some(rotation))
+    }
+
+    fn sign_with(self, key: Key, rotation: Option<&KeyRotation>) -> Result<Apk, NdkError> {
+        let Self(config, temp_paths, previous_size) = self;
+        let exec = config.ndk.exec();
+        if config.ndk.packaging_backend() == PackagingBackend::Native {
+            if rotation.is_some() {
+                return Err(NdkError::NativePackagingUnsupported(
+                    "signing with rotated keys".to_string(),
+                ));
+            }
+            exec.plan("sign", config.apk().display());
+            if !exec.is_dry_run() {
+                native::sign(&config.apk(), &key)?;
+            }
+        } else {
+            let mut apksigner = config.build_tool(bat!("apksigner"))?;
+            sign_args(&mut apksigner, &key, rotation, &config.apk());
+            exec.output_error(apksigner, None)
+                .map_err(|error| match rotation {
+                    // Mostly a lineage that doesn't match the keys, which `apksigner` explains
+                    Some(rotation) => NdkError::SignRotatedFailed {
+                        lineage: rotation.lineage.clone(),
+                        output: error.to_string(),
+                    },
+                    None => error,
+                })?;
+        }
+        drop(temp_paths);
+        exec.plan("apk", config.apk().display());
+        if exec.is_dry_run() {
+            return Ok(Apk::from_config(config));
+        }
+        if let (Some(previous), Ok(metadata)) = (previous_size, fs::metadata(config.apk())) {
+            let size = metadata.len();
+            let (sign, difference) = if size >= previous {
+                ("+", size - previous)
+            } else {
+                ("-", previous - size)
+            };
+            status(
+                "Packaged",
+                format_args!(
+                    "`{}` ({}, {}{} compared to the previous build)",
+                    config.apk().display(),
+                    format_size(size),
+                    sign,
+                    format_size(difference)
+                ),
+            );
+        }
+        Ok(Apk::from_config(config))
+    }
+}
+
+fn sign_args(apksigner: &mut Command, key: &Key, rotation: Option<&KeyRotation>, apk: &Path) {
+    apksigner.arg("sign");
+    for older in rotation.iter().flat_map(|rotation| &rotation.older_keys) {
+        signer_args(apksigner, &older.key);
+        if let Some(alias) = &older.alias {
+            apksigner.arg("--ks-key-alias").arg(alias);
+        }
+        apksigner.arg("--next-signer");
+    }
+    signer_args(apksigner, key);
+    if let Some(rotation) = rotation {
         apksigner
-            .arg("sign")
-            .arg("--ks")
-            .arg(&key.path)
-            .arg("--ks-pass")
-            .arg(format!("pass:{}", &key.password))
-            .arg(self.0.apk());
-        output_error(apksigner)?;
-        Ok(Apk::from_config(self.0))
+            .arg("--lineage")
+            .arg(dunce::simplified(&rotation.lineage));
+        if let Some(min_sdk_version) = rotation.rotation_min_sdk_version {
+            apksigner
+                .arg("--rotation-min-sdk-version")
+                .arg(min_sdk_version.to_string());
+        }
     }
+    apksigner.arg(apk);
+}
+
+fn signer_args(apksigner: &mut Command, key: &Key) {
+    apksigner
+        .arg("--ks")
+        .arg(dunce::simplified(&key.path))
+        .arg("--ks-pass")
+        .arg(SecretArg::new(format!("pass:{}", &key.password)));
 }
 
 pub struct Apk {
     path: PathBuf,
     package_name: String,
+    version_name: Option<String>,
+    version_code: Option<u32>,
     ndk: Ndk,
-    reverse_port_forward: HashMap<String, String>,
+    reverse_port_forward: HashMap<String, ReversePort>,
+    install_args: Vec<String>,
 }
 
 impl Apk {
@@ -246,56 +806,249 @@ impl Apk {
         Self {
             path: config.apk(),
             package_name: config.manifest.package.clone(),
+            version_name: config.manifest.version_name.clone(),
+            version_code: config.manifest.version_code,
             ndk,
             reverse_port_forward: config.reverse_port_forward.clone(),
+            install_args: config.install_args.clone(),
         }
     }
 
+    /// Forwards the device ports of `reverse_port_forward` through `adb reverse`,
+    /// skipping those that `adb reverse --list` shows as already forwarded. Ports that
+    /// can't be forwarded are reported as warnings, and as
+    /// [`NdkError::ReversePortForwardFailed`] once all were tried if they are
+    /// [`ReversePort::required()`]. A [remote](Ndk::remote) adb server would forward
+    /// them to its own host, which is refused with [`NdkError::UnsupportedOverRemote`].
     pub fn reverse_port_forwarding(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
-        for (from, to) in &self.reverse_port_forward {
-            println!("Reverse port forwarding from {} to {}", from, to);
+        if self.reverse_port_forward.is_empty() {
+            return Ok(());
+        }
+        self.ndk.require_local_adb("`reverse_port_forward`")?;
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("reverse").arg("--list");
+        let list = self.ndk.exec().output_error(adb, self.ndk.adb_timeout())?;
+        let existing = reverse::parse_list(&String::from_utf8_lossy(&list));
+
+        let mut ports = self.reverse_port_forward.iter().collect::<Vec<_>>();
+        ports.sort_by_key(|(from, _)| *from);
+        let mut failed = vec![];
+        for (from, to) in ports {
+            let to_host = to.host();
+            match reverse::plan(&existing, from, to_host) {
+                ReverseAction::Keep => {
+                    status(
+                        "Fresh",
+                        format_args!(
+                            "device port `{}` is already forwarded to host port `{}`",
+                            from, to_host
+                        ),
+                    );
+                    continue;
+                }
+                ReverseAction::Replace { previous } => status(
+                    "Replacing",
+                    format_args!(
+                        "forwarding of device port `{}` to host port `{}` with host port `{}`",
+                        from, previous, to_host
+                    ),
+                ),
+                ReverseAction::Forward => status(
+                    "Forwarding",
+                    format_args!("device port `{}` to host port `{}`", from, to_host),
+                ),
+            }
             let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("reverse").arg(from).arg(to_host);
+            if let Err(err) = self.ndk.exec().output_error(adb, self.ndk.adb_timeout()) {
+                let message = err.to_string();
+                let holder = match reverse::tcp_port(from) {
+                    Some(port) if message.contains("cannot bind") => {
+                        self.port_holder(device_serial, port)
+                    }
+                    _ => None,
+                };
+                eprintln!(
+                    "Warning: Could not forward device port `{}` to host port `{}`{}: {}",
+                    from,
+                    to_host,
+                    holder
+                        .map(|holder| format!(", it is likely held by {} on the device", holder))
+                        .unwrap_or_default(),
+                    message.trim()
+                );
+                if to.required() {
+                    failed.push(from.clone());
+                }
+            }
+        }
 
-            adb.arg("reverse").arg(from).arg(to);
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(NdkError::ReversePortForwardFailed(failed))
+        }
+    }
+
+    /// The process listening on the TCP `port` of the device, if `ss` shows it.
+    fn port_holder(&self, device_serial: Option<&str>, port: &str) -> Option<String> {
+        let filter = format!("sport = :{}", port);
+        let ss = self
+            .ndk
+            .shell(device_serial, &["ss", "-Hltnp", &filter])
+            .ok()?;
+        reverse::parse_ss_process(&ss)
+    }
 
-            output_error(adb)?;
+    pub fn install(&self, device_serial: Option<&str>, user: Option<u32>) -> Result<(), NdkError> {
+        self.install_with_splits(device_serial, user, &[])
+    }
+
+    /// Installs the apk together with configuration splits built by
+    /// [`ApkConfig::config_split()`] through `adb install-multiple`.
+    pub fn install_with_splits(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        splits: &[&Path],
+    ) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+
+        if splits.is_empty() {
+            adb.arg("install");
+        } else {
+            adb.arg("install-multiple");
         }
+        adb.arg("-r");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.args(&self.install_args).arg(&self.path).args(splits);
+        let command = crate::util::command_line(&adb);
+        let stdout = self
+            .ndk
+            .exec()
+            .output_error(adb, self.ndk.adb_transfer_timeout())
+            .map_err(install_failure)?;
+        check_install_output(command, &String::from_utf8_lossy(&stdout))
+    }
 
+    /// The app with the package name of the apk as installed on the device, [`None`] if
+    /// it is not installed.
+    pub fn installed(&self, device_serial: Option<&str>) -> Result<Option<InstalledApp>, NdkError> {
+        let dumpsys = self
+            .ndk
+            .shell(device_serial, &["dumpsys", "package", &self.package_name])?;
+        Ok(InstalledApp::parse(&dumpsys))
+    }
+
+    /// Compiles the installed app ahead of time through `cmd package compile -m <mode> -f`,
+    /// with a mode such as `speed`, `speed-profile` or `verify`. Requires API 24.
+    pub fn compile(&self, device_serial: Option<&str>, mode: &str) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("cmd")
+            .arg("package")
+            .arg("compile")
+            .arg("-m")
+            .arg(mode)
+            .arg("-f")
+            .arg(&self.package_name);
+        check_shell_output(self.ndk.exec().output_error(adb, self.ndk.adb_timeout())?)?;
         Ok(())
     }
 
-    pub fn install(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+    pub fn uninstall(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+    ) -> Result<(), NdkError> {
         let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("uninstall");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg(&self.package_name);
+        check_shell_output(output_error(adb, self.ndk.adb_timeout())?)?;
+        Ok(())
+    }
 
-        adb.arg("install").arg("-r").arg(&self.path);
-        output_error(adb)?;
+    /// Deletes all data associated with the app through `pm clear`.
+    pub fn clear(&self, device_serial: Option<&str>, user: Option<u32>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("clear");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg(&self.package_name);
+        check_shell_output(output_error(adb, self.ndk.adb_timeout())?)?;
         Ok(())
     }
 
-    pub fn start(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+    /// Launches the app, passing `extras` on its launch intent.
+    pub fn start(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        extras: &LaunchExtras,
+    ) -> Result<(), NdkError> {
         let mut adb = self.ndk.adb(device_serial)?;
-        adb.arg("shell")
-            .arg("am")
-            .arg("start")
-            .arg("-a")
+        adb.arg("shell").arg("am").arg("start");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg("-a")
             .arg("android.intent.action.MAIN")
             .arg("-n")
-            .arg(format!("{}/android.app.NativeActivity", self.package_name));
+            .arg(format!("{}/android.app.NativeActivity", self.package_name))
+            .args(extras.am_args());
 
-        output_error(adb)?;
+        check_shell_output(self.ndk.exec().output_error(adb, self.ndk.adb_timeout())?)?;
 
         Ok(())
     }
 
-    pub fn uidof(&self, device_serial: Option<&str>) -> Result<u32, NdkError> {
+    /// Stops all processes of the app, e.g. before starting it again. An app that isn't
+    /// running is not an error, a failing `am` is.
+    pub fn force_stop(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+    ) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("am").arg("force-stop");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg(&self.package_name);
+        check_shell_output(self.ndk.exec().output_error(adb, self.ndk.adb_timeout())?)?;
+        Ok(())
+    }
+
+    /// [`Apk::force_stop()`]s the app before [`Apk::start()`]ing it, so that an instance
+    /// that is still running doesn't keep the new one from starting.
+    pub fn restart(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        extras: &LaunchExtras,
+    ) -> Result<(), NdkError> {
+        self.force_stop(device_serial, user)?;
+        self.start(device_serial, user, extras)
+    }
+
+    pub fn uidof(&self, device_serial: Option<&str>, user: Option<u32>) -> Result<u32, NdkError> {
         let mut adb = self.ndk.adb(device_serial)?;
         adb.arg("shell")
             .arg("pm")
             .arg("list")
             .arg("package")
-            .arg("-U")
-            .arg(&self.package_name);
-        let output = output_error(adb)?;
+            .arg("-U");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg(&self.package_name);
+        let output = output_error(adb, self.ndk.adb_timeout())?;
         let output = String::from_utf8_lossy(&output);
 
         let (_package, uid) = output
@@ -315,7 +1068,1817 @@ impl Apk {
             .map_err(|e| NdkError::NotAUid(e, uid.to_owned()))
     }
 
+    /// Returns the pid of the running app, or [`None`] if it isn't running.
+    ///
+    /// Tries `pidof -s` first, and falls back to parsing `ps` output on devices
+    /// where `pidof` is missing (API 23 and lower). When `ps` lists multiple
+    /// matching processes, the one whose `/proc/<pid>/cmdline` equals the
+    /// package name is selected.
+    ///
+    /// `pidof` cannot filter by user, so `ps` is always used when a `user` is given.
+    pub fn pid(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+    ) -> Result<Option<u32>, NdkError> {
+        if user.is_none() {
+            if let Some(pid) = self.pidof(device_serial)? {
+                return Ok(Some(pid));
+            }
+        }
+
+        // `ps -A` is required since Android O to list all processes, but older
+        // `toolbox` implementations interpret it as a name filter and print nothing.
+        let mut pids = self.ps_pids(device_serial, user, &["-A"])?;
+        if pids.is_empty() {
+            pids = self.ps_pids(device_serial, user, &[])?;
+        }
+
+        match pids.as_slice() {
+            [] => Ok(None),
+            [pid] => Ok(Some(*pid)),
+            [first, ..] => {
+                for &pid in &pids {
+                    let mut adb = self.ndk.adb(device_serial)?;
+                    adb.arg("shell")
+                        .arg("cat")
+                        .arg(format!("/proc/{}/cmdline", pid));
+                    let cmdline = output_error(adb, self.ndk.adb_timeout())?;
+                    if cmdline_matches(&cmdline, &self.package_name) {
+                        return Ok(Some(pid));
+                    }
+                }
+                Ok(Some(*first))
+            }
+        }
+    }
+
+    fn pidof(&self, device_serial: Option<&str>) -> Result<Option<u32>, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell")
+            .arg("pidof")
+            .arg("-s")
+            .arg(&self.package_name);
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        // Old `adb shell` versions always exit with `0`, only trust `stdout` if it parses.
+        if output.status.success() {
+            if let Ok(pid) = String::from_utf8_lossy(&output.stdout).trim().parse() {
+                return Ok(Some(pid));
+            }
+        }
+        Ok(None)
+    }
+
+    fn ps_pids(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        args: &[&str],
+    ) -> Result<Vec<u32>, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("ps").args(args);
+        let output = output_error(adb, self.ndk.adb_timeout())?;
+        parse_ps_pids(&String::from_utf8_lossy(&output), &self.package_name, user)
+    }
+
+    /// Copies `device_path`, relative to the app's internal storage (e.g.
+    /// `databases` or `files/save.dat`), to `host_path` on the host.
+    ///
+    /// Files are streamed through `run-as <package>`, which only works for
+    /// debuggable apps. Otherwise `adbd` running as root (`adb root`) or `su` is
+    /// used, and [`NdkError::NotDebuggable`] is returned if neither is available.
+    /// Directories are transferred as a tarball and extracted into `host_path`
+    /// with the host's `tar`.
+    pub fn pull_private(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        device_path: &str,
+        host_path: &Path,
+    ) -> Result<(), NdkError> {
+        let access = self.private_access(device_serial, user)?;
+
+        let mut test = self.private_shell(
+            device_serial,
+            user,
+            access,
+            &format!("test -d {} && echo dir", shell_quote(device_path)),
+        )?;
+        let output = output_timeout(&mut test, self.ndk.adb_timeout())?;
+        let is_dir = String::from_utf8_lossy(&output.stdout).trim() == "dir";
+
+        if is_dir {
+            fs::create_dir_all(host_path)
+                .map_err(|e| NdkError::IoPathError(host_path.to_owned(), e))?;
+            let mut adb = self.private_shell(
+                device_serial,
+                user,
+                access,
+                &format!("tar -cf - -C {} .", shell_quote(device_path)),
+            )?;
+            adb.stdin(Stdio::null()).stdout(Stdio::piped());
+            echo_command(&adb);
+            let mut process = adb.spawn()?;
+
+            let mut tar = Command::new(
+                which::which("tar").map_err(|_| NdkError::CmdNotFound("tar".to_string()))?,
+            );
+            tar.arg("-xf")
+                .arg("-")
+                .arg("-C")
+                .arg(host_path)
+                .stdin(process.stdout.take().unwrap());
+            echo_command(&tar);
+            let tar_output = tar.output()?;
+            let adb_status = process.wait()?;
+            if !adb_status.success() {
+                return Err(NdkError::cmd_failed(
+                    &adb,
+                    Some(adb_status),
+                    std::io::Error::other(""),
+                ));
+            }
+            if !tar_output.status.success() {
+                return Err(NdkError::cmd_failed(
+                    &tar,
+                    Some(tar_output.status),
+                    std::io::Error::other(String::from_utf8_lossy(&tar_output.stderr)),
+                ));
+            }
+        } else {
+            if let Some(parent) = host_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| NdkError::IoPathError(parent.to_owned(), e))?;
+            }
+            let file = fs::File::create(host_path)
+                .map_err(|e| NdkError::IoPathError(host_path.to_owned(), e))?;
+            let mut adb = self.private_shell(
+                device_serial,
+                user,
+                access,
+                &format!("cat {}", shell_quote(device_path)),
+            )?;
+            adb.stdin(Stdio::null()).stdout(file);
+            echo_command(&adb);
+            let output = adb.output()?;
+            if !output.status.success() {
+                let _ = fs::remove_file(host_path);
+                return Err(NdkError::cmd_failed(
+                    &adb,
+                    Some(output.status),
+                    std::io::Error::other(String::from_utf8_lossy(&output.stderr)),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies each host file of `files` to its path relative to the app's internal
+    /// storage (e.g. `files/assets/level1.json`), creating missing directories.
+    ///
+    /// Gains access like [`Apk::pull_private()`], files written through root are
+    /// handed to the app's uid. Each file replaces the previous one at once, so the app
+    /// never reads a partial copy.
+    pub fn push_private(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        files: &[(PathBuf, String)],
+    ) -> Result<(), NdkError> {
+        let access = self.private_access(device_serial, user)?;
+        for (host_path, device_path) in files {
+            let file = fs::File::open(host_path)
+                .map_err(|e| NdkError::IoPathError(host_path.clone(), e))?;
+            let script = push_script(device_path, access);
+            let mut adb = self.private_shell(device_serial, user, access, &script)?;
+            adb.stdin(file).stdout(Stdio::null());
+            echo_command(&adb);
+            let output = adb.output()?;
+            if !output.status.success() {
+                return Err(NdkError::cmd_failed(
+                    &adb,
+                    Some(output.status),
+                    std::io::Error::other(String::from_utf8_lossy(&output.stderr)),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The app-specific directory on external storage that `adb` can write without
+    /// root, `/sdcard/Android/data/<package>/files` for the primary user.
+    pub fn external_files_dir(&self, user: Option<u32>) -> String {
+        format!(
+            "/storage/emulated/{}/Android/data/{}/files",
+            user.unwrap_or(0),
+            self.package_name
+        )
+    }
+
+    /// Sends the broadcast `action` to the app only, which also reaches receivers
+    /// registered at runtime on API 26+ where implicit broadcasts are dropped.
+    pub fn broadcast(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        action: &str,
+    ) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("am").arg("broadcast");
+        if let Some(user) = user {
+            adb.arg("--user").arg(user.to_string());
+        }
+        adb.arg("-a").arg(action).arg("-p").arg(&self.package_name);
+        check_shell_output(self.ndk.exec().output_error(adb, self.ndk.adb_timeout())?)?;
+        Ok(())
+    }
+
+    /// Checks the `crash` and `system` log buffers for a crash or ANR of process `pid`.
+    pub fn crashed(&self, device_serial: Option<&str>, pid: u32) -> Result<bool, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("logcat")
+            .arg("-d")
+            .arg("-b")
+            .arg("crash")
+            .arg("-b")
+            .arg("system");
+        let output = output_error(adb, self.ndk.adb_timeout())?;
+        Ok(has_crash_marker(
+            &String::from_utf8_lossy(&output),
+            &self.package_name,
+            pid,
+        ))
+    }
+
+    /// Starts following the process `pid` of the app, see [`ProcessWatcher`].
+    pub fn watch_process<'a>(
+        &'a self,
+        device_serial: Option<&'a str>,
+        user: Option<u32>,
+        pid: u32,
+    ) -> Result<ProcessWatcher<'a>, NdkError> {
+        let mut logcat = self.ndk.adb(device_serial)?;
+        // `-T 1` skips the events logged before, and makes `logcat` fail on devices
+        // older than API 21 that don't support it
+        logcat
+            .arg("logcat")
+            .arg("-b")
+            .arg("events")
+            .arg("-T")
+            .arg("1")
+            .arg("-s")
+            .arg("am_proc_died")
+            .arg("am_kill")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        echo_command(&logcat);
+        let mut child =
+            TrackedChild::spawn(&mut logcat).map_err(|e| NdkError::cmd_failed(&logcat, None, e))?;
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(ProcessWatcher {
+            apk: self,
+            device_serial,
+            user,
+            pid,
+            logcat: child,
+            lines,
+        })
+    }
+
+    /// Saves the `crash` log buffer into `dir`, and the latest tombstone and ANR trace
+    /// if they were written within `max_age`, e.g. since the app was started.
+    ///
+    /// `/data/tombstones` and `/data/anr` are not readable on most production
+    /// devices; with `bugreport`, `adb bugreport` (which includes recent tombstones)
+    /// is captured instead when tombstones are inaccessible, for at most
+    /// [`BUGREPORT_TIMEOUT`]. Sources that could not be read are listed in
+    /// [`Diagnostics::inaccessible`] rather than failing.
+    pub fn collect_diagnostics(
+        &self,
+        device_serial: Option<&str>,
+        dir: &Path,
+        max_age: Duration,
+        bugreport: bool,
+    ) -> Result<Diagnostics, NdkError> {
+        fs::create_dir_all(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))?;
+        let mut diagnostics = Diagnostics::default();
+
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("logcat").arg("-d").arg("-b").arg("crash");
+        match output_error(adb, self.ndk.adb_timeout()) {
+            Ok(log) => {
+                let path = dir.join("crash.log");
+                fs::write(&path, log).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+                diagnostics.files.push(path);
+            }
+            Err(e) => diagnostics
+                .inaccessible
+                .push(("logcat -b crash".to_string(), e.to_string())),
+        }
+
+        for source in ["/data/tombstones", "/data/anr"] {
+            match self.pull_latest(device_serial, source, dir, max_age)? {
+                Ok(Some(path)) => diagnostics.files.push(path),
+                Ok(None) => {}
+                Err(reason) => diagnostics.inaccessible.push((source.to_string(), reason)),
+            }
+        }
+
+        if bugreport
+            && diagnostics
+                .inaccessible
+                .iter()
+                .any(|(source, _)| source == "/data/tombstones")
+        {
+            status(
+                "Capturing",
+                "`adb bugreport`, the tombstones are inaccessible",
+            );
+            let path = dir.join("bugreport.zip");
+            let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("bugreport").arg(&path);
+            // Takes minutes on most devices
+            match output_error(adb, Some(BUGREPORT_TIMEOUT)) {
+                Ok(_) if path.exists() => diagnostics.files.push(path),
+                Ok(_) => diagnostics.inaccessible.push((
+                    "adb bugreport".to_string(),
+                    "no bugreport was written".to_string(),
+                )),
+                Err(e) => diagnostics
+                    .inaccessible
+                    .push(("adb bugreport".to_string(), e.to_string())),
+            }
+        }
+
+        Ok(diagnostics)
+    }
+
+    /// Copies the most recently modified file in the device directory `source` into
+    /// `dir`, unless it is older than `max_age`. The inner [`Err`] describes why
+    /// `source` could not be read.
+    fn pull_latest(
+        &self,
+        device_serial: Option<&str>,
+        source: &str,
+        dir: &Path,
+        max_age: Duration,
+    ) -> Result<Result<Option<PathBuf>, String>, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg("ls").arg("-t").arg(source);
+        // Old `adb shell` versions always exit with `0` and mix `stderr` into `stdout`
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        let listing = String::from_utf8_lossy(&output.stdout);
+        let errors = String::from_utf8_lossy(&output.stderr);
+        let Some(latest) = latest_file(&listing) else {
+            let reason = format!("{}{}", listing.trim(), errors.trim());
+            return Ok(if output.status.success() && reason.is_empty() {
+                Ok(None)
+            } else {
+                Err(reason)
+            });
+        };
+
+        // Measured against the clock of the device. Devices whose shell can't tell the
+        // age, e.g. without `stat -c`, get the latest file regardless.
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("shell").arg(format!(
+            "echo $(($(date +%s) - $(stat -c %Y {}/{})))",
+            source, latest
+        ));
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        if parse_age(&String::from_utf8_lossy(&output.stdout))
+            .is_some_and(|age| age > max_age + Duration::from_secs(1))
+        {
+            return Ok(Ok(None));
+        }
+
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("exec-out")
+            .arg(format!("cat {}/{}", source, latest));
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(Err(String::from_utf8_lossy(&output.stderr)
+                .trim()
+                .to_string()));
+        }
+        let path = dir.join(latest);
+        fs::write(&path, output.stdout).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+        Ok(Ok(Some(path)))
+    }
+
+    /// Finds a way to read the app's internal storage, preferring `run-as`.
+    fn private_access(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+    ) -> Result<PrivateAccess, NdkError> {
+        for access in [PrivateAccess::RunAs, PrivateAccess::Root, PrivateAccess::Su] {
+            let mut adb = self.private_shell(device_serial, user, access, "id -u")?;
+            let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+            // Old `adb` versions always exit with `0`, only trust `stdout` if it parses.
+            // `run-as` prints `package not debuggable` on `stderr` instead of a uid.
+            let uid = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse::<u32>();
+            let accessible = match access {
+                PrivateAccess::RunAs => uid.is_ok(),
+                PrivateAccess::Root | PrivateAccess::Su => uid == Ok(0),
+            };
+            if output.status.success() && accessible {
+                return Ok(access);
+            }
+        }
+        Err(NdkError::NotDebuggable(self.package_name.clone()))
+    }
+
+    /// Builds an `adb exec-out` invocation that runs `script` with access to the
+    /// app's internal storage as working directory.
+    fn private_shell(
+        &self,
+        device_serial: Option<&str>,
+        user: Option<u32>,
+        access: PrivateAccess,
+        script: &str,
+    ) -> Result<Command, NdkError> {
+        let data_dir = match user {
+            None | Some(0) => format!("/data/data/{}", self.package_name),
+            Some(user) => format!("/data/user/{}/{}", user, self.package_name),
+        };
+        // `adb exec-out` joins its arguments into a single command line for the device shell
+        let command = match access {
+            PrivateAccess::RunAs => {
+                let user = user.map(|u| format!("--user {} ", u)).unwrap_or_default();
+                format!(
+                    "run-as {}{} sh -c {}",
+                    user,
+                    self.package_name,
+                    shell_quote(script)
+                )
+            }
+            PrivateAccess::Root => format!("cd {} && {}", shell_quote(&data_dir), script),
+            PrivateAccess::Su => format!(
+                "su 0 sh -c {}",
+                shell_quote(&format!("cd {} && {}", shell_quote(&data_dir), script))
+            ),
+        };
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("exec-out").arg(command);
+        Ok(adb)
+    }
+
+    /// Installs the apk as a privileged system app on a rooted `userdebug` or `eng`
+    /// build: restarts `adbd` as root, remounts `/system` read-write (rebooting first
+    /// if disabling verity requires it), pushes the apk to
+    /// `/system/priv-app/<name>/<name>.apk` and reboots for the package manager to pick
+    /// it up. Devices that refuse the remount get a `pm install -r --full` instead.
+    pub fn install_system(&self, device_serial: Option<&str>) -> Result<SystemInstall, NdkError> {
+        self.adb_root(device_serial)?;
+        let mut remount = self.remount(device_serial)?;
+        if matches!(remount, Remount::NeedsReboot(_)) {
+            status("Rebooting", "the device to finish disabling verity");
+            self.reboot(device_serial)?;
+            self.adb_root(device_serial)?;
+            remount = self.remount(device_serial)?;
+        }
+
+        let name = self.path.file_stem().unwrap().to_string_lossy();
+        let shell = |command: String| {
+            let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("shell").arg(command);
+            check_shell_output(output_error(adb, self.ndk.adb_timeout())?)
+        };
+        let push = |device_path: &str| {
+            let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("push").arg(&self.path).arg(device_path);
+            output_error(adb, self.ndk.adb_transfer_timeout())
+        };
+        match remount {
+            Remount::Done => {
+                let dir = format!("/system/priv-app/{}", name);
+                let apk = format!("{}/{}.apk", dir, name);
+                shell(format!("mkdir -p {}", shell_quote(&dir)))?;
+                push(&apk)?;
+                shell(format!("chmod 644 {}", shell_quote(&apk)))?;
+                status("Rebooting", "the device to register the system app");
+                self.reboot(device_serial)?;
+                Ok(SystemInstall::PrivApp(apk))
+            }
+            // Still asking for a reboot after one means the remount did not stick
+            Remount::NeedsReboot(output) | Remount::Refused(output) => {
+                let tmp = format!("/data/local/tmp/{}.apk", name);
+                push(&tmp)?;
+                let installed = shell(format!("pm install -r --full {}", shell_quote(&tmp)));
+                let _ = shell(format!("rm -f {}", shell_quote(&tmp)));
+                installed?;
+                Ok(SystemInstall::PmInstall(output))
+            }
+        }
+    }
+
+    /// Restarts `adbd` as root and waits for it to come back.
+    fn adb_root(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("root");
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        check_adb_root(output.status.success(), &combined_output(&output))?;
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("wait-for-device");
+        output_error(adb, Some(REBOOT_TIMEOUT))?;
+        Ok(())
+    }
+
+    fn remount(&self, device_serial: Option<&str>) -> Result<Remount, NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("remount");
+        let output = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+        Ok(parse_remount(
+            output.status.success(),
+            &combined_output(&output),
+        ))
+    }
+
+    /// Reboots the device and waits for `sys.boot_completed`.
+    fn reboot(&self, device_serial: Option<&str>) -> Result<(), NdkError> {
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("reboot");
+        output_error(adb, self.ndk.adb_timeout())?;
+        let start = Instant::now();
+        let mut adb = self.ndk.adb(device_serial)?;
+        adb.arg("wait-for-device");
+        output_error(adb, Some(REBOOT_TIMEOUT))?;
+        loop {
+            let mut adb = self.ndk.adb(device_serial)?;
+            adb.arg("shell").arg("getprop").arg("sys.boot_completed");
+            let boot_completed = output_timeout(&mut adb, self.ndk.adb_timeout())?;
+            if boot_completed.status.success()
+                && String::from_utf8_lossy(&boot_completed.stdout).trim() == "1"
+            {
+                return Ok(());
+            }
+            if start.elapsed() > REBOOT_TIMEOUT {
+                return Err(NdkError::RebootTimeout(REBOOT_TIMEOUT));
+            }
+            sleep(Duration::from_secs(1));
+        }
+    }
+
     pub fn package(&self) -> &str {
         &self.package_name
     }
+
+    /// The signed APK on the host.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The `android:versionName` from the manifest.
+    pub fn version_name(&self) -> Option<&str> {
+        self.version_name.as_deref()
+    }
+
+    /// The `android:versionCode` from the manifest.
+    pub fn version_code(&self) -> Option<u32> {
+        self.version_code
+    }
+}
+
+/// How long [`Apk::collect_diagnostics()`] waits for `adb bugreport`, which takes
+/// minutes on most devices.
+pub const BUGREPORT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Files saved by [`Apk::collect_diagnostics()`].
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    /// Paths of all files written on the host
+    pub files: Vec<PathBuf>,
+    /// Device sources that could not be read, and why
+    pub inaccessible: Vec<(String, String)>,
+}
+
+/// How long [`ProcessWatcher::wait()`] waits for an event before checking the pid, in
+/// case `logcat` missed the exit, e.g. of a process that died before it was attached.
+const WATCH_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How often [`ProcessWatcher::wait()`] checks the pid without access to the events.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follows a process of the app, returned by [`Apk::watch_process()`].
+///
+/// The activity manager logs an `am_proc_died` or `am_kill` event to the `events` log
+/// buffer for every process that exits, which `adb logcat -b events` streams as it
+/// happens. On devices where `logcat` exits because the buffer is inaccessible, the
+/// watcher falls back to polling [`Apk::pid()`] every second.
+pub struct ProcessWatcher<'a> {
+    apk: &'a Apk,
+    device_serial: Option<&'a str>,
+    user: Option<u32>,
+    pid: u32,
+    logcat: TrackedChild,
+    /// Lines printed by [`ProcessWatcher::logcat`], disconnected once it exited
+    lines: Receiver<String>,
+}
+
+impl ProcessWatcher<'_> {
+    /// Blocks until the process exited, counting a restart under a new pid as an exit.
+    pub fn wait(self) -> Result<(), NdkError> {
+        wait_for_exit(
+            &self.lines,
+            self.pid,
+            WATCH_RECHECK_INTERVAL,
+            WATCH_POLL_INTERVAL,
+            || Ok(self.apk.pid(self.device_serial, self.user)? == Some(self.pid)),
+        )
+    }
+}
+
+impl Drop for ProcessWatcher<'_> {
+    fn drop(&mut self) {
+        let _ = self.logcat.kill();
+    }
+}
+
+/// Waits for an event in `lines` reporting the death of `pid`, checking `alive` after
+/// every `recheck` without one, and every `poll` once `lines` is disconnected.
+fn wait_for_exit(
+    lines: &Receiver<String>,
+    pid: u32,
+    recheck: Duration,
+    poll: Duration,
+    mut alive: impl FnMut() -> Result<bool, NdkError>,
+) -> Result<(), NdkError> {
+    loop {
+        match lines.recv_timeout(recheck) {
+            Ok(line) if death_event_pid(&line) == Some(pid) => return Ok(()),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                if !alive()? {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    while alive()? {
+        sleep(poll);
+    }
+    Ok(())
+}
+
+/// The pid in an `am_proc_died` or `am_kill` event, whose fields start with the user
+/// and pid in all formats of `logcat`, like
+/// `I/am_proc_died( 1510): [0,4242,rust.foo,900,17]`.
+fn death_event_pid(line: &str) -> Option<u32> {
+    let (_, event) = line
+        .split_once("am_proc_died")
+        .or_else(|| line.split_once("am_kill"))?;
+    let (_, fields) = event.split_once('[')?;
+    fields.split(',').nth(1)?.trim().parse().ok()
+}
+
+/// Where [`Apk::install_system()`] put the app.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SystemInstall {
+    /// Pushed to this path under `/system/priv-app/`, registered by the reboot
+    PrivApp(String),
+    /// Installed through `pm install -r --full` because the device refused
+    /// `adb remount` with this output
+    PmInstall(String),
+}
+
+/// How the device answered `adb remount`.
+#[derive(Debug, PartialEq, Eq)]
+enum Remount {
+    Done,
+    /// Verity was disabled, which only takes effect after a reboot
+    NeedsReboot(String),
+    Refused(String),
+}
+
+/// Maximum time for a device to come back after `adb root` or `adb reboot`
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// How [`Apk::pull_private()`] and [`Apk::push_private()`] gain access to the app's
+/// internal storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PrivateAccess {
+    /// `run-as <package>`, only available for debuggable apps
+    RunAs,
+    /// `adbd` itself runs as root, e.g. after `adb root` on emulators
+    Root,
+    /// `su 0` on rooted devices
+    Su,
+}
+
+/// Looks for the crash markers that `debuggerd` (`>>> package <<<`) and the
+/// Java runtime (`Process: package, PID: pid`) log, and for `ActivityManager`
+/// ANR reports (`ANR in package` followed by a `PID: pid` line).
+fn has_crash_marker(log: &str, package: &str, pid: u32) -> bool {
+    let native = format!("pid: {}, ", pid);
+    let native_name = format!(">>> {} <<<", package);
+    let java = format!("Process: {}, PID: {}", package, pid);
+    let anr = format!("ANR in {}", package);
+    let anr_pid = format!("PID: {}", pid);
+
+    let mut lines = log.lines();
+    while let Some(line) = lines.next() {
+        if line.contains(&native) && line.contains(&native_name) || line.contains(&java) {
+            return true;
+        }
+        if line.contains(&anr) && lines.next().is_some_and(|line| line.ends_with(&anr_pid)) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Picks the first regular entry from `ls -t` output, skipping the protobuf
+/// copies (`tombstone_00.pb`) that Android 12+ writes next to each tombstone.
+fn latest_file(listing: &str) -> Option<&str> {
+    listing
+        .lines()
+        .map(str::trim)
+        .find(|name| !name.is_empty() && !name.contains(':') && !name.ends_with(".pb"))
+}
+
+/// Parses the age in seconds that [`Apk::pull_latest()`] computes on the device, which
+/// is garbage if `stat` failed.
+fn parse_age(output: &str) -> Option<Duration> {
+    output.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// The string-array extra of the launch intent that holds the arguments given after `--`
+/// to `cargo apk run`, read with `Intent.getStringArrayExtra()`.
+pub const ARGS_EXTRA: &str = "cargo_apk.args";
+/// Prefix of the string extras of the launch intent that hold the variables given with
+/// `cargo apk run --env KEY=VALUE`, followed by `KEY`.
+pub const ENV_EXTRA_PREFIX: &str = "cargo_apk.env.";
+
+/// Arguments and variables that [`Apk::start()`] passes to the app as extras of its
+/// launch intent, as activities have no `argv` or environment of their own.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchExtras {
+    /// Passed as the [`ARGS_EXTRA`] string array, if not empty
+    pub args: Vec<String>,
+    /// Each passed as a string extra named [`ENV_EXTRA_PREFIX`] followed by the key
+    pub env: Vec<(String, String)>,
+}
+
+impl LaunchExtras {
+    /// The `am start` arguments that add the extras, quoted for the device shell.
+    ///
+    /// `am` splits the value of `--esa` on commas that don't follow a `\`, and then
+    /// replaces `\,` with `,`. Commas in arguments are escaped that way, so an argument
+    /// that ends with `\` runs into the next one, and trailing empty arguments are
+    /// dropped by the split.
+    fn am_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if !self.args.is_empty() {
+            let array = self
+                .args
+                .iter()
+                .map(|arg| arg.replace(',', "\\,"))
+                .collect::<Vec<_>>()
+                .join(",");
+            args.extend([
+                "--esa".to_string(),
+                ARGS_EXTRA.to_string(),
+                shell_quote(&array),
+            ]);
+        }
+        for (key, value) in &self.env {
+            args.extend([
+                "--es".to_string(),
+                shell_quote(&format!("{}{}", ENV_EXTRA_PREFIX, key)),
+                shell_quote(value),
+            ]);
+        }
+        args
+    }
+}
+
+/// Quotes `arg` for the device shell by wrapping it in single quotes.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// The device script of [`Apk::push_private()`] that writes `stdin` to `device_path`,
+/// through a `.partial` file that is renamed once complete.
+fn push_script(device_path: &str, access: PrivateAccess) -> String {
+    let partial = shell_quote(&format!("{}.partial", device_path));
+    let mut script = match device_path.rsplit_once('/') {
+        Some((dir, _)) => format!("mkdir -p {} && ", shell_quote(dir)),
+        None => String::new(),
+    };
+    script += &format!("cat > {} && ", partial);
+    if access != PrivateAccess::RunAs {
+        // Owned by root otherwise, the app's data directory has its uid
+        script += &format!("chown $(stat -c %u:%g .) {} && ", partial);
+    }
+    script + &format!("mv {} {}", partial, shell_quote(device_path))
+}
+
+/// `am` and `pm` report most failures on `stdout` while still exiting with `0`.
+fn check_shell_output(output: Vec<u8>) -> Result<Vec<u8>, NdkError> {
+    let text = String::from_utf8_lossy(&output);
+    if text
+        .lines()
+        .any(|line| line.starts_with("Error") || line.starts_with("Failure"))
+    {
+        return Err(NdkError::ShellCommandFailed(text.trim().to_string()));
+    }
+    Ok(output)
+}
+
+fn combined_output(output: &std::process::Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// Pairs of `adb install` arguments that `adb` refuses to combine.
+const CONFLICTING_INSTALL_ARGS: &[(&str, &str)] = &[
+    ("--incremental", "--no-incremental"),
+    ("--streaming", "--no-streaming"),
+    ("--incremental", "--no-streaming"),
+    ("--instant", "--full"),
+];
+
+/// Fails if `args` for `adb install` contain a combination that `adb` or the package
+/// manager rejects, before anything is built.
+pub fn check_install_args(args: &[String]) -> Result<(), NdkError> {
+    let has = |arg: &str| args.iter().any(|a| a == arg);
+    match CONFLICTING_INSTALL_ARGS
+        .iter()
+        .find(|(a, b)| has(a) && has(b))
+    {
+        Some((a, b)) => Err(NdkError::ConflictingInstallArgs(
+            a.to_string(),
+            b.to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// An `adb install` failure recognized by its `INSTALL_FAILED_*` or
+/// `INSTALL_PARSE_FAILED_*` code, see [`InstallError::parse()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstallError {
+    VersionDowngrade,
+    UpdateIncompatible,
+    SharedUserIncompatible,
+    NoCertificates,
+    TestOnly,
+    OlderSdk,
+    NoMatchingAbis,
+    InvalidApk,
+    ManifestMalformed,
+    MissingSplit,
+    InsufficientStorage,
+    DuplicatePermission,
+    ConflictingProvider,
+    UserRestricted,
+    Aborted,
+}
+
+/// Every [`InstallError`] with its code, what it means and how to fix it.
+const INSTALL_ERRORS: &[(InstallError, &str, &str, &str)] = &[
+    (
+        InstallError::VersionDowngrade,
+        "INSTALL_FAILED_VERSION_DOWNGRADE",
+        "the device has the app with a higher `versionCode` installed",
+        "Pass `--install-arg=-d` or add `\"-d\"` to `install_flags` to downgrade, or \
+        uninstall the app first",
+    ),
+    (
+        InstallError::UpdateIncompatible,
+        "INSTALL_FAILED_UPDATE_INCOMPATIBLE",
+        "the installed app is signed with a different key, e.g. on another machine",
+        "Uninstall it first with `adb uninstall <package>`, which deletes its data",
+    ),
+    (
+        InstallError::SharedUserIncompatible,
+        "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE",
+        "the other apps of the `shared_user_id` are signed with a different key",
+        "Sign the apk with their keystore, or uninstall them first",
+    ),
+    (
+        InstallError::NoCertificates,
+        "INSTALL_PARSE_FAILED_NO_CERTIFICATES",
+        "the apk is not signed, or only with a scheme that the device doesn't know",
+        "Install the signed apk that `cargo apk build` writes, not the `-unaligned.apk`",
+    ),
+    (
+        InstallError::TestOnly,
+        "INSTALL_FAILED_TEST_ONLY",
+        "the app is marked `android:testOnly`",
+        "Pass `--install-arg=-t` or add `\"-t\"` to `install_flags`",
+    ),
+    (
+        InstallError::OlderSdk,
+        "INSTALL_FAILED_OLDER_SDK",
+        "the device runs an older Android version than the `min_sdk_version` of the apk",
+        "Lower `min_sdk_version` or use a newer device",
+    ),
+    (
+        InstallError::NoMatchingAbis,
+        "INSTALL_FAILED_NO_MATCHING_ABIS",
+        "the apk has no libraries for any ABI of the device",
+        "Add one of the ABIs the device lists in `cargo apk devices` to `build_targets`",
+    ),
+    (
+        InstallError::InvalidApk,
+        "INSTALL_FAILED_INVALID_APK",
+        "the package manager could not read the apk or extract its libraries",
+        "Rebuild the apk, and check that its libraries are not compressed when \
+        `extract_native_libs = false`",
+    ),
+    (
+        InstallError::ManifestMalformed,
+        "INSTALL_PARSE_FAILED_MANIFEST_MALFORMED",
+        "the device rejected the `AndroidManifest.xml`",
+        "Fix the attribute named in the output below, e.g. set `exported` on activities \
+        with intent filters when targeting API 31 or higher",
+    ),
+    (
+        InstallError::MissingSplit,
+        "INSTALL_FAILED_MISSING_SPLIT",
+        "a split apk was installed without its base apk, or the base apk requires a split",
+        "Install the base apk together with its splits through `adb install-multiple`",
+    ),
+    (
+        InstallError::InsufficientStorage,
+        "INSTALL_FAILED_INSUFFICIENT_STORAGE",
+        "the device has not enough free space for the apk",
+        "Free up space on the device, e.g. by uninstalling other apps",
+    ),
+    (
+        InstallError::DuplicatePermission,
+        "INSTALL_FAILED_DUPLICATE_PERMISSION",
+        "another app already defines one of the custom permissions of the apk",
+        "Uninstall the other app, or rename the permission",
+    ),
+    (
+        InstallError::ConflictingProvider,
+        "INSTALL_FAILED_CONFLICTING_PROVIDER",
+        "another app already uses the authority of one of the content providers",
+        "Uninstall the other app, or give the provider an authority of its own",
+    ),
+    (
+        InstallError::UserRestricted,
+        "INSTALL_FAILED_USER_RESTRICTED",
+        "the device doesn't allow installing apps over USB",
+        "Enable \"Install via USB\" in the developer options, which some devices also \
+        require for the `adb install-multiple` of `split_assets`",
+    ),
+    (
+        InstallError::Aborted,
+        "INSTALL_FAILED_ABORTED",
+        "the installation was canceled on the device",
+        "Confirm the prompt that some devices show for installs over USB",
+    ),
+];
+
+impl InstallError {
+    /// The first known code in the output of `adb install`, skipping codes without a
+    /// known fix like `INSTALL_FAILED_INTERNAL_ERROR`.
+    pub fn parse(output: &str) -> Option<Self> {
+        output.match_indices("INSTALL_").find_map(|(start, _)| {
+            let code = &output[start..];
+            let end = code
+                .find(|c: char| !c.is_ascii_uppercase() && c != '_')
+                .unwrap_or(code.len());
+            Self::from_code(&code[..end])
+        })
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        INSTALL_ERRORS
+            .iter()
+            .find(|(_, c, _, _)| *c == code)
+            .map(|(error, _, _, _)| *error)
+    }
+
+    fn entry(self) -> &'static (Self, &'static str, &'static str, &'static str) {
+        INSTALL_ERRORS
+            .iter()
+            .find(|(e, _, _, _)| *e == self)
+            .unwrap()
+    }
+
+    /// Like `INSTALL_FAILED_VERSION_DOWNGRADE`
+    pub fn code(self) -> &'static str {
+        self.entry().1
+    }
+
+    /// What went wrong, starting in lower case
+    pub fn explanation(self) -> &'static str {
+        self.entry().2
+    }
+
+    /// What to do about it
+    pub fn fix(self) -> &'static str {
+        self.entry().3
+    }
+}
+
+/// Turns [`NdkError::CmdFailed`] of `adb install` into [`NdkError::InstallFailed`] when
+/// the output has an [`InstallError`] code.
+fn install_failure(error: NdkError) -> NdkError {
+    let NdkError::CmdFailed { error: output, .. } = &error else {
+        return error;
+    };
+    match InstallError::parse(&output.to_string()) {
+        Some(install_error) => NdkError::InstallFailed {
+            error: install_error,
+            output: error.to_string(),
+        },
+        None => error,
+    }
+}
+
+/// `adb` before Android 7 exits with `0` and prints the `Failure [...]` of `command` to
+/// stdout, which is [`NdkError::InstallFailed`] for a known [`InstallError`] code and
+/// [`NdkError::CmdFailed`] with the output otherwise.
+fn check_install_output(command: String, stdout: &str) -> Result<(), NdkError> {
+    if !stdout.contains("Failure [") {
+        return Ok(());
+    }
+    let output = stdout.trim().to_string();
+    Err(match InstallError::parse(&output) {
+        Some(error) => NdkError::InstallFailed { error, output },
+        None => NdkError::CmdFailed {
+            command,
+            status: None,
+            error: std::io::Error::other(output),
+        },
+    })
+}
+
+/// Older `adb` versions exit with `0` even when `adbd` refuses to restart as root on
+/// production builds.
+fn check_adb_root(success: bool, output: &str) -> Result<(), NdkError> {
+    if !success || output.contains("cannot run as root") {
+        return Err(NdkError::AdbRootFailed(output.trim().to_string()));
+    }
+    Ok(())
+}
+
+fn parse_remount(success: bool, output: &str) -> Remount {
+    let lower = output.to_lowercase();
+    if lower.contains("reboot") {
+        Remount::NeedsReboot(output.trim().to_string())
+    } else if success && !lower.contains("failed") {
+        Remount::Done
+    } else {
+        Remount::Refused(output.trim().to_string())
+    }
+}
+
+/// Collects the pids of all processes named `package` from `ps` output,
+/// optionally only those owned by the given Android `user`.
+///
+/// Both `toolbox` and `toybox` print a `USER PID ...` header with the process
+/// name in the last column. App processes are owned by `u<user>_a<app id>`.
+fn parse_ps_pids(output: &str, package: &str, user: Option<u32>) -> Result<Vec<u32>, NdkError> {
+    let mut lines = output.lines();
+    let pid_column = lines
+        .next()
+        .and_then(|header| header.split_whitespace().position(|c| c == "PID"))
+        .ok_or_else(|| NdkError::PsOutputUnrecognized(output.to_string()))?;
+
+    let mut pids = vec![];
+    for line in lines {
+        let columns = line.split_whitespace().collect::<Vec<_>>();
+        if columns.last() != Some(&package) {
+            continue;
+        }
+        if let Some(user) = user {
+            if !columns
+                .first()
+                .is_some_and(|owner| owner.starts_with(&format!("u{}_", user)))
+            {
+                continue;
+            }
+        }
+        let pid = columns
+            .get(pid_column)
+            .ok_or_else(|| NdkError::PsOutputUnrecognized(output.to_string()))?;
+        pids.push(
+            pid.parse()
+                .map_err(|e| NdkError::NotAPid(e, pid.to_string()))?,
+        );
+    }
+    Ok(pids)
+}
+
+/// `/proc/<pid>/cmdline` contains `NUL`-separated arguments, the first being the process name.
+fn cmdline_matches(cmdline: &[u8], package: &str) -> bool {
+    cmdline
+        .split(|&b| b == 0)
+        .next()
+        .is_some_and(|name| String::from_utf8_lossy(name).trim() == package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndk::OlderKey;
+    use crate::test_dir::TestDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_sign_hides_password() {
+        let key = Key {
+            path: PathBuf::from("release.keystore"),
+            password: "correct horse battery staple".to_string(),
+        };
+        let mut apksigner = Command::new("false");
+        sign_args(&mut apksigner, &key, None, Path::new("app.apk"));
+        let error = output_error(apksigner, None).unwrap_err().to_string();
+        assert!(error.contains("--ks-pass pass:*** app.apk"), "{}", error);
+        assert!(!error.contains(&key.password), "{}", error);
+        assert!(error.ends_with("failed with exit code 1."), "{}", error);
+    }
+
+    #[test]
+    fn sign_rotated_args() {
+        let key = |path: &str, password: &str| Key {
+            path: PathBuf::from(path),
+            password: password.to_string(),
+        };
+        let rotation = KeyRotation {
+            lineage: PathBuf::from("upload.lineage"),
+            rotation_min_sdk_version: Some(28),
+            older_keys: vec![OlderKey {
+                key: key("old.keystore", "old"),
+                alias: Some("upload".to_string()),
+            }],
+        };
+        let mut apksigner = Command::new("apksigner");
+        sign_args(
+            &mut apksigner,
+            &key("new.keystore", "new"),
+            Some(&rotation),
+            Path::new("app.apk"),
+        );
+        assert_eq!(
+            crate::util::command_line(&apksigner),
+            "apksigner sign --ks old.keystore --ks-pass pass:*** --ks-key-alias upload \
+            --next-signer --ks new.keystore --ks-pass pass:*** --lineage upload.lineage \
+            --rotation-min-sdk-version 28 app.apk"
+        );
+    }
+
+    #[test]
+    fn baseline_profile() {
+        let dir = TestDir::new("baseline");
+        let profiles = dir.join("profiles");
+        std::fs::create_dir_all(&profiles).unwrap();
+        std::fs::write(profiles.join("app.prof"), "prof").unwrap();
+        std::fs::write(profiles.join("app.profm"), "profm").unwrap();
+        let config = ApkConfig {
+            ndk: crate::ndk::tests::fake_ndk(&dir),
+            build_dir: dir.join("apk"),
+            apk_name: "app".to_string(),
+            assets: None,
+            resources: None,
+            manifest: AndroidManifest::default(),
+            disable_aapt_compression: false,
+            strip: StripMode::None,
+            split_debuginfo: false,
+            reverse_port_forward: HashMap::new(),
+            keep_stale: false,
+            skip_resource_validation: false,
+            split: None,
+            install_args: vec![],
+        };
+        let mut apk = UnalignedApk {
+            config: &config,
+            pending_libs: HashSet::default(),
+            libs: BTreeMap::new(),
+            temp_paths: vec![],
+            previous_size: None,
+        };
+
+        apk.add_baseline_profile(&profiles.join("app.prof"))
+            .unwrap();
+        let mut pending = apk.pending_libs.iter().collect::<Vec<_>>();
+        pending.sort();
+        assert_eq!(
+            pending,
+            [
+                "assets/dexopt/baseline.prof",
+                "assets/dexopt/baseline.profm"
+            ]
+        );
+        let profm = std::fs::read_to_string(dir.join("apk/assets/dexopt/baseline.profm"));
+        assert_eq!(profm.unwrap(), "profm");
+        assert!(matches!(
+            apk.add_baseline_profile(&profiles.join("missing.prof")),
+            Err(NdkError::PathNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn dry_run_stages_nothing() {
+        let dir = TestDir::new("dry-run");
+        let config = ApkConfig {
+            ndk: crate::ndk::tests::fake_dry_run_ndk(&dir),
+            build_dir: dir.join("apk"),
+            apk_name: "app".to_string(),
+            assets: None,
+            resources: None,
+            manifest: AndroidManifest::default(),
+            disable_aapt_compression: false,
+            strip: StripMode::None,
+            split_debuginfo: false,
+            reverse_port_forward: HashMap::new(),
+            keep_stale: false,
+            skip_resource_validation: false,
+            split: None,
+            install_args: vec![],
+        };
+        let mut apk = UnalignedApk {
+            config: &config,
+            pending_libs: HashSet::default(),
+            libs: BTreeMap::new(),
+            temp_paths: vec![],
+            previous_size: None,
+        };
+
+        // The library is not built in a dry run
+        let lib = dir.join("target/aarch64-linux-android/debug/libapp.so");
+        apk.add_lib(&lib, Target::Arm64V8a, LibOrigin::Deps)
+            .unwrap();
+        apk.add_asset("build_info.json", b"{}").unwrap();
+        let mut pending = apk.pending_libs.iter().collect::<Vec<_>>();
+        pending.sort();
+        assert_eq!(
+            pending,
+            ["assets/build_info.json", "lib/arm64-v8a/libapp.so"]
+        );
+        assert_eq!(apk.libs["arm64-v8a/libapp.so"].source, lib);
+        assert_eq!(apk.libs["arm64-v8a/libapp.so"].origin, LibOrigin::Deps);
+        assert!(!config.build_dir.exists());
+    }
+
+    #[test]
+    fn native_packaging() {
+        let dir = TestDir::new("native");
+        let assets = dir.join("assets");
+        std::fs::create_dir_all(assets.join("shaders")).unwrap();
+        std::fs::write(assets.join("shaders/main.wgsl"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("libapp.so"), [0x7f; 100]).unwrap();
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "rust.native".to_string();
+        manifest.sdk.min_sdk_version = Some(24);
+        let config = ApkConfig {
+            ndk: crate::ndk::tests::fake_native_ndk(&dir),
+            build_dir: dir.join("apk"),
+            apk_name: "app".to_string(),
+            assets: Some(assets),
+            resources: None,
+            manifest,
+            disable_aapt_compression: false,
+            strip: StripMode::None,
+            split_debuginfo: false,
+            reverse_port_forward: HashMap::new(),
+            keep_stale: false,
+            skip_resource_validation: false,
+            split: None,
+            install_args: vec![],
+        };
+
+        // Without build-tools or an `android.jar` in the fake SDK
+        let mut apk = config.create_apk().unwrap();
+        apk.add_lib(&dir.join("libapp.so"), Target::Arm64V8a, LibOrigin::Deps)
+            .unwrap();
+        let key = Key {
+            path: Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/native/debug.keystore"),
+            password: "android".to_string(),
+        };
+        apk.add_pending_libs_and_align().unwrap().sign(key).unwrap();
+        let signed = std::fs::read(config.apk()).unwrap();
+        let contains = |needle: &[u8]| signed.windows(needle.len()).any(|w| w == needle);
+        assert!(signed.starts_with(b"PK\x03\x04"));
+        assert!(contains(b"AndroidManifest.xml"));
+        assert!(contains(b"assets/shaders/main.wgsl"));
+        assert!(contains(b"lib/arm64-v8a/libapp.so"));
+        assert!(contains(b"APK Sig Block 42"));
+
+        // Below API 24 apps need the v1 signatures of `apksigner`
+        let config = ApkConfig {
+            manifest: AndroidManifest::default(),
+            ..config
+        };
+        assert!(matches!(
+            config.create_apk(),
+            Err(NdkError::NativePackagingUnsupported(_))
+        ));
+    }
+
+    #[test]
+    fn prune_stale_staging() {
+        let dir = TestDir::new("prune");
+        for (path, contents) in [
+            ("lib/arm64-v8a/libapp.so", "elf"),
+            ("lib/arm64-v8a/libapp.dwarf", "dwarf"),
+            ("lib/x86/libapp.so", "stale"),
+            ("assets/dexopt/baseline.prof", "prof"),
+            ("assets/build_info.json", "{}"),
+            ("classes.dex", "dex"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let keep = ["lib/arm64-v8a/libapp.so", "assets/build_info.json"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(prune_staging(&dir, &keep).unwrap(), (2, 9));
+        assert!(dir.join("lib/arm64-v8a/libapp.dwarf").exists());
+        assert!(!dir.join("lib/x86").exists());
+        assert!(!dir.join("assets/dexopt").exists());
+        assert!(dir.join("assets/build_info.json").exists());
+        assert!(dir.join("classes.dex").exists());
+        assert_eq!(prune_staging(&dir, &keep).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn ps_pids_toybox() {
+        let output = "\
+USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME
+root             1     0 10932416  9824 0                   0 S init
+u0_a123       4242   712 15203432 98116 0                   0 S rust.example.hello_world
+u0_a123       4250   712 15203432 98116 0                   0 S rust.example.hello_world:remote
+";
+        assert_eq!(
+            parse_ps_pids(output, "rust.example.hello_world", None).unwrap(),
+            [4242]
+        );
+    }
+
+    #[test]
+    fn ps_pids_toolbox_multiple() {
+        let output = "\
+USER     PID   PPID  VSIZE  RSS     WCHAN    PC         NAME
+root      1     0     8904   788   ffffffff 00000000 S /init
+u0_a52    2001  178   1024   512   ffffffff 00000000 S rust.foo
+u0_a52    2002  2001  1024   512   ffffffff 00000000 S rust.foo
+";
+        assert_eq!(
+            parse_ps_pids(output, "rust.foo", None).unwrap(),
+            [2001, 2002]
+        );
+        assert!(parse_ps_pids(output, "rust.bar", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ps_pids_user() {
+        let output = "\
+USER           PID  PPID     VSZ    RSS WCHAN            ADDR S NAME
+u0_a123       4242   712 15203432 98116 0                   0 S rust.foo
+u10_a123      5151   712 15203432 98116 0                   0 S rust.foo
+";
+        assert_eq!(parse_ps_pids(output, "rust.foo", Some(10)).unwrap(), [5151]);
+        assert_eq!(parse_ps_pids(output, "rust.foo", Some(0)).unwrap(), [4242]);
+        assert!(
+            parse_ps_pids(output, "rust.foo", Some(1))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn shell_output() {
+        assert!(
+            check_shell_output(b"Starting: Intent { act=android.intent.action.MAIN }\n".to_vec())
+                .is_ok()
+        );
+        assert!(matches!(
+            check_shell_output(b"Error: Activity not started, unknown user 99\n".to_vec()),
+            Err(NdkError::ShellCommandFailed(msg)) if msg.contains("unknown user 99")
+        ));
+    }
+
+    #[test]
+    fn ps_pids_unrecognized() {
+        assert!(matches!(
+            parse_ps_pids("/system/bin/sh: ps: not found\n", "rust.foo", None),
+            Err(NdkError::PsOutputUnrecognized(_))
+        ));
+    }
+
+    #[test]
+    fn crash_markers() {
+        let native = "\
+10-14 12:00:00.000  4300  4300 F DEBUG   : pid: 4242, tid: 4260, name: Thread-2  >>> rust.foo <<<
+";
+        assert!(has_crash_marker(native, "rust.foo", 4242));
+        assert!(!has_crash_marker(native, "rust.foo", 4243));
+
+        let java =
+            "10-14 12:00:00.000  4242  4242 E AndroidRuntime: Process: rust.foo, PID: 4242\n";
+        assert!(has_crash_marker(java, "rust.foo", 4242));
+
+        let anr = "\
+10-14 12:00:00.000   712   730 E ActivityManager: ANR in rust.foo (rust.foo/android.app.NativeActivity)
+10-14 12:00:00.000   712   730 E ActivityManager: PID: 4242
+";
+        assert!(has_crash_marker(anr, "rust.foo", 4242));
+        assert!(!has_crash_marker(anr, "rust.foo", 1));
+    }
+
+    #[test]
+    fn death_events() {
+        assert_eq!(
+            death_event_pid("I/am_proc_died( 1510): [0,4242,rust.foo,900,17]"),
+            Some(4242)
+        );
+        assert_eq!(
+            death_event_pid(
+                "10-14 12:00:00.000  1510  1620 I am_kill : [0,4242,rust.foo,906,remove task]"
+            ),
+            Some(4242)
+        );
+        assert_eq!(
+            death_event_pid(
+                "10-14 12:00:00.000  1510  1620 I am_proc_start: [0,4242,10123,rust.foo,activity,{rust.foo/android.app.NativeActivity}]"
+            ),
+            None
+        );
+        assert_eq!(death_event_pid("--------- beginning of events"), None);
+    }
+
+    #[test]
+    fn wait_for_exit_events() {
+        let (sender, lines) = channel();
+        for line in [
+            "--------- beginning of events",
+            "I/am_proc_died( 1510): [0,4141,com.android.chrome,0,11]",
+            "I/am_proc_died( 1510): [0,4242,rust.foo,900,17]",
+        ] {
+            sender.send(line.to_string()).unwrap();
+        }
+        let never = || -> Result<bool, NdkError> { panic!("polled despite the event") };
+        wait_for_exit(&lines, 4242, Duration::from_secs(60), Duration::ZERO, never).unwrap();
+
+        // Without an event, `alive` is checked after every `recheck`
+        let mut checks = 0;
+        wait_for_exit(&lines, 4242, Duration::ZERO, Duration::ZERO, || {
+            checks += 1;
+            Ok(checks < 3)
+        })
+        .unwrap();
+        assert_eq!(checks, 3);
+
+        // `logcat` exited, falling back to polling
+        drop(sender);
+        let mut checks = 0;
+        wait_for_exit(
+            &lines,
+            4242,
+            Duration::from_secs(60),
+            Duration::ZERO,
+            || {
+                checks += 1;
+                Ok(checks < 5)
+            },
+        )
+        .unwrap();
+        assert_eq!(checks, 5);
+    }
+
+    #[test]
+    fn latest_tombstone() {
+        assert_eq!(
+            latest_file("tombstone_03.pb\ntombstone_03\ntombstone_02\n"),
+            Some("tombstone_03")
+        );
+        assert_eq!(
+            latest_file("ls: /data/tombstones: Permission denied\n"),
+            None
+        );
+        assert_eq!(latest_file(""), None);
+        assert_eq!(parse_age("42\n"), Some(Duration::from_secs(42)));
+        assert_eq!(parse_age("stat: Unknown option 'c'\n"), None);
+        // `$(date +%s) - $()` when `stat` printed nothing
+        assert_eq!(parse_age("1700000000-\n"), None);
+    }
+
+    #[test]
+    fn private_shell_serial() {
+        let dir = TestDir::new("private-shell");
+        let apk = Apk {
+            path: PathBuf::from("app.apk"),
+            package_name: "rust.foo".to_string(),
+            version_name: None,
+            version_code: None,
+            ndk: crate::ndk::tests::fake_ndk(&dir),
+            reverse_port_forward: Default::default(),
+            install_args: vec![],
+        };
+        let adb = apk
+            .private_shell(Some("R58M12ABCDE"), Some(10), PrivateAccess::RunAs, "id -u")
+            .unwrap();
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            [
+                "-s",
+                "R58M12ABCDE",
+                "exec-out",
+                "run-as --user 10 rust.foo sh -c 'id -u'"
+            ]
+        );
+    }
+
+    #[test]
+    fn push_scripts() {
+        assert_eq!(
+            push_script("files/assets/level 1.json", PrivateAccess::RunAs),
+            "mkdir -p 'files/assets' && cat > 'files/assets/level 1.json.partial' && \
+            mv 'files/assets/level 1.json.partial' 'files/assets/level 1.json'"
+        );
+        assert_eq!(
+            push_script("save.dat", PrivateAccess::Su),
+            "cat > 'save.dat.partial' && chown $(stat -c %u:%g .) 'save.dat.partial' && \
+            mv 'save.dat.partial' 'save.dat'"
+        );
+    }
+
+    #[test]
+    fn launch_extras() {
+        assert!(LaunchExtras::default().am_args().is_empty());
+        let extras = LaunchExtras {
+            args: ["--level", "2", "a,b", "it's here"]
+                .map(String::from)
+                .to_vec(),
+            env: vec![("RUST_LOG".to_string(), "debug,wgpu=warn".to_string())],
+        };
+        assert_eq!(
+            extras.am_args(),
+            [
+                "--esa",
+                "cargo_apk.args",
+                r"'--level,2,a\,b,it'\''s here'",
+                "--es",
+                "'cargo_apk.env.RUST_LOG'",
+                "'debug,wgpu=warn'",
+            ]
+        );
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(shell_quote("files/save.dat"), "'files/save.dat'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn cmdline() {
+        assert!(cmdline_matches(b"rust.foo\0\0\0", "rust.foo"));
+        assert!(!cmdline_matches(b"rust.foo:remote\0", "rust.foo"));
+        assert!(!cmdline_matches(b"", "rust.foo"));
+    }
+
+    #[test]
+    fn system_install_outputs() {
+        assert!(check_adb_root(true, "restarting adbd as root\n").is_ok());
+        assert!(check_adb_root(true, "adbd is already running as root\n").is_ok());
+        assert!(matches!(
+            check_adb_root(true, "adbd cannot run as root in production builds\n"),
+            Err(NdkError::AdbRootFailed(output)) if output.ends_with("production builds")
+        ));
+        assert!(check_adb_root(false, "error: device offline\n").is_err());
+
+        assert_eq!(parse_remount(true, "remount succeeded\n"), Remount::Done);
+        assert_eq!(
+            parse_remount(
+                true,
+                "Disabling verity for /system\nNow reboot your device for settings to take effect\n"
+            ),
+            Remount::NeedsReboot(
+                "Disabling verity for /system\nNow reboot your device for settings to take effect"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_remount(true, "remount of /system failed: Read-only file system\n"),
+            Remount::Refused("remount of /system failed: Read-only file system".to_string())
+        );
+        assert!(matches!(
+            parse_remount(false, "Not running as root. Try \"adb root\" first.\n"),
+            Remount::Refused(_)
+        ));
+    }
+
+    #[test]
+    fn resource_references() {
+        let dir = TestDir::new("res");
+        let res = dir.join("res");
+        for (path, contents) in [
+            (
+                "values/strings.xml",
+                "<resources>\n    <string name=\"app_name\">App</string>\n</resources>\n",
+            ),
+            (
+                "values-de/strings.xml",
+                "<resources><string-array name=\"levels\"/></resources>",
+            ),
+            ("mipmap-hdpi/ic_launcher.png", "png"),
+            ("drawable/banner.9.png", "png"),
+        ] {
+            let path = res.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let mut manifest = AndroidManifest::default();
+        manifest.application.label = "@string/app_name".to_string();
+        manifest.application.icon = Some("@mipmap/ic_launcher".to_string());
+        manifest.application.activity.label = Some("@android:string/ok".to_string());
+        validate_resource_references(&manifest, Some(&res)).unwrap();
+
+        manifest.application.icon = Some("@drawable/banner".to_string());
+        validate_resource_references(&manifest, Some(&res)).unwrap();
+        manifest.application.banner = Some("@drawable/tv_banner".to_string());
+        assert!(matches!(
+            validate_resource_references(&manifest, Some(&res)),
+            Err(NdkError::ResourceNotFound { field, .. }) if field == "application.banner"
+        ));
+        manifest.application.banner = None;
+
+        manifest.application.label = "@string/levels".to_string();
+        let error = validate_resource_references(&manifest, Some(&res)).unwrap_err();
+        assert!(matches!(
+            &error,
+            NdkError::ResourceNotFound { reference, field, .. }
+                if reference == "@string/levels" && field == "application.label"
+        ));
+
+        manifest.application.label = "My App".to_string();
+        manifest.application.icon = None;
+        validate_resource_references(&manifest, None).unwrap();
+        manifest.application.icon = Some("@mipmap/ic_launcher".to_string());
+        assert_eq!(
+            validate_resource_references(&manifest, None)
+                .unwrap_err()
+                .to_string(),
+            "Resource `@mipmap/ic_launcher` referenced by `application.icon` not found as no \
+            `resources` directory is configured, pass `--skip-resource-validation` to package \
+            anyway"
+        );
+    }
+
+    #[test]
+    fn config_split() {
+        let dir = TestDir::new("config-split");
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "com.example.app".to_string();
+        manifest.version_code = Some(3);
+        manifest.application.label = "App".to_string();
+        let config = ApkConfig {
+            ndk: crate::ndk::tests::fake_ndk(&dir),
+            build_dir: PathBuf::from("/target/debug/apk/app"),
+            apk_name: "app".to_string(),
+            assets: Some(PathBuf::from("/app/assets")),
+            resources: Some(PathBuf::from("/app/res")),
+            manifest,
+            disable_aapt_compression: false,
+            strip: StripMode::None,
+            split_debuginfo: false,
+            reverse_port_forward: HashMap::new(),
+            keep_stale: false,
+            skip_resource_validation: false,
+            split: None,
+            install_args: vec![],
+        };
+
+        let split = config.config_split("xxhdpi", PathBuf::from("/app/assets-xxhdpi"));
+        assert_eq!(
+            split.apk(),
+            Path::new("/target/debug/apk/app/splits/xxhdpi/app-xxhdpi.apk")
+        );
+        assert_eq!(
+            split.assets.as_deref(),
+            Some(Path::new("/app/assets-xxhdpi"))
+        );
+        assert_eq!(split.resources, None);
+        assert_eq!(
+            split.manifest.config_split("xxhdpi").to_xml().unwrap(),
+            "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\" \
+            package=\"com.example.app\" android:versionCode=\"3\" split=\"config.xxhdpi\">\
+            <application android:hasCode=\"false\"/></manifest>"
+        );
+    }
+
+    #[test]
+    fn install_args() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        check_install_args(&args(&["-d", "--incremental"])).unwrap();
+        assert!(matches!(
+            check_install_args(&args(&["--no-streaming", "-d", "--incremental"])),
+            Err(NdkError::ConflictingInstallArgs(a, b))
+                if a == "--incremental" && b == "--no-streaming"
+        ));
+
+        let failed = |output: &str| NdkError::CmdFailed {
+            command: "adb install -r app.apk".to_string(),
+            status: None,
+            error: std::io::Error::other(output.to_string()),
+        };
+        let output = "Performing Streamed Install\nadb: failed to install app.apk: Failure \
+            [INSTALL_FAILED_VERSION_DOWNGRADE: Downgrade detected: Update version code 1 is \
+            older than current 2]";
+        let error = install_failure(failed(output));
+        assert!(matches!(
+            &error,
+            NdkError::InstallFailed {
+                error: InstallError::VersionDowngrade,
+                ..
+            }
+        ));
+        let message = error.to_string();
+        assert!(
+            message.starts_with(
+                "Installing failed with `INSTALL_FAILED_VERSION_DOWNGRADE`: the device has \
+                the app with a higher `versionCode` installed. Pass `--install-arg=-d`"
+            ),
+            "{}",
+            message
+        );
+        // The output of `adb` comes last
+        assert!(
+            message.contains("\n\nPerforming Streamed Install\n"),
+            "{}",
+            message
+        );
+        assert!(matches!(
+            install_failure(failed("Failure [INSTALL_FAILED_INTERNAL_ERROR]")),
+            NdkError::CmdFailed { .. }
+        ));
+    }
+
+    /// Every file in `testdata/install_failures/` is the output of a failed `adb install`
+    /// named after the code it is expected to parse as, or `unrecognized_*` for none.
+    #[test]
+    fn install_failure_corpus() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/install_failures");
+        let mut seen = HashSet::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let output = fs::read_to_string(&path).unwrap();
+            let parsed = InstallError::parse(&output);
+            if name.starts_with("unrecognized_") {
+                assert_eq!(parsed, None, "{}", name);
+            } else {
+                let parsed = parsed.unwrap_or_else(|| panic!("{} not recognized", name));
+                assert_eq!(parsed.code().to_lowercase(), name);
+                seen.insert(parsed);
+            }
+        }
+        for (error, _, _, _) in INSTALL_ERRORS {
+            assert!(seen.contains(error), "no capture of {}", error.code());
+        }
+    }
+
+    /// Every `Failure [...]` of the corpus fails the install even when `adb` exits with
+    /// `0`, with the raw output for codes that aren't recognized.
+    #[test]
+    fn install_failure_exit_0() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/install_failures");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let output = fs::read_to_string(&path).unwrap();
+            if !output.contains("Failure [") {
+                continue;
+            }
+            match check_install_output("adb install app.apk".to_string(), &output) {
+                Err(NdkError::InstallFailed { error, .. }) => {
+                    assert_eq!(error.code().to_lowercase(), name)
+                }
+                Err(NdkError::CmdFailed { error, .. }) => {
+                    assert!(name.starts_with("unrecognized_"), "{}", name);
+                    assert_eq!(error.to_string(), output.trim());
+                }
+                result => panic!("{}: {:?}", name, result),
+            }
+        }
+        assert!(check_install_output("adb install app.apk".to_string(), "Success\n").is_ok());
+    }
 }
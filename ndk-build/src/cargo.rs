@@ -1,82 +1,98 @@
+use crate::env;
 use crate::error::NdkError;
 use crate::ndk::Ndk;
+use crate::sanitizer::Sanitizer;
 use crate::target::Target;
 use crate::util::color;
 use std::path::Path;
 use std::process::Command;
 
+const SEP: &str = "\x1f";
+
+/// Extra `rustc` and linker flags for [`cargo_ndk_with()`], passed through
+/// `CARGO_ENCODED_RUSTFLAGS` after the flags from the environment.
+#[derive(Clone, Debug, Default)]
+pub struct CargoNdkOptions {
+    /// Passed as `-Clink-arg=<arg>` after the default arguments. `clang` is the linker
+    /// driver, so `lld` flags need a `-Wl,` prefix, e.g. `-Wl,--icf=all`.
+    pub link_args: Vec<String>,
+    /// Omit the default `-Clink-arg=--target=<triple><sdk_version>` and the `-L`
+    /// directory working around the missing `libgcc`, leaving only
+    /// [`CargoNdkOptions::link_args`].
+    pub replace_default_args: bool,
+    /// Instruments the build and links the NDK's runtime, see [`Sanitizer::rustflags()`]
+    pub sanitizer: Option<Sanitizer>,
+    /// Passed verbatim, before [`CargoNdkOptions::link_args`]
+    pub rustflags: Vec<String>,
+    /// Passed as `-Ctarget-cpu=<cpu>`, e.g. `cortex-a53`
+    pub target_cpu: Option<String>,
+    /// Passed as one `-Ctarget-feature=<features>`, e.g. `+neon` and `+vfp4`
+    pub target_features: Vec<String>,
+}
+
 pub fn cargo_ndk(
     ndk: &Ndk,
     target: Target,
     sdk_version: u32,
     target_dir: impl AsRef<Path>,
+) -> Result<Command, NdkError> {
+    cargo_ndk_with(
+        ndk,
+        target,
+        sdk_version,
+        target_dir,
+        &CargoNdkOptions::default(),
+    )
+}
+
+pub fn cargo_ndk_with(
+    ndk: &Ndk,
+    target: Target,
+    sdk_version: u32,
+    target_dir: impl AsRef<Path>,
+    options: &CargoNdkOptions,
 ) -> Result<Command, NdkError> {
     let triple = target.rust_triple();
     let clang_target = format!("--target={}{}", target.ndk_llvm_triple(), sdk_version);
-    let mut cargo = Command::new("cargo");
+    let mut cargo = ndk.command("cargo");
 
     if color() {
         cargo.arg("--color=always");
     }
-
-    const SEP: &str = "\x1f";
+    if ndk.frozen_env() {
+        cargo.arg("--offline").arg("--locked");
+    }
 
     // Read initial CARGO_ENCODED_/RUSTFLAGS
-    let mut rustflags = match std::env::var("CARGO_ENCODED_RUSTFLAGS") {
-        Ok(val) => {
-            if std::env::var_os("RUSTFLAGS").is_some() {
-                panic!(
-                    "Both `CARGO_ENCODED_RUSTFLAGS` and `RUSTFLAGS` were found in the environment, please clear one or the other before invoking this script"
-                );
-            }
-
-            val
-        }
-        Err(std::env::VarError::NotPresent) => {
-            match std::env::var("RUSTFLAGS") {
-                Ok(val) => {
-                    cargo.env_remove("RUSTFLAGS");
-
-                    // Same as cargo
-                    // https://github.com/rust-lang/cargo/blob/f6de921a5d807746e972d9d10a4d8e1ca21e1b1f/src/cargo/core/compiler/build_context/target_info.rs#L682-L690
-                    val.split(' ')
-                        .map(str::trim)
-                        .filter(|s| !s.is_empty())
-                        .collect::<Vec<_>>()
-                        .join(SEP)
-                }
-                Err(std::env::VarError::NotPresent) => String::new(),
-                Err(std::env::VarError::NotUnicode(_)) => {
-                    panic!("RUSTFLAGS environment variable contains non-unicode characters")
-                }
-            }
-        }
-        Err(std::env::VarError::NotUnicode(_)) => {
-            panic!("CARGO_ENCODED_RUSTFLAGS environment variable contains non-unicode characters")
-        }
-    };
+    let encoded = env::CARGO_ENCODED_RUSTFLAGS.get();
+    let plain = env::RUSTFLAGS.get();
+    if encoded.is_err() && plain.is_ok() {
+        cargo.env_remove("RUSTFLAGS");
+    }
+    let mut rustflags = encoded_rustflags(encoded, plain)?;
 
     let (clang, clang_pp) = ndk.clang()?;
+    let clang = dunce::simplified(&clang);
+    let clang_pp = dunce::simplified(&clang_pp);
 
     // Configure cross-compiler for `cc` crate
     // https://github.com/rust-lang/cc-rs#external-configuration-via-environment-variables
-    cargo.env(format!("CC_{}", triple), &clang);
+    cargo.env(format!("CC_{}", triple), clang);
     cargo.env(format!("CFLAGS_{}", triple), &clang_target);
-    cargo.env(format!("CXX_{}", triple), &clang_pp);
+    cargo.env(format!("CXX_{}", triple), clang_pp);
     cargo.env(format!("CXXFLAGS_{}", triple), &clang_target);
 
     // Configure LINKER for `rustc`
     // https://doc.rust-lang.org/beta/cargo/reference/environment-variables.html#configuration-environment-variables
-    cargo.env(cargo_env_target_cfg("LINKER", triple), &clang);
-    if !rustflags.is_empty() {
-        rustflags.push_str(SEP);
+    cargo.env(cargo_env_target_cfg("LINKER", triple), clang);
+    if !options.replace_default_args {
+        push_flag(&mut rustflags, &format!("-Clink-arg={}", clang_target));
     }
-    rustflags.push_str("-Clink-arg=");
-    rustflags.push_str(&clang_target);
 
     let ar = ndk.toolchain_bin("ar", target)?;
-    cargo.env(format!("AR_{}", triple), &ar);
-    cargo.env(cargo_env_target_cfg("AR", triple), &ar);
+    let ar = dunce::simplified(&ar);
+    cargo.env(format!("AR_{}", triple), ar);
+    cargo.env(cargo_env_target_cfg("AR", triple), ar);
 
     // Workaround for https://github.com/rust-windowing/android-ndk-rs/issues/149:
     // Rust (1.56 as of writing) still requires libgcc during linking, but this does
@@ -84,10 +100,9 @@ pub fn cargo_ndk(
     // See https://github.com/rust-lang/rust/pull/85806 for a discussion on why libgcc
     // is still required even after replacing it with libunwind in the source.
     // XXX: Add an upper-bound on the Rust version whenever this is not necessary anymore.
-    if ndk.build_tag() > 7272597 {
-        let cargo_apk_link_dir = target_dir
-            .as_ref()
-            .join("cargo-apk-temp-extra-link-libraries");
+    if ndk.build_tag() > 7272597 && !options.replace_default_args {
+        let cargo_apk_link_dir =
+            dunce::simplified(target_dir.as_ref()).join("cargo-apk-temp-extra-link-libraries");
         std::fs::create_dir_all(&cargo_apk_link_dir)
             .map_err(|e| NdkError::IoPathError(cargo_apk_link_dir.clone(), e))?;
         let libgcc = cargo_apk_link_dir.join("libgcc.a");
@@ -98,38 +113,137 @@ pub fn cargo_ndk(
         // forwarded to the final compiler invocation rendering our workaround ineffective.
         // The cargo page documenting this discrepancy (https://doc.rust-lang.org/cargo/commands/cargo-rustc.html)
         // suggests to resort to RUSTFLAGS.
-        // Note that `rustflags` will never be empty because of an unconditional `.push_str` above,
-        // so we can safely start with appending \x1f here.
-        rustflags.push_str(SEP);
-        rustflags.push_str("-L");
-        rustflags.push_str(SEP);
-        rustflags.push_str(
+        // Every flag is a separate \x1f-delimited argument that is passed to `rustc`
+        // verbatim, so the path must not be quoted even if it contains spaces.
+        push_flag(&mut rustflags, "-L");
+        push_flag(
+            &mut rustflags,
             cargo_apk_link_dir
                 .to_str()
-                .expect("Target dir must be valid UTF-8"),
+                .ok_or_else(|| NdkError::NonUtf8Path(cargo_apk_link_dir.clone()))?,
+        );
+    }
+
+    if let Some(sanitizer) = options.sanitizer {
+        for flag in sanitizer.rustflags() {
+            push_flag(&mut rustflags, &flag);
+        }
+    }
+
+    if let Some(cpu) = &options.target_cpu {
+        push_flag(&mut rustflags, &format!("-Ctarget-cpu={}", cpu));
+    }
+    if !options.target_features.is_empty() {
+        push_flag(
+            &mut rustflags,
+            &format!("-Ctarget-feature={}", options.target_features.join(",")),
         );
     }
 
+    for flag in &options.rustflags {
+        push_flag(&mut rustflags, flag);
+    }
+
+    for arg in &options.link_args {
+        push_flag(&mut rustflags, &format!("-Clink-arg={}", arg));
+    }
+
     cargo.env("CARGO_ENCODED_RUSTFLAGS", rustflags);
 
     Ok(cargo)
 }
 
+/// The flags that [`cargo_ndk_with()`] takes from `CARGO_ENCODED_RUSTFLAGS` or
+/// `RUSTFLAGS`.
+pub fn env_rustflags() -> Result<Vec<String>, NdkError> {
+    let rustflags = encoded_rustflags(env::CARGO_ENCODED_RUSTFLAGS.get(), env::RUSTFLAGS.get())?;
+    Ok(rustflags
+        .split(SEP)
+        .filter(|flag| !flag.is_empty())
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Returns `CARGO_ENCODED_RUSTFLAGS`, or `RUSTFLAGS` converted to the same
+/// `\x1f`-separated encoding.
+fn encoded_rustflags(
+    encoded: Result<String, std::env::VarError>,
+    plain: Result<String, std::env::VarError>,
+) -> Result<String, NdkError> {
+    Ok(match encoded {
+        Ok(val) => {
+            if plain.is_ok() {
+                return Err(NdkError::ConflictingRustflags);
+            }
+
+            val
+        }
+        Err(std::env::VarError::NotPresent) => {
+            match plain {
+                // Same as cargo
+                // https://github.com/rust-lang/cargo/blob/f6de921a5d807746e972d9d10a4d8e1ca21e1b1f/src/cargo/core/compiler/build_context/target_info.rs#L682-L690
+                Ok(val) => val
+                    .split(' ')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(SEP),
+                Err(std::env::VarError::NotPresent) => String::new(),
+                Err(std::env::VarError::NotUnicode(_)) => {
+                    return Err(NdkError::NonUnicodeEnv("RUSTFLAGS"));
+                }
+            }
+        }
+        Err(std::env::VarError::NotUnicode(_)) => {
+            return Err(NdkError::NonUnicodeEnv("CARGO_ENCODED_RUSTFLAGS"));
+        }
+    })
+}
+
+/// Appends `flag` as a separate argument to the encoded `rustflags`.
+fn push_flag(rustflags: &mut String, flag: &str) {
+    if !rustflags.is_empty() {
+        rustflags.push_str(SEP);
+    }
+    rustflags.push_str(flag);
+}
+
 fn cargo_env_target_cfg(tool: &str, target: &str) -> String {
     let utarget = target.replace('-', "_");
     let env = format!("CARGO_TARGET_{}_{}", &utarget, tool);
     env.to_uppercase()
 }
 
+/// The largest `versionCode` that Google Play accepts.
+pub const MAX_VERSION_CODE: u32 = 2_100_000_000;
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct VersionCode {
-    major: u8,
-    minor: u8,
-    patch: u8,
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+/// The number of bits that [`VersionCode::to_code_with()`] packs each component into.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SemverWidths {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Default for SemverWidths {
+    fn default() -> Self {
+        Self {
+            major: 8,
+            minor: 8,
+            patch: 8,
+        }
+    }
 }
 
 impl VersionCode {
-    pub fn new(major: u8, minor: u8, patch: u8) -> Self {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
         Self {
             major,
             minor,
@@ -148,17 +262,248 @@ impl VersionCode {
         Ok(Self::new(p()?, p()?, p()?))
     }
 
-    pub fn to_code(&self, apk_id: u8) -> u32 {
-        (apk_id as u32) << 24
-            | (self.major as u32) << 16
-            | (self.minor as u32) << 8
-            | self.patch as u32
+    /// Packs the components into 8 bits each, below `apk_id`.
+    pub fn to_code(&self, apk_id: u8) -> Result<u32, NdkError> {
+        self.to_code_with(apk_id, SemverWidths::default(), 0)
+    }
+
+    /// Packs the components into `widths` bits each, below `apk_id`, and adds `offset`.
+    ///
+    /// Fails when a component doesn't fit into its width, or the code exceeds
+    /// [`MAX_VERSION_CODE`].
+    pub fn to_code_with(
+        &self,
+        apk_id: u8,
+        widths: SemverWidths,
+        offset: u32,
+    ) -> Result<u32, NdkError> {
+        let mut code = apk_id as u64;
+        for (component, value, bits) in [
+            ("major", self.major, widths.major),
+            ("minor", self.minor, widths.minor),
+            ("patch", self.patch, widths.patch),
+        ] {
+            let max = (1u64 << bits.min(32)) - 1;
+            if value as u64 > max {
+                return Err(NdkError::VersionCodeComponentTooLarge {
+                    component,
+                    value,
+                    max,
+                });
+            }
+            code = code.checked_shl(bits.into()).unwrap_or(u64::MAX) | value as u64;
+        }
+        with_offset(code, offset)
+    }
+
+    /// The `yyMMddNN` code of the `build`th build on the date, plus `offset`.
+    pub fn date_code(
+        year: u32,
+        month: u32,
+        day: u32,
+        build: u32,
+        offset: u32,
+    ) -> Result<u32, NdkError> {
+        for (component, value, range) in [
+            ("year", year, 2000..=2099),
+            ("month", month, 1..=12),
+            ("day", day, 1..=31),
+            ("build number", build, 0..=99),
+        ] {
+            if !range.contains(&value) {
+                return Err(NdkError::VersionCodeComponentTooLarge {
+                    component,
+                    value,
+                    max: *range.end() as u64,
+                });
+            }
+        }
+        let code = (year % 100) * 1_000_000 + month * 10_000 + day * 100 + build;
+        with_offset(code.into(), offset)
+    }
+
+    /// `code` as is, plus `offset`.
+    pub fn explicit_code(code: u32, offset: u32) -> Result<u32, NdkError> {
+        with_offset(code.into(), offset)
     }
 }
 
+fn with_offset(code: u64, offset: u32) -> Result<u32, NdkError> {
+    let code = code.saturating_add(offset.into());
+    if code > MAX_VERSION_CODE.into() {
+        return Err(NdkError::VersionCodeTooLarge(code));
+    }
+    Ok(code as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn paths_with_spaces() {
+        let dir = TestDir::new("cargo");
+        let root = dir.join("Program Files (x86)");
+        let ndk = crate::ndk::tests::fake_ndk_with_toolchain(&root);
+        // Longer than `MAX_PATH` on Windows
+        let target_dir = (0..20).fold(root.join("target"), |dir, i| {
+            dir.join(format!("nested directory {}", i))
+        });
+        assert!(target_dir.as_os_str().len() > 260);
+
+        let cargo = cargo_ndk(&ndk, Target::Arm64V8a, 23, &target_dir).unwrap();
+        let env = |key: &str| {
+            cargo
+                .get_envs()
+                .find(|(k, _)| *k == key)
+                .and_then(|(_, v)| v)
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap()
+        };
+
+        let clang = env("CC_aarch64-linux-android");
+        assert!(clang.starts_with(root.to_str().unwrap()));
+        assert_eq!(
+            env("CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER"),
+            clang,
+            "the linker path must not be quoted or otherwise altered"
+        );
+
+        let rustflags = env("CARGO_ENCODED_RUSTFLAGS");
+        let link_dir = target_dir.join("cargo-apk-temp-extra-link-libraries");
+        let flags = rustflags.split('\x1f').collect::<Vec<_>>();
+        let l = flags.iter().position(|f| *f == "-L").unwrap();
+        assert_eq!(flags[l + 1], link_dir.to_str().unwrap());
+        assert!(link_dir.join("libgcc.a").exists());
+    }
+
+    fn rustflags(options: &CargoNdkOptions) -> (String, String) {
+        rustflags_for(Target::Arm64V8a, options)
+    }
+
+    fn rustflags_for(target: Target, options: &CargoNdkOptions) -> (String, String) {
+        let root = TestDir::new("link-args");
+        let ndk = crate::ndk::tests::fake_ndk_with_toolchain(&root);
+        let target_dir = root.join("target");
+        let cargo = cargo_ndk_with(&ndk, target, 23, &target_dir, options).unwrap();
+        let rustflags = cargo
+            .get_envs()
+            .find(|(k, _)| *k == "CARGO_ENCODED_RUSTFLAGS")
+            .and_then(|(_, v)| v)
+            .map(|v| v.to_str().unwrap().to_string())
+            .unwrap();
+        let link_dir = target_dir.join("cargo-apk-temp-extra-link-libraries");
+        (rustflags, link_dir.to_str().unwrap().to_string())
+    }
+
+    /// The flags inherited from the environment running the tests
+    fn inherited() -> String {
+        let mut inherited =
+            encoded_rustflags(env::CARGO_ENCODED_RUSTFLAGS.get(), env::RUSTFLAGS.get()).unwrap();
+        if !inherited.is_empty() {
+            inherited.push_str(SEP);
+        }
+        inherited
+    }
+
+    #[test]
+    fn link_args() {
+        let (rustflags, link_dir) = rustflags(&CargoNdkOptions {
+            link_args: vec![
+                "-Wl,-z,max-page-size=16384".to_string(),
+                "-Wl,--icf=all".to_string(),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(
+            rustflags,
+            format!(
+                "{}-Clink-arg=--target=aarch64-linux-android23\x1f-L\x1f{}\x1f\
+                -Clink-arg=-Wl,-z,max-page-size=16384\x1f-Clink-arg=-Wl,--icf=all",
+                inherited(),
+                link_dir
+            )
+        );
+    }
+
+    #[test]
+    fn replace_default_linker_args() {
+        let (rustflags, _) = rustflags(&CargoNdkOptions {
+            link_args: vec!["--target=aarch64-linux-android29".to_string()],
+            replace_default_args: true,
+            sanitizer: Some(Sanitizer::Hwaddress),
+            rustflags: vec!["-Copt-level=z".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            rustflags,
+            format!(
+                "{}-Zsanitizer=hwaddress\x1f-Ctarget-feature=+tagged-globals\x1f\
+                -Clink-arg=-fsanitize=hwaddress\x1f-Copt-level=z\x1f\
+                -Clink-arg=--target=aarch64-linux-android29",
+                inherited()
+            )
+        );
+    }
+
+    #[test]
+    fn target_codegen() {
+        let (armv7, link_dir) = rustflags_for(
+            Target::ArmV7a,
+            &CargoNdkOptions {
+                target_cpu: Some("cortex-a7".to_string()),
+                target_features: vec!["+neon".to_string(), "+vfp4".to_string()],
+                rustflags: vec!["-Copt-level=s".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            armv7,
+            format!(
+                "{}-Clink-arg=--target=armv7a-linux-androideabi23\x1f-L\x1f{}\x1f\
+                -Ctarget-cpu=cortex-a7\x1f-Ctarget-feature=+neon,+vfp4\x1f-Copt-level=s",
+                inherited(),
+                link_dir
+            )
+        );
+        let (arm64, _) = rustflags(&CargoNdkOptions {
+            rustflags: vec!["-Copt-level=s".to_string()],
+            ..Default::default()
+        });
+        assert!(!arm64.contains("-Ctarget-"), "{}", arm64);
+    }
+
+    #[test]
+    fn merge_rustflags() {
+        use std::env::VarError::NotPresent;
+        assert_eq!(
+            encoded_rustflags(
+                Err(NotPresent),
+                Ok(" -Ctarget-cpu=native  --cfg  foo ".into())
+            )
+            .unwrap(),
+            "-Ctarget-cpu=native\x1f--cfg\x1ffoo"
+        );
+        assert_eq!(
+            encoded_rustflags(Ok("--cfg\x1ffoo bar".into()), Err(NotPresent)).unwrap(),
+            "--cfg\x1ffoo bar"
+        );
+        assert_eq!(
+            encoded_rustflags(Err(NotPresent), Err(NotPresent)).unwrap(),
+            ""
+        );
+        assert_eq!(
+            encoded_rustflags(Ok("--cfg\x1ffoo".into()), Ok("--cfg foo".into()))
+                .unwrap_err()
+                .to_string(),
+            "Both `CARGO_ENCODED_RUSTFLAGS` and `RUSTFLAGS` are set, unset one of them"
+        );
+
+        let mut rustflags = encoded_rustflags(Err(NotPresent), Ok("-Dwarnings".into())).unwrap();
+        push_flag(&mut rustflags, "-Clink-arg=-Wl,--icf=all");
+        assert_eq!(rustflags, "-Dwarnings\x1f-Clink-arg=-Wl,--icf=all");
+    }
 
     #[test]
     fn from_semver() {
@@ -167,4 +512,75 @@ mod tests {
         let v = VersionCode::from_semver("254.254.254-alpha.fix+2").unwrap();
         assert_eq!(v, VersionCode::new(254, 254, 254));
     }
+
+    #[test]
+    fn semver_codes() {
+        let v = VersionCode::new(1, 2, 3);
+        assert_eq!(v.to_code(1).unwrap(), 0x01_01_02_03);
+        let widths = SemverWidths {
+            major: 4,
+            minor: 10,
+            patch: 6,
+        };
+        assert_eq!(
+            VersionCode::new(1, 300, 63)
+                .to_code_with(1, widths, 100_000)
+                .unwrap(),
+            (1 << 20 | 1 << 16 | 300 << 6 | 63) + 100_000
+        );
+        assert!(matches!(
+            VersionCode::new(1, 2, 64).to_code_with(1, widths, 0),
+            Err(NdkError::VersionCodeComponentTooLarge {
+                component: "patch",
+                value: 64,
+                max: 63
+            })
+        ));
+        assert!(matches!(
+            VersionCode::new(1, 256, 0).to_code(1),
+            Err(NdkError::VersionCodeComponentTooLarge {
+                component: "minor",
+                ..
+            })
+        ));
+        // 2^31 and up is more than Play accepts
+        let wide = SemverWidths {
+            major: 8,
+            minor: 12,
+            patch: 12,
+        };
+        assert!(matches!(
+            VersionCode::new(0, 0, 0).to_code_with(1, wide, 0),
+            Err(NdkError::VersionCodeTooLarge(0x1_00_00_00_00))
+        ));
+        assert!(matches!(
+            VersionCode::new(1, 2, 3).to_code_with(1, SemverWidths::default(), MAX_VERSION_CODE),
+            Err(NdkError::VersionCodeTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn date_codes() {
+        assert_eq!(
+            VersionCode::date_code(2024, 5, 1, 0, 0).unwrap(),
+            24_05_01_00
+        );
+        assert_eq!(
+            VersionCode::date_code(2031, 12, 31, 99, 1_000_000_000).unwrap(),
+            1_031_123_199
+        );
+        assert!(matches!(
+            VersionCode::date_code(2024, 5, 1, 100, 0),
+            Err(NdkError::VersionCodeComponentTooLarge {
+                component: "build number",
+                value: 100,
+                max: 99
+            })
+        ));
+        assert!(matches!(
+            VersionCode::date_code(2024, 5, 1, 0, 2_080_000_000),
+            Err(NdkError::VersionCodeTooLarge(2_104_050_100))
+        ));
+        assert_eq!(VersionCode::explicit_code(42, 100_000).unwrap(), 100_042);
+    }
 }
@@ -0,0 +1,237 @@
+//! SHA-256 digests of prebuilt libraries, which
+//! [`UnalignedApk::add_runtime_libs()`](crate::apk::UnalignedApk::add_runtime_libs)
+//! verifies before packaging them.
+
+use crate::error::NdkError;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+/// The expected digest of a file.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct LibChecksum {
+    /// Path relative to the `runtime_libs` directory with `/` separators, like
+    /// `arm64-v8a/libfoo.so`
+    pub file: String,
+    /// Lowercase or uppercase hex
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LibChecksums {
+    pub entries: Vec<LibChecksum>,
+    /// Warn about files without an entry
+    pub require: bool,
+}
+
+impl LibChecksums {
+    /// Fails with [`NdkError::ChecksumMismatch`] if `path`, named `file` in
+    /// [`LibChecksum::file`], doesn't have the digest of its entry. Files without an
+    /// entry are not hashed.
+    pub fn verify(&self, file: &str, path: &Path) -> Result<(), NdkError> {
+        let Some(entry) = self.entries.iter().find(|entry| entry.file == file) else {
+            if self.require {
+                eprintln!(
+                    "Warning: `{}` has no entry in `runtime_lib_checksums`, generate one with \
+                    `cargo apk hash-libs`",
+                    file
+                );
+            }
+            return Ok(());
+        };
+        let actual = sha256_file(path)?;
+        if !actual.eq_ignore_ascii_case(&entry.sha256) {
+            return Err(NdkError::ChecksumMismatch {
+                file: path.to_owned(),
+                expected: entry.sha256.to_lowercase(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The SHA-256 of the file at `path` in lowercase hex, read in chunks.
+pub fn sha256_file(path: &Path) -> Result<String, NdkError> {
+    let io_error = |e| NdkError::IoPathError(path.to_owned(), e);
+    let mut file = std::fs::File::open(path).map_err(io_error)?;
+    let mut sha256 = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf).map_err(io_error)? {
+            0 => break,
+            len => sha256.update(&buf[..len]),
+        }
+    }
+    Ok(sha256
+        .finish()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 as specified by FIPS 180-4, fed in pieces of any length.
+#[derive(Clone)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    /// Bytes hashed so far
+    len: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let take = data.len().min(64 - self.block_len);
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn hex(data: &[u8]) -> String {
+        let mut sha256 = Sha256::new();
+        // Uneven pieces cross the block boundaries
+        for piece in data.chunks(7) {
+            sha256.update(piece);
+        }
+        sha256
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[test]
+    fn digests() {
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let dir = TestDir::new("checksums");
+        let lib = dir.join("libfoo.so");
+        std::fs::write(&lib, "abc").unwrap();
+        let checksums = |sha256: &str| LibChecksums {
+            entries: vec![LibChecksum {
+                file: "arm64-v8a/libfoo.so".to_string(),
+                sha256: sha256.to_string(),
+            }],
+            require: true,
+        };
+        let abc = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        checksums(abc).verify("arm64-v8a/libfoo.so", &lib).unwrap();
+        checksums(abc).verify("x86/libfoo.so", &lib).unwrap();
+        let error = checksums(&"0".repeat(64))
+            .verify("arm64-v8a/libfoo.so", &lib)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            NdkError::ChecksumMismatch { file, expected, actual }
+                if file == lib && expected == "0".repeat(64) && actual == abc.to_lowercase()
+        ));
+    }
+}
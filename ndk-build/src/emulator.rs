@@ -0,0 +1,182 @@
+use crate::error::NdkError;
+use crate::ndk::Ndk;
+use crate::util::{ManagedChild, echo_command, output_error, output_timeout};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Options for launching an Android Virtual Device through [`Emulator::start`].
+#[derive(Clone, Debug)]
+pub struct EmulatorOptions {
+    /// Run the emulator headless through `-no-window`
+    pub no_window: bool,
+    /// GPU emulation mode passed to `-gpu`, e.g. `swiftshader_indirect`
+    pub gpu: Option<String>,
+    /// Maximum time to wait for `sys.boot_completed`
+    pub boot_timeout: Duration,
+    /// Extra arguments passed to the `emulator` binary verbatim
+    pub extra_args: Vec<String>,
+}
+
+impl Default for EmulatorOptions {
+    fn default() -> Self {
+        Self {
+            no_window: true,
+            gpu: Some("swiftshader_indirect".to_string()),
+            boot_timeout: Duration::from_secs(300),
+            extra_args: vec![],
+        }
+    }
+}
+
+/// A running emulator instance, started by [`Emulator::start`].
+///
+/// Dropping this does not stop the emulator; use [`Emulator::stop`] for that.
+pub struct Emulator {
+    avd: String,
+    serial: String,
+    /// Detached, so that the emulator keeps running after `cargo apk` exits
+    process: ManagedChild,
+    log: PathBuf,
+}
+
+impl Emulator {
+    /// Starts `avd` on the first free console port and blocks until it reports
+    /// `sys.boot_completed`.
+    pub fn start(ndk: &Ndk, avd: &str, options: &EmulatorOptions) -> Result<Self, NdkError> {
+        let port = free_console_port(ndk)?;
+        let serial = format!("emulator-{}", port);
+
+        // Write the emulator output to a file instead of a pipe, so that the emulator
+        // can outlive this process without getting killed by `SIGPIPE`.
+        let log = std::env::temp_dir().join(format!("cargo-apk-emulator-{}.log", port));
+        let log_file = File::create(&log).map_err(|e| NdkError::IoPathError(log.clone(), e))?;
+
+        let mut emulator = ndk.emulator()?;
+        emulator
+            .arg("-avd")
+            .arg(avd)
+            .arg("-port")
+            .arg(port.to_string());
+        if options.no_window {
+            emulator.arg("-no-window");
+        }
+        if let Some(gpu) = &options.gpu {
+            emulator.arg("-gpu").arg(gpu);
+        }
+        emulator
+            .args(&options.extra_args)
+            .stdin(Stdio::null())
+            .stdout(log_file.try_clone()?)
+            .stderr(log_file);
+
+        echo_command(&emulator);
+        let process = ManagedChild::spawn_detached(&mut emulator)
+            .map_err(|e| NdkError::cmd_failed(&emulator, None, e))?;
+        let mut emulator = Self {
+            avd: avd.to_string(),
+            serial,
+            process,
+            log,
+        };
+        emulator.wait_for_boot(ndk, options.boot_timeout)?;
+        Ok(emulator)
+    }
+
+    fn wait_for_boot(&mut self, ndk: &Ndk, timeout: Duration) -> Result<(), NdkError> {
+        let start = Instant::now();
+        loop {
+            if self.process.try_wait()?.is_some() {
+                return Err(NdkError::EmulatorExited {
+                    avd: self.avd.clone(),
+                    output: self.output(),
+                });
+            }
+
+            // Fails until the device shows up in `adb devices`
+            let mut adb = ndk.adb(Some(&self.serial))?;
+            adb.arg("shell").arg("getprop").arg("sys.boot_completed");
+            let boot_completed = output_timeout(&mut adb, ndk.adb_timeout())?;
+            if boot_completed.status.success()
+                && String::from_utf8_lossy(&boot_completed.stdout).trim() == "1"
+            {
+                return Ok(());
+            }
+
+            if start.elapsed() > timeout {
+                let _ = self.process.kill();
+                return Err(NdkError::EmulatorBootTimeout {
+                    avd: self.avd.clone(),
+                    timeout,
+                    output: self.output(),
+                });
+            }
+            sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// The `adb` serial of this emulator, e.g. `emulator-5554`.
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Shuts the emulator down through `adb emu kill` and waits for it to exit.
+    pub fn stop(mut self, ndk: &Ndk) -> Result<(), NdkError> {
+        let mut adb = ndk.adb(Some(&self.serial))?;
+        adb.arg("emu").arg("kill");
+        if output_error(adb, ndk.adb_timeout()).is_err() {
+            self.process.kill()?;
+        }
+        self.process.wait()?;
+        let _ = std::fs::remove_file(&self.log);
+        Ok(())
+    }
+
+    fn output(&self) -> String {
+        std::fs::read_to_string(&self.log).unwrap_or_default()
+    }
+}
+
+/// Emulators listen on even console ports between `5554` and `5682`, and show
+/// up in `adb devices` as `emulator-<port>`.
+fn free_console_port(ndk: &Ndk) -> Result<u16, NdkError> {
+    let devices = ndk.devices()?;
+    (5554..=5682)
+        .step_by(2)
+        .find(|port| {
+            let serial = format!("emulator-{}", port);
+            devices.iter().all(|d| d.serial != serial)
+        })
+        .ok_or(NdkError::NoFreeEmulatorPort)
+}
+
+/// Lists the names of all Android Virtual Devices known to the emulator.
+pub fn list_avds(ndk: &Ndk) -> Result<Vec<String>, NdkError> {
+    let mut emulator = ndk.emulator()?;
+    emulator.arg("-list-avds");
+    let output = output_error(emulator, None)?;
+    Ok(parse_avds(&String::from_utf8_lossy(&output)))
+}
+
+fn parse_avds(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        // The emulator prints diagnostics such as `INFO    | ...` before the list
+        .filter(|line| !line.is_empty() && !line.contains('|'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avds() {
+        let output = "INFO    | Storing crashdata in: /tmp/android-user/emu-crash.db\nPixel_6_API_33\n\nNexus_6_API_29\n";
+        assert_eq!(parse_avds(output), ["Pixel_6_API_33", "Nexus_6_API_29"]);
+    }
+}
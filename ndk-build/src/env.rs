@@ -0,0 +1,433 @@
+//! The environment variables read by `ndk-build` and `cargo-apk`.
+//!
+//! Every lookup goes through an [`EnvVar`] of [`ALL`], so that `cargo apk env` lists
+//! exactly what influences a build.
+
+use std::collections::BTreeMap;
+use std::env::VarError;
+use std::ffi::OsString;
+
+/// An environment variable, with its name and what it configures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvVar {
+    /// The name, or a template with `<PROFILE>`-style placeholders, see [`EnvVar::expand()`]
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Whether the value is a password, which [`EnvValue`] renders as `***`
+    pub secret: bool,
+}
+
+impl EnvVar {
+    const fn new(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            secret: false,
+        }
+    }
+
+    const fn secret(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            secret: true,
+        }
+    }
+
+    /// Like [`std::env::var()`].
+    pub fn get(&self) -> Result<String, VarError> {
+        debug_assert!(!self.is_template(), "`{}` must be expanded", self.name);
+        std::env::var(self.name)
+    }
+
+    /// Like [`std::env::var_os()`].
+    pub fn get_os(&self) -> Option<OsString> {
+        debug_assert!(!self.is_template(), "`{}` must be expanded", self.name);
+        std::env::var_os(self.name)
+    }
+
+    pub fn is_template(&self) -> bool {
+        self.name.contains('<')
+    }
+
+    /// Replaces the placeholders of the name by `values`, in order, uppercased and with
+    /// `-` replaced by `_` like cargo does for profile names.
+    pub fn expand(&self, values: &[&str]) -> String {
+        let mut name = String::new();
+        let mut rest = self.name;
+        for value in values {
+            let (Some(start), Some(end)) = (rest.find('<'), rest.find('>')) else {
+                break;
+            };
+            name.push_str(&rest[..start]);
+            name.push_str(&value.to_uppercase().replace('-', "_"));
+            rest = &rest[end + 1..];
+        }
+        debug_assert!(!rest.contains('<'), "Too few values for `{}`", self.name);
+        name.push_str(rest);
+        name
+    }
+
+    /// Whether `name` is this variable, or an expansion of this template.
+    pub fn matches(&self, name: &str) -> bool {
+        match (self.name.find('<'), self.name.rfind('>')) {
+            (Some(start), Some(end)) => {
+                let (prefix, suffix) = (&self.name[..start], &self.name[end + 1..]);
+                name.len() > prefix.len() + suffix.len()
+                    && name.starts_with(prefix)
+                    && name.ends_with(suffix)
+            }
+            _ => name == self.name,
+        }
+    }
+}
+
+/// Reads a variable whose name was [expanded](EnvVar::expand) from a template of [`ALL`].
+pub fn var(name: &str) -> Result<String, VarError> {
+    debug_assert!(is_known(name), "`{}` is not registered in `ALL`", name);
+    std::env::var(name)
+}
+
+/// Like [`var()`], for [`std::env::var_os()`].
+pub fn var_os(name: &str) -> Option<OsString> {
+    debug_assert!(is_known(name), "`{}` is not registered in `ALL`", name);
+    std::env::var_os(name)
+}
+
+/// Like [`var()`], but reads `overrides` first, e.g. [`NdkOptions::env`].
+///
+/// [`NdkOptions::env`]: crate::ndk::NdkOptions::env
+pub fn var_with(overrides: &BTreeMap<String, String>, name: &str) -> Option<String> {
+    match overrides.get(name) {
+        Some(value) => Some(value.clone()),
+        None => var(name).ok(),
+    }
+}
+
+/// Whether a variable that the user named, e.g. in the `[env]` table of a cargo
+/// config, is set. Unlike [`var()`], `name` does not have to be in [`ALL`].
+pub fn is_set(name: &str) -> bool {
+    std::env::var_os(name).is_some()
+}
+
+/// The value of a variable that the user named, e.g. as `$VAR` in a path setting, if it
+/// is set to valid unicode. Like [`is_set()`], `name` does not have to be in [`ALL`].
+pub fn user_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Like [`is_set()`], the value of a variable that the user named, e.g. for a header of
+/// a `[package.metadata.android.publish]` backend.
+pub fn configured(name: &str) -> Result<String, VarError> {
+    std::env::var(name)
+}
+
+fn is_known(name: &str) -> bool {
+    ALL.iter().any(|var| var.matches(name))
+}
+
+pub const ANDROID_HOME: EnvVar = EnvVar::new("ANDROID_HOME", "Android SDK root");
+pub const ANDROID_SDK_ROOT: EnvVar = EnvVar::new(
+    "ANDROID_SDK_ROOT",
+    "Deprecated Android SDK root, takes precedence over `ANDROID_HOME`",
+);
+pub const LOCALAPPDATA: EnvVar = EnvVar::new(
+    "LOCALAPPDATA",
+    "Windows only: holds the default SDK location `Android\\Sdk`",
+);
+pub const ANDROID_NDK_ROOT: EnvVar = EnvVar::new("ANDROID_NDK_ROOT", "Android NDK root");
+pub const ANDROID_NDK_PATH: EnvVar = EnvVar::new(
+    "ANDROID_NDK_PATH",
+    "Android NDK root, if `ANDROID_NDK_ROOT` is not set",
+);
+pub const ANDROID_NDK_HOME: EnvVar = EnvVar::new(
+    "ANDROID_NDK_HOME",
+    "Android NDK root, if `ANDROID_NDK_PATH` is not set",
+);
+pub const NDK_HOME: EnvVar = EnvVar::new(
+    "NDK_HOME",
+    "Android NDK root, if `ANDROID_NDK_HOME` is not set",
+);
+pub const ANDROID_BUILD_TOOLS_VERSION: EnvVar = EnvVar::new(
+    "ANDROID_BUILD_TOOLS_VERSION",
+    "`build-tools` version, overrides the `build_tools_version` metadata",
+);
+pub const ANDROID_SDK_HOME: EnvVar = EnvVar::new(
+    "ANDROID_SDK_HOME",
+    "Deprecated parent of the `.android` directory, takes precedence over `ANDROID_USER_HOME`",
+);
+pub const ANDROID_USER_HOME: EnvVar = EnvVar::new(
+    "ANDROID_USER_HOME",
+    "`.android` directory with the debug keystore and AVDs, defaults to `~/.android`",
+);
+pub const ANDROID_SERIAL: EnvVar =
+    EnvVar::new("ANDROID_SERIAL", "Default for `--device`, like for `adb`");
+pub const JAVA_HOME: EnvVar = EnvVar::new(
+    "JAVA_HOME",
+    "JDK to take `keytool` from, if it is not on `PATH`",
+);
+pub const HOST: EnvVar = EnvVar::new(
+    "HOST",
+    "Host triple selecting the NDK's prebuilt tools, defaults to the running OS",
+);
+pub const CARGO_HOME: EnvVar = EnvVar::new(
+    "CARGO_HOME",
+    "Directory of the user's `config.toml`, defaults to `~/.cargo`",
+);
+pub const CARGO_ENCODED_RUSTFLAGS: EnvVar = EnvVar::new(
+    "CARGO_ENCODED_RUSTFLAGS",
+    "Extra `rustc` flags, separated by `\\x1f`",
+);
+pub const RUSTFLAGS: EnvVar = EnvVar::new("RUSTFLAGS", "Extra `rustc` flags, separated by spaces");
+pub const CARGO_PROFILE: EnvVar = EnvVar::new(
+    "CARGO_PROFILE_<PROFILE>_<KEY>",
+    "Cargo profile setting, an `optimize` preset does not override it",
+);
+pub const CARGO_APK_KEYSTORE: EnvVar = EnvVar::new(
+    "CARGO_APK_<PROFILE>_KEYSTORE",
+    "Keystore for the profile, overrides `[package.metadata.android.signing.<profile>]`",
+);
+pub const CARGO_APK_KEYSTORE_PASSWORD: EnvVar = EnvVar::secret(
+    "CARGO_APK_<PROFILE>_KEYSTORE_PASSWORD",
+    "Password of `CARGO_APK_<PROFILE>_KEYSTORE`",
+);
+pub const CARGO_APK_OUTPUT_LIMIT: EnvVar = EnvVar::new(
+    "CARGO_APK_OUTPUT_LIMIT",
+    "Bytes of tool output embedded in errors, defaults to 16 KiB",
+);
+pub const CARGO_APK_BUG_REPORT: EnvVar = EnvVar::new(
+    "CARGO_APK_BUG_REPORT",
+    "Set to `1` to write a report into the build directory when a command fails, like `--bug-report`",
+);
+pub const SOURCE_DATE_EPOCH: EnvVar = EnvVar::new(
+    "SOURCE_DATE_EPOCH",
+    "Build time recorded by `stamp_build_info`, in seconds since the Unix epoch",
+);
+pub const DISPLAY: EnvVar = EnvVar::new(
+    "DISPLAY",
+    "X11 display that `cargo apk build --open` shows the apk on",
+);
+pub const WAYLAND_DISPLAY: EnvVar = EnvVar::new(
+    "WAYLAND_DISPLAY",
+    "Wayland display that `cargo apk build --open` shows the apk on",
+);
+pub const ALWAYS_COLOR: EnvVar = EnvVar::new("ALWAYS_COLOR", "Force colored output");
+pub const NO_COLOR: EnvVar = EnvVar::new(
+    "NO_COLOR",
+    "Disable colored output, unless `ALWAYS_COLOR` is set",
+);
+pub const RUST_LOG: EnvVar = EnvVar::new(
+    "RUST_LOG",
+    "Log filter, e.g. `info` to print where the SDK and NDK were found",
+);
+pub const COMPLETE: EnvVar = EnvVar::new(
+    "COMPLETE",
+    "Set by the shell completion script to request completions",
+);
+
+/// Every variable that is read, grouped by what it configures.
+pub const ALL: &[EnvVar] = &[
+    ANDROID_HOME,
+    ANDROID_SDK_ROOT,
+    LOCALAPPDATA,
+    ANDROID_NDK_ROOT,
+    ANDROID_NDK_PATH,
+    ANDROID_NDK_HOME,
+    NDK_HOME,
+    ANDROID_BUILD_TOOLS_VERSION,
+    ANDROID_SDK_HOME,
+    ANDROID_USER_HOME,
+    ANDROID_SERIAL,
+    JAVA_HOME,
+    HOST,
+    CARGO_HOME,
+    CARGO_ENCODED_RUSTFLAGS,
+    RUSTFLAGS,
+    CARGO_PROFILE,
+    CARGO_APK_KEYSTORE,
+    CARGO_APK_KEYSTORE_PASSWORD,
+    CARGO_APK_OUTPUT_LIMIT,
+    CARGO_APK_BUG_REPORT,
+    SOURCE_DATE_EPOCH,
+    DISPLAY,
+    WAYLAND_DISPLAY,
+    ALWAYS_COLOR,
+    NO_COLOR,
+    RUST_LOG,
+    COMPLETE,
+];
+
+/// A variable of [`ALL`] as found in the environment, see [`lookup()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvValue {
+    pub var: EnvVar,
+    /// [`EnvVar::name`], or the name of a set variable matching the template
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl std::fmt::Display for EnvValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(_) if self.var.secret => write!(f, "{}=***", self.name),
+            Some(value) => write!(f, "{}={}", self.name, value),
+            None => write!(f, "{} (not set)", self.name),
+        }
+    }
+}
+
+/// Looks up every variable of [`ALL`] in `env`. A template is listed once for every
+/// variable matching it, or unexpanded if there is none.
+pub fn lookup(env: &[(String, String)]) -> Vec<EnvValue> {
+    let mut values = Vec::new();
+    for var in ALL {
+        let mut set = env
+            .iter()
+            .filter(|(name, _)| owner(name) == Some(var))
+            .map(|(name, value)| EnvValue {
+                var: *var,
+                name: name.clone(),
+                value: Some(value.clone()),
+            })
+            .collect::<Vec<_>>();
+        if set.is_empty() {
+            set.push(EnvValue {
+                var: *var,
+                name: var.name.to_string(),
+                value: None,
+            });
+        }
+        set.sort_by(|a, b| a.name.cmp(&b.name));
+        values.extend(set);
+    }
+    values
+}
+
+/// The variable of [`ALL`] that `name` is, preferring exact names over templates and
+/// longer templates over shorter ones.
+fn owner(name: &str) -> Option<&'static EnvVar> {
+    ALL.iter().find(|var| var.name == name).or_else(|| {
+        ALL.iter()
+            .filter(|var| var.is_template() && var.matches(name))
+            .max_by_key(|var| var.name.len())
+    })
+}
+
+/// [`lookup()`] in the environment of this process.
+pub fn current() -> Vec<EnvValue> {
+    current_with(&BTreeMap::new())
+}
+
+/// [`current()`] with `overrides` taking the place of the variables of this process,
+/// like [`NdkOptions::env`](crate::ndk::NdkOptions::env).
+pub fn current_with(overrides: &BTreeMap<String, String>) -> Vec<EnvValue> {
+    let mut env = std::env::vars_os()
+        .map(|(name, value)| {
+            (
+                name.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            )
+        })
+        .filter(|(name, _)| !overrides.contains_key(name))
+        .collect::<Vec<_>>();
+    env.extend(overrides.clone());
+    lookup(&env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn expand() {
+        assert_eq!(
+            CARGO_APK_KEYSTORE_PASSWORD.expand(&["release-lto"]),
+            "CARGO_APK_RELEASE_LTO_KEYSTORE_PASSWORD"
+        );
+        assert_eq!(
+            CARGO_PROFILE.expand(&["dev", "opt-level"]),
+            "CARGO_PROFILE_DEV_OPT_LEVEL"
+        );
+        assert!(CARGO_APK_KEYSTORE.matches("CARGO_APK_DEV_KEYSTORE"));
+        assert!(!CARGO_APK_KEYSTORE.matches("CARGO_APK__KEYSTORE"));
+        assert!(!CARGO_APK_KEYSTORE.matches("CARGO_APK_DEV_KEYSTORE_PASSWORD"));
+        assert!(ANDROID_HOME.matches("ANDROID_HOME"));
+    }
+
+    #[test]
+    fn lookup_redacts_secrets() {
+        let env = [
+            ("ANDROID_HOME", "/sdk"),
+            ("CARGO_APK_OUTPUT_LIMIT", "1024"),
+            ("CARGO_APK_RELEASE_KEYSTORE", "release.keystore"),
+            ("CARGO_APK_RELEASE_KEYSTORE_PASSWORD", "hunter2"),
+            ("CARGO_APK_DEV_KEYSTORE", "dev.keystore"),
+            ("PATH", "/bin"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let values = lookup(&env);
+        assert_eq!(values.len(), ALL.len() + 1);
+        let lines = values.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let find = |prefix: &str| {
+            lines
+                .iter()
+                .filter(|line| line.starts_with(prefix))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("ANDROID_HOME"), ["ANDROID_HOME=/sdk"]);
+        assert_eq!(find("ANDROID_SDK_ROOT"), ["ANDROID_SDK_ROOT (not set)"]);
+        assert_eq!(
+            find("CARGO_APK_OUTPUT_LIMIT"),
+            ["CARGO_APK_OUTPUT_LIMIT=1024"]
+        );
+        assert_eq!(
+            find("CARGO_APK_"),
+            [
+                "CARGO_APK_DEV_KEYSTORE=dev.keystore",
+                "CARGO_APK_RELEASE_KEYSTORE=release.keystore",
+                "CARGO_APK_RELEASE_KEYSTORE_PASSWORD=***",
+                "CARGO_APK_OUTPUT_LIMIT=1024",
+                "CARGO_APK_BUG_REPORT (not set)",
+            ]
+        );
+        assert_eq!(
+            find("CARGO_PROFILE"),
+            ["CARGO_PROFILE_<PROFILE>_<KEY> (not set)"]
+        );
+    }
+
+    /// Lines in the `.rs` files under `dir` that read the environment without an [`EnvVar`].
+    fn direct_lookups(dir: &Path) -> Vec<String> {
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                found.extend(direct_lookups(&path));
+            } else if path.extension().is_some_and(|ext| ext == "rs") && !path.ends_with("env.rs") {
+                let contents = std::fs::read_to_string(&path).unwrap();
+                for (i, line) in contents.lines().enumerate() {
+                    // Also catches imports like `use std::{env::var, ...}`
+                    if line.contains("std::env::var") || line.contains("env::var,") {
+                        found.push(format!("{}:{}: {}", path.display(), i + 1, line.trim()));
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// All lookups go through [`ALL`], so that `cargo apk env` cannot miss any.
+    #[test]
+    fn no_direct_lookups() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut found = direct_lookups(&root.join("src"));
+        // Only available in the workspace, not in the published crate
+        let cargo_apk = root.join("../cargo-apk/src");
+        if cargo_apk.exists() {
+            found.extend(direct_lookups(&cargo_apk));
+        }
+        assert_eq!(found, Vec::<String>::new());
+    }
+}
@@ -1,7 +1,9 @@
+use crate::apk::InstallError;
+use crate::target::Target;
 use std::io::Error as IoError;
 use std::num::ParseIntError;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -32,10 +34,32 @@ pub enum NdkError {
     CmdNotFound(String),
     #[error("Android SDK has no build tools.")]
     BuildToolsNotFound,
-    #[error("Android SDK has no platforms installed.")]
-    NoPlatformFound,
+    #[error(
+        "Build tools version `{version}` is not installed, installed versions: {}",
+        .installed.join(", ")
+    )]
+    BuildToolsVersionNotFound {
+        version: String,
+        installed: Vec<String>,
+    },
+    #[error(
+        "Android SDK has no platforms installed that the NDK supports, `platforms` contains: {}",
+        platform_list(.installed)
+    )]
+    NoPlatformFound { installed: Vec<String> },
+    #[error(
+        "Platform `android-{platform}` or higher is not installed, `platforms` contains: {}. \
+        Install it with `sdkmanager \"platforms;android-{platform}\"`.",
+        platform_list(.installed)
+    )]
+    CompilePlatformNotFound {
+        platform: u32,
+        installed: Vec<String>,
+    },
     #[error("Platform `{0}` is not installed.")]
     PlatformNotFound(u32),
+    #[error("`sdkmanager` has no stable `{0}<version>` package available.")]
+    SdkPackageNotAvailable(String),
     #[error("Target is not supported.")]
     UnsupportedTarget,
     #[error("Host `{0}` is not supported.")]
@@ -46,8 +70,41 @@ pub enum NdkError {
     IoPathError(PathBuf, #[source] IoError),
     #[error("Invalid semver")]
     InvalidSemver,
-    #[error("{}\n\nCommand `{}` had a non-zero exit code.", .1, format!("{:?}", .0).replace('"', ""))]
-    CmdFailed(Command, IoError),
+    #[error(
+        "No desktop session to open a file manager in, `$DISPLAY` and `$WAYLAND_DISPLAY` are unset"
+    )]
+    NoDesktopSession,
+    #[error(
+        "The {component} `{value}` does not fit into the versionCode, which allows up to {max}"
+    )]
+    VersionCodeComponentTooLarge {
+        component: &'static str,
+        value: u32,
+        max: u64,
+    },
+    #[error("versionCode {0} exceeds 2100000000, the largest that Google Play accepts")]
+    VersionCodeTooLarge(u64),
+    #[error("`aapt` failed with {0} error(s) shown above, pass `-vv` for its full output")]
+    AaptFailed(usize),
+    /// `command` is the [`command_line()`](crate::util::command_line) of the command,
+    /// which has its passwords redacted. `status` is [`None`] if it could not be started.
+    #[error("{}Command `{command}` {}", paragraph(error), describe_status(status.as_ref()))]
+    CmdFailed {
+        command: String,
+        status: Option<ExitStatus>,
+        error: IoError,
+    },
+    #[error("Command `{command}` did not finish within {timeout:?} and was killed.")]
+    Timeout {
+        command: String,
+        timeout: std::time::Duration,
+    },
+    #[error("Both `CARGO_ENCODED_RUSTFLAGS` and `RUSTFLAGS` are set, unset one of them")]
+    ConflictingRustflags,
+    #[error("`${0}` contains non-unicode characters")]
+    NonUnicodeEnv(&'static str),
+    #[error("Path `{}` must be valid UTF-8", .0.display())]
+    NonUtf8Path(PathBuf),
     #[error(transparent)]
     Serialize(#[from] quick_xml::de::DeError),
     #[error("String `{1}` is not a UID")]
@@ -56,4 +113,227 @@ pub enum NdkError {
     PackageNotInOutput { package: String, output: String },
     #[error("Could not find `uid:` in output `{0}`")]
     UidNotInOutput(String),
+    #[error("String `{1}` is not a PID")]
+    NotAPid(#[source] ParseIntError, String),
+    #[error("Could not find a `PID` column in `ps` output `{0}`")]
+    PsOutputUnrecognized(String),
+    #[error("Device shell command failed: {0}")]
+    ShellCommandFailed(String),
+    #[error("Could not parse user from `pm list users` output line `{0}`")]
+    UserNotInOutput(String),
+    #[error("Emulator `{avd}` exited before it finished booting:\n{output}")]
+    EmulatorExited { avd: String, output: String },
+    #[error("Emulator `{avd}` did not finish booting within {timeout:?}:\n{output}")]
+    EmulatorBootTimeout {
+        avd: String,
+        timeout: std::time::Duration,
+        output: String,
+    },
+    #[error("All emulator console ports are in use")]
+    NoFreeEmulatorPort,
+    #[error(
+        "Cannot access the internal storage of `{0}`: `run-as` requires a debuggable app \
+        (set `debuggable = true` under `[package.metadata.android.application]` or build \
+        with the `dev` profile), or a rooted device (`adb root` or `su`)"
+    )]
+    NotDebuggable(String),
+    #[error(
+        "`adb root` failed, installing a system app requires a rooted `userdebug` or `eng` \
+        build: {0}"
+    )]
+    AdbRootFailed(String),
+    #[error("Device did not finish booting within {0:?} after `adb reboot`")]
+    RebootTimeout(std::time::Duration),
+    #[error("Failed to parse `{0:?}`: {1}")]
+    PlatformsJson(PathBuf, serde_json::Error),
+    #[error(
+        "NDK {ndk_version} cannot run on this {host} host: `{}` is built for {archs}. {hint}",
+        .tool.display()
+    )]
+    NdkUnsupportedHost {
+        ndk_version: String,
+        tool: PathBuf,
+        archs: String,
+        host: String,
+        hint: &'static str,
+    },
+    #[error(
+        "Generating a debug keystore is disabled by `--frozen-env`, configure one through \
+        `[package.metadata.android.signing.dev]` or `$CARGO_APK_DEV_KEYSTORE`"
+    )]
+    DebugKeyInFrozenEnv,
+    #[error("`keytool -list -rfc` printed no certificate of keystore `{0}`")]
+    NoCertificate(PathBuf),
+    #[error("Missing tools required by `--frozen-env`:\n  {}", .0.join("\n  "))]
+    MissingTools(Vec<String>),
+    #[error(
+        "Sanitizer `{sanitizer}` is not supported for `{target}` by NDK {ndk_version}: \
+        `{library}` not found in the toolchain"
+    )]
+    SanitizerNotSupported {
+        sanitizer: &'static str,
+        target: &'static str,
+        ndk_version: String,
+        library: String,
+    },
+    #[error(
+        "`libVkLayer_khronos_validation.so` for {target} not found in `{}`. Extract \
+        `android-binaries-<version>.zip` from \
+        https://github.com/KhronosGroup/Vulkan-ValidationLayers/releases and point \
+        `vulkan_validation_layers` in `[package.metadata.android]` at its directory",
+        .dir.display()
+    )]
+    VulkanValidationLayerNotFound { target: &'static str, dir: PathBuf },
+    #[error("Could not find `Pkg.Revision` in `{0:?}`")]
+    NdkRevisionNotFound(PathBuf),
+    #[error(
+        "No NDK matching `ndk_version = \"{requirement}\"` found in `{}`, installed versions: {}",
+        .ndk_dir.display(),
+        .found.join(", ")
+    )]
+    NdkVersionNotFound {
+        requirement: String,
+        ndk_dir: PathBuf,
+        found: Vec<String>,
+    },
+    #[error(
+        "Resource `{reference}` referenced by `{field}` not found {}, pass \
+        `--skip-resource-validation` to package anyway",
+        describe_resources(.resources.as_deref())
+    )]
+    ResourceNotFound {
+        reference: String,
+        field: String,
+        resources: Option<PathBuf>,
+    },
+    #[error(
+        "`adb install` arguments `{0}` and `{1}` can't be combined, check `install_flags` and \
+        `--install-arg`"
+    )]
+    ConflictingInstallArgs(String, String),
+    #[error(
+        "Signing with the lineage `{}` failed, check that `older_keys` and the keystore are \
+        the keys it was rotated through, from the oldest to the newest\n\n{output}",
+        .lineage.display()
+    )]
+    SignRotatedFailed { lineage: PathBuf, output: String },
+    #[error(
+        "Could not forward the required device port(s) {}, see the warnings above",
+        .0.iter().map(|port| format!("`{}`", port)).collect::<Vec<_>>().join(", ")
+    )]
+    ReversePortForwardFailed(Vec<String>),
+    /// `adb install` failed for a known reason, `output` is the [`NdkError::CmdFailed`]
+    /// message with the output of `adb`
+    #[error(
+        "Installing failed with `{}`: {}. {}\n\n{output}",
+        .error.code(),
+        .error.explanation(),
+        .error.fix()
+    )]
+    InstallFailed { error: InstallError, output: String },
+    #[error("`{0}` is not a remote like `ssh://user@host` or `ssh://user@host:2222`")]
+    InvalidRemote(String),
+    /// `ssh` itself failed, before `adb` ran on `remote`
+    #[error("Could not tunnel `adb` to `{remote}` over SSH: {error}")]
+    SshFailed { remote: String, error: String },
+    #[error(
+        "Reached `{remote}` over SSH, but could not start its adb server, is `adb` on the \
+        `PATH` of non-interactive shells there?\n\n{error}"
+    )]
+    RemoteAdbFailed {
+        remote: String,
+        error: Box<NdkError>,
+    },
+    #[error(
+        "{feature} doesn't work with the remote adb server of `{remote}`, which would \
+        forward the ports to that host instead of this machine"
+    )]
+    UnsupportedOverRemote {
+        feature: &'static str,
+        remote: String,
+    },
+    /// A runtime library doesn't have the digest listed in `runtime_lib_checksums`
+    #[error(
+        "`{file}` has the SHA-256 {actual}, but {expected} was expected. If the library was \
+        updated on purpose, regenerate its checksum with `cargo apk hash-libs`"
+    )]
+    ChecksumMismatch {
+        file: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("Unknown target `{0}`, expected one of {}", target_spellings())]
+    UnknownTarget(String),
+    #[error(
+        "The `native` packaging backend does not support {0} yet, set \
+        `packaging_backend = \"external\"` to package with the SDK's build-tools"
+    )]
+    NativePackagingUnsupported(String),
+    #[error("Could not read the signing key from `{}`: {reason}", .path.display())]
+    InvalidKeystore { path: PathBuf, reason: String },
+    #[error("`{}` is not a valid apk", .0.display())]
+    InvalidApk(PathBuf),
+}
+
+impl NdkError {
+    pub(crate) fn cmd_failed(
+        command: &Command,
+        status: Option<ExitStatus>,
+        error: IoError,
+    ) -> Self {
+        Self::CmdFailed {
+            command: crate::util::command_line(command),
+            status,
+            error,
+        }
+    }
+}
+
+fn describe_resources(resources: Option<&Path>) -> String {
+    match resources {
+        Some(resources) => format!("under `{}`", resources.display()),
+        None => "as no `resources` directory is configured".to_string(),
+    }
+}
+
+/// The output of a failed command, followed by a blank line.
+fn paragraph(error: &IoError) -> String {
+    let message = error.to_string();
+    if message.trim().is_empty() {
+        String::new()
+    } else {
+        format!("{}\n\n", message.trim_end())
+    }
+}
+
+fn describe_status(status: Option<&ExitStatus>) -> String {
+    let Some(status) = status else {
+        return "could not be started.".to_string();
+    };
+    if let Some(code) = status.code() {
+        return format!("failed with exit code {}.", code);
+    }
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(status) {
+        return format!("was terminated by signal {}.", signal);
+    }
+    format!("failed: {}.", status)
+}
+
+fn platform_list(installed: &[String]) -> String {
+    if installed.is_empty() {
+        "nothing".to_string()
+    } else {
+        installed.join(", ")
+    }
+}
+
+/// Every target as its ABI and its rust triple, the spellings
+/// [`Target::from_abi_or_triple()`] accepts.
+fn target_spellings() -> String {
+    Target::ALL
+        .iter()
+        .map(|t| format!("`{}`/`{}`", t.android_abi(), t.rust_triple()))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
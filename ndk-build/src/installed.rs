@@ -0,0 +1,256 @@
+//! Compares an apk with the app installed on a device before installing over it, to
+//! explain downgrades and signing certificates that `adb install` will refuse.
+
+use crate::ndk::parse_version_code;
+use std::fmt::{self, Display};
+
+/// The app as installed on a device, see [`Apk::installed()`](crate::apk::Apk::installed).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstalledApp {
+    pub version_code: Option<u32>,
+    /// The [`certificate_hash()`]es of the certificates it is signed with
+    pub signatures: Vec<String>,
+    /// The certificates it was signed with before a key rotation (API 28+)
+    pub past_signatures: Vec<String>,
+}
+
+impl InstalledApp {
+    /// Reads the first package listed by `dumpsys package <package>`, [`None`] if it
+    /// lists none because the package is not installed.
+    ///
+    /// Android 9 prints `signatures=PackageSignatures{<id> version:2, signatures:[<hash>],
+    /// past signatures:[<hash> {<flags>}]}`, older versions only
+    /// `signatures=PackageSignatures{<id> [<hash>]}`.
+    pub fn parse(dumpsys: &str) -> Option<Self> {
+        let package = &dumpsys[dumpsys.find("Package [")?..];
+        // The next one is a hidden system package or another user's copy
+        let end = package[1..]
+            .find("Package [")
+            .map_or(package.len(), |i| i + 1);
+        let package = &package[..end];
+        let signatures = package
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("signatures=PackageSignatures{"));
+        let (signatures, past_signatures) = match signatures {
+            Some(s) if s.contains("signatures:[") => {
+                (hashes(s, "signatures:["), hashes(s, "past signatures:["))
+            }
+            Some(s) => (hashes(s, "["), vec![]),
+            None => (vec![], vec![]),
+        };
+        Some(Self {
+            version_code: parse_version_code(package),
+            signatures,
+            past_signatures,
+        })
+    }
+
+    /// What is worth knowing before installing an apk with `version_code` and signed
+    /// with the certificates of `signatures` over this app. Certificates are only
+    /// compared if both sides are known.
+    pub fn compare(&self, version_code: Option<u32>, signatures: &[String]) -> Vec<InstallNotice> {
+        let mut notices = vec![];
+        if let (Some(installed), Some(apk)) = (self.version_code, version_code) {
+            if apk < installed {
+                notices.push(InstallNotice::Downgrade { installed, apk });
+            } else if apk == installed {
+                notices.push(InstallNotice::SameVersion(apk));
+            }
+        }
+        let known = |s: &String| self.signatures.contains(s) || self.past_signatures.contains(s);
+        if !self.signatures.is_empty() && !signatures.is_empty() && !signatures.iter().any(known) {
+            notices.push(InstallNotice::SignatureMismatch {
+                installed: self.signatures[0].clone(),
+                apk: signatures[0].clone(),
+            });
+        }
+        notices
+    }
+}
+
+/// The hexadecimal hashes of the list in `s` after `start`, without the flags of past
+/// signatures.
+fn hashes(s: &str, start: &str) -> Vec<String> {
+    let Some(list) = s.find(start).map(|i| &s[i + start.len()..]) else {
+        return vec![];
+    };
+    let mut hashes = vec![];
+    let mut depth = 0;
+    let mut hash = String::new();
+    for c in list.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth > 0 => {}
+            ']' => break,
+            c if c.is_ascii_hexdigit() => hash.push(c),
+            _ if !hash.is_empty() => hashes.push(std::mem::take(&mut hash)),
+            _ => {}
+        }
+    }
+    if !hash.is_empty() {
+        hashes.push(hash);
+    }
+    hashes
+}
+
+/// Found by [`InstalledApp::compare()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstallNotice {
+    /// The apk has a lower `versionCode`, which `adb install` refuses without `-d`
+    Downgrade { installed: u32, apk: u32 },
+    /// The apk is signed with none of the certificates of the installed app, which
+    /// `adb install` refuses
+    SignatureMismatch { installed: String, apk: String },
+    /// The apk has the `versionCode` of the installed app
+    SameVersion(u32),
+}
+
+impl Display for InstallNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Downgrade { installed, apk } => write!(
+                f,
+                "the device has `versionCode` {} installed, which is newer than the {} of \
+                the APK. Pass `--install-arg=-d` or add `\"-d\"` to `install_flags` to \
+                downgrade, or uninstall the app first",
+                installed, apk
+            ),
+            Self::SignatureMismatch { installed, apk } => write!(
+                f,
+                "the installed app is signed with another certificate (`{}`, the APK has \
+                `{}`), e.g. by Google Play or on another machine. Uninstall it first with \
+                `adb uninstall <package>`, which deletes its data",
+                installed, apk
+            ),
+            Self::SameVersion(code) => write!(
+                f,
+                "the device has the same `versionCode` {} installed, which is replaced",
+                code
+            ),
+        }
+    }
+}
+
+/// The hash that `dumpsys package` prints for a certificate, its Java
+/// `Signature.hashCode()`: `Arrays.hashCode()` of the DER encoding in hexadecimal.
+pub fn certificate_hash(der: &[u8]) -> String {
+    let hash = der.iter().fold(1i32, |hash, &b| {
+        hash.wrapping_mul(31).wrapping_add(b as i8 as i32)
+    });
+    format!("{:x}", hash)
+}
+
+/// The DER encoding of the first certificate of `keytool -list -rfc`.
+pub(crate) fn parse_pem_certificate(output: &str) -> Option<Vec<u8>> {
+    let start = output.find("-----BEGIN CERTIFICATE-----")? + "-----BEGIN CERTIFICATE-----".len();
+    let end = start + output[start..].find("-----END CERTIFICATE-----")?;
+    decode_base64(&output[start..end])
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let (mut bits, mut len) = (0u32, 0);
+    for c in s.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            bytes.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn dumpsys(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/dumpsys")
+            .join(name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn parse_dumpsys() {
+        let installed = |name| InstalledApp::parse(&dumpsys(name)).unwrap();
+        assert_eq!(
+            installed("android-7.txt"),
+            InstalledApp {
+                version_code: Some(1000100),
+                signatures: vec!["3d8f2a71".to_string()],
+                past_signatures: vec![],
+            }
+        );
+        assert_eq!(
+            installed("android-10.txt"),
+            InstalledApp {
+                version_code: Some(1000200),
+                signatures: vec!["3d8f2a71".to_string()],
+                past_signatures: vec![],
+            }
+        );
+        // Signed with a rotated key, followed by the hidden copy of a system app
+        assert_eq!(
+            installed("android-14.txt"),
+            InstalledApp {
+                version_code: Some(1000300),
+                signatures: vec!["9e41b6d0".to_string()],
+                past_signatures: vec!["3d8f2a71".to_string(), "9e41b6d0".to_string()],
+            }
+        );
+        assert_eq!(InstalledApp::parse(&dumpsys("not_installed.txt")), None);
+    }
+
+    #[test]
+    fn compare() {
+        let installed = InstalledApp::parse(&dumpsys("android-14.txt")).unwrap();
+        let signed = |hash: &str| vec![hash.to_string()];
+        assert_eq!(installed.compare(Some(1000400), &signed("9e41b6d0")), []);
+        // Still signed with the key before the rotation
+        assert_eq!(installed.compare(None, &signed("3d8f2a71")), []);
+        assert_eq!(
+            installed.compare(Some(1000300), &[]),
+            [InstallNotice::SameVersion(1000300)]
+        );
+        assert_eq!(
+            installed.compare(Some(1000100), &signed("5c1aeb5d")),
+            [
+                InstallNotice::Downgrade {
+                    installed: 1000300,
+                    apk: 1000100
+                },
+                InstallNotice::SignatureMismatch {
+                    installed: "9e41b6d0".to_string(),
+                    apk: "5c1aeb5d".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn certificate() {
+        // 31 * (31 * (31 + 1) + 2) + 3
+        assert_eq!(certificate_hash(&[1, 2, 3]), "7861");
+        // Bytes are signed in Java
+        assert_eq!(certificate_hash(&[0xff]), "1e");
+        assert_eq!(certificate_hash(&[0x80; 8]), "4edc2f01");
+        let keytool = "Alias name: androiddebugkey\nCertificate[1]:\n\
+            -----BEGIN CERTIFICATE-----\nAQID\nBA==\n-----END CERTIFICATE-----\n";
+        assert_eq!(parse_pem_certificate(keytool), Some(vec![1, 2, 3, 4]));
+        assert_eq!(parse_pem_certificate("keytool error"), None);
+    }
+}
@@ -28,12 +28,25 @@ macro_rules! cmd {
     };
 }
 
+pub mod aapt;
 pub mod apk;
 pub mod cargo;
+pub mod checksums;
 pub mod dylibs;
+pub mod emulator;
+pub mod env;
 pub mod error;
+pub mod installed;
 pub mod manifest;
+pub mod native;
 pub mod ndk;
 pub mod readelf;
+pub mod reverse;
+pub mod sanitizer;
+pub mod simpleperf;
 pub mod target;
+#[cfg(test)]
+mod test_dir;
+pub mod timings;
 pub mod util;
+pub mod vulkan;
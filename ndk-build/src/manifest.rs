@@ -27,7 +27,14 @@ pub struct AndroidManifest {
     pub uses_feature: Vec<Feature>,
     #[serde(rename(serialize = "uses-permission"))]
     #[serde(default)]
-    pub uses_permission: Vec<Permission>,
+    pub uses_permission: Vec<UsesPermission>,
+    /// Custom permissions declared by this app, which [`AndroidManifest::uses_permission`]
+    /// of this or other apps can request
+    #[serde(default)]
+    pub permission: Vec<Permission>,
+    #[serde(rename(serialize = "permission-group"))]
+    #[serde(default)]
+    pub permission_group: Vec<PermissionGroup>,
 
     #[serde(default)]
     pub queries: Option<Queries>,
@@ -47,6 +54,8 @@ impl Default for AndroidManifest {
             sdk: Default::default(),
             uses_feature: Default::default(),
             uses_permission: Default::default(),
+            permission: Default::default(),
+            permission_group: Default::default(),
             queries: Default::default(),
             application: Default::default(),
         }
@@ -60,6 +69,63 @@ impl AndroidManifest {
         quick_xml::se::to_writer(w, &self)?;
         Ok(())
     }
+
+    /// Renders the manifest as written by [`AndroidManifest::write_to()`].
+    pub fn to_xml(&self) -> Result<String, NdkError> {
+        Ok(quick_xml::se::to_string(&self)?)
+    }
+
+    /// Every attribute that [`AndroidManifest::to_xml()`] renders, with the key of
+    /// `[package.metadata.android]` that it is configured with.
+    pub fn provenance(&self) -> Vec<Provenance> {
+        let mut provenance = vec![];
+        let value = serde_json::to_value(self).expect("manifest is not serializable");
+        collect_provenance(&value, "manifest", "", &mut provenance);
+        provenance
+    }
+
+    /// The manifest of the configuration split `config.<name>` of this app.
+    pub fn config_split(&self, name: &str) -> SplitManifest {
+        SplitManifest {
+            ns_android: default_namespace(),
+            package: self.package.clone(),
+            version_code: self.version_code,
+            version_name: self.version_name.clone(),
+            split: format!("config.{}", name),
+            application: SplitApplication::default(),
+        }
+    }
+}
+
+/// Android [manifest element](https://developer.android.com/guide/topics/manifest/manifest-element)
+/// of a configuration split, which `adb install-multiple` installs next to the base apk
+/// of the same package and `versionCode`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename = "manifest")]
+pub struct SplitManifest {
+    #[serde(rename(serialize = "xmlns:android"))]
+    ns_android: String,
+    pub package: String,
+    #[serde(rename(serialize = "android:versionCode"))]
+    pub version_code: Option<u32>,
+    #[serde(rename(serialize = "android:versionName"))]
+    pub version_name: Option<String>,
+    /// Like `config.hdpi`
+    pub split: String,
+    pub application: SplitApplication,
+}
+
+impl SplitManifest {
+    pub fn to_xml(&self) -> Result<String, NdkError> {
+        Ok(quick_xml::se::to_string(&self)?)
+    }
+}
+
+/// The application element of a [`SplitManifest`], which only holds resources and assets.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SplitApplication {
+    #[serde(rename(serialize = "android:hasCode"))]
+    pub has_code: bool,
 }
 
 /// Android [application element](https://developer.android.com/guide/topics/manifest/application-element), containing an [`Activity`] element.
@@ -74,6 +140,9 @@ pub struct Application {
     pub has_code: bool,
     #[serde(rename(serialize = "android:icon"))]
     pub icon: Option<String>,
+    /// The banner that Android TV shows in its launcher, like `@drawable/banner`
+    #[serde(rename(serialize = "android:banner"))]
+    pub banner: Option<String>,
     #[serde(rename(serialize = "android:label"))]
     #[serde(default)]
     pub label: String,
@@ -81,12 +150,35 @@ pub struct Application {
     pub extract_native_libs: Option<bool>,
     #[serde(rename(serialize = "android:usesCleartextTraffic"))]
     pub uses_cleartext_traffic: Option<bool>,
+    /// Opts out of scoped storage on Android 10 (API 29)
+    #[serde(rename(serialize = "android:requestLegacyExternalStorage"))]
+    pub request_legacy_external_storage: Option<bool>,
+    /// Keeps legacy storage across an update to an app targeting Android 11 (API 30)
+    #[serde(rename(serialize = "android:preserveLegacyExternalStorage"))]
+    pub preserve_legacy_external_storage: Option<bool>,
+    /// Opts into predictive back, where the system dispatches back through `OnBackInvokedCallback`
+    #[serde(rename(serialize = "android:enableOnBackInvokedCallback"))]
+    pub enable_on_back_invoked_callback: Option<bool>,
 
+    #[serde(default)]
+    pub profileable: Option<Profileable>,
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
     pub meta_data: Vec<MetaData>,
     #[serde(default)]
     pub activity: Activity,
+    #[serde(rename(serialize = "activity-alias"))]
+    #[serde(default)]
+    pub activity_alias: Vec<ActivityAlias>,
+}
+
+/// Android [profileable element](https://developer.android.com/guide/topics/manifest/profileable-element).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Profileable {
+    #[serde(rename(serialize = "android:shell"))]
+    pub shell: Option<bool>,
+    #[serde(rename(serialize = "android:enabled"))]
+    pub enabled: Option<bool>,
 }
 
 /// Android [activity element](https://developer.android.com/guide/topics/manifest/activity-element).
@@ -110,6 +202,10 @@ pub struct Activity {
     pub resizeable_activity: Option<bool>,
     #[serde(rename(serialize = "android:alwaysRetainTaskState"))]
     pub always_retain_task_state: Option<bool>,
+    /// One of [`Activity::DISPLAY_CUTOUT_MODES`], e.g. `shortEdges` to draw edge-to-edge
+    /// around the cutout
+    #[serde(rename(serialize = "android:windowLayoutInDisplayCutoutMode"))]
+    pub window_layout_in_display_cutout_mode: Option<String>,
 
     #[serde(rename(serialize = "meta-data"))]
     #[serde(default)]
@@ -118,6 +214,55 @@ pub struct Activity {
     #[serde(rename(serialize = "intent-filter"))]
     #[serde(default)]
     pub intent_filter: Vec<IntentFilter>,
+    /// Keeps `cargo-apk` from adding the default `MAIN` filter, for apps without a launcher icon.
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub no_default_intent_filter: bool,
+    /// Attributes that `cargo-apk` merges into the activity for some `targetSdkVersion`s.
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub when: Vec<ActivityWhen>,
+}
+
+/// Attributes of an [`Activity`] that apply if the `targetSdkVersion` matches
+/// [`ActivityWhen::target_sdk`], overriding those of the activity.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ActivityWhen {
+    /// Comparisons like `">= 24, < 34"` that must all hold
+    pub target_sdk: String,
+    pub config_changes: Option<String>,
+    pub label: Option<String>,
+    pub launch_mode: Option<String>,
+    pub orientation: Option<String>,
+    pub exported: Option<bool>,
+    pub resizeable_activity: Option<bool>,
+    pub always_retain_task_state: Option<bool>,
+    pub window_layout_in_display_cutout_mode: Option<String>,
+}
+
+impl ActivityWhen {
+    /// Sets the attributes of `activity` that this overrides.
+    pub fn apply(&self, activity: &mut Activity) {
+        fn set<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if let Some(value) = value {
+                *field = Some(value.clone());
+            }
+        }
+        set(&mut activity.config_changes, &self.config_changes);
+        set(&mut activity.label, &self.label);
+        set(&mut activity.launch_mode, &self.launch_mode);
+        set(&mut activity.orientation, &self.orientation);
+        set(&mut activity.exported, &self.exported);
+        set(&mut activity.resizeable_activity, &self.resizeable_activity);
+        set(
+            &mut activity.always_retain_task_state,
+            &self.always_retain_task_state,
+        );
+        set(
+            &mut activity.window_layout_in_display_cutout_mode,
+            &self.window_layout_in_display_cutout_mode,
+        );
+    }
 }
 
 impl Default for Activity {
@@ -131,12 +276,55 @@ impl Default for Activity {
             exported: None,
             resizeable_activity: None,
             always_retain_task_state: None,
+            window_layout_in_display_cutout_mode: None,
             meta_data: Default::default(),
             intent_filter: Default::default(),
+            no_default_intent_filter: false,
+            when: Default::default(),
         }
     }
 }
 
+impl Activity {
+    /// The values of [`Activity::window_layout_in_display_cutout_mode`].
+    pub const DISPLAY_CUTOUT_MODES: &'static [&'static str] =
+        &["default", "shortEdges", "never", "always"];
+
+    /// Whether [`Activity::window_layout_in_display_cutout_mode`] is unset or one of
+    /// [`Activity::DISPLAY_CUTOUT_MODES`].
+    pub fn has_valid_display_cutout_mode(&self) -> bool {
+        self.window_layout_in_display_cutout_mode
+            .as_deref()
+            .is_none_or(|mode| Self::DISPLAY_CUTOUT_MODES.contains(&mode))
+    }
+}
+
+/// Android [activity alias element](https://developer.android.com/guide/topics/manifest/activity-alias-element),
+/// e.g. to switch the launcher icon by enabling one of several aliases at runtime.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ActivityAlias {
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    /// The [`Activity::name`] this alias launches
+    #[serde(rename(serialize = "android:targetActivity"))]
+    pub target_activity: String,
+    #[serde(rename(serialize = "android:enabled"))]
+    pub enabled: Option<bool>,
+    #[serde(rename(serialize = "android:exported"))]
+    pub exported: Option<bool>,
+    #[serde(rename(serialize = "android:icon"))]
+    pub icon: Option<String>,
+    #[serde(rename(serialize = "android:label"))]
+    pub label: Option<String>,
+
+    #[serde(rename(serialize = "meta-data"))]
+    #[serde(default)]
+    pub meta_data: Vec<MetaData>,
+    #[serde(rename(serialize = "intent-filter"))]
+    #[serde(default)]
+    pub intent_filter: Vec<IntentFilter>,
+}
+
 /// Android [intent filter element](https://developer.android.com/guide/topics/manifest/intent-filter-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct IntentFilter {
@@ -154,6 +342,15 @@ pub struct IntentFilter {
     pub data: Vec<IntentFilterData>,
 }
 
+impl IntentFilter {
+    /// Whether this filter puts its activity in the launcher.
+    pub fn is_launcher(&self) -> bool {
+        self.categories
+            .iter()
+            .any(|c| c == "android.intent.category.LAUNCHER")
+    }
+}
+
 fn serialize_actions<S>(actions: &[String], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -221,6 +418,11 @@ pub struct MetaData {
     pub name: String,
     #[serde(rename(serialize = "android:value"))]
     pub value: String,
+    /// Passed on to apps that depend on the crate declaring it, when they set
+    /// `inherit_dependency_manifest = true`.
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub exported_to_dependents: bool,
 }
 
 /// Android [uses-feature element](https://developer.android.com/guide/topics/manifest/uses-feature-element).
@@ -265,13 +467,97 @@ where
 
 /// Android [uses-permission element](https://developer.android.com/guide/topics/manifest/uses-permission-element).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
-pub struct Permission {
+pub struct UsesPermission {
     #[serde(rename(serialize = "android:name"))]
     pub name: String,
     #[serde(rename(serialize = "android:maxSdkVersion"))]
     pub max_sdk_version: Option<u32>,
 }
 
+/// Android [permission element](https://developer.android.com/guide/topics/manifest/permission-element),
+/// declaring a custom permission.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Permission {
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    /// A base level like `signature`, optionally followed by `|`-separated flags
+    #[serde(rename(serialize = "android:protectionLevel"))]
+    pub protection_level: Option<String>,
+    #[serde(rename(serialize = "android:label"))]
+    pub label: Option<String>,
+    #[serde(rename(serialize = "android:description"))]
+    pub description: Option<String>,
+    #[serde(rename(serialize = "android:permissionGroup"))]
+    pub permission_group: Option<String>,
+}
+
+impl Permission {
+    /// The base levels of [`Permission::protection_level`], of which at most one may be set.
+    pub const PROTECTION_LEVELS: &'static [&'static str] = &[
+        "normal",
+        "dangerous",
+        "signature",
+        "signatureOrSystem",
+        "internal",
+    ];
+    /// The flags that may be combined with one of [`Permission::PROTECTION_LEVELS`].
+    pub const PROTECTION_FLAGS: &'static [&'static str] = &[
+        "privileged",
+        "system",
+        "development",
+        "appop",
+        "pre23",
+        "installer",
+        "verifier",
+        "preinstalled",
+        "setup",
+        "instant",
+        "runtime",
+        "oem",
+        "vendorPrivileged",
+        "textClassifier",
+        "configurator",
+        "incidentReportApprover",
+        "appPredictor",
+        "companion",
+        "retailDemo",
+        "recents",
+        "role",
+        "knownSigner",
+    ];
+
+    /// Whether [`Permission::protection_level`] is unset or consists of at most one base
+    /// level and known flags, like `signature|privileged`.
+    pub fn has_valid_protection_level(&self) -> bool {
+        let Some(level) = &self.protection_level else {
+            return true;
+        };
+        let parts = level.split('|').map(str::trim).collect::<Vec<_>>();
+        let levels = parts
+            .iter()
+            .filter(|part| Self::PROTECTION_LEVELS.contains(part))
+            .count();
+        levels <= 1
+            && parts.iter().all(|part| {
+                Self::PROTECTION_LEVELS.contains(part) || Self::PROTECTION_FLAGS.contains(part)
+            })
+    }
+}
+
+/// Android [permission-group element](https://developer.android.com/guide/topics/manifest/permission-group-element),
+/// which [`Permission::permission_group`] can refer to.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PermissionGroup {
+    #[serde(rename(serialize = "android:name"))]
+    pub name: String,
+    #[serde(rename(serialize = "android:label"))]
+    pub label: Option<String>,
+    #[serde(rename(serialize = "android:description"))]
+    pub description: Option<String>,
+    #[serde(rename(serialize = "android:icon"))]
+    pub icon: Option<String>,
+}
+
 /// Android [package element](https://developer.android.com/guide/topics/manifest/queries-element#package).
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Package {
@@ -323,6 +609,102 @@ impl Default for Sdk {
     }
 }
 
+/// An attribute of the rendered manifest, see [`AndroidManifest::provenance()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    /// The tag of the element, like `activity`
+    pub element: String,
+    /// Like `android:screenOrientation`
+    pub attribute: String,
+    pub value: String,
+    /// Like `application.activity.orientation`
+    pub field: String,
+}
+
+/// Walks the serialized `element`, whose keys are the names in the XML and whose
+/// values are either attributes or child elements, at `field` of the TOML.
+fn collect_provenance(
+    element: &serde_json::Value,
+    tag: &str,
+    field: &str,
+    provenance: &mut Vec<Provenance>,
+) {
+    use serde_json::Value;
+
+    let Value::Object(children) = element else {
+        return;
+    };
+    for (name, value) in children {
+        if name.starts_with("xmlns:") {
+            continue;
+        }
+        let key = toml_key(name);
+        let field = match field {
+            "" => key,
+            field => format!("{}.{}", field, key),
+        };
+        match value {
+            Value::Null => {}
+            Value::Object(_) => collect_provenance(value, name, &field, provenance),
+            Value::Array(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let field = format!("{}[{}]", field, i);
+                    match element.get("android:name") {
+                        // `actions` and `categories` are lists of names in the TOML
+                        Some(Value::String(value)) if matches!(&**name, "action" | "category") => {
+                            provenance.push(Provenance {
+                                element: name.clone(),
+                                attribute: "android:name".to_string(),
+                                value: value.clone(),
+                                field,
+                            })
+                        }
+                        _ => collect_provenance(element, name, &field, provenance),
+                    }
+                }
+            }
+            Value::String(value) => provenance.push(Provenance {
+                element: tag.to_string(),
+                attribute: name.clone(),
+                value: value.clone(),
+                field,
+            }),
+            value => provenance.push(Provenance {
+                element: tag.to_string(),
+                attribute: name.clone(),
+                value: value.to_string(),
+                field,
+            }),
+        }
+    }
+}
+
+/// The key in the TOML of the XML attribute or element `name`, which is its name in
+/// snake case unless renamed.
+fn toml_key(name: &str) -> String {
+    match name {
+        "uses-sdk" => return "sdk".to_string(),
+        "action" => return "actions".to_string(),
+        "category" => return "categories".to_string(),
+        "android:screenOrientation" => return "orientation".to_string(),
+        "android:glEsVersion" => return "opengles_version".to_string(),
+        _ => {}
+    }
+    let name = name.strip_prefix("android:").unwrap_or(name);
+    let mut key = String::new();
+    for c in name.chars() {
+        if c == '-' {
+            key.push('_');
+        } else if c.is_ascii_uppercase() {
+            key.push('_');
+            key.push(c.to_ascii_lowercase());
+        } else {
+            key.push(c);
+        }
+    }
+    key
+}
+
 fn default_namespace() -> String {
     "http://schemas.android.com/apk/res/android".to_string()
 }
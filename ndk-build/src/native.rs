@@ -0,0 +1,220 @@
+//! The [`PackagingBackend::Native`] backend, which packages and signs apks without the
+//! `aapt`, `zipalign` and `apksigner` of the SDK's build-tools, and without an
+//! `android.jar` to compile the manifest against.
+//!
+//! It covers what a pure-NDK app needs: the manifest generated from
+//! `[package.metadata.android]`, assets and native libraries, stored uncompressed and
+//! aligned, and a v2 signature with an RSA key from a PKCS#12 keystore. Apps with
+//! `resources`, or that must install on Android 6 and older, which only verifies v1
+//! signatures, need the [`PackagingBackend::External`] tools. Since every entry is
+//! stored, apks with compressible assets are larger than the ones `aapt` deflates.
+
+mod axml;
+mod crypto;
+mod der;
+mod pkcs12;
+mod v2;
+mod zip;
+
+use crate::apk::ApkConfig;
+use crate::error::NdkError;
+use crate::ndk::Key;
+use pkcs12::{KeystoreError, read_keystore};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use zip::ZipWriter;
+
+/// The tools that package and sign apks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackagingBackend {
+    /// `aapt`, `zipalign` and `apksigner` of the SDK's build-tools
+    #[default]
+    External,
+    /// Built-in replacements, which need neither the SDK nor a JDK besides `keytool`
+    /// for the debug keystore, see [`crate::native`]
+    Native,
+}
+
+impl std::fmt::Display for PackagingBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::External => "external",
+            Self::Native => "native",
+        })
+    }
+}
+
+/// The lowest `minSdkVersion` that verifies the v2 signatures of this backend.
+pub(crate) const MIN_SDK_VERSION: u32 = 24;
+
+/// Fails with [`NdkError::NativePackagingUnsupported`] for what `config` needs that
+/// this backend can't package yet.
+pub(crate) fn check_supported(config: &ApkConfig) -> Result<(), NdkError> {
+    if let Some(resources) = &config.resources {
+        return Err(NdkError::NativePackagingUnsupported(format!(
+            "compiling the `resources` at `{}`",
+            resources.display()
+        )));
+    }
+    let min_sdk_version = config.manifest.sdk.min_sdk_version.unwrap_or(1);
+    if min_sdk_version < MIN_SDK_VERSION {
+        return Err(NdkError::NativePackagingUnsupported(format!(
+            "v1 signatures, which `min_sdk_version = {}` needs below {}",
+            min_sdk_version, MIN_SDK_VERSION
+        )));
+    }
+    Ok(())
+}
+
+/// Writes the unsigned apk of `config` to [`ApkConfig::apk()`], with the binary XML of
+/// `manifest` and the `files` staged under [`ApkConfig::build_dir`] by their path in the
+/// apk, after those under [`ApkConfig::assets`].
+pub(crate) fn write_apk<'a>(
+    config: &ApkConfig,
+    manifest: &str,
+    files: impl IntoIterator<Item = &'a String>,
+) -> Result<(), NdkError> {
+    let mut entries = BTreeMap::new();
+    if let Some(assets) = &config.assets {
+        collect_files(assets, "assets", &mut entries)?;
+    }
+    for file in files {
+        entries.insert(file.clone(), config.build_dir.join(file));
+    }
+    let apk = config.apk();
+    let io_error = |e| NdkError::IoPathError(apk.clone(), e);
+    let out = fs::File::create(&apk).map_err(io_error)?;
+    let mut zip = ZipWriter::new(BufWriter::new(out));
+    // Like `aapt`, the manifest comes first
+    zip.add("AndroidManifest.xml", &axml::compile(manifest)?)
+        .map_err(io_error)?;
+    for (name, path) in entries {
+        let data = fs::read(&path).map_err(|e| NdkError::IoPathError(path, e))?;
+        zip.add(&name, &data).map_err(io_error)?;
+    }
+    zip.finish().map_err(io_error)?;
+    Ok(())
+}
+
+/// Zips the files at `entries` under `dir` into `zip`, in place of `aapt add`.
+pub(crate) fn write_zip(zip: &Path, dir: &Path, entries: &[&String]) -> Result<(), NdkError> {
+    let io_error = |e| NdkError::IoPathError(zip.to_owned(), e);
+    let out = fs::File::create(zip).map_err(io_error)?;
+    let mut writer = ZipWriter::new(BufWriter::new(out));
+    for entry in entries {
+        let path = dir.join(entry);
+        let data = fs::read(&path).map_err(|e| NdkError::IoPathError(path, e))?;
+        writer.add(entry, &data).map_err(io_error)?;
+    }
+    writer.finish().map_err(io_error)?;
+    Ok(())
+}
+
+/// Adds the files under `dir` to `entries`, keyed by their path under `prefix` with `/`
+/// separators.
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    entries: &mut BTreeMap<String, PathBuf>,
+) -> Result<(), NdkError> {
+    for entry in fs::read_dir(dir).map_err(|e| NdkError::IoPathError(dir.to_owned(), e))? {
+        let entry = entry?;
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &name, entries)?;
+        } else {
+            entries.insert(name, entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Signs the apk at `apk` in place with a v2 signature by the key in the keystore of
+/// `key`.
+pub(crate) fn sign(apk: &Path, key: &Key) -> Result<(), NdkError> {
+    let keystore = fs::read(&key.path).map_err(|e| NdkError::IoPathError(key.path.clone(), e))?;
+    let signing_key = read_keystore(&keystore, &key.password).map_err(|error| match error {
+        KeystoreError::Unsupported(what) => NdkError::NativePackagingUnsupported(what.to_string()),
+        KeystoreError::Malformed(reason) => NdkError::InvalidKeystore {
+            path: key.path.clone(),
+            reason: reason.to_string(),
+        },
+        KeystoreError::WrongPassword => NdkError::InvalidKeystore {
+            path: key.path.clone(),
+            reason: "wrong password".to_string(),
+        },
+    })?;
+    let io_error = |e| NdkError::IoPathError(apk.to_owned(), e);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(apk)
+        .map_err(io_error)?;
+    if !v2::sign(&mut file, &signing_key).map_err(io_error)? {
+        return Err(NdkError::InvalidApk(apk.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::AndroidManifest;
+    use crate::ndk::Ndk;
+    use crate::test_dir::TestDir;
+    use crate::util::output_error;
+
+    #[test]
+    fn sign_invalid_apk() {
+        let dir = TestDir::new("native");
+        let apk = dir.join("invalid.apk");
+        fs::write(&apk, "not a zip archive").unwrap();
+        let path = dir.join("debug.keystore");
+        fs::write(&path, pkcs12::tests::KEYSTORE).unwrap();
+        let key = Key {
+            path,
+            password: "android".to_string(),
+        };
+        assert!(matches!(sign(&apk, &key), Err(NdkError::InvalidApk(path)) if path == apk));
+    }
+
+    /// Checks the signature with the SDK's `apksigner`, which is skipped when the
+    /// build-tools aren't installed.
+    #[test]
+    fn apksigner_verifies_signature() {
+        let Ok(apksigner) = Ndk::from_env().and_then(|ndk| ndk.build_tool(bat!("apksigner")))
+        else {
+            eprintln!("Skipped: `apksigner` of the SDK's build-tools not found");
+            return;
+        };
+        let dir = TestDir::new("apksigner");
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "rust.example".to_string();
+        manifest.sdk.min_sdk_version = Some(MIN_SDK_VERSION);
+        let apk = dir.join("example.apk");
+        let mut zip = ZipWriter::new(fs::File::create(&apk).unwrap());
+        let xml = axml::compile(&manifest.to_xml().unwrap()).unwrap();
+        zip.add("AndroidManifest.xml", &xml).unwrap();
+        zip.add("lib/arm64-v8a/libexample.so", &[0; 5000]).unwrap();
+        zip.finish().unwrap();
+        let path = dir.join("debug.keystore");
+        fs::write(&path, pkcs12::tests::KEYSTORE).unwrap();
+        let key = Key {
+            path,
+            password: "android".to_string(),
+        };
+        sign(&apk, &key).unwrap();
+
+        let mut apksigner = apksigner;
+        apksigner
+            .arg("verify")
+            .arg("--min-sdk-version")
+            .arg(MIN_SDK_VERSION.to_string())
+            .arg(&apk);
+        let result = output_error(apksigner, None);
+        result.unwrap();
+    }
+}
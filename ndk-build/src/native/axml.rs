@@ -0,0 +1,842 @@
+//! Compiles the `AndroidManifest.xml` rendered by
+//! [`AndroidManifest::to_xml()`](crate::manifest::AndroidManifest::to_xml) into the
+//! binary XML that Android parses, like `aapt` does against an `android.jar`.
+//!
+//! Without an `android.jar` there are no resources to link against, so the attributes
+//! of the `android` namespace are looked up in [`ATTRIBUTES`], and references like
+//! `@string/app_name` are not supported.
+
+use crate::error::NdkError;
+use quick_xml::Reader;
+use quick_xml::de::DeError;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+
+const ANDROID_NAMESPACE: &str = "http://schemas.android.com/apk/res/android";
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_NAMESPACE_TYPE: u16 = 0x0100;
+const RES_XML_END_NAMESPACE_TYPE: u16 = 0x0101;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_FLOAT: u8 = 0x04;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+/// How the value of an attribute is compiled, from its `format` in the `attrs.xml` of
+/// the framework.
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    String,
+    Boolean,
+    Integer,
+    /// An integer written in hex, like `glEsVersion`
+    Hex,
+    Enum(&'static [(&'static str, u32)]),
+    Flags(&'static [(&'static str, u32)]),
+    /// A boolean, integer, float or string, like the value of a `<meta-data>`
+    Any,
+}
+
+const LAUNCH_MODES: &[(&str, u32)] = &[
+    ("standard", 0),
+    ("singleTop", 1),
+    ("singleTask", 2),
+    ("singleInstance", 3),
+    ("singleInstancePerTask", 4),
+];
+
+const SCREEN_ORIENTATIONS: &[(&str, u32)] = &[
+    ("unspecified", -1i32 as u32),
+    ("landscape", 0),
+    ("portrait", 1),
+    ("user", 2),
+    ("behind", 3),
+    ("sensor", 4),
+    ("nosensor", 5),
+    ("sensorLandscape", 6),
+    ("sensorPortrait", 7),
+    ("reverseLandscape", 8),
+    ("reversePortrait", 9),
+    ("fullSensor", 10),
+    ("userLandscape", 11),
+    ("userPortrait", 12),
+    ("fullUser", 13),
+    ("locked", 14),
+];
+
+const CONFIG_CHANGES: &[(&str, u32)] = &[
+    ("mcc", 0x0001),
+    ("mnc", 0x0002),
+    ("locale", 0x0004),
+    ("touchscreen", 0x0008),
+    ("keyboard", 0x0010),
+    ("keyboardHidden", 0x0020),
+    ("navigation", 0x0040),
+    ("orientation", 0x0080),
+    ("screenLayout", 0x0100),
+    ("uiMode", 0x0200),
+    ("screenSize", 0x0400),
+    ("smallestScreenSize", 0x0800),
+    ("density", 0x1000),
+    ("layoutDirection", 0x2000),
+    ("colorMode", 0x4000),
+    ("fontScale", 0x4000_0000),
+];
+
+const PROTECTION_LEVELS: &[(&str, u32)] = &[
+    ("normal", 0x00),
+    ("dangerous", 0x01),
+    ("signature", 0x02),
+    ("signatureOrSystem", 0x03),
+    ("privileged", 0x10),
+    ("system", 0x10),
+    ("development", 0x20),
+    ("appop", 0x40),
+    ("pre23", 0x80),
+    ("installer", 0x100),
+    ("verifier", 0x200),
+    ("preinstalled", 0x400),
+    ("setup", 0x800),
+    ("instant", 0x1000),
+    ("runtime", 0x2000),
+    ("internal", 0x04),
+    ("oem", 0x4000),
+    ("vendorPrivileged", 0x8000),
+    ("textClassifier", 0x1_0000),
+    ("configurator", 0x8_0000),
+    ("incidentReportApprover", 0x10_0000),
+    ("appPredictor", 0x20_0000),
+    ("companion", 0x80_0000),
+    ("retailDemo", 0x100_0000),
+    ("recents", 0x200_0000),
+    ("role", 0x400_0000),
+    ("knownSigner", 0x800_0000),
+];
+
+const DISPLAY_CUTOUT_MODES: &[(&str, u32)] = &[
+    ("default", 0),
+    ("shortEdges", 1),
+    ("never", 2),
+    ("always", 3),
+];
+
+/// The resource IDs of the attributes of the `android` namespace that the manifest can
+/// render, from the `public.xml` of the framework.
+const ATTRIBUTES: &[(&str, u32, Format)] = &[
+    ("theme", 0x0101_0000, Format::String),
+    ("label", 0x0101_0001, Format::String),
+    ("icon", 0x0101_0002, Format::String),
+    ("name", 0x0101_0003, Format::String),
+    (
+        "protectionLevel",
+        0x0101_0009,
+        Format::Flags(PROTECTION_LEVELS),
+    ),
+    ("permissionGroup", 0x0101_000a, Format::String),
+    ("sharedUserId", 0x0101_000b, Format::String),
+    ("hasCode", 0x0101_000c, Format::Boolean),
+    ("enabled", 0x0101_000e, Format::Boolean),
+    ("debuggable", 0x0101_000f, Format::Boolean),
+    ("exported", 0x0101_0010, Format::Boolean),
+    ("authorities", 0x0101_0018, Format::String),
+    ("launchMode", 0x0101_001d, Format::Enum(LAUNCH_MODES)),
+    (
+        "screenOrientation",
+        0x0101_001e,
+        Format::Enum(SCREEN_ORIENTATIONS),
+    ),
+    ("configChanges", 0x0101_001f, Format::Flags(CONFIG_CHANGES)),
+    ("description", 0x0101_0020, Format::String),
+    ("value", 0x0101_0024, Format::Any),
+    ("mimeType", 0x0101_0026, Format::String),
+    ("scheme", 0x0101_0027, Format::String),
+    ("host", 0x0101_0028, Format::String),
+    ("port", 0x0101_0029, Format::String),
+    ("path", 0x0101_002a, Format::String),
+    ("pathPrefix", 0x0101_002b, Format::String),
+    ("pathPattern", 0x0101_002c, Format::String),
+    ("targetActivity", 0x0101_0202, Format::String),
+    ("alwaysRetainTaskState", 0x0101_0203, Format::Boolean),
+    ("minSdkVersion", 0x0101_020c, Format::Integer),
+    ("versionCode", 0x0101_021b, Format::Integer),
+    ("versionName", 0x0101_021c, Format::String),
+    ("targetSdkVersion", 0x0101_0270, Format::Integer),
+    ("maxSdkVersion", 0x0101_0271, Format::Integer),
+    ("glEsVersion", 0x0101_0281, Format::Hex),
+    ("required", 0x0101_028e, Format::Boolean),
+    ("banner", 0x0101_03f2, Format::String),
+    ("extractNativeLibs", 0x0101_04ea, Format::Boolean),
+    ("usesCleartextTraffic", 0x0101_04ec, Format::Boolean),
+    ("resizeableActivity", 0x0101_04f6, Format::Boolean),
+    ("version", 0x0101_0519, Format::Integer),
+    (
+        "windowLayoutInDisplayCutoutMode",
+        0x0101_0586,
+        Format::Enum(DISPLAY_CUTOUT_MODES),
+    ),
+    ("shell", 0x0101_0594, Format::Boolean),
+    ("requestLegacyExternalStorage", 0x0101_0603, Format::Boolean),
+    (
+        "preserveLegacyExternalStorage",
+        0x0101_0614,
+        Format::Boolean,
+    ),
+    ("enableOnBackInvokedCallback", 0x0101_066c, Format::Boolean),
+];
+
+/// An attribute compiled to a `Res_value`.
+struct Attribute {
+    namespace: Option<u32>,
+    name: u32,
+    /// The ID of an attribute of the `android` namespace, which attributes are sorted by
+    resource_id: u32,
+    raw_value: u32,
+    data_type: u8,
+    data: u32,
+}
+
+enum Node {
+    Start {
+        name: u32,
+        attributes: Vec<Attribute>,
+    },
+    End {
+        name: u32,
+    },
+}
+
+/// The strings of the document, starting with the names of the attributes in
+/// [`StringPool::resource_ids`] as the resource map requires.
+#[derive(Default)]
+struct StringPool {
+    strings: Vec<String>,
+    indices: HashMap<String, u32>,
+    resource_ids: Vec<u32>,
+}
+
+impl StringPool {
+    fn intern(&mut self, string: &str) -> u32 {
+        if let Some(&index) = self.indices.get(string) {
+            return index;
+        }
+        let index = self.strings.len() as u32;
+        self.strings.push(string.to_string());
+        self.indices.insert(string.to_string(), index);
+        index
+    }
+
+    /// A UTF-16 string pool chunk.
+    fn encode(&self) -> Vec<u8> {
+        let mut offsets = vec![];
+        let mut data = vec![];
+        for string in &self.strings {
+            offsets.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            let units = string.encode_utf16().collect::<Vec<_>>();
+            // Lengths above 0x7fff take two units, the first with the high bit set
+            if units.len() > 0x7fff {
+                data.extend_from_slice(&(0x8000 | (units.len() >> 16) as u16).to_le_bytes());
+            }
+            data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+            for unit in units.into_iter().chain([0]) {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        data.resize(data.len().next_multiple_of(4), 0);
+        let header_size = 28;
+        let strings_start = header_size + offsets.len() as u32;
+        let mut chunk = chunk_header(
+            RES_STRING_POOL_TYPE,
+            header_size as u16,
+            strings_start + data.len() as u32,
+        );
+        chunk.extend_from_slice(&(self.strings.len() as u32).to_le_bytes());
+        // No styles, UTF-16
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&strings_start.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&offsets);
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+}
+
+fn chunk_header(chunk_type: u16, header_size: u16, size: u32) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(&chunk_type.to_le_bytes());
+    header.extend_from_slice(&header_size.to_le_bytes());
+    header.extend_from_slice(&size.to_le_bytes());
+    header
+}
+
+/// A `ResXMLTree_node` with its extension, at `line`.
+fn node(chunk_type: u16, line: u32, extension: &[u8]) -> Vec<u8> {
+    let mut chunk = chunk_header(chunk_type, 16, 16 + extension.len() as u32);
+    chunk.extend_from_slice(&line.to_le_bytes());
+    // No comment
+    chunk.extend_from_slice(&u32::MAX.to_le_bytes());
+    chunk.extend_from_slice(extension);
+    chunk
+}
+
+/// The manifest is serialized by [`quick_xml`] too, so it only fails to parse back if
+/// that's broken.
+fn xml_error(error: impl Into<DeError>) -> NdkError {
+    NdkError::Serialize(error.into())
+}
+
+fn unsupported(what: String) -> NdkError {
+    NdkError::NativePackagingUnsupported(what)
+}
+
+/// Compiles the value of the attribute `name` of the `android` namespace.
+fn compile_value(
+    name: &str,
+    value: &str,
+    format: Format,
+    pool: &mut StringPool,
+) -> Result<(u8, u32), NdkError> {
+    let invalid = || unsupported(format!("`android:{}=\"{}\"`", name, value));
+    if value.starts_with('@') || value.starts_with('?') {
+        return Err(unsupported(format!(
+            "resource references like `android:{}=\"{}\"`",
+            name, value
+        )));
+    }
+    let boolean = |value: &str| match value {
+        "true" => Some(u32::MAX),
+        "false" => Some(0),
+        _ => None,
+    };
+    let integer = |value: &str| match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse::<i32>().ok().map(|value| value as u32),
+    };
+    let lookup = |values: &[(&str, u32)], value: &str| {
+        values
+            .iter()
+            .find(|(name, _)| *name == value.trim())
+            .map(|(_, value)| *value)
+    };
+    Ok(match format {
+        Format::String => (TYPE_STRING, pool.intern(value)),
+        Format::Boolean => (TYPE_INT_BOOLEAN, boolean(value).ok_or_else(invalid)?),
+        Format::Integer => (TYPE_INT_DEC, integer(value).ok_or_else(invalid)?),
+        Format::Hex => (TYPE_INT_HEX, integer(value).ok_or_else(invalid)?),
+        Format::Enum(values) => (TYPE_INT_DEC, lookup(values, value).ok_or_else(invalid)?),
+        Format::Flags(values) => {
+            let mut flags = 0;
+            for flag in value.split('|') {
+                flags |= lookup(values, flag).ok_or_else(invalid)?;
+            }
+            (TYPE_INT_HEX, flags)
+        }
+        Format::Any => {
+            if let Some(boolean) = boolean(value) {
+                (TYPE_INT_BOOLEAN, boolean)
+            } else if let Some(integer) = integer(value) {
+                let is_hex = value.starts_with("0x");
+                (if is_hex { TYPE_INT_HEX } else { TYPE_INT_DEC }, integer)
+            } else if let Some(float) = value.parse::<f32>().ok().filter(|_| value.contains('.')) {
+                (TYPE_FLOAT, float.to_bits())
+            } else {
+                (TYPE_STRING, pool.intern(value))
+            }
+        }
+    })
+}
+
+/// An element as its name and its attributes as namespace prefix, name and value.
+type Element = (String, Vec<(Option<String>, String, String)>);
+
+/// Compiles `xml`, which declares the `android` namespace on its root element.
+pub(crate) fn compile(xml: &str) -> Result<Vec<u8>, NdkError> {
+    // `None` ends the innermost element
+    let mut events: Vec<Option<Element>> = vec![];
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(start) => events.push(Some(read_element(&start)?)),
+            Event::Empty(start) => events.extend([Some(read_element(&start)?), None]),
+            Event::End(_) => events.push(None),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    // The names of the attributes with resource IDs come first in the pool, in the
+    // order of the resource map
+    let mut pool = StringPool::default();
+    let mut used = vec![];
+    for (_, attributes) in events.iter().flatten() {
+        for (prefix, name, _) in attributes {
+            if prefix.as_deref() == Some("android") {
+                let (_, id, _) = find_attribute(name)?;
+                used.push((id, name));
+            }
+        }
+    }
+    used.sort();
+    used.dedup();
+    for (id, name) in used {
+        pool.intern(name);
+        pool.resource_ids.push(id);
+    }
+
+    let mut namespaces = vec![];
+    let mut nodes = vec![];
+    let mut open = vec![];
+    for event in events {
+        let Some((name, attributes)) = event else {
+            nodes.push(Node::End {
+                name: open.pop().unwrap(),
+            });
+            continue;
+        };
+        let mut compiled = vec![];
+        for (prefix, attribute, value) in attributes {
+            match prefix.as_deref() {
+                Some("xmlns") => {
+                    namespaces.push((pool.intern(&attribute), pool.intern(&value)));
+                }
+                Some("android") => {
+                    let (_, id, format) = find_attribute(&attribute)?;
+                    let (data_type, data) = compile_value(&attribute, &value, format, &mut pool)?;
+                    compiled.push(Attribute {
+                        namespace: Some(pool.intern(ANDROID_NAMESPACE)),
+                        name: pool.intern(&attribute),
+                        resource_id: id,
+                        raw_value: if data_type == TYPE_STRING {
+                            data
+                        } else {
+                            u32::MAX
+                        },
+                        data_type,
+                        data,
+                    });
+                }
+                Some(prefix) => {
+                    return Err(unsupported(format!("the `{}` namespace", prefix)));
+                }
+                None => {
+                    let data = pool.intern(&value);
+                    compiled.push(Attribute {
+                        namespace: None,
+                        name: pool.intern(&attribute),
+                        resource_id: 0,
+                        raw_value: data,
+                        data_type: TYPE_STRING,
+                        data,
+                    });
+                }
+            }
+        }
+        // Android looks attributes up by walking them in the order of their IDs
+        compiled.sort_by_key(|attribute| attribute.resource_id);
+        let name = pool.intern(&name);
+        open.push(name);
+        nodes.push(Node::Start {
+            name,
+            attributes: compiled,
+        });
+    }
+
+    let mut body = chunk_header(
+        RES_XML_RESOURCE_MAP_TYPE,
+        8,
+        8 + 4 * pool.resource_ids.len() as u32,
+    );
+    for id in &pool.resource_ids {
+        body.extend_from_slice(&id.to_le_bytes());
+    }
+    for (prefix, uri) in &namespaces {
+        let extension = [prefix.to_le_bytes(), uri.to_le_bytes()].concat();
+        body.extend_from_slice(&node(RES_XML_START_NAMESPACE_TYPE, 1, &extension));
+    }
+    // Without the source lines, elements are numbered instead
+    for (line, element) in (1..).zip(&nodes) {
+        match element {
+            Node::Start { name, attributes } => {
+                let mut extension = vec![];
+                extension.extend_from_slice(&u32::MAX.to_le_bytes());
+                extension.extend_from_slice(&name.to_le_bytes());
+                // Attributes start after this header and are 20 bytes each, and there
+                // is no `id`, `class` or `style` attribute
+                extension.extend_from_slice(&20u16.to_le_bytes());
+                extension.extend_from_slice(&20u16.to_le_bytes());
+                extension.extend_from_slice(&(attributes.len() as u16).to_le_bytes());
+                extension.extend_from_slice(&[0; 6]);
+                for attribute in attributes {
+                    let namespace = attribute.namespace.unwrap_or(u32::MAX);
+                    extension.extend_from_slice(&namespace.to_le_bytes());
+                    extension.extend_from_slice(&attribute.name.to_le_bytes());
+                    extension.extend_from_slice(&attribute.raw_value.to_le_bytes());
+                    // A `Res_value` of 8 bytes
+                    extension.extend_from_slice(&8u16.to_le_bytes());
+                    extension.push(0);
+                    extension.push(attribute.data_type);
+                    extension.extend_from_slice(&attribute.data.to_le_bytes());
+                }
+                body.extend_from_slice(&node(RES_XML_START_ELEMENT_TYPE, line, &extension));
+            }
+            Node::End { name } => {
+                let extension = [u32::MAX.to_le_bytes(), name.to_le_bytes()].concat();
+                body.extend_from_slice(&node(RES_XML_END_ELEMENT_TYPE, line, &extension));
+            }
+        }
+    }
+    for (prefix, uri) in namespaces.iter().rev() {
+        let extension = [prefix.to_le_bytes(), uri.to_le_bytes()].concat();
+        body.extend_from_slice(&node(RES_XML_END_NAMESPACE_TYPE, 1, &extension));
+    }
+
+    let pool = pool.encode();
+    let mut document = chunk_header(RES_XML_TYPE, 8, (8 + pool.len() + body.len()) as u32);
+    document.extend_from_slice(&pool);
+    document.extend_from_slice(&body);
+    Ok(document)
+}
+
+fn find_attribute(name: &str) -> Result<(&'static str, u32, Format), NdkError> {
+    ATTRIBUTES
+        .iter()
+        .find(|(attribute, _, _)| *attribute == name)
+        .copied()
+        .ok_or_else(|| unsupported(format!("the `android:{}` attribute", name)))
+}
+
+/// The name of `start` and its attributes as namespace prefix, name and unescaped
+/// value.
+fn read_element(start: &BytesStart<'_>) -> Result<Element, NdkError> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut attributes = vec![];
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(xml_error)?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(xml_error)?.into_owned();
+        let (prefix, name) = match key.split_once(':') {
+            Some((prefix, name)) => (Some(prefix.to_string()), name.to_string()),
+            None => (None, key),
+        };
+        attributes.push((prefix, name, value));
+    }
+    Ok((name, attributes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{
+        Activity, ActivityAlias, AndroidManifest, Feature, IntentFilter, IntentFilterData,
+        MetaData, Package, Permission, PermissionGroup, Profileable, Queries, QueryProvider,
+        UsesPermission,
+    };
+    use std::collections::BTreeSet;
+
+    /// The strings of the pool at the start of `chunk`.
+    fn strings(chunk: &[u8]) -> Vec<String> {
+        let u32_at = |at: usize| u32::from_le_bytes(chunk[at..at + 4].try_into().unwrap());
+        let strings_start = u32_at(20) as usize;
+        (0..u32_at(8) as usize)
+            .map(|i| {
+                let start = strings_start + u32_at(28 + 4 * i) as usize;
+                let len = u16::from_le_bytes([chunk[start], chunk[start + 1]]) as usize;
+                let units = (0..len)
+                    .map(|j| {
+                        u16::from_le_bytes([chunk[start + 2 + 2 * j], chunk[start + 3 + 2 * j]])
+                    })
+                    .collect::<Vec<_>>();
+                String::from_utf16(&units).unwrap()
+            })
+            .collect()
+    }
+
+    /// An attribute as name, resource ID, type and value, where strings are resolved.
+    type Decoded = (String, u32, u8, String);
+
+    /// The elements of `document` with their attributes.
+    fn decode(document: &[u8]) -> Vec<(String, Vec<Decoded>)> {
+        let u16_at = |at: usize| u16::from_le_bytes([document[at], document[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(document[at..at + 4].try_into().unwrap());
+        assert_eq!(u16_at(0), RES_XML_TYPE);
+        assert_eq!(u32_at(4) as usize, document.len());
+        let mut strings = vec![];
+        let mut resource_ids = vec![];
+        let mut elements = vec![];
+        let mut at = 8;
+        while at < document.len() {
+            let size = u32_at(at + 4) as usize;
+            match u16_at(at) {
+                RES_STRING_POOL_TYPE => strings = self::strings(&document[at..at + size]),
+                RES_XML_RESOURCE_MAP_TYPE => {
+                    resource_ids = (at + 8..at + size).step_by(4).map(u32_at).collect()
+                }
+                RES_XML_START_ELEMENT_TYPE => {
+                    let name = strings[u32_at(at + 20) as usize].clone();
+                    let count = u16_at(at + 28) as usize;
+                    let attributes = (0..count)
+                        .map(|i| {
+                            let attribute = at + 36 + 20 * i;
+                            let name = u32_at(attribute + 4) as usize;
+                            let data_type = document[attribute + 15];
+                            let data = u32_at(attribute + 16);
+                            let value = match data_type {
+                                TYPE_STRING => strings[data as usize].clone(),
+                                _ => format!("{:#x}", data),
+                            };
+                            let id = resource_ids.get(name).copied().unwrap_or(0);
+                            (strings[name].clone(), id, data_type, value)
+                        })
+                        .collect();
+                    elements.push((name, attributes));
+                }
+                _ => {}
+            }
+            at += size;
+        }
+        elements
+    }
+
+    #[test]
+    fn compile_manifest() {
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "rust.example".to_string();
+        manifest.version_code = Some(3);
+        manifest.sdk.min_sdk_version = Some(24);
+        manifest.application.label = "Example & co".to_string();
+        manifest.application.debuggable = Some(true);
+        let elements = decode(&compile(&manifest.to_xml().unwrap()).unwrap());
+
+        let (name, attributes) = &elements[0];
+        assert_eq!(name, "manifest");
+        // Attributes without a namespace come first, then the rest in order of their IDs
+        assert_eq!(attributes[0].0, "package");
+        assert_eq!(attributes[0].3, "rust.example");
+        assert!(attributes.contains(&(
+            "versionCode".to_string(),
+            0x0101_021b,
+            TYPE_INT_DEC,
+            "0x3".to_string()
+        )));
+        let ids = attributes
+            .iter()
+            .map(|attribute| attribute.1)
+            .collect::<Vec<_>>();
+        assert!(ids.is_sorted());
+
+        let (_, sdk) = elements
+            .iter()
+            .find(|(name, _)| name == "uses-sdk")
+            .unwrap();
+        assert!(sdk.contains(&(
+            "minSdkVersion".to_string(),
+            0x0101_020c,
+            TYPE_INT_DEC,
+            "0x18".to_string()
+        )));
+        let (_, application) = elements
+            .iter()
+            .find(|(name, _)| name == "application")
+            .unwrap();
+        assert!(application.contains(&(
+            "label".to_string(),
+            0x0101_0001,
+            TYPE_STRING,
+            "Example & co".to_string()
+        )));
+        assert!(application.contains(&(
+            "debuggable".to_string(),
+            0x0101_000f,
+            TYPE_INT_BOOLEAN,
+            "0xffffffff".to_string()
+        )));
+    }
+
+    /// A manifest that renders every attribute of the `android` namespace.
+    fn full_manifest() -> AndroidManifest {
+        let filter = IntentFilter {
+            actions: vec!["android.intent.action.VIEW".to_string()],
+            categories: vec!["android.intent.category.DEFAULT".to_string()],
+            data: vec![IntentFilterData {
+                scheme: Some("https".to_string()),
+                host: Some("example.com".to_string()),
+                port: Some("443".to_string()),
+                path: Some("/app".to_string()),
+                path_pattern: Some("/app/.*".to_string()),
+                path_prefix: Some("/app/".to_string()),
+                mime_type: Some("text/plain".to_string()),
+            }],
+        };
+        let meta_data = MetaData {
+            name: "android.app.lib_name".to_string(),
+            value: "example".to_string(),
+            exported_to_dependents: false,
+        };
+        let mut manifest = AndroidManifest::default();
+        manifest.package = "rust.example".to_string();
+        manifest.shared_user_id = Some("rust.shared".to_string());
+        manifest.version_code = Some(3);
+        manifest.version_name = Some("1.0".to_string());
+        manifest.sdk.target_sdk_version = Some(34);
+        manifest.sdk.max_sdk_version = Some(35);
+        manifest.uses_feature.push(Feature {
+            name: Some("android.hardware.vulkan.level".to_string()),
+            required: Some(true),
+            version: Some(1),
+            opengles_version: Some((3, 2)),
+        });
+        manifest.uses_permission.push(UsesPermission {
+            name: "android.permission.INTERNET".to_string(),
+            max_sdk_version: Some(32),
+        });
+        manifest.permission.push(Permission {
+            name: "rust.example.permission.READ".to_string(),
+            protection_level: Some("signature|privileged".to_string()),
+            label: Some("Read".to_string()),
+            description: Some("Reads the data".to_string()),
+            permission_group: Some("rust.example.group".to_string()),
+        });
+        manifest.permission_group.push(PermissionGroup {
+            name: "rust.example.group".to_string(),
+            label: Some("Example".to_string()),
+            description: Some("Example permissions".to_string()),
+            icon: Some("icon.png".to_string()),
+        });
+        manifest.queries = Some(Queries {
+            package: vec![Package {
+                name: "rust.other".to_string(),
+            }],
+            intent: vec![filter.clone()],
+            provider: vec![QueryProvider {
+                authorities: "rust.other.provider".to_string(),
+                name: "rust.other.Provider".to_string(),
+            }],
+        });
+        let application = &mut manifest.application;
+        application.debuggable = Some(true);
+        application.theme = Some("Theme.Example".to_string());
+        application.icon = Some("icon.png".to_string());
+        application.banner = Some("banner.png".to_string());
+        application.label = "Example".to_string();
+        application.extract_native_libs = Some(true);
+        application.uses_cleartext_traffic = Some(false);
+        application.request_legacy_external_storage = Some(true);
+        application.preserve_legacy_external_storage = Some(true);
+        application.enable_on_back_invoked_callback = Some(true);
+        application.profileable = Some(Profileable {
+            shell: Some(true),
+            enabled: Some(true),
+        });
+        application.meta_data.push(meta_data.clone());
+        application.activity = Activity {
+            label: Some("Main".to_string()),
+            launch_mode: Some("singleTask".to_string()),
+            orientation: Some("sensorLandscape".to_string()),
+            exported: Some(true),
+            resizeable_activity: Some(false),
+            always_retain_task_state: Some(true),
+            window_layout_in_display_cutout_mode: Some("shortEdges".to_string()),
+            meta_data: vec![meta_data.clone()],
+            intent_filter: vec![filter.clone()],
+            ..Default::default()
+        };
+        application.activity_alias.push(ActivityAlias {
+            name: ".Alias".to_string(),
+            target_activity: "android.app.NativeActivity".to_string(),
+            enabled: Some(false),
+            exported: Some(true),
+            icon: Some("icon.png".to_string()),
+            label: Some("Alias".to_string()),
+            meta_data: vec![meta_data],
+            intent_filter: vec![filter],
+        });
+        manifest
+    }
+
+    #[test]
+    fn compile_every_attribute() {
+        let xml = full_manifest().to_xml().unwrap();
+        let elements = decode(&compile(&xml).unwrap());
+        let compiled = elements
+            .iter()
+            .flat_map(|(_, attributes)| attributes)
+            .filter(|attribute| attribute.1 != 0)
+            .map(|attribute| attribute.0.as_str())
+            .collect::<BTreeSet<_>>();
+        let known = ATTRIBUTES
+            .iter()
+            .map(|(name, _, _)| *name)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(compiled, known);
+
+        let (_, activity) = elements
+            .iter()
+            .find(|(name, _)| name == "activity")
+            .unwrap();
+        assert!(activity.contains(&(
+            "windowLayoutInDisplayCutoutMode".to_string(),
+            0x0101_0586,
+            TYPE_INT_DEC,
+            "0x1".to_string()
+        )));
+        let (_, profileable) = elements
+            .iter()
+            .find(|(name, _)| name == "profileable")
+            .unwrap();
+        assert!(profileable.contains(&(
+            "shell".to_string(),
+            0x0101_0594,
+            TYPE_INT_BOOLEAN,
+            "0xffffffff".to_string()
+        )));
+
+        // Every value that the manifest accepts for an enum or flags attribute
+        let mut manifest = full_manifest();
+        for mode in Activity::DISPLAY_CUTOUT_MODES {
+            manifest
+                .application
+                .activity
+                .window_layout_in_display_cutout_mode = Some(mode.to_string());
+            compile(&manifest.to_xml().unwrap()).unwrap();
+        }
+        for level in Permission::PROTECTION_LEVELS
+            .iter()
+            .chain(Permission::PROTECTION_FLAGS)
+        {
+            manifest.permission[0].protection_level = Some(level.to_string());
+            compile(&manifest.to_xml().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn unsupported_values() {
+        let xml = |attribute: &str| {
+            format!(
+                r#"<manifest xmlns:android="{}" package="a"><application {}/></manifest>"#,
+                ANDROID_NAMESPACE, attribute
+            )
+        };
+        assert!(compile(&xml(r#"android:hasCode="false""#)).is_ok());
+        for attribute in [
+            r#"android:icon="@mipmap/icon""#,
+            r#"android:hasCode="maybe""#,
+            r#"android:unknownAttribute="true""#,
+            r#"tools:replace="android:label""#,
+        ] {
+            assert!(
+                matches!(
+                    compile(&xml(attribute)),
+                    Err(NdkError::NativePackagingUnsupported(_))
+                ),
+                "{}",
+                attribute
+            );
+        }
+    }
+}
@@ -0,0 +1,470 @@
+//! The primitives that reading a PKCS#12 keystore and signing with its RSA key need:
+//! HMAC-SHA256, PBKDF2, AES-256-CBC decryption and RSASSA-PKCS1-v1_5.
+
+use crate::checksums::Sha256;
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut sha256 = Sha256::new();
+    sha256.update(data);
+    sha256.finish()
+}
+
+/// HMAC-SHA256 as specified by RFC 2104, with the padded key hashed once.
+#[derive(Clone)]
+struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0; 64];
+        if key.len() > 64 {
+            block[..32].copy_from_slice(&sha256(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(&block.map(|b| b ^ 0x5c));
+        Self { inner, outer }
+    }
+
+    fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        inner.update(data);
+        let mut outer = self.outer.clone();
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+}
+
+/// PBKDF2 with HMAC-SHA256 as specified by RFC 8018, deriving `len` bytes.
+pub(crate) fn pbkdf2_hmac_sha256(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    len: usize,
+) -> Vec<u8> {
+    let hmac = HmacSha256::new(password);
+    let mut key = Vec::with_capacity(len);
+    for block in 1..=len.div_ceil(32) as u32 {
+        let mut u = hmac.mac(&[salt, &block.to_be_bytes()].concat());
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac.mac(&u);
+            t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+        }
+        key.extend_from_slice(&t);
+    }
+    key.truncate(len);
+    key
+}
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// The AES S-box and its inverse, computed from the multiplicative inverses in GF(2^8)
+/// rather than spelled out.
+fn sboxes() -> ([u8; 256], [u8; 256]) {
+    let mut sbox = [0; 256];
+    let mut inverse = [0; 256];
+    for i in 0..=255u8 {
+        // a^254 is the inverse of a, and maps 0 to 0
+        let mut inv = 1;
+        for _ in 0..254 {
+            inv = gf_mul(inv, i);
+        }
+        let s = inv
+            ^ inv.rotate_left(1)
+            ^ inv.rotate_left(2)
+            ^ inv.rotate_left(3)
+            ^ inv.rotate_left(4)
+            ^ 0x63;
+        sbox[i as usize] = s;
+        inverse[s as usize] = i;
+    }
+    (sbox, inverse)
+}
+
+/// AES-256 decryption as specified by FIPS 197.
+struct Aes256 {
+    round_keys: [[u8; 16]; 15],
+    inverse_sbox: [u8; 256],
+}
+
+impl Aes256 {
+    fn new(key: &[u8; 32]) -> Self {
+        let (sbox, inverse_sbox) = sboxes();
+        let mut words = [[0u8; 4]; 60];
+        for (word, chunk) in words.iter_mut().zip(key.chunks(4)) {
+            word.copy_from_slice(chunk);
+        }
+        let mut rcon = 1;
+        for i in 8..60 {
+            let mut temp = words[i - 1];
+            if i % 8 == 0 {
+                temp.rotate_left(1);
+                temp = temp.map(|b| sbox[b as usize]);
+                temp[0] ^= rcon;
+                rcon = gf_mul(rcon, 2);
+            } else if i % 8 == 4 {
+                temp = temp.map(|b| sbox[b as usize]);
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 8][j] ^ temp[j];
+            }
+        }
+        let mut round_keys = [[0; 16]; 15];
+        for (round_key, words) in round_keys.iter_mut().zip(words.chunks(4)) {
+            round_key.copy_from_slice(words.as_flattened());
+        }
+        Self {
+            round_keys,
+            inverse_sbox,
+        }
+    }
+
+    /// Decrypts a block, whose byte `r + 4 * c` is row `r` of column `c` of the state.
+    fn decrypt_block(&self, block: &mut [u8; 16]) {
+        let add_round_key = |block: &mut [u8; 16], round: usize| {
+            block
+                .iter_mut()
+                .zip(self.round_keys[round])
+                .for_each(|(b, k)| *b ^= k);
+        };
+        let inv_shift_rows_and_sub_bytes = |block: &mut [u8; 16]| {
+            let state = *block;
+            for r in 0..4 {
+                for c in 0..4 {
+                    block[r + 4 * ((c + r) % 4)] = self.inverse_sbox[state[r + 4 * c] as usize];
+                }
+            }
+        };
+        add_round_key(block, 14);
+        for round in (0..14).rev() {
+            inv_shift_rows_and_sub_bytes(block);
+            add_round_key(block, round);
+            if round == 0 {
+                break;
+            }
+            for column in block.chunks_mut(4) {
+                let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+                column[0] = gf_mul(a, 14) ^ gf_mul(b, 11) ^ gf_mul(c, 13) ^ gf_mul(d, 9);
+                column[1] = gf_mul(a, 9) ^ gf_mul(b, 14) ^ gf_mul(c, 11) ^ gf_mul(d, 13);
+                column[2] = gf_mul(a, 13) ^ gf_mul(b, 9) ^ gf_mul(c, 14) ^ gf_mul(d, 11);
+                column[3] = gf_mul(a, 11) ^ gf_mul(b, 13) ^ gf_mul(c, 9) ^ gf_mul(d, 14);
+            }
+        }
+    }
+}
+
+/// Decrypts AES-256-CBC with PKCS#7 padding, [`None`] if the padding is invalid, which
+/// mostly means that the key was derived from the wrong password.
+pub(crate) fn aes256_cbc_decrypt(key: &[u8; 32], iv: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if iv.len() != 16 || data.is_empty() || data.len() % 16 != 0 {
+        return None;
+    }
+    let aes = Aes256::new(key);
+    let mut previous = iv;
+    let mut plaintext = Vec::with_capacity(data.len());
+    for chunk in data.chunks(16) {
+        let mut block: [u8; 16] = chunk.try_into().unwrap();
+        aes.decrypt_block(&mut block);
+        plaintext.extend(block.iter().zip(previous).map(|(b, p)| b ^ p));
+        previous = chunk;
+    }
+    let padding = *plaintext.last()? as usize;
+    if !(1..=16).contains(&padding)
+        || !plaintext[plaintext.len() - padding..]
+            .iter()
+            .all(|&b| b as usize == padding)
+    {
+        return None;
+    }
+    plaintext.truncate(plaintext.len() - padding);
+    Some(plaintext)
+}
+
+/// An unsigned integer of little-endian 32-bit limbs, just enough for RSA.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    /// The integer of big-endian `bytes`, in at least `limbs` limbs.
+    fn from_be_bytes(bytes: &[u8], limbs: usize) -> Self {
+        let mut value = vec![0; limbs.max(bytes.len().div_ceil(4))];
+        for (i, &byte) in bytes.iter().rev().enumerate() {
+            value[i / 4] |= (byte as u32) << (8 * (i % 4));
+        }
+        Self(value)
+    }
+
+    /// The lowest `len` bytes in big-endian order.
+    fn to_be_bytes(&self, len: usize) -> Vec<u8> {
+        (0..len)
+            .rev()
+            .map(|i| (self.0.get(i / 4).unwrap_or(&0) >> (8 * (i % 4))) as u8)
+            .collect()
+    }
+
+    fn bits(&self) -> usize {
+        match self.0.iter().rposition(|&limb| limb != 0) {
+            Some(i) => 32 * i + 32 - self.0[i].leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.0[i / 32] >> (i % 32) & 1 != 0
+    }
+}
+
+/// `a >= b` for integers with `b.len()` limbs, plus a carry limb on top of `a`.
+fn ge(a: &[u32], carry: u32, b: &[u32]) -> bool {
+    if carry != 0 {
+        return true;
+    }
+    for (a, b) in a.iter().zip(b).rev() {
+        if a != b {
+            return a > b;
+        }
+    }
+    true
+}
+
+/// `a -= b`, ignoring the borrow out of the top limb.
+fn sub_assign(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0;
+    for (a, &b) in a.iter_mut().zip(b) {
+        let (d, b1) = a.overflowing_sub(b);
+        let (d, b2) = d.overflowing_sub(borrow);
+        *a = d;
+        borrow = (b1 || b2) as u32;
+    }
+}
+
+/// Modular arithmetic in the Montgomery form of an odd modulus.
+struct Montgomery<'a> {
+    modulus: &'a [u32],
+    /// `-modulus^-1 mod 2^32`
+    m0: u32,
+    /// `R^2 mod modulus`, to convert into the Montgomery form
+    r2: Vec<u32>,
+}
+
+impl<'a> Montgomery<'a> {
+    fn new(modulus: &'a [u32]) -> Self {
+        // Newton's iteration doubles the correct low bits of the inverse every step
+        let mut inverse = 1u32;
+        for _ in 0..5 {
+            inverse = inverse.wrapping_mul(2u32.wrapping_sub(modulus[0].wrapping_mul(inverse)));
+        }
+        // Doubling 1 mod `modulus` 2 * 32 * len times gives R^2
+        let mut r2 = vec![0; modulus.len()];
+        r2[0] = 1;
+        for _ in 0..64 * modulus.len() {
+            let carry = r2[modulus.len() - 1] >> 31;
+            for i in (1..r2.len()).rev() {
+                r2[i] = (r2[i] << 1) | (r2[i - 1] >> 31);
+            }
+            r2[0] <<= 1;
+            if ge(&r2, carry, modulus) {
+                sub_assign(&mut r2, modulus);
+            }
+        }
+        Self {
+            modulus,
+            m0: inverse.wrapping_neg(),
+            r2,
+        }
+    }
+
+    /// `a * b / R mod modulus`, by coarsely integrated operand scanning.
+    fn mul(&self, a: &[u32], b: &[u32]) -> Vec<u32> {
+        let n = self.modulus;
+        let s = n.len();
+        let mut t = vec![0u32; s + 2];
+        for &b in b {
+            let mut carry = 0u64;
+            for j in 0..s {
+                let sum = t[j] as u64 + a[j] as u64 * b as u64 + carry;
+                t[j] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[s] as u64 + carry;
+            t[s] = sum as u32;
+            t[s + 1] = (sum >> 32) as u32;
+
+            let m = t[0].wrapping_mul(self.m0) as u64;
+            let mut carry = (t[0] as u64 + m * n[0] as u64) >> 32;
+            for j in 1..s {
+                let sum = t[j] as u64 + m * n[j] as u64 + carry;
+                t[j - 1] = sum as u32;
+                carry = sum >> 32;
+            }
+            let sum = t[s] as u64 + carry;
+            t[s - 1] = sum as u32;
+            t[s] = t[s + 1] + (sum >> 32) as u32;
+        }
+        let carry = t[s];
+        t.truncate(s);
+        if ge(&t, carry, n) {
+            sub_assign(&mut t, n);
+        }
+        t
+    }
+
+    /// `base^exponent mod modulus`, for `base < modulus`.
+    fn pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        let mut one = vec![0; self.modulus.len()];
+        one[0] = 1;
+        let base = self.mul(&base.0, &self.r2);
+        let mut result = self.mul(&one, &self.r2);
+        for i in (0..exponent.bits()).rev() {
+            result = self.mul(&result, &result);
+            if exponent.bit(i) {
+                result = self.mul(&result, &base);
+            }
+        }
+        BigUint(self.mul(&result, &one))
+    }
+}
+
+/// The `DigestInfo` of a SHA-256 digest, without the digest.
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+/// An RSA private key, from the big-endian modulus and private exponent.
+pub(crate) struct RsaPrivateKey {
+    modulus: BigUint,
+    private_exponent: BigUint,
+    /// Length of the modulus and the signatures in bytes
+    len: usize,
+}
+
+impl RsaPrivateKey {
+    /// [`None`] for an even modulus, which isn't an RSA key.
+    pub(crate) fn new(modulus: &[u8], private_exponent: &[u8]) -> Option<Self> {
+        let modulus = strip_leading_zeros(modulus);
+        let modulus_be = BigUint::from_be_bytes(modulus, 0);
+        if modulus.len() < 64 || modulus_be.0[0] & 1 == 0 {
+            return None;
+        }
+        let private_exponent =
+            BigUint::from_be_bytes(strip_leading_zeros(private_exponent), modulus_be.0.len());
+        Some(Self {
+            len: modulus.len(),
+            modulus: modulus_be,
+            private_exponent,
+        })
+    }
+
+    /// Signs the SHA-256 of `data` with RSASSA-PKCS1-v1_5, as specified by RFC 8017.
+    pub(crate) fn sign_sha256(&self, data: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![0xff; self.len];
+        encoded[0] = 0;
+        encoded[1] = 1;
+        let t = [&SHA256_DIGEST_INFO[..], &sha256(data)].concat();
+        let start = self.len - t.len();
+        encoded[start - 1] = 0;
+        encoded[start..].copy_from_slice(&t);
+        let message = BigUint::from_be_bytes(&encoded, self.modulus.0.len());
+        Montgomery::new(&self.modulus.0)
+            .pow(&message, &self.private_exponent)
+            .to_be_bytes(self.len)
+    }
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub(crate) fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// `signature^exponent mod modulus`, to check signatures in tests.
+    pub(crate) fn rsa_public(modulus: &[u8], exponent: &[u8], signature: &[u8]) -> Vec<u8> {
+        let modulus = strip_leading_zeros(modulus);
+        let n = BigUint::from_be_bytes(modulus, 0);
+        let s = BigUint::from_be_bytes(signature, n.0.len());
+        let e = BigUint::from_be_bytes(exponent, 0);
+        Montgomery::new(&n.0).pow(&s, &e).to_be_bytes(modulus.len())
+    }
+
+    #[test]
+    fn hmac_and_pbkdf2() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(&HmacSha256::new(b"Jefe").mac(b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // RFC 7914, section 11
+        assert_eq!(
+            hex(&pbkdf2_hmac_sha256(b"passwd", b"salt", 1, 64)),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+            49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    #[test]
+    fn aes256_decrypt() {
+        // FIPS 197, appendix C.3
+        let key = unhex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let aes = Aes256::new(&key.try_into().unwrap());
+        let mut block = unhex("8ea2b7ca516745bfeafc49904b496089")
+            .try_into()
+            .unwrap();
+        aes.decrypt_block(&mut block);
+        assert_eq!(hex(&block), "00112233445566778899aabbccddeeff");
+    }
+
+    #[test]
+    fn rsa_roundtrip() {
+        // A toy key of the primes 2^64 - 59 and 2^61 - 1, spanning four limbs
+        let p = 0xffff_ffff_ffff_ffc5u64 as u128;
+        let q = 0x1fff_ffff_ffff_ffffu64 as u128;
+        let n = p * q;
+        let e = 65537u128;
+        // d = e^-1 mod (p - 1)(q - 1), by the extended Euclidean algorithm
+        let phi = (p - 1) * (q - 1);
+        let (mut r0, mut r1, mut t0, mut t1) = (phi as i128, e as i128, 0i128, 1i128);
+        while r1 != 0 {
+            let quotient = r0 / r1;
+            (r0, r1) = (r1, r0 - quotient * r1);
+            (t0, t1) = (t1, t0 - quotient * t1);
+        }
+        let d = t0.rem_euclid(phi as i128) as u128;
+        let modulus = BigUint::from_be_bytes(&n.to_be_bytes(), 0);
+        let montgomery = Montgomery::new(&modulus.0);
+        let message = BigUint::from_be_bytes(&0x1234_5678_9abc_def0u64.to_be_bytes(), 4);
+        let signature = montgomery.pow(&message, &BigUint::from_be_bytes(&d.to_be_bytes(), 4));
+        let recovered = montgomery.pow(&signature, &BigUint::from_be_bytes(&e.to_be_bytes(), 4));
+        assert_eq!(recovered, message);
+    }
+}
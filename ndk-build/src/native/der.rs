@@ -0,0 +1,129 @@
+//! A reader for the DER encoding of ASN.1, for the structures of PKCS#12 keystores
+//! and X.509 certificates. Everything that isn't valid DER reads as [`None`].
+
+pub(crate) const SEQUENCE: u8 = 0x30;
+pub(crate) const SET: u8 = 0x31;
+pub(crate) const INTEGER: u8 = 0x02;
+pub(crate) const OCTET_STRING: u8 = 0x04;
+pub(crate) const OBJECT_IDENTIFIER: u8 = 0x06;
+/// `[0]` wrapping a value, like the content of a `ContentInfo`
+pub(crate) const EXPLICIT_0: u8 = 0xa0;
+/// `[0]` replacing the tag of a primitive value, like the `encryptedContent` of an
+/// `EncryptedContentInfo`
+pub(crate) const IMPLICIT_0: u8 = 0x80;
+
+/// A tag, length and value.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Tlv<'a> {
+    pub(crate) tag: u8,
+    pub(crate) value: &'a [u8],
+    /// The whole encoding, including the tag and length
+    pub(crate) raw: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// The value, if this has `tag`.
+    pub(crate) fn expect(self, tag: u8) -> Option<&'a [u8]> {
+        (self.tag == tag).then_some(self.value)
+    }
+
+    /// The elements of a `SEQUENCE`, `SET` or explicitly tagged value.
+    pub(crate) fn elements(self, tag: u8) -> Option<Der<'a>> {
+        self.expect(tag).map(Der::new)
+    }
+}
+
+/// A sequence of DER values, read front to back.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Reads the next value, with a length in the short or long form.
+    pub(crate) fn read(&mut self) -> Option<Tlv<'a>> {
+        let [tag, first, rest @ ..] = self.0 else {
+            return None;
+        };
+        let (len, header) = match *first {
+            len @ 0..=0x7f => (len as usize, 2),
+            0x81..=0x84 => {
+                let bytes = (*first & 0x7f) as usize;
+                let len = rest
+                    .get(..bytes)?
+                    .iter()
+                    .fold(0, |len, &b| (len << 8) | b as usize);
+                (len, 2 + bytes)
+            }
+            // Indefinite lengths are BER, not DER
+            _ => return None,
+        };
+        let end = header.checked_add(len)?;
+        let raw = self.0.get(..end)?;
+        self.0 = &self.0[end..];
+        Some(Tlv {
+            tag: *tag,
+            value: &raw[header..],
+            raw,
+        })
+    }
+
+    /// Reads the next value, if it has `tag`.
+    pub(crate) fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        self.read()?.expect(tag)
+    }
+
+    /// Reads the elements of the next value, if it has `tag`.
+    pub(crate) fn elements(&mut self, tag: u8) -> Option<Der<'a>> {
+        self.read()?.elements(tag)
+    }
+
+    /// Reads a non-negative `INTEGER` that fits into a [`u32`].
+    pub(crate) fn small_integer(&mut self) -> Option<u32> {
+        let value = self.expect(INTEGER)?;
+        if value.is_empty() || value.len() > 5 || value[0] & 0x80 != 0 {
+            return None;
+        }
+        value
+            .iter()
+            .try_fold(0u32, |n, &b| n.checked_mul(256).map(|n| n | b as u32))
+    }
+}
+
+impl<'a> Iterator for Der<'a> {
+    type Item = Tlv<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        let long = [&[SEQUENCE, 0x81, 0x80][..], &[0; 0x80]].concat();
+        let mut der = Der::new(&long);
+        let tlv = der.read().unwrap();
+        assert_eq!(tlv.value.len(), 0x80);
+        assert_eq!(tlv.raw.len(), 0x83);
+        assert!(der.is_empty());
+
+        assert_eq!(
+            Der::new(&[INTEGER, 2, 0x01, 0x00]).small_integer(),
+            Some(256)
+        );
+        // Negative, truncated and indefinite-length values
+        assert_eq!(Der::new(&[INTEGER, 1, 0xff]).small_integer(), None);
+        assert!(Der::new(&[OCTET_STRING, 3, 0]).read().is_none());
+        assert!(Der::new(&[SEQUENCE, 0x80, 0, 0]).read().is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Reads the signing key and certificate out of a PKCS#12 keystore, as generated by
+//! `keytool` of JDK 12 and later, whose bags are encrypted with PBES2, PBKDF2 with
+//! HMAC-SHA256 and AES-256-CBC. The MAC over the keystore is not verified, a wrong
+//! password is noticed when decrypting.
+
+use super::crypto::{RsaPrivateKey, aes256_cbc_decrypt, pbkdf2_hmac_sha256};
+use super::der::{
+    Der, EXPLICIT_0, IMPLICIT_0, INTEGER, OBJECT_IDENTIFIER, OCTET_STRING, SEQUENCE, SET, Tlv,
+};
+
+// The DER of the object identifiers of PKCS#7, #8, #9 and #12, without tag and length
+const DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const ENCRYPTED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x06];
+const KEY_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x01,
+];
+const SHROUDED_KEY_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x02,
+];
+const CERT_BAG: &[u8] = &[
+    0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x0c, 0x0a, 0x01, 0x03,
+];
+const X509_CERTIFICATE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x16, 0x01];
+const LOCAL_KEY_ID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x15];
+const PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+const PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+const HMAC_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+const AES256_CBC: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a];
+const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// Why a keystore could not be read.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum KeystoreError {
+    /// Not valid PKCS#12, or of a structure that isn't supported
+    Malformed(&'static str),
+    /// Supported in principle by Android, but not by this reader
+    Unsupported(&'static str),
+    WrongPassword,
+}
+
+type Result<T> = std::result::Result<T, KeystoreError>;
+
+/// The RSA key of a keystore, with the certificate of its public key.
+pub(crate) struct SigningKey {
+    pub(crate) private_key: RsaPrivateKey,
+    /// The DER of the X.509 certificate
+    pub(crate) certificate: Vec<u8>,
+    /// The DER of the `SubjectPublicKeyInfo` of the certificate
+    pub(crate) public_key: Vec<u8>,
+}
+
+fn malformed<T>(what: &'static str) -> impl FnOnce() -> Result<T> {
+    move || Err(KeystoreError::Malformed(what))
+}
+
+/// Reads the only key of the PKCS#12 keystore `data` and its certificate.
+pub(crate) fn read_keystore(data: &[u8], password: &str) -> Result<SigningKey> {
+    // JKS and JCEKS keystores start with a magic number instead of a `SEQUENCE`
+    if data.starts_with(&[0xfe, 0xed, 0xfe, 0xed]) || data.starts_with(&[0xce, 0xce, 0xce, 0xce]) {
+        return Err(KeystoreError::Unsupported(
+            "JKS keystores, convert it with `keytool -importkeystore -deststoretype pkcs12`",
+        ));
+    }
+    let Some(auth_safe) = Der::new(data).elements(SEQUENCE).and_then(|mut pfx| {
+        pfx.small_integer().filter(|&version| version == 3)?;
+        content_info_data(pfx.read()?)
+    }) else {
+        return malformed("not a PKCS#12 keystore")();
+    };
+
+    let mut keys = vec![];
+    let mut certificates = vec![];
+    let content_infos = Der::new(auth_safe)
+        .elements(SEQUENCE)
+        .ok_or(KeystoreError::Malformed("no authenticated safe"))?;
+    for content_info in content_infos {
+        let safe_contents = read_content_info(content_info, password)?;
+        let bags = Der::new(&safe_contents)
+            .elements(SEQUENCE)
+            .ok_or(KeystoreError::Malformed("no safe contents"))?;
+        for bag in bags {
+            let SafeBag {
+                id,
+                value,
+                local_key_id,
+            } = read_safe_bag(bag).ok_or(KeystoreError::Malformed("invalid safe bag"))?;
+            let local_key_id = local_key_id.map(<[u8]>::to_vec);
+            if id == SHROUDED_KEY_BAG {
+                keys.push((decrypt_private_key_info(value, password)?, local_key_id));
+            } else if id == KEY_BAG {
+                keys.push((value.raw.to_vec(), local_key_id));
+            } else if id == CERT_BAG {
+                let certificate =
+                    read_cert_bag(value).ok_or(KeystoreError::Malformed("invalid certificate"))?;
+                certificates.push((certificate.to_vec(), local_key_id));
+            }
+        }
+    }
+
+    let (private_key_info, local_key_id) = match keys.len() {
+        0 => return malformed("no private key")(),
+        1 => keys.pop().unwrap(),
+        _ => {
+            return Err(KeystoreError::Unsupported(
+                "keystores with more than one key",
+            ));
+        }
+    };
+    let certificate = certificates
+        .iter()
+        .find(|(_, id)| *id == local_key_id)
+        .or(certificates.first())
+        .map(|(certificate, _)| certificate.clone())
+        .ok_or(KeystoreError::Malformed("no certificate"))?;
+    let public_key = subject_public_key_info(&certificate)
+        .ok_or(KeystoreError::Malformed("invalid certificate"))?
+        .to_vec();
+    Ok(SigningKey {
+        private_key: read_private_key_info(&private_key_info)?,
+        certificate,
+        public_key,
+    })
+}
+
+/// The content of a `ContentInfo` of type `data`.
+fn content_info_data(content_info: Tlv<'_>) -> Option<&[u8]> {
+    let mut content_info = content_info.elements(SEQUENCE)?;
+    if content_info.expect(OBJECT_IDENTIFIER)? != DATA {
+        return None;
+    }
+    content_info.elements(EXPLICIT_0)?.expect(OCTET_STRING)
+}
+
+/// The content of a `ContentInfo` of the authenticated safe, decrypted if it is of type
+/// `encryptedData`.
+fn read_content_info(content_info: Tlv<'_>, password: &str) -> Result<Vec<u8>> {
+    if let Some(data) = content_info_data(content_info) {
+        return Ok(data.to_vec());
+    }
+    let encrypted = (|| {
+        let mut content_info = content_info.elements(SEQUENCE)?;
+        if content_info.expect(OBJECT_IDENTIFIER)? != ENCRYPTED_DATA {
+            return None;
+        }
+        let mut encrypted_data = content_info.elements(EXPLICIT_0)?.elements(SEQUENCE)?;
+        encrypted_data.small_integer()?;
+        let mut encrypted_content_info = encrypted_data.elements(SEQUENCE)?;
+        encrypted_content_info.expect(OBJECT_IDENTIFIER)?;
+        let algorithm = encrypted_content_info.read()?;
+        let content = encrypted_content_info.expect(IMPLICIT_0)?;
+        Some((algorithm, content))
+    })();
+    let (algorithm, content) = encrypted.ok_or(KeystoreError::Malformed("unknown content type"))?;
+    decrypt(algorithm, content, password)
+}
+
+struct SafeBag<'a> {
+    id: &'a [u8],
+    value: Tlv<'a>,
+    /// Pairs a key with its certificate
+    local_key_id: Option<&'a [u8]>,
+}
+
+fn read_safe_bag(bag: Tlv<'_>) -> Option<SafeBag<'_>> {
+    let mut bag = bag.elements(SEQUENCE)?;
+    let id = bag.expect(OBJECT_IDENTIFIER)?;
+    let value = bag.elements(EXPLICIT_0)?.read()?;
+    let mut local_key_id = None;
+    if !bag.is_empty() {
+        for attribute in bag.elements(SET)? {
+            let mut attribute = attribute.elements(SEQUENCE)?;
+            if attribute.expect(OBJECT_IDENTIFIER)? == LOCAL_KEY_ID {
+                local_key_id = Some(attribute.elements(SET)?.expect(OCTET_STRING)?);
+            }
+        }
+    }
+    Some(SafeBag {
+        id,
+        value,
+        local_key_id,
+    })
+}
+
+fn read_cert_bag(value: Tlv<'_>) -> Option<&[u8]> {
+    let mut cert_bag = value.elements(SEQUENCE)?;
+    if cert_bag.expect(OBJECT_IDENTIFIER)? != X509_CERTIFICATE {
+        return None;
+    }
+    cert_bag.elements(EXPLICIT_0)?.expect(OCTET_STRING)
+}
+
+/// The `PrivateKeyInfo` of an `EncryptedPrivateKeyInfo`.
+fn decrypt_private_key_info(value: Tlv<'_>, password: &str) -> Result<Vec<u8>> {
+    let (algorithm, data) = (|| {
+        let mut info = value.elements(SEQUENCE)?;
+        Some((info.read()?, info.expect(OCTET_STRING)?))
+    })()
+    .ok_or(KeystoreError::Malformed("invalid encrypted private key"))?;
+    decrypt(algorithm, data, password)
+}
+
+/// Decrypts `data` with the PBES2 scheme of the `AlgorithmIdentifier` `algorithm`.
+fn decrypt(algorithm: Tlv<'_>, data: &[u8], password: &str) -> Result<Vec<u8>> {
+    let legacy = KeystoreError::Unsupported(
+        "keystores encrypted with legacy algorithms, convert it with a recent `keytool \
+        -importkeystore`",
+    );
+    let mut algorithm = algorithm
+        .elements(SEQUENCE)
+        .ok_or(KeystoreError::Malformed("invalid encryption algorithm"))?;
+    if algorithm.expect(OBJECT_IDENTIFIER) != Some(PBES2) {
+        return Err(legacy);
+    }
+    let params = (|| {
+        let mut params = algorithm.elements(SEQUENCE)?;
+        let mut kdf = params.elements(SEQUENCE)?;
+        let kdf_id = kdf.expect(OBJECT_IDENTIFIER)?;
+        let mut kdf_params = kdf.elements(SEQUENCE)?;
+        let salt = kdf_params.expect(OCTET_STRING)?;
+        let iterations = kdf_params.small_integer()?;
+        let mut prf = None;
+        for param in kdf_params {
+            // The optional key length is implied by the cipher
+            if param.tag == SEQUENCE {
+                prf = Some(Der::new(param.value).expect(OBJECT_IDENTIFIER)?);
+            }
+        }
+        let mut cipher = params.elements(SEQUENCE)?;
+        let cipher_id = cipher.expect(OBJECT_IDENTIFIER)?;
+        let iv = cipher.expect(OCTET_STRING)?;
+        Some((kdf_id, salt, iterations, prf, cipher_id, iv))
+    })();
+    let Some((kdf_id, salt, iterations, prf, cipher_id, iv)) = params else {
+        return malformed("invalid PBES2 parameters")();
+    };
+    // Without a PRF, PBKDF2 defaults to HMAC-SHA1
+    if kdf_id != PBKDF2 || prf != Some(HMAC_WITH_SHA256) || cipher_id != AES256_CBC {
+        return Err(legacy);
+    }
+    let key = pbkdf2_hmac_sha256(password.as_bytes(), salt, iterations, 32);
+    // The padding of a wrong key is only mostly invalid, but the plaintext never parses
+    aes256_cbc_decrypt(&key.try_into().unwrap(), iv, data)
+        .filter(|plaintext| {
+            let mut der = Der::new(plaintext);
+            der.read().is_some_and(|tlv| tlv.tag == SEQUENCE) && der.is_empty()
+        })
+        .ok_or(KeystoreError::WrongPassword)
+}
+
+/// The RSA key of a `PrivateKeyInfo`.
+fn read_private_key_info(info: &[u8]) -> Result<RsaPrivateKey> {
+    let key = (|| {
+        let mut info = Der::new(info).elements(SEQUENCE)?;
+        info.small_integer()?;
+        let algorithm = info.elements(SEQUENCE)?.expect(OBJECT_IDENTIFIER)?;
+        Some((algorithm, info.expect(OCTET_STRING)?))
+    })();
+    let Some((algorithm, key)) = key else {
+        return malformed("invalid private key")();
+    };
+    if algorithm != RSA_ENCRYPTION {
+        return Err(KeystoreError::Unsupported("keys other than RSA keys"));
+    }
+    // RSAPrivateKey ::= SEQUENCE { version, modulus, publicExponent, privateExponent, ... }
+    (|| {
+        let mut key = Der::new(key).elements(SEQUENCE)?;
+        key.small_integer()?;
+        let modulus = key.expect(INTEGER)?;
+        key.expect(INTEGER)?;
+        let private_exponent = key.expect(INTEGER)?;
+        RsaPrivateKey::new(modulus, private_exponent)
+    })()
+    .ok_or(KeystoreError::Malformed("invalid RSA key"))
+}
+
+/// The DER of the `SubjectPublicKeyInfo` of an X.509 `certificate`.
+pub(crate) fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let mut tbs = Der::new(certificate)
+        .elements(SEQUENCE)?
+        .elements(SEQUENCE)?;
+    // An optional version precedes the serial number, signature algorithm, issuer,
+    // validity and subject
+    let skip = if tbs.read()?.tag == EXPLICIT_0 { 5 } else { 4 };
+    for _ in 0..skip {
+        tbs.read()?;
+    }
+    tbs.read()
+        .filter(|spki| spki.tag == SEQUENCE)
+        .map(|spki| spki.raw)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::native::crypto::tests::rsa_public;
+
+    /// A keystore generated by `keytool -genkey -keyalg RSA -keysize 2048` of JDK 17,
+    /// with the password `android`, like the debug keystore.
+    pub(crate) const KEYSTORE: &[u8] = include_bytes!("../../testdata/native/debug.keystore");
+
+    /// The modulus and public exponent of `public_key`.
+    pub(crate) fn rsa_public_key(public_key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut spki = Der::new(public_key).elements(SEQUENCE).unwrap();
+        spki.read().unwrap();
+        // The BIT STRING has a byte of unused bits before the RSAPublicKey
+        let bits = spki.expect(0x03).unwrap();
+        let mut key = Der::new(&bits[1..]).elements(SEQUENCE).unwrap();
+        (
+            key.expect(INTEGER).unwrap().to_vec(),
+            key.expect(INTEGER).unwrap().to_vec(),
+        )
+    }
+
+    #[test]
+    fn debug_keystore() {
+        let key = read_keystore(KEYSTORE, "android").unwrap();
+        assert!(subject_public_key_info(&key.certificate).is_some());
+
+        // The key belongs to the certificate
+        let (modulus, exponent) = rsa_public_key(&key.public_key);
+        let signature = key.private_key.sign_sha256(b"data");
+        let encoded = rsa_public(&modulus, &exponent, &signature);
+        assert_eq!(&encoded[..2], [0, 1]);
+        assert!(encoded.ends_with(&crate::native::crypto::sha256(b"data")));
+    }
+
+    #[test]
+    fn unreadable_keystores() {
+        assert_eq!(
+            read_keystore(KEYSTORE, "wrong").err(),
+            Some(KeystoreError::WrongPassword)
+        );
+        assert!(matches!(
+            read_keystore(&[0xfe, 0xed, 0xfe, 0xed, 0, 0, 0, 2], "android"),
+            Err(KeystoreError::Unsupported(_))
+        ));
+        assert!(matches!(
+            read_keystore(b"not a keystore", "android"),
+            Err(KeystoreError::Malformed(_))
+        ));
+    }
+}
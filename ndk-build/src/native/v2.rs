@@ -0,0 +1,224 @@
+//! [APK Signature Scheme v2](https://source.android.com/docs/security/features/apksigning/v2),
+//! which Android 7.0 (API 24) and later verify over the whole apk.
+
+use super::crypto::sha256;
+use super::pkcs12::SigningKey;
+use super::zip::{MAX_EOCD_LEN, end_of_central_directory};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// The ID of the v2 signature in the APK Signing Block
+const V2_BLOCK_ID: u32 = 0x7109_871a;
+/// RSASSA-PKCS1-v1_5 with SHA2-256, of the content digests and signature
+const RSA_PKCS1_V1_5_WITH_SHA256: u32 = 0x0103;
+const MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Appends `data` prefixed with its length as a `u32`.
+fn push_prefixed(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+/// The digest of the `sections` of `apk`, its entries, central directory and end of
+/// central directory record, hashed in chunks of 1 MiB that are read one at a time.
+fn content_digest(apk: &mut (impl Read + Seek), sections: &[Range<u64>]) -> io::Result<[u8; 32]> {
+    let chunks = sections
+        .iter()
+        .map(|section| (section.end - section.start).div_ceil(CHUNK_SIZE))
+        .sum::<u64>();
+    let mut digests = vec![0x5a];
+    digests.extend_from_slice(&(chunks as u32).to_le_bytes());
+    // The prefix of each chunk, followed by its data
+    let mut chunk = vec![0; 5 + CHUNK_SIZE as usize];
+    chunk[0] = 0xa5;
+    for section in sections {
+        apk.seek(SeekFrom::Start(section.start))?;
+        let mut remaining = section.end - section.start;
+        while remaining > 0 {
+            let len = remaining.min(CHUNK_SIZE) as usize;
+            chunk[1..5].copy_from_slice(&(len as u32).to_le_bytes());
+            apk.read_exact(&mut chunk[5..5 + len])?;
+            digests.extend_from_slice(&sha256(&chunk[..5 + len]));
+            remaining -= len as u64;
+        }
+    }
+    Ok(sha256(&digests))
+}
+
+/// Inserts the APK Signing Block with a v2 signature by `key` in front of the central
+/// directory of the unsigned `apk`, in place. Returns `false` if it isn't a zip archive.
+///
+/// Only the central directory is moved, the entries are hashed without reading the
+/// whole apk into memory.
+pub(crate) fn sign(apk: &mut (impl Read + Write + Seek), key: &SigningKey) -> io::Result<bool> {
+    let len = apk.seek(SeekFrom::End(0))?;
+    let tail_start = len.saturating_sub(MAX_EOCD_LEN);
+    apk.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![];
+    apk.read_to_end(&mut tail)?;
+    let Some(eocd) = end_of_central_directory(&tail) else {
+        return Ok(false);
+    };
+    let start = u32::from_le_bytes(tail[eocd + 16..eocd + 20].try_into().unwrap()) as u64;
+    let eocd = tail_start + eocd as u64;
+    if start > eocd {
+        return Ok(false);
+    }
+    // The block is inserted where the central directory starts, which the record
+    // already points to as the digest requires
+    let digest = content_digest(apk, &[0..start, start..eocd, eocd..len])?;
+
+    let mut digests = vec![];
+    let mut digest_entry = RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes().to_vec();
+    push_prefixed(&mut digest_entry, &digest);
+    push_prefixed(&mut digests, &digest_entry);
+    let mut certificates = vec![];
+    push_prefixed(&mut certificates, &key.certificate);
+    let mut signed_data = vec![];
+    push_prefixed(&mut signed_data, &digests);
+    push_prefixed(&mut signed_data, &certificates);
+    // No additional attributes
+    push_prefixed(&mut signed_data, &[]);
+
+    let mut signatures = vec![];
+    let mut signature = RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes().to_vec();
+    push_prefixed(&mut signature, &key.private_key.sign_sha256(&signed_data));
+    push_prefixed(&mut signatures, &signature);
+    let mut signer = vec![];
+    push_prefixed(&mut signer, &signed_data);
+    push_prefixed(&mut signer, &signatures);
+    push_prefixed(&mut signer, &key.public_key);
+    let mut signers = vec![];
+    push_prefixed(&mut signers, &signer);
+    let mut v2_block = vec![];
+    push_prefixed(&mut v2_block, &signers);
+
+    let pair_len = 4 + v2_block.len() as u64;
+    let block_len = 8 + pair_len + 8 + MAGIC.len() as u64;
+    let mut block = vec![];
+    block.extend_from_slice(&block_len.to_le_bytes());
+    block.extend_from_slice(&pair_len.to_le_bytes());
+    block.extend_from_slice(&V2_BLOCK_ID.to_le_bytes());
+    block.extend_from_slice(&v2_block);
+    block.extend_from_slice(&block_len.to_le_bytes());
+    block.extend_from_slice(MAGIC);
+
+    // The central directory and the record, which now points behind the block
+    apk.seek(SeekFrom::Start(start))?;
+    let mut central_directory = vec![];
+    apk.read_to_end(&mut central_directory)?;
+    let record = (eocd - start) as usize;
+    central_directory[record + 16..record + 20]
+        .copy_from_slice(&((start + block.len() as u64) as u32).to_le_bytes());
+    apk.seek(SeekFrom::Start(start))?;
+    apk.write_all(&block)?;
+    apk.write_all(&central_directory)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::crypto::tests::rsa_public;
+    use crate::native::pkcs12::read_keystore;
+    use crate::native::pkcs12::tests::{KEYSTORE, rsa_public_key};
+    use crate::native::zip::{ZipWriter, central_directory};
+    use std::io::Cursor;
+
+    /// Reads the value prefixed with its length as a `u32` off the front of `data`.
+    fn take<'a>(data: &mut &'a [u8]) -> &'a [u8] {
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let value = &data[4..4 + len];
+        *data = &data[4 + len..];
+        value
+    }
+
+    #[test]
+    fn signing_block() {
+        let mut zip = ZipWriter::new(vec![]);
+        zip.add("AndroidManifest.xml", b"manifest").unwrap();
+        zip.add("lib/x86_64/libmain.so", &[0; 5000]).unwrap();
+        let unsigned = zip.finish().unwrap();
+        let key = read_keystore(KEYSTORE, "android").unwrap();
+        let mut apk = Cursor::new(unsigned.clone());
+        assert!(sign(&mut apk, &key).unwrap());
+        let signed = apk.into_inner();
+
+        // The central directory moved behind the block, which ends with the magic
+        let (start, eocd) = central_directory(&signed).unwrap();
+        assert!(signed[..start].ends_with(MAGIC));
+        let block_len = u64::from_le_bytes(signed[start - 24..start - 16].try_into().unwrap());
+        let block_start = start - block_len as usize - 8;
+        let (unsigned_start, _) = central_directory(&unsigned).unwrap();
+        assert_eq!(block_start, unsigned_start);
+        assert_eq!(signed[..block_start], unsigned[..block_start]);
+
+        let mut pairs = &signed[block_start + 8..start - 24];
+        let pair_len = u64::from_le_bytes(pairs[..8].try_into().unwrap()) as usize;
+        assert_eq!(pair_len, pairs.len() - 8);
+        assert_eq!(pairs[8..12], V2_BLOCK_ID.to_le_bytes());
+        pairs = &pairs[12..];
+        let mut signers = take(&mut pairs);
+        let mut signer = take(&mut signers);
+        let signed_data = take(&mut signer);
+        let mut signatures = take(&mut signer);
+        assert_eq!(take(&mut signer), key.public_key);
+
+        // The digest covers the apk with the record pointing at the block
+        let mut eocd_record = signed[eocd..].to_vec();
+        eocd_record[16..20].copy_from_slice(&(block_start as u32).to_le_bytes());
+        let unsigned_start = block_start as u64;
+        let unsigned_eocd = unsigned_start + (eocd - start) as u64;
+        let digested = [&signed[..block_start], &signed[start..eocd], &eocd_record].concat();
+        let sections = [
+            0..unsigned_start,
+            unsigned_start..unsigned_eocd,
+            unsigned_eocd..digested.len() as u64,
+        ];
+        let digest = content_digest(&mut Cursor::new(digested), &sections).unwrap();
+        let mut data = signed_data;
+        let mut digests = take(&mut data);
+        let mut digest_entry = take(&mut digests);
+        assert_eq!(digest_entry[..4], RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes());
+        digest_entry = &digest_entry[4..];
+        assert_eq!(take(&mut digest_entry), digest);
+        let mut certificates = take(&mut data);
+        assert_eq!(take(&mut certificates), key.certificate);
+
+        let mut signature = take(&mut signatures);
+        signature = &signature[4..];
+        let (modulus, exponent) = rsa_public_key(&key.public_key);
+        let encoded = rsa_public(&modulus, &exponent, take(&mut signature));
+        assert!(encoded.ends_with(&sha256(signed_data)));
+    }
+
+    #[test]
+    fn chunked_digest() {
+        // Chunks don't span sections, and the last one of a section is shorter
+        let data = (0..CHUNK_SIZE + 10).map(|i| i as u8).collect::<Vec<_>>();
+        let len = data.len() as u64;
+        let sections = [0..CHUNK_SIZE + 2, CHUNK_SIZE + 2..len, len..len];
+        let digest = content_digest(&mut Cursor::new(&data), &sections).unwrap();
+
+        let mut digests = vec![0x5a, 3, 0, 0, 0];
+        for chunk in [
+            &data[..CHUNK_SIZE as usize],
+            &data[CHUNK_SIZE as usize..][..2],
+            &data[CHUNK_SIZE as usize + 2..],
+        ] {
+            let mut prefixed = vec![0xa5];
+            push_prefixed(&mut prefixed, chunk);
+            digests.extend_from_slice(&sha256(&prefixed));
+        }
+        assert_eq!(digest, sha256(&digests));
+    }
+
+    #[test]
+    fn not_a_zip() {
+        let key = read_keystore(KEYSTORE, "android").unwrap();
+        let mut apk = Cursor::new(b"not a zip archive".to_vec());
+        assert!(!sign(&mut apk, &key).unwrap());
+        assert_eq!(apk.into_inner(), b"not a zip archive");
+    }
+}
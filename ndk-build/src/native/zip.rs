@@ -0,0 +1,217 @@
+//! A writer of uncompressed zip archives whose entries are aligned like `zipalign -p`
+//! aligns them, so that Android can map them straight out of the apk.
+//!
+//! Nothing is deflated, so apks with compressible assets or resources come out larger
+//! than `aapt` packages them. Compressing entries would need a deflate implementation,
+//! e.g. from the `zip` crate.
+
+use std::io::{self, Write};
+
+/// Alignment of the data of uncompressed entries, which `zipalign 4` ensures
+const ALIGNMENT: u64 = 4;
+/// Alignment of the data of native libraries, so that they can be loaded without
+/// extracting them on devices with pages of up to 16 KiB
+const LIB_ALIGNMENT: u64 = 16 * 1024;
+/// The extra field that `zipalign` and `apksigner` pad entries with, holding the
+/// alignment followed by zeros
+const ALIGNMENT_EXTRA_ID: u16 = 0xd935;
+/// Entry names are UTF-8
+const FLAG_UTF8: u16 = 0x0800;
+/// 1980-01-01 00:00 in MS-DOS format, for reproducible archives
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+/// The length of the end of central directory record with the longest comment
+pub(crate) const MAX_EOCD_LEN: u64 = 22 + 0xffff;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+struct Entry {
+    name: String,
+    crc32: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes the entries added with [`ZipWriter::add()`] to `out`, and the central
+/// directory on [`ZipWriter::finish()`].
+pub(crate) struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    entries: Vec<Entry>,
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("archives larger than 4 GiB are not supported")
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Self {
+            out,
+            offset: 0,
+            entries: vec![],
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Stores `data` as `name`, aligned to 16 KiB for `.so` files and 4 bytes otherwise.
+    pub(crate) fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let alignment = if name.ends_with(".so") {
+            LIB_ALIGNMENT
+        } else {
+            ALIGNMENT
+        };
+        let header_end = self.offset + 30 + name.len() as u64;
+        let extra = if header_end % alignment == 0 {
+            vec![]
+        } else {
+            let padding = (alignment - (header_end + 6) % alignment) % alignment;
+            let mut extra = vec![];
+            extra.extend_from_slice(&ALIGNMENT_EXTRA_ID.to_le_bytes());
+            extra.extend_from_slice(&(2 + padding as u16).to_le_bytes());
+            extra.extend_from_slice(&(alignment as u16).to_le_bytes());
+            extra.resize(6 + padding as usize, 0);
+            extra
+        };
+        let entry = Entry {
+            name: name.to_string(),
+            crc32: crc32(data),
+            size: u32::try_from(data.len()).map_err(|_| too_large())?,
+            offset: u32::try_from(self.offset).map_err(|_| too_large())?,
+        };
+
+        let mut header = vec![];
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&10u16.to_le_bytes());
+        header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        // Stored
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&DOS_TIME.to_le_bytes());
+        header.extend_from_slice(&DOS_DATE.to_le_bytes());
+        header.extend_from_slice(&entry.crc32.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&extra);
+        self.write(&header)?;
+        self.write(data)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let start = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let mut directory = vec![];
+        for entry in &self.entries {
+            directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            // Made by and needed to extract: version 1.0
+            directory.extend_from_slice(&10u16.to_le_bytes());
+            directory.extend_from_slice(&10u16.to_le_bytes());
+            directory.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes());
+            directory.extend_from_slice(&DOS_TIME.to_le_bytes());
+            directory.extend_from_slice(&DOS_DATE.to_le_bytes());
+            directory.extend_from_slice(&entry.crc32.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk number, internal and external attributes
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let count = u16::try_from(self.entries.len())
+            .map_err(|_| io::Error::other("archives of more than 65535 files are not supported"))?;
+        let mut end = vec![];
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        // Number of this disk and of the disk with the central directory
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&start.to_le_bytes());
+        // Comment length
+        end.extend_from_slice(&[0; 2]);
+        self.write(&directory)?;
+        self.write(&end)?;
+        Ok(self.out)
+    }
+}
+
+/// The offset of the end of central directory record in `tail`, the last up to
+/// [`MAX_EOCD_LEN`] bytes of a zip archive, or [`None`] if it has none.
+pub(crate) fn end_of_central_directory(tail: &[u8]) -> Option<usize> {
+    let min = tail.len().checked_sub(22)?;
+    // The record ends with a comment of up to 64 KiB
+    (min.saturating_sub(0xffff)..=min).rev().find(|&i| {
+        tail[i..i + 4] == 0x0605_4b50u32.to_le_bytes()
+            && i + 22 + u16::from_le_bytes([tail[i + 20], tail[i + 21]]) as usize == tail.len()
+    })
+}
+
+/// The offsets of the central directory and of the end of central directory record of
+/// `zip`, or [`None`] if it isn't a zip archive.
+#[cfg(test)]
+pub(crate) fn central_directory(zip: &[u8]) -> Option<(usize, usize)> {
+    let eocd = end_of_central_directory(zip)?;
+    let start = u32::from_le_bytes(zip[eocd + 16..eocd + 20].try_into().unwrap()) as usize;
+    (start <= eocd).then_some((start, eocd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names and data offsets of the entries in the central directory of `zip`.
+    fn entries(zip: &[u8]) -> Vec<(String, usize)> {
+        let (start, end) = central_directory(zip).unwrap();
+        let mut entries = vec![];
+        let mut i = start;
+        while i < end {
+            let u16_at = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]) as usize;
+            let name_len = u16_at(i + 28);
+            let name = String::from_utf8(zip[i + 46..i + 46 + name_len].to_vec()).unwrap();
+            let local = u32::from_le_bytes(zip[i + 42..i + 46].try_into().unwrap()) as usize;
+            let data = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+            entries.push((name, data));
+            i += 46 + name_len + u16_at(i + 30) + u16_at(i + 32);
+        }
+        entries
+    }
+
+    #[test]
+    fn aligned_entries() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let mut zip = ZipWriter::new(vec![]);
+        zip.add("AndroidManifest.xml", b"manifest").unwrap();
+        zip.add("assets/a.txt", b"odd").unwrap();
+        zip.add("lib/arm64-v8a/libmain.so", &[0x7f; 100]).unwrap();
+        zip.add("assets/b.txt", b"b").unwrap();
+        let zip = zip.finish().unwrap();
+
+        let entries = entries(&zip);
+        assert_eq!(entries.len(), 4);
+        for (name, offset) in entries {
+            let alignment = if name.ends_with(".so") { 16384 } else { 4 };
+            assert_eq!(offset % alignment, 0, "{} is not aligned", name);
+        }
+    }
+}
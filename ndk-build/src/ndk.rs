@@ -1,164 +1,251 @@
+use crate::env::{self, EnvVar};
 use crate::error::NdkError;
+use crate::installed::{certificate_hash, parse_pem_certificate};
+use crate::native::PackagingBackend;
 use crate::target::Target;
-use crate::util::output_error;
-use std::collections::HashMap;
+use crate::util::{
+    Exec, SecretArg, echo_command, format_size, output_error, output_timeout, status,
+};
+use sdkmanager::{Package, find_or_install};
+use semver::Prerelease;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{Once, OnceLock};
+use std::time::Duration;
+use transport::{SshRemote, Transport};
+
+pub use semver::{Version, VersionReq};
+
+pub mod doctor;
+mod host;
+pub mod plan;
+pub mod sdkmanager;
+pub mod transport;
 
 /// The default password used when creating the default `debug.keystore` via
 /// [`Ndk::debug_key`]
 pub const DEFAULT_DEV_KEYSTORE_PASSWORD: &str = "android";
 
+/// How long `adb` commands other than transfers may take unless
+/// [`NdkOptions::adb_timeout`] is set
+pub const DEFAULT_ADB_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Constraints on the SDK components selected by [`Ndk::from_env_with()`].
+#[derive(Clone, Debug, Default)]
+pub struct NdkOptions {
+    /// SDK root, taking precedence over `$ANDROID_HOME` and `$ANDROID_SDK_ROOT`
+    pub sdk_path: Option<PathBuf>,
+    /// NDK root, taking precedence over `$ANDROID_NDK_ROOT` and friends
+    pub ndk_path: Option<PathBuf>,
+    /// Version requirement for NDKs installed side by side under `$ANDROID_HOME/ndk/`,
+    /// e.g. `>=26, <28`. An NDK selected through the environment is always used.
+    pub ndk_version: Option<VersionReq>,
+    /// Exact `build-tools` version to use, e.g. `34.0.0`, instead of the highest installed
+    /// one. Overridden by `$ANDROID_BUILD_TOOLS_VERSION`.
+    pub build_tools_version: Option<String>,
+    /// Install a missing NDK, build-tools or platform through `sdkmanager`
+    pub install_missing: bool,
+    /// Accept all SDK licenses before installing anything, see [`NdkOptions::install_missing`]
+    pub accept_licenses: bool,
+    /// Platform to install when [`NdkOptions::install_missing`] is set, defaults to
+    /// the [default target platform](Ndk::default_target_platform)
+    pub target_sdk_version: Option<u32>,
+    /// Platform whose `android.jar` `aapt` compiles against, like `compileSdk` in Gradle,
+    /// instead of the `targetSdkVersion`. Also the platform to install.
+    pub compile_sdk_version: Option<u32>,
+    /// Hermetic mode for sandboxed builds: never install SDK components, never
+    /// generate the debug keystore, and pass `--offline --locked` to `cargo`
+    pub frozen_env: bool,
+    /// How long `adb` commands may take before they are killed, defaults to
+    /// [`DEFAULT_ADB_TIMEOUT`]. [`Duration::ZERO`] disables the timeout. Transfers like
+    /// `install` and `push` only time out when this is set, as their duration grows
+    /// with the size of the files.
+    pub adb_timeout: Option<Duration>,
+    /// Print the commands and file operations of [`crate::apk`] and [`Ndk::debug_key()`]
+    /// instead of carrying them out, see [`Ndk::exec()`]. Nothing is installed either.
+    pub dry_run: bool,
+    /// Reach the adb server of this host through an SSH tunnel, instead of the local one
+    pub remote: Option<SshRemote>,
+    /// The tools that package and sign apks. [`PackagingBackend::Native`] also works
+    /// without an SDK, given an NDK through [`NdkOptions::ndk_path`] or the environment.
+    pub packaging_backend: PackagingBackend,
+    /// Variables taking the place of those of this process, like the `[env]` table of
+    /// a cargo config. They are used to find the SDK, NDK, build-tools, `.android`
+    /// directory and `keytool`, and are set on every command spawned through [`Ndk`].
+    pub env: BTreeMap<String, String>,
+}
+
+impl NdkOptions {
+    /// Whether [`NdkOptions::install_missing`] is in effect.
+    pub(crate) fn installs_missing(&self) -> bool {
+        self.install_missing && !self.frozen_env && !self.dry_run
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Ndk {
-    sdk_path: PathBuf,
+    /// Only [`PackagingBackend::Native`] can do without an SDK
+    sdk_path: Option<PathBuf>,
     user_home: PathBuf,
     ndk_path: PathBuf,
+    ndk_version: Version,
     build_tools_version: String,
     build_tag: u32,
+    platform_levels: RangeInclusive<u32>,
     platforms: Vec<u32>,
+    compile_sdk_version: Option<u32>,
+    /// The platform requested from [`Ndk::compile_android_jar()`] and the `android.jar`
+    /// it resolved to, so that it's looked up once for all artifacts
+    compile_android_jar: OnceLock<(u32, PathBuf)>,
+    frozen_env: bool,
+    packaging_backend: PackagingBackend,
+    adb_timeout: Option<Duration>,
+    adb_transfer_timeout: Option<Duration>,
+    adb_transport: Transport,
+    remote: Option<SshRemote>,
+    env: BTreeMap<String, String>,
+    exec: Exec,
 }
 
 impl Ndk {
     pub fn from_env() -> Result<Self, NdkError> {
-        let sdk_path = {
-            let sdk_path = std::env::var("ANDROID_SDK_ROOT").ok();
-            if sdk_path.is_some() {
-                eprintln!(
-                    "Warning: Environment variable ANDROID_SDK_ROOT is deprecated \
-                    (https://developer.android.com/studio/command-line/variables#envar). \
-                    It will be used until it is unset and replaced by ANDROID_HOME."
-                );
-            }
+        Self::from_env_with(&NdkOptions::default())
+    }
 
-            PathBuf::from(
-                sdk_path
-                    .or_else(|| std::env::var("ANDROID_HOME").ok())
-                    .ok_or(NdkError::SdkNotFound)?,
-            )
+    /// Like [`Ndk::from_env()`], but picks SDK components according to `options`.
+    pub fn from_env_with(options: &NdkOptions) -> Result<Self, NdkError> {
+        // Strip `\\?\` verbatim prefixes once, so that they don't leak into any of the
+        // derived paths that are passed to tools as arguments or environment variables
+        let native = options.packaging_backend == PackagingBackend::Native;
+        let sdk_path = match sdk_path_from_env(options) {
+            Some(sdk_path) => Some(dunce::simplified(&sdk_path).to_owned()),
+            // The native backend only needs an explicitly selected NDK
+            None if native => None,
+            None => return Err(NdkError::SdkNotFound),
         };
+        let user_home = user_home_from_env(options)
+            .ok_or_else(|| NdkError::PathNotFound(PathBuf::from("$HOME")))?;
 
-        let user_home = {
-            let user_home = std::env::var("ANDROID_SDK_HOME")
-                .map(PathBuf::from)
-                // Unlike ANDROID_USER_HOME, ANDROID_SDK_HOME points to the _parent_ directory of .android:
-                // https://developer.android.com/studio/command-line/variables#envar
-                .map(|home| home.join(".android"))
-                .ok();
-
-            if user_home.is_some() {
-                eprintln!(
-                    "Warning: Environment variable ANDROID_SDK_HOME is deprecated \
-                    (https://developer.android.com/studio/command-line/variables#envar). \
-                    It will be used until it is unset and replaced by ANDROID_USER_HOME."
-                );
-            }
-
-            // Default to $HOME/.android
-            user_home
-                .or_else(|| std::env::var("ANDROID_USER_HOME").map(PathBuf::from).ok())
-                .or_else(|| dirs::home_dir().map(|home| home.join(".android")))
-                .ok_or_else(|| NdkError::PathNotFound(PathBuf::from("$HOME")))?
+        let (ndk_path, ndk_version) = match &sdk_path {
+            Some(sdk_path) => find_or_install(sdk_path, options, Package::Ndk, || {
+                find_ndk(Some(sdk_path), options)
+            })?,
+            None => find_ndk(None, options)?,
         };
+        static PRINT_NDK: Once = Once::new();
+        PRINT_NDK.call_once(|| {
+            status(
+                "Using",
+                format_args!("NDK {} at `{}`", ndk_version, ndk_path.display()),
+            );
+        });
 
-        let ndk_path = {
-            let ndk_path = std::env::var("ANDROID_NDK_ROOT")
-                .ok()
-                .or_else(|| std::env::var("ANDROID_NDK_PATH").ok())
-                .or_else(|| std::env::var("ANDROID_NDK_HOME").ok())
-                .or_else(|| std::env::var("NDK_HOME").ok());
-
-            // default ndk installation path
-            if ndk_path.is_none() && sdk_path.join("ndk-bundle").exists() {
-                sdk_path.join("ndk-bundle")
-            } else {
-                PathBuf::from(ndk_path.ok_or(NdkError::NdkNotFound)?)
+        let build_tools_version = match &sdk_path {
+            Some(sdk_path) if !native => {
+                let build_tools_version =
+                    find_or_install(sdk_path, options, Package::BuildTools, || {
+                        find_build_tools(sdk_path, options)
+                    })?;
+                static PRINT_BUILD_TOOLS: Once = Once::new();
+                PRINT_BUILD_TOOLS.call_once(|| {
+                    status("Using", format_args!("build-tools {}", build_tools_version));
+                });
+                build_tools_version
             }
+            Some(sdk_path) => find_build_tools(sdk_path, options).unwrap_or_default(),
+            None => String::new(),
         };
 
-        let build_tools_dir = sdk_path.join("build-tools");
-        let build_tools_version = std::fs::read_dir(&build_tools_dir)
-            .or(Err(NdkError::PathNotFound(build_tools_dir)))?
-            .filter_map(|path| path.ok())
-            .filter(|path| path.path().is_dir())
-            .filter_map(|path| path.file_name().into_string().ok())
-            .filter(|name| name.chars().next().unwrap().is_ascii_digit())
-            .max()
-            .ok_or(NdkError::BuildToolsNotFound)?;
-
-        let build_tag = std::fs::read_to_string(ndk_path.join("source.properties"))
-            .expect("Failed to read source.properties");
-
-        let build_tag = build_tag
-            .split('\n')
-            .find_map(|line| {
-                let (key, value) = line
-                    .split_once('=')
-                    .expect("Failed to parse `key = value` from source.properties");
-                if key.trim() == "Pkg.Revision" {
-                    // AOSP writes a constantly-incrementing build version to the patch field.
-                    // This number is incrementing across NDK releases.
-                    let mut parts = value.trim().split('.');
-                    let _major = parts.next().unwrap();
-                    let _minor = parts.next().unwrap();
-                    let patch = parts.next().unwrap();
-                    // Can have an optional `XXX-beta1`
-                    let patch = patch.split_once('-').map_or(patch, |(patch, _beta)| patch);
-                    Some(patch.parse().expect("Failed to parse patch field"))
-                } else {
-                    None
-                }
-            })
-            .expect("No `Pkg.Revision` in source.properties");
-
-        let ndk_platforms = std::fs::read_to_string(ndk_path.join("build/core/platforms.mk"))?;
-        let ndk_platforms = ndk_platforms
-            .split('\n')
-            .map(|s| s.split_once(" := ").unwrap())
-            .collect::<HashMap<_, _>>();
+        // AOSP writes a constantly-incrementing build version to the patch field.
+        // This number is incrementing across NDK releases.
+        let build_tag = ndk_version.patch as u32;
 
-        let min_platform_level = ndk_platforms["NDK_MIN_PLATFORM_LEVEL"]
-            .parse::<u32>()
-            .unwrap();
-        let max_platform_level = ndk_platforms["NDK_MAX_PLATFORM_LEVEL"]
-            .parse::<u32>()
-            .unwrap();
-
-        let platforms_dir = sdk_path.join("platforms");
-        let platforms: Vec<u32> = std::fs::read_dir(&platforms_dir)
-            .or(Err(NdkError::PathNotFound(platforms_dir)))?
-            .filter_map(|path| path.ok())
-            .filter(|path| path.path().is_dir())
-            .filter_map(|path| path.file_name().into_string().ok())
-            .filter_map(|name| {
-                name.strip_prefix("android-")
-                    .and_then(|api| api.parse::<u32>().ok())
-            })
-            .filter(|level| (min_platform_level..=max_platform_level).contains(level))
-            .collect();
-
-        if platforms.is_empty() {
-            return Err(NdkError::NoPlatformFound);
-        }
+        let platform_levels = ndk_platform_levels(&ndk_path)?;
+        let requested_platform = options.compile_sdk_version.or(options.target_sdk_version);
+        let target_platform =
+            requested_platform.unwrap_or_else(|| (*platform_levels.end()).min(30));
+        let platforms = match &sdk_path {
+            Some(sdk_path) if !native => find_or_install(
+                sdk_path,
+                options,
+                Package::Platform(target_platform),
+                || {
+                    let installed = installed_platforms(sdk_path)?;
+                    // Platforms above the NDK's range still provide an `android.jar`
+                    if options.installs_missing()
+                        && requested_platform.is_some()
+                        && !installed.contains(&target_platform)
+                    {
+                        return Err(NdkError::PlatformNotFound(target_platform));
+                    }
+                    let platforms: Vec<u32> = installed
+                        .into_iter()
+                        .filter(|level| platform_levels.contains(level))
+                        .collect();
+                    if platforms.is_empty() {
+                        Err(NdkError::NoPlatformFound {
+                            installed: platform_dir_names(sdk_path),
+                        })
+                    } else {
+                        Ok(platforms)
+                    }
+                },
+            )?,
+            // Nothing is compiled against an `android.jar`, so any level of the NDK
+            // can be targeted
+            _ => platform_levels.clone().collect(),
+        };
 
         Ok(Self {
             sdk_path,
             user_home,
             ndk_path,
+            ndk_version,
             build_tools_version,
             build_tag,
+            platform_levels,
             platforms,
+            compile_sdk_version: options.compile_sdk_version,
+            compile_android_jar: OnceLock::new(),
+            frozen_env: options.frozen_env,
+            packaging_backend: options.packaging_backend,
+            exec: if options.dry_run {
+                Exec::DryRun
+            } else {
+                Exec::Run
+            },
+            adb_timeout: adb_timeout(options.adb_timeout, false),
+            adb_transfer_timeout: adb_timeout(options.adb_timeout, true),
+            adb_transport: Transport::new(options.remote.as_ref()),
+            remote: options.remote.clone(),
+            env: options.env.clone(),
         })
     }
 
-    pub fn sdk(&self) -> &Path {
-        &self.sdk_path
+    /// The SDK, which is only missing for [`PackagingBackend::Native`].
+    pub fn sdk(&self) -> Option<&Path> {
+        self.sdk_path.as_deref()
+    }
+
+    /// The SDK that a tool is looked up in, failing with [`NdkError::SdkNotFound`]
+    /// without one.
+    fn sdk_path(&self) -> Result<&Path, NdkError> {
+        self.sdk().ok_or(NdkError::SdkNotFound)
     }
 
     pub fn ndk(&self) -> &Path {
         &self.ndk_path
     }
 
+    /// The `Pkg.Revision` of the NDK.
+    pub fn ndk_version(&self) -> &Version {
+        &self.ndk_version
+    }
+
     pub fn build_tools_version(&self) -> &str {
         &self.build_tools_version
     }
@@ -167,24 +254,59 @@ impl Ndk {
         self.build_tag
     }
 
+    /// The API levels that the NDK supports as `minSdkVersion`.
+    pub fn supported_api_levels(&self) -> RangeInclusive<u32> {
+        self.platform_levels.clone()
+    }
+
     pub fn platforms(&self) -> &[u32] {
         &self.platforms
     }
 
-    pub fn build_tool(&self, tool: &str) -> Result<Command, NdkError> {
+    /// Whether [`NdkOptions::frozen_env`] was set.
+    pub fn frozen_env(&self) -> bool {
+        self.frozen_env
+    }
+
+    /// The tools that package and sign apks, see [`NdkOptions::packaging_backend`].
+    pub fn packaging_backend(&self) -> PackagingBackend {
+        self.packaging_backend
+    }
+
+    /// Whether commands run, or are only printed for [`NdkOptions::dry_run`].
+    pub fn exec(&self) -> Exec {
+        self.exec
+    }
+
+    /// How long `adb` commands may take, see [`NdkOptions::adb_timeout`].
+    pub fn adb_timeout(&self) -> Option<Duration> {
+        self.adb_timeout
+    }
+
+    /// How long `adb` commands transferring files, like `install`, `push` and `pull`, may
+    /// take: only as long as [`NdkOptions::adb_timeout`] if it is set explicitly.
+    pub fn adb_transfer_timeout(&self) -> Option<Duration> {
+        self.adb_transfer_timeout
+    }
+
+    pub fn build_tool_path(&self, tool: &str) -> Result<PathBuf, NdkError> {
         let path = self
-            .sdk_path
+            .sdk_path()?
             .join("build-tools")
             .join(&self.build_tools_version)
             .join(tool);
         if !path.exists() {
             return Err(NdkError::CmdNotFound(tool.to_string()));
         }
-        Ok(Command::new(dunce::canonicalize(path)?))
+        Ok(dunce::canonicalize(path)?)
+    }
+
+    pub fn build_tool(&self, tool: &str) -> Result<Command, NdkError> {
+        Ok(self.command(self.build_tool_path(tool)?))
     }
 
     pub fn platform_tool_path(&self, tool: &str) -> Result<PathBuf, NdkError> {
-        let path = self.sdk_path.join("platform-tools").join(tool);
+        let path = self.sdk_path()?.join("platform-tools").join(tool);
         if !path.exists() {
             return Err(NdkError::CmdNotFound(tool.to_string()));
         }
@@ -196,7 +318,44 @@ impl Ndk {
     }
 
     pub fn platform_tool(&self, tool: &str) -> Result<Command, NdkError> {
-        Ok(Command::new(self.platform_tool_path(tool)?))
+        Ok(self.command(self.platform_tool_path(tool)?))
+    }
+
+    pub fn emulator(&self) -> Result<Command, NdkError> {
+        let path = self.sdk_path()?.join("emulator").join(bin!("emulator"));
+        if !path.exists() {
+            return Err(NdkError::CmdNotFound("emulator".to_string()));
+        }
+        Ok(self.command(dunce::canonicalize(path)?))
+    }
+
+    /// A [`Command`] running `program` with [`NdkOptions::env`] set.
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command.envs(&self.env);
+        command
+    }
+
+    /// The variables of [`NdkOptions::env`].
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// The host whose adb server `adb` is tunneled to, see [`NdkOptions::remote`].
+    pub fn remote(&self) -> Option<&SshRemote> {
+        self.remote.as_ref()
+    }
+
+    /// Fails with [`NdkError::UnsupportedOverRemote`] for a [remote](Ndk::remote) adb
+    /// server, which opens the ports of `adb forward` and `adb reverse` on its own host.
+    pub(crate) fn require_local_adb(&self, feature: &'static str) -> Result<(), NdkError> {
+        match &self.remote {
+            Some(remote) => Err(NdkError::UnsupportedOverRemote {
+                feature,
+                remote: remote.to_string(),
+            }),
+            None => Ok(()),
+        }
     }
 
     pub fn highest_supported_platform(&self) -> u32 {
@@ -213,7 +372,7 @@ impl Ndk {
 
     pub fn platform_dir(&self, platform: u32) -> Result<PathBuf, NdkError> {
         let dir = self
-            .sdk_path
+            .sdk_path()?
             .join("platforms")
             .join(format!("android-{}", platform));
         if !dir.exists() {
@@ -230,8 +389,46 @@ impl Ndk {
         Ok(android_jar)
     }
 
+    /// The `android.jar` that `aapt` compiles against: the one of
+    /// [`NdkOptions::compile_sdk_version`], or else of `target_sdk_version`. When that
+    /// platform isn't installed, the nearest higher one is used with a warning.
+    pub fn compile_android_jar(&self, target_sdk_version: u32) -> Result<PathBuf, NdkError> {
+        let requested = self.compile_sdk_version.unwrap_or(target_sdk_version);
+        if let Some((platform, android_jar)) = self.compile_android_jar.get() {
+            if *platform == requested {
+                return Ok(android_jar.clone());
+            }
+        }
+        let sdk_path = self.sdk_path()?;
+        let installed = installed_platforms(sdk_path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|platform| self.android_jar(*platform).is_ok())
+            .collect::<Vec<_>>();
+        let platform = compile_platform(&installed, requested).ok_or_else(|| {
+            NdkError::CompilePlatformNotFound {
+                platform: requested,
+                installed: platform_dir_names(sdk_path),
+            }
+        })?;
+        if platform != requested {
+            eprintln!(
+                "Warning: platform `android-{}` is not installed, compiling against \
+                `android-{}` instead. Install it with `sdkmanager \"platforms;android-{}\"`.",
+                requested, platform, requested
+            );
+        }
+        let android_jar = self.android_jar(platform)?;
+        // Another thread may have resolved a different platform in the meantime, which
+        // is then looked up again on every call
+        let _ = self
+            .compile_android_jar
+            .set((requested, android_jar.clone()));
+        Ok(android_jar)
+    }
+
     fn host_arch() -> Result<&'static str, NdkError> {
-        let host_os = std::env::var("HOST").ok();
+        let host_os = env::HOST.get().ok();
         let host_contains = |s| host_os.as_ref().map(|h| h.contains(s)).unwrap_or(false);
 
         Ok(if host_contains("linux") {
@@ -259,19 +456,18 @@ impl Ndk {
     }
 
     pub fn toolchain_dir(&self) -> Result<PathBuf, NdkError> {
-        let arch = Self::host_arch()?;
-        let mut toolchain_dir = self
+        let prebuilt = self
             .ndk_path
             .join("toolchains")
             .join("llvm")
-            .join("prebuilt")
-            .join(format!("{}-x86_64", arch));
-        if !toolchain_dir.exists() {
-            toolchain_dir.set_file_name(arch);
-        }
-        if !toolchain_dir.exists() {
-            return Err(NdkError::PathNotFound(toolchain_dir));
-        }
+            .join("prebuilt");
+        let toolchain_dir = self.host_prebuilt(&prebuilt)?;
+        host::check_runnable(
+            &self.ndk_version,
+            &toolchain_dir
+                .join("bin")
+                .join(format!("clang{}", std::env::consts::EXE_SUFFIX)),
+        )?;
         Ok(toolchain_dir)
     }
 
@@ -331,15 +527,19 @@ impl Ndk {
     }
 
     pub fn prebuilt_dir(&self) -> Result<PathBuf, NdkError> {
-        let arch = Self::host_arch()?;
-        let prebuilt_dir = self
-            .ndk_path
-            .join("prebuilt")
-            .join(format!("{}-x86_64", arch));
-        if !prebuilt_dir.exists() {
-            Err(NdkError::PathNotFound(prebuilt_dir))
-        } else {
-            Ok(prebuilt_dir)
+        self.host_prebuilt(&self.ndk_path.join("prebuilt"))
+    }
+
+    /// Picks the directory for this host in `prebuilt`, see [`host::host_tags()`].
+    fn host_prebuilt(&self, prebuilt: &Path) -> Result<PathBuf, NdkError> {
+        let dirs = host::host_tags(Self::host_arch()?, std::env::consts::ARCH)
+            .into_iter()
+            .map(|tag| prebuilt.join(tag))
+            .collect::<Vec<_>>();
+        match dirs.iter().find(|dir| dir.exists()) {
+            Some(dir) => Ok(dir.clone()),
+            // Report the most generic candidate
+            None => Err(NdkError::PathNotFound(dirs.last().unwrap().clone())),
         }
     }
 
@@ -349,6 +549,8 @@ impl Ndk {
         launch_activity: &str,
         device_serial: Option<&str>,
     ) -> Result<(), NdkError> {
+        // `ndk-gdb` connects to the debugger through `adb forward`
+        self.require_local_adb("`ndk-gdb`")?;
         let abi = self.detect_abi(device_serial)?;
         let jni_dir = launch_dir.as_ref().join("jni");
         std::fs::create_dir_all(&jni_dir)?;
@@ -356,22 +558,68 @@ impl Ndk {
             jni_dir.join("Android.mk"),
             format!("APP_ABI={}\nTARGET_OUT=\n", abi.android_abi()),
         )?;
-        let mut ndk_gdb = Command::new(self.prebuilt_dir()?.join("bin").join(cmd!("ndk-gdb")));
+        let mut ndk_gdb = self.command(self.prebuilt_dir()?.join("bin").join(cmd!("ndk-gdb")));
 
         if let Some(device_serial) = &device_serial {
             ndk_gdb.arg("-s").arg(device_serial);
         }
 
+        // `ndk-gdb` runs the `adb` it is passed
+        self.adb_transport.configure(&mut ndk_gdb)?;
         ndk_gdb
             .arg("--adb")
             .arg(self.adb_path()?)
             .arg("--launch")
             .arg(launch_activity)
-            .current_dir(launch_dir)
-            .status()?;
+            .current_dir(launch_dir);
+        echo_command(&ndk_gdb);
+        ndk_gdb.status()?;
         Ok(())
     }
 
+    /// The `simpleperf` directory of the NDK, containing its binaries and scripts.
+    pub fn simpleperf_dir(&self) -> Result<PathBuf, NdkError> {
+        let simpleperf_dir = self.ndk_path.join("simpleperf");
+        if !simpleperf_dir.exists() {
+            return Err(NdkError::PathNotFound(simpleperf_dir));
+        }
+        Ok(simpleperf_dir)
+    }
+
+    /// The prebuilt `simpleperf` binary to push to and run on a device of the given ABI.
+    pub fn simpleperf_device_bin(&self, target: Target) -> Result<PathBuf, NdkError> {
+        let arch = match target {
+            Target::ArmV7a => "arm",
+            Target::Arm64V8a => "arm64",
+            Target::X86 => "x86",
+            Target::X86_64 => "x86_64",
+        };
+        let simpleperf = self
+            .simpleperf_dir()?
+            .join("bin")
+            .join("android")
+            .join(arch)
+            .join("simpleperf");
+        if !simpleperf.exists() {
+            return Err(NdkError::PathNotFound(simpleperf));
+        }
+        Ok(simpleperf)
+    }
+
+    /// The host `simpleperf` binary, used to `report` recordings pulled from a device.
+    pub fn simpleperf(&self) -> Result<Command, NdkError> {
+        let simpleperf = self
+            .simpleperf_dir()?
+            .join("bin")
+            .join(Self::host_arch()?)
+            .join("x86_64")
+            .join(bin!("simpleperf"));
+        if !simpleperf.exists() {
+            return Err(NdkError::PathNotFound(simpleperf));
+        }
+        Ok(self.command(simpleperf))
+    }
+
     pub fn android_user_home(&self) -> Result<PathBuf, NdkError> {
         let android_user_home = self.user_home.clone();
         std::fs::create_dir_all(&android_user_home)?;
@@ -379,19 +627,18 @@ impl Ndk {
     }
 
     pub fn keytool(&self) -> Result<Command, NdkError> {
-        if let Ok(keytool) = which::which(bin!("keytool")) {
-            return Ok(Command::new(keytool));
-        }
-        if let Ok(java) = std::env::var("JAVA_HOME") {
-            let keytool = PathBuf::from(java).join("bin").join(bin!("keytool"));
-            if keytool.exists() {
-                return Ok(Command::new(keytool));
-            }
-        }
-        Err(NdkError::CmdNotFound("keytool".to_string()))
+        find_keytool(&overlay_env(&self.env))
+            .map(|keytool| self.command(keytool))
+            .ok_or_else(|| NdkError::CmdNotFound("keytool".to_string()))
     }
 
+    /// Returns the `debug.keystore` in [`Ndk::android_user_home()`], generating it if it
+    /// doesn't exist. Fails with [`NdkError::DebugKeyInFrozenEnv`] in
+    /// [frozen](NdkOptions::frozen_env) mode.
     pub fn debug_key(&self) -> Result<Key, NdkError> {
+        if self.frozen_env {
+            return Err(NdkError::DebugKeyInFrozenEnv);
+        }
         let path = self.android_user_home()?.join("debug.keystore");
         let password = DEFAULT_DEV_KEYSTORE_PASSWORD.to_owned();
 
@@ -403,11 +650,11 @@ impl Ndk {
                 .arg("-keystore")
                 .arg(&path)
                 .arg("-storepass")
-                .arg(&password)
+                .arg(SecretArg::new(&password))
                 .arg("-alias")
                 .arg("androiddebugkey")
                 .arg("-keypass")
-                .arg(&password)
+                .arg(SecretArg::new(&password))
                 .arg("-dname")
                 .arg("CN=Android Debug,O=Android,C=US")
                 .arg("-keyalg")
@@ -416,11 +663,28 @@ impl Ndk {
                 .arg("2048")
                 .arg("-validity")
                 .arg("10000");
-            output_error(keytool)?;
+            self.exec.output_error(keytool, None)?;
         }
         Ok(Key { path, password })
     }
 
+    /// The [`certificate_hash()`] of the certificate of `key`, to compare with the
+    /// signatures of an installed app.
+    pub fn certificate_hash(&self, key: &Key) -> Result<String, NdkError> {
+        let mut keytool = self.keytool()?;
+        keytool
+            .arg("-list")
+            .arg("-rfc")
+            .arg("-keystore")
+            .arg(&key.path)
+            .arg("-storepass")
+            .arg(SecretArg::new(&key.password));
+        let output = output_error(keytool, None)?;
+        parse_pem_certificate(&String::from_utf8_lossy(&output))
+            .map(|der| certificate_hash(&der))
+            .ok_or_else(|| NdkError::NoCertificate(key.path.clone()))
+    }
+
     pub fn sysroot_lib_dir(&self, target: Target) -> Result<PathBuf, NdkError> {
         let sysroot_lib_dir = self
             .toolchain_dir()?
@@ -464,21 +728,166 @@ impl Ndk {
         Err(NdkError::PlatformNotFound(min_sdk_version))
     }
 
+    /// Returns the primary ABI of the device, see [`Ndk::detect_abis()`].
     pub fn detect_abi(&self, device_serial: Option<&str>) -> Result<Target, NdkError> {
+        self.detect_abis(device_serial)?
+            .into_iter()
+            .next()
+            .ok_or(NdkError::UnsupportedTarget)
+    }
+
+    /// Like [`Ndk::detect_abi()`], but falls back to [`Target::Arm64V8a`] with a note when
+    /// `adb` isn't installed or no device answers, for builds that don't need one.
+    pub fn detect_abi_or_default(&self, device_serial: Option<&str>) -> Target {
+        self.detect_abi(device_serial).unwrap_or_else(|e| {
+            let reason = match e {
+                NdkError::CmdNotFound(_) => "`adb` is not installed".to_string(),
+                NdkError::UnsupportedTarget => {
+                    "no connected device reported a supported ABI".to_string()
+                }
+                e => e.to_string(),
+            };
+            status(
+                "Defaulting",
+                format_args!("to {}, {}", Target::Arm64V8a.android_abi(), reason),
+            );
+            Target::Arm64V8a
+        })
+    }
+
+    /// Returns all ABIs the device can run, in order of preference, through
+    /// `ro.product.cpu.abilist`. ABIs that the NDK cannot target are skipped with
+    /// a warning.
+    pub fn detect_abis(&self, device_serial: Option<&str>) -> Result<Vec<Target>, NdkError> {
+        Ok(parse_abis(&self.abilist(device_serial)?))
+    }
+
+    fn abilist(&self, device_serial: Option<&str>) -> Result<String, NdkError> {
+        let abilist = self.shell(device_serial, &["getprop", "ro.product.cpu.abilist"])?;
+        // `abilist` was introduced in Android 5.0
+        if abilist.is_empty() {
+            return self.shell(device_serial, &["getprop", "ro.product.cpu.abi"]);
+        }
+        Ok(abilist)
+    }
+
+    /// All ABIs listed by `ro.product.cpu.abilist`, including those that the NDK cannot
+    /// target, unlike [`Ndk::detect_abis()`].
+    pub fn device_abis(&self, device_serial: Option<&str>) -> Result<Vec<String>, NdkError> {
+        Ok(self
+            .abilist(device_serial)?
+            .split(',')
+            .map(str::trim)
+            .filter(|abi| !abi.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Free space in bytes of the file system holding `path` on the device, through
+    /// `df`, or [`None`] if the output is not recognized.
+    pub fn free_space(
+        &self,
+        device_serial: Option<&str>,
+        path: &str,
+    ) -> Result<Option<u64>, NdkError> {
+        Ok(parse_df(&self.shell(device_serial, &["df", "-k", path])?))
+    }
+
+    /// The API level of the device through `ro.build.version.sdk`, if it reports one.
+    pub fn api_level(&self, device_serial: Option<&str>) -> Result<Option<u32>, NdkError> {
+        let sdk = self.shell(device_serial, &["getprop", "ro.build.version.sdk"])?;
+        Ok(sdk.parse().ok())
+    }
+
+    /// The screen density in dpi through `wm density`, preferring an override.
+    pub fn density(&self, device_serial: Option<&str>) -> Result<Option<u32>, NdkError> {
+        let wm = self.shell(device_serial, &["wm", "density"])?;
+        Ok(parse_wm(&wm, "density").and_then(|density| density.parse().ok()))
+    }
+
+    /// The locale of the device like `de-DE` through `persist.sys.locale`, falling back
+    /// to the `ro.product.locale` it shipped with.
+    pub fn locale(&self, device_serial: Option<&str>) -> Result<Option<String>, NdkError> {
+        for prop in ["persist.sys.locale", "ro.product.locale"] {
+            let locale = self.shell(device_serial, &["getprop", prop])?;
+            if !locale.is_empty() {
+                return Ok(Some(locale));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The trimmed stdout of `adb shell <args>`, which is empty if the command fails.
+    pub(crate) fn shell(
+        &self,
+        device_serial: Option<&str>,
+        args: &[&str],
+    ) -> Result<String, NdkError> {
         let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").args(args);
+        let stdout = output_timeout(&mut adb, self.adb_timeout())?.stdout;
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+    }
 
-        let stdout = adb
-            .arg("shell")
-            .arg("getprop")
-            .arg("ro.product.cpu.abi")
-            .output()?
-            .stdout;
-        let abi = std::str::from_utf8(&stdout).or(Err(NdkError::UnsupportedTarget))?;
-        Target::from_android_abi(abi.trim())
+    /// Queries the model, Android version, ABIs, storage and screen of `device`, and whether
+    /// `package` is installed on it. Only the state is reported for devices that are
+    /// offline or unauthorized.
+    pub fn device_info(
+        &self,
+        device: &AdbDevice,
+        package: Option<&str>,
+    ) -> Result<DeviceInfo, NdkError> {
+        let mut info = DeviceInfo {
+            serial: device.serial.clone(),
+            state: device.state.clone(),
+            ..Default::default()
+        };
+        if device.state != "device" {
+            return Ok(info);
+        }
+        let serial = Some(device.serial.as_str());
+        let getprop = |prop| {
+            self.shell(serial, &["getprop", prop])
+                .map(|value| Some(value).filter(|value| !value.is_empty()))
+        };
+        info.model = getprop("ro.product.model")?;
+        info.release = getprop("ro.build.version.release")?;
+        info.sdk = self.api_level(serial)?;
+        info.abis = self.device_abis(serial)?;
+        info.free_data = self.free_space(serial, "/data")?;
+        info.resolution = parse_wm(&self.shell(serial, &["wm", "size"])?, "size");
+        info.density = self.density(serial)?;
+        if let Some(package) = package {
+            let dumpsys = self.shell(serial, &["dumpsys", "package", package])?;
+            let version_code = parse_version_code(&dumpsys);
+            info.package = Some(InstalledPackage {
+                name: package.to_string(),
+                installed: version_code.is_some(),
+                version_code,
+            });
+        }
+        Ok(info)
+    }
+
+    /// Lists all devices known to `adb`, including offline and unauthorized ones.
+    pub fn devices(&self) -> Result<Vec<AdbDevice>, NdkError> {
+        let mut adb = self.adb(None)?;
+        adb.arg("devices");
+        let output = output_error(adb, self.adb_timeout())?;
+        Ok(parse_devices(&String::from_utf8_lossy(&output)))
+    }
+
+    /// Lists the (work profile, guest, ...) users on the device through `pm list users`.
+    pub fn users(&self, device_serial: Option<&str>) -> Result<Vec<User>, NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("pm").arg("list").arg("users");
+        let output = output_error(adb, self.adb_timeout())?;
+        parse_users(&String::from_utf8_lossy(&output))
     }
 
     pub fn adb(&self, device_serial: Option<&str>) -> Result<Command, NdkError> {
-        let mut adb = Command::new(self.adb_path()?);
+        let mut adb = self.command(self.adb_path()?);
+        self.adb_transport.configure(&mut adb)?;
 
         if let Some(device_serial) = device_serial {
             adb.arg("-s").arg(device_serial);
@@ -488,14 +897,1120 @@ impl Ndk {
     }
 }
 
+fn parse_abis(abilist: &str) -> Vec<Target> {
+    abilist
+        .split(',')
+        .map(str::trim)
+        .filter(|abi| !abi.is_empty())
+        .filter_map(|abi| match Target::from_android_abi(abi) {
+            Ok(target) => Some(target),
+            Err(_) => {
+                eprintln!("Skipping device ABI `{}`, which is not supported", abi);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Where an SDK or NDK path was found, printed at info level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PathSource {
+    /// [`NdkOptions::sdk_path`] or [`NdkOptions::ndk_path`]
+    Options,
+    Env(&'static str),
+    /// `$ANDROID_HOME/ndk/<version>/`
+    SideBySide,
+    /// `$ANDROID_HOME/ndk-bundle/`
+    NdkBundle,
+    /// Where Android Studio installs the SDK by default
+    DefaultLocation,
+}
+
+impl std::fmt::Display for PathSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Options => write!(f, "`sdk_path`/`ndk_path` metadata"),
+            Self::Env(var) => write!(f, "${}", var),
+            Self::SideBySide => write!(f, "side-by-side NDK installation"),
+            Self::NdkBundle => write!(f, "legacy `ndk-bundle` installation"),
+            Self::DefaultLocation => write!(f, "default SDK location"),
+        }
+    }
+}
+
+/// Looks up a non-empty environment variable. Abstracted so that tests can pass
+/// a fake environment.
+type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Looks `key` up in `overrides`, see [`NdkOptions::env`], then in the environment of
+/// this process. Empty values count as unset.
+fn overlay_env(overrides: &BTreeMap<String, String>) -> impl Fn(&str) -> Option<String> + '_ {
+    move |key| env::var_with(overrides, key).filter(|value| !value.is_empty())
+}
+
+/// Resolves the SDK root, see [`resolve_sdk_path()`].
+pub(crate) fn sdk_path_from_env(options: &NdkOptions) -> Option<PathBuf> {
+    let (sdk_path, source) =
+        resolve_sdk_path(options, &overlay_env(&options.env), dirs::home_dir())?;
+    log::info!(
+        "Using Android SDK at `{}` from {}",
+        sdk_path.display(),
+        source
+    );
+    Some(sdk_path)
+}
+
+/// The timeout of `adb` commands for [`NdkOptions::adb_timeout`], where `transfer` commands
+/// only time out if it is set.
+fn adb_timeout(option: Option<Duration>, transfer: bool) -> Option<Duration> {
+    match option {
+        None if transfer => None,
+        None => Some(DEFAULT_ADB_TIMEOUT),
+        Some(timeout) if timeout.is_zero() => None,
+        Some(timeout) => Some(timeout),
+    }
+}
+
+/// Resolves the SDK root from, in order of precedence:
+/// 1. [`NdkOptions::sdk_path`];
+/// 2. the deprecated `$ANDROID_SDK_ROOT`, then `$ANDROID_HOME`;
+/// 3. the default Android Studio location, if it exists: `~/Library/Android/sdk` on
+///    macOS, `%LOCALAPPDATA%\Android\Sdk` on Windows and `~/Android/Sdk` elsewhere.
+fn resolve_sdk_path(
+    options: &NdkOptions,
+    env: Env<'_>,
+    home: Option<PathBuf>,
+) -> Option<(PathBuf, PathSource)> {
+    if let Some(sdk_path) = &options.sdk_path {
+        return Some((sdk_path.clone(), PathSource::Options));
+    }
+    if let Some(sdk_path) = env(env::ANDROID_SDK_ROOT.name) {
+        eprintln!(
+            "Warning: Environment variable ANDROID_SDK_ROOT is deprecated \
+            (https://developer.android.com/studio/command-line/variables#envar). \
+            It will be used until it is unset and replaced by ANDROID_HOME."
+        );
+        return Some((sdk_path.into(), PathSource::Env(env::ANDROID_SDK_ROOT.name)));
+    }
+    if let Some(sdk_path) = env(env::ANDROID_HOME.name) {
+        return Some((sdk_path.into(), PathSource::Env(env::ANDROID_HOME.name)));
+    }
+    default_sdk_path(env, home)
+        .filter(|sdk_path| sdk_path.is_dir())
+        .map(|sdk_path| (sdk_path, PathSource::DefaultLocation))
+}
+
+fn default_sdk_path(env: Env<'_>, home: Option<PathBuf>) -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        env(env::LOCALAPPDATA.name).map(|local| PathBuf::from(local).join("Android").join("Sdk"))
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library").join("Android").join("sdk"))
+    } else {
+        home.map(|home| home.join("Android").join("Sdk"))
+    }
+}
+
+/// Resolves the `.android` directory holding the debug keystore and AVDs.
+pub(crate) fn user_home_from_env(options: &NdkOptions) -> Option<PathBuf> {
+    resolve_user_home(&overlay_env(&options.env), dirs::home_dir())
+}
+
+/// Resolves the `.android` directory from the deprecated `$ANDROID_SDK_HOME`, then
+/// `$ANDROID_USER_HOME`, defaulting to `~/.android`.
+fn resolve_user_home(env: Env<'_>, home: Option<PathBuf>) -> Option<PathBuf> {
+    // Unlike ANDROID_USER_HOME, ANDROID_SDK_HOME points to the _parent_ directory of .android:
+    // https://developer.android.com/studio/command-line/variables#envar
+    if let Some(sdk_home) = env(env::ANDROID_SDK_HOME.name) {
+        eprintln!(
+            "Warning: Environment variable ANDROID_SDK_HOME is deprecated \
+            (https://developer.android.com/studio/command-line/variables#envar). \
+            It will be used until it is unset and replaced by ANDROID_USER_HOME."
+        );
+        return Some(PathBuf::from(sdk_home).join(".android"));
+    }
+
+    env(env::ANDROID_USER_HOME.name)
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| home.join(".android")))
+}
+
+/// Environment variables selecting an NDK, in order of precedence.
+const NDK_ENV_VARS: [EnvVar; 4] = [
+    env::ANDROID_NDK_ROOT,
+    env::ANDROID_NDK_PATH,
+    env::ANDROID_NDK_HOME,
+    env::NDK_HOME,
+];
+
+/// Finds the NDK, see [`resolve_ndk()`].
+pub(crate) fn find_ndk(
+    sdk_path: Option<&Path>,
+    options: &NdkOptions,
+) -> Result<(PathBuf, Version), NdkError> {
+    let (ndk_path, ndk_version, source) =
+        resolve_ndk(sdk_path, options, &overlay_env(&options.env))?;
+    let ndk_path = dunce::simplified(&ndk_path).to_owned();
+    log::info!("Using NDK at `{}` from {}", ndk_path.display(), source);
+    Ok((ndk_path, ndk_version))
+}
+
+/// Finds the NDK from, in order of precedence:
+/// 1. [`NdkOptions::ndk_path`];
+/// 2. `$ANDROID_NDK_ROOT`, `$ANDROID_NDK_PATH`, `$ANDROID_NDK_HOME` or `$NDK_HOME`;
+/// 3. the highest side-by-side NDK in the SDK matching [`NdkOptions::ndk_version`];
+/// 4. the legacy `ndk-bundle` in the SDK.
+///
+/// An explicitly selected NDK is used even if it doesn't match `ndk_version`.
+fn resolve_ndk(
+    sdk_path: Option<&Path>,
+    options: &NdkOptions,
+    env: Env<'_>,
+) -> Result<(PathBuf, Version, PathSource), NdkError> {
+    let explicit = options
+        .ndk_path
+        .clone()
+        .map(|ndk_path| (ndk_path, PathSource::Options))
+        .or_else(|| {
+            NDK_ENV_VARS
+                .iter()
+                .find_map(|var| Some((PathBuf::from(env(var.name)?), PathSource::Env(var.name))))
+        });
+    if let Some((ndk_path, source)) = explicit {
+        let ndk_version = ndk_revision(&ndk_path)?;
+        if let Some(requirement) = &options.ndk_version {
+            if !requirement.matches(&ndk_version) {
+                eprintln!(
+                    "Warning: NDK {} at `{}` does not match `ndk_version = \"{}\"`, \
+                    but is used because it was explicitly selected through {}",
+                    ndk_version,
+                    ndk_path.display(),
+                    requirement,
+                    source
+                );
+            }
+        }
+        return Ok((ndk_path, ndk_version, source));
+    }
+    // Without an SDK there is nowhere else to look
+    let sdk_path = sdk_path.ok_or(NdkError::NdkNotFound)?;
+    if let Some((ndk_path, ndk_version)) =
+        find_side_by_side_ndk(&sdk_path.join("ndk"), options.ndk_version.as_ref())?
+    {
+        Ok((ndk_path, ndk_version, PathSource::SideBySide))
+    } else if sdk_path.join("ndk-bundle").exists() {
+        // default ndk installation path before side-by-side NDKs
+        let ndk_path = sdk_path.join("ndk-bundle");
+        let ndk_version = ndk_revision(&ndk_path)?;
+        Ok((ndk_path, ndk_version, PathSource::NdkBundle))
+    } else {
+        Err(NdkError::NdkNotFound)
+    }
+}
+
+/// The `build-tools` version requested through `$ANDROID_BUILD_TOOLS_VERSION` or
+/// [`NdkOptions::build_tools_version`], in that order.
+pub(crate) fn requested_build_tools_version(options: &NdkOptions) -> Option<String> {
+    overlay_env(&options.env)(env::ANDROID_BUILD_TOOLS_VERSION.name)
+        .or_else(|| options.build_tools_version.clone())
+}
+
+/// Picks the [requested](requested_build_tools_version) version in `build-tools/`,
+/// or the highest installed one. Every build-tools binary is taken from this
+/// single directory by [`Ndk::build_tool()`].
+pub(crate) fn find_build_tools(sdk_path: &Path, options: &NdkOptions) -> Result<String, NdkError> {
+    let build_tools_dir = sdk_path.join("build-tools");
+    let installed = std::fs::read_dir(&build_tools_dir)
+        .or(Err(NdkError::PathNotFound(build_tools_dir)))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.path().is_dir())
+        .filter_map(|path| path.file_name().into_string().ok())
+        .filter(|name| name.chars().next().unwrap().is_ascii_digit())
+        .collect();
+    select_build_tools(installed, requested_build_tools_version(options))
+}
+
+fn select_build_tools(
+    mut installed: Vec<String>,
+    requested: Option<String>,
+) -> Result<String, NdkError> {
+    // Compare numerically, so that `9.0.0` doesn't win over `34.0.0`, and prefer
+    // stable versions over release candidates
+    installed.sort_by_key(|version| {
+        parse_revision(version).map(|version| (version.pre.is_empty(), version))
+    });
+    match requested {
+        Some(version) if installed.contains(&version) => Ok(version),
+        Some(version) => Err(NdkError::BuildToolsVersionNotFound { version, installed }),
+        None => installed.pop().ok_or(NdkError::BuildToolsNotFound),
+    }
+}
+
+/// The range of API levels the NDK can target, from `meta/platforms.json`, or
+/// `build/core/platforms.mk` for NDKs older than r21.
+pub(crate) fn ndk_platform_levels(ndk_path: &Path) -> Result<RangeInclusive<u32>, NdkError> {
+    let platforms_json = ndk_path.join("meta").join("platforms.json");
+    if platforms_json.exists() {
+        return parse_platforms_json(&platforms_json);
+    }
+
+    let ndk_platforms = std::fs::read_to_string(ndk_path.join("build/core/platforms.mk"))?;
+    let ndk_platforms = ndk_platforms
+        .split('\n')
+        .map(|s| s.split_once(" := ").unwrap())
+        .collect::<HashMap<_, _>>();
+
+    let min_platform_level = ndk_platforms["NDK_MIN_PLATFORM_LEVEL"]
+        .parse::<u32>()
+        .unwrap();
+    let max_platform_level = ndk_platforms["NDK_MAX_PLATFORM_LEVEL"]
+        .parse::<u32>()
+        .unwrap();
+    Ok(min_platform_level..=max_platform_level)
+}
+
+fn parse_platforms_json(path: &Path) -> Result<RangeInclusive<u32>, NdkError> {
+    #[derive(serde::Deserialize)]
+    struct Platforms {
+        min: u32,
+        max: u32,
+    }
+
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+    let platforms: Platforms =
+        serde_json::from_str(&contents).map_err(|e| NdkError::PlatformsJson(path.to_owned(), e))?;
+    Ok(platforms.min..=platforms.max)
+}
+
+/// API levels of all `platforms/android-<level>` directories in the SDK.
+pub(crate) fn installed_platforms(sdk_path: &Path) -> Result<Vec<u32>, NdkError> {
+    let platforms_dir = sdk_path.join("platforms");
+    Ok(std::fs::read_dir(&platforms_dir)
+        .or(Err(NdkError::PathNotFound(platforms_dir)))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.path().is_dir())
+        .filter_map(|path| path.file_name().into_string().ok())
+        .filter_map(|name| {
+            name.strip_prefix("android-")
+                .and_then(|api| api.parse::<u32>().ok())
+        })
+        .collect())
+}
+
+/// The names of everything in the `platforms` directory of the SDK, sorted.
+pub(crate) fn platform_dir_names(sdk_path: &Path) -> Vec<String> {
+    let mut names = std::fs::read_dir(sdk_path.join("platforms"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// `requested` when it is `installed`, or else the nearest higher installed platform,
+/// as resources compiled against a newer `android.jar` still work on older versions.
+pub(crate) fn compile_platform(installed: &[u32], requested: u32) -> Option<u32> {
+    installed
+        .iter()
+        .copied()
+        .filter(|platform| *platform >= requested)
+        .min()
+}
+
+/// Looks for `keytool` on `$PATH` and in `$JAVA_HOME`.
+pub(crate) fn find_keytool(env: Env<'_>) -> Option<PathBuf> {
+    if let Ok(keytool) = which::which(bin!("keytool")) {
+        return Some(keytool);
+    }
+    if let Some(java) = env(env::JAVA_HOME.name) {
+        let keytool = PathBuf::from(java).join("bin").join(bin!("keytool"));
+        if keytool.exists() {
+            return Some(keytool);
+        }
+    }
+    None
+}
+
+/// Reads `Pkg.Revision` from the `source.properties` file of an NDK.
+fn ndk_revision(ndk_path: &Path) -> Result<Version, NdkError> {
+    let path = ndk_path.join("source.properties");
+    let properties =
+        std::fs::read_to_string(&path).map_err(|e| NdkError::IoPathError(path.clone(), e))?;
+    properties
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "Pkg.Revision")
+        .and_then(|(_, value)| parse_revision(value.trim()))
+        .ok_or(NdkError::NdkRevisionNotFound(path))
+}
+
+/// NDK revisions look like `25.2.9519653`, or `26.0.10404224-beta1` for pre-releases.
+/// Tolerate revisions without a patch field.
+fn parse_revision(revision: &str) -> Option<Version> {
+    Version::parse(revision).ok().or_else(|| {
+        let mut parts = revision.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+        Some(Version::new(major, minor, 0))
+    })
+}
+
+/// Picks the highest NDK in the `sdkmanager` side-by-side layout (`ndk/<version>/`)
+/// that satisfies `requirement`. Returns [`None`] if `ndk_dir` contains no NDKs.
+///
+/// Pre-releases match like their release would, since [`VersionReq::matches()`] skips
+/// them unless the requirement names one: `26` selects `26.0.10404224-beta1`.
+fn find_side_by_side_ndk(
+    ndk_dir: &Path,
+    requirement: Option<&VersionReq>,
+) -> Result<Option<(PathBuf, Version)>, NdkError> {
+    let Ok(entries) = std::fs::read_dir(ndk_dir) else {
+        return Ok(None);
+    };
+    let mut found = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|path| ndk_revision(&path).ok().map(|version| (path, version)))
+        .collect::<Vec<_>>();
+    if found.is_empty() {
+        return Ok(None);
+    }
+    found.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    match requirement {
+        None => Ok(found.pop()),
+        Some(requirement) => match found.iter().rposition(|(_, v)| {
+            requirement.matches(v)
+                || requirement.matches(&Version {
+                    pre: Prerelease::EMPTY,
+                    ..v.clone()
+                })
+        }) {
+            Some(i) => Ok(Some(found.swap_remove(i))),
+            None => Err(NdkError::NdkVersionNotFound {
+                requirement: requirement.to_string(),
+                ndk_dir: ndk_dir.to_owned(),
+                found: found.iter().map(|(_, v)| v.to_string()).collect(),
+            }),
+        },
+    }
+}
+
 pub struct Key {
     pub path: PathBuf,
     pub password: String,
 }
 
+/// A signing lineage from `apksigner rotate`, which lets devices that know only the
+/// older keys install an apk signed with the rotated key (APK signature scheme v3.1).
+pub struct KeyRotation {
+    /// The `--lineage` file recording that the signing key was rotated from `older_keys`
+    pub lineage: PathBuf,
+    /// The lowest API level that verifies the apk with the rotated key, `apksigner`
+    /// defaults to 33
+    pub rotation_min_sdk_version: Option<u32>,
+    /// The keys of the lineage before the signing key, from the oldest to the newest
+    pub older_keys: Vec<OlderKey>,
+}
+
+/// A key of a [`KeyRotation`] that the signing key was rotated from.
+pub struct OlderKey {
+    pub key: Key,
+    /// `--ks-key-alias`, required if the keystore holds more than one key
+    pub alias: Option<String>,
+}
+
+/// A device as listed by `adb devices`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdbDevice {
+    pub serial: String,
+    /// `device` when connected and authorized, otherwise e.g. `offline` or `unauthorized`
+    pub state: String,
+}
+
+fn parse_devices(output: &str) -> Vec<AdbDevice> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("List of devices attached"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            Some(AdbDevice {
+                serial: columns.next()?.to_string(),
+                state: columns.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// What [`Ndk::device_info()`] found out about a device. Everything but the serial and
+/// state is left empty for devices that are not in the `device` state.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    pub serial: String,
+    pub state: String,
+    /// `ro.product.model`
+    pub model: Option<String>,
+    /// `ro.build.version.release`, e.g. `14`
+    pub release: Option<String>,
+    /// `ro.build.version.sdk`, the API level
+    pub sdk: Option<u32>,
+    /// `ro.product.cpu.abilist`, in order of preference, including ABIs that the NDK
+    /// cannot target
+    pub abis: Vec<String>,
+    /// `wm size`, e.g. `1080x2400`, preferring the override size
+    pub resolution: Option<String>,
+    /// `wm density` in dpi, preferring the override density
+    pub density: Option<u32>,
+    /// Free space on `/data` in bytes, through `df`
+    pub free_data: Option<u64>,
+    pub package: Option<InstalledPackage>,
+}
+
+/// Space that [`DeviceInfo::incompatibilities()`] requires on top of twice the size of
+/// the apk, which is copied to `/data/app/` with its native libraries extracted next to it.
+const INSTALL_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+
+impl DeviceInfo {
+    /// Why an apk of `size` bytes with libraries for `abis` and `min_sdk_version` can't
+    /// be installed on the device, empty when it fits. Details the device did not
+    /// report are not checked.
+    pub fn incompatibilities(
+        &self,
+        min_sdk_version: u32,
+        abis: &[Target],
+        size: u64,
+    ) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let Some(sdk) = self.sdk.filter(|&sdk| sdk < min_sdk_version) {
+            reasons.push(format!(
+                "device is API {}, APK requires {}",
+                sdk, min_sdk_version
+            ));
+        }
+        if !self.abis.is_empty()
+            && !abis
+                .iter()
+                .any(|abi| self.abis.iter().any(|a| a == abi.android_abi()))
+        {
+            let packaged = abis.iter().map(|abi| abi.android_abi()).collect::<Vec<_>>();
+            reasons.push(format!(
+                "device supports {}, APK contains {}",
+                self.abis.join(", "),
+                packaged.join(", ")
+            ));
+        }
+        let required = size.saturating_mul(2).saturating_add(INSTALL_SPACE_MARGIN);
+        if let Some(free) = self.free_data.filter(|&free| free < required) {
+            reasons.push(format!(
+                "device has {} free on /data, APK needs {}",
+                format_size(free),
+                format_size(required)
+            ));
+        }
+        reasons
+    }
+}
+
+/// Whether the package passed to [`Ndk::device_info()`] is installed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub installed: bool,
+    /// The installed `versionCode`
+    pub version_code: Option<u32>,
+}
+
+/// Parses the `Available` column of `toybox df -k` in KiB, or the `Free` column of the
+/// `toolbox df` of Android 6 and lower, which ignores `-k` and prints sizes like `4.7G`.
+fn parse_df(output: &str) -> Option<u64> {
+    let mut lines = output
+        .lines()
+        .skip_while(|line| !line.starts_with("Filesystem"));
+    let column = lines
+        .next()?
+        .split_whitespace()
+        .position(|column| column == "Available" || column == "Free")?;
+    let value = lines.last()?.split_whitespace().nth(column)?;
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(value.len()),
+    );
+    let scale: u64 = match unit {
+        "" | "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    Some((number.parse::<f64>().ok()? * scale as f64) as u64)
+}
+
+/// Parses the output of `wm size` or `wm density`, like `Physical size: 1080x2400`
+/// followed by an optional `Override size: 720x1600`.
+fn parse_wm(output: &str, key: &str) -> Option<String> {
+    let value = |kind: &str| {
+        output.lines().find_map(|line| {
+            line.trim()
+                .strip_prefix(&format!("{} {}:", kind, key))
+                .map(|value| value.trim().to_string())
+        })
+    };
+    value("Override").or_else(|| value("Physical"))
+}
+
+/// Finds the first `versionCode=<code>` in the output of `dumpsys package <package>`,
+/// which has none if the package is not installed.
+pub(crate) fn parse_version_code(dumpsys: &str) -> Option<u32> {
+    dumpsys
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("versionCode="))
+        .and_then(|code| code.parse().ok())
+}
+
+/// An Android user as listed by `pm list users`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+    pub running: bool,
+}
+
+/// Parses lines in the form of `UserInfo{10:Work profile:1030} running`.
+fn parse_users(output: &str) -> Result<Vec<User>, NdkError> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("UserInfo{"))
+        .map(|line| {
+            let err = || NdkError::UserNotInOutput(line.to_string());
+            let (info, state) = line
+                .strip_prefix("UserInfo{")
+                .and_then(|l| l.split_once('}'))
+                .ok_or_else(err)?;
+            let (id, rest) = info.split_once(':').ok_or_else(err)?;
+            // The name may itself contain `:`, the flags are always last
+            let name = rest.rsplit_once(':').map_or(rest, |(name, _flags)| name);
+            Ok(User {
+                id: id.parse().map_err(|_| err())?,
+                name: name.to_string(),
+                running: state.trim() == "running",
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_parse_devices() {
+        let output = "* daemon started successfully\nList of devices attached\nemulator-5554\tdevice\nR58M12ABCDE\tunauthorized\n\n";
+        assert_eq!(
+            parse_devices(output),
+            [
+                AdbDevice {
+                    serial: "emulator-5554".to_string(),
+                    state: "device".to_string()
+                },
+                AdbDevice {
+                    serial: "R58M12ABCDE".to_string(),
+                    state: "unauthorized".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_abis() {
+        assert_eq!(
+            parse_abis("arm64-v8a,armeabi-v7a,armeabi\n"),
+            [Target::Arm64V8a, Target::ArmV7a]
+        );
+        assert_eq!(
+            parse_abis("x86_64,x86,arm64-v8a,armeabi-v7a"),
+            [
+                Target::X86_64,
+                Target::X86,
+                Target::Arm64V8a,
+                Target::ArmV7a
+            ]
+        );
+        assert!(parse_abis("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_users() {
+        let output = "Users:\n\tUserInfo{0:Owner:c13} running\n\tUserInfo{10:Work: profile:1030}\n";
+        assert_eq!(
+            parse_users(output).unwrap(),
+            [
+                User {
+                    id: 0,
+                    name: "Owner".to_string(),
+                    running: true
+                },
+                User {
+                    id: 10,
+                    name: "Work: profile".to_string(),
+                    running: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_wm() {
+        let size = "Physical size: 1080x2400\nOverride size: 720x1600\n";
+        assert_eq!(parse_wm(size, "size").as_deref(), Some("720x1600"));
+        let density = "Physical density: 420\n";
+        assert_eq!(parse_wm(density, "density").as_deref(), Some("420"));
+        assert_eq!(parse_wm("", "density"), None);
+    }
+
+    #[test]
+    fn test_parse_df() {
+        let toybox = "Filesystem     1K-blocks    Used Available Use% Mounted on\n\
+            /dev/block/dm-5  115609024 32185144  83292808  28% /data\n";
+        assert_eq!(parse_df(toybox), Some(83292808 * 1024));
+        let toolbox = "-k: No such file or directory\n\
+            Filesystem               Size     Used     Free   Blksize\n\
+            /data                    5.9G     1.2G     4.5G   4096\n";
+        assert_eq!(parse_df(toolbox), Some((4.5 * (1u64 << 30) as f64) as u64));
+        assert_eq!(parse_df("df: /data: Permission denied"), None);
+    }
+
+    #[test]
+    fn test_incompatibilities() {
+        let device = DeviceInfo {
+            sdk: Some(26),
+            abis: vec!["armeabi-v7a".to_string(), "armeabi".to_string()],
+            free_data: Some(100 * 1024 * 1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            device.incompatibilities(28, &[Target::Arm64V8a, Target::X86_64], 500 * 1024 * 1024),
+            [
+                "device is API 26, APK requires 28",
+                "device supports armeabi-v7a, armeabi, APK contains arm64-v8a, x86_64",
+                "device has 100.00 MiB free on /data, APK needs 1.04 GiB",
+            ]
+        );
+        assert!(
+            device
+                .incompatibilities(23, &[Target::ArmV7a], 8 * 1024 * 1024)
+                .is_empty()
+        );
+        // Nothing is known about unreachable devices
+        assert!(
+            DeviceInfo::default()
+                .incompatibilities(34, &[Target::X86], u64::MAX / 2)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_version_code() {
+        let dumpsys = "Packages:\n  Package [com.example.app] (4e1a2f3):\n    userId=10151\n    \
+            versionCode=1002003 minSdk=23 targetSdk=33\n    versionName=1.2.3\n";
+        assert_eq!(parse_version_code(dumpsys), Some(1002003));
+        assert_eq!(parse_version_code("Dexopt state:\n"), None);
+    }
+
+    /// An [`Ndk`] whose SDK is `root`, with an empty `adb`.
+    pub(crate) fn fake_ndk(root: &Path) -> Ndk {
+        let sdk_path = root.to_owned();
+        let platform_tools = sdk_path.join("platform-tools");
+        std::fs::create_dir_all(&platform_tools).unwrap();
+        std::fs::write(platform_tools.join(bin!("adb")), "").unwrap();
+        Ndk {
+            ndk_path: sdk_path.join("ndk"),
+            user_home: sdk_path.join(".android"),
+            sdk_path: Some(sdk_path),
+            ndk_version: Version::new(25, 2, 9519653),
+            build_tools_version: "33.0.0".to_string(),
+            build_tag: 9519653,
+            platform_levels: 19..=33,
+            platforms: vec![33],
+            compile_sdk_version: None,
+            compile_android_jar: OnceLock::new(),
+            frozen_env: false,
+            packaging_backend: PackagingBackend::External,
+            adb_timeout: Some(DEFAULT_ADB_TIMEOUT),
+            adb_transfer_timeout: None,
+            adb_transport: Transport::new(None),
+            remote: None,
+            env: BTreeMap::new(),
+            exec: Exec::Run,
+        }
+    }
+
+    /// Like [`fake_ndk()`], but only printing what it would run.
+    pub(crate) fn fake_dry_run_ndk(root: &Path) -> Ndk {
+        Ndk {
+            exec: Exec::DryRun,
+            ..fake_ndk(root)
+        }
+    }
+
+    /// Like [`fake_ndk()`], but packaging with [`PackagingBackend::Native`].
+    pub(crate) fn fake_native_ndk(root: &Path) -> Ndk {
+        Ndk {
+            packaging_backend: PackagingBackend::Native,
+            ..fake_ndk(root)
+        }
+    }
+
+    /// Like [`fake_ndk()`], but with a toolchain of empty `clang`, `clang++` and
+    /// `llvm-ar` binaries.
+    pub(crate) fn fake_ndk_with_toolchain(root: &Path) -> Ndk {
+        let ndk = fake_ndk(root);
+        let bin = ndk
+            .ndk_path
+            .join("toolchains")
+            .join("llvm")
+            .join("prebuilt")
+            .join(format!("{}-x86_64", Ndk::host_arch().unwrap()))
+            .join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let ext = std::env::consts::EXE_SUFFIX;
+        for tool in ["clang", "clang++", "llvm-ar"] {
+            std::fs::write(bin.join(format!("{}{}", tool, ext)), "").unwrap();
+        }
+        ndk
+    }
+
+    #[test]
+    fn test_adb_serial() {
+        let dir = TestDir::new("adb-serial");
+        let ndk = fake_ndk(&dir);
+        let adb = ndk.adb(Some("emulator-5554")).unwrap();
+        assert_eq!(adb.get_args().collect::<Vec<_>>(), ["-s", "emulator-5554"]);
+        assert_eq!(ndk.adb(None).unwrap().get_args().count(), 0);
+    }
+
+    #[test]
+    fn test_sdk_tools_without_sdk() {
+        let dir = TestDir::new("without-sdk");
+        let ndk = Ndk {
+            sdk_path: None,
+            ..fake_native_ndk(&dir)
+        };
+        assert_eq!(ndk.sdk(), None);
+        assert!(matches!(ndk.adb_path(), Err(NdkError::SdkNotFound)));
+        assert!(matches!(ndk.adb(None), Err(NdkError::SdkNotFound)));
+        assert!(matches!(
+            ndk.build_tool_path("apksigner"),
+            Err(NdkError::SdkNotFound)
+        ));
+        assert!(matches!(ndk.emulator(), Err(NdkError::SdkNotFound)));
+        assert!(matches!(ndk.platform_dir(33), Err(NdkError::SdkNotFound)));
+    }
+
+    /// Installs NDKs of the given revisions side by side in the SDK at `root`, returning
+    /// their parent directory.
+    fn fake_sdk_with_ndks(root: &Path, revisions: &[&str]) -> PathBuf {
+        let ndk_dir = root.join("ndk");
+        for revision in revisions {
+            let ndk_path = ndk_dir.join(revision);
+            std::fs::create_dir_all(&ndk_path).unwrap();
+            std::fs::write(
+                ndk_path.join("source.properties"),
+                format!("Pkg.Desc = Android NDK\nPkg.Revision = {}\n", revision),
+            )
+            .unwrap();
+        }
+        ndk_dir
+    }
+
+    #[test]
+    fn test_side_by_side_ndk() {
+        let sdk = TestDir::new("side-by-side");
+        let ndk_dir = fake_sdk_with_ndks(&sdk, &["25.2.9519653", "26.1.10909125", "28.0.12433566"]);
+
+        let (path, version) = find_side_by_side_ndk(&ndk_dir, None).unwrap().unwrap();
+        assert_eq!(version, Version::new(28, 0, 12433566));
+        assert_eq!(path, ndk_dir.join("28.0.12433566"));
+
+        let requirement = VersionReq::parse(">=25, <28").unwrap();
+        let (_, version) = find_side_by_side_ndk(&ndk_dir, Some(&requirement))
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, Version::new(26, 1, 10909125));
+
+        let requirement = VersionReq::parse("^27").unwrap();
+        assert!(matches!(
+            find_side_by_side_ndk(&ndk_dir, Some(&requirement)),
+            Err(NdkError::NdkVersionNotFound { found, .. }) if found.len() == 3
+        ));
+
+        assert!(
+            find_side_by_side_ndk(&ndk_dir.join("missing"), None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_side_by_side_beta_ndk() {
+        let sdk = TestDir::new("side-by-side-beta");
+        let ndk_dir = fake_sdk_with_ndks(&sdk, &["25.2.9519653", "26.0.10404224-beta1"]);
+
+        let requirement = VersionReq::parse("26").unwrap();
+        let (path, version) = find_side_by_side_ndk(&ndk_dir, Some(&requirement))
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, ndk_dir.join("26.0.10404224-beta1"));
+        assert_eq!(version.pre.as_str(), "beta1");
+
+        // The release wins over its beta
+        fake_sdk_with_ndks(&sdk, &["26.0.10404224"]);
+        let (_, version) = find_side_by_side_ndk(&ndk_dir, Some(&requirement))
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, Version::new(26, 0, 10404224));
+    }
+
+    fn fake_env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn test_sdk_path_precedence() {
+        let home = TestDir::new("home");
+        let vars = [
+            ("ANDROID_SDK_ROOT", "/sdk-root"),
+            ("ANDROID_HOME", "/android-home"),
+            ("LOCALAPPDATA", home.to_str().unwrap()),
+        ];
+        let options = NdkOptions {
+            sdk_path: Some("/metadata".into()),
+            ..Default::default()
+        };
+        let resolve =
+            |options, vars| resolve_sdk_path(options, &fake_env(vars), Some(home.to_path_buf()));
+
+        assert_eq!(
+            resolve(&options, &vars),
+            Some(("/metadata".into(), PathSource::Options))
+        );
+        let options = NdkOptions::default();
+        assert_eq!(
+            resolve(&options, &vars),
+            Some(("/sdk-root".into(), PathSource::Env("ANDROID_SDK_ROOT")))
+        );
+        assert_eq!(
+            resolve(&options, &vars[1..]),
+            Some(("/android-home".into(), PathSource::Env("ANDROID_HOME")))
+        );
+        assert_eq!(resolve(&options, &vars[2..]), None);
+
+        let default = default_sdk_path(&fake_env(&vars), Some(home.to_path_buf())).unwrap();
+        std::fs::create_dir_all(&default).unwrap();
+        assert_eq!(
+            resolve(&options, &vars[2..]),
+            Some((default, PathSource::DefaultLocation))
+        );
+    }
+
+    #[test]
+    fn test_adb_timeouts() {
+        let timeouts = |option| (adb_timeout(option, false), adb_timeout(option, true));
+        assert_eq!(timeouts(None), (Some(DEFAULT_ADB_TIMEOUT), None));
+        assert_eq!(timeouts(Some(Duration::ZERO)), (None, None));
+        let timeout = Duration::from_secs(600);
+        assert_eq!(timeouts(Some(timeout)), (Some(timeout), Some(timeout)));
+    }
+
+    #[test]
+    fn test_user_home_precedence() {
+        let vars = [
+            ("ANDROID_SDK_HOME", "/sdk-home"),
+            ("ANDROID_USER_HOME", "/user-home"),
+        ];
+        let resolve = |vars| resolve_user_home(&fake_env(vars), Some("/home".into()));
+        assert_eq!(resolve(&vars), Some("/sdk-home/.android".into()));
+        assert_eq!(resolve(&vars[1..]), Some("/user-home".into()));
+        assert_eq!(resolve(&[]), Some("/home/.android".into()));
+    }
+
+    #[test]
+    fn test_ndk_precedence() {
+        let sdk = TestDir::new("precedence");
+        let ndk_dir = fake_sdk_with_ndks(&sdk, &["25.2.9519653", "26.1.10909125", "27.0.12077973"]);
+        let sdk_path = &*sdk;
+        let env_ndk = ndk_dir.join("25.2.9519653");
+        let metadata_ndk = ndk_dir.join("26.1.10909125");
+        let vars = [
+            ("NDK_HOME", "/ignored"),
+            ("ANDROID_NDK_ROOT", env_ndk.to_str().unwrap()),
+        ];
+        let options = NdkOptions {
+            ndk_path: Some(metadata_ndk.clone()),
+            ..Default::default()
+        };
+
+        let (path, _, source) = resolve_ndk(Some(sdk_path), &options, &fake_env(&vars)).unwrap();
+        assert_eq!((path, source), (metadata_ndk, PathSource::Options));
+
+        let options = NdkOptions::default();
+        let (path, _, source) = resolve_ndk(Some(sdk_path), &options, &fake_env(&vars)).unwrap();
+        assert_eq!(
+            (path, source),
+            (env_ndk, PathSource::Env("ANDROID_NDK_ROOT"))
+        );
+
+        let (path, version, source) =
+            resolve_ndk(Some(sdk_path), &options, &fake_env(&[])).unwrap();
+        assert_eq!(path, ndk_dir.join("27.0.12077973"));
+        assert_eq!(version.major, 27);
+        assert_eq!(source, PathSource::SideBySide);
+
+        // Without an SDK, for the native packaging backend, only the environment counts
+        let env_ndk = ndk_dir.join("26.1.10909125");
+        let vars = [("NDK_HOME", env_ndk.to_str().unwrap())];
+        let (path, _, _) = resolve_ndk(None, &options, &fake_env(&vars)).unwrap();
+        assert_eq!(path, env_ndk);
+        assert!(matches!(
+            resolve_ndk(None, &options, &fake_env(&[])),
+            Err(NdkError::NdkNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_platforms_json() {
+        let ndk_path = TestDir::new("platforms-json");
+        std::fs::create_dir_all(ndk_path.join("meta")).unwrap();
+        std::fs::write(
+            ndk_path.join("meta").join("platforms.json"),
+            r#"{ "min": 21, "max": 34, "aliases": { "20": 19, "L": 21 } }"#,
+        )
+        .unwrap();
+        assert_eq!(ndk_platform_levels(&ndk_path).unwrap(), 21..=34);
+    }
+
+    #[test]
+    fn test_parse_revision() {
+        assert_eq!(
+            parse_revision("26.0.10404224-beta1").map(|v| v.patch),
+            Some(10404224)
+        );
+        assert_eq!(parse_revision("10.4"), Some(Version::new(10, 4, 0)));
+        assert_eq!(parse_revision("garbage"), None);
+    }
+
+    #[test]
+    fn test_select_build_tools() {
+        let installed = ["9.0.0", "34.0.0", "30.0.3", "35.0.0-rc1"]
+            .map(ToString::to_string)
+            .to_vec();
+        assert_eq!(
+            select_build_tools(installed.clone(), None).unwrap(),
+            "34.0.0"
+        );
+        assert_eq!(
+            select_build_tools(installed.clone(), Some("30.0.3".to_string())).unwrap(),
+            "30.0.3"
+        );
+        assert!(matches!(
+            select_build_tools(installed, Some("33.0.1".to_string())),
+            Err(NdkError::BuildToolsVersionNotFound { installed, .. })
+                if installed == ["35.0.0-rc1", "9.0.0", "30.0.3", "34.0.0"]
+        ));
+        assert!(matches!(
+            select_build_tools(vec![], None),
+            Err(NdkError::BuildToolsNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_options_env() {
+        let options = NdkOptions {
+            build_tools_version: Some("33.0.0".to_string()),
+            env: BTreeMap::from([
+                (
+                    "ANDROID_BUILD_TOOLS_VERSION".to_string(),
+                    "34.0.0".to_string(),
+                ),
+                ("JAVA_HOME".to_string(), String::new()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            requested_build_tools_version(&options).as_deref(),
+            Some("34.0.0")
+        );
+        assert_eq!(overlay_env(&options.env)("JAVA_HOME"), None);
+
+        let dir = TestDir::new("options-env");
+        let ndk = Ndk {
+            env: options.env,
+            ..fake_ndk(&dir)
+        };
+        let command = ndk.command("adb");
+        assert!(command.get_envs().any(|(key, value)| {
+            key == "ANDROID_BUILD_TOOLS_VERSION" && value == Some(OsStr::new("34.0.0"))
+        }));
+    }
+
+    #[test]
+    fn test_compile_android_jar() {
+        let sdk_path = TestDir::new("compile");
+        for platform in ["android-33", "android-35"] {
+            let dir = sdk_path.join("platforms").join(platform);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("android.jar"), "").unwrap();
+        }
+        // Without `android.jar` a platform is skipped
+        std::fs::create_dir_all(sdk_path.join("platforms").join("android-34")).unwrap();
+        let jar = |platform: &str| {
+            sdk_path
+                .join("platforms")
+                .join(platform)
+                .join("android.jar")
+        };
+        let ndk = fake_ndk(&sdk_path);
+        assert_eq!(ndk.compile_android_jar(33).unwrap(), jar("android-33"));
+        assert_eq!(ndk.compile_android_jar(34).unwrap(), jar("android-35"));
+        assert!(matches!(
+            ndk.compile_android_jar(36),
+            Err(NdkError::CompilePlatformNotFound { platform: 36, installed })
+                if installed == ["android-33", "android-34", "android-35"]
+        ));
+
+        let pinned = Ndk {
+            compile_sdk_version: Some(35),
+            compile_android_jar: OnceLock::new(),
+            ..ndk
+        };
+        assert_eq!(pinned.compile_android_jar(30).unwrap(), jar("android-35"));
+        // Cached for later artifacts, even once the platform is gone
+        std::fs::remove_dir_all(&*sdk_path).unwrap();
+        assert_eq!(pinned.compile_android_jar(30).unwrap(), jar("android-35"));
+    }
+
+    #[test]
+    fn test_gdb_over_remote() {
+        let dir = TestDir::new("gdb-remote");
+        let ndk = Ndk {
+            remote: Some("ssh://lab@bastion".parse().unwrap()),
+            ..fake_ndk(&dir)
+        };
+        assert!(matches!(
+            ndk.ndk_gdb(&*dir, "android.app.NativeActivity", None),
+            Err(NdkError::UnsupportedOverRemote { feature: "`ndk-gdb`", remote })
+                if remote == "ssh://lab@bastion"
+        ));
+    }
 
     #[test]
     #[ignore]
@@ -0,0 +1,514 @@
+//! Environment checks backing `cargo apk doctor`.
+//!
+//! Unlike [`Ndk::from_env()`](super::Ndk::from_env), which bails out on the first
+//! missing component, every [`Check`] runs independently and reports what it found
+//! or how to fix it.
+
+use super::{
+    NdkOptions, compile_platform, find_build_tools, find_keytool, find_ndk, installed_platforms,
+    ndk_platform_levels, overlay_env, parse_devices, sdk_path_from_env, user_home_from_env,
+};
+use crate::native::{self, PackagingBackend};
+use crate::util::echo_command;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The environment that [`Check`]s inspect, resolved without requiring any of
+/// it to exist.
+#[derive(Clone, Debug, Default)]
+pub struct Environment {
+    pub sdk_path: Option<PathBuf>,
+    pub user_home: Option<PathBuf>,
+    pub options: NdkOptions,
+    /// `minSdkVersion` that the NDK must be able to target
+    pub min_sdk_version: u32,
+    /// `targetSdkVersion` to look up `android.jar` for unless
+    /// [`NdkOptions::compile_sdk_version`] is set, defaults to the highest installed
+    /// platform
+    pub target_sdk_version: Option<u32>,
+}
+
+impl Environment {
+    pub fn from_env(
+        options: NdkOptions,
+        min_sdk_version: u32,
+        target_sdk_version: Option<u32>,
+    ) -> Self {
+        Self {
+            sdk_path: sdk_path_from_env(&options),
+            user_home: user_home_from_env(&options),
+            options,
+            min_sdk_version,
+            target_sdk_version,
+        }
+    }
+}
+
+/// Result of a single [`Check`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The component was found, with its path and/or version
+    Ok(String),
+    /// The component works, but something deserves attention
+    Warning(String),
+    /// The component is missing, with a remediation hint
+    Missing(String),
+}
+
+pub trait Check {
+    /// Name of the checked component, e.g. `NDK`
+    fn name(&self) -> String;
+
+    /// Whether [`Status::Missing`] should make `doctor` fail
+    fn required(&self) -> bool {
+        true
+    }
+
+    fn run(&self, env: &Environment) -> Status;
+}
+
+/// All checks run by `cargo apk doctor`, in the order they are printed.
+pub fn default_checks() -> Vec<Box<dyn Check>> {
+    vec![
+        Box::new(PackagingBackendCheck),
+        Box::new(SdkCheck),
+        Box::new(NdkCheck),
+        Box::new(BuildToolCheck {
+            tool: bin!("aapt"),
+            required: true,
+        }),
+        Box::new(BuildToolCheck {
+            tool: bin!("aapt2"),
+            required: false,
+        }),
+        Box::new(BuildToolCheck {
+            tool: bin!("zipalign"),
+            required: true,
+        }),
+        Box::new(BuildToolCheck {
+            tool: bat!("apksigner"),
+            required: true,
+        }),
+        Box::new(PlatformCheck),
+        Box::new(AdbCheck),
+        Box::new(KeytoolCheck),
+        Box::new(DebugKeystoreCheck),
+    ]
+}
+
+const SDK_HINT: &str = "Set $ANDROID_HOME to the root of the Android SDK";
+
+/// Reports what [`NdkOptions::packaging_backend`] packages and signs apks with.
+pub struct PackagingBackendCheck;
+
+impl Check for PackagingBackendCheck {
+    fn name(&self) -> String {
+        "Packaging backend".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        match env.options.packaging_backend {
+            PackagingBackend::External => Status::Ok(
+                "external, `aapt`, `zipalign` and `apksigner` of build-tools".to_string(),
+            ),
+            PackagingBackend::Native if env.min_sdk_version < native::MIN_SDK_VERSION => {
+                Status::Missing(format!(
+                    "native, which needs `min_sdk_version = {}` or higher for its v2-only \
+                    signatures, but minSdkVersion is {}",
+                    native::MIN_SDK_VERSION,
+                    env.min_sdk_version
+                ))
+            }
+            PackagingBackend::Native => {
+                Status::Ok("native, built in without build-tools or android.jar".to_string())
+            }
+        }
+    }
+}
+
+/// Turns [`Status::Missing`] into a [`Status::Warning`] for the SDK components that
+/// [`PackagingBackend::Native`] does without.
+fn unless_native(env: &Environment, status: Status) -> Status {
+    match status {
+        Status::Missing(hint) if env.options.packaging_backend == PackagingBackend::Native => {
+            Status::Warning(format!(
+                "Not needed by `packaging_backend = \"native\"`. {}",
+                hint
+            ))
+        }
+        status => status,
+    }
+}
+
+pub struct SdkCheck;
+
+impl Check for SdkCheck {
+    fn name(&self) -> String {
+        "Android SDK".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        let status = match &env.sdk_path {
+            Some(sdk_path) if sdk_path.is_dir() => Status::Ok(sdk_path.display().to_string()),
+            Some(sdk_path) => Status::Missing(format!(
+                "`{}` does not exist. {}",
+                sdk_path.display(),
+                SDK_HINT
+            )),
+            None => Status::Missing(SDK_HINT.to_string()),
+        };
+        unless_native(env, status)
+    }
+}
+
+pub struct NdkCheck;
+
+impl Check for NdkCheck {
+    fn name(&self) -> String {
+        "Android NDK".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        let (ndk_path, version) = match find_ndk(env.sdk_path.as_deref(), &env.options) {
+            Ok(found) => found,
+            Err(e) => {
+                return Status::Missing(format!(
+                    "{} Install one with `sdkmanager \"ndk;<version>\"` or set $ANDROID_NDK_ROOT",
+                    e
+                ));
+            }
+        };
+        let found = format!("{} at {}", version, ndk_path.display());
+        match ndk_platform_levels(&ndk_path) {
+            Ok(levels) if env.min_sdk_version > *levels.end() => Status::Missing(format!(
+                "NDK {} only supports up to API level {}, but minSdkVersion is {}. Install a newer NDK",
+                version,
+                levels.end(),
+                env.min_sdk_version
+            )),
+            Ok(levels) if env.min_sdk_version < *levels.start() => Status::Warning(format!(
+                "{}, but it only supports API level {} and up while minSdkVersion is {}",
+                found,
+                levels.start(),
+                env.min_sdk_version
+            )),
+            Ok(_) => Status::Ok(found),
+            Err(e) => Status::Missing(format!("{}: {}", found, e)),
+        }
+    }
+}
+
+/// Checks for a binary in the highest installed `build-tools` version.
+pub struct BuildToolCheck {
+    pub tool: &'static str,
+    pub required: bool,
+}
+
+impl Check for BuildToolCheck {
+    fn name(&self) -> String {
+        format!("build-tools `{}`", self.tool)
+    }
+
+    fn required(&self) -> bool {
+        self.required
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        unless_native(env, self.find(env))
+    }
+}
+
+impl BuildToolCheck {
+    fn find(&self, env: &Environment) -> Status {
+        let Some(sdk_path) = &env.sdk_path else {
+            return Status::Missing(SDK_HINT.to_string());
+        };
+        let version = match find_build_tools(sdk_path, &env.options) {
+            Ok(version) => version,
+            Err(_) => {
+                return Status::Missing(
+                    "Install build-tools with `sdkmanager \"build-tools;<version>\"`".to_string(),
+                );
+            }
+        };
+        let path = sdk_path.join("build-tools").join(&version).join(self.tool);
+        if path.exists() {
+            Status::Ok(format!("{} at {}", version, path.display()))
+        } else {
+            Status::Missing(format!(
+                "`{}` is missing, reinstall build-tools with `sdkmanager \"build-tools;{}\"`",
+                path.display(),
+                version
+            ))
+        }
+    }
+}
+
+/// Checks for the `android.jar` of the target platform.
+pub struct PlatformCheck;
+
+impl Check for PlatformCheck {
+    fn name(&self) -> String {
+        "Platform android.jar".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        unless_native(env, self.find(env))
+    }
+}
+
+impl PlatformCheck {
+    fn find(&self, env: &Environment) -> Status {
+        let Some(sdk_path) = &env.sdk_path else {
+            return Status::Missing(SDK_HINT.to_string());
+        };
+        let platform = match env.options.compile_sdk_version.or(env.target_sdk_version) {
+            Some(platform) => platform,
+            None => match installed_platforms(sdk_path)
+                .ok()
+                .and_then(|p| p.into_iter().max())
+            {
+                Some(platform) => platform,
+                None => {
+                    return Status::Missing(
+                        "Install a platform with `sdkmanager \"platforms;android-<api>\"`"
+                            .to_string(),
+                    );
+                }
+            },
+        };
+        let android_jar = |platform: u32| {
+            sdk_path
+                .join("platforms")
+                .join(format!("android-{}", platform))
+                .join("android.jar")
+        };
+        let installed = installed_platforms(sdk_path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|platform| android_jar(*platform).exists())
+            .collect::<Vec<_>>();
+        match compile_platform(&installed, platform) {
+            Some(found) if found == platform => {
+                Status::Ok(android_jar(found).display().to_string())
+            }
+            Some(found) => Status::Warning(format!(
+                "`android-{}` is not installed, `aapt` compiles against `{}` instead",
+                platform,
+                android_jar(found).display()
+            )),
+            None => Status::Missing(format!(
+                "Install it with `sdkmanager \"platforms;android-{}\"`",
+                platform
+            )),
+        }
+    }
+}
+
+/// Checks that `adb` is installed in the SDK, and whether it sees any usable device.
+pub struct AdbCheck;
+
+impl Check for AdbCheck {
+    fn name(&self) -> String {
+        "adb".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        let Some(sdk_path) = &env.sdk_path else {
+            return Status::Missing(SDK_HINT.to_string());
+        };
+        let adb = sdk_path.join("platform-tools").join(bin!("adb"));
+        if !adb.exists() {
+            return Status::Missing(
+                "Install platform-tools with `sdkmanager \"platform-tools\"`".to_string(),
+            );
+        }
+
+        let mut devices = Command::new(&adb);
+        devices.envs(&env.options.env);
+        devices.arg("devices");
+        echo_command(&devices);
+        let output = match devices.output() {
+            Ok(output) if output.status.success() => output,
+            _ => {
+                return Status::Warning(format!(
+                    "{}, but `adb devices` failed. Try `adb kill-server`",
+                    adb.display()
+                ));
+            }
+        };
+        let devices = parse_devices(&String::from_utf8_lossy(&output.stdout));
+        let unusable = devices
+            .iter()
+            .filter(|d| d.state != "device")
+            .map(|d| format!("{} ({})", d.serial, d.state))
+            .collect::<Vec<_>>();
+        if !unusable.is_empty() {
+            Status::Warning(format!(
+                "{}, but these devices are not usable: {}. Accept the USB debugging prompt \
+                on the device",
+                adb.display(),
+                unusable.join(", ")
+            ))
+        } else if devices.is_empty() {
+            Status::Warning(format!("{}, but no device is connected", adb.display()))
+        } else {
+            Status::Ok(format!("{}, {} device(s)", adb.display(), devices.len()))
+        }
+    }
+}
+
+/// Checks for the JDK's `keytool`, used to generate the debug keystore.
+pub struct KeytoolCheck;
+
+impl Check for KeytoolCheck {
+    fn name(&self) -> String {
+        "JDK keytool".to_string()
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        match find_keytool(&overlay_env(&env.options.env)) {
+            Some(keytool) => Status::Ok(keytool.display().to_string()),
+            None => Status::Missing(
+                "Install a JDK and add its `bin` directory to $PATH, or set $JAVA_HOME".to_string(),
+            ),
+        }
+    }
+}
+
+/// Checks for `debug.keystore`, which is generated on demand if missing.
+pub struct DebugKeystoreCheck;
+
+impl Check for DebugKeystoreCheck {
+    fn name(&self) -> String {
+        "Debug keystore".to_string()
+    }
+
+    fn required(&self) -> bool {
+        false
+    }
+
+    fn run(&self, env: &Environment) -> Status {
+        let Some(user_home) = &env.user_home else {
+            return Status::Missing("Set $ANDROID_USER_HOME or $HOME".to_string());
+        };
+        let keystore = user_home.join("debug.keystore");
+        if keystore.exists() {
+            Status::Ok(keystore.display().to_string())
+        } else {
+            Status::Warning(format!(
+                "`{}` will be generated by the first `dev` build",
+                keystore.display()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::path::Path;
+
+    /// Installs `build-tools;34.0.0` without `apksigner` and `platforms;android-33` in
+    /// the SDK at `root`.
+    fn fake_sdk(root: &Path) -> PathBuf {
+        let sdk_path = root.to_owned();
+        let build_tools = sdk_path.join("build-tools").join("34.0.0");
+        std::fs::create_dir_all(&build_tools).unwrap();
+        std::fs::write(build_tools.join(bin!("aapt")), "").unwrap();
+        std::fs::write(build_tools.join(bin!("zipalign")), "").unwrap();
+        let platform = sdk_path.join("platforms").join("android-33");
+        std::fs::create_dir_all(&platform).unwrap();
+        std::fs::write(platform.join("android.jar"), "").unwrap();
+        sdk_path
+    }
+
+    #[test]
+    fn sdk() {
+        let env = Environment::default();
+        assert_eq!(SdkCheck.run(&env), Status::Missing(SDK_HINT.to_string()));
+        let dir = TestDir::new("doctor-sdk");
+
+        let env = Environment {
+            sdk_path: Some(fake_sdk(&dir)),
+            ..Default::default()
+        };
+        assert!(matches!(SdkCheck.run(&env), Status::Ok(_)));
+    }
+
+    #[test]
+    fn build_tools() {
+        let dir = TestDir::new("doctor-build-tools");
+        let env = Environment {
+            sdk_path: Some(fake_sdk(&dir)),
+            ..Default::default()
+        };
+        let check = |tool| BuildToolCheck {
+            tool,
+            required: true,
+        };
+        assert!(matches!(check(bin!("aapt")).run(&env), Status::Ok(s) if s.starts_with("34.0.0")));
+        assert!(matches!(
+            check(bat!("apksigner")).run(&env),
+            Status::Missing(s) if s.contains("build-tools;34.0.0")
+        ));
+    }
+
+    #[test]
+    fn platform() {
+        let dir = TestDir::new("doctor-platform");
+        let mut env = Environment {
+            sdk_path: Some(fake_sdk(&dir)),
+            ..Default::default()
+        };
+        assert!(matches!(PlatformCheck.run(&env), Status::Ok(s) if s.contains("android-33")));
+
+        env.target_sdk_version = Some(34);
+        assert!(matches!(
+            PlatformCheck.run(&env),
+            Status::Missing(s) if s.contains("platforms;android-34")
+        ));
+
+        // A higher platform stands in for a missing one
+        env.target_sdk_version = Some(31);
+        assert!(matches!(PlatformCheck.run(&env), Status::Warning(s) if s.contains("android-33")));
+        env.options.compile_sdk_version = Some(33);
+        assert!(matches!(PlatformCheck.run(&env), Status::Ok(s) if s.contains("android-33")));
+    }
+
+    #[test]
+    fn debug_keystore() {
+        let dir = TestDir::new("doctor-keystore");
+        let env = Environment {
+            user_home: Some(fake_sdk(&dir)),
+            ..Default::default()
+        };
+        assert!(!DebugKeystoreCheck.required());
+        assert!(matches!(DebugKeystoreCheck.run(&env), Status::Warning(_)));
+    }
+
+    #[test]
+    fn packaging_backend() {
+        let mut env = Environment {
+            min_sdk_version: 23,
+            ..Default::default()
+        };
+        assert!(
+            matches!(PackagingBackendCheck.run(&env), Status::Ok(s) if s.starts_with("external"))
+        );
+        assert_eq!(SdkCheck.run(&env), Status::Missing(SDK_HINT.to_string()));
+
+        env.options.packaging_backend = PackagingBackend::Native;
+        assert!(
+            matches!(PackagingBackendCheck.run(&env), Status::Missing(s) if s.contains("= 24"))
+        );
+        env.min_sdk_version = 24;
+        assert!(
+            matches!(PackagingBackendCheck.run(&env), Status::Ok(s) if s.starts_with("native"))
+        );
+        // The SDK is still needed to install and run, but not to package
+        assert!(matches!(SdkCheck.run(&env), Status::Warning(s) if s.ends_with(SDK_HINT)));
+        assert!(matches!(PlatformCheck.run(&env), Status::Warning(_)));
+    }
+}
@@ -0,0 +1,172 @@
+//! Selection of the NDK's host prebuilts, and checking that they can run on this host.
+
+use crate::error::NdkError;
+use semver::Version;
+use std::io::Read;
+use std::path::Path;
+
+/// CPU architectures of host binaries, as named by [`std::env::consts::ARCH`].
+const X86_64: &str = "x86_64";
+const AARCH64: &str = "aarch64";
+
+/// Candidate names for the NDK's host prebuilt directories in order of preference,
+/// e.g. `toolchains/llvm/prebuilt/<tag>`.
+///
+/// The NDK still names its macOS prebuilts `darwin-x86_64` even though they are
+/// universal binaries since r23b, so arm64 hosts fall back to that name.
+pub(crate) fn host_tags(os: &str, arch: &str) -> Vec<String> {
+    let mut tags = vec![];
+    if arch == AARCH64 {
+        tags.push(format!("{}-arm64", os));
+        tags.push(format!("{}-aarch64", os));
+    }
+    tags.push(format!("{}-x86_64", os));
+    tags.push(os.to_string());
+    tags
+}
+
+/// Fails with [`NdkError::NdkUnsupportedHost`] if `tool`, e.g. `clang`, has no slice
+/// for this host's architecture and cannot be emulated either.
+///
+/// Binaries that can't be read or whose format is unknown are assumed to work.
+pub(crate) fn check_runnable(ndk_version: &Version, tool: &Path) -> Result<(), NdkError> {
+    let mut header = Vec::with_capacity(4096);
+    let Ok(file) = std::fs::File::open(tool) else {
+        return Ok(());
+    };
+    if file.take(4096).read_to_end(&mut header).is_err() {
+        return Ok(());
+    }
+    let archs = binary_archs(&header);
+    let host = std::env::consts::ARCH;
+    if archs.is_empty() || archs.contains(&host) || can_emulate(&archs) {
+        return Ok(());
+    }
+
+    let hint = match std::env::consts::OS {
+        "macos" => {
+            "NDK r23b (23.1.7779620) and newer ship universal binaries that run natively on \
+            Apple Silicon. Alternatively install Rosetta 2 with `softwareupdate --install-rosetta`"
+        }
+        "linux" => {
+            "The NDK only ships x86_64 Linux binaries. Register qemu-user through binfmt_misc \
+            to emulate them, or build on an x86_64 host"
+        }
+        _ => "Use an NDK built for this host",
+    };
+    Err(NdkError::NdkUnsupportedHost {
+        ndk_version: ndk_version.to_string(),
+        tool: tool.to_owned(),
+        archs: archs.join(", "),
+        host: format!("{}-{}", std::env::consts::OS, host),
+        hint,
+    })
+}
+
+/// Whether the OS transparently runs x86_64 binaries on this arm64 host.
+fn can_emulate(archs: &[&str]) -> bool {
+    if std::env::consts::ARCH != AARCH64 || !archs.contains(&X86_64) {
+        return false;
+    }
+    match std::env::consts::OS {
+        "macos" => Path::new("/Library/Apple/usr/share/rosetta/rosetta").exists(),
+        "linux" => Path::new("/proc/sys/fs/binfmt_misc/qemu-x86_64").exists(),
+        // Windows on Arm emulates x86_64 out of the box
+        "windows" => true,
+        _ => false,
+    }
+}
+
+/// CPU architectures contained in an ELF, PE or (universal) Mach-O executable,
+/// sniffed from its `header`.
+fn binary_archs(header: &[u8]) -> Vec<&'static str> {
+    let u16_le = |at: usize| {
+        header
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_le = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let u32_be = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let mach_cpu = |cpu_type| match cpu_type {
+        0x0100_0007 => Some(X86_64),
+        0x0100_000c => Some(AARCH64),
+        _ => None,
+    };
+
+    match header {
+        [0x7f, b'E', b'L', b'F', ..] => match u16_le(18) {
+            Some(0x3e) => vec![X86_64],
+            Some(0xb7) => vec![AARCH64],
+            _ => vec![],
+        },
+        // Thin 64-bit Mach-O, little endian
+        [0xcf, 0xfa, 0xed, 0xfe, ..] => u32_le(4).and_then(mach_cpu).into_iter().collect(),
+        // Universal Mach-O: `fat_header` followed by 20-byte `fat_arch` entries
+        [0xca, 0xfe, 0xba, 0xbe, ..] => {
+            let count = u32_be(4).unwrap_or(0) as usize;
+            (0..count)
+                .filter_map(|i| u32_be(8 + i * 20).and_then(mach_cpu))
+                .collect()
+        }
+        [b'M', b'Z', ..] => {
+            let machine = u32_le(0x3c).and_then(|pe| u16_le(pe as usize + 4));
+            match machine {
+                Some(0x8664) => vec![X86_64],
+                Some(0xaa64) => vec![AARCH64],
+                _ => vec![],
+            }
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        assert_eq!(host_tags("linux", "x86_64"), ["linux-x86_64", "linux"]);
+        assert_eq!(
+            host_tags("darwin", "aarch64"),
+            ["darwin-arm64", "darwin-aarch64", "darwin-x86_64", "darwin"]
+        );
+    }
+
+    #[test]
+    fn elf() {
+        let mut header = vec![0x7f, b'E', b'L', b'F'];
+        header.resize(20, 0);
+        header[18] = 0x3e;
+        assert_eq!(binary_archs(&header), [X86_64]);
+        header[18] = 0xb7;
+        assert_eq!(binary_archs(&header), [AARCH64]);
+    }
+
+    #[test]
+    fn mach_o() {
+        let thin = [0xcf, 0xfa, 0xed, 0xfe, 0x07, 0x00, 0x00, 0x01];
+        assert_eq!(binary_archs(&thin), [X86_64]);
+
+        let mut fat = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2];
+        for cpu_type in [0x0100_0007u32, 0x0100_000c] {
+            fat.extend(cpu_type.to_be_bytes());
+            fat.extend([0; 16]);
+        }
+        assert_eq!(binary_archs(&fat), [X86_64, AARCH64]);
+    }
+
+    #[test]
+    fn unknown() {
+        assert!(binary_archs(b"#!/bin/sh\n").is_empty());
+        assert!(binary_archs(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 9]).is_empty());
+    }
+}
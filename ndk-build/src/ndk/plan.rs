@@ -0,0 +1,138 @@
+//! Up-front resolution of every external tool a build needs, so that a
+//! [frozen](super::NdkOptions::frozen_env) build can fail before doing any work.
+
+use super::Ndk;
+use crate::error::NdkError;
+use crate::native::PackagingBackend;
+use crate::target::Target;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// The resolved tools and files of a build, see [`Ndk::tool_plan()`].
+#[derive(Clone, Debug, Default)]
+pub struct ToolPlan {
+    tools: Vec<(String, Result<PathBuf, String>)>,
+}
+
+impl ToolPlan {
+    /// Adds a tool, or the error explaining why it couldn't be resolved. Paths that
+    /// don't exist are recorded as missing, duplicate names are ignored.
+    pub fn require(&mut self, name: impl Into<String>, path: Result<PathBuf, impl Display>) {
+        let name = name.into();
+        if self.tools.iter().any(|(n, _)| *n == name) {
+            return;
+        }
+        let path = match path {
+            Ok(path) if !path.exists() => Err(format!("`{}` does not exist", path.display())),
+            Ok(path) => Ok(path),
+            Err(e) => Err(e.to_string()),
+        };
+        self.tools.push((name, path));
+    }
+
+    /// All tools that were resolved, by name.
+    pub fn tools(&self) -> impl Iterator<Item = (&str, &PathBuf)> {
+        self.tools
+            .iter()
+            .filter_map(|(name, path)| Some((name.as_str(), path.as_ref().ok()?)))
+    }
+
+    /// Descriptions of all missing tools, e.g. ``aapt: Command `aapt` not found.``
+    pub fn missing(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .filter_map(|(name, path)| Some(format!("{}: {}", name, path.as_ref().err()?)))
+            .collect()
+    }
+
+    /// Fails with [`NdkError::MissingTools`] listing every missing tool at once.
+    pub fn validate(&self) -> Result<(), NdkError> {
+        let missing = self.missing();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(NdkError::MissingTools(missing))
+        }
+    }
+}
+
+impl Ndk {
+    /// Resolves everything that building an APK for `targets` against the
+    /// `target_sdk_version` platform needs from the host, the SDK and the NDK.
+    pub fn tool_plan(&self, targets: &[Target], target_sdk_version: u32) -> ToolPlan {
+        let mut plan = ToolPlan::default();
+        plan.require("cargo", which::which("cargo"));
+        match self.clang() {
+            Ok((clang, clang_pp)) => {
+                plan.require("clang", Ok::<_, NdkError>(clang));
+                plan.require("clang++", Ok::<_, NdkError>(clang_pp));
+            }
+            Err(e) => plan.require("clang", Err(e)),
+        }
+        for target in targets {
+            for tool in ["ar", "readelf"] {
+                plan.require(
+                    format!("{} ({})", tool, target.android_abi()),
+                    self.toolchain_bin(tool, *target),
+                );
+            }
+        }
+        if self.packaging_backend() == PackagingBackend::Native {
+            return plan;
+        }
+        for tool in [bin!("aapt"), bin!("zipalign"), bat!("apksigner")] {
+            plan.require(tool, self.build_tool_path(tool));
+        }
+        plan.require("android.jar", self.compile_android_jar(target_sdk_version));
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn missing() {
+        let mut plan = ToolPlan::default();
+        plan.require("present", Ok::<_, NdkError>(std::env::temp_dir()));
+        plan.require(
+            "absent",
+            Ok::<_, NdkError>(PathBuf::from("/does/not/exist")),
+        );
+        plan.require("failed", Err(NdkError::CmdNotFound("failed".to_string())));
+        plan.require("present", Err("duplicates are ignored"));
+
+        assert_eq!(plan.tools().count(), 1);
+        assert_eq!(plan.missing().len(), 2);
+        assert!(
+            matches!(plan.validate(), Err(NdkError::MissingTools(m)) if m[1].starts_with("failed: "))
+        );
+    }
+
+    #[test]
+    fn fake_ndk_plan() {
+        let dir = TestDir::new("plan");
+        let ndk = crate::ndk::tests::fake_ndk(&dir);
+        let plan = ndk.tool_plan(&[Target::Arm64V8a, Target::X86_64], 33);
+        let missing = plan.missing();
+        // Everything but `cargo` is missing from the fake NDK, reported all at once
+        assert!(missing.iter().any(|m| m.starts_with("clang: ")));
+        assert!(missing.iter().any(|m| m.starts_with("ar (x86_64): ")));
+        assert!(missing.iter().any(|m| m.starts_with("android.jar: ")));
+
+        // The native backend needs neither build-tools nor a platform
+        let ndk = Ndk {
+            packaging_backend: PackagingBackend::Native,
+            ..ndk
+        };
+        let missing = ndk.tool_plan(&[Target::Arm64V8a], 33).missing();
+        assert!(missing.iter().any(|m| m.starts_with("clang: ")));
+        assert!(
+            !missing
+                .iter()
+                .any(|m| m.starts_with("aapt") || m.starts_with("android.jar"))
+        );
+    }
+}
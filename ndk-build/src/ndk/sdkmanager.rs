@@ -0,0 +1,251 @@
+//! Opt-in installation of missing SDK components through `sdkmanager`, see
+//! [`NdkOptions::install_missing`].
+
+use super::{NdkOptions, parse_revision, requested_build_tools_version};
+use crate::error::NdkError;
+use crate::util::{echo_command, status};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The `sdkmanager` from the SDK's command-line tools.
+pub struct SdkManager {
+    path: PathBuf,
+    sdk_path: PathBuf,
+    env: BTreeMap<String, String>,
+}
+
+impl SdkManager {
+    /// Looks for `sdkmanager` in `cmdline-tools/latest`, any other `cmdline-tools`
+    /// version, the legacy `tools` package, and finally on `$PATH`.
+    pub fn find(sdk_path: &Path) -> Result<Self, NdkError> {
+        let cmdline_tools = sdk_path.join("cmdline-tools");
+        let mut candidates = vec![cmdline_tools.join("latest")];
+        if let Ok(entries) = std::fs::read_dir(&cmdline_tools) {
+            candidates.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+        }
+        candidates.push(sdk_path.join("tools"));
+
+        let path = candidates
+            .into_iter()
+            .map(|dir| dir.join("bin").join(bat!("sdkmanager")))
+            .find(|path| path.exists())
+            .or_else(|| which::which(bat!("sdkmanager")).ok())
+            .ok_or_else(|| NdkError::CmdNotFound("sdkmanager".to_string()))?;
+        Ok(Self {
+            path,
+            sdk_path: sdk_path.to_owned(),
+            env: BTreeMap::new(),
+        })
+    }
+
+    /// Runs `sdkmanager` with these variables set, e.g. `JAVA_HOME`, see
+    /// [`NdkOptions::env`].
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut sdkmanager = Command::new(&self.path);
+        sdkmanager.envs(&self.env);
+        sdkmanager.arg(format!("--sdk_root={}", self.sdk_path.display()));
+        sdkmanager
+    }
+
+    /// Lists the packages that can be installed, e.g. `ndk;26.1.10909125`.
+    pub fn available_packages(&self) -> Result<Vec<String>, NdkError> {
+        let mut sdkmanager = self.command();
+        sdkmanager.arg("--list");
+        let output = crate::util::output_error(sdkmanager, None)?;
+        Ok(parse_available_packages(&String::from_utf8_lossy(&output)))
+    }
+
+    /// Accepts all SDK licenses by answering `y` to every `sdkmanager --licenses` prompt.
+    pub fn accept_licenses(&self) -> Result<(), NdkError> {
+        let mut sdkmanager = self.command();
+        sdkmanager.arg("--licenses").stdin(Stdio::piped());
+        echo_command(&sdkmanager);
+        let mut process = sdkmanager.spawn()?;
+        let mut stdin = process.stdin.take().unwrap();
+        // The prompts stop reading once all licenses are accepted
+        for _ in 0..32 {
+            if stdin.write_all(b"y\n").is_err() {
+                break;
+            }
+        }
+        drop(stdin);
+        check_status(sdkmanager, process.wait()?)
+    }
+
+    /// Installs `package`, streaming the `sdkmanager` progress output.
+    ///
+    /// Without [`SdkManager::accept_licenses()`] this fails for packages whose
+    /// license has not been accepted before.
+    pub fn install(&self, package: &str) -> Result<(), NdkError> {
+        status(
+            "Installing",
+            format_args!("`{}` through `sdkmanager`", package),
+        );
+        let mut sdkmanager = self.command();
+        sdkmanager
+            .arg("--install")
+            .arg(package)
+            .stdin(Stdio::null());
+        echo_command(&sdkmanager);
+        let status = sdkmanager.status()?;
+        check_status(sdkmanager, status)
+    }
+}
+
+fn check_status(command: Command, status: std::process::ExitStatus) -> Result<(), NdkError> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(NdkError::cmd_failed(
+            &command,
+            Some(status),
+            std::io::Error::other(""),
+        ))
+    }
+}
+
+/// Which SDK package [`find_or_install()`] should install.
+pub(crate) enum Package {
+    /// The highest stable NDK matching [`NdkOptions::ndk_version`]
+    Ndk,
+    /// The requested or highest stable build-tools version
+    BuildTools,
+    Platform(u32),
+}
+
+/// Runs `find`, and if it fails while [`NdkOptions::install_missing`] is set (and
+/// [`NdkOptions::frozen_env`] is not), installs
+/// `package` through `sdkmanager` and retries. The original error is returned if the
+/// installation fails or doesn't help.
+pub(crate) fn find_or_install<T>(
+    sdk_path: &Path,
+    options: &NdkOptions,
+    package: Package,
+    find: impl Fn() -> Result<T, NdkError>,
+) -> Result<T, NdkError> {
+    let error = match find() {
+        Err(error) if options.installs_missing() => error,
+        result => return result,
+    };
+    match install(sdk_path, options, package) {
+        Ok(()) => find().map_err(|_| error),
+        Err(install_error) => {
+            eprintln!("Failed to install missing SDK component: {}", install_error);
+            Err(error)
+        }
+    }
+}
+
+fn install(sdk_path: &Path, options: &NdkOptions, package: Package) -> Result<(), NdkError> {
+    let sdkmanager = SdkManager::find(sdk_path)?.with_env(options.env.clone());
+    if options.accept_licenses {
+        sdkmanager.accept_licenses()?;
+    }
+    let package = match package {
+        Package::Ndk => highest_available(&sdkmanager, "ndk;", options.ndk_version.as_ref())?,
+        Package::BuildTools => match requested_build_tools_version(options) {
+            Some(version) => format!("build-tools;{}", version),
+            None => highest_available(&sdkmanager, "build-tools;", None)?,
+        },
+        Package::Platform(level) => format!("platforms;android-{}", level),
+    };
+    sdkmanager.install(&package)
+}
+
+fn highest_available(
+    sdkmanager: &SdkManager,
+    prefix: &str,
+    requirement: Option<&semver::VersionReq>,
+) -> Result<String, NdkError> {
+    let available = sdkmanager.available_packages()?;
+    highest_package(&available, prefix, requirement)
+        .map(ToString::to_string)
+        .ok_or_else(|| NdkError::SdkPackageNotAvailable(prefix.to_string()))
+}
+
+/// Picks the highest stable `<prefix><version>` package matching `requirement`.
+fn highest_package<'a>(
+    available: &'a [String],
+    prefix: &str,
+    requirement: Option<&semver::VersionReq>,
+) -> Option<&'a str> {
+    available
+        .iter()
+        .filter_map(|package| {
+            let version = parse_revision(package.strip_prefix(prefix)?)?;
+            Some((package, version))
+        })
+        .filter(|(_, version)| version.pre.is_empty())
+        .filter(|(_, version)| requirement.is_none_or(|r| r.matches(version)))
+        .max_by(|(_, a), (_, b)| a.cmp(b))
+        .map(|(package, _)| package.as_str())
+}
+
+/// Extracts the package paths from the `Available Packages:` table of `sdkmanager --list`.
+fn parse_available_packages(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Available Packages:"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("Available Updates:"))
+        .filter_map(|line| line.split_once('|'))
+        .map(|(package, _)| package.trim())
+        .filter(|package| !package.is_empty() && *package != "Path" && !package.starts_with('-'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "\
+Installed packages:
+  Path              | Version | Description             | Location
+  -------           | ------- | -------                 | -------
+  build-tools;30.0.3 | 30.0.3 | Android SDK Build-Tools 30.0.3 | build-tools/30.0.3
+
+Available Packages:
+  Path                      | Version       | Description
+  -------                   | -------       | -------
+  build-tools;34.0.0        | 34.0.0        | Android SDK Build-Tools 34
+  build-tools;35.0.0-rc1    | 35.0.0 rc1    | Android SDK Build-Tools 35-rc1
+  ndk;25.2.9519653          | 25.2.9519653  | NDK (Side by side) 25.2.9519653
+  ndk;26.1.10909125         | 26.1.10909125 | NDK (Side by side) 26.1.10909125
+  ndk;27.0.11718014-beta1   | 27.0.11718014 rc1 | NDK (Side by side) 27.0.11718014
+  platforms;android-34      | 3             | Android SDK Platform 34
+";
+
+    #[test]
+    fn available_packages() {
+        let packages = parse_available_packages(LIST);
+        assert_eq!(packages.len(), 6);
+        assert_eq!(packages[0], "build-tools;34.0.0");
+        assert!(!packages.iter().any(|p| p == "build-tools;30.0.3"));
+    }
+
+    #[test]
+    fn highest() {
+        let packages = parse_available_packages(LIST);
+        assert_eq!(
+            highest_package(&packages, "build-tools;", None),
+            Some("build-tools;34.0.0")
+        );
+        assert_eq!(
+            highest_package(&packages, "ndk;", None),
+            Some("ndk;26.1.10909125")
+        );
+        let requirement = semver::VersionReq::parse("<26").unwrap();
+        assert_eq!(
+            highest_package(&packages, "ndk;", Some(&requirement)),
+            Some("ndk;25.2.9519653")
+        );
+    }
+}
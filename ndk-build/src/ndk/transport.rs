@@ -0,0 +1,266 @@
+//! How `adb` reaches its server: on this machine, or through an SSH tunnel to the
+//! server of a remote device lab, see [`NdkOptions::remote`](super::NdkOptions::remote).
+
+use crate::error::NdkError;
+use crate::util::{ManagedChild, output_error, status};
+use std::io::Read;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The port of the adb server on the remote host, the default of `adb`.
+const REMOTE_ADB_PORT: u16 = 5037;
+/// How long `ssh` may take to log in and start forwarding.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Points `adb`, or a tool that runs it, at an adb server.
+pub trait AdbTransport: std::fmt::Debug + Send + Sync {
+    /// Called for every `adb` command, before it runs.
+    fn configure(&self, command: &mut Command) -> Result<(), NdkError>;
+}
+
+/// The adb server of this machine, leaving commands as they are.
+#[derive(Debug)]
+pub struct Local;
+
+impl AdbTransport for Local {
+    fn configure(&self, _command: &mut Command) -> Result<(), NdkError> {
+        Ok(())
+    }
+}
+
+/// A host running the adb server of a device lab, parsed from `ssh://[user@]host[:port]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshRemote {
+    /// `[user@]host`, as passed to `ssh`
+    pub destination: String,
+    pub port: Option<u16>,
+}
+
+impl SshRemote {
+    /// `ssh` with the options to reach the host, to be followed by more options and
+    /// [`SshRemote::destination`].
+    fn ssh(&self) -> Command {
+        let mut ssh = Command::new("ssh");
+        // Never prompt, there is no terminal to answer on
+        ssh.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+        ssh
+    }
+}
+
+impl FromStr for SshRemote {
+    type Err = NdkError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let invalid = || NdkError::InvalidRemote(url.to_string());
+        let authority = url.strip_prefix("ssh://").ok_or_else(invalid)?;
+        let authority = authority.strip_suffix('/').unwrap_or(authority);
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => (destination, Some(port.parse().map_err(|_| invalid())?)),
+            None => (authority, None),
+        };
+        let host = destination
+            .rsplit_once('@')
+            .map_or(destination, |(_, host)| host);
+        // `ssh` would take a leading `-` for an option
+        if host.is_empty() || destination.starts_with('-') || destination.contains('/') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            destination: destination.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for SshRemote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ssh://{}", self.destination)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// Forwards a local port to the adb server of an [`SshRemote`], and points `adb` at it
+/// through `$ANDROID_ADB_SERVER_PORT`. The tunnel is opened by the first `adb` command
+/// and closed once the last clone of the [`Ndk`](super::Ndk) is dropped, or on Linux
+/// when the thread that opened it exits, see [`ManagedChild::spawn()`].
+#[derive(Debug)]
+pub struct SshTunnel {
+    remote: SshRemote,
+    tunnel: Mutex<Option<Tunnel>>,
+}
+
+struct Tunnel {
+    /// Killed on drop, on Ctrl-C and when this process dies
+    _ssh: ManagedChild,
+    port: u16,
+}
+
+impl std::fmt::Debug for Tunnel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tunnel").field("port", &self.port).finish()
+    }
+}
+
+impl SshTunnel {
+    pub fn new(remote: SshRemote) -> Self {
+        Self {
+            remote,
+            tunnel: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> Result<Tunnel, NdkError> {
+        // Without a server the forwarded connections are refused, and failing here tells
+        // a missing `adb` on the remote apart from `ssh` problems
+        let mut start_server = self.remote.ssh();
+        start_server
+            .arg(&self.remote.destination)
+            .args(["adb", "start-server"]);
+        if let Err(error) = output_error(start_server, Some(CONNECT_TIMEOUT)) {
+            return Err(match error {
+                // `ssh` exits with 255 on its own errors, otherwise with the status of `adb`
+                NdkError::CmdFailed {
+                    status: Some(status),
+                    ..
+                } if status.code() != Some(255) => NdkError::RemoteAdbFailed {
+                    remote: self.remote.to_string(),
+                    error: Box::new(error),
+                },
+                error => self.ssh_failed(error.to_string()),
+            });
+        }
+
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .map_err(|e| self.ssh_failed(format!("no free local port: {}", e)))?
+            .port();
+        let mut forward = self.remote.ssh();
+        forward
+            .args(["-N", "-o", "ExitOnForwardFailure=yes", "-L"])
+            .arg(format!("127.0.0.1:{}:127.0.0.1:{}", port, REMOTE_ADB_PORT))
+            .arg(&self.remote.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let mut ssh =
+            ManagedChild::spawn(&mut forward).map_err(|e| self.ssh_failed(e.to_string()))?;
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            if TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_ok() {
+                break;
+            }
+            if let Some(exit) = ssh.try_wait()? {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = ssh.stderr.take() {
+                    let _ = pipe.read_to_string(&mut stderr);
+                }
+                return Err(self.ssh_failed(format!("`ssh` {}: {}", exit, stderr.trim())));
+            }
+            if Instant::now() >= deadline {
+                ssh.kill()?;
+                return Err(self.ssh_failed(format!(
+                    "the tunnel was not up within {:?}",
+                    CONNECT_TIMEOUT
+                )));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        status(
+            "Tunneling",
+            format_args!("`adb` to `{}` through local port {}", self.remote, port),
+        );
+        Ok(Tunnel { _ssh: ssh, port })
+    }
+
+    fn ssh_failed(&self, error: String) -> NdkError {
+        NdkError::SshFailed {
+            remote: self.remote.to_string(),
+            error,
+        }
+    }
+}
+
+impl AdbTransport for SshTunnel {
+    fn configure(&self, command: &mut Command) -> Result<(), NdkError> {
+        let mut tunnel = self.tunnel.lock().unwrap();
+        let port = match &*tunnel {
+            Some(tunnel) => tunnel.port,
+            None => tunnel.insert(self.open()?).port,
+        };
+        command.env("ANDROID_ADB_SERVER_PORT", port.to_string());
+        Ok(())
+    }
+}
+
+/// The [`AdbTransport`] of an [`Ndk`](super::Ndk), shared by its clones.
+#[derive(Clone, Debug)]
+pub(crate) struct Transport(Arc<dyn AdbTransport>);
+
+impl Transport {
+    pub(crate) fn new(remote: Option<&SshRemote>) -> Self {
+        match remote {
+            Some(remote) => Self(Arc::new(SshTunnel::new(remote.clone()))),
+            None => Self(Arc::new(Local)),
+        }
+    }
+
+    pub(crate) fn configure(&self, command: &mut Command) -> Result<(), NdkError> {
+        self.0.configure(command)
+    }
+}
+
+impl PartialEq for Transport {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Transport {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_remote() {
+        let remote = |url: &str| url.parse::<SshRemote>();
+        assert_eq!(
+            remote("ssh://lab@bastion.example.com").unwrap(),
+            SshRemote {
+                destination: "lab@bastion.example.com".to_string(),
+                port: None,
+            }
+        );
+        let with_port = remote("ssh://bastion:2222/").unwrap();
+        assert_eq!(
+            (with_port.destination.as_str(), with_port.port),
+            ("bastion", Some(2222))
+        );
+        assert_eq!(with_port.to_string(), "ssh://bastion:2222");
+        for invalid in [
+            "bastion",
+            "tcp://bastion",
+            "ssh://",
+            "ssh://lab@",
+            "ssh://bastion:ssh",
+            "ssh://bastion/adb",
+            "ssh://-oProxyCommand=true",
+            "ssh://-lroot@bastion",
+        ] {
+            assert!(
+                matches!(remote(invalid), Err(NdkError::InvalidRemote(url)) if url == invalid),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -1,11 +1,22 @@
-use crate::apk::UnalignedApk;
+use crate::apk::{LibOrigin, UnalignedApk};
 use crate::error::NdkError;
 use crate::target::Target;
-use crate::util::output_error;
-use std::collections::HashSet;
+use crate::util::{Exec, output_error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+/// Caches the `NEEDED` entries of the libraries under the build directory.
+const NEEDED_CACHE_FILE: &str = "needed-libs.json";
+
+/// Most libraries are read in a few milliseconds, more threads than this only wait on
+/// the disk.
+const MAX_READELF_THREADS: usize = 8;
 
 impl UnalignedApk<'_> {
     pub fn add_lib_recursively(
@@ -13,7 +24,25 @@ impl UnalignedApk<'_> {
         lib: &Path,
         target: Target,
         search_paths: &[&Path],
+        origin: LibOrigin,
     ) -> Result<(), NdkError> {
+        self.add_libs_recursively(&[lib.to_path_buf()], target, search_paths, origin)
+    }
+
+    /// Adds `libs` and the libraries they need that Android doesn't provide, reading the
+    /// `NEEDED` entries of each round of newly found libraries in parallel. Libraries
+    /// inside the NDK are recorded as [`LibOrigin::NdkSysroot`], the other dependencies
+    /// that are found as [`LibOrigin::Deps`].
+    pub(crate) fn add_libs_recursively(
+        &mut self,
+        libs: &[PathBuf],
+        target: Target,
+        search_paths: &[&Path],
+        origin: LibOrigin,
+    ) -> Result<(), NdkError> {
+        if libs.is_empty() {
+            return Ok(());
+        }
         let ndk = &self.config().ndk;
         let default_min_sdk = crate::manifest::Sdk::default().min_sdk_version.unwrap();
         let min_sdk_version = self
@@ -23,6 +52,9 @@ impl UnalignedApk<'_> {
             .min_sdk_version
             .unwrap_or(default_min_sdk);
         let readelf_path = ndk.toolchain_bin("readelf", target)?;
+        let exec = ndk.exec();
+        let cache_path = self.config().build_dir.join(NEEDED_CACHE_FILE);
+        let mut cache = NeededCache::load(&cache_path);
 
         let android_search_paths = [
             &*ndk.sysroot_lib_dir(target)?,
@@ -37,41 +69,167 @@ impl UnalignedApk<'_> {
                 }
             }
         }
+        for lib in libs {
+            if let Some(name) = lib.file_name() {
+                provided.insert(name.to_string_lossy().into_owned());
+            }
+        }
 
-        let mut artifacts = vec![lib.to_path_buf()];
-        while let Some(artifact) = artifacts.pop() {
-            self.add_lib(&artifact, target)?;
-            for need in list_needed_libs(&readelf_path, &artifact)? {
-                // c++_shared is available in the NDK but not on-device.
-                // Must be bundled with the apk if used:
-                // https://developer.android.com/ndk/guides/cpp-support#libc
-                let search_paths = if need == "libc++_shared.so" {
-                    &android_search_paths
-                } else if !provided.contains(&need) {
-                    search_paths
-                } else {
-                    continue;
-                };
+        let read = |lib: &Path| list_needed_libs(exec, &readelf_path, lib);
+        let ndk_dir = ndk.ndk().to_owned();
+        let origin_of = |lib: &Path, origin| {
+            if lib.starts_with(&ndk_dir) {
+                LibOrigin::NdkSysroot
+            } else {
+                origin
+            }
+        };
+        let mut artifacts = libs.to_vec();
+        let mut origins = vec![origin; libs.len()];
+        while !artifacts.is_empty() {
+            let needed = cache.needed(&artifacts, &read)?;
+            let round = std::mem::take(&mut artifacts)
+                .into_iter()
+                .zip(std::mem::take(&mut origins));
+            for ((artifact, origin), needed) in round.zip(needed) {
+                self.add_lib(&artifact, target, origin_of(&artifact, origin))?;
+                for need in needed {
+                    // c++_shared is available in the NDK but not on-device.
+                    // Must be bundled with the apk if used:
+                    // https://developer.android.com/ndk/guides/cpp-support#libc
+                    let search_paths = if need == "libc++_shared.so" {
+                        &android_search_paths
+                    } else if !provided.contains(&need) {
+                        search_paths
+                    } else {
+                        continue;
+                    };
 
-                if let Some(path) = find_library_path(search_paths, &need)? {
-                    if provided.insert(path.file_name().unwrap().to_str().unwrap().to_string()) {
-                        artifacts.push(path);
+                    if let Some(path) = find_library_path(search_paths, &need)? {
+                        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                        if provided.insert(name) {
+                            artifacts.push(path);
+                            origins.push(LibOrigin::Deps);
+                        }
+                    } else {
+                        eprintln!("Shared library \"{}\" not found.", need);
                     }
-                } else {
-                    eprintln!("Shared library \"{}\" not found.", need);
                 }
             }
         }
 
+        // A dry run reads no libraries
+        if !exec.is_dry_run() {
+            cache.save(&cache_path)?;
+        }
         Ok(())
     }
 }
 
+/// The `NEEDED` entries of libraries read by previous builds, keyed by their path and
+/// invalidated when their size or modification time changes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NeededCache {
+    libs: BTreeMap<PathBuf, CachedNeeded>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedNeeded {
+    stamp: Stamp,
+    needed: Vec<String>,
+}
+
+/// The size and modification time of a file, in seconds and nanoseconds since the epoch.
+type Stamp = (u64, u64, u32);
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+impl NeededCache {
+    /// An empty cache if `path` doesn't exist or is from an incompatible version.
+    fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Skipped when a path isn't UTF-8 and can't be a JSON key, its libraries are then
+    /// read again by the next build.
+    fn save(&self, path: &Path) -> Result<(), NdkError> {
+        let Ok(json) = serde_json::to_vec(self) else {
+            return Ok(());
+        };
+        std::fs::write(path, json).map_err(|e| NdkError::IoPathError(path.to_owned(), e))
+    }
+
+    /// The sorted `NEEDED` entries of every library of `libs`, calling `read` on a few
+    /// threads for those that are not cached or changed since.
+    fn needed(
+        &mut self,
+        libs: &[PathBuf],
+        read: &(dyn Fn(&Path) -> Result<HashSet<String>, NdkError> + Sync),
+    ) -> Result<Vec<Vec<String>>, NdkError> {
+        let stamps = libs.iter().map(|lib| stamp(lib)).collect::<Vec<_>>();
+        let mut needed = libs
+            .iter()
+            .zip(&stamps)
+            .map(|(lib, stamp)| {
+                let cached = self.libs.get(lib)?;
+                (Some(cached.stamp) == *stamp).then(|| cached.needed.clone())
+            })
+            .collect::<Vec<_>>();
+        let misses = (0..libs.len())
+            .filter(|&i| needed[i].is_none())
+            .collect::<Vec<_>>();
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(misses.len()));
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_READELF_THREADS)
+            .min(misses.len());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    while let Some(&i) = misses.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let result = read(&libs[i]);
+                        results.lock().unwrap().push((i, result));
+                    }
+                });
+            }
+        });
+
+        for (i, result) in results.into_inner().unwrap() {
+            let mut libs_needed = result?.into_iter().collect::<Vec<_>>();
+            libs_needed.sort();
+            // Libraries that don't exist, like in a dry run, are read every time
+            if let Some(stamp) = stamps[i] {
+                let cached = CachedNeeded {
+                    stamp,
+                    needed: libs_needed.clone(),
+                };
+                self.libs.insert(libs[i].clone(), cached);
+            }
+            needed[i] = Some(libs_needed);
+        }
+        Ok(needed.into_iter().map(Option::unwrap).collect())
+    }
+}
+
 /// List all linked shared libraries
-fn list_needed_libs(readelf_path: &Path, library_path: &Path) -> Result<HashSet<String>, NdkError> {
+fn list_needed_libs(
+    exec: Exec,
+    readelf_path: &Path,
+    library_path: &Path,
+) -> Result<HashSet<String>, NdkError> {
     let mut readelf = Command::new(readelf_path);
     readelf.arg("-d").arg(library_path);
-    let output = output_error(readelf)?;
+    // Empty in a dry run, where the libraries are not built
+    let output = exec.output_error(readelf, None)?;
     let mut needed = HashSet::new();
     for line in output.lines() {
         let line = line?;
@@ -88,6 +246,109 @@ fn list_needed_libs(readelf_path: &Path, library_path: &Path) -> Result<HashSet<
     Ok(needed)
 }
 
+/// Whether the library has a symbol table or DWARF debug info, which `strip` removes
+pub(crate) fn has_symbols(readelf_path: &Path, library_path: &Path) -> Result<bool, NdkError> {
+    let mut readelf = Command::new(readelf_path);
+    readelf.arg("-S").arg("-W").arg(library_path);
+    let output = output_error(readelf, None)?;
+    Ok(lists_symbol_sections(&String::from_utf8_lossy(&output)))
+}
+
+/// Looks for the `.symtab` or `.debug_info` sections in the output of `readelf -S`
+fn lists_symbol_sections(sections: &str) -> bool {
+    sections
+        .split_whitespace()
+        .any(|word| word == ".symtab" || word == ".debug_info")
+}
+
+/// The alignment of every `LOAD` segment of the library, which must be at least the
+/// page size of the device to load it
+pub fn load_alignments(readelf_path: &Path, library_path: &Path) -> Result<Vec<u64>, NdkError> {
+    let mut readelf = Command::new(readelf_path);
+    readelf.arg("-l").arg("-W").arg(library_path);
+    let output = output_error(readelf, None)?;
+    Ok(parse_load_alignments(&String::from_utf8_lossy(&output)))
+}
+
+/// Reads the last column, `Align`, of the `LOAD` rows in the output of `readelf -l`
+fn parse_load_alignments(program_headers: &str) -> Vec<u64> {
+    program_headers
+        .lines()
+        .filter(|line| line.split_whitespace().next() == Some("LOAD"))
+        .filter_map(|line| line.split_whitespace().last())
+        .filter_map(|align| u64::from_str_radix(align.trim_start_matches("0x"), 16).ok())
+        .collect()
+}
+
+/// The dynamic symbols that the library defines, without their version suffix
+pub fn defined_dynamic_symbols(
+    readelf_path: &Path,
+    library_path: &Path,
+) -> Result<HashSet<String>, NdkError> {
+    let output = dynamic_symbols(readelf_path, library_path)?;
+    Ok(parse_dynamic_symbols(&output, true))
+}
+
+/// The dynamic symbols that the library imports from other libraries, without their
+/// version suffix
+pub fn imported_dynamic_symbols(
+    readelf_path: &Path,
+    library_path: &Path,
+) -> Result<HashSet<String>, NdkError> {
+    let output = dynamic_symbols(readelf_path, library_path)?;
+    Ok(parse_dynamic_symbols(&output, false))
+}
+
+fn dynamic_symbols(readelf_path: &Path, library_path: &Path) -> Result<String, NdkError> {
+    let mut readelf = Command::new(readelf_path);
+    readelf.arg("--dyn-syms").arg("-W").arg(library_path);
+    let output = output_error(readelf, None)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Reads the rows of `readelf --dyn-syms`, `Num: Value Size Type Bind Vis Ndx Name`,
+/// keeping either the defined or the undefined (`UND`) ones
+fn parse_dynamic_symbols(symbols: &str, defined: bool) -> HashSet<String> {
+    symbols
+        .lines()
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            match columns[..] {
+                [num, _, _, _, _, _, ndx, name, ..]
+                    if num.trim_end_matches(':').parse::<u32>().is_ok()
+                        && (ndx != "UND") == defined =>
+                {
+                    Some(name.split('@').next().unwrap().to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The NUL-terminated strings in `section` of the library, like `.rodata`, empty if
+/// the library doesn't have it
+pub fn section_strings(
+    readelf_path: &Path,
+    library_path: &Path,
+    section: &str,
+) -> Result<Vec<String>, NdkError> {
+    let mut readelf = Command::new(readelf_path);
+    readelf.arg("-p").arg(section).arg("-W").arg(library_path);
+    let output = output_error(readelf, None)?;
+    Ok(parse_string_dump(&String::from_utf8_lossy(&output)))
+}
+
+/// Reads the `[offset]  string` rows of `readelf -p`
+fn parse_string_dump(dump: &str) -> Vec<String> {
+    dump.lines()
+        .filter_map(|line| line.trim_start().strip_prefix('['))
+        .filter_map(|line| line.split_once(']'))
+        .filter(|(offset, _)| usize::from_str_radix(offset.trim(), 16).is_ok())
+        .map(|(_, string)| string.trim_start().to_string())
+        .collect()
+}
+
 /// List shared libraries
 fn list_libs(path: &Path) -> Result<HashSet<String>, NdkError> {
     let mut libs = HashSet::new();
@@ -118,3 +379,147 @@ fn find_library_path<S: AsRef<Path>>(
     }
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn symbol_sections() {
+        let section = |name: &str, kind: &str| {
+            format!(
+                "  [27] {:<17} {:<15} 0000000000000000 0f1a28 0478c0 00      0   0  1\n",
+                name, kind
+            )
+        };
+        let header = "There are 29 section headers, starting at offset 0x2a1f40:\n\n\
+            Section Headers:\n  [Nr] Name              Type            Address          Off    Size   ES Flg Lk Inf Al\n";
+        let stripped = format!(
+            "{}{}{}",
+            header,
+            section(".dynsym", "DYNSYM"),
+            section(".shstrtab", "STRTAB")
+        );
+        assert!(!lists_symbol_sections(&stripped));
+        let symtab = format!("{}{}", stripped, section(".symtab", "SYMTAB"));
+        assert!(lists_symbol_sections(&symtab));
+        let debug_info = format!("{}{}", stripped, section(".debug_info", "PROGBITS"));
+        assert!(lists_symbol_sections(&debug_info));
+    }
+
+    #[test]
+    fn program_headers() {
+        let output = "\nElf file type is DYN (Shared object file)\n\
+            Entry point 0x0\n\
+            There are 10 program headers, starting at offset 64\n\n\
+            Program Headers:\n  \
+            Type           Offset   VirtAddr           PhysAddr           FileSiz  MemSiz   Flg Align\n  \
+            PHDR           0x000040 0x0000000000000040 0x0000000000000040 0x000230 0x000230 R   0x8\n  \
+            LOAD           0x000000 0x0000000000000000 0x0000000000000000 0x0a1b2c 0x0a1b2c R   0x4000\n  \
+            LOAD           0x0a1b30 0x00000000000a5b30 0x00000000000a5b30 0x1f2e40 0x1f2e40 R E 0x1000\n  \
+            DYNAMIC        0x2a0d88 0x00000000002a8d88 0x00000000002a8d88 0x0001d0 0x0001d0 RW  0x8\n\n \
+            Section to Segment mapping:\n  \
+            Segment Sections...\n   \
+            00     \n   \
+            01     .dynsym .dynstr .rodata\n";
+        assert_eq!(parse_load_alignments(output), [0x4000, 0x1000]);
+    }
+
+    #[test]
+    fn dynamic_symbols() {
+        let output = "\nSymbol table '.dynsym' contains 4 entries:\n   \
+            Num:    Value          Size Type    Bind   Vis      Ndx Name\n     \
+            0: 0000000000000000     0 NOTYPE  LOCAL  DEFAULT  UND \n     \
+            1: 0000000000000000     0 FUNC    GLOBAL DEFAULT  UND __cxa_finalize@LIBC\n     \
+            2: 00000000000a1b20   128 FUNC    GLOBAL DEFAULT   14 ANativeActivity_onCreate\n     \
+            3: 00000000000a2c40    64 FUNC    GLOBAL DEFAULT   14 rust_eh_personality@@V1\n";
+        let symbols = parse_dynamic_symbols(output, true);
+        assert_eq!(
+            symbols,
+            HashSet::from([
+                "ANativeActivity_onCreate".to_string(),
+                "rust_eh_personality".to_string()
+            ])
+        );
+        let imports = parse_dynamic_symbols(output, false);
+        assert_eq!(imports, HashSet::from(["__cxa_finalize".to_string()]));
+    }
+
+    #[test]
+    fn needed_cache() {
+        let dir = TestDir::new("needed");
+        let libs = (0..40)
+            .map(|i| {
+                let lib = dir.join(format!("libdep{}.so", i));
+                std::fs::write(&lib, format!("ELF {}", i)).unwrap();
+                lib
+            })
+            .collect::<Vec<_>>();
+        let reads = AtomicUsize::new(0);
+        let read = |lib: &Path| {
+            reads.fetch_add(1, Ordering::Relaxed);
+            let name = lib.file_name().unwrap().to_string_lossy();
+            Ok(HashSet::from([
+                "libc.so".to_string(),
+                format!("{}.needed", name),
+            ]))
+        };
+
+        let cache_path = dir.join(NEEDED_CACHE_FILE);
+        let mut cache = NeededCache::load(&cache_path);
+        let needed = cache.needed(&libs, &read).unwrap();
+        assert_eq!(needed[3], ["libc.so", "libdep3.so.needed"]);
+        assert_eq!(reads.load(Ordering::Relaxed), 40);
+        cache.save(&cache_path).unwrap();
+
+        // Unchanged libraries are not read again by the next build
+        let mut cache = NeededCache::load(&cache_path);
+        assert_eq!(cache.needed(&libs, &read).unwrap(), needed);
+        assert_eq!(reads.load(Ordering::Relaxed), 40);
+
+        std::fs::write(&libs[7], "ELF 7, relinked").unwrap();
+        cache.needed(&libs, &read).unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 41);
+
+        let missing = dir.join("libmissing.so");
+        cache.needed(&[missing.clone(), missing], &read).unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 43);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn needed_cache_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TestDir::new("needed-cache-non-utf8");
+        let mut cache = NeededCache::default();
+        cache.libs.insert(
+            PathBuf::from(OsStr::from_bytes(b"lib\xff.so")),
+            CachedNeeded {
+                stamp: (0, 0, 0),
+                needed: vec!["libc.so".to_string()],
+            },
+        );
+        let cache_path = dir.join(NEEDED_CACHE_FILE);
+        cache.save(&cache_path).unwrap();
+        assert!(!cache_path.exists());
+    }
+
+    #[test]
+    fn string_dump() {
+        let output = "\nString dump of section '.rodata':\n  \
+            [     0]  android/hardware/Camera\n  \
+            [    18]  called `Option::unwrap()` on a `None` value [1]\n  \
+            [    4a]  %s\n\n";
+        assert_eq!(
+            parse_string_dump(output),
+            [
+                "android/hardware/Camera",
+                "called `Option::unwrap()` on a `None` value [1]",
+                "%s"
+            ]
+        );
+    }
+}
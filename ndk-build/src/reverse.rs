@@ -0,0 +1,178 @@
+//! Applies `reverse_port_forward` idempotently, keeping the mappings that
+//! `adb reverse --list` already shows and replacing stale ones on the same device port.
+
+/// The host side of a forwarded device port, either the host port like `"tcp:8080"` or
+/// a table `{ to = "tcp:8080", required = true }`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ReversePort {
+    Host(String),
+    Table {
+        to: String,
+        /// Fail once all ports are forwarded if this one could not be, instead of only
+        /// warning about it
+        #[serde(default)]
+        required: bool,
+    },
+}
+
+impl ReversePort {
+    pub fn host(&self) -> &str {
+        match self {
+            Self::Host(host) | Self::Table { to: host, .. } => host,
+        }
+    }
+
+    pub fn required(&self) -> bool {
+        matches!(self, Self::Table { required: true, .. })
+    }
+}
+
+/// A device port forwarded to a host port, as listed by `adb reverse --list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReverseMapping {
+    pub device: String,
+    pub host: String,
+}
+
+/// Reads the `<transport> <device> <host>` lines of `adb reverse --list`, of which older
+/// `adb` versions prefix the transport with `(reverse)`.
+pub fn parse_list(output: &str) -> Vec<ReverseMapping> {
+    output.lines().filter_map(parse_list_line).collect()
+}
+
+fn parse_list_line(line: &str) -> Option<ReverseMapping> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["(reverse)", _, device, host] | [_, device, host] => Some(ReverseMapping {
+            device: device.to_string(),
+            host: host.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// What [`plan()`] decided for a mapping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReverseAction {
+    /// The device port is already forwarded to the host port
+    Keep,
+    /// The device port is not forwarded yet
+    Forward,
+    /// The device port is forwarded to the host port `previous`, which `adb reverse`
+    /// rebinds
+    Replace { previous: String },
+}
+
+/// How to forward `device` to `host` given the `existing` mappings.
+pub fn plan(existing: &[ReverseMapping], device: &str, host: &str) -> ReverseAction {
+    match existing.iter().find(|mapping| mapping.device == device) {
+        Some(mapping) if mapping.host == host => ReverseAction::Keep,
+        Some(mapping) => ReverseAction::Replace {
+            previous: mapping.host.clone(),
+        },
+        None => ReverseAction::Forward,
+    }
+}
+
+/// The port number of a `tcp:<port>` device port, the only kind that `ss` can find the
+/// listener of.
+pub(crate) fn tcp_port(device: &str) -> Option<&str> {
+    device
+        .strip_prefix("tcp:")
+        .filter(|port| port.parse::<u16>().is_ok())
+}
+
+/// The process listening in the output of `ss -Hltnp`, as `` `<name>` (pid <pid>) ``.
+/// `ss` only shows the process to the user running it or `root`.
+pub(crate) fn parse_ss_process(output: &str) -> Option<String> {
+    let users = &output[output.find("users:((\"")? + "users:((\"".len()..];
+    let (name, rest) = users.split_once('"')?;
+    let pid = rest
+        .strip_prefix(",pid=")
+        .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+        .filter(|pid| !pid.is_empty());
+    Some(match pid {
+        Some(pid) => format!("`{}` (pid {})", name, pid),
+        None => format!("`{}`", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(device: &str, host: &str) -> ReverseMapping {
+        ReverseMapping {
+            device: device.to_string(),
+            host: host.to_string(),
+        }
+    }
+
+    #[test]
+    fn list() {
+        let output = "UsbFfs tcp:8081 tcp:8081\nemulator-5554 tcp:1338 tcp:9000\n\
+            (reverse) localabstract:chrome tcp:9222\n\n";
+        assert_eq!(
+            parse_list(output),
+            [
+                mapping("tcp:8081", "tcp:8081"),
+                mapping("tcp:1338", "tcp:9000"),
+                mapping("localabstract:chrome", "tcp:9222"),
+            ]
+        );
+        assert_eq!(parse_list(""), []);
+        assert_eq!(parse_list("error: no devices found"), []);
+    }
+
+    #[test]
+    fn actions() {
+        let existing = [
+            mapping("tcp:8081", "tcp:8081"),
+            mapping("tcp:1338", "tcp:9000"),
+        ];
+        assert_eq!(plan(&existing, "tcp:8081", "tcp:8081"), ReverseAction::Keep);
+        assert_eq!(
+            plan(&existing, "tcp:1338", "tcp:1338"),
+            ReverseAction::Replace {
+                previous: "tcp:9000".to_string()
+            }
+        );
+        assert_eq!(
+            plan(&existing, "tcp:5000", "tcp:5000"),
+            ReverseAction::Forward
+        );
+        // Only the device side is unique
+        assert_eq!(
+            plan(&existing, "tcp:9000", "tcp:8081"),
+            ReverseAction::Forward
+        );
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(tcp_port("tcp:1338"), Some("1338"));
+        assert_eq!(tcp_port("tcp:http"), None);
+        assert_eq!(tcp_port("localabstract:chrome"), None);
+    }
+
+    #[test]
+    fn ss_process() {
+        let ss = "LISTEN 0 50 127.0.0.1:1338 0.0.0.0:* users:((\"adbd\",pid=612,fd=12))";
+        assert_eq!(parse_ss_process(ss), Some("`adbd` (pid 612)".to_string()));
+        let ss = "LISTEN 0 50 *:1338 *:* users:((\"node\",fd=3))";
+        assert_eq!(parse_ss_process(ss), Some("`node`".to_string()));
+        // Not visible without root
+        assert_eq!(parse_ss_process("LISTEN 0 50 *:1338 *:*"), None);
+        assert_eq!(parse_ss_process(""), None);
+    }
+
+    #[test]
+    fn config() {
+        let port = |json| serde_json::from_str::<ReversePort>(json).unwrap();
+        let host = port(r#""tcp:80""#);
+        assert_eq!((host.host(), host.required()), ("tcp:80", false));
+        let table = port(r#"{ "to": "tcp:80", "required": true }"#);
+        assert_eq!((table.host(), table.required()), ("tcp:80", true));
+        assert!(!port(r#"{ "to": "tcp:80" }"#).required());
+    }
+}
@@ -0,0 +1,147 @@
+//! Builds instrumented with AddressSanitizer or HWAddressSanitizer, see
+//! <https://developer.android.com/ndk/guides/asan> and
+//! <https://developer.android.com/ndk/guides/hwasan>.
+
+use crate::error::NdkError;
+use crate::ndk::Ndk;
+use crate::target::Target;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// `wrap.sh` preloading the ASan runtime, which must be loaded before any other library.
+const ASAN_WRAP_SH: &str = r#"#!/system/bin/sh
+HERE="$(cd "$(dirname "$0")" && pwd)"
+export ASAN_OPTIONS=log_to_syslog=false,allow_user_segv_handler=1
+ASAN_LIB=$(ls "$HERE"/libclang_rt.asan-*-android.so)
+if [ -f "$HERE/libc++_shared.so" ]; then
+    # Workaround for https://github.com/android/ndk/issues/988
+    export LD_PRELOAD="$ASAN_LIB $HERE/libc++_shared.so"
+else
+    export LD_PRELOAD="$ASAN_LIB"
+fi
+"$@"
+"#;
+
+/// `wrap.sh` making the dynamic linker load HWASan-instrumented libraries.
+const HWASAN_WRAP_SH: &str = "#!/system/bin/sh\nLD_HWASAN=1 exec \"$@\"\n";
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sanitizer {
+    /// AddressSanitizer, on all targets
+    Address,
+    /// Hardware-assisted AddressSanitizer, on `arm64-v8a` only
+    Hwaddress,
+}
+
+impl Sanitizer {
+    /// The `-Zsanitizer=` and `-fsanitize=` value.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Address => "address",
+            Self::Hwaddress => "hwaddress",
+        }
+    }
+
+    pub fn supports(self, target: Target) -> bool {
+        match self {
+            Self::Address => true,
+            Self::Hwaddress => target == Target::Arm64V8a,
+        }
+    }
+
+    /// The flags instrumenting Rust code and linking the NDK's sanitizer runtime.
+    /// These require a nightly toolchain.
+    pub fn rustflags(self) -> Vec<String> {
+        let mut flags = vec![format!("-Zsanitizer={}", self.name())];
+        if self == Self::Hwaddress {
+            flags.push("-Ctarget-feature=+tagged-globals".to_string());
+        }
+        flags.push(format!("-Clink-arg=-fsanitize={}", self.name()));
+        flags
+    }
+
+    /// The file name of the runtime library for `target`, e.g.
+    /// `libclang_rt.hwasan-aarch64-android.so`.
+    pub fn runtime_name(self, target: Target) -> String {
+        let arch = match target {
+            Target::Arm64V8a => "aarch64",
+            Target::ArmV7a => "arm",
+            Target::X86 => "i686",
+            Target::X86_64 => "x86_64",
+        };
+        let runtime = match self {
+            Self::Address => "asan",
+            Self::Hwaddress => "hwasan",
+        };
+        format!("libclang_rt.{}-{}-android.so", runtime, arch)
+    }
+
+    /// The `wrap.sh` to package into `lib/<abi>/`, which requires `extractNativeLibs`
+    /// and a debuggable app.
+    pub fn wrap_sh(self) -> &'static str {
+        match self {
+            Self::Address => ASAN_WRAP_SH,
+            Self::Hwaddress => HWASAN_WRAP_SH,
+        }
+    }
+}
+
+impl Ndk {
+    /// Finds the [`Sanitizer::runtime_name()`] library in the toolchain's
+    /// `lib/clang/<version>/lib/linux/` (`lib64/` before NDK r23).
+    pub fn sanitizer_runtime(
+        &self,
+        sanitizer: Sanitizer,
+        target: Target,
+    ) -> Result<PathBuf, NdkError> {
+        let toolchain_dir = self.toolchain_dir()?;
+        let runtime_name = sanitizer.runtime_name(target);
+        ["lib", "lib64"]
+            .iter()
+            .filter_map(|lib| std::fs::read_dir(toolchain_dir.join(lib).join("clang")).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().join("lib").join("linux").join(&runtime_name))
+            .find(|path| path.exists())
+            .ok_or_else(|| NdkError::SanitizerNotSupported {
+                sanitizer: sanitizer.name(),
+                target: target.android_abi(),
+                ndk_version: self.ndk_version().to_string(),
+                library: runtime_name,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn runtime() {
+        let root = TestDir::new("sanitizer");
+        let ndk = crate::ndk::tests::fake_ndk_with_toolchain(&root);
+        let linux = ndk
+            .toolchain_dir()
+            .unwrap()
+            .join("lib")
+            .join("clang")
+            .join("17")
+            .join("lib")
+            .join("linux");
+        std::fs::create_dir_all(&linux).unwrap();
+        std::fs::write(linux.join("libclang_rt.hwasan-aarch64-android.so"), "").unwrap();
+
+        assert_eq!(
+            ndk.sanitizer_runtime(Sanitizer::Hwaddress, Target::Arm64V8a)
+                .unwrap(),
+            linux.join("libclang_rt.hwasan-aarch64-android.so")
+        );
+        assert!(matches!(
+            ndk.sanitizer_runtime(Sanitizer::Address, Target::X86_64),
+            Err(NdkError::SanitizerNotSupported { library, .. })
+                if library == "libclang_rt.asan-x86_64-android.so"
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::NdkError;
+use crate::ndk::Ndk;
+use crate::target::Target;
+use crate::util::{output_error, output_timeout};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Where [`push`] places the NDK's `simpleperf` binary on the device.
+pub const DEVICE_SIMPLEPERF: &str = "/data/local/tmp/simpleperf";
+const DEVICE_PERF_DATA: &str = "/data/local/tmp/perf.data";
+
+/// Options for [`record`].
+#[derive(Clone, Debug)]
+pub struct RecordOptions {
+    /// How long to record for
+    pub duration: Duration,
+    /// Event to sample, e.g. `cpu-cycles`, or `cpu-clock` on emulators without
+    /// hardware counters
+    pub event: String,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(10),
+            event: "cpu-cycles".to_string(),
+        }
+    }
+}
+
+/// Pushes the NDK's `simpleperf` for `target` to [`DEVICE_SIMPLEPERF`], unless a
+/// binary of the same size is already present.
+pub fn push(ndk: &Ndk, device_serial: Option<&str>, target: Target) -> Result<(), NdkError> {
+    let simpleperf = ndk.simpleperf_device_bin(target)?;
+    let size = std::fs::metadata(&simpleperf)
+        .map_err(|e| NdkError::IoPathError(simpleperf.clone(), e))?
+        .len();
+
+    let mut adb = ndk.adb(device_serial)?;
+    adb.arg("shell")
+        .arg("stat")
+        .arg("-c")
+        .arg("%s")
+        .arg(DEVICE_SIMPLEPERF);
+    // Fails, or prints an error on old `adb` versions, when the file doesn't exist
+    let device_size = output_timeout(&mut adb, ndk.adb_timeout())?.stdout;
+    if String::from_utf8_lossy(&device_size).trim() == size.to_string() {
+        return Ok(());
+    }
+
+    let mut adb = ndk.adb(device_serial)?;
+    adb.arg("push").arg(&simpleperf).arg(DEVICE_SIMPLEPERF);
+    output_error(adb, ndk.adb_transfer_timeout())?;
+
+    let mut adb = ndk.adb(device_serial)?;
+    adb.arg("shell")
+        .arg("chmod")
+        .arg("a+x")
+        .arg(DEVICE_SIMPLEPERF);
+    output_error(adb, ndk.adb_timeout())?;
+    Ok(())
+}
+
+/// Records call graphs of the app `package` with a previously [`push`]ed `simpleperf`,
+/// and pulls the resulting `perf.data` to `output`.
+///
+/// `--app` follows all processes of the app and waits for it to start. Without root
+/// this requires the app to be debuggable or
+/// [`profileable`](crate::manifest::Profileable) by the shell.
+pub fn record(
+    ndk: &Ndk,
+    device_serial: Option<&str>,
+    package: &str,
+    options: &RecordOptions,
+    output: &Path,
+) -> Result<(), NdkError> {
+    let adb = record_command(ndk, device_serial, package, options)?;
+    output_error(adb, ndk.adb_timeout().map(|t| t + options.duration))?;
+
+    let mut adb = ndk.adb(device_serial)?;
+    adb.arg("pull").arg(DEVICE_PERF_DATA).arg(output);
+    output_error(adb, ndk.adb_transfer_timeout())?;
+    Ok(())
+}
+
+fn record_command(
+    ndk: &Ndk,
+    device_serial: Option<&str>,
+    package: &str,
+    options: &RecordOptions,
+) -> Result<Command, NdkError> {
+    let mut adb = ndk.adb(device_serial)?;
+    adb.arg("shell")
+        .arg(DEVICE_SIMPLEPERF)
+        .arg("record")
+        .arg("--app")
+        .arg(package)
+        .arg("-e")
+        .arg(&options.event)
+        .arg("--duration")
+        .arg(options.duration.as_secs_f64().to_string())
+        .arg("-g")
+        .arg("-o")
+        .arg(DEVICE_PERF_DATA);
+    Ok(adb)
+}
+
+/// Runs the host `simpleperf report` on `perf_data` and returns its output.
+///
+/// `symfs` should contain the unstripped libraries: `simpleperf` falls back to
+/// looking up libraries by file name (and build id) in this directory, which
+/// also covers libraries loaded straight from the APK.
+pub fn report(ndk: &Ndk, perf_data: &Path, symfs: &Path) -> Result<Vec<u8>, NdkError> {
+    let mut simpleperf = ndk.simpleperf()?;
+    simpleperf
+        .arg("report")
+        .arg("-i")
+        .arg(perf_data)
+        .arg("--symfs")
+        .arg(symfs)
+        .arg("--sort")
+        .arg("dso,symbol");
+    output_error(simpleperf, None)
+}
+
+/// Converts `perf_data` into the folded stack format consumed by `flamegraph.pl` and
+/// `inferno`, through the NDK's `stackcollapse.py`. See [`report`] for `symfs`.
+pub fn folded(ndk: &Ndk, perf_data: &Path, symfs: &Path) -> Result<Vec<u8>, NdkError> {
+    let python = ["python3", "python"]
+        .into_iter()
+        .find_map(|python| which::which(python).ok())
+        .ok_or_else(|| NdkError::CmdNotFound("python3".to_string()))?;
+    let mut stackcollapse = Command::new(python);
+    stackcollapse
+        .arg(ndk.simpleperf_dir()?.join("stackcollapse.py"))
+        .arg("-i")
+        .arg(perf_data)
+        .arg("--symfs")
+        .arg(symfs);
+    output_error(stackcollapse, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndk::tests::fake_ndk;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_record_command() {
+        let options = RecordOptions {
+            duration: Duration::from_millis(2500),
+            ..Default::default()
+        };
+        let dir = TestDir::new("simpleperf");
+        let adb = record_command(
+            &fake_ndk(&dir),
+            Some("emulator-5554"),
+            "rust.example",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            adb.get_args().collect::<Vec<_>>(),
+            [
+                "-s",
+                "emulator-5554",
+                "shell",
+                DEVICE_SIMPLEPERF,
+                "record",
+                "--app",
+                "rust.example",
+                "-e",
+                "cpu-cycles",
+                "--duration",
+                "2.5",
+                "-g",
+                "-o",
+                DEVICE_PERF_DATA,
+            ]
+        );
+    }
+}
@@ -1,20 +1,26 @@
 use crate::error::NdkError;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+/// Deserialized from its ABI or rust triple like [`Target::from_abi_or_triple()`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[repr(u8)]
 pub enum Target {
-    #[serde(rename = "armv7-linux-androideabi")]
     ArmV7a = 1,
-    #[serde(rename = "aarch64-linux-android")]
     Arm64V8a = 2,
-    #[serde(rename = "i686-linux-android")]
     X86 = 3,
-    #[serde(rename = "x86_64-linux-android")]
     X86_64 = 4,
 }
 
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::from_abi_or_triple(&name).map_err(serde::de::Error::custom)
+    }
+}
+
 impl Target {
+    pub const ALL: [Self; 4] = [Self::ArmV7a, Self::Arm64V8a, Self::X86, Self::X86_64];
+
     /// Identifier used in the NDK to refer to the ABI
     pub fn android_abi(self) -> &'static str {
         match self {
@@ -57,6 +63,18 @@ impl Target {
         }
     }
 
+    /// Returns `Target` for an ABI like `arm64-v8a` or a rust triple like
+    /// `aarch64-linux-android`, ignoring case.
+    pub fn from_abi_or_triple(name: &str) -> Result<Self, NdkError> {
+        Self::ALL
+            .into_iter()
+            .find(|target| {
+                target.android_abi().eq_ignore_ascii_case(name)
+                    || target.rust_triple().eq_ignore_ascii_case(name)
+            })
+            .ok_or_else(|| NdkError::UnknownTarget(name.to_string()))
+    }
+
     // Returns the triple NDK provided LLVM
     pub fn ndk_llvm_triple(self) -> &'static str {
         match self {
@@ -77,3 +95,49 @@ impl Target {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_or_triple() {
+        for target in Target::ALL {
+            for name in [target.android_abi(), target.rust_triple()] {
+                assert_eq!(Target::from_abi_or_triple(name).unwrap(), target);
+                let upper = name.to_uppercase();
+                assert_eq!(Target::from_abi_or_triple(&upper).unwrap(), target);
+            }
+        }
+        assert_eq!(
+            Target::from_abi_or_triple("Arm64-V8a").unwrap(),
+            Target::Arm64V8a
+        );
+
+        for name in [
+            "",
+            "arm64",
+            "armv7a-linux-androideabi",
+            "x86-64",
+            "aarch64-apple-darwin",
+        ] {
+            let error = Target::from_abi_or_triple(name).unwrap_err();
+            assert!(matches!(&error, NdkError::UnknownTarget(n) if n == name));
+        }
+        assert_eq!(
+            Target::from_abi_or_triple("mips").unwrap_err().to_string(),
+            "Unknown target `mips`, expected one of `armeabi-v7a`/`armv7-linux-androideabi`, \
+            `arm64-v8a`/`aarch64-linux-android`, `x86`/`i686-linux-android`, \
+            `x86_64`/`x86_64-linux-android`"
+        );
+    }
+
+    #[test]
+    fn deserialize() {
+        let targets: Vec<Target> =
+            serde_json::from_str(r#"["arm64-v8a", "ARMV7-LINUX-ANDROIDEABI", "x86_64"]"#).unwrap();
+        assert_eq!(targets, [Target::Arm64V8a, Target::ArmV7a, Target::X86_64]);
+        let error = serde_json::from_str::<Target>(r#""riscv64""#).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown target `riscv64`"));
+    }
+}
@@ -0,0 +1,148 @@
+//! Records when every [`Phase`](crate::util::Phase) and every command run through
+//! [`output_error()`](crate::util::output_error) and its siblings started and how long it
+//! took, once enabled through [`enable()`].
+
+use crate::util::command_line;
+use serde::Serialize;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The start of the recording and what was recorded since, [`None`] until [`enable()`].
+static RECORDING: Mutex<Option<(Instant, Vec<Timing>)>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimingKind {
+    Phase,
+    Command,
+}
+
+/// A [`Phase`](crate::util::Phase) or command that ran.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Timing {
+    pub kind: TimingKind,
+    /// The name of the phase, or the file name of the program
+    pub name: String,
+    /// Hash of the command line of a command, with secrets redacted, to tell repeated
+    /// runs of the same program apart without recording its arguments
+    pub args_hash: Option<String>,
+    /// Seconds since [`enable()`]
+    pub start: f64,
+    pub duration: f64,
+    /// Whether the phase was finished or the command exited successfully
+    pub success: bool,
+    /// The exit code of a command, [`None`] if it was killed, timed out or could not be
+    /// started
+    pub exit_code: Option<i32>,
+}
+
+/// Starts recording, discarding what was recorded before.
+pub fn enable() {
+    *RECORDING.lock().unwrap() = Some((Instant::now(), vec![]));
+}
+
+/// Stops recording and returns what was recorded in the order it started, or [`None`]
+/// if the recording was never enabled.
+pub fn take() -> Option<Vec<Timing>> {
+    let (_, mut timings) = RECORDING.lock().unwrap().take()?;
+    timings.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Some(timings)
+}
+
+/// Whether [`enable()`] was called, for callers that would otherwise measure in vain.
+pub(crate) fn is_enabled() -> bool {
+    RECORDING.lock().unwrap().is_some()
+}
+
+pub(crate) fn record_phase(name: &str, start: Instant, finished: bool) {
+    record(
+        start,
+        TimingKind::Phase,
+        name.to_string(),
+        None,
+        finished,
+        None,
+    );
+}
+
+/// Records `command`, which started at `start` and exited with `status`, or failed to
+/// start or time out without one.
+pub(crate) fn record_command(command: &Command, start: Instant, status: Option<ExitStatus>) {
+    let program = command.get_program();
+    let name = Path::new(program)
+        .file_name()
+        .unwrap_or(program)
+        .to_string_lossy()
+        .into_owned();
+    record(
+        start,
+        TimingKind::Command,
+        name,
+        Some(format!("{:016x}", fnv1a(command_line(command).as_bytes()))),
+        status.is_some_and(|status| status.success()),
+        status.and_then(|status| status.code()),
+    );
+}
+
+/// Adds what started at `start` and ends now, if recording.
+fn record(
+    start: Instant,
+    kind: TimingKind,
+    name: String,
+    args_hash: Option<String>,
+    success: bool,
+    exit_code: Option<i32>,
+) {
+    if let Some((origin, timings)) = &mut *RECORDING.lock().unwrap() {
+        timings.push(Timing {
+            kind,
+            name,
+            args_hash,
+            start: start.saturating_duration_since(*origin).as_secs_f64(),
+            duration: start.elapsed().as_secs_f64(),
+            success,
+            exit_code,
+        });
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{Phase, output_error};
+
+    #[test]
+    fn record() {
+        enable();
+        Phase::start("timed phase").finish();
+        drop(Phase::start("failed phase"));
+        let mut command = Command::new(if cfg!(windows) { "cmd" } else { "true" });
+        if cfg!(windows) {
+            command.args(["/C", "exit 0"]);
+        }
+        output_error(command, None).unwrap();
+        let timings = take().unwrap();
+        assert!(take().is_none());
+
+        let phase = |name| timings.iter().find(|t| t.name == name).unwrap();
+        assert_eq!(phase("timed phase").kind, TimingKind::Phase);
+        assert!(phase("timed phase").success);
+        assert!(!phase("failed phase").success);
+        // Other tests may run commands meanwhile
+        let command = timings
+            .iter()
+            .find(|t| t.kind == TimingKind::Command && ["true", "cmd"].contains(&&*t.name))
+            .unwrap();
+        assert!(command.success);
+        assert_eq!(command.exit_code, Some(0));
+        assert_eq!(command.args_hash.as_ref().unwrap().len(), 16);
+    }
+}
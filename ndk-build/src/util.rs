@@ -1,12 +1,24 @@
 use std::{
-    env::var,
-    io::{self, IsTerminal, Read, stderr},
-    process::{Command, Stdio},
-    sync::{Arc, Mutex},
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    io::{self, IsTerminal, Read, Write, stderr},
+    path::{Path, PathBuf},
+    process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering},
+    },
     thread::spawn,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use crate::env::{ALWAYS_COLOR, DISPLAY, NO_COLOR, WAYLAND_DISPLAY};
 use crate::error::NdkError;
+use crate::timings;
+
+mod managed_child;
+
+pub use managed_child::ManagedChild;
 
 #[derive(PartialEq, Eq)]
 enum Stream {
@@ -22,40 +34,76 @@ impl Output {
         self.output.push((stream, data));
     }
 
-    pub fn stdout(&self) -> Vec<u8> {
-        let len = self
-            .output
-            .iter()
-            .filter(|x| x.0 == Stream::Stdout)
-            .map(|x| x.1.len())
-            .sum();
-        let mut val = Vec::with_capacity(len);
-        for x in self
-            .output
-            .iter()
-            .filter(|x| x.0 == Stream::Stdout)
-            .map(|x| &x.1)
-        {
+    fn collect(&self, filter: impl Fn(&Stream) -> bool) -> Vec<u8> {
+        let chunks = || self.output.iter().filter(|x| filter(&x.0)).map(|x| &x.1);
+        let mut val = Vec::with_capacity(chunks().map(|x| x.len()).sum());
+        for x in chunks() {
             val.extend_from_slice(x);
         }
         val
     }
 
+    pub fn stdout(&self) -> Vec<u8> {
+        self.collect(|s| *s == Stream::Stdout)
+    }
+
     pub fn stderr(&self) -> Vec<u8> {
-        let len = self.output.iter().map(|x| x.1.len()).sum();
-        let mut val = Vec::with_capacity(len);
-        for x in self.output.iter().map(|x| &x.1) {
-            val.extend_from_slice(x);
-        }
-        val
+        self.collect(|s| *s == Stream::Stderr)
+    }
+
+    /// Both streams, in the order they were read.
+    pub fn combined(&self) -> Vec<u8> {
+        self.collect(|_| true)
+    }
+}
+
+/// The default of [`set_output_limit()`].
+pub const DEFAULT_OUTPUT_LIMIT: usize = 16 * 1024;
+
+static OUTPUT_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_OUTPUT_LIMIT);
+static LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets how many bytes of output [`output_error()`] embeds in [`NdkError::CmdFailed`].
+/// Only the end of longer output is kept.
+pub fn set_output_limit(bytes: usize) {
+    OUTPUT_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Sets where [`output_error()`] writes the full output of failed commands whose output
+/// exceeds [`set_output_limit()`].
+pub fn set_log_dir(dir: impl Into<PathBuf>) {
+    *LOG_DIR.lock().unwrap() = Some(dir.into());
+}
+
+/// Runs `command` with piped output, killing it once `timeout` elapses. With `stream`,
+/// the output is also copied to `stderr` as it is read. Recorded by [`timings`].
+fn run(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    stream: bool,
+) -> Result<(ExitStatus, Output), NdkError> {
+    let start = timings::is_enabled().then(Instant::now);
+    let result = run_untimed(command, timeout, stream);
+    if let Some(start) = start {
+        timings::record_command(
+            command,
+            start,
+            result.as_ref().ok().map(|(status, _)| *status),
+        );
     }
+    result
 }
 
-pub fn output_error(mut command: Command) -> Result<Vec<u8>, NdkError> {
+fn run_untimed(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    stream: bool,
+) -> Result<(ExitStatus, Output), NdkError> {
+    echo_command(command);
     command.stdin(Stdio::null());
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
-    let mut process = command.spawn()?;
+    let mut process = TrackedChild::spawn(command)?;
     let (Some(mut stdout), Some(mut stderr)) = (process.stdout.take(), process.stderr.take())
     else {
         unreachable!();
@@ -71,10 +119,15 @@ pub fn output_error(mut command: Command) -> Result<Vec<u8>, NdkError> {
                     match stdout.read(&mut buf) {
                         Err(why) => break Err(why),
                         Ok(0) => break Ok(()),
-                        Ok(l) => output
-                            .lock()
-                            .unwrap()
-                            .push(Stream::Stdout, buf[0..l].to_vec()),
+                        Ok(l) => {
+                            if stream {
+                                io::stderr().write_all(&buf[0..l])?;
+                            }
+                            output
+                                .lock()
+                                .unwrap()
+                                .push(Stream::Stdout, buf[0..l].to_vec())
+                        }
                     }
                 }
             }
@@ -87,35 +140,842 @@ pub fn output_error(mut command: Command) -> Result<Vec<u8>, NdkError> {
                     match stderr.read(&mut buf) {
                         Err(why) => break Err(why),
                         Ok(0) => break Ok(()),
-                        Ok(l) => output
-                            .lock()
-                            .unwrap()
-                            .push(Stream::Stderr, buf[0..l].to_vec()),
+                        Ok(l) => {
+                            if stream {
+                                io::stderr().write_all(&buf[0..l])?;
+                            }
+                            output
+                                .lock()
+                                .unwrap()
+                                .push(Stream::Stderr, buf[0..l].to_vec())
+                        }
                     }
                 }
             }
         }),
     );
 
+    let status = match timeout {
+        // The readers are not joined, processes spawned by the killed command may
+        // still hold on to its output
+        Some(timeout) => process
+            .wait_timeout(timeout)?
+            .ok_or_else(|| NdkError::Timeout {
+                command: command_line(command),
+                timeout,
+            })?,
+        None => process.wait()?,
+    };
     h1.join().map_err(|_| io::Error::other("join error"))??;
     h2.join().map_err(|_| io::Error::other("join error"))??;
 
-    if process.wait()?.success() {
-        Ok(output.lock().unwrap().stdout())
+    let output = std::mem::replace(&mut *output.lock().unwrap(), Output { output: vec![] });
+    Ok((status, output))
+}
+
+/// Like [`Command::output()`], but killing `command` once `timeout` elapses and
+/// interruptible through [`terminate_children()`].
+pub fn output_timeout(
+    command: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<std::process::Output, NdkError> {
+    let (status, output) = run(command, timeout, false)?;
+    Ok(std::process::Output {
+        status,
+        stdout: output.stdout(),
+        stderr: output.stderr(),
+    })
+}
+
+/// Runs `command` and returns its stdout, or [`NdkError::CmdFailed`] with its output if
+/// it fails. With a `timeout`, the command is killed once it elapses and
+/// [`NdkError::Timeout`] is returned.
+pub fn output_error(command: Command, timeout: Option<Duration>) -> Result<Vec<u8>, NdkError> {
+    checked_output(command, timeout, false, true)
+}
+
+/// Like [`output_error()`], but copies the output of `command` to `stderr` while it runs,
+/// unless [`Verbosity::Quiet`], e.g. for user-provided commands.
+pub fn stream_error(command: Command, timeout: Option<Duration>) -> Result<Vec<u8>, NdkError> {
+    checked_output(command, timeout, verbosity() > Verbosity::Quiet, true)
+}
+
+/// Like [`output_error()`], for commands whose stdout is meant for machines, like
+/// `cargo build --message-format=json`: only their stderr is echoed at
+/// [`Verbosity::Verbose`].
+pub fn output_messages(command: Command, timeout: Option<Duration>) -> Result<Vec<u8>, NdkError> {
+    checked_output(command, timeout, false, false)
+}
+
+fn checked_output(
+    mut command: Command,
+    timeout: Option<Duration>,
+    stream: bool,
+    echo_stdout: bool,
+) -> Result<Vec<u8>, NdkError> {
+    let (status, output) = run(&mut command, timeout, stream)?;
+    if status.success() {
+        if !stream && verbosity() >= Verbosity::Verbose {
+            let echo = if echo_stdout {
+                output.combined()
+            } else {
+                output.stderr()
+            };
+            io::stderr().write_all(&echo)?;
+        }
+        Ok(output.stdout())
     } else {
-        Err(NdkError::CmdFailed(
-            command,
-            io::Error::other(String::from_utf8_lossy(&output.lock().unwrap().stderr())),
+        let mut message = output.stderr();
+        // Some tools, like `aapt`, report errors on stdout
+        if message.iter().all(u8::is_ascii_whitespace) {
+            message = output.stdout();
+        }
+        let limit = OUTPUT_LIMIT.load(Ordering::Relaxed);
+        let log = (message.len() > limit)
+            .then(|| write_log(&command, &output.combined()))
+            .flatten();
+        Err(NdkError::cmd_failed(
+            &command,
+            Some(status),
+            io::Error::other(truncate_output(&message, limit, log.as_deref())),
         ))
     }
 }
 
+static CHILDREN: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+static TEMP_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+#[allow(clippy::type_complexity)]
+static CLEANUPS: Mutex<Vec<(u64, Box<dyn FnOnce() + Send>)>> = Mutex::new(Vec::new());
+static NEXT_CLEANUP: AtomicU64 = AtomicU64::new(0);
+
+/// A child process that is killed by [`terminate_children()`], e.g. on Ctrl-C.
+pub struct TrackedChild {
+    id: u32,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+impl TrackedChild {
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let mut child = command.spawn()?;
+        let tracked = Self {
+            id: child.id(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+        };
+        CHILDREN.lock().unwrap().push(child);
+        Ok(tracked)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn with_child<T>(&self, f: impl FnOnce(&mut Child) -> io::Result<T>) -> io::Result<T> {
+        let mut children = CHILDREN.lock().unwrap();
+        let child = children
+            .iter_mut()
+            .find(|child| child.id() == self.id)
+            .expect("tracked child is only removed on drop");
+        f(child)
+    }
+
+    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.with_child(Child::try_wait)
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        self.with_child(|child| {
+            child.kill()?;
+            child.wait().map(drop)
+        })
+    }
+
+    /// Polls for the exit status, as [`Child::wait()`] would block [`terminate_children()`].
+    pub fn wait(&self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Waits for the child to exit, or kills it and returns [`None`] once `timeout` elapses.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                self.kill()?;
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        CHILDREN
+            .lock()
+            .unwrap()
+            .retain(|child| child.id() != self.id);
+    }
+}
+
+/// Kills all running [`TrackedChild`]ren, and the process trees of [`ManagedChild`]ren
+/// that aren't detached.
+pub fn terminate_children() {
+    managed_child::terminate();
+    for child in CHILDREN.lock().unwrap().iter_mut() {
+        if let Ok(None) = child.try_wait() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A file or directory that [`remove_temp_paths()`] deletes while this guard is alive,
+/// such as an APK that is still being written.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        TEMP_PATHS.lock().unwrap().push(path.clone());
+        Self(path)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let mut paths = TEMP_PATHS.lock().unwrap();
+        if let Some(i) = paths.iter().position(|path| *path == self.0) {
+            paths.remove(i);
+        }
+    }
+}
+
+/// Deletes the paths of all live [`TempPath`]s, when the build is interrupted.
+pub fn remove_temp_paths() {
+    for path in TEMP_PATHS.lock().unwrap().iter() {
+        let _ = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+    }
+}
+
+/// Work that undoes a change outside the build directory, like a setting on a device.
+/// It is done when the guard is dropped, or by [`run_cleanups()`] while it is alive.
+pub struct Cleanup(u64);
+
+impl Cleanup {
+    pub fn new(cleanup: impl FnOnce() + Send + 'static) -> Self {
+        let id = NEXT_CLEANUP.fetch_add(1, Ordering::Relaxed);
+        CLEANUPS.lock().unwrap().push((id, Box::new(cleanup)));
+        Self(id)
+    }
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        let mut cleanups = CLEANUPS.lock().unwrap();
+        if let Some(i) = cleanups.iter().position(|(id, _)| *id == self.0) {
+            let (_, cleanup) = cleanups.remove(i);
+            // Not holding the lock, the cleanup may take a while
+            drop(cleanups);
+            cleanup();
+        }
+    }
+}
+
+/// Does the work of all live [`Cleanup`]s, when the run is interrupted.
+pub fn run_cleanups() {
+    let cleanups = std::mem::take(&mut *CLEANUPS.lock().unwrap());
+    for (_, cleanup) in cleanups {
+        cleanup();
+    }
+}
+
+/// Writes `output` of `command` to the directory set with [`set_log_dir()`], if any.
+fn write_log(command: &Command, output: &[u8]) -> Option<PathBuf> {
+    let dir = LOG_DIR.lock().unwrap().clone()?;
+    let program = Path::new(command.get_program());
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let path = dir.join(format!(
+        "{}-{}.log",
+        program.file_stem()?.to_string_lossy(),
+        millis
+    ));
+    std::fs::create_dir_all(&dir).ok()?;
+    let mut contents = command_line(command).into_bytes();
+    contents.extend_from_slice(b"\n\n");
+    contents.extend_from_slice(output);
+    std::fs::write(&path, contents).ok()?;
+    Some(path)
+}
+
+/// Keeps the last `limit` bytes of `output`, noting how much was cut and where the full
+/// `log` is.
+fn truncate_output(output: &[u8], limit: usize, log: Option<&Path>) -> String {
+    if output.len() <= limit {
+        return String::from_utf8_lossy(output).into_owned();
+    }
+    let omitted = output.len() - limit;
+    let note = match log {
+        Some(log) => format!(
+            "[{} bytes truncated, full output in `{}`]",
+            omitted,
+            log.display()
+        ),
+        None => format!("[{} bytes truncated]", omitted),
+    };
+    format!("{}\n{}", note, String::from_utf8_lossy(&output[omitted..]))
+}
+
 pub fn color() -> bool {
-    if var("ALWAYS_COLOR").is_ok() {
+    if ALWAYS_COLOR.get_os().is_some() {
         true
-    } else if var("NO_COLOR").is_ok() {
+    } else if NO_COLOR.get_os().is_some() {
         false
     } else {
         stderr().is_terminal()
     }
 }
+
+/// How much `ndk-build` reports while running, see [`set_verbosity()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors and the final result
+    Quiet,
+    /// Status lines and the duration of every [`Phase`]
+    #[default]
+    Normal,
+    /// Also the output of commands that succeed
+    Verbose,
+    /// Also every command line before it runs, see [`command_line()`]
+    VeryVerbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Sets the verbosity for the whole process, [`Verbosity::Normal`] by default.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::VeryVerbose,
+    }
+}
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+const CYAN: &str = "36";
+
+/// `text` in bold `ansi_color` if [`color()`].
+pub(crate) fn paint(text: &str, ansi_color: &str) -> String {
+    if color() {
+        format!("\x1b[1;{}m{}\x1b[0m", ansi_color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Right-aligns `verb` like `cargo` status lines, in bold `ansi_color` if [`color()`].
+fn label(verb: &str, ansi_color: &str) -> String {
+    if color() {
+        format!("\x1b[1;{}m{:>12}\x1b[0m", ansi_color, verb)
+    } else {
+        format!("{:>12}", verb)
+    }
+}
+
+/// Prints a `cargo` style status line to `stderr`, unless [`Verbosity::Quiet`].
+pub fn status(verb: &str, message: impl Display) {
+    if verbosity() > Verbosity::Quiet {
+        eprintln!("{} {}", label(verb, GREEN), message);
+    }
+}
+
+/// Times a step of the build. Dropping it reports the step as finished with its duration
+/// if [`Phase::finish()`] was called, or as failed otherwise, e.g. when returning early
+/// with an error.
+#[must_use = "the phase is reported when it is dropped"]
+pub struct Phase {
+    name: String,
+    start: Instant,
+    finished: bool,
+}
+
+impl Phase {
+    pub fn start(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        timings::record_phase(&self.name, self.start, self.finished);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if self.finished {
+            status("Finished", format_args!("{} in {:.2}s", self.name, elapsed));
+        } else if verbosity() > Verbosity::Quiet {
+            eprintln!(
+                "{} {} after {:.2}s",
+                label("Failed", RED),
+                self.name,
+                elapsed
+            );
+        }
+    }
+}
+
+/// Flags whose value is a password, as passed to `apksigner` and `keytool`.
+const PASSWORD_FLAGS: &[&str] = &[
+    "--ks-pass",
+    "--key-pass",
+    "-storepass",
+    "-keypass",
+    "-srcstorepass",
+    "-deststorepass",
+];
+
+/// The values of all [`SecretArg`]s created so far.
+static SECRETS: Mutex<Vec<OsString>> = Mutex::new(Vec::new());
+
+/// A command argument, or environment variable value, that is passed to the child process
+/// as is but rendered as `***` by [`command_line()`], and thus in `-vv` output and
+/// [`NdkError::CmdFailed`].
+pub struct SecretArg(OsString);
+
+impl SecretArg {
+    pub fn new(value: impl Into<OsString>) -> Self {
+        let value = value.into();
+        let mut secrets = SECRETS.lock().unwrap();
+        if !secrets.contains(&value) {
+            secrets.push(value.clone());
+        }
+        Self(value)
+    }
+}
+
+impl AsRef<OsStr> for SecretArg {
+    fn as_ref(&self) -> &OsStr {
+        &self.0
+    }
+}
+
+fn is_secret(value: &OsStr) -> bool {
+    SECRETS.lock().unwrap().iter().any(|s| s == value)
+}
+
+/// Renders `command` with the environment it sets, like it would be typed in a shell.
+/// [`SecretArg`]s and the values of password flags are replaced by `***`.
+pub fn command_line(command: &Command) -> String {
+    let mut words = command
+        .get_envs()
+        .filter_map(|(key, value)| {
+            let value = value?;
+            let value = if is_secret(value) {
+                "***".to_string()
+            } else {
+                shell_word(&value.to_string_lossy())
+            };
+            Some(format!("{}={}", key.to_string_lossy(), value))
+        })
+        .collect::<Vec<_>>();
+    words.push(shell_word(&command.get_program().to_string_lossy()));
+    let mut is_password = false;
+    for arg in command.get_args() {
+        let lossy = arg.to_string_lossy();
+        words.push(if lossy.starts_with("pass:") {
+            // The `apksigner` password source that holds the password itself
+            "pass:***".to_string()
+        } else if is_password || is_secret(arg) {
+            "***".to_string()
+        } else {
+            shell_word(&lossy)
+        });
+        is_password = PASSWORD_FLAGS.contains(&lossy.as_ref());
+    }
+    words.join(" ")
+}
+
+fn shell_word(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=+./:,@%^".contains(c))
+    {
+        word.to_string()
+    } else {
+        // Also escapes control characters, like the separators of `CARGO_ENCODED_RUSTFLAGS`
+        format!("{:?}", word)
+    }
+}
+
+/// Prints `command` to `stderr` before it runs at [`Verbosity::VeryVerbose`].
+pub fn echo_command(command: &Command) {
+    if verbosity() >= Verbosity::VeryVerbose {
+        eprintln!("{} {}", label("Running", CYAN), command_line(command));
+    }
+}
+
+/// Whether the commands and file operations of a build are carried out, see
+/// [`NdkOptions::dry_run`](crate::ndk::NdkOptions::dry_run).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exec {
+    #[default]
+    Run,
+    /// Print every command and file operation to stdout instead of carrying it out
+    DryRun,
+}
+
+impl Exec {
+    pub fn is_dry_run(self) -> bool {
+        self == Self::DryRun
+    }
+
+    /// Runs [`output_error()`], or prints `command` with the directory it would run in
+    /// and returns empty output.
+    pub fn output_error(
+        self,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, NdkError> {
+        match self {
+            Self::Run => output_error(command, timeout),
+            Self::DryRun => {
+                self.plan("run", planned_command(&command));
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Like [`Exec::output_error()`], for [`output_messages()`].
+    pub fn output_messages(
+        self,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, NdkError> {
+        match self {
+            Self::Run => output_messages(command, timeout),
+            Self::DryRun => self.output_error(command, timeout),
+        }
+    }
+
+    /// Like [`Exec::output_error()`], for [`stream_error()`].
+    pub fn stream_error(
+        self,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, NdkError> {
+        match self {
+            Self::Run => stream_error(command, timeout),
+            Self::DryRun => self.output_error(command, timeout),
+        }
+    }
+
+    pub fn copy(self, from: &Path, to: &Path) -> Result<(), NdkError> {
+        match self {
+            Self::Run => {
+                std::fs::copy(from, to).map_err(|e| NdkError::IoPathError(to.to_owned(), e))?;
+            }
+            Self::DryRun => self.plan(
+                "copy",
+                format_args!("{} -> {}", from.display(), to.display()),
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn write(self, path: &Path, contents: impl AsRef<[u8]>) -> Result<(), NdkError> {
+        match self {
+            Self::Run => std::fs::write(path, contents)
+                .map_err(|e| NdkError::IoPathError(path.to_owned(), e))?,
+            Self::DryRun => self.plan("write", path.display()),
+        }
+        Ok(())
+    }
+
+    /// Not printed, the directories are implied by the files written into them.
+    pub fn create_dir_all(self, path: &Path) -> Result<(), NdkError> {
+        if self == Self::Run {
+            std::fs::create_dir_all(path).map_err(|e| NdkError::IoPathError(path.to_owned(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Removes the file or directory at `path`, if there is one.
+    pub fn remove(self, path: &Path) -> Result<(), NdkError> {
+        if !path.exists() {
+            return Ok(());
+        }
+        match self {
+            Self::Run if path.is_dir() => std::fs::remove_dir_all(path),
+            Self::Run => std::fs::remove_file(path),
+            Self::DryRun => {
+                self.plan("rm", path.display());
+                return Ok(());
+            }
+        }
+        .map_err(|e| NdkError::IoPathError(path.to_owned(), e))
+    }
+
+    /// Prints a step of a dry run, like `apk` followed by the path of the apk it would
+    /// build. Does nothing when running.
+    pub fn plan(self, step: &str, detail: impl Display) {
+        if self == Self::DryRun {
+            println!("{:<5} {}", step, detail);
+        }
+    }
+}
+
+/// [`command_line()`], followed by the directory `command` runs in if it sets one.
+fn planned_command(command: &Command) -> String {
+    match command.get_current_dir() {
+        Some(dir) => format!("{} (in {})", command_line(command), dir.display()),
+        None => command_line(command),
+    }
+}
+
+/// Formats a number of bytes with a binary unit, like `1.50 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+/// Shows `file` in the file manager of the desktop: selected in Explorer and the Finder,
+/// or by opening its directory with `xdg-open` elsewhere.
+///
+/// Fails without a desktop session to show it in, like on headless CI machines, or when
+/// the opener is missing or fails.
+pub fn show_in_file_manager(file: &Path) -> Result<(), NdkError> {
+    let has_display = DISPLAY.get_os().is_some() || WAYLAND_DISPLAY.get_os().is_some();
+    let mut command = file_manager_command(file, has_display)?;
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // Only waits for the opener, the file manager it starts keeps running
+    let status = command
+        .status()
+        .map_err(|e| NdkError::cmd_failed(&command, None, e))?;
+    // Explorer exits with 1 also when it opened the window
+    if status.success() || cfg!(windows) {
+        Ok(())
+    } else {
+        Err(NdkError::cmd_failed(
+            &command,
+            Some(status),
+            io::Error::other(""),
+        ))
+    }
+}
+
+/// The platform's command to show `file`, where `has_display` tells whether a desktop
+/// session runs on platforms without a builtin file manager.
+fn file_manager_command(file: &Path, has_display: bool) -> Result<Command, NdkError> {
+    if cfg!(windows) {
+        let mut explorer = Command::new("explorer");
+        let mut select = OsString::from("/select,");
+        select.push(file);
+        explorer.arg(select);
+        Ok(explorer)
+    } else if cfg!(target_os = "macos") {
+        let mut open = Command::new("open");
+        open.arg("-R").arg(file);
+        Ok(open)
+    } else if has_display {
+        let mut xdg_open = Command::new("xdg-open");
+        xdg_open.arg(file.parent().unwrap_or(file));
+        Ok(xdg_open)
+    } else {
+        Err(NdkError::NoDesktopSession)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn cleanups() {
+        let (sender, done) = std::sync::mpsc::channel();
+        let cleanup = {
+            let sender = sender.clone();
+            Cleanup::new(move || sender.send("dropped").unwrap())
+        };
+        let interrupted = Cleanup::new(move || sender.send("interrupted").unwrap());
+        drop(cleanup);
+        assert_eq!(done.try_recv(), Ok("dropped"));
+        run_cleanups();
+        assert_eq!(done.try_recv(), Ok("interrupted"));
+        // Already done
+        drop(interrupted);
+        assert!(done.try_recv().is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.50 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 + 1024 * 1024 / 4), "5.25 MiB");
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn file_manager() {
+        let apk = Path::new("/app/target/release/apk/app.apk");
+        let xdg_open = file_manager_command(apk, true).unwrap();
+        assert_eq!(command_line(&xdg_open), "xdg-open /app/target/release/apk");
+        assert!(matches!(
+            file_manager_command(apk, false),
+            Err(NdkError::NoDesktopSession)
+        ));
+    }
+
+    #[test]
+    fn output_streams() {
+        let mut output = Output { output: vec![] };
+        output.push(Stream::Stdout, b"out1 ".to_vec());
+        output.push(Stream::Stderr, b"err1 ".to_vec());
+        output.push(Stream::Stdout, b"out2".to_vec());
+        output.push(Stream::Stderr, b"err2".to_vec());
+        assert_eq!(output.stdout(), b"out1 out2");
+        assert_eq!(output.stderr(), b"err1 err2");
+        assert_eq!(output.combined(), b"out1 err1 out2err2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_command() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("600");
+        let start = Instant::now();
+        let error = output_error(sleep, Some(Duration::from_millis(100))).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(
+            matches!(&error, NdkError::Timeout { command, .. } if command == "sleep 600"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn truncated_output() {
+        assert_eq!(truncate_output(b"0123456789", 10, None), "0123456789");
+        assert_eq!(
+            truncate_output(b"0123456789", 9, None),
+            "[1 bytes truncated]\n123456789"
+        );
+        assert_eq!(
+            truncate_output(b"0123456789", 4, Some(Path::new("logs/aapt-1.log"))),
+            "[6 bytes truncated, full output in `logs/aapt-1.log`]\n6789"
+        );
+    }
+
+    #[test]
+    fn secret_args() {
+        let mut cmd = Command::new("tool");
+        cmd.env("TOOL_PASSWORD", SecretArg::new("hunter2"))
+            .arg("--password-file")
+            .arg(SecretArg::new("/secrets/hunter3"));
+        assert_eq!(
+            command_line(&cmd),
+            "TOOL_PASSWORD=*** tool --password-file ***"
+        );
+        // Only reaches the child process
+        assert_eq!(cmd.get_args().last().unwrap(), "/secrets/hunter3");
+    }
+
+    #[test]
+    fn redacted_command_line() {
+        let mut apksigner = Command::new("apksigner");
+        apksigner
+            .arg("sign")
+            .arg("--ks")
+            .arg("/path with space/release.keystore")
+            .arg("--ks-pass")
+            .arg("pass:hunter2")
+            .arg("app.apk");
+        assert_eq!(
+            command_line(&apksigner),
+            r#"apksigner sign --ks "/path with space/release.keystore" --ks-pass pass:*** app.apk"#
+        );
+
+        let mut keytool = Command::new("keytool");
+        keytool
+            .env("JAVA_HOME", "/opt/jdk")
+            .arg("-storepass")
+            .arg("hunter2")
+            .arg("-keypass")
+            .arg("hunter2")
+            .arg("-alias")
+            .arg("androiddebugkey");
+        assert_eq!(
+            command_line(&keytool),
+            "JAVA_HOME=/opt/jdk keytool -storepass *** -keypass *** -alias androiddebugkey"
+        );
+    }
+
+    #[test]
+    fn dry_run() {
+        let tmp = TestDir::new("dry-run");
+        let dir = tmp.join("apk");
+        let exec = Exec::DryRun;
+        let mut command = Command::new("false");
+        command
+            .current_dir(&dir)
+            .env("KEY", SecretArg::new("dry-run secret"));
+        assert_eq!(
+            planned_command(&command),
+            format!("KEY=*** false (in {})", dir.display())
+        );
+        assert!(exec.output_error(command, None).unwrap().is_empty());
+
+        exec.create_dir_all(&dir).unwrap();
+        exec.write(&dir.join("AndroidManifest.xml"), "<manifest/>")
+            .unwrap();
+        exec.copy(Path::new("libmain.so"), &dir.join("libmain.so"))
+            .unwrap();
+        assert!(!dir.exists());
+
+        Exec::Run.create_dir_all(&dir).unwrap();
+        exec.remove(&dir).unwrap();
+        assert!(dir.exists());
+        Exec::Run.remove(&dir).unwrap();
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,331 @@
+//! [`ManagedChild`]: a child process whose whole process tree is killed with it, even
+//! when this process is interrupted before it gets to clean up.
+//!
+//! On Unix the child leads a process group of its own, and on Linux it additionally gets
+//! `SIGKILL` through `PR_SET_PDEATHSIG` once this process dies. On Windows the child is
+//! placed in a Job Object, which kills all of its processes when the last handle to it
+//! is closed, including by the exit of this process.
+
+use std::io;
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus};
+use std::sync::Mutex;
+
+/// The trees of all live, attached [`ManagedChild`]ren, killed by [`terminate()`].
+static TREES: Mutex<Vec<sys::Tree>> = Mutex::new(Vec::new());
+
+/// A child process that takes the processes it spawns down with it.
+///
+/// Children from [`ManagedChild::spawn()`] are killed when the handle is dropped, by
+/// [`super::terminate_children()`] on Ctrl-C, and when this process dies. Children from
+/// [`ManagedChild::spawn_detached()`] outlive this process, and are only killed through
+/// [`ManagedChild::kill()`].
+pub struct ManagedChild {
+    child: Child,
+    tree: sys::Tree,
+    detached: bool,
+    /// Whether the child was waited for, after which the id of its process group may be
+    /// reused by an unrelated process and must not be signalled anymore.
+    reaped: bool,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+impl ManagedChild {
+    /// Spawns `command` with its process tree bound to the lifetime of the handle.
+    ///
+    /// On Linux the child is killed when the spawning thread exits, so spawn long-running
+    /// children from the main thread. The child doesn't receive Ctrl-C from the terminal
+    /// itself and should not read from it.
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let child = Self::spawn_with(command, false)?;
+        TREES.lock().unwrap().push(child.tree.clone());
+        Ok(child)
+    }
+
+    /// Spawns `command` in a process tree of its own that keeps running after this
+    /// process exits or is interrupted with Ctrl-C.
+    pub fn spawn_detached(command: &mut Command) -> io::Result<Self> {
+        Self::spawn_with(command, true)
+    }
+
+    fn spawn_with(command: &mut Command, detached: bool) -> io::Result<Self> {
+        sys::configure(command, detached);
+        let mut child = command.spawn()?;
+        let tree = sys::Tree::new(&child, detached);
+        Ok(Self {
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            child,
+            tree,
+            detached,
+            reaped: false,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait()?;
+        if status.is_some() {
+            self.reap();
+        }
+        Ok(status)
+    }
+
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        self.reap();
+        Ok(status)
+    }
+
+    /// Kills the child and every process it spawned, and waits for the child to exit.
+    /// Does nothing once the child was waited for, including by an earlier `kill()`.
+    pub fn kill(&mut self) -> io::Result<()> {
+        if self.reaped {
+            return Ok(());
+        }
+        // Kill the tree first, the child may have exited and left its children behind
+        self.tree.kill();
+        match self.child.kill() {
+            // The child exited already
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => {}
+            result => result?,
+        }
+        self.wait().map(drop)
+    }
+
+    /// Records that the child was waited for, and stops [`terminate()`] from signalling
+    /// its tree.
+    fn reap(&mut self) {
+        self.reaped = true;
+        TREES.lock().unwrap().retain(|tree| *tree != self.tree);
+    }
+}
+
+impl Drop for ManagedChild {
+    fn drop(&mut self) {
+        if self.detached {
+            return;
+        }
+        let _ = self.kill();
+        TREES.lock().unwrap().retain(|tree| *tree != self.tree);
+        self.tree.close();
+    }
+}
+
+/// Kills the trees of all live [`ManagedChild`]ren from [`ManagedChild::spawn()`].
+pub(super) fn terminate() {
+    for tree in TREES.lock().unwrap().iter() {
+        tree.kill();
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+
+    /// The id of the process group that the child leads.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(super) struct Tree(pub(super) libc::pid_t);
+
+    pub(super) fn configure(command: &mut Command, detached: bool) {
+        command.process_group(0);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if !detached {
+            let parent = std::process::id();
+            // SAFETY: only async-signal-safe functions are called between `fork()` and
+            // `exec()`
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    // The parent died before the signal was set up
+                    if libc::getppid() as u32 != parent {
+                        libc::_exit(1);
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = detached;
+    }
+
+    impl Tree {
+        pub(super) fn new(child: &Child, _detached: bool) -> Self {
+            Self(child.id() as libc::pid_t)
+        }
+
+        pub(super) fn kill(&self) {
+            // SAFETY: signals the process group, failing with `ESRCH` once it is empty
+            unsafe {
+                libc::kill(-self.0, libc::SIGKILL);
+            }
+        }
+
+        pub(super) fn close(&self) {}
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::os::windows::io::AsRawHandle;
+    use std::os::windows::process::CommandExt;
+    use std::process::{Child, Command};
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_BREAKAWAY_OK,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation, SetInformationJobObject, TerminateJobObject,
+    };
+
+    /// Keeps Ctrl-C of the console away from detached children.
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    /// The Job Object holding the child, if one could be set up: assigning fails in
+    /// job environments that forbid nested jobs and breakaway. The handle is stored as
+    /// an address, as raw handles aren't `Send`.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub(super) struct Tree(Option<usize>);
+
+    pub(super) fn configure(command: &mut Command, detached: bool) {
+        if detached {
+            command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+    }
+
+    impl Tree {
+        pub(super) fn new(child: &Child, detached: bool) -> Self {
+            // SAFETY: `job` is a valid handle until it is closed, and `info` outlives the
+            // call reading it
+            unsafe {
+                let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if job.is_null() {
+                    return Self(None);
+                }
+                let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+                // Daemons like the `adb` server may leave the job when they ask for it
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_BREAKAWAY_OK;
+                if !detached {
+                    info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                }
+                let configured = SetInformationJobObject(
+                    job,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const _,
+                    std::mem::size_of_val(&info) as u32,
+                ) != 0;
+                if !configured
+                    || AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE) == 0
+                {
+                    CloseHandle(job);
+                    return Self(None);
+                }
+                Self(Some(job as usize))
+            }
+        }
+
+        pub(super) fn kill(&self) {
+            if let Some(job) = self.0 {
+                // SAFETY: the handle is only closed by `close()`, after the last `kill()`
+                unsafe {
+                    TerminateJobObject(job as HANDLE, 1);
+                }
+            }
+        }
+
+        pub(super) fn close(&self) {
+            if let Some(job) = self.0 {
+                // SAFETY: called once, when the [`super::ManagedChild`] is dropped
+                unsafe {
+                    CloseHandle(job as HANDLE);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    /// Whether `pid` is running, counting zombies that no init process reaped yet as dead.
+    fn alive(pid: libc::pid_t) -> bool {
+        // SAFETY: signal 0 only checks for the existence of the process
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        let zombie = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| stat.rsplit(") ").next().is_some_and(|s| s.starts_with('Z')));
+        exists && !zombie
+    }
+
+    #[test]
+    fn kills_process_group_on_drop() {
+        let mut sh = Command::new("sh");
+        sh.arg("-c")
+            .arg("sleep 60 & echo $!; wait")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+        let mut child = ManagedChild::spawn(&mut sh).unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let sleep: libc::pid_t = line.trim().parse().unwrap();
+        let sh = child.id() as libc::pid_t;
+        assert!(alive(sleep));
+        assert_eq!(
+            TREES.lock().unwrap().iter().filter(|t| t.0 == sh).count(),
+            1
+        );
+
+        drop(child);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while alive(sleep) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!alive(sleep), "grandchild {} survived", sleep);
+        assert!(!TREES.lock().unwrap().iter().any(|t| t.0 == sh));
+    }
+
+    #[test]
+    fn no_signal_after_reap() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("60").stdin(Stdio::null());
+        let mut child = ManagedChild::spawn(&mut sleep).unwrap();
+        let pid = child.id() as libc::pid_t;
+        child.kill().unwrap();
+        assert!(child.reaped);
+        assert!(!alive(pid));
+        // The process group id may belong to another process by now
+        assert!(!TREES.lock().unwrap().iter().any(|t| t.0 == pid));
+        child.kill().unwrap();
+
+        let mut sh = Command::new("sh");
+        sh.arg("-c").arg("exit 3").stdin(Stdio::null());
+        let mut child = ManagedChild::spawn(&mut sh).unwrap();
+        let pid = child.id() as libc::pid_t;
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        assert!(child.reaped);
+        assert!(!TREES.lock().unwrap().iter().any(|t| t.0 == pid));
+    }
+
+    #[test]
+    fn detached() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("60").stdin(Stdio::null());
+        let mut child = ManagedChild::spawn_detached(&mut sleep).unwrap();
+        let pid = child.id() as libc::pid_t;
+        // Left alone by `terminate()`
+        assert!(!TREES.lock().unwrap().iter().any(|t| t.0 == pid));
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        assert!(!alive(pid));
+    }
+}
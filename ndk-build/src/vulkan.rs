@@ -0,0 +1,171 @@
+//! The Khronos Vulkan validation layer, packaged into the apk and enabled for the app
+//! through the GPU debug layer settings of Android 9+, see
+//! <https://developer.android.com/ndk/guides/graphics/validation-layer>.
+
+use crate::error::NdkError;
+use crate::ndk::Ndk;
+use crate::target::Target;
+use crate::util::{Cleanup, status};
+use std::path::{Path, PathBuf};
+
+/// The name the Vulkan loader knows the validation layer by.
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// The library of [`VALIDATION_LAYER`] in `lib/<abi>/` of the apk.
+pub const VALIDATION_LAYER_LIB: &str = "libVkLayer_khronos_validation.so";
+
+/// Android 9 is the first to load layers from the library directory of debuggable apps.
+pub const GPU_DEBUG_LAYERS_MIN_API_LEVEL: u32 = 28;
+
+impl Ndk {
+    /// Finds [`VALIDATION_LAYER_LIB`] for `target` in `<dir>/<abi>/`, the layout of the
+    /// `android-binaries-<version>.zip` releases of the layers. Without a `dir`, the copy
+    /// that NDKs up to r22 shipped in `sources/third_party/vulkan/` is used.
+    pub fn vulkan_validation_layer(
+        &self,
+        dir: Option<&Path>,
+        target: Target,
+    ) -> Result<PathBuf, NdkError> {
+        let abi_dir = match dir {
+            Some(dir) => dir.join(target.android_abi()),
+            None => self
+                .ndk()
+                .join("sources")
+                .join("third_party")
+                .join("vulkan")
+                .join("src")
+                .join("build-android")
+                .join("jniLibs")
+                .join(target.android_abi()),
+        };
+        let layer = abi_dir.join(VALIDATION_LAYER_LIB);
+        if layer.exists() {
+            Ok(layer)
+        } else {
+            Err(NdkError::VulkanValidationLayerNotFound {
+                target: target.android_abi(),
+                dir: abi_dir,
+            })
+        }
+    }
+
+    /// Makes the Vulkan loader of the debuggable `package` load `layers` from its library
+    /// directory, from its next launch on. The settings of the device are restored when
+    /// the returned guard is dropped, also when the run is interrupted.
+    pub fn enable_gpu_debug_layers(
+        &self,
+        device_serial: Option<&str>,
+        package: &str,
+        layers: &[&str],
+    ) -> Result<Cleanup, NdkError> {
+        let mut previous = Vec::new();
+        for (key, value) in gpu_debug_settings(package, layers) {
+            let before = if self.exec().is_dry_run() {
+                None
+            } else {
+                let value = self.shell(device_serial, &["settings", "get", "global", key])?;
+                // Unset settings read as `null`
+                Some(value).filter(|value| !value.is_empty() && value != "null")
+            };
+            self.put_global_setting(device_serial, key, Some(&value))?;
+            previous.push((key, before));
+        }
+        let ndk = self.clone();
+        let device_serial = device_serial.map(str::to_owned);
+        Ok(Cleanup::new(move || {
+            for (key, value) in previous.into_iter().rev() {
+                if let Err(e) =
+                    ndk.put_global_setting(device_serial.as_deref(), key, value.as_deref())
+                {
+                    eprintln!(
+                        "Warning: failed to restore `settings global {}`: {}",
+                        key, e
+                    );
+                }
+            }
+            status("Restored", "the GPU debug layer settings of the device");
+        }))
+    }
+
+    /// Sets the global setting `key` to `value`, or deletes it for [`None`].
+    fn put_global_setting(
+        &self,
+        device_serial: Option<&str>,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), NdkError> {
+        let mut adb = self.adb(device_serial)?;
+        adb.arg("shell").arg("settings");
+        match value {
+            Some(value) => adb.arg("put").arg("global").arg(key).arg(value),
+            None => adb.arg("delete").arg("global").arg(key),
+        };
+        self.exec().output_error(adb, self.adb_timeout())?;
+        Ok(())
+    }
+}
+
+/// The global settings that make the Vulkan loader look for `layers` in the library
+/// directory of `package`, in the order they are set.
+fn gpu_debug_settings(package: &str, layers: &[&str]) -> [(&'static str, String); 3] {
+    [
+        ("enable_gpu_debug_layers", "1".to_string()),
+        ("gpu_debug_app", package.to_string()),
+        // The loader enables the layers in this order
+        ("gpu_debug_layers", layers.join(":")),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn validation_layer() {
+        let root = TestDir::new("vulkan");
+        let ndk = crate::ndk::tests::fake_ndk_with_toolchain(&root);
+        let bundled = ndk
+            .ndk()
+            .join("sources/third_party/vulkan/src/build-android/jniLibs/arm64-v8a");
+        let downloaded = root.join("android-binaries-1.3.290").join("x86_64");
+        for dir in [&bundled, &downloaded] {
+            std::fs::create_dir_all(dir).unwrap();
+            std::fs::write(dir.join(VALIDATION_LAYER_LIB), "").unwrap();
+        }
+
+        assert_eq!(
+            ndk.vulkan_validation_layer(None, Target::Arm64V8a).unwrap(),
+            bundled.join(VALIDATION_LAYER_LIB)
+        );
+        let dir = root.join("android-binaries-1.3.290");
+        assert_eq!(
+            ndk.vulkan_validation_layer(Some(&dir), Target::X86_64)
+                .unwrap(),
+            downloaded.join(VALIDATION_LAYER_LIB)
+        );
+        assert!(matches!(
+            ndk.vulkan_validation_layer(Some(&dir), Target::Arm64V8a),
+            Err(NdkError::VulkanValidationLayerNotFound { target: "arm64-v8a", dir: missing })
+                if missing == dir.join("arm64-v8a")
+        ));
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(
+            gpu_debug_settings(
+                "rust.example",
+                &[VALIDATION_LAYER, "VK_LAYER_LUNARG_api_dump"]
+            ),
+            [
+                ("enable_gpu_debug_layers", "1".to_string()),
+                ("gpu_debug_app", "rust.example".to_string()),
+                (
+                    "gpu_debug_layers",
+                    "VK_LAYER_KHRONOS_validation:VK_LAYER_LUNARG_api_dump".to_string()
+                ),
+            ]
+        );
+    }
+}